@@ -0,0 +1,182 @@
+//! Allocations and time per frame of the sample and packet buffers
+//!
+//! Each buffer is handled twice: copied into a fresh allocation, as every
+//! frame and packet was before the pools, and through the shared sample
+//! pool or a bytes arena. The allocations per frame come from the
+//! `profiling` feature's counting allocator and are printed before the
+//! timings:
+//!
+//! ```text
+//! cargo bench --features profiling --bench frame_buffers
+//! ```
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use lan_audio_streamer::audio::buffer::AudioFrame;
+use lan_audio_streamer::audio::pool;
+use lan_audio_streamer::codec::OpusEncoder;
+use lan_audio_streamer::profiling::{self, Stage};
+use lan_audio_streamer::protocol::AudioPacket;
+
+/// 10 ms of stereo at 48 kHz
+const FRAME_SAMPLES: usize = 960;
+
+/// Frames run for the allocation counts
+const COUNTED_FRAMES: u64 = 10_000;
+
+/// Track the copying variant is counted on
+const COPIED: u8 = 0;
+
+/// Track the pooled variant is counted on
+const POOLED: u8 = 1;
+
+fn captured() -> Vec<f32> {
+    (0..FRAME_SAMPLES).map(|n| (n as f32 * 0.05).sin() * 0.5).collect()
+}
+
+fn packet(sequence: u32) -> AudioPacket {
+    AudioPacket::new(0, sequence, sequence as u64 * 10_000, Bytes::from(vec![0x5a; 160]))
+}
+
+/// Captured samples into a frame that is consumed and freed
+fn frame_copied(data: &[f32], sequence: u32) {
+    let frame = AudioFrame::new(black_box(data).to_vec(), 2, 0, sequence);
+    drop(black_box(frame));
+}
+
+/// Captured samples into a pooled frame that is consumed and recycled
+fn frame_pooled(data: &[f32], sequence: u32) {
+    let frame = AudioFrame::new(pool::samples().take_copy(black_box(data)), 2, 0, sequence);
+    black_box(frame).recycle();
+}
+
+/// Packet serialized into its own datagram
+fn send_copied(packet: &AudioPacket) {
+    black_box(packet.serialize());
+}
+
+/// Packet serialized into the sender thread's arena
+fn send_pooled(packet: &AudioPacket, arena: &mut BytesMut) {
+    black_box(packet.serialize_into(arena));
+}
+
+/// Datagram copied out of the receive buffer
+fn receive_copied(datagram: &[u8], recv_buffer: &mut [u8]) {
+    recv_buffer[..datagram.len()].copy_from_slice(datagram);
+    black_box(AudioPacket::deserialize(Bytes::copy_from_slice(&recv_buffer[..datagram.len()])));
+}
+
+/// Datagram split off the receive arena
+fn receive_pooled(datagram: &[u8], arena: &mut BytesMut) {
+    arena.resize(2048, 0);
+    arena[..datagram.len()].copy_from_slice(datagram);
+    arena.truncate(datagram.len());
+    black_box(AudioPacket::deserialize(arena.split().freeze()));
+}
+
+/// Encoded packet copied out of the encoder's buffer
+fn encode_copied(encoder: &mut OpusEncoder, samples: &[f32]) {
+    let packet = encoder.encode(samples).unwrap();
+    black_box(Bytes::copy_from_slice(&packet));
+}
+
+/// Encoded packet split off the encoder's arena
+fn encode_pooled(encoder: &mut OpusEncoder, samples: &[f32]) {
+    black_box(encoder.encode(samples).unwrap());
+}
+
+/// Allocations per frame of each variant, by the counting allocator
+fn report_allocations() {
+    if !profiling::is_enabled() {
+        println!("Allocation counts need the `profiling` feature");
+        return;
+    }
+
+    let data = captured();
+    let datagram = packet(0).serialize();
+    let mut encoder = OpusEncoder::music(48000, 2).unwrap();
+    let mut send_arena = BytesMut::with_capacity(64 * 1024);
+    let mut recv_arena = BytesMut::with_capacity(64 * 1024);
+    let mut recv_buffer = vec![0u8; 2048];
+
+    profiling::reset();
+    for sequence in 0..COUNTED_FRAMES as u32 {
+        let packet = packet(sequence);
+        {
+            let _stage = profiling::stage(COPIED, Stage::Capture);
+            frame_copied(&data, sequence);
+        }
+        {
+            let _stage = profiling::stage(POOLED, Stage::Capture);
+            frame_pooled(&data, sequence);
+        }
+        {
+            let _stage = profiling::stage(COPIED, Stage::Encode);
+            encode_copied(&mut encoder, &data);
+        }
+        {
+            let _stage = profiling::stage(POOLED, Stage::Encode);
+            encode_pooled(&mut encoder, &data);
+        }
+        {
+            let _stage = profiling::stage(COPIED, Stage::Send);
+            send_copied(&packet);
+        }
+        {
+            let _stage = profiling::stage(POOLED, Stage::Send);
+            send_pooled(&packet, &mut send_arena);
+        }
+        {
+            let _stage = profiling::stage(COPIED, Stage::Decode);
+            receive_copied(&datagram, &mut recv_buffer);
+        }
+        {
+            let _stage = profiling::stage(POOLED, Stage::Decode);
+            receive_pooled(&datagram, &mut recv_arena);
+        }
+    }
+
+    println!("Allocations per frame ({} frames):", COUNTED_FRAMES);
+    for summary in profiling::summary() {
+        let variant = if summary.track_id == COPIED { "copied" } else { "pooled" };
+        let stage = match summary.stage {
+            Stage::Capture => "frame samples",
+            Stage::Encode => "encoded packet",
+            Stage::Send => "sent datagram",
+            Stage::Decode | Stage::Playout => "received datagram",
+        };
+        println!(
+            "  {:<18} {:<7} {:>6.3}",
+            stage,
+            variant,
+            summary.allocations as f64 / summary.calls as f64
+        );
+    }
+}
+
+fn frame_buffers(c: &mut Criterion) {
+    report_allocations();
+
+    let data = captured();
+    let packet = packet(0);
+    let datagram = packet.serialize();
+
+    c.bench_function("frame samples copied", |b| b.iter(|| frame_copied(&data, 0)));
+    c.bench_function("frame samples pooled", |b| b.iter(|| frame_pooled(&data, 0)));
+
+    let mut encoder = OpusEncoder::music(48000, 2).unwrap();
+    c.bench_function("encoded packet copied", |b| b.iter(|| encode_copied(&mut encoder, &data)));
+    c.bench_function("encoded packet pooled", |b| b.iter(|| encode_pooled(&mut encoder, &data)));
+
+    let mut arena = BytesMut::with_capacity(64 * 1024);
+    c.bench_function("sent datagram copied", |b| b.iter(|| send_copied(&packet)));
+    c.bench_function("sent datagram pooled", |b| b.iter(|| send_pooled(&packet, &mut arena)));
+
+    let mut recv_buffer = vec![0u8; 2048];
+    c.bench_function("received datagram copied", |b| b.iter(|| receive_copied(&datagram, &mut recv_buffer)));
+    c.bench_function("received datagram pooled", |b| b.iter(|| receive_pooled(&datagram, &mut arena)));
+}
+
+criterion_group!(benches, frame_buffers);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+-- Wireshark dissector for the LAN audio streamer protocol
+--
+-- Dissects the audio packet header (magic 0xAF01) and the handshake
+-- header (magic "LAHS") of captures written with `--pcap FILE`, or of
+-- live traffic on the audio port. Copy it into the personal Lua plugins
+-- folder (Help > About Wireshark > Folders) or load it with
+-- `wireshark -X lua_script:lan_audio.lua capture.pcapng`.
+--
+-- Useful for timing issues: the `lanaudio.seq` and `lanaudio.timestamp`
+-- fields, an I/O graph of `lanaudio.track == 0`, and the
+-- `frame.time_delta_displayed` column with a filter on one track.
+-- Packets are found by their magic on any UDP port; RTP streams
+-- (`--packet-format rtp`) are left to Wireshark's RTP dissector.
+
+local lanaudio = Proto("lanaudio", "LAN Audio Streamer")
+
+local codecs = { [0] = "Opus", [1] = "FLAC" }
+
+local packet_types = {
+    [0x01] = "Hello", [0x02] = "HelloAck", [0x03] = "SyncRequest", [0x04] = "SyncResponse",
+    [0x05] = "Ping", [0x06] = "Pong", [0x07] = "Goodbye", [0x08] = "Feedback",
+    [0x09] = "Resync", [0x0A] = "Subscribe", [0x0B] = "FileOffer", [0x0C] = "FileChunk",
+    [0x0D] = "FileAck", [0x0E] = "TrackAdded", [0x0F] = "TrackRemoved",
+    [0x10] = "ProbeRequest", [0x11] = "ProbeEcho", [0x12] = "PairingChallenge", [0xFF] = "Error",
+}
+
+local f = lanaudio.fields
+f.magic = ProtoField.uint16("lanaudio.magic", "Magic", base.HEX)
+f.track = ProtoField.uint8("lanaudio.track", "Track")
+f.flags = ProtoField.uint8("lanaudio.flags", "Flags", base.HEX)
+f.keyframe = ProtoField.bool("lanaudio.flags.keyframe", "Stream restart", 8, nil, 0x01)
+f.stereo = ProtoField.bool("lanaudio.flags.stereo", "Stereo", 8, nil, 0x02)
+f.fec = ProtoField.bool("lanaudio.flags.fec", "FEC", 8, nil, 0x04)
+f.encrypted = ProtoField.bool("lanaudio.flags.encrypted", "Encrypted", 8, nil, 0x08)
+f.probe = ProtoField.bool("lanaudio.flags.probe", "Latency probe", 8, nil, 0x10)
+f.fragment = ProtoField.bool("lanaudio.flags.fragment", "Fragment", 8, nil, 0x20)
+f.codec = ProtoField.uint8("lanaudio.flags.codec", "Codec", base.DEC, codecs, 0xC0)
+f.seq = ProtoField.uint32("lanaudio.seq", "Sequence")
+f.timestamp = ProtoField.uint64("lanaudio.timestamp", "Timestamp (µs)")
+f.frag_index = ProtoField.uint8("lanaudio.fragment.index", "Fragment index")
+f.frag_count = ProtoField.uint8("lanaudio.fragment.count", "Fragment count")
+f.payload = ProtoField.bytes("lanaudio.payload", "Payload")
+f.silence = ProtoField.none("lanaudio.silence", "Silence marker")
+f.hs_version = ProtoField.uint8("lanaudio.handshake.version", "Version")
+f.hs_type = ProtoField.uint8("lanaudio.handshake.type", "Type", base.HEX, packet_types)
+f.hs_session = ProtoField.uint32("lanaudio.handshake.session", "Session ID", base.HEX)
+f.hs_payload = ProtoField.bytes("lanaudio.handshake.payload", "Payload")
+
+local function dissect_audio(buffer, pinfo, tree)
+    local subtree = tree:add(lanaudio, buffer(), "LAN Audio packet")
+    subtree:add_le(f.magic, buffer(0, 2))
+    subtree:add(f.track, buffer(2, 1))
+    local flags = subtree:add(f.flags, buffer(3, 1))
+    for _, field in ipairs({ f.keyframe, f.stereo, f.fec, f.encrypted, f.probe, f.fragment, f.codec }) do
+        flags:add(field, buffer(3, 1))
+    end
+    subtree:add_le(f.seq, buffer(4, 4))
+    subtree:add_le(f.timestamp, buffer(8, 8))
+
+    local track = buffer(2, 1):uint()
+    local flag_bits = buffer(3, 1):uint()
+    local seq = buffer(4, 4):le_uint()
+    local offset = 16
+    local info = string.format("Track %d seq %d", track, seq)
+    if bit.band(flag_bits, 0x20) ~= 0 and buffer:len() >= 18 then
+        subtree:add(f.frag_index, buffer(16, 1))
+        subtree:add(f.frag_count, buffer(17, 1))
+        info = info .. string.format(" fragment %d/%d", buffer(16, 1):uint() + 1, buffer(17, 1):uint())
+        offset = 18
+    end
+    if buffer:len() > offset then
+        subtree:add(f.payload, buffer(offset))
+    else
+        subtree:add(f.silence, buffer(0, 0))
+        info = info .. " silence"
+    end
+    if bit.band(flag_bits, 0x01) ~= 0 then
+        info = info .. " restart"
+    end
+    if bit.band(flag_bits, 0x10) ~= 0 then
+        info = info .. " probe"
+    end
+    pinfo.cols.info:set(info)
+end
+
+local function dissect_handshake(buffer, pinfo, tree)
+    local subtree = tree:add(lanaudio, buffer(), "LAN Audio handshake")
+    subtree:add(f.hs_version, buffer(4, 1))
+    subtree:add(f.hs_type, buffer(5, 1))
+    subtree:add_le(f.hs_session, buffer(6, 4))
+    if buffer:len() > 10 then
+        subtree:add(f.hs_payload, buffer(10))
+    end
+    local packet_type = buffer(5, 1):uint()
+    pinfo.cols.info:set(string.format("%s session %08x", packet_types[packet_type] or "Unknown",
+        buffer(6, 4):le_uint()))
+end
+
+local function heuristic(buffer, pinfo, tree)
+    if buffer:len() >= 16 and buffer(0, 2):le_uint() == 0xAF01 then
+        pinfo.cols.protocol:set("LANAUDIO")
+        dissect_audio(buffer, pinfo, tree)
+        return true
+    end
+    if buffer:len() >= 10 and buffer(0, 4):string() == "LAHS" then
+        pinfo.cols.protocol:set("LANAUDIO")
+        dissect_handshake(buffer, pinfo, tree)
+        return true
+    end
+    return false
+end
+
+function lanaudio.dissector(buffer, pinfo, tree)
+    heuristic(buffer, pinfo, tree)
+end
+
+lanaudio:register_heuristic("udp", heuristic)
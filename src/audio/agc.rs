@@ -0,0 +1,180 @@
+//! Automatic gain control on capture
+//!
+//! A track with `TrackConfig::agc` brings its input to `agc_target_db`
+//! (RMS loudness in dBFS): every frame's loudness sets the gain that would
+//! reach the target, within [`MAX_GAIN_DB`] of boost and [`MAX_CUT_DB`] of
+//! cut. The applied gain follows it with `agc_attack_ms` when it has to
+//! come down (a loud passage) and `agc_release_ms` when it may rise again,
+//! and is ramped across each frame. Frames below [`GATE_DB`] hold the gain,
+//! so pauses and background noise are not pulled up, and a frame that
+//! would clip gets only as much gain as fits.
+
+use crate::audio::simd;
+use crate::protocol::TrackConfig;
+
+/// Most boost the AGC applies
+pub const MAX_GAIN_DB: f32 = 30.0;
+
+/// Most cut the AGC applies
+pub const MAX_CUT_DB: f32 = 20.0;
+
+/// Frames quieter than this (RMS dBFS) leave the gain alone
+pub const GATE_DB: f32 = -55.0;
+
+/// Lowest and highest loudness target in dBFS
+pub const TARGET_RANGE: std::ops::RangeInclusive<f32> = -40.0..=-6.0;
+
+/// Automatic gain control of one track
+#[derive(Debug, Clone)]
+pub struct Agc {
+    channels: usize,
+    target_db: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    /// Gain currently applied in dB
+    gain_db: f32,
+}
+
+impl Agc {
+    pub fn new(channels: usize, target_db: f32, attack_ms: f32, release_ms: f32) -> Self {
+        Self {
+            channels: channels.max(1),
+            target_db,
+            attack_ms,
+            release_ms,
+            gain_db: 0.0,
+        }
+    }
+
+    /// AGC of a track with gain control on (None when off)
+    pub fn for_track(config: &TrackConfig, channels: usize) -> Option<Self> {
+        config
+            .agc
+            .then(|| Self::new(channels, config.agc_target_db, config.agc_attack_ms, config.agc_release_ms))
+    }
+
+    /// Follow a changed track config, keeping the gain of a running AGC
+    pub fn reconfigure(agc: &mut Option<Self>, config: &TrackConfig, channels: usize) {
+        match (agc.as_mut(), config.agc) {
+            (Some(agc), true) => {
+                agc.target_db = config.agc_target_db;
+                agc.attack_ms = config.agc_attack_ms;
+                agc.release_ms = config.agc_release_ms;
+            }
+            (None, true) => *agc = Self::for_track(config, channels),
+            (_, false) => *agc = None,
+        }
+    }
+
+    /// Gain currently applied in dB
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    /// Level an interleaved frame lasting `frame_ms` in place
+    pub fn process(&mut self, samples: &mut [f32], frame_ms: f32) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let power = samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+        let loudness_db = 10.0 * power.max(1e-12).log10();
+        let from_db = self.gain_db;
+
+        if loudness_db >= GATE_DB {
+            let wanted_db = (self.target_db - loudness_db).clamp(-MAX_CUT_DB, MAX_GAIN_DB);
+            let time_ms = if wanted_db < self.gain_db { self.attack_ms } else { self.release_ms };
+            let coefficient = (-frame_ms / time_ms.max(1.0)).exp();
+            self.gain_db = wanted_db + (self.gain_db - wanted_db) * coefficient;
+
+            // Never push the frame into clipping
+            let peak = simd::peak_abs(samples);
+            if peak > 0.0 {
+                self.gain_db = self.gain_db.min(-20.0 * peak.log10());
+            }
+        }
+
+        if from_db != 0.0 || self.gain_db != 0.0 {
+            simd::apply_gain_ramp(samples, self.channels, db_to_gain(from_db), db_to_gain(self.gain_db));
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(amplitude: f32) -> Vec<f32> {
+        (0..960).map(|i| (i as f32 * 0.05).sin() * amplitude).collect()
+    }
+
+    fn rms_db(samples: &[f32]) -> f32 {
+        let power = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+        10.0 * power.log10()
+    }
+
+    #[test]
+    fn test_levels_towards_target() {
+        let mut agc = Agc::new(2, -20.0, 50.0, 500.0);
+
+        // A quiet microphone (-40 dBFS RMS) is brought up within a few seconds
+        let mut last = Vec::new();
+        for _ in 0..300 {
+            last = tone(0.0141);
+            agc.process(&mut last, 10.0);
+        }
+        assert!((agc.gain_db() - 20.0).abs() < 0.5);
+        assert!((rms_db(&last) + 20.0).abs() < 0.5);
+
+        // A loud passage is pulled down much faster than the gain rose
+        for _ in 0..30 {
+            agc.process(&mut tone(0.5), 10.0);
+        }
+        assert!(agc.gain_db() < 0.0);
+        let mut loud = tone(0.5);
+        agc.process(&mut loud, 10.0);
+        assert!(simd::peak_abs(&loud) <= 1.0);
+    }
+
+    #[test]
+    fn test_pauses_hold_gain() {
+        let mut agc = Agc::new(1, -20.0, 10.0, 100.0);
+        for _ in 0..100 {
+            agc.process(&mut tone(0.05), 10.0);
+        }
+        let gain_db = agc.gain_db();
+
+        // Background noise below the gate is not pulled up
+        for _ in 0..100 {
+            agc.process(&mut vec![0.0005; 960], 10.0);
+        }
+        assert_eq!(agc.gain_db(), gain_db);
+        assert!(gain_db > 0.0 && gain_db <= MAX_GAIN_DB);
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let mut config = TrackConfig::default();
+        let mut agc = Agc::for_track(&config, 2);
+        assert!(agc.is_none());
+
+        config.agc = true;
+        Agc::reconfigure(&mut agc, &config, 2);
+        agc.as_mut().unwrap().process(&mut tone(0.01), 10.0);
+        let gain_db = agc.as_ref().unwrap().gain_db();
+        assert!(gain_db > 0.0);
+
+        // A new target keeps the gain reached so far
+        config.agc_target_db = -30.0;
+        Agc::reconfigure(&mut agc, &config, 2);
+        assert_eq!(agc.as_ref().unwrap().gain_db(), gain_db);
+
+        config.agc = false;
+        Agc::reconfigure(&mut agc, &config, 2);
+        assert!(agc.is_none());
+    }
+}
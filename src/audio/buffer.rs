@@ -7,6 +7,17 @@ use crossbeam::queue::ArrayQueue;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+use crate::codec::encoder::FRAME_DURATIONS_MS;
+use crate::constants::{DEFAULT_FRAME_SIZE_MS, DEFAULT_SAMPLE_RATE};
+use crate::protocol::{JitterMode, TrackConfig};
+
+/// Frame duration until the sender's is known (µs)
+const DEFAULT_FRAME_DURATION_US: u64 = (DEFAULT_FRAME_SIZE_MS * 1000.0) as u64;
+
+/// Arrival gaps longer than this are stream pauses (talkback released,
+/// sender asleep), not network jitter
+const STREAM_PAUSE_US: f64 = 500_000.0;
+
 /// Audio frame containing interleaved samples
 #[derive(Clone)]
 pub struct AudioFrame {
@@ -18,6 +29,11 @@ pub struct AudioFrame {
     pub timestamp: u64,
     /// Frame sequence number
     pub sequence: u32,
+    /// Latency probe frame: local media time the sender sent it at
+    pub probe_us: Option<u64>,
+    /// Silence marker: the sender stops sending after this slot
+    /// (no samples, see `audio::silence`)
+    pub silence_marker: bool,
 }
 
 impl AudioFrame {
@@ -27,9 +43,24 @@ impl AudioFrame {
             channels,
             timestamp,
             sequence,
+            probe_us: None,
+            silence_marker: false,
+        }
+    }
+    
+    /// Slot of a silence marker received in place of a frame
+    pub fn silence_marker(channels: u16, timestamp: u64, sequence: u32) -> Self {
+        Self {
+            silence_marker: true,
+            ..Self::new(Vec::new(), channels, timestamp, sequence)
         }
     }
     
+    /// Whether this is a silence marker
+    pub fn is_silence_marker(&self) -> bool {
+        self.silence_marker
+    }
+    
     /// Get number of samples per channel
     pub fn samples_per_channel(&self) -> usize {
         self.samples.len() / self.channels as usize
@@ -137,6 +168,15 @@ pub fn create_shared_buffer(capacity: usize) -> SharedRingBuffer {
     Arc::new(RingBuffer::new(capacity))
 }
 
+/// Outcome of advancing the jitter buffer playback point by one slot
+#[derive(Clone)]
+pub enum Playout {
+    /// Frame arrived (or was recovered) in time
+    Frame(AudioFrame),
+    /// Frame never arrived; conceal it with the expected timestamp
+    Lost { sequence: u32, timestamp: u64 },
+}
+
 /// Jitter buffer for packet reordering and loss concealment
 pub struct JitterBuffer {
     /// Buffer slots indexed by sequence modulo capacity
@@ -149,6 +189,10 @@ pub struct JitterBuffer {
     next_sequence: u32,
     /// Minimum buffer delay in frames
     min_delay: usize,
+    /// Minimum delay in frames when the settings don't give one
+    default_min_delay: usize,
+    /// Delay settings of the track
+    delay: JitterDelay,
     /// Maximum buffer delay (adaptive ceiling)
     max_delay: usize,
     /// Current target delay (adaptive)
@@ -159,6 +203,10 @@ pub struct JitterBuffer {
     received: AtomicUsize,
     /// Packets lost
     lost: AtomicUsize,
+    /// Lost frames recovered from FEC data of the following packet
+    recovered: AtomicUsize,
+    /// Lost frames replaced by decoder concealment (PLC)
+    concealed: AtomicUsize,
     /// Late packets (arrived after playback point)
     late: AtomicUsize,
     /// Out of order packets
@@ -167,14 +215,33 @@ pub struct JitterBuffer {
     last_receive_time: Option<std::time::Instant>,
     /// Jitter estimator (exponential moving average)
     jitter_estimate_us: f64,
+    /// Duration of the received frames (µs): the expected inter-arrival
+    /// time and the unit of the delays
+    frame_duration_us: u64,
     /// Has been initialized with first packet
     initialized: bool,
+    /// Playout has consumed at least one frame
+    playout_started: bool,
+    /// Sequence and timestamp of the last frame played out
+    last_played: Option<(u32, u64)>,
+    /// Timestamp step between consecutive frames (learned from playout)
+    frame_interval_us: u64,
+    /// Silence marker the buffer plays out to regardless of the target
+    /// delay (the sender stopped sending after it)
+    drain_through: Option<u32>,
 }
 
 impl JitterBuffer {
-    /// Create a new jitter buffer
+    /// Create a new jitter buffer for 10 ms frames
     /// capacity must be a power of 2
     pub fn new(capacity: usize, min_delay: usize) -> Self {
+        Self::with_frame_duration(capacity, min_delay, DEFAULT_FRAME_DURATION_US)
+    }
+    
+    /// Create a new jitter buffer for frames of `frame_duration_us` (e.g.
+    /// as the sender announced them; the frames received take over)
+    /// capacity must be a power of 2
+    pub fn with_frame_duration(capacity: usize, min_delay: usize, frame_duration_us: u64) -> Self {
         assert!(capacity.is_power_of_two(), "Capacity must be power of 2");
         
         let mut slots = Vec::with_capacity(capacity);
@@ -186,29 +253,51 @@ impl JitterBuffer {
             mask: capacity - 1,
             next_sequence: 0,
             min_delay,
+            default_min_delay: min_delay,
+            delay: JitterDelay::default(),
             max_delay: capacity / 2, // Max half the buffer
             target_delay: min_delay,
             level: AtomicUsize::new(0),
             received: AtomicUsize::new(0),
             lost: AtomicUsize::new(0),
+            recovered: AtomicUsize::new(0),
+            concealed: AtomicUsize::new(0),
             late: AtomicUsize::new(0),
             out_of_order: AtomicUsize::new(0),
             last_receive_time: None,
             jitter_estimate_us: 0.0,
+            frame_duration_us: frame_duration_us.max(1),
             initialized: false,
+            playout_started: false,
+            last_played: None,
+            frame_interval_us: frame_duration_us.max(1),
+            drain_through: None,
         }
     }
     
     /// Insert a frame into the jitter buffer with adaptive delay
+    /// (a silence marker frame also plays out everything before it)
     pub fn insert(&mut self, frame: AudioFrame) -> bool {
         let seq = frame.sequence;
+        let marker = frame.is_silence_marker();
         let now = std::time::Instant::now();
         
-        // Update jitter estimate
-        if let Some(last_time) = self.last_receive_time {
-            let inter_arrival_us = now.duration_since(last_time).as_micros() as f64;
+        // The sender changed its frame size: delays follow the new frames
+        if let Some(frame_duration_us) = codec_frame_duration_us(&frame) {
+            self.set_frame_duration_us(frame_duration_us);
+        }
+        
+        // Update jitter estimate (pre-buffering arrivals are bursty, skip them;
+        // so are pauses, which would inflate the target delay for minutes,
+        // and silence markers, which arrive far apart)
+        let inter_arrival_us = self
+            .last_receive_time
+            .filter(|_| self.playout_started && !marker)
+            .map(|last_time| now.duration_since(last_time).as_micros() as f64)
+            .filter(|&us| us < STREAM_PAUSE_US);
+        if let Some(inter_arrival_us) = inter_arrival_us {
             // Expected inter-arrival based on frame timing (e.g., 10ms = 10000us)
-            let expected_us = 10000.0; // TODO: Could be calculated from frame size
+            let expected_us = self.frame_duration_us as f64;
             let deviation = (inter_arrival_us - expected_us).abs();
             
             // Exponential moving average with alpha = 0.1
@@ -217,7 +306,7 @@ impl JitterBuffer {
             // Adapt target delay based on jitter
             self.adapt_delay();
         }
-        self.last_receive_time = Some(now);
+        self.last_receive_time = (!marker).then_some(now);
         
         // Initialize sequence on first packet
         if !self.initialized {
@@ -233,6 +322,9 @@ impl JitterBuffer {
             let behind = (-seq_diff) as u32;
             if behind > self.capacity as u32 / 2 {
                 // Large negative = sequence wrapped, this is actually future
+            } else if !self.playout_started {
+                // Nothing played yet: reordered start of stream, move playback point back
+                self.next_sequence = seq;
             } else {
                 // Packet is genuinely late
                 self.late.fetch_add(1, Ordering::Relaxed);
@@ -245,18 +337,63 @@ impl JitterBuffer {
             self.out_of_order.fetch_add(1, Ordering::Relaxed);
         }
         
+        self.store(frame);
+        self.received.fetch_add(1, Ordering::Relaxed);
+        if marker {
+            self.drain_through = Some(seq);
+        }
+        
+        true
+    }
+    
+    /// Put a frame into its slot; a frame already holding the slot
+    /// (e.g. one recovered from FEC) is replaced without changing the level
+    fn store(&mut self, frame: AudioFrame) {
+        let seq = frame.sequence;
         let index = (seq as usize) & self.mask;
+        let replaced = self.slots[index].as_ref().is_some_and(|f| f.sequence == seq);
         self.slots[index] = Some(frame);
-        self.received.fetch_add(1, Ordering::Relaxed);
-        self.level.fetch_add(1, Ordering::Relaxed);
+        if !replaced {
+            self.level.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    /// Check whether a frame that is still ahead of the playback point
+    /// has not arrived yet (candidate for FEC recovery)
+    pub fn is_missing(&self, seq: u32) -> bool {
+        if !self.initialized {
+            return false;
+        }
+        
+        let ahead = seq.wrapping_sub(self.next_sequence);
+        if ahead >= self.capacity as u32 / 2 {
+            return false;
+        }
         
+        let index = (seq as usize) & self.mask;
+        self.slots[index].as_ref().map(|f| f.sequence) != Some(seq)
+    }
+    
+    /// Insert a frame reconstructed from FEC data of the following packet.
+    /// The frame must still be missing; returns false otherwise.
+    pub fn insert_recovered(&mut self, frame: AudioFrame) -> bool {
+        if !self.is_missing(frame.sequence) {
+            return false;
+        }
+        
+        self.store(frame);
+        self.recovered.fetch_add(1, Ordering::Relaxed);
         true
     }
     
     /// Adapt delay based on network jitter
     fn adapt_delay(&mut self) {
-        // Convert jitter estimate to frames (assuming 10ms frames)
-        let jitter_frames = (self.jitter_estimate_us / 10000.0).ceil() as usize;
+        if self.delay.manual {
+            return;
+        }
+        
+        // Convert jitter estimate to frames
+        let jitter_frames = (self.jitter_estimate_us / self.frame_duration_us as f64).ceil() as usize;
         
         // Target delay = min_delay + jitter margin
         let new_target = (self.min_delay + jitter_frames).clamp(self.min_delay, self.max_delay);
@@ -270,24 +407,80 @@ impl JitterBuffer {
     }
     
     /// Get the next frame if available and buffered enough
+    /// (a lost slot is skipped and yields None, see `next_playout`)
     pub fn get_next(&mut self) -> Option<AudioFrame> {
-        // Use adaptive target delay
-        if self.level.load(Ordering::Relaxed) < self.target_delay {
+        match self.next_playout()? {
+            Playout::Frame(frame) => Some(frame),
+            Playout::Lost { .. } => None,
+        }
+    }
+    
+    /// Advance the playback point by one slot if buffered enough,
+    /// reporting lost slots so the caller can conceal them
+    pub fn next_playout(&mut self) -> Option<Playout> {
+        // Use adaptive target delay; up to a silence marker nothing more
+        // is coming, so the buffer plays out what it holds
+        let draining = self
+            .drain_through
+            .is_some_and(|seq| seq.wrapping_sub(self.next_sequence) < self.capacity as u32 / 2);
+        if self.level.load(Ordering::Relaxed) < self.target_delay && !draining {
             return None;
         }
-        
-        let index = (self.next_sequence as usize) & self.mask;
-        let frame = self.slots[index].take();
-        
-        if frame.is_some() {
-            self.level.fetch_sub(1, Ordering::Relaxed);
-        } else {
-            // Packet was lost
-            self.lost.fetch_add(1, Ordering::Relaxed);
+        Some(self.advance())
+    }
+    
+    /// Advance the playback point by one slot because its time has come
+    /// (see `PlayoutScheduler`), below the target delay too; None once
+    /// nothing is buffered
+    pub fn next_playout_due(&mut self) -> Option<Playout> {
+        if self.level.load(Ordering::Relaxed) == 0 {
+            return None;
         }
+        Some(self.advance())
+    }
+    
+    /// Play out the slot at the playback point and move past it
+    fn advance(&mut self) -> Playout {
+        let sequence = self.next_sequence;
+        let index = (sequence as usize) & self.mask;
+        let playout = match self.slots[index].take() {
+            Some(frame) => {
+                self.level.fetch_sub(1, Ordering::Relaxed);
+                // The silence gap is no frame interval
+                if !frame.is_silence_marker() {
+                    if let Some((last_seq, last_ts)) = self.last_played {
+                        if sequence.wrapping_sub(last_seq) == 1 && frame.timestamp > last_ts {
+                            self.frame_interval_us = frame.timestamp - last_ts;
+                        }
+                    }
+                    self.last_played = Some((sequence, frame.timestamp));
+                }
+                Playout::Frame(frame)
+            }
+            None => {
+                // Packet was lost
+                self.lost.fetch_add(1, Ordering::Relaxed);
+                let timestamp = self
+                    .last_played
+                    .map(|(last_seq, last_ts)| {
+                        last_ts + sequence.wrapping_sub(last_seq) as u64 * self.frame_interval_us
+                    })
+                    .unwrap_or(0);
+                Playout::Lost { sequence, timestamp }
+            }
+        };
         
+        if self.drain_through == Some(sequence) {
+            self.drain_through = None;
+        }
         self.next_sequence = self.next_sequence.wrapping_add(1);
-        frame
+        self.playout_started = true;
+        playout
+    }
+    
+    /// Count a lost slot that was filled with a concealment frame
+    pub fn record_concealed(&self) {
+        self.concealed.fetch_add(1, Ordering::Relaxed);
     }
     
     /// Force get the next frame even if buffer level is low
@@ -304,6 +497,7 @@ impl JitterBuffer {
         }
         
         self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.playout_started = true;
         frame
     }
     
@@ -314,10 +508,13 @@ impl JitterBuffer {
         }
         self.next_sequence = 0;
         self.level.store(0, Ordering::Relaxed);
-        self.target_delay = self.min_delay;
+        self.apply_delay(0);
         self.jitter_estimate_us = 0.0;
         self.last_receive_time = None;
         self.initialized = false;
+        self.playout_started = false;
+        self.last_played = None;
+        self.drain_through = None;
     }
     
     /// Set the next expected sequence (for sync)
@@ -325,6 +522,31 @@ impl JitterBuffer {
         self.reset();
         self.next_sequence = seq;
         self.initialized = true;
+        self.playout_started = true;
+    }
+    
+    /// Restart the stream at `seq` (restart marker received).
+    /// Returns buffered frames preceding the marker in playout order, or
+    /// None if the buffer has not started yet and nothing had to be reset.
+    pub fn restart_at(&mut self, seq: u32) -> Option<Vec<AudioFrame>> {
+        if !self.initialized {
+            return None;
+        }
+        
+        let mut flushed = Vec::new();
+        for i in 0..self.capacity as u32 {
+            let s = self.next_sequence.wrapping_add(i);
+            if s == seq {
+                break;
+            }
+            let index = (s as usize) & self.mask;
+            if self.slots[index].as_ref().map(|f| f.sequence) == Some(s) {
+                flushed.extend(self.slots[index].take());
+            }
+        }
+        
+        self.set_next_sequence(seq);
+        Some(flushed)
     }
     
     /// Get current target delay
@@ -332,6 +554,70 @@ impl JitterBuffer {
         self.target_delay
     }
     
+    /// Frames buffered ahead of the playback point
+    pub fn level(&self) -> usize {
+        self.level.load(Ordering::Relaxed)
+    }
+    
+    /// Count delays in frames of `frame_duration_us`: the target delay
+    /// keeps its length in time (within the frame limits), lost frames are
+    /// timed with the new duration until playout learns the real step
+    pub fn set_frame_duration_us(&mut self, frame_duration_us: u64) {
+        let frame_duration_us = frame_duration_us.max(1);
+        if frame_duration_us == self.frame_duration_us {
+            return;
+        }
+        
+        let delay_us = self.delay_us();
+        self.frame_duration_us = frame_duration_us;
+        self.frame_interval_us = frame_duration_us;
+        self.apply_delay(delay_us);
+    }
+    
+    /// Take new delay settings; an adaptive delay keeps its length within
+    /// the new limits
+    pub fn set_delay(&mut self, delay: JitterDelay) {
+        if delay != self.delay {
+            self.delay = delay;
+            self.apply_delay(self.delay_us());
+        }
+    }
+    
+    /// Delay settings in use
+    pub fn delay(&self) -> JitterDelay {
+        self.delay
+    }
+    
+    /// Count the delay settings in frames (up to half the buffer) and set
+    /// the target: the fixed delay, or `delay_us` within the limits
+    fn apply_delay(&mut self, delay_us: u64) {
+        let frame_duration_us = self.frame_duration_us;
+        let max_frames = self.capacity / 2;
+        let frames = |us: u64| (us.div_ceil(frame_duration_us) as usize).clamp(1, max_frames);
+        
+        self.max_delay = self.delay.max_us.map_or(max_frames, frames);
+        self.min_delay = self
+            .delay
+            .min_us
+            .map_or(self.default_min_delay, frames)
+            .min(self.max_delay);
+        self.target_delay = if self.delay.manual {
+            self.delay.target_us.map_or(self.min_delay, frames)
+        } else {
+            frames(delay_us).clamp(self.min_delay, self.max_delay)
+        };
+    }
+    
+    /// Frame duration the delays are counted in (µs)
+    pub fn frame_duration_us(&self) -> u64 {
+        self.frame_duration_us
+    }
+    
+    /// Current target delay in microseconds
+    pub fn delay_us(&self) -> u64 {
+        self.target_delay as u64 * self.frame_duration_us
+    }
+    
     /// Get jitter estimate in microseconds
     pub fn jitter_estimate_us(&self) -> f64 {
         self.jitter_estimate_us
@@ -345,6 +631,8 @@ impl JitterBuffer {
             target_delay: self.target_delay,
             received: self.received.load(Ordering::Relaxed),
             lost: self.lost.load(Ordering::Relaxed),
+            recovered: self.recovered.load(Ordering::Relaxed),
+            concealed: self.concealed.load(Ordering::Relaxed),
             late: self.late.load(Ordering::Relaxed),
             out_of_order: self.out_of_order.load(Ordering::Relaxed),
             jitter_us: self.jitter_estimate_us,
@@ -352,6 +640,42 @@ impl JitterBuffer {
     }
 }
 
+/// Delay settings of a jitter buffer, in time so that they hold when the
+/// frame size changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitterDelay {
+    /// Lowest delay (None = the buffer's minimum in frames)
+    pub min_us: Option<u64>,
+    /// Highest delay (None = half the buffer)
+    pub max_us: Option<u64>,
+    /// Hold `target_us` instead of following the jitter
+    pub manual: bool,
+    /// Delay held in manual mode (None = the lowest delay)
+    pub target_us: Option<u64>,
+}
+
+impl JitterDelay {
+    /// Delay settings of a received track
+    pub fn from_config(config: &TrackConfig) -> Self {
+        let us = |ms: Option<u32>| ms.map(|ms| ms as u64 * 1000);
+        Self {
+            min_us: us(config.jitter_min_ms),
+            max_us: us(config.jitter_max_ms),
+            manual: config.jitter_mode == JitterMode::Manual,
+            target_us: us(config.jitter_target_ms),
+        }
+    }
+}
+
+/// Duration of a decoded frame (µs) if it is one a codec sends
+fn codec_frame_duration_us(frame: &AudioFrame) -> Option<u64> {
+    let samples_per_channel = frame.samples.len() / frame.channels.max(1) as usize;
+    let duration_ms = samples_per_channel as f32 * 1000.0 / DEFAULT_SAMPLE_RATE as f32;
+    FRAME_DURATIONS_MS
+        .contains(&duration_ms)
+        .then_some((duration_ms * 1000.0) as u64)
+}
+
 /// Jitter buffer statistics
 #[derive(Debug, Clone)]
 pub struct JitterBufferStats {
@@ -360,6 +684,8 @@ pub struct JitterBufferStats {
     pub target_delay: usize,
     pub received: usize,
     pub lost: usize,
+    pub recovered: usize,
+    pub concealed: usize,
     pub late: usize,
     pub out_of_order: usize,
     pub jitter_us: f64,
@@ -426,4 +752,161 @@ mod tests {
         // Not enough buffered for min_delay now
         assert!(jitter.get_next().is_none());
     }
+    
+    #[test]
+    fn test_jitter_buffer_restart() {
+        let mut jitter = JitterBuffer::new(16, 2);
+        assert!(jitter.restart_at(0).is_none());
+        
+        for seq in 10..14 {
+            jitter.insert(AudioFrame::new(vec![], 2, seq as u64 * 10000, seq));
+        }
+        assert_eq!(jitter.get_next().unwrap().sequence, 10);
+        
+        // Sender restarted its stream at sequence 0
+        let flushed = jitter.restart_at(0).unwrap();
+        let seqs: Vec<u32> = flushed.iter().map(|f| f.sequence).collect();
+        assert_eq!(seqs, vec![11, 12, 13]);
+        assert_eq!(jitter.stats().level, 0);
+        
+        for seq in 0..4 {
+            assert!(jitter.insert(AudioFrame::new(vec![], 2, seq as u64 * 10000, seq)));
+        }
+        assert_eq!(jitter.get_next().unwrap().sequence, 0);
+    }
+    
+    #[test]
+    fn test_silence_marker_drains() {
+        let mut jitter = JitterBuffer::new(16, 3);
+        for seq in 0..3 {
+            jitter.insert(AudioFrame::new(vec![0.5], 2, seq as u64 * 10000, seq));
+        }
+        assert_eq!(jitter.get_next().unwrap().sequence, 0);
+        
+        // Nothing follows the marker: the buffer plays out through it
+        assert!(jitter.insert(AudioFrame::silence_marker(2, 30000, 3)));
+        let played: Vec<AudioFrame> = std::iter::from_fn(|| jitter.get_next()).collect();
+        assert_eq!(played.iter().map(|f| f.sequence).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(played[2].is_silence_marker());
+        
+        // Speech resumes after the marker: pre-roll again, no loss
+        jitter.insert(AudioFrame::new(vec![0.5], 2, 900000, 4));
+        assert!(jitter.get_next().is_none());
+        for seq in 5..8 {
+            jitter.insert(AudioFrame::new(vec![0.5], 2, 850000 + seq as u64 * 10000, seq));
+        }
+        assert_eq!(jitter.get_next().unwrap().sequence, 4);
+        assert_eq!(jitter.stats().lost, 0);
+    }
+    
+    #[test]
+    fn test_announced_frame_duration() {
+        let mut jitter = JitterBuffer::with_frame_duration(16, 2, 2_500);
+        assert_eq!(jitter.delay_us(), 5_000);
+        
+        // A lost first step is timed with the announced duration
+        jitter.insert(AudioFrame::new(vec![0.5], 2, 0, 0));
+        jitter.insert(AudioFrame::new(vec![0.5], 2, 5_000, 2));
+        jitter.insert(AudioFrame::new(vec![0.5], 2, 7_500, 3));
+        assert!(matches!(jitter.next_playout(), Some(Playout::Frame(_))));
+        assert!(matches!(jitter.next_playout(), Some(Playout::Lost { sequence: 1, timestamp: 2_500 })));
+    }
+    
+    #[test]
+    fn test_frame_duration_from_frames() {
+        let frame = |duration_ms: f32, seq: u32| {
+            let samples = (48.0 * duration_ms) as usize * 2;
+            AudioFrame::new(vec![0.0; samples], 2, seq as u64 * (duration_ms * 1000.0) as u64, seq)
+        };
+        
+        for duration_ms in [2.5, 5.0, 20.0] {
+            let frame_duration_us = (duration_ms * 1000.0) as u64;
+            let mut jitter = JitterBuffer::new(32, 2);
+            for seq in (0..=20).filter(|&seq| seq != 10) {
+                jitter.insert(frame(duration_ms, seq));
+            }
+            assert_eq!(jitter.frame_duration_us(), frame_duration_us);
+            
+            // The 20 ms of the minimum delay in 10 ms frames, but never below it in frames
+            let expected = 20_000u64.div_ceil(frame_duration_us).max(2) as usize;
+            assert_eq!(jitter.target_delay(), expected, "{} ms frames", duration_ms);
+            assert_eq!(jitter.delay_us(), expected as u64 * frame_duration_us);
+            
+            // Playout and concealment step by the frames' duration
+            let lost: Vec<_> = std::iter::from_fn(|| jitter.next_playout())
+                .filter_map(|playout| match playout {
+                    Playout::Lost { sequence, timestamp } => Some((sequence, timestamp)),
+                    Playout::Frame(_) => None,
+                })
+                .collect();
+            assert_eq!(lost, vec![(10, 10 * frame_duration_us)]);
+        }
+        
+        // Silence markers and odd lengths don't change the duration
+        let mut jitter = JitterBuffer::new(16, 2);
+        jitter.insert(AudioFrame::silence_marker(2, 0, 0));
+        jitter.insert(AudioFrame::new(vec![0.0; 100], 2, 10_000, 1));
+        assert_eq!(jitter.frame_duration_us(), 10_000);
+    }
+    
+    #[test]
+    fn test_delay_settings() {
+        let frame = |seq: u32, duration_ms: u64| {
+            AudioFrame::new(vec![0.0; 96 * duration_ms as usize], 2, seq as u64 * duration_ms * 1000, seq)
+        };
+        let mut jitter = JitterBuffer::new(32, 2);
+        
+        // A fixed +40 ms holds four 10 ms frames back and doesn't adapt
+        jitter.set_delay(JitterDelay { manual: true, target_us: Some(40_000), ..Default::default() });
+        assert_eq!(jitter.delay_us(), 40_000);
+        for seq in 0..3 {
+            jitter.insert(frame(seq, 10));
+        }
+        assert!(jitter.get_next().is_none());
+        jitter.insert(frame(3, 10));
+        assert_eq!(jitter.get_next().unwrap().sequence, 0);
+        jitter.jitter_estimate_us = 50_000.0;
+        jitter.insert(frame(4, 10));
+        assert_eq!(jitter.target_delay(), 4);
+        
+        // ... and stays 40 ms in 20 ms frames
+        jitter.insert(frame(5, 20));
+        assert_eq!((jitter.target_delay(), jitter.delay_us()), (2, 40_000));
+        
+        // Back to auto: the delay adapts within the limits
+        jitter.set_delay(JitterDelay { min_us: Some(60_000), max_us: Some(100_000), ..Default::default() });
+        assert_eq!(jitter.delay_us(), 60_000);
+        for seq in 6..10 {
+            jitter.insert(frame(seq, 20));
+        }
+        assert_eq!(jitter.delay_us(), 100_000);
+        
+        // A reset starts over at the lowest delay
+        jitter.reset();
+        assert_eq!(jitter.delay_us(), 60_000);
+    }
+    
+    #[test]
+    fn test_jitter_buffer_recovered_frame() {
+        let mut jitter = JitterBuffer::new(16, 2);
+        jitter.insert(AudioFrame::new(vec![], 2, 0, 0));
+        jitter.insert(AudioFrame::new(vec![], 2, 20000, 2));
+        
+        assert!(jitter.is_missing(1));
+        assert!(!jitter.is_missing(2));
+        assert!(jitter.insert_recovered(AudioFrame::new(vec![0.5], 2, 10000, 1)));
+        assert!(!jitter.insert_recovered(AudioFrame::new(vec![0.5], 2, 10000, 1)));
+        
+        // Late original replaces the recovered frame without double counting
+        jitter.insert(AudioFrame::new(vec![1.0], 2, 10000, 1));
+        let stats = jitter.stats();
+        assert_eq!(stats.level, 3);
+        assert_eq!(stats.recovered, 1);
+        
+        assert_eq!(jitter.get_next().unwrap().sequence, 0);
+        assert_eq!(jitter.get_next().unwrap().samples, vec![1.0]);
+        
+        // Already played out
+        assert!(!jitter.is_missing(0));
+    }
 }
@@ -2,17 +2,37 @@
 //!
 //! Handles capturing audio from multiple devices simultaneously,
 //! each running in its own dedicated thread for low latency.
+//!
+//! On Windows an output device can be captured too: a `loopback:` device ID
+//! opens an input stream on the output, which WASAPI turns into a loopback
+//! recording of whatever plays there (system audio, a game, a browser).
+//!
+//! A `file:<path>` device ID plays a WAV or FLAC file instead and a
+//! `generator:` ID a test signal, both paced in real time (see
+//! `audio::file_source` and `audio::generator`).
 
 use cpal::traits::{DeviceTrait, StreamTrait};
 use cpal::StreamConfig;
 use crossbeam_channel::{bounded, Receiver};
+use parking_lot::{Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::audio::buffer::{AudioFrame, SharedRingBuffer};
-use crate::audio::device::get_device_by_id;
+use crate::audio::clock::StreamTiming;
+use crate::audio::convert::{convert_channels, is_passthrough};
+use crate::audio::device::{self, get_device_by_id, get_track_device};
+use crate::audio::file_source::{self, FilePlayer};
+use crate::audio::generator::{Signal, SignalGenerator};
+use crate::audio::pool;
+use crate::audio::resample::Resampler;
+use crate::audio::wasapi;
+#[cfg(all(feature = "pipewire", target_os = "linux"))]
+use crate::audio::pw_cat;
+#[cfg(all(feature = "pipewire", target_os = "linux"))]
+use crate::config::AudioBackend;
 use crate::constants::DEFAULT_SAMPLE_RATE;
 use crate::error::AudioError;
 
@@ -45,8 +65,26 @@ pub struct AudioCapture {
     /// Stream configuration
     config: StreamConfig,
     
+    /// Channel count of the produced frames (converted from the stream)
+    output_channels: u16,
+    
+    /// Sample rate of the produced frames (resampled from the stream)
+    output_rate: u32,
+    
+    /// Source stream channel of every output channel (empty = automatic)
+    channel_map: Arc<RwLock<Vec<usize>>>,
+    
     /// Start time for timestamps
     start_time: Instant,
+    
+    /// Period and latency the device delivers at
+    timing: Arc<StreamTiming>,
+    
+    /// Player of a `file:` source
+    file_player: Option<Arc<FilePlayer>>,
+    
+    /// Signal of a `generator:` source
+    signal: Option<Signal>,
 }
 
 impl AudioCapture {
@@ -59,13 +97,21 @@ impl AudioCapture {
         buffer_size: Option<u32>,
         output_buffer: SharedRingBuffer,
     ) -> Result<Self, AudioError> {
-        let device = get_device_by_id(device_id)?;
+        let file_player = file_source::file_path(device_id)
+            .map(|path| FilePlayer::open(path).map(Arc::new))
+            .transpose()?;
+        let signal = Signal::from_device_id(device_id).transpose()?;
         
-        // Get default config and override with requested settings
-        let default_config = device.default_input_config()?;
+        // A file plays at its own channel count, a generator is mono
+        let default_channels = match (&file_player, signal, channels) {
+            (Some(player), _, _) => player.channels(),
+            (None, Some(_), _) => 1,
+            (None, None, Some(channels)) => channels,
+            (None, None, None) => Self::default_channels(device_id)?,
+        };
         
         let config = StreamConfig {
-            channels: channels.unwrap_or(default_config.channels()),
+            channels: default_channels,
             sample_rate: cpal::SampleRate(sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE)),
             buffer_size: match buffer_size {
                 Some(size) => cpal::BufferSize::Fixed(size),
@@ -73,6 +119,9 @@ impl AudioCapture {
             },
         };
         
+        let output_channels = config.channels;
+        let output_rate = config.sample_rate.0;
+        
         Ok(Self {
             track_id,
             device_id: device_id.to_string(),
@@ -83,78 +132,130 @@ impl AudioCapture {
             sequence: Arc::new(AtomicU32::new(0)),
             samples_captured: Arc::new(AtomicU64::new(0)),
             config,
+            output_channels,
+            output_rate,
+            channel_map: Arc::new(RwLock::new(Vec::new())),
             start_time: Instant::now(),
+            timing: Arc::new(StreamTiming::new(output_rate)),
+            file_player,
+            signal,
         })
     }
     
+    /// Channel count the device captures by default
+    fn default_channels(device_id: &str) -> Result<u16, AudioError> {
+        #[cfg(all(feature = "pipewire", target_os = "linux"))]
+        if device::backend() == AudioBackend::Pipewire {
+            return pw_cat::input_channels(device_id)
+                .ok_or_else(|| AudioError::DeviceNotFound(device_id.to_string()));
+        }
+        
+        let device = get_device_by_id(device_id)?;
+        Ok(device.default_input_config()?.channels())
+    }
+    
     /// Start capturing audio
     pub fn start(&mut self) -> Result<(), AudioError> {
         if self.running.load(Ordering::SeqCst) {
             return Ok(());
         }
         
-        let device = get_device_by_id(&self.device_id)?;
+        if let Some(player) = self.file_player.clone() {
+            return self.start_file(player);
+        }
+        if let Some(signal) = self.signal {
+            // Generated at the track rate, no resampling
+            self.config.sample_rate = cpal::SampleRate(self.output_rate);
+            let mut generator = SignalGenerator::new(signal, self.output_rate);
+            return self.start_paced(move |out| generator.fill(out));
+        }
+        
+        #[cfg(all(feature = "pipewire", target_os = "linux"))]
+        if device::backend() == AudioBackend::Pipewire {
+            return self.start_pw_cat();
+        }
+        
+        let device = get_track_device(self.track_id, &self.device_id, true)?;
         let (error_tx, error_rx) = bounded::<AudioError>(16);
         self.error_rx = Some(error_rx);
         
+        // Devices fixed at another rate are captured at it and resampled
+        self.config.sample_rate = cpal::SampleRate(device.input_sample_rate(self.output_rate));
+        if self.config.sample_rate.0 != self.output_rate {
+            tracing::info!(
+                "Capturing {} at {} Hz, resampled to {} Hz",
+                self.device_id, self.config.sample_rate.0, self.output_rate
+            );
+        }
+        
+        // A fixed buffer is kept within what the device supports
+        let supported = device.default_input_config().ok().map(|default| *default.buffer_size());
+        let buffer_size = device::fit_buffer_size(self.config.buffer_size, supported);
+        if buffer_size != self.config.buffer_size {
+            tracing::info!(
+                "Buffer of {:?} not supported by {}, using {:?}",
+                self.config.buffer_size, self.device_id, buffer_size
+            );
+            self.config.buffer_size = buffer_size;
+        }
+        
         let running = self.running.clone();
         let running_for_loop = self.running.clone();
-        let output_buffer = self.output_buffer.clone();
-        let sequence = self.sequence.clone();
-        let samples_captured = self.samples_captured.clone();
         let config = self.config.clone();
-        let channels = self.config.channels;
-        let _sample_rate = self.config.sample_rate.0;
+        let mut on_data = self.frame_sink();
+        let timing = self.timing.clone();
         
-        // Reset counters
-        self.sequence.store(0, Ordering::SeqCst);
-        self.samples_captured.store(0, Ordering::SeqCst);
-        self.start_time = Instant::now();
-        let start_time = self.start_time;
+        // WASAPI: exclusive mode first if asked for, then shared mode
+        let exclusive = wasapi::use_exclusive(&device);
+        let stream_id = wasapi::stream_id(&device);
+        let low_latency = wasapi::low_latency_buffer(&config, supported);
         
         running.store(true, Ordering::SeqCst);
         
         let handle = thread::Builder::new()
             .name(format!("capture-track-{}", self.track_id))
             .spawn(move || {
+                if exclusive {
+                    match wasapi::capture_exclusive(&device.name, &config, &running_for_loop, &mut on_data) {
+                        Ok(()) => return,
+                        Err(e) => tracing::warn!(
+                            "Exclusive mode failed on {}: {}, falling back to shared mode",
+                            device.name, e
+                        ),
+                    }
+                }
+                
+                // For a loopback device this is the output; WASAPI records what it plays
                 let cpal_device = device.into_inner();
                 
-                let stream = cpal_device.build_input_stream(
-                    &config,
-                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                        if !running.load(Ordering::Relaxed) {
-                            return;
-                        }
-                        
-                        // Calculate timestamp
-                        let elapsed = start_time.elapsed();
-                        let timestamp = elapsed.as_micros() as u64;
-                        
-                        // Get sequence number
-                        let seq = sequence.fetch_add(1, Ordering::Relaxed);
-                        
-                        // Update sample count
-                        samples_captured.fetch_add(data.len() as u64, Ordering::Relaxed);
-                        
-                        // Create frame and push to buffer
-                        let frame = AudioFrame::new(
-                            data.to_vec(),
-                            channels,
-                            timestamp,
-                            seq,
-                        );
-                        
-                        // Push to ring buffer (may fail on overflow)
-                        let _ = output_buffer.push(frame);
-                    },
-                    move |err| {
-                        let _ = error_tx.try_send(AudioError::StreamError(err.to_string()));
-                    },
-                    None,
-                );
+                // Shared by the tries of `build_shared`
+                let on_data = Arc::new(Mutex::new(on_data));
+                let built = wasapi::build_shared(&stream_id, &config, low_latency, |config| {
+                    let running = running.clone();
+                    let on_data = on_data.clone();
+                    let timing = timing.clone();
+                    let error_tx = error_tx.clone();
+                    cpal_device.build_input_stream(
+                        config,
+                        move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                            if !running.load(Ordering::Relaxed) {
+                                return;
+                            }
+                            let timestamp = info.timestamp();
+                            if let Some(latency) = timestamp.callback.duration_since(&timestamp.capture) {
+                                timing.record_latency(latency);
+                            }
+                            (on_data.lock())(data);
+                        },
+                        move |err| {
+                            let _ = error_tx.try_send(AudioError::StreamError(err.to_string()));
+                        },
+                        None,
+                    )
+                });
                 
-                match stream {
-                    Ok(stream) => {
+                match built {
+                    Ok((stream, _mode)) => {
                         if let Err(e) = stream.play() {
                             tracing::error!("Failed to start stream: {}", e);
                             return;
@@ -178,6 +279,152 @@ impl AudioCapture {
         Ok(())
     }
     
+    /// Start capturing through a PipeWire node of this track (`pw-cat`)
+    #[cfg(all(feature = "pipewire", target_os = "linux"))]
+    fn start_pw_cat(&mut self) -> Result<(), AudioError> {
+        let (error_tx, error_rx) = bounded::<AudioError>(16);
+        self.error_rx = Some(error_rx);
+        
+        let running = self.running.clone();
+        let track_id = self.track_id;
+        let device_id = self.device_id.clone();
+        let sample_rate = self.config.sample_rate.0;
+        let channels = self.config.channels;
+        let on_data = self.frame_sink();
+        
+        running.store(true, Ordering::SeqCst);
+        
+        let handle = thread::Builder::new()
+            .name(format!("capture-track-{}", self.track_id))
+            .spawn(move || {
+                let result = pw_cat::capture(track_id, &device_id, sample_rate, channels, &running, on_data);
+                if let Err(e) = result {
+                    tracing::error!("PipeWire capture of track {} stopped: {}", track_id, e);
+                    let _ = error_tx.try_send(e);
+                }
+            })
+            .map_err(|e| AudioError::StreamError(e.to_string()))?;
+        
+        self.thread_handle = Some(handle);
+        Ok(())
+    }
+    
+    /// Start feeding a decoded file in real time
+    fn start_file(&mut self, player: Arc<FilePlayer>) -> Result<(), AudioError> {
+        // The file is captured at its own rate and resampled
+        self.config.sample_rate = cpal::SampleRate(player.sample_rate());
+        if self.config.sample_rate.0 != self.output_rate {
+            tracing::info!(
+                "Playing {} at {} Hz, resampled to {} Hz",
+                self.device_id, self.config.sample_rate.0, self.output_rate
+            );
+        }
+        
+        self.start_paced(move |out| player.read(out))
+    }
+    
+    /// Start pulling samples of the stream configuration from `source`
+    /// at the pace of the system clock
+    fn start_paced(&mut self, mut source: impl FnMut(&mut [f32]) + Send + 'static) -> Result<(), AudioError> {
+        let running = self.running.clone();
+        let rate = self.config.sample_rate.0 as u64;
+        let channels = self.config.channels as usize;
+        let mut on_data = self.frame_sink();
+        
+        running.store(true, Ordering::SeqCst);
+        
+        let handle = thread::Builder::new()
+            .name(format!("source-track-{}", self.track_id))
+            .spawn(move || {
+                let start = Instant::now();
+                let mut emitted = 0u64;
+                let mut samples = Vec::new();
+                
+                while running.load(Ordering::Relaxed) {
+                    // Emit what is due by the clock, at most 250 ms after a stall
+                    let due = start.elapsed().as_micros() as u64 * rate / 1_000_000;
+                    let frames = (due - emitted).min(rate / 4) as usize;
+                    emitted = due;
+                    if frames > 0 {
+                        samples.resize(frames * channels, 0.0);
+                        source(&mut samples);
+                        on_data(&samples);
+                    }
+                    thread::sleep(Duration::from_millis(5));
+                }
+            })
+            .map_err(|e| AudioError::StreamError(e.to_string()))?;
+        
+        self.thread_handle = Some(handle);
+        Ok(())
+    }
+    
+    /// Reset the counters and build the handler turning captured device
+    /// samples into frames in the output buffer
+    fn frame_sink(&mut self) -> impl FnMut(&[f32]) + Send + 'static {
+        let output_buffer = self.output_buffer.clone();
+        let sequence = self.sequence.clone();
+        let samples_captured = self.samples_captured.clone();
+        let channels = self.config.channels;
+        let output_channels = self.output_channels;
+        let channel_map = self.channel_map.clone();
+        let timing = self.timing.clone();
+        timing.reset(self.config.sample_rate.0);
+        let mut resampler = (self.config.sample_rate.0 != self.output_rate)
+            .then(|| Resampler::new(self.config.sample_rate.0, self.output_rate, output_channels as usize));
+        
+        // Reset counters
+        self.sequence.store(0, Ordering::SeqCst);
+        self.samples_captured.store(0, Ordering::SeqCst);
+        self.start_time = Instant::now();
+        let start_time = self.start_time;
+        
+        move |data: &[f32]| {
+            // Calculate timestamp
+            let elapsed = start_time.elapsed();
+            let timestamp = elapsed.as_micros() as u64;
+            
+            // Update sample count
+            samples_captured.fetch_add(data.len() as u64, Ordering::Relaxed);
+            timing.record_period(data.len() / (channels as usize).max(1));
+            
+            // Convert to the track layout
+            let map = channel_map.read();
+            let mut samples = if is_passthrough(channels as usize, output_channels as usize, &map) {
+                pool::samples().take_copy(data)
+            } else {
+                convert_channels(data, channels as usize, output_channels as usize, &map)
+            };
+            drop(map);
+            
+            // Convert to the track rate
+            if let Some(ref mut resampler) = resampler {
+                let mut resampled = pool::samples().take(samples.len() + samples.len() / 8);
+                resampler.process(&samples, &mut resampled);
+                pool::samples().recycle(samples);
+                if resampled.is_empty() {
+                    pool::samples().recycle(resampled);
+                    return;
+                }
+                samples = resampled;
+            }
+            
+            // Get sequence number
+            let seq = sequence.fetch_add(1, Ordering::Relaxed);
+            
+            // Create frame and push to buffer
+            let frame = AudioFrame::new(
+                samples,
+                output_channels,
+                timestamp,
+                seq,
+            );
+            
+            // Push to ring buffer (may fail on overflow)
+            let _ = output_buffer.push(frame);
+        }
+    }
+    
     /// Stop capturing audio
     pub fn stop(&mut self) {
         self.running.store(false, Ordering::SeqCst);
@@ -207,16 +454,48 @@ impl AudioCapture {
         &self.config
     }
     
+    /// Get the period and latency the device delivers at
+    pub fn timing(&self) -> &Arc<StreamTiming> {
+        &self.timing
+    }
+    
     /// Get sample rate
     pub fn sample_rate(&self) -> u32 {
+        self.output_rate
+    }
+    
+    /// Get the rate the device stream runs at (differs from
+    /// [`sample_rate`](Self::sample_rate) when the device is resampled)
+    pub fn device_sample_rate(&self) -> u32 {
         self.config.sample_rate.0
     }
     
-    /// Get channel count
+    /// Get channel count of the device stream
     pub fn channels(&self) -> u16 {
         self.config.channels
     }
     
+    /// Get channel count of the produced frames
+    pub fn output_channels(&self) -> u16 {
+        self.output_channels
+    }
+    
+    /// Set channel count of the produced frames (applied on next start)
+    pub fn set_output_channels(&mut self, channels: u16) {
+        self.output_channels = channels.max(1);
+    }
+    
+    /// Set the source stream channel of every output channel
+    /// (empty = automatic up/down-mix, applied while running)
+    pub fn set_channel_map(&self, map: Vec<usize>) {
+        *self.channel_map.write() = map;
+    }
+    
+    /// Get the player of a `file:` source
+    pub fn file_player(&self) -> Option<Arc<FilePlayer>> {
+        self.file_player.clone()
+    }
+    
     /// Check for errors
     pub fn check_errors(&self) -> Option<AudioError> {
         self.error_rx.as_ref().and_then(|rx| rx.try_recv().ok())
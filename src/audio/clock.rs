@@ -0,0 +1,246 @@
+//! Host clock vs audio clock skew monitor
+//!
+//! Compares the number of frames consumed by an output callback with the
+//! host monotonic clock. The result (in ppm) shows how far the device's
+//! crystal is from its nominal sample rate, which is what drift
+//! compensation has to correct and a quick way to spot misbehaving
+//! USB interfaces.
+//!
+//! [`StreamTiming`] keeps what the host actually gave a stream: the
+//! period (frames per callback) and the latency between the device and
+//! the callback, which can differ from the buffer size a track asked for.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Marker for "not started yet"
+const UNSET: u64 = u64::MAX;
+
+/// Callbacks during the first 500 ms are ignored (device start-up burst)
+const WARMUP_US: u64 = 500_000;
+
+/// Minimum measurement window before a skew value is reported
+const MIN_WINDOW_US: u64 = 2_000_000;
+
+/// Lock-free skew monitor, safe to update from the audio callback
+#[derive(Debug)]
+pub struct ClockSkewMonitor {
+    /// Nominal device sample rate
+    nominal_rate: u32,
+    /// Reference point for all timestamps
+    base: Instant,
+    /// Time of the very first callback
+    first_callback_us: AtomicU64,
+    /// Start of the measurement window (after warm-up)
+    window_start_us: AtomicU64,
+    /// Frame count at the start of the window
+    window_start_frames: AtomicU64,
+    /// Time of the latest callback
+    last_callback_us: AtomicU64,
+    /// Total frames consumed
+    frames: AtomicU64,
+}
+
+impl ClockSkewMonitor {
+    pub fn new(nominal_rate: u32) -> Self {
+        Self {
+            nominal_rate,
+            base: Instant::now(),
+            first_callback_us: AtomicU64::new(UNSET),
+            window_start_us: AtomicU64::new(UNSET),
+            window_start_frames: AtomicU64::new(0),
+            last_callback_us: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+        }
+    }
+
+    /// Record frames consumed by one callback (call from the audio thread)
+    pub fn record_frames(&self, frames: usize) {
+        let now_us = self.base.elapsed().as_micros() as u64;
+        self.record_frames_at(frames, now_us);
+    }
+
+    fn record_frames_at(&self, frames: usize, now_us: u64) {
+        let total = self.frames.fetch_add(frames as u64, Ordering::Relaxed) + frames as u64;
+
+        let first = self.first_callback_us.load(Ordering::Relaxed);
+        if first == UNSET {
+            self.first_callback_us.store(now_us, Ordering::Relaxed);
+        } else if self.window_start_us.load(Ordering::Relaxed) == UNSET
+            && now_us.saturating_sub(first) >= WARMUP_US
+        {
+            // Callbacks k+1..n together cover the interval [t_k, t_n)
+            self.window_start_frames.store(total, Ordering::Relaxed);
+            self.window_start_us.store(now_us, Ordering::Release);
+        }
+
+        self.last_callback_us.store(now_us, Ordering::Release);
+    }
+
+    /// Measured device rate in frames per second (None until enough data)
+    pub fn measured_rate(&self) -> Option<f64> {
+        let start_us = self.window_start_us.load(Ordering::Acquire);
+        if start_us == UNSET {
+            return None;
+        }
+
+        let last_us = self.last_callback_us.load(Ordering::Acquire);
+        let elapsed_us = last_us.saturating_sub(start_us);
+        if elapsed_us < MIN_WINDOW_US {
+            return None;
+        }
+
+        let frames = self.frames.load(Ordering::Relaxed);
+        let start_frames = self.window_start_frames.load(Ordering::Relaxed);
+        let counted = frames.saturating_sub(start_frames) as f64;
+
+        Some(counted / (elapsed_us as f64 / 1_000_000.0))
+    }
+
+    /// Skew of the device clock relative to the host clock, in ppm.
+    /// Positive means the device consumes samples faster than nominal.
+    pub fn skew_ppm(&self) -> Option<f64> {
+        self.rate_ratio().map(|ratio| (ratio - 1.0) * 1_000_000.0)
+    }
+
+    /// Ratio measured / nominal rate (the playout scheduler releases
+    /// frames at this rate)
+    pub fn rate_ratio(&self) -> Option<f64> {
+        if self.nominal_rate == 0 {
+            return None;
+        }
+        self.measured_rate().map(|rate| rate / self.nominal_rate as f64)
+    }
+
+    /// Nominal sample rate
+    pub fn nominal_rate(&self) -> u32 {
+        self.nominal_rate
+    }
+
+    /// Restart measurement (e.g. after the stream was restarted)
+    pub fn reset(&self) {
+        self.first_callback_us.store(UNSET, Ordering::Relaxed);
+        self.window_start_us.store(UNSET, Ordering::Relaxed);
+        self.window_start_frames.store(0, Ordering::Relaxed);
+        self.last_callback_us.store(0, Ordering::Relaxed);
+        self.frames.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Period and latency of an open device stream, updated from its callback
+#[derive(Debug)]
+pub struct StreamTiming {
+    /// Rate of the device stream
+    sample_rate: AtomicU32,
+    /// Frames of the latest callback (0 = no callback yet)
+    period_frames: AtomicU32,
+    /// Latency reported by the host in µs (UNSET = not reported)
+    latency_us: AtomicU64,
+}
+
+impl StreamTiming {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: AtomicU32::new(sample_rate),
+            period_frames: AtomicU32::new(0),
+            latency_us: AtomicU64::new(UNSET),
+        }
+    }
+
+    /// Record the frames of one callback (call from the audio thread)
+    pub fn record_period(&self, frames: usize) {
+        self.period_frames.store(frames as u32, Ordering::Relaxed);
+    }
+
+    /// Record the time between the device and the callback: when the
+    /// input was captured, or until the output will be played
+    pub fn record_latency(&self, latency: Duration) {
+        self.latency_us.store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Frames per callback (None until the first callback)
+    pub fn period_frames(&self) -> Option<u32> {
+        Some(self.period_frames.load(Ordering::Relaxed)).filter(|&frames| frames > 0)
+    }
+
+    /// Duration of a callback period in ms
+    pub fn period_ms(&self) -> Option<f32> {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        if sample_rate == 0 {
+            return None;
+        }
+        self.period_frames()
+            .map(|frames| frames as f32 * 1000.0 / sample_rate as f32)
+    }
+
+    /// Latency reported by the host in ms (None if the host does not report it)
+    pub fn latency_ms(&self) -> Option<f32> {
+        let latency_us = self.latency_us.load(Ordering::Relaxed);
+        (latency_us != UNSET).then(|| latency_us as f32 / 1000.0)
+    }
+
+    /// Forget the recorded values (the stream was reopened, maybe at another rate)
+    pub fn reset(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.period_frames.store(0, Ordering::Relaxed);
+        self.latency_us.store(UNSET, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulate callbacks of `period` frames on a device running at `actual_rate`
+    fn simulate(monitor: &ClockSkewMonitor, actual_rate: f64, period: usize, seconds: f64) {
+        let callbacks = (seconds * actual_rate / period as f64) as usize;
+        for i in 0..callbacks {
+            let now_us = (i as f64 * period as f64 / actual_rate * 1_000_000.0) as u64;
+            monitor.record_frames_at(period, now_us);
+        }
+    }
+
+    #[test]
+    fn test_no_skew_before_window() {
+        let monitor = ClockSkewMonitor::new(48000);
+        assert!(monitor.skew_ppm().is_none());
+
+        simulate(&monitor, 48000.0, 480, 1.0);
+        assert!(monitor.skew_ppm().is_none());
+    }
+
+    #[test]
+    fn test_skew_measurement() {
+        let nominal = ClockSkewMonitor::new(48000);
+        simulate(&nominal, 48000.0, 480, 10.0);
+        assert!(nominal.skew_ppm().unwrap().abs() < 1.0);
+
+        // Device running 100 ppm fast
+        let fast = ClockSkewMonitor::new(48000);
+        simulate(&fast, 48004.8, 480, 10.0);
+        let ppm = fast.skew_ppm().unwrap();
+        assert!((ppm - 100.0).abs() < 5.0, "measured {} ppm", ppm);
+
+        fast.reset();
+        assert!(fast.skew_ppm().is_none());
+    }
+
+    #[test]
+    fn test_stream_timing() {
+        let timing = StreamTiming::new(48000);
+        assert!(timing.period_ms().is_none());
+        assert!(timing.latency_ms().is_none());
+
+        timing.record_period(240);
+        timing.record_latency(Duration::from_micros(7_500));
+        assert_eq!(timing.period_frames(), Some(240));
+        assert!((timing.period_ms().unwrap() - 5.0).abs() < 1e-6);
+        assert!((timing.latency_ms().unwrap() - 7.5).abs() < 1e-6);
+
+        timing.reset(44100);
+        assert!(timing.period_frames().is_none());
+        timing.record_period(441);
+        assert!((timing.period_ms().unwrap() - 10.0).abs() < 1e-6);
+        assert!(timing.latency_ms().is_none());
+    }
+}
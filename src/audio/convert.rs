@@ -0,0 +1,153 @@
+//! Channel mapping and up/down-mixing
+//!
+//! A channel map lists, for every output channel, the input channel it is
+//! taken from: `[0, 0]` plays a mono microphone on both sides of a stereo
+//! output, `[1, 0]` swaps left and right, `[2, 3]` picks the second pair
+//! of a multichannel interface. An empty map converts automatically:
+//!
+//! - mono is copied to every output channel
+//! - everything is averaged when going to mono
+//! - 5.1 and 7.1 (SMPTE order: L R C LFE Ls Rs [Lb Rb]) are folded down to
+//!   stereo with the centre and surrounds at -3 dB, LFE dropped
+//! - other layouts keep the channels both sides have and leave the rest
+//!   silent
+
+use crate::error::AudioError;
+
+/// Highest channel count a device stream is expected to have
+pub const MAX_CHANNELS: usize = 8;
+
+/// Level of the centre and surround channels in a stereo fold-down (-3 dB)
+const FOLD_DOWN_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Check that a channel map only refers to channels a device can have
+pub fn validate_channel_map(map: &[usize]) -> Result<(), AudioError> {
+    if map.len() > MAX_CHANNELS {
+        return Err(AudioError::UnsupportedFormat(format!(
+            "Channel map has {} entries, at most {} supported",
+            map.len(),
+            MAX_CHANNELS
+        )));
+    }
+    if let Some(&channel) = map.iter().find(|&&channel| channel >= MAX_CHANNELS) {
+        return Err(AudioError::UnsupportedFormat(format!(
+            "Channel map refers to channel {}, at most {} channels supported",
+            channel, MAX_CHANNELS
+        )));
+    }
+    Ok(())
+}
+
+/// Check whether converting between these layouts changes the samples
+pub fn is_passthrough(from: usize, to: usize, map: &[usize]) -> bool {
+    if map.is_empty() || map.len() != to {
+        return from == to;
+    }
+    from == to && map.iter().enumerate().all(|(out, &src)| out == src)
+}
+
+/// Convert interleaved samples from `from` to `to` channels.
+/// An explicit `map` is used when it has one entry per output channel
+/// (sources the input does not have play silence); otherwise the layouts
+/// are converted automatically.
+pub fn convert_channels(samples: &[f32], from: usize, to: usize, map: &[usize]) -> Vec<f32> {
+    let from = from.max(1);
+    let to = to.max(1);
+    if is_passthrough(from, to, map) {
+        return samples.to_vec();
+    }
+
+    let frames = samples.len() / from;
+    let mut out = Vec::with_capacity(frames * to);
+
+    if !map.is_empty() && map.len() == to {
+        for frame in samples.chunks_exact(from) {
+            out.extend(map.iter().map(|&src| frame.get(src).copied().unwrap_or(0.0)));
+        }
+        return out;
+    }
+
+    match (from, to) {
+        (1, _) => {
+            for &sample in samples {
+                out.extend(std::iter::repeat_n(sample, to));
+            }
+        }
+        (_, 1) => {
+            for frame in samples.chunks_exact(from) {
+                out.push(frame.iter().sum::<f32>() / from as f32);
+            }
+        }
+        (6 | 8, 2) => {
+            // Normalised so a full-scale signal on every channel does not clip
+            let norm = 1.0 / (1.0 + FOLD_DOWN_GAIN * if from == 8 { 3.0 } else { 2.0 });
+            for frame in samples.chunks_exact(from) {
+                let centre = frame[2] * FOLD_DOWN_GAIN;
+                let mut left = frame[0] + centre + frame[4] * FOLD_DOWN_GAIN;
+                let mut right = frame[1] + centre + frame[5] * FOLD_DOWN_GAIN;
+                if from == 8 {
+                    left += frame[6] * FOLD_DOWN_GAIN;
+                    right += frame[7] * FOLD_DOWN_GAIN;
+                }
+                out.push(left * norm);
+                out.push(right * norm);
+            }
+        }
+        _ => {
+            for frame in samples.chunks_exact(from) {
+                out.extend((0..to).map(|ch| frame.get(ch).copied().unwrap_or(0.0)));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_automatic_conversion() {
+        // Mono microphone on a stereo output
+        assert_eq!(convert_channels(&[0.1, 0.2], 1, 2, &[]), vec![0.1, 0.1, 0.2, 0.2]);
+        // Stereo to mono averages
+        assert_eq!(convert_channels(&[0.25, 0.75, 0.5, 1.0], 2, 1, &[]), vec![0.5, 0.75]);
+        // Stereo on a quad output: rear channels silent
+        assert_eq!(convert_channels(&[0.5, 0.25], 2, 4, &[]), vec![0.5, 0.25, 0.0, 0.0]);
+        assert_eq!(convert_channels(&[0.5, 0.25], 2, 2, &[]), vec![0.5, 0.25]);
+    }
+
+    #[test]
+    fn test_surround_fold_down() {
+        // L R C LFE Ls Rs
+        let frame = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let out = convert_channels(&frame, 6, 2, &[]);
+        assert_eq!(out.len(), 2);
+        assert!(out[0] > 0.0 && out[1] == 0.0, "{:?}", out);
+
+        // Centre splits equally, LFE is dropped
+        let out = convert_channels(&[0.0, 0.0, 1.0, 1.0, 0.0, 0.0], 6, 2, &[]);
+        assert!((out[0] - out[1]).abs() < 1e-6);
+
+        // Full scale on every channel stays within range
+        let out = convert_channels(&[1.0; 16], 8, 2, &[]);
+        assert!(out.iter().all(|&s| s <= 1.0 + 1e-6), "{:?}", out);
+    }
+
+    #[test]
+    fn test_explicit_map() {
+        // Swap left and right
+        assert_eq!(convert_channels(&[0.1, 0.2], 2, 2, &[1, 0]), vec![0.2, 0.1]);
+        // Second input of a 4-channel interface, duplicated
+        assert_eq!(convert_channels(&[0.1, 0.2, 0.3, 0.4], 4, 2, &[1, 1]), vec![0.2, 0.2]);
+        // Missing source channel is silent
+        assert_eq!(convert_channels(&[0.5], 1, 2, &[0, 3]), vec![0.5, 0.0]);
+        // A map that does not fit the output falls back to automatic
+        assert_eq!(convert_channels(&[0.5], 1, 2, &[0]), vec![0.5, 0.5]);
+
+        assert!(is_passthrough(2, 2, &[0, 1]));
+        assert!(!is_passthrough(2, 2, &[1, 0]));
+        assert!(validate_channel_map(&[0, 7]).is_ok());
+        assert!(validate_channel_map(&[0, 8]).is_err());
+    }
+}
@@ -0,0 +1,754 @@
+//! Decoding of audio files for the file player
+//!
+//! WAV (8 to 32-bit integer PCM, 32/64-bit float, WAVE_FORMAT_EXTENSIBLE)
+//! and FLAC are decoded whole into interleaved f32 samples. MP3 files are
+//! recognised and rejected with a hint: this build has no MP3 decoder.
+
+use std::path::Path;
+
+use crate::error::AudioError;
+
+/// Longest file decoded (the samples are held in memory)
+pub const MAX_DURATION_SECS: u64 = 30 * 60;
+
+/// Decoded file contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Interleaved samples in -1.0..1.0
+    pub samples: Vec<f32>,
+}
+
+impl DecodedAudio {
+    /// Length in frames (samples per channel)
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+}
+
+/// Read and decode a WAV or FLAC file
+pub fn decode_file(path: &Path) -> Result<DecodedAudio, AudioError> {
+    let data = std::fs::read(path)
+        .map_err(|e| AudioError::DeviceNotFound(format!("{}: {}", path.display(), e)))?;
+    decode(&data)
+}
+
+/// Decode the contents of a WAV or FLAC file
+pub fn decode(data: &[u8]) -> Result<DecodedAudio, AudioError> {
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        decode_wav(data)
+    } else if data.starts_with(b"fLaC") {
+        decode_flac(data)
+    } else if data.starts_with(b"ID3") || (data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0) {
+        Err(invalid("MP3 is not supported, convert the file to WAV or FLAC"))
+    } else {
+        Err(invalid("not a WAV or FLAC file"))
+    }
+}
+
+fn invalid(message: &str) -> AudioError {
+    AudioError::UnsupportedFormat(message.to_string())
+}
+
+fn check_duration(frames: u64, sample_rate: u32) -> Result<(), AudioError> {
+    if frames > MAX_DURATION_SECS * sample_rate as u64 {
+        return Err(AudioError::UnsupportedFormat(format!(
+            "files longer than {} minutes are not played",
+            MAX_DURATION_SECS / 60
+        )));
+    }
+    Ok(())
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// Format tags of the `fmt ` chunk
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+struct WavFormat {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+fn decode_wav(data: &[u8]) -> Result<DecodedAudio, AudioError> {
+    let mut format = None;
+    let mut pos = 12;
+
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let size = u32_at(data, pos + 4) as usize;
+        let body = pos + 8;
+
+        match id {
+            b"fmt " => {
+                if size < 16 || body + 16 > data.len() {
+                    return Err(invalid("WAV format chunk is too short"));
+                }
+                let mut tag = u16_at(data, body);
+                if tag == WAVE_FORMAT_EXTENSIBLE && size >= 26 && body + 26 <= data.len() {
+                    // The sub-format GUID starts with the plain format tag
+                    tag = u16_at(data, body + 24);
+                }
+                format = Some(WavFormat {
+                    tag,
+                    channels: u16_at(data, body + 2),
+                    sample_rate: u32_at(data, body + 4),
+                    bits: u16_at(data, body + 14),
+                });
+            }
+            b"data" => {
+                let format = format.ok_or_else(|| invalid("WAV data chunk before the format chunk"))?;
+                // A size of 0 or past the end is a file whose writer never
+                // finished it (e.g. a recording cut by a crash): read to the end
+                let end = if size == 0 || body + size > data.len() {
+                    data.len()
+                } else {
+                    body + size
+                };
+                return wav_samples(&format, &data[body..end]);
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        pos = body.saturating_add(size).saturating_add(size & 1);
+    }
+
+    Err(invalid("WAV file has no data chunk"))
+}
+
+fn wav_samples(format: &WavFormat, data: &[u8]) -> Result<DecodedAudio, AudioError> {
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err(invalid("WAV file has no channels or sample rate"));
+    }
+
+    let bytes = format.bits.div_ceil(8) as usize;
+    let frame_bytes = bytes * format.channels as usize;
+    let frames = data.len() / frame_bytes.max(1);
+    check_duration(frames as u64, format.sample_rate)?;
+    let data = &data[..frames * frame_bytes];
+
+    let samples = match (format.tag, bytes) {
+        (WAVE_FORMAT_PCM, 1) => data.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+        (WAVE_FORMAT_PCM, 2..=4) => {
+            // Samples are left-justified in their container
+            let scale = 1.0 / (1u64 << (bytes * 8 - 1)) as f32;
+            data.chunks_exact(bytes)
+                .map(|sample| {
+                    let mut word = [0u8; 4];
+                    word[4 - bytes..].copy_from_slice(sample);
+                    (i32::from_le_bytes(word) >> ((4 - bytes) * 8)) as f32 * scale
+                })
+                .collect()
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 4) => data
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 8) => data
+            .chunks_exact(8)
+            .map(|sample| f64::from_le_bytes(sample.try_into().unwrap()) as f32)
+            .collect(),
+        _ => {
+            return Err(AudioError::UnsupportedFormat(format!(
+                "WAV format {} with {} bits per sample",
+                format.tag, format.bits
+            )))
+        }
+    };
+
+    Ok(DecodedAudio {
+        sample_rate: format.sample_rate,
+        channels: format.channels,
+        samples,
+    })
+}
+
+/// MSB-first reader of a FLAC bitstream
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn eof() -> AudioError {
+        invalid("FLAC stream ends inside a frame")
+    }
+
+    fn read(&mut self, mut bits: u32) -> Result<u64, AudioError> {
+        if self.pos + bits as usize > self.data.len() * 8 {
+            return Err(Self::eof());
+        }
+        let mut value = 0u64;
+        while bits > 0 {
+            let byte = self.data[self.pos / 8] as u16;
+            let available = 8 - (self.pos % 8) as u32;
+            let take = available.min(bits);
+            let chunk = (byte >> (available - take)) & ((1 << take) - 1);
+            value = (value << take) | chunk as u64;
+            bits -= take;
+            self.pos += take as usize;
+        }
+        Ok(value)
+    }
+
+    fn read_signed(&mut self, bits: u32) -> Result<i64, AudioError> {
+        if bits == 0 {
+            return Ok(0);
+        }
+        let value = self.read(bits)?;
+        let shift = 64 - bits;
+        Ok(((value << shift) as i64) >> shift)
+    }
+
+    fn read_bit(&mut self) -> Result<bool, AudioError> {
+        Ok(self.read(1)? == 1)
+    }
+
+    /// Count zero bits up to the next one
+    fn read_unary(&mut self) -> Result<u32, AudioError> {
+        let mut count = 0;
+        loop {
+            let Some(&byte) = self.data.get(self.pos / 8) else {
+                return Err(Self::eof());
+            };
+            let offset = (self.pos % 8) as u32;
+            let rest = byte << offset;
+            if rest == 0 {
+                count += 8 - offset;
+                self.pos += (8 - offset) as usize;
+            } else {
+                let zeros = rest.leading_zeros();
+                count += zeros;
+                self.pos += zeros as usize + 1;
+                return Ok(count);
+            }
+        }
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.pos / 8
+    }
+}
+
+/// STREAMINFO fields the decoder needs
+struct StreamInfo {
+    sample_rate: u32,
+    channels: u16,
+    bits: u32,
+}
+
+fn decode_flac(data: &[u8]) -> Result<DecodedAudio, AudioError> {
+    // Metadata blocks
+    let mut pos = 4;
+    let mut info = None;
+    loop {
+        if pos + 4 > data.len() {
+            return Err(invalid("FLAC metadata is cut short"));
+        }
+        let header = data[pos];
+        let length = u32::from_be_bytes([0, data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let body = pos + 4;
+        if header & 0x7F == 0 {
+            if length < 18 || body + 18 > data.len() {
+                return Err(invalid("FLAC STREAMINFO is too short"));
+            }
+            let mut reader = BitReader::new(&data[body + 10..body + 18]);
+            let sample_rate = reader.read(20)? as u32;
+            let channels = reader.read(3)? as u16 + 1;
+            let bits = reader.read(5)? as u32 + 1;
+            let total_frames = reader.read(36)?;
+            if sample_rate == 0 {
+                return Err(invalid("FLAC stream has no sample rate"));
+            }
+            check_duration(total_frames, sample_rate)?;
+            info = Some(StreamInfo { sample_rate, channels, bits });
+        }
+        pos = body + length;
+        if header & 0x80 != 0 {
+            break;
+        }
+    }
+    let info = info.ok_or_else(|| invalid("FLAC stream has no STREAMINFO"))?;
+
+    let mut samples = Vec::new();
+    let mut reader = BitReader::new(&data[pos.min(data.len())..]);
+    let mut decoded_frames = 0u64;
+    let mut channel_buffers: Vec<Vec<i64>> = Vec::new();
+
+    while reader.byte_pos() + 2 <= reader.data.len() {
+        match decode_flac_frame(&mut reader, &info, &mut channel_buffers) {
+            Ok(block_size) => {
+                decoded_frames += block_size as u64;
+                check_duration(decoded_frames, info.sample_rate)?;
+                interleave(&channel_buffers, block_size, info.bits, &mut samples);
+            }
+            // A file cut short loses its last frame only
+            Err(e) if decoded_frames > 0 => {
+                tracing::warn!("FLAC decoding stopped after {} frames: {}", decoded_frames, e);
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(DecodedAudio {
+        sample_rate: info.sample_rate,
+        channels: info.channels,
+        samples,
+    })
+}
+
+/// Decode a single FLAC frame sent without a stream header (lossless
+/// network tracks, see `codec::flac`) into interleaved samples
+pub(crate) fn decode_flac_block(data: &[u8], channels: u16, bits: u32) -> Result<Vec<f32>, AudioError> {
+    // Frames of network tracks carry their sample rate and size
+    let info = StreamInfo { sample_rate: 0, channels, bits };
+    let mut channel_buffers = Vec::new();
+    let block_size = decode_flac_frame(&mut BitReader::new(data), &info, &mut channel_buffers)?;
+    let mut samples = Vec::with_capacity(block_size * channels as usize);
+    interleave(&channel_buffers, block_size, bits, &mut samples);
+    Ok(samples)
+}
+
+/// Append decoded channels as interleaved samples in -1.0..1.0
+fn interleave(channels: &[Vec<i64>], block_size: usize, bits: u32, out: &mut Vec<f32>) {
+    let scale = 1.0 / (1u64 << (bits - 1)) as f32;
+    for i in 0..block_size {
+        for channel in channels {
+            out.push(channel[i] as f32 * scale);
+        }
+    }
+}
+
+/// Decode one frame into `channels`, returning its block size
+fn decode_flac_frame(
+    reader: &mut BitReader,
+    info: &StreamInfo,
+    channels: &mut Vec<Vec<i64>>,
+) -> Result<usize, AudioError> {
+    if reader.read(14)? != 0x3FFE {
+        return Err(invalid("FLAC frame sync lost"));
+    }
+    // Reserved bit and blocking strategy
+    reader.read(2)?;
+    let block_code = reader.read(4)?;
+    let rate_code = reader.read(4)?;
+    let assignment = reader.read(4)?;
+    let size_code = reader.read(3)?;
+    reader.read(1)?;
+
+    // UTF-8 coded frame or sample number
+    let first = reader.read(8)?;
+    let continuation = (first as u8).leading_ones().saturating_sub(1);
+    for _ in 0..continuation {
+        reader.read(8)?;
+    }
+
+    let block_size = match block_code {
+        1 => 192,
+        2..=5 => 576 << (block_code - 2),
+        6 => reader.read(8)? as usize + 1,
+        7 => reader.read(16)? as usize + 1,
+        8..=15 => 256 << (block_code - 8),
+        _ => return Err(invalid("FLAC frame has a reserved block size")),
+    };
+    match rate_code {
+        12 => {
+            reader.read(8)?;
+        }
+        13 | 14 => {
+            reader.read(16)?;
+        }
+        15 => return Err(invalid("FLAC frame has an invalid sample rate")),
+        _ => {}
+    }
+    let bits = match size_code {
+        0 => info.bits,
+        1 => 8,
+        2 => 12,
+        4 => 16,
+        5 => 20,
+        6 => 24,
+        7 => 32,
+        _ => return Err(invalid("FLAC frame has a reserved sample size")),
+    };
+    // CRC-8 of the header
+    reader.read(8)?;
+
+    let channel_count = match assignment {
+        0..=7 => assignment as usize + 1,
+        8..=10 => 2,
+        _ => return Err(invalid("FLAC frame has a reserved channel assignment")),
+    };
+    if channel_count != info.channels as usize {
+        return Err(invalid("FLAC frame channel count differs from STREAMINFO"));
+    }
+
+    channels.resize_with(channel_count, Vec::new);
+    for (index, channel) in channels.iter_mut().enumerate() {
+        // The side channel carries one extra bit
+        let side = matches!((assignment, index), (8, 1) | (9, 0) | (10, 1));
+        decode_subframe(reader, block_size, bits + side as u32, channel)?;
+    }
+
+    // Stereo decorrelation
+    if assignment >= 8 {
+        let (left, right) = channels.split_at_mut(1);
+        for (a, b) in left[0].iter_mut().zip(right[0].iter_mut()) {
+            match assignment {
+                // Left and side
+                8 => *b = *a - *b,
+                // Side and right
+                9 => *a += *b,
+                // Mid and side
+                _ => {
+                    let side = *b;
+                    let mid = (*a << 1) | (side & 1);
+                    *a = (mid + side) >> 1;
+                    *b = (mid - side) >> 1;
+                }
+            }
+        }
+    }
+
+    // Padding and the CRC-16 of the frame
+    reader.align();
+    reader.read(16)?;
+
+    Ok(block_size)
+}
+
+fn decode_subframe(
+    reader: &mut BitReader,
+    block_size: usize,
+    bits: u32,
+    out: &mut Vec<i64>,
+) -> Result<(), AudioError> {
+    out.clear();
+    if reader.read_bit()? {
+        return Err(invalid("FLAC subframe padding bit is set"));
+    }
+    let kind = reader.read(6)?;
+    let wasted = if reader.read_bit()? {
+        reader.read_unary()? + 1
+    } else {
+        0
+    };
+    let bits = bits.checked_sub(wasted).filter(|&bits| bits > 0)
+        .ok_or_else(|| invalid("FLAC subframe wastes every bit"))?;
+
+    match kind {
+        // Constant
+        0 => {
+            let value = reader.read_signed(bits)?;
+            out.resize(block_size, value);
+        }
+        // Verbatim
+        1 => {
+            for _ in 0..block_size {
+                out.push(reader.read_signed(bits)?);
+            }
+        }
+        // Fixed predictor
+        8..=12 => {
+            let order = (kind - 8) as usize;
+            decode_warmup(reader, order, bits, block_size, out)?;
+            decode_residual(reader, block_size, order, out)?;
+            restore_fixed(out, order);
+        }
+        // Linear predictor
+        32..=63 => {
+            let order = (kind - 31) as usize;
+            decode_warmup(reader, order, bits, block_size, out)?;
+            let precision = reader.read(4)? as u32 + 1;
+            if precision == 16 {
+                return Err(invalid("FLAC subframe has an invalid coefficient precision"));
+            }
+            let shift = reader.read_signed(5)?;
+            if shift < 0 {
+                return Err(invalid("FLAC subframe has a negative predictor shift"));
+            }
+            let mut coefficients = Vec::with_capacity(order);
+            for _ in 0..order {
+                coefficients.push(reader.read_signed(precision)?);
+            }
+            decode_residual(reader, block_size, order, out)?;
+            restore_lpc(out, &coefficients, shift as u32);
+        }
+        _ => return Err(invalid("FLAC subframe has a reserved type")),
+    }
+
+    if wasted > 0 {
+        for sample in out.iter_mut() {
+            *sample <<= wasted;
+        }
+    }
+    Ok(())
+}
+
+fn decode_warmup(
+    reader: &mut BitReader,
+    order: usize,
+    bits: u32,
+    block_size: usize,
+    out: &mut Vec<i64>,
+) -> Result<(), AudioError> {
+    if order > block_size {
+        return Err(invalid("FLAC predictor order exceeds the block size"));
+    }
+    for _ in 0..order {
+        out.push(reader.read_signed(bits)?);
+    }
+    Ok(())
+}
+
+/// Append the partitioned Rice coded residual after the warm-up samples
+fn decode_residual(
+    reader: &mut BitReader,
+    block_size: usize,
+    order: usize,
+    out: &mut Vec<i64>,
+) -> Result<(), AudioError> {
+    let (parameter_bits, escape) = match reader.read(2)? {
+        0 => (4, 15),
+        1 => (5, 31),
+        _ => return Err(invalid("FLAC residual has a reserved coding method")),
+    };
+    let partition_order = reader.read(4)? as u32;
+    let partitions = 1usize << partition_order;
+    let partition_size = block_size >> partition_order;
+    if partition_size * partitions != block_size || partition_size < order {
+        return Err(invalid("FLAC residual partitions don't fit the block"));
+    }
+
+    for partition in 0..partitions {
+        let count = if partition == 0 { partition_size - order } else { partition_size };
+        let parameter = reader.read(parameter_bits)? as u32;
+        if parameter == escape {
+            let bits = reader.read(5)? as u32;
+            for _ in 0..count {
+                out.push(reader.read_signed(bits)?);
+            }
+        } else {
+            for _ in 0..count {
+                let quotient = reader.read_unary()? as u64;
+                let value = (quotient << parameter) | reader.read(parameter)?;
+                out.push((value >> 1) as i64 ^ -((value & 1) as i64));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn restore_fixed(samples: &mut [i64], order: usize) {
+    for i in order..samples.len() {
+        let prediction = match order {
+            0 => 0,
+            1 => samples[i - 1],
+            2 => 2 * samples[i - 1] - samples[i - 2],
+            3 => 3 * samples[i - 1] - 3 * samples[i - 2] + samples[i - 3],
+            _ => 4 * samples[i - 1] - 6 * samples[i - 2] + 4 * samples[i - 3] - samples[i - 4],
+        };
+        samples[i] += prediction;
+    }
+}
+
+fn restore_lpc(samples: &mut [i64], coefficients: &[i64], shift: u32) {
+    let order = coefficients.len();
+    for i in order..samples.len() {
+        let prediction: i64 = coefficients
+            .iter()
+            .enumerate()
+            .map(|(j, coefficient)| coefficient * samples[i - 1 - j])
+            .sum();
+        samples[i] += prediction >> shift;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::flac::FlacWriter;
+    use crate::recording::wav::WavWriter;
+    use std::io::Cursor;
+
+    fn test_signal(frames: usize) -> Vec<i32> {
+        (0..frames)
+            .flat_map(|i| {
+                let left = ((i as f32 * 0.05).sin() * 4_000_000.0) as i32;
+                let right = if i % 1000 < 500 { 1_000 } else { -(i as i32) };
+                [left, right]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wav_round_trip() {
+        let signal = test_signal(3_000);
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 44_100, 2).unwrap();
+        writer.write(&signal).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let decoded = decode(&data).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels, decoded.frames()), (44_100, 2, 3_000));
+        for (sample, expected) in decoded.samples.iter().zip(&signal) {
+            assert_eq!(*sample, *expected as f32 / 8_388_608.0);
+        }
+
+        // Unfinished file with zero chunk sizes
+        let mut unfinished = data.clone();
+        unfinished[40..44].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(decode(&unfinished).unwrap().frames(), 3_000);
+    }
+
+    #[test]
+    fn test_wav_16_bit_mono() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RIFF\0\0\0\0WAVEfmt ");
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&22_050u32.to_le_bytes());
+        data.extend_from_slice(&44_100u32.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&16u16.to_le_bytes());
+        // An odd-sized chunk before the data is skipped with its padding
+        data.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&6u32.to_le_bytes());
+        for sample in [16_384i16, -32_768, 0] {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let decoded = decode(&data).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels), (22_050, 1));
+        assert_eq!(decoded.samples, vec![0.5, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_flac_round_trip() {
+        // Longer than a block, with a partial last block
+        let signal = test_signal(10_000);
+        let mut writer = FlacWriter::new(Cursor::new(Vec::new()), 48_000, 2).unwrap();
+        writer.write(&signal).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let decoded = decode(&data).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels, decoded.frames()), (48_000, 2, 10_000));
+        for (sample, expected) in decoded.samples.iter().zip(&signal) {
+            assert_eq!(*sample, *expected as f32 / 8_388_608.0);
+        }
+    }
+
+    #[test]
+    fn test_flac_stereo_decorrelation_and_lpc() {
+        // Hand-built frame: 4 frames of 8-bit stereo, mid/side, the mid
+        // channel LPC coded and the side channel verbatim
+        let mid = [10i64, 12, 14, 16];
+        let side = [2i64, -2, 3, 0];
+
+        let mut bits = BitWriter::default();
+        bits.put(0x3FFE, 14);
+        bits.put(0, 2);
+        // Block size in an 8-bit field, STREAMINFO rate, mid/side, 8 bits
+        bits.put(6, 4);
+        bits.put(0, 4);
+        bits.put(10, 4);
+        bits.put(1, 3);
+        bits.put(0, 1);
+        bits.put(0, 8);
+        bits.put(3, 8);
+        bits.put(0, 8);
+
+        // Mid: LPC order 1, coefficient 1, shift 0; residual +2 each after
+        // the warm-up sample (Rice parameter 2)
+        bits.put(0, 1);
+        bits.put(32, 6);
+        bits.put(0, 1);
+        bits.put(mid[0] as u64, 8);
+        bits.put(1, 4);
+        bits.put(0, 5);
+        bits.put(1, 2);
+        bits.put(0, 2);
+        bits.put(0, 4);
+        bits.put(2, 4);
+        for _ in 0..3 {
+            // Folded +2 = 4: quotient 1, remainder 0
+            bits.put(0b01, 2);
+            bits.put(0, 2);
+        }
+
+        // Side: verbatim, 9 bits
+        bits.put(0, 1);
+        bits.put(1, 6);
+        bits.put(0, 1);
+        for value in side {
+            bits.put(value as u64 & 0x1FF, 9);
+        }
+        bits.align();
+        bits.put(0, 16);
+
+        let info = StreamInfo { sample_rate: 8_000, channels: 2, bits: 8 };
+        let mut channels = Vec::new();
+        let mut reader = BitReader::new(&bits.bytes);
+        assert_eq!(decode_flac_frame(&mut reader, &info, &mut channels).unwrap(), 4);
+
+        for i in 0..4 {
+            let m = (mid[i] << 1) | (side[i] & 1);
+            assert_eq!(channels[0][i], (m + side[i]) >> 1);
+            assert_eq!(channels[1][i], (m - side[i]) >> 1);
+        }
+    }
+
+    #[test]
+    fn test_mp3_rejected() {
+        let error = decode(b"ID3\x04\0\0\0\0\0\0").unwrap_err();
+        assert!(error.to_string().contains("MP3"));
+        assert!(decode(b"OggS").is_err());
+    }
+
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        used: u32,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u64, bits: u32) {
+            for bit in (0..bits).rev() {
+                if self.used.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                if (value >> bit) & 1 == 1 {
+                    *self.bytes.last_mut().unwrap() |= 0x80 >> (self.used % 8);
+                }
+                self.used += 1;
+            }
+        }
+
+        fn align(&mut self) {
+            self.used = self.used.div_ceil(8) * 8;
+        }
+    }
+}
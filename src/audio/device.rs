@@ -1,15 +1,136 @@
 //! Audio device enumeration and management
 
 use cpal::traits::{DeviceTrait, HostTrait};
+use std::sync::atomic::{AtomicU8, Ordering};
+use crate::audio::{generator, virtual_output, wasapi};
+use crate::config::AudioBackend;
 use crate::error::AudioError;
 use crate::protocol::AudioDeviceInfo;
 
+/// Backend selected with [`set_backend`] (see [`backend`])
+static BACKEND: AtomicU8 = AtomicU8::new(0);
+
+/// Select the audio host used for device lookup and streams.
+/// Must be called before any stream is opened.
+pub fn set_backend(backend: AudioBackend) -> Result<(), AudioError> {
+    match backend {
+        AudioBackend::Default => {}
+        AudioBackend::Jack => {
+            jack_host()?;
+        }
+        AudioBackend::Pipewire => check_pipewire()?,
+    }
+    BACKEND.store(backend as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Currently selected audio host backend
+pub fn backend() -> AudioBackend {
+    match BACKEND.load(Ordering::Relaxed) {
+        1 => AudioBackend::Jack,
+        2 => AudioBackend::Pipewire,
+        _ => AudioBackend::Default,
+    }
+}
+
+#[cfg(all(feature = "pipewire", target_os = "linux"))]
+fn check_pipewire() -> Result<(), AudioError> {
+    crate::audio::pw_cat::check()
+}
+
+#[cfg(not(all(feature = "pipewire", target_os = "linux")))]
+fn check_pipewire() -> Result<(), AudioError> {
+    Err(AudioError::CpalError(
+        "PipeWire backend not compiled in (build with --features pipewire)".to_string(),
+    ))
+}
+
+fn host() -> cpal::Host {
+    if backend() == AudioBackend::Jack {
+        match jack_host() {
+            Ok(host) => return host,
+            Err(e) => tracing::warn!("{}, using the default host", e),
+        }
+    }
+    cpal::default_host()
+}
+
+/// JACK host, if compiled in and a server is running
+#[cfg(all(feature = "jack", target_os = "linux"))]
+fn jack_host() -> Result<cpal::Host, AudioError> {
+    let host = cpal::host_from_id(cpal::HostId::Jack)
+        .map_err(|e| AudioError::CpalError(format!("JACK host unavailable: {}", e)))?;
+    // The JACK host always opens; its devices exist only if a server answered
+    if host.default_output_device().is_none() && host.default_input_device().is_none() {
+        return Err(AudioError::CpalError("JACK server is not running".to_string()));
+    }
+    Ok(host)
+}
+
+#[cfg(not(all(feature = "jack", target_os = "linux")))]
+fn jack_host() -> Result<cpal::Host, AudioError> {
+    Err(AudioError::CpalError(
+        "JACK backend not compiled in (build with --features jack)".to_string(),
+    ))
+}
+
+/// Name of the node (JACK client, PipeWire node) of a track's own stream:
+/// `lan-audio.track-<id>` for playback, `lan-audio.capture-<id>` for capture
+pub fn track_node_name(track_id: u8, is_input: bool) -> String {
+    if is_input {
+        format!("lan-audio.capture-{}", track_id)
+    } else {
+        format!("lan-audio.track-{}", track_id)
+    }
+}
+
+/// Device to open a track's stream on. Under JACK every track is a client
+/// of its own named after it (see [`track_node_name`]; cpal adds `_out` or
+/// `_in` and names the ports `out_<n>` / `in_<n>`), so each track can be
+/// patched on its own; the JACK graph decides where it plays, not `id`.
+/// Other backends look the device up by ID.
+pub fn get_track_device(track_id: u8, id: &str, is_input: bool) -> Result<AudioDevice, AudioError> {
+    #[cfg(all(feature = "jack", target_os = "linux"))]
+    if backend() == AudioBackend::Jack && !id.starts_with(LOOPBACK_PREFIX) {
+        return jack_track_device(&track_node_name(track_id, is_input), is_input);
+    }
+    #[cfg(not(all(feature = "jack", target_os = "linux")))]
+    let _ = (track_id, is_input);
+    get_device_by_id(id)
+}
+
+/// JACK client named `name` for one stream
+#[cfg(all(feature = "jack", target_os = "linux"))]
+fn jack_track_device(name: &str, is_input: bool) -> Result<AudioDevice, AudioError> {
+    let mut host = cpal::platform::JackHost::new()
+        .map_err(|e| AudioError::CpalError(format!("JACK host unavailable: {}", e)))?;
+    let device = if is_input {
+        host.input_device_with_name(name)
+    } else {
+        host.output_device_with_name(name)
+    };
+    device
+        .map(|device| AudioDevice::from_cpal(device.into(), is_input, !is_input))
+        .ok_or_else(|| AudioError::CpalError("JACK server is not running".to_string()))
+}
+
+/// Prefix of the IDs of loopback inputs (`loopback:<output name>`)
+pub const LOOPBACK_PREFIX: &str = "loopback:";
+
+/// Whether outputs can be captured as loopback inputs: WASAPI records an
+/// output device when an input stream is opened on it
+pub fn supports_loopback() -> bool {
+    cfg!(target_os = "windows") && backend() == AudioBackend::Default
+}
+
 /// Wrapper around cpal device
 pub struct AudioDevice {
     inner: cpal::Device,
     pub name: String,
     pub is_input: bool,
     pub is_output: bool,
+    /// Output device captured as an input (WASAPI loopback)
+    pub is_loopback: bool,
 }
 
 impl AudioDevice {
@@ -20,6 +141,15 @@ impl AudioDevice {
             name,
             is_input,
             is_output,
+            is_loopback: false,
+        }
+    }
+    
+    /// Capture the audio played on an output device
+    pub fn loopback(device: cpal::Device) -> Self {
+        Self {
+            is_loopback: true,
+            ..Self::from_cpal(device, true, false)
         }
     }
     
@@ -31,8 +161,11 @@ impl AudioDevice {
         self.inner
     }
     
-    /// Get supported input configurations
+    /// Get supported input configurations (of the recorded output for a loopback input)
     pub fn supported_input_configs(&self) -> Result<Vec<cpal::SupportedStreamConfigRange>, AudioError> {
+        if self.is_loopback {
+            return self.supported_output_configs();
+        }
         self.inner
             .supported_input_configs()
             .map(|iter| iter.collect())
@@ -47,8 +180,19 @@ impl AudioDevice {
             .map_err(|e| AudioError::DeviceNotFound(e.to_string()))
     }
     
-    /// Get default input config
+    /// Most channels an output stream of the device can have (0 if unknown)
+    pub fn max_output_channels(&self) -> u16 {
+        self.supported_output_configs()
+            .ok()
+            .and_then(|configs| configs.iter().map(|config| config.channels()).max())
+            .unwrap_or(0)
+    }
+    
+    /// Get default input config (the recorded output's mix format for a loopback input)
     pub fn default_input_config(&self) -> Result<cpal::SupportedStreamConfig, AudioError> {
+        if self.is_loopback {
+            return self.default_output_config();
+        }
         self.inner
             .default_input_config()
             .map_err(|e| AudioError::DeviceNotFound(e.to_string()))
@@ -60,11 +204,64 @@ impl AudioDevice {
             .default_output_config()
             .map_err(|e| AudioError::DeviceNotFound(e.to_string()))
     }
+    
+    /// Rate to open an input stream at (see [`negotiate_sample_rate`])
+    pub fn input_sample_rate(&self, preferred: u32) -> u32 {
+        match (self.supported_input_configs(), self.default_input_config()) {
+            (Ok(configs), Ok(default)) => negotiate_sample_rate(&configs, default.sample_rate().0, preferred),
+            _ => preferred,
+        }
+    }
+    
+    /// Rate to open an output stream at (see [`negotiate_sample_rate`])
+    pub fn output_sample_rate(&self, preferred: u32) -> u32 {
+        match (self.supported_output_configs(), self.default_output_config()) {
+            (Ok(configs), Ok(default)) => negotiate_sample_rate(&configs, default.sample_rate().0, preferred),
+            _ => preferred,
+        }
+    }
+}
+
+/// Rate a device stream is opened at: `preferred` when one of the device's
+/// configurations supports it, otherwise the device's default rate (the
+/// stream is then resampled, see [`Resampler`](crate::audio::Resampler))
+pub fn negotiate_sample_rate(configs: &[cpal::SupportedStreamConfigRange], default_rate: u32, preferred: u32) -> u32 {
+    let supported = configs
+        .iter()
+        .any(|config| (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&preferred));
+    if supported || configs.is_empty() {
+        preferred
+    } else {
+        default_rate
+    }
+}
+
+/// Buffer sizes in frames a track can ask its device for
+pub const BUFFER_FRAMES_RANGE: std::ops::RangeInclusive<u32> = 16..=8192;
+
+/// Buffer size a stream asking for `buffer_size` is opened with: a fixed
+/// size is brought within the range the device supports, the host default
+/// stays as it is
+pub fn fit_buffer_size(buffer_size: cpal::BufferSize, supported: Option<cpal::SupportedBufferSize>) -> cpal::BufferSize {
+    match (buffer_size, supported) {
+        (cpal::BufferSize::Fixed(frames), Some(cpal::SupportedBufferSize::Range { min, max })) if min <= max => {
+            cpal::BufferSize::Fixed(frames.clamp(min, max))
+        }
+        (buffer_size, _) => buffer_size,
+    }
 }
 
 /// List all available audio devices
 pub fn list_devices() -> Vec<AudioDeviceInfo> {
-    let host = cpal::default_host();
+    #[cfg(all(feature = "pipewire", target_os = "linux"))]
+    if backend() == AudioBackend::Pipewire {
+        let mut devices = crate::audio::pw_cat::list_devices();
+        devices.extend(virtual_output::device_info(&devices));
+        devices.extend(generator::device_info());
+        return devices;
+    }
+    
+    let host = host();
     let mut devices = Vec::new();
     
     // Get default devices
@@ -92,6 +289,9 @@ pub fn list_devices() -> Vec<AudioDeviceInfo> {
                     is_default,
                     sample_rates,
                     channels,
+                    is_loopback: false,
+                    capture_mode: None,
+                    playback_mode: None,
                 });
             }
         }
@@ -106,6 +306,22 @@ pub fn list_devices() -> Vec<AudioDeviceInfo> {
                 
                 let (sample_rates, channels) = get_device_capabilities(&device, false);
                 
+                // The output's loopback, e.g. to send game audio
+                if supports_loopback() {
+                    devices.push(AudioDeviceInfo {
+                        id: format!("{}{}", LOOPBACK_PREFIX, name),
+                        name: format!("{} (loopback)", name),
+                        is_input: true,
+                        is_output: false,
+                        is_default: false,
+                        sample_rates: sample_rates.clone(),
+                        channels: channels.clone(),
+                        is_loopback: true,
+                        capture_mode: None,
+                        playback_mode: None,
+                    });
+                }
+                
                 // Check if we already have this device as input
                 if let Some(existing) = devices.iter_mut().find(|d| d.name == name) {
                     existing.is_output = true;
@@ -121,12 +337,36 @@ pub fn list_devices() -> Vec<AudioDeviceInfo> {
                         is_default,
                         sample_rates,
                         channels,
+                        is_loopback: false,
+                        capture_mode: None,
+                        playback_mode: None,
                     });
                 }
             }
         }
     }
     
+    // Virtual output for capture software (OBS)
+    if let Some(virtual_device) = virtual_output::device_info(&devices) {
+        devices.push(virtual_device);
+    }
+    
+    // Test signals, selectable like inputs
+    devices.extend(generator::device_info());
+    
+    // Modes of the streams open on the devices (WASAPI)
+    if wasapi::is_active() {
+        for device in devices.iter_mut() {
+            let capture_id = if device.is_loopback {
+                device.id.clone()
+            } else {
+                format!("input:{}", device.name)
+            };
+            device.capture_mode = wasapi::effective_mode(&capture_id);
+            device.playback_mode = wasapi::effective_mode(&format!("output:{}", device.name));
+        }
+    }
+    
     devices
 }
 
@@ -142,10 +382,9 @@ fn get_device_capabilities(device: &cpal::Device, is_input: bool) -> (Vec<u32>,
             // Common sample rates
             for rate_val in [44100u32, 48000, 88200, 96000, 176400, 192000] {
                 let rate = cpal::SampleRate(rate_val);
-                if rate >= config.min_sample_rate() && rate <= config.max_sample_rate() {
-                    if !rates.contains(&rate_val) {
-                        rates.push(rate_val);
-                    }
+                let in_range = rate >= config.min_sample_rate() && rate <= config.max_sample_rate();
+                if in_range && !rates.contains(&rate_val) {
+                    rates.push(rate_val);
                 }
             }
             
@@ -179,13 +418,21 @@ fn get_device_capabilities(device: &cpal::Device, is_input: bool) -> (Vec<u32>,
 
 /// Get a device by its ID
 pub fn get_device_by_id(id: &str) -> Result<AudioDevice, AudioError> {
-    let host = cpal::default_host();
+    let host = host();
     
     // Parse device type from ID
     let (device_type, name) = if let Some(name) = id.strip_prefix("input:") {
         ("input", name)
     } else if let Some(name) = id.strip_prefix("output:") {
         ("output", name)
+    } else if let Some(name) = id.strip_prefix(LOOPBACK_PREFIX) {
+        if !supports_loopback() {
+            return Err(AudioError::DeviceNotFound(format!(
+                "{} (loopback capture needs WASAPI on Windows)",
+                id
+            )));
+        }
+        ("loopback", name)
     } else {
         // Assume input for backward compatibility
         ("input", id)
@@ -193,7 +440,7 @@ pub fn get_device_by_id(id: &str) -> Result<AudioDevice, AudioError> {
     
     let devices = match device_type {
         "input" => host.input_devices(),
-        "output" => host.output_devices(),
+        "output" | "loopback" => host.output_devices(),
         _ => return Err(AudioError::DeviceNotFound(id.to_string())),
     };
     
@@ -202,6 +449,9 @@ pub fn get_device_by_id(id: &str) -> Result<AudioDevice, AudioError> {
     for device in devices {
         if let Ok(device_name) = device.name() {
             if device_name == name {
+                if device_type == "loopback" {
+                    return Ok(AudioDevice::loopback(device));
+                }
                 return Ok(AudioDevice::from_cpal(
                     device,
                     device_type == "input",
@@ -216,7 +466,7 @@ pub fn get_device_by_id(id: &str) -> Result<AudioDevice, AudioError> {
 
 /// Get default input device
 pub fn get_default_input_device() -> Result<AudioDevice, AudioError> {
-    let host = cpal::default_host();
+    let host = host();
     host.default_input_device()
         .map(|d| AudioDevice::from_cpal(d, true, false))
         .ok_or_else(|| AudioError::DeviceNotFound("No default input device".to_string()))
@@ -224,45 +474,40 @@ pub fn get_default_input_device() -> Result<AudioDevice, AudioError> {
 
 /// Get default output device
 pub fn get_default_output_device() -> Result<AudioDevice, AudioError> {
-    let host = cpal::default_host();
+    let host = host();
     host.default_output_device()
         .map(|d| AudioDevice::from_cpal(d, false, true))
         .ok_or_else(|| AudioError::DeviceNotFound("No default output device".to_string()))
 }
 
-#[cfg(target_os = "windows")]
-pub mod wasapi {
-    //! WASAPI-specific device handling
-    //!
-    //! For low-latency audio on Windows, we can use WASAPI in either:
-    //! - Shared mode: Lower latency than MME/DirectSound, allows multiple apps
-    //! - Exclusive mode: Lowest latency, but exclusive access to device
-    
-    /// WASAPI mode configuration
-    #[derive(Debug, Clone, Copy)]
-    pub enum WasapiMode {
-        /// Shared mode (default)
-        Shared,
-        /// Exclusive mode for lowest latency
-        Exclusive,
-    }
-    
-    /// Check if WASAPI is available
-    pub fn is_available() -> bool {
-        // cpal uses WASAPI by default on Windows
-        cfg!(target_os = "windows")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_node_names() {
+        assert_eq!(track_node_name(3, false), "lan-audio.track-3");
+        assert_eq!(track_node_name(3, true), "lan-audio.capture-3");
     }
-    
-    /// Get WASAPI-specific host
-    pub fn get_wasapi_host() -> Option<cpal::Host> {
-        #[cfg(target_os = "windows")]
+
+    #[test]
+    fn test_backend_not_compiled_in_is_refused() {
+        // Failing selections leave the default host in place
+        #[cfg(not(all(feature = "jack", target_os = "linux")))]
         {
-            // cpal's default host on Windows is WASAPI
-            Some(cpal::default_host())
+            let Err(e) = set_backend(AudioBackend::Jack) else {
+                panic!("JACK selected without the jack feature");
+            };
+            assert!(e.to_string().contains("--features jack"), "{}", e);
         }
-        #[cfg(not(target_os = "windows"))]
+        #[cfg(not(all(feature = "pipewire", target_os = "linux")))]
         {
-            None
+            let Err(e) = set_backend(AudioBackend::Pipewire) else {
+                panic!("PipeWire selected without the pipewire feature");
+            };
+            assert!(e.to_string().contains("--features pipewire"), "{}", e);
         }
+        assert_eq!(backend(), AudioBackend::Default);
+        assert!(set_backend(AudioBackend::Default).is_ok());
     }
 }
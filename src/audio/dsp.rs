@@ -0,0 +1,442 @@
+//! Master processing of an output device mix
+//!
+//! An [`OutputProcessor`] runs in the output callback after all tracks
+//! routed to a device have been summed, so a speaker that is boomy, late
+//! or too loud in its room can be corrected once for the device instead
+//! of on every track. The chain is fixed: EQ bands (RBJ biquads), a delay
+//! line, a compressor, then a peak limiter. The gain the compressor and
+//! limiter take off is reported for the UI meter
+//! ([`OutputProcessor::gain_reduction_db`]).
+
+use std::f64::consts::PI;
+
+use crate::protocol::{CompressorSettings, EqBand, EqBandKind, LimiterSettings, OutputDsp};
+
+/// Most EQ bands on one output
+pub const MAX_EQ_BANDS: usize = 16;
+
+/// Longest output delay in ms
+pub const MAX_DELAY_MS: f32 = 1000.0;
+
+/// Largest EQ boost or cut in dB
+pub const MAX_EQ_GAIN_DB: f32 = 24.0;
+
+/// Check settings before they reach an output callback
+pub fn validate(dsp: &OutputDsp) -> Result<(), String> {
+    if dsp.eq.len() > MAX_EQ_BANDS {
+        return Err(format!("At most {} EQ bands are allowed", MAX_EQ_BANDS));
+    }
+    for band in &dsp.eq {
+        if !(10.0..=20_000.0).contains(&band.frequency_hz) {
+            return Err("EQ frequency must be between 10 and 20000 Hz".to_string());
+        }
+        if !(-MAX_EQ_GAIN_DB..=MAX_EQ_GAIN_DB).contains(&band.gain_db) {
+            return Err(format!("EQ gain must be between -{0} and {0} dB", MAX_EQ_GAIN_DB));
+        }
+        if !(0.1..=24.0).contains(&band.q) {
+            return Err("EQ Q must be between 0.1 and 24".to_string());
+        }
+    }
+    if !(0.0..=MAX_DELAY_MS).contains(&dsp.delay_ms) {
+        return Err(format!("Delay must be between 0 and {} ms", MAX_DELAY_MS));
+    }
+    if let Some(compressor) = &dsp.compressor {
+        if !(-60.0..=0.0).contains(&compressor.threshold_db) {
+            return Err("Compressor threshold must be between -60 and 0 dBFS".to_string());
+        }
+        if !(1.0..=20.0).contains(&compressor.ratio) {
+            return Err("Compressor ratio must be between 1 and 20".to_string());
+        }
+        if !(0.1..=500.0).contains(&compressor.attack_ms) {
+            return Err("Compressor attack must be between 0.1 and 500 ms".to_string());
+        }
+        if !(1.0..=5000.0).contains(&compressor.release_ms) {
+            return Err("Compressor release must be between 1 and 5000 ms".to_string());
+        }
+        if !(0.0..=24.0).contains(&compressor.makeup_db) {
+            return Err("Compressor makeup gain must be between 0 and 24 dB".to_string());
+        }
+    }
+    if let Some(limiter) = &dsp.limiter {
+        if !(-40.0..=0.0).contains(&limiter.ceiling_db) {
+            return Err("Limiter ceiling must be between -40 and 0 dBFS".to_string());
+        }
+        if !(1.0..=5000.0).contains(&limiter.release_ms) {
+            return Err("Limiter release must be between 1 and 5000 ms".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Processing chain of one device stream
+pub struct OutputProcessor {
+    channels: usize,
+    /// Filter coefficients in order
+    filters: Vec<Biquad>,
+    /// `filters.len()` states per channel
+    filter_states: Vec<BiquadState>,
+    delay: Vec<f32>,
+    delay_position: usize,
+    compressor: Option<Compressor>,
+    limiter: Option<Limiter>,
+}
+
+impl OutputProcessor {
+    /// Build the chain for interleaved audio at `sample_rate`
+    pub fn new(dsp: &OutputDsp, sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let filters: Vec<Biquad> = dsp.eq.iter().map(|band| Biquad::new(band, sample_rate)).collect();
+        let delay_frames = (dsp.delay_ms as f64 * sample_rate as f64 / 1000.0).round() as usize;
+
+        Self {
+            channels,
+            filter_states: vec![BiquadState::default(); filters.len() * channels],
+            filters,
+            delay: vec![0.0; delay_frames * channels],
+            delay_position: 0,
+            compressor: dsp.compressor.as_ref().map(|compressor| Compressor::new(compressor, sample_rate)),
+            limiter: dsp.limiter.as_ref().map(|limiter| Limiter::new(limiter, sample_rate)),
+        }
+    }
+
+    /// Gain the compressor and limiter currently take off, in dB
+    /// (None without either)
+    pub fn gain_reduction_db(&self) -> Option<f32> {
+        if self.compressor.is_none() && self.limiter.is_none() {
+            return None;
+        }
+        let compressor_db = self.compressor.as_ref().map_or(0.0, |compressor| compressor.reduction_db);
+        let limiter_db = self.limiter.as_ref().map_or(0.0, |limiter| -20.0 * limiter.gain.log10());
+        Some(compressor_db + limiter_db)
+    }
+
+    /// Process an interleaved block in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.channels;
+
+        if !self.filters.is_empty() {
+            for frame in samples.chunks_mut(channels) {
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let states = &mut self.filter_states[channel * self.filters.len()..][..self.filters.len()];
+                    let mut value = *sample as f64;
+                    for (filter, state) in self.filters.iter().zip(states) {
+                        value = filter.process(state, value);
+                    }
+                    *sample = value as f32;
+                }
+            }
+        }
+
+        if !self.delay.is_empty() {
+            for sample in samples.iter_mut() {
+                std::mem::swap(sample, &mut self.delay[self.delay_position]);
+                self.delay_position = (self.delay_position + 1) % self.delay.len();
+            }
+        }
+
+        if let Some(compressor) = &mut self.compressor {
+            for frame in samples.chunks_mut(channels) {
+                compressor.process(frame);
+            }
+        }
+
+        if let Some(limiter) = &mut self.limiter {
+            for frame in samples.chunks_mut(channels) {
+                limiter.process(frame);
+            }
+        }
+    }
+}
+
+/// Normalised biquad coefficients (a0 = 1)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+/// Transposed direct form II state
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BiquadState {
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /// Audio EQ Cookbook (R. Bristow-Johnson) coefficients
+    pub(crate) fn new(band: &EqBand, sample_rate: u32) -> Self {
+        // Keep the band below Nyquist on low-rate streams
+        let frequency = (band.frequency_hz as f64).min(sample_rate as f64 * 0.45);
+        let w0 = 2.0 * PI * frequency / sample_rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q as f64);
+        let a = 10f64.powf(band.gain_db as f64 / 40.0);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            EqBandKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            EqBandKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            EqBandKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            EqBandKind::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            EqBandKind::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    pub(crate) fn process(&self, state: &mut BiquadState, input: f64) -> f64 {
+        let output = self.b0 * input + state.z1;
+        state.z1 = self.b1 * input - self.a1 * output + state.z2;
+        state.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+/// Peak compressor, linked across channels
+struct Compressor {
+    threshold_db: f32,
+    /// Share of the level above the threshold that is taken off
+    slope: f32,
+    attack: f32,
+    release: f32,
+    makeup: f32,
+    /// Gain reduction in dB
+    reduction_db: f32,
+}
+
+impl Compressor {
+    fn new(settings: &CompressorSettings, sample_rate: u32) -> Self {
+        let coefficient = |time_ms: f32| (-1000.0 / (time_ms * sample_rate as f32).max(1.0)).exp();
+        Self {
+            threshold_db: settings.threshold_db,
+            slope: 1.0 - 1.0 / settings.ratio.max(1.0),
+            attack: coefficient(settings.attack_ms),
+            release: coefficient(settings.release_ms),
+            makeup: 10f32.powf(settings.makeup_db / 20.0),
+            reduction_db: 0.0,
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        let peak = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        let level_db = 20.0 * peak.max(1e-9).log10();
+        let target_db = (level_db - self.threshold_db).max(0.0) * self.slope;
+        let coefficient = if target_db > self.reduction_db { self.attack } else { self.release };
+        self.reduction_db = target_db + (self.reduction_db - target_db) * coefficient;
+
+        let gain = 10f32.powf(-self.reduction_db / 20.0) * self.makeup;
+        for sample in frame.iter_mut() {
+            *sample *= gain;
+        }
+    }
+}
+
+/// Peak limiter with instant attack, linked across channels
+struct Limiter {
+    ceiling: f32,
+    release: f32,
+    gain: f32,
+}
+
+impl Limiter {
+    fn new(settings: &LimiterSettings, sample_rate: u32) -> Self {
+        let release_frames = settings.release_ms * sample_rate as f32 / 1000.0;
+        Self {
+            ceiling: 10f32.powf(settings.ceiling_db / 20.0),
+            release: (-1.0 / release_frames.max(1.0)).exp(),
+            gain: 1.0,
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        let peak = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        let target = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+        self.gain = if target < self.gain {
+            target
+        } else {
+            target + (self.gain - target) * self.release
+        };
+        for sample in frame.iter_mut() {
+            *sample = (*sample * self.gain).clamp(-self.ceiling, self.ceiling);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, frames: usize, amplitude: f32) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency * i as f64 / 48_000.0).sin() as f32 * amplitude)
+            .collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn test_eq_boosts_band_only() {
+        let dsp = OutputDsp {
+            eq: vec![EqBand {
+                kind: EqBandKind::Peaking,
+                frequency_hz: 1000.0,
+                gain_db: 6.0,
+                q: 2.0,
+            }],
+            ..OutputDsp::default()
+        };
+
+        let mut at_band = sine(1000.0, 48_000, 0.25);
+        OutputProcessor::new(&dsp, 48_000, 1).process(&mut at_band);
+        // +6 dB is about twice the amplitude (past the filter's start-up)
+        assert!((peak(&at_band[4800..]) - 0.5).abs() < 0.01);
+
+        let mut far = sine(100.0, 48_000, 0.25);
+        OutputProcessor::new(&dsp, 48_000, 1).process(&mut far);
+        assert!((peak(&far[4800..]) - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_delay_and_limiter() {
+        let dsp = OutputDsp {
+            delay_ms: 1.0,
+            limiter: Some(LimiterSettings {
+                ceiling_db: -6.0,
+                release_ms: 50.0,
+            }),
+            ..OutputDsp::default()
+        };
+        let mut processor = OutputProcessor::new(&dsp, 48_000, 2);
+
+        // An impulse comes out 48 frames later, limited to the ceiling
+        let mut block = vec![0.0; 256];
+        block[0] = 1.0;
+        block[1] = -0.1;
+        processor.process(&mut block);
+        let ceiling = 10f32.powf(-6.0 / 20.0);
+        assert!(block[..96].iter().all(|&s| s == 0.0));
+        assert!((block[96] - ceiling).abs() < 1e-6);
+        assert!((block[97] + 0.1 * ceiling).abs() < 1e-6);
+
+        // Quiet audio passes unchanged once the limiter has released
+        let mut quiet = vec![0.1; 48_000];
+        processor.process(&mut quiet);
+        assert!((quiet[quiet.len() - 1] - 0.1).abs() < 1e-4);
+        assert!(peak(&quiet) <= ceiling);
+    }
+
+    #[test]
+    fn test_compressor_reduces_loud_passages() {
+        let dsp = OutputDsp {
+            compressor: Some(CompressorSettings {
+                threshold_db: -20.0,
+                ratio: 4.0,
+                attack_ms: 1.0,
+                release_ms: 50.0,
+                makeup_db: 6.0,
+            }),
+            ..OutputDsp::default()
+        };
+        let mut processor = OutputProcessor::new(&dsp, 48_000, 1);
+        assert_eq!(processor.gain_reduction_db(), Some(0.0));
+
+        // A peak 14 dB over the threshold comes out 3.5 dB over it, plus makeup
+        let mut loud = sine(1000.0, 48_000, 10f32.powf(-6.0 / 20.0));
+        processor.process(&mut loud);
+        let reduction_db = processor.gain_reduction_db().unwrap();
+        assert!((reduction_db - 10.5).abs() < 0.6);
+        let peak_db = 20.0 * peak(&loud[24_000..]).log10();
+        assert!((peak_db - (-16.5 + 6.0)).abs() < 0.6);
+
+        // Quiet audio only gets the makeup gain once released
+        let mut quiet = sine(1000.0, 48_000, 0.05);
+        processor.process(&mut quiet);
+        assert!(processor.gain_reduction_db().unwrap() < 0.1);
+        assert!((peak(&quiet[24_000..]) - 0.1).abs() < 0.005);
+
+        assert!(OutputProcessor::new(&OutputDsp::default(), 48_000, 1).gain_reduction_db().is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&OutputDsp::default()).is_ok());
+
+        let band = |frequency_hz: f32, gain_db: f32| EqBand {
+            kind: EqBandKind::LowShelf,
+            frequency_hz,
+            gain_db,
+            q: 0.7,
+        };
+        let dsp = |eq| OutputDsp {
+            eq,
+            ..OutputDsp::default()
+        };
+        assert!(validate(&dsp(vec![band(80.0, -4.0)])).is_ok());
+        assert!(validate(&dsp(vec![band(5.0, 0.0)])).is_err());
+        assert!(validate(&dsp(vec![band(80.0, f32::NAN)])).is_err());
+        assert!(validate(&dsp(vec![band(80.0, 0.0); MAX_EQ_BANDS + 1])).is_err());
+        assert!(validate(&OutputDsp {
+            delay_ms: MAX_DELAY_MS + 1.0,
+            ..OutputDsp::default()
+        })
+        .is_err());
+        let compressor = |ratio| OutputDsp {
+            compressor: Some(CompressorSettings {
+                threshold_db: -18.0,
+                ratio,
+                attack_ms: 5.0,
+                release_ms: 100.0,
+                makeup_db: 0.0,
+            }),
+            ..OutputDsp::default()
+        };
+        assert!(validate(&compressor(3.0)).is_ok());
+        assert!(validate(&compressor(0.5)).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Audio files played as a track source
+//!
+//! A track whose device ID is `file:<path>` streams a WAV or FLAC file on
+//! the host instead of a capture device. The file is decoded into memory
+//! when the capture is created and fed to the track in real time, so it
+//! takes the same channel conversion, resampling, Opus encoding and UDP
+//! path as a microphone. Play, pause, seek and loop go through the track's
+//! [`FilePlayer`] (WebSocket `FilePlayer` messages). Useful for test
+//! material and jingles.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::audio::decode::{decode_file, DecodedAudio};
+use crate::error::AudioError;
+use crate::protocol::{FilePlayerStatus, PlayerCommand};
+
+/// Prefix of the device IDs of file sources (`file:<path>`)
+pub const FILE_PREFIX: &str = "file:";
+
+/// Path of the file a device ID plays, if it is a file source
+pub fn file_path(device_id: &str) -> Option<&str> {
+    device_id.strip_prefix(FILE_PREFIX)
+}
+
+/// No seek pending
+const NO_SEEK: u64 = u64::MAX;
+
+/// Decoded file with its transport state
+///
+/// The capture thread pulls audio with [`read`](Self::read); the commands
+/// may come from any thread.
+pub struct FilePlayer {
+    path: String,
+    audio: DecodedAudio,
+    playing: AtomicBool,
+    looping: AtomicBool,
+    /// Next frame to play
+    position: AtomicU64,
+    /// Frame to jump to on the next read
+    seek_to: AtomicU64,
+}
+
+impl FilePlayer {
+    /// Decode a file, ready to play from the start
+    pub fn open(path: &str) -> Result<Self, AudioError> {
+        let audio = decode_file(Path::new(path))?;
+        tracing::info!(
+            "Opened {}: {} Hz, {} channels, {:.1} s",
+            path,
+            audio.sample_rate,
+            audio.channels,
+            audio.frames() as f64 / audio.sample_rate as f64
+        );
+        Ok(Self::new(path, audio))
+    }
+
+    /// Play already decoded audio
+    pub fn new(path: impl Into<String>, audio: DecodedAudio) -> Self {
+        Self {
+            path: path.into(),
+            audio,
+            playing: AtomicBool::new(true),
+            looping: AtomicBool::new(false),
+            position: AtomicU64::new(0),
+            seek_to: AtomicU64::new(NO_SEEK),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.audio.channels
+    }
+
+    /// Resume, or start over once the file has played to the end
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Release);
+    }
+
+    /// Hold the position; the track sends silence meanwhile
+    pub fn pause(&self) {
+        self.playing.store(false, Ordering::Release);
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        self.looping.store(looping, Ordering::Release);
+    }
+
+    /// Jump to a position (past the end = the end)
+    pub fn seek(&self, position_ms: u64) {
+        let frame = position_ms.saturating_mul(self.audio.sample_rate as u64) / 1000;
+        self.seek_to.store(frame.min(self.audio.frames() as u64), Ordering::Release);
+    }
+
+    pub fn apply(&self, command: PlayerCommand) {
+        match command {
+            PlayerCommand::Play => self.play(),
+            PlayerCommand::Pause => self.pause(),
+            PlayerCommand::Seek { position_ms } => self.seek(position_ms),
+            PlayerCommand::SetLoop { enabled } => self.set_looping(enabled),
+        }
+    }
+
+    pub fn status(&self) -> FilePlayerStatus {
+        let seek = self.seek_to.load(Ordering::Acquire);
+        let position = if seek == NO_SEEK {
+            self.position.load(Ordering::Acquire)
+        } else {
+            seek
+        };
+        FilePlayerStatus {
+            path: self.path.clone(),
+            playing: self.playing.load(Ordering::Acquire),
+            looping: self.looping.load(Ordering::Acquire),
+            position_ms: self.frames_to_ms(position),
+            duration_ms: self.frames_to_ms(self.audio.frames() as u64),
+        }
+    }
+
+    fn frames_to_ms(&self, frames: u64) -> u64 {
+        frames * 1000 / self.audio.sample_rate.max(1) as u64
+    }
+
+    /// Fill `out` with the next interleaved samples, silence while paused
+    ///
+    /// At the end of the file playback starts over when looping, otherwise
+    /// it stops and rewinds, so the next play repeats the file (a jingle).
+    pub fn read(&self, out: &mut [f32]) {
+        let channels = self.audio.channels.max(1) as usize;
+        let total = self.audio.frames();
+        let seek = self.seek_to.swap(NO_SEEK, Ordering::AcqRel);
+        let mut position = if seek == NO_SEEK {
+            self.position.load(Ordering::Acquire)
+        } else {
+            seek
+        } as usize;
+
+        let mut written = 0;
+        if self.playing.load(Ordering::Acquire) {
+            while written < out.len() {
+                if position >= total {
+                    position = 0;
+                    if self.looping.load(Ordering::Acquire) && total > 0 {
+                        continue;
+                    }
+                    self.playing.store(false, Ordering::Release);
+                    break;
+                }
+                let count = ((total - position) * channels).min(out.len() - written);
+                let start = position * channels;
+                out[written..written + count].copy_from_slice(&self.audio.samples[start..start + count]);
+                written += count;
+                position += count / channels;
+            }
+        }
+        out[written..].fill(0.0);
+
+        self.position.store(position as u64, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> FilePlayer {
+        // One second of mono audio at 1 kHz, sample value = frame index
+        let audio = DecodedAudio {
+            sample_rate: 1_000,
+            channels: 1,
+            samples: (0..1_000).map(|i| i as f32).collect(),
+        };
+        FilePlayer::new("test.wav", audio)
+    }
+
+    #[test]
+    fn test_transport() {
+        let player = player();
+        let mut out = [0.0; 4];
+
+        player.read(&mut out);
+        assert_eq!(out, [0.0, 1.0, 2.0, 3.0]);
+
+        player.pause();
+        player.read(&mut out);
+        assert_eq!(out, [0.0; 4]);
+        assert_eq!(player.status().position_ms, 4);
+
+        player.apply(PlayerCommand::Seek { position_ms: 500 });
+        assert_eq!(player.status().position_ms, 500);
+        player.play();
+        player.read(&mut out);
+        assert_eq!(out, [500.0, 501.0, 502.0, 503.0]);
+
+        // Stops at the end and rewinds
+        player.seek(998);
+        player.read(&mut out);
+        assert_eq!(out, [998.0, 999.0, 0.0, 0.0]);
+        let status = player.status();
+        assert!(!status.playing);
+        assert_eq!((status.position_ms, status.duration_ms), (0, 1_000));
+
+        // Wraps around when looping
+        player.apply(PlayerCommand::SetLoop { enabled: true });
+        player.play();
+        player.seek(999);
+        player.read(&mut out);
+        assert_eq!(out, [999.0, 0.0, 1.0, 2.0]);
+        assert!(player.status().playing);
+    }
+
+    #[test]
+    fn test_file_path() {
+        assert_eq!(file_path("file:/srv/jingle.flac"), Some("/srv/jingle.flac"));
+        assert_eq!(file_path("input:Microphone"), None);
+    }
+}
@@ -0,0 +1,257 @@
+//! Synthetic test signals as a track source
+//!
+//! A track whose device ID starts with `generator:` sends a generated
+//! signal instead of capturing a device, to check a link end to end (is
+//! audio arriving, how much is lost, how late) without a microphone:
+//!
+//! * `generator:sine:<Hz>` – steady tone (default 1000 Hz)
+//! * `generator:sweep:<from Hz>:<to Hz>:<seconds>` – repeating logarithmic
+//!   sweep (default 20 Hz to 20 kHz in 10 s)
+//! * `generator:pink`, `generator:white` – noise
+//! * `generator:silence`
+//!
+//! Tones peak at -18 dBFS and noise has the same RMS level as the tone.
+
+use crate::error::AudioError;
+use crate::protocol::AudioDeviceInfo;
+use crate::constants::DEFAULT_SAMPLE_RATE;
+
+/// Prefix of the device IDs of test signals
+pub const GENERATOR_PREFIX: &str = "generator:";
+
+/// Peak level of tones (dBFS)
+pub const LEVEL_DB: f32 = -18.0;
+
+/// Signal produced by a generator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    Sine { frequency: f32 },
+    Sweep { from: f32, to: f32, seconds: f32 },
+    PinkNoise,
+    WhiteNoise,
+    Silence,
+}
+
+impl Signal {
+    /// Parse a `generator:` device ID (None for other devices)
+    pub fn from_device_id(device_id: &str) -> Option<Result<Self, AudioError>> {
+        let spec = device_id.strip_prefix(GENERATOR_PREFIX)?;
+        Some(Self::parse(spec).ok_or_else(|| {
+            AudioError::DeviceNotFound(format!("{} (unknown test signal)", device_id))
+        }))
+    }
+
+    fn parse(spec: &str) -> Option<Self> {
+        let mut parts = spec.split(':');
+        let kind = parts.next()?;
+        let args = parts
+            .map(|part| part.parse::<f32>().ok().filter(|value| value.is_finite() && *value > 0.0))
+            .collect::<Option<Vec<_>>>()?;
+        let arg = |index: usize, default: f32| args.get(index).copied().unwrap_or(default);
+        let nyquist = DEFAULT_SAMPLE_RATE as f32 / 2.0;
+
+        let signal = match kind {
+            "sine" if args.len() <= 1 => Signal::Sine { frequency: arg(0, 1000.0).min(nyquist) },
+            "sweep" if args.len() <= 3 => Signal::Sweep {
+                from: arg(0, 20.0).min(nyquist),
+                to: arg(1, 20_000.0).min(nyquist),
+                seconds: arg(2, 10.0),
+            },
+            "pink" if args.is_empty() => Signal::PinkNoise,
+            "white" if args.is_empty() => Signal::WhiteNoise,
+            "silence" if args.is_empty() => Signal::Silence,
+            _ => return None,
+        };
+        Some(signal)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Signal::Sine { frequency } => format!("sine {} Hz", frequency),
+            Signal::Sweep { from, to, seconds } => format!("sweep {}-{} Hz, {} s", from, to, seconds),
+            Signal::PinkNoise => "pink noise".to_string(),
+            Signal::WhiteNoise => "white noise".to_string(),
+            Signal::Silence => "silence".to_string(),
+        }
+    }
+}
+
+/// Test signals offered in the device list
+pub fn device_info() -> Vec<AudioDeviceInfo> {
+    ["sine:1000", "sweep", "pink", "silence"]
+        .iter()
+        .filter_map(|spec| generator_device(&format!("{}{}", GENERATOR_PREFIX, spec)))
+        .collect()
+}
+
+/// Device entry of a `generator:` ID (None if it isn't a valid one)
+pub fn generator_device(device_id: &str) -> Option<AudioDeviceInfo> {
+    let signal = Signal::from_device_id(device_id)?.ok()?;
+    Some(AudioDeviceInfo {
+        id: device_id.to_string(),
+        name: format!("Test signal: {}", signal.describe()),
+        is_input: true,
+        is_output: false,
+        is_default: false,
+        sample_rates: vec![DEFAULT_SAMPLE_RATE],
+        channels: vec![1],
+        is_loopback: false,
+        capture_mode: None,
+        playback_mode: None,
+    })
+}
+
+/// Mono signal generator
+pub struct SignalGenerator {
+    signal: Signal,
+    sample_rate: f32,
+    amplitude: f32,
+    /// Oscillator phase in cycles
+    phase: f64,
+    /// Time into the current sweep
+    sweep_time: f64,
+    /// xorshift state of the noise
+    seed: u32,
+    /// Pink noise filter state
+    pink: [f32; 7],
+}
+
+/// Noise gains matching the RMS of the tone: sine RMS = peak / sqrt 2,
+/// uniform noise RMS = 1 / sqrt 3, the pink filter's gain is measured
+const WHITE_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2 * 1.732_050_8;
+const PINK_GAIN: f32 = 0.4;
+
+impl SignalGenerator {
+    pub fn new(signal: Signal, sample_rate: u32) -> Self {
+        Self {
+            signal,
+            sample_rate: sample_rate as f32,
+            amplitude: 10f32.powf(LEVEL_DB / 20.0),
+            phase: 0.0,
+            sweep_time: 0.0,
+            seed: 0x9E37_79B9,
+            pink: [0.0; 7],
+        }
+    }
+
+    /// Fill `out` with the next samples
+    pub fn fill(&mut self, out: &mut [f32]) {
+        match self.signal {
+            Signal::Sine { frequency } => {
+                let step = frequency as f64 / self.sample_rate as f64;
+                for sample in out.iter_mut() {
+                    *sample = self.amplitude * (self.phase * std::f64::consts::TAU).sin() as f32;
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+            Signal::Sweep { from, to, seconds } => {
+                let ratio = (to / from) as f64;
+                let dt = 1.0 / self.sample_rate as f64;
+                for sample in out.iter_mut() {
+                    *sample = self.amplitude * (self.phase * std::f64::consts::TAU).sin() as f32;
+                    let frequency = from as f64 * ratio.powf(self.sweep_time / seconds as f64);
+                    self.phase = (self.phase + frequency * dt).fract();
+                    self.sweep_time += dt;
+                    if self.sweep_time >= seconds as f64 {
+                        self.sweep_time = 0.0;
+                    }
+                }
+            }
+            Signal::WhiteNoise => {
+                for sample in out.iter_mut() {
+                    *sample = self.amplitude * WHITE_GAIN * self.white();
+                }
+            }
+            Signal::PinkNoise => {
+                for sample in out.iter_mut() {
+                    // Paul Kellet's refined pink filter
+                    let white = self.white();
+                    let b = &mut self.pink;
+                    b[0] = 0.99886 * b[0] + white * 0.055_517_9;
+                    b[1] = 0.99332 * b[1] + white * 0.075_075_9;
+                    b[2] = 0.96900 * b[2] + white * 0.153_852;
+                    b[3] = 0.86650 * b[3] + white * 0.310_485_6;
+                    b[4] = 0.55000 * b[4] + white * 0.532_952_2;
+                    b[5] = -0.7616 * b[5] - white * 0.016_898;
+                    let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                    b[6] = white * 0.115_926;
+                    *sample = (self.amplitude * PINK_GAIN * pink).clamp(-1.0, 1.0);
+                }
+            }
+            Signal::Silence => out.fill(0.0),
+        }
+    }
+
+    /// Uniform noise in -1.0..1.0
+    fn white(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn parse(device_id: &str) -> Option<Signal> {
+        Signal::from_device_id(device_id).map(|signal| signal.unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("generator:sine:440"), Some(Signal::Sine { frequency: 440.0 }));
+        assert_eq!(parse("generator:sine"), Some(Signal::Sine { frequency: 1000.0 }));
+        assert_eq!(
+            parse("generator:sweep:100:1000"),
+            Some(Signal::Sweep { from: 100.0, to: 1000.0, seconds: 10.0 })
+        );
+        assert_eq!(parse("generator:pink"), Some(Signal::PinkNoise));
+        assert_eq!(parse("input:Microphone"), None);
+        assert!(Signal::from_device_id("generator:sine:-5").unwrap().is_err());
+        assert!(Signal::from_device_id("generator:square").unwrap().is_err());
+        assert_eq!(device_info().len(), 4);
+        assert_eq!(generator_device("generator:sine:440").unwrap().name, "Test signal: sine 440 Hz");
+    }
+
+    #[test]
+    fn test_levels() {
+        let tone_rms = 10f32.powf(LEVEL_DB / 20.0) * std::f32::consts::FRAC_1_SQRT_2;
+        let mut samples = vec![0.0; 48_000];
+
+        let mut sine = SignalGenerator::new(Signal::Sine { frequency: 1000.0 }, 48_000);
+        sine.fill(&mut samples);
+        assert!((rms(&samples) - tone_rms).abs() < 0.001);
+        // 48 samples per cycle: one cycle later the waveform repeats
+        assert!((samples[10] - samples[58]).abs() < 1e-4);
+
+        for signal in [Signal::WhiteNoise, Signal::PinkNoise] {
+            let mut noise = SignalGenerator::new(signal, 48_000);
+            noise.fill(&mut samples);
+            let level = rms(&samples);
+            assert!((level / tone_rms - 1.0).abs() < 0.25, "{:?}: {} vs {}", signal, level, tone_rms);
+        }
+
+        let mut silence = SignalGenerator::new(Signal::Silence, 48_000);
+        silence.fill(&mut samples);
+        assert!(samples.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_sweep_rises_and_repeats() {
+        // Count zero crossings in the first and last tenth of a 1 s sweep
+        let mut sweep = SignalGenerator::new(Signal::Sweep { from: 100.0, to: 10_000.0, seconds: 1.0 }, 48_000);
+        let mut samples = vec![0.0; 96_000];
+        sweep.fill(&mut samples);
+        let crossings = |range: &[f32]| range.windows(2).filter(|w| w[0] <= 0.0 && w[1] > 0.0).count();
+        let start = crossings(&samples[..4_800]);
+        let end = crossings(&samples[43_200..48_000]);
+        assert!(end > start * 20, "{} -> {}", start, end);
+        assert!(crossings(&samples[48_000..52_800]).abs_diff(start) <= 1);
+    }
+}
@@ -20,9 +20,14 @@
 //! - Атомарные операции для безблокировочного доступа из разных потоков
 //! - Пиковый индикатор с плавным затуханием
 
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::time::Instant;
 
+use crate::audio::simd;
+
+/// Количество каналов, пики которых считаются за один проход
+const MAX_BLOCK_CHANNELS: usize = 16;
+
 /// Параметры сглаживания измерителя уровня
 #[derive(Debug, Clone, Copy)]
 pub struct LevelMeterParams {
@@ -46,6 +51,11 @@ pub struct LevelMeterParams {
     
     /// Максимальный уровень в dB (0 dB = полная шкала)
     pub ceiling_db: f32,
+    
+    /// Минимальный интервал пересчёта сглаживания в миллисекундах
+    /// (0 = на каждый блок). Между пересчётами копится только пиковая
+    /// амплитуда - целочисленным максимумом, без log10/exp
+    pub update_interval_ms: f32,
 }
 
 impl Default for LevelMeterParams {
@@ -63,6 +73,19 @@ impl Default for LevelMeterParams {
             floor_db: -96.0,
             // Максимум 0 dB (цифровой потолок)
             ceiling_db: 0.0,
+            // Пересчёт на каждый блок
+            update_interval_ms: 0.0,
+        }
+    }
+}
+
+impl LevelMeterParams {
+    /// Параметры для слабых ARM-устройств: сглаживание пересчитывается
+    /// не чаще раза в 50 мс (UI всё равно опрашивает реже)
+    pub fn low_power() -> Self {
+        Self {
+            update_interval_ms: 50.0,
+            ..Self::default()
         }
     }
 }
@@ -128,6 +151,9 @@ pub struct SmoothLevelMeter {
     
     /// Время старта для относительных вычислений
     start_time: Instant,
+    
+    /// Пик, накопленный между пересчётами (биты f32)
+    pending_peak: AtomicU32,
 }
 
 impl SmoothLevelMeter {
@@ -149,6 +175,7 @@ impl SmoothLevelMeter {
             last_update_us: AtomicU64::new(0),
             last_peak_us: AtomicU64::new(0),
             start_time: Instant::now(),
+            pending_peak: AtomicU32::new(0),
         }
     }
     
@@ -166,9 +193,22 @@ impl SmoothLevelMeter {
         }
         
         // Вычисляем пиковый уровень входного сигнала
-        let peak_amplitude = samples.iter()
-            .map(|s| s.abs())
-            .fold(0.0f32, f32::max);
+        self.update_from_peak(simd::peak_abs(samples));
+    }
+    
+    /// Обновить уровень уже вычисленной пиковой амплитудой (lock-free)
+    pub fn update_from_peak(&self, peak_amplitude: f32) {
+        if self.params.update_interval_ms > 0.0 {
+            let last_us = self.last_update_us.load(Ordering::Relaxed);
+            let elapsed_ms = self.current_time_us().saturating_sub(last_us) as f32 / 1000.0;
+            if last_us > 0 && elapsed_ms < self.params.update_interval_ms {
+                // Биты неотрицательных f32 упорядочены так же, как u32
+                self.pending_peak.fetch_max(peak_amplitude.abs().to_bits(), Ordering::Relaxed);
+                return;
+            }
+        }
+        let pending = f32::from_bits(self.pending_peak.swap(0, Ordering::Relaxed));
+        let peak_amplitude = peak_amplitude.abs().max(pending);
         
         // Конвертируем в dB
         let input_db = if peak_amplitude > 1e-10 {
@@ -278,6 +318,7 @@ impl SmoothLevelMeter {
         self.state.store(initial_state.pack(), Ordering::Relaxed);
         self.last_update_us.store(0, Ordering::Relaxed);
         self.last_peak_us.store(0, Ordering::Relaxed);
+        self.pending_peak.store(0, Ordering::Relaxed);
     }
     
     /// Обновить состояние для UI (вызвать перед чтением для плавной анимации)
@@ -383,22 +424,32 @@ impl MultiChannelLevelMeter {
             return;
         }
         
-        // Обновляем каждый канал отдельно
-        for (ch, meter) in self.channels.iter().enumerate() {
-            if ch < channel_count {
-                // Извлекаем семплы для этого канала
-                let channel_samples: Vec<f32> = samples.iter()
-                    .skip(ch)
-                    .step_by(channel_count)
-                    .copied()
-                    .collect();
-                
-                meter.update_from_samples(&channel_samples);
+        // Семплы читаются на месте (без копирования по каналам)
+        let mut combined = 0.0f32;
+        
+        if channel_count <= MAX_BLOCK_CHANNELS {
+            // Пики всех каналов за один векторизуемый проход
+            let mut peaks = [0.0f32; MAX_BLOCK_CHANNELS];
+            let peaks = &mut peaks[..channel_count];
+            simd::channel_peaks(samples, peaks);
+            
+            for (meter, &peak) in self.channels.iter().zip(peaks.iter()) {
+                meter.update_from_peak(peak);
+            }
+            combined = peaks.iter().copied().fold(combined, f32::max);
+        } else {
+            // Много каналов: шаговый проход по каждому каналу
+            for ch in 0..channel_count {
+                let peak = simd::strided_peak_abs(samples, ch, channel_count);
+                if let Some(meter) = self.channels.get(ch) {
+                    meter.update_from_peak(peak);
+                }
+                combined = combined.max(peak);
             }
         }
         
-        // Обновляем комбинированный измеритель (для всех семплов)
-        self.combined.update_from_samples(samples);
+        // Комбинированный пик - максимум пиков каналов
+        self.combined.update_from_peak(combined);
     }
     
     /// Получить уровень канала в dB
@@ -490,6 +541,46 @@ mod tests {
         assert!(meter.level_db() > -96.0);
     }
     
+    #[test]
+    fn test_multichannel_update_in_place() {
+        for channel_count in [2, 24] {
+            let meter = MultiChannelLevelMeter::new(channel_count);
+            
+            // Громкий сигнал только в последнем канале
+            let samples: Vec<f32> = (0..480 * channel_count)
+                .map(|i| if i % channel_count == channel_count - 1 { 0.9 } else { 0.0 })
+                .collect();
+            meter.update_interleaved(&samples, channel_count);
+            
+            assert!(meter.channel_peak_db(channel_count - 1) > -2.0);
+            assert!(meter.channel_peak_db(0) <= -90.0);
+            assert!(meter.combined_level_db() > meter.channel_level_db(0));
+        }
+    }
+    
+    #[test]
+    fn test_low_power_keeps_peaks_between_updates() {
+        let meter = SmoothLevelMeter::with_params(LevelMeterParams {
+            update_interval_ms: 60_000.0,
+            ..LevelMeterParams::default()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        
+        meter.update_from_peak(0.01);
+        let first_peak = meter.peak_db();
+        
+        // Громкий блок между пересчётами только накапливается...
+        meter.update_from_peak(0.9);
+        meter.update_from_peak(0.02);
+        assert_eq!(meter.peak_db(), first_peak);
+        assert_eq!(f32::from_bits(meter.pending_peak.load(Ordering::Relaxed)), 0.9);
+        
+        // ...и не теряется при следующем пересчёте
+        meter.last_update_us.store(0, Ordering::Relaxed);
+        meter.update_from_peak(0.0);
+        assert!(meter.peak_db() > -2.0);
+    }
+    
     #[test]
     fn test_lerp_i32() {
         assert_eq!(lerp_i32(0, 100, 0.0), 0);
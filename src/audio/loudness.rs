@@ -0,0 +1,247 @@
+//! Loudness metering after EBU R128 (ITU-R BS.1770)
+//!
+//! The peak meter of a track (`level_meter`) shows how close the audio
+//! is to clipping, not how loud it sounds. A [`LoudnessMeter`] measures
+//! loudness in LUFS the way broadcast loudness is specified: every
+//! channel goes through the K-weighting filter (a high shelf for the head
+//! and a high-pass), the weighted power is summed over the channels and
+//! averaged over 100 ms blocks. Momentary loudness covers the last 400 ms,
+//! short-term loudness the last 3 s, and integrated loudness the whole
+//! measurement: the 400 ms blocks above the absolute gate of -70 LUFS,
+//! then only those within 10 LU of their average (relative gate).
+
+use std::f64::consts::PI;
+
+/// Blocks quieter than this never count towards integrated loudness
+pub const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+
+/// Blocks this far below the average of the gated blocks are left out too
+pub const RELATIVE_GATE_LU: f64 = 10.0;
+
+/// Averaging step
+const BLOCK_MS: u32 = 100;
+
+/// Momentary loudness window in blocks (400 ms)
+const MOMENTARY_BLOCKS: usize = 4;
+
+/// Short-term loudness window in blocks (3 s)
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// Resolution of the integrated loudness histogram
+const HISTOGRAM_STEP_LU: f64 = 0.1;
+
+/// Histogram range above the absolute gate (up to +5 LUFS)
+const HISTOGRAM_BINS: usize = 750;
+
+/// Second-order filter section
+#[derive(Debug, Clone, Copy)]
+struct Section {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Section {
+    fn process(&self, state: &mut [f64; 2], input: f64) -> f64 {
+        let output = self.b[0] * input + state[0];
+        state[0] = self.b[1] * input - self.a[0] * output + state[1];
+        state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+/// K-weighting for any sample rate (the BS.1770 filters at 48 kHz,
+/// re-derived from their analog prototypes)
+fn k_weighting(sample_rate: u32) -> [Section; 2] {
+    let fs = sample_rate as f64;
+
+    // Head-related high shelf, +4 dB above about 1.7 kHz
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Section {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    // Revised low-frequency B-curve high-pass at about 38 Hz
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Section {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    [shelf, high_pass]
+}
+
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Loudness meter of one track
+pub struct LoudnessMeter {
+    filters: [Section; 2],
+    /// Filter states per channel
+    states: Vec<[[f64; 2]; 2]>,
+    block_frames: usize,
+    /// Weighted power summed over the current block
+    block_sum: f64,
+    block_filled: usize,
+    /// Mean power of the last blocks, newest last
+    blocks: Vec<f64>,
+    /// Gating blocks above the absolute gate: count and power sum per
+    /// 0.1 LU of loudness
+    histogram_counts: Vec<u64>,
+    histogram_power: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            filters: k_weighting(sample_rate),
+            states: Vec::new(),
+            block_frames: (sample_rate * BLOCK_MS / 1000) as usize,
+            block_sum: 0.0,
+            block_filled: 0,
+            blocks: Vec::with_capacity(SHORT_TERM_BLOCKS),
+            histogram_counts: vec![0; HISTOGRAM_BINS],
+            histogram_power: vec![0.0; HISTOGRAM_BINS],
+        }
+    }
+
+    /// Start a new measurement
+    pub fn reset(&mut self) {
+        self.states.clear();
+        self.block_sum = 0.0;
+        self.block_filled = 0;
+        self.blocks.clear();
+        self.histogram_counts.fill(0);
+        self.histogram_power.fill(0.0);
+    }
+
+    /// Measure interleaved samples with `channels` channels
+    pub fn process(&mut self, samples: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        if self.states.len() != channels {
+            self.states = vec![[[0.0; 2]; 2]; channels];
+        }
+
+        for frame in samples.chunks_exact(channels) {
+            for (&sample, state) in frame.iter().zip(self.states.iter_mut()) {
+                let shelved = self.filters[0].process(&mut state[0], sample as f64);
+                let weighted = self.filters[1].process(&mut state[1], shelved);
+                self.block_sum += weighted * weighted;
+            }
+
+            self.block_filled += 1;
+            if self.block_filled == self.block_frames {
+                self.finish_block();
+            }
+        }
+    }
+
+    fn finish_block(&mut self) {
+        if self.blocks.len() == SHORT_TERM_BLOCKS {
+            self.blocks.remove(0);
+        }
+        self.blocks.push(self.block_sum / self.block_frames as f64);
+        self.block_sum = 0.0;
+        self.block_filled = 0;
+
+        // Every 100 ms closes a 400 ms gating block (75% overlap)
+        if let Some(power) = self.window_power(MOMENTARY_BLOCKS) {
+            let lufs = to_lufs(power);
+            if lufs > ABSOLUTE_GATE_LUFS {
+                let bin = (((lufs - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU) as usize).min(HISTOGRAM_BINS - 1);
+                self.histogram_counts[bin] += 1;
+                self.histogram_power[bin] += power;
+            }
+        }
+    }
+
+    /// Mean power of the last `blocks` blocks (None until measured)
+    fn window_power(&self, blocks: usize) -> Option<f64> {
+        let window = self.blocks.get(self.blocks.len().checked_sub(blocks)?..)?;
+        Some(window.iter().sum::<f64>() / blocks as f64)
+    }
+
+    /// Loudness of the last 400 ms in LUFS
+    pub fn momentary_lufs(&self) -> Option<f64> {
+        self.window_power(MOMENTARY_BLOCKS).map(to_lufs).filter(|lufs| lufs.is_finite())
+    }
+
+    /// Loudness of the last 3 s in LUFS
+    pub fn short_term_lufs(&self) -> Option<f64> {
+        self.window_power(SHORT_TERM_BLOCKS).map(to_lufs).filter(|lufs| lufs.is_finite())
+    }
+
+    /// Gated loudness since the start of the measurement in LUFS
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        let gated = |from_bin: usize| {
+            let count: u64 = self.histogram_counts[from_bin..].iter().sum();
+            let power: f64 = self.histogram_power[from_bin..].iter().sum();
+            (count > 0).then(|| power / count as f64)
+        };
+
+        let relative_gate = to_lufs(gated(0)?) - RELATIVE_GATE_LU;
+        let from_bin = ((relative_gate - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU).ceil().max(0.0) as usize;
+        gated(from_bin.min(HISTOGRAM_BINS - 1)).map(to_lufs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stereo 997 Hz sine with the given peak level in dBFS
+    fn tone(seconds: f64, level_db: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(level_db / 20.0);
+        (0..(seconds * 48_000.0) as usize)
+            .flat_map(|i| {
+                let sample = ((2.0 * PI * 997.0 * i as f64 / 48_000.0).sin() * amplitude) as f32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reference_tone() {
+        // EBU Tech 3341: a -23 dBFS stereo sine at 997 Hz reads -23 LUFS
+        let mut meter = LoudnessMeter::new(48_000);
+        assert!(meter.momentary_lufs().is_none());
+        meter.process(&tone(4.0, -23.0), 2);
+
+        for lufs in [meter.momentary_lufs(), meter.short_term_lufs(), meter.integrated_lufs()] {
+            assert!((lufs.unwrap() + 23.0).abs() < 0.1, "{:?}", lufs);
+        }
+
+        meter.reset();
+        assert!(meter.integrated_lufs().is_none());
+    }
+
+    #[test]
+    fn test_gating() {
+        let mut meter = LoudnessMeter::new(48_000);
+        meter.process(&tone(5.0, -23.0), 2);
+
+        // Silence is below the absolute gate, quiet passages below the
+        // relative gate: neither pulls integrated loudness down
+        meter.process(&vec![0.0; 48_000 * 2 * 5], 2);
+        meter.process(&tone(10.0, -40.0), 2);
+        assert!((meter.integrated_lufs().unwrap() + 23.0).abs() < 0.2);
+        assert!((meter.momentary_lufs().unwrap() + 40.0).abs() < 0.1);
+
+        // A mono input at a lower sample rate
+        let mut mono = LoudnessMeter::new(44_100);
+        let samples: Vec<f32> = (0..44_100 * 2)
+            .map(|i| ((2.0 * PI * 997.0 * i as f64 / 44_100.0).sin() * 0.1) as f32)
+            .collect();
+        mono.process(&samples, 1);
+        // A single channel at -20 dBFS carries half the power of two
+        assert!(mono.short_term_lufs().is_none());
+        assert!((mono.momentary_lufs().unwrap() + 23.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,899 @@
+//! Output mixing of several tracks into one device stream
+//!
+//! Opening a separate output stream per incoming track makes tracks that
+//! target the same device compete for it (on Windows shared-mode endpoints
+//! this causes glitches and failed opens). The [`OutputMixer`] keeps one
+//! stream per device instead: every attached track gets its own frame
+//! buffer and playout cursor, and the output callback sums them with a
+//! per-track gain. An optional [`OutputProcessor`] then runs over the
+//! device's mix (see [`OutputMixer::set_dsp`]).
+//!
+//! With the JACK and PipeWire backends every track keeps a stream of its
+//! own instead, so each track shows up as a separate JACK client or
+//! PipeWire node and can be patched (into a DAW, Helvum, qpwgraph)
+//! individually.
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::audio::buffer::{create_shared_buffer, AudioFrame, SharedRingBuffer};
+use crate::audio::clock::{ClockSkewMonitor, StreamTiming};
+use crate::audio::convert::{convert_channels, is_passthrough};
+use crate::audio::device;
+use crate::audio::dsp::OutputProcessor;
+use crate::audio::playback::AudioPlayback;
+use crate::audio::playout::{PlayoutConfig, PlayoutCursor};
+use crate::audio::null_output::{self, NullOutput};
+use crate::audio::pool;
+use crate::audio::probe::ProbeMeter;
+#[cfg(all(feature = "pipewire", target_os = "linux"))]
+use crate::audio::pw_cat::PwCatOutput;
+use crate::audio::simd;
+use crate::audio::virtual_output::{self, VirtualOutput};
+use crate::config::AudioBackend;
+use crate::error::AudioError;
+use crate::protocol::OutputDsp;
+
+/// Frame buffer capacity of one mixer input
+const INPUT_BUFFER_FRAMES: usize = 64;
+
+/// One track feeding a device mix
+struct MixerInput {
+    buffer: SharedRingBuffer,
+    cursor: PlayoutCursor,
+    /// Requested gain (f32 bits, set from outside the callback)
+    gain: Arc<AtomicU32>,
+    /// Gain applied to the last block (ramped towards `gain`)
+    current_gain: f32,
+    /// Latency probes of the track
+    probe: Arc<ProbeMeter>,
+    /// Times the input ran dry while playing
+    underruns: Arc<AtomicU64>,
+    /// The sender suppresses silence: running dry is expected
+    silent: Arc<AtomicBool>,
+    /// Clock drift correction of the cursor in ppm (f32 bits, NaN until
+    /// estimated)
+    drift: Arc<AtomicU32>,
+    /// Drift correction set by the track's scheduler in ppm (f32 bits, NaN =
+    /// the cursor estimates it)
+    rate: Arc<AtomicU32>,
+}
+
+/// Tracks mixed into one output stream (read by the output callback)
+pub(crate) struct MixerInputs {
+    channels: usize,
+    playout_config: PlayoutConfig,
+    inputs: Vec<MixerInput>,
+    /// Per-input read buffer, reused between callbacks
+    scratch: Vec<f32>,
+    /// Master processing of the device mix
+    dsp: Option<OutputProcessor>,
+    /// Gain the master processing takes off in dB (f32 bits, NaN = no
+    /// compressor or limiter), read by the tracks' channels
+    gain_reduction: Arc<AtomicU32>,
+}
+
+impl MixerInputs {
+    pub(crate) fn new(channels: usize, playout_config: PlayoutConfig) -> Self {
+        Self {
+            channels: channels.max(1),
+            playout_config,
+            inputs: Vec::new(),
+            scratch: Vec::new(),
+            dsp: None,
+            gain_reduction: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+        }
+    }
+
+    /// Replace the master processing of the mix (None = bypass)
+    fn set_dsp(&mut self, dsp: Option<OutputProcessor>) {
+        self.dsp = dsp;
+        self.store_gain_reduction();
+    }
+
+    fn store_gain_reduction(&self) {
+        let reduction_db = self.dsp.as_ref().and_then(OutputProcessor::gain_reduction_db);
+        self.gain_reduction.store(reduction_db.unwrap_or(f32::NAN).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Add a track input reading from `buffer` at the drift correction in
+    /// `rate`; returns the input's clock drift correction (see
+    /// [`MixerChannel::drift_ppm`])
+    fn add(
+        &mut self,
+        buffer: SharedRingBuffer,
+        gain: Arc<AtomicU32>,
+        probe: Arc<ProbeMeter>,
+        underruns: Arc<AtomicU64>,
+        silent: Arc<AtomicBool>,
+        rate: Arc<AtomicU32>,
+    ) -> Arc<AtomicU32> {
+        let current_gain = f32::from_bits(gain.load(Ordering::Relaxed));
+        let drift = Arc::new(AtomicU32::new(f32::NAN.to_bits()));
+        self.inputs.push(MixerInput {
+            buffer,
+            cursor: PlayoutCursor::new(self.channels, self.playout_config),
+            gain,
+            current_gain,
+            probe,
+            underruns,
+            silent,
+            drift: drift.clone(),
+            rate,
+        });
+        drift
+    }
+
+    /// Remove the input reading from `buffer`; returns true if it was attached
+    /// (matched by buffer, so a track re-attached before the old channel is
+    /// dropped keeps its new input)
+    fn remove(&mut self, buffer: &SharedRingBuffer) -> bool {
+        let before = self.inputs.len();
+        self.inputs.retain(|input| !Arc::ptr_eq(&input.buffer, buffer));
+        self.inputs.len() != before
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Whether any input is accelerated to catch up to the live edge
+    pub(crate) fn is_catching_up(&self) -> bool {
+        self.inputs.iter().any(|input| input.cursor.is_catching_up())
+    }
+
+    /// Sum all inputs into an interleaved output buffer.
+    /// Returns the number of samples inputs could not fill (underrun).
+    pub(crate) fn mix(&mut self, out: &mut [f32]) -> usize {
+        out.fill(0.0);
+        self.scratch.resize(out.len(), 0.0);
+
+        let mut missing = 0;
+        for input in &mut self.inputs {
+            let rate = f32::from_bits(input.rate.load(Ordering::Relaxed));
+            input.cursor.set_drift_ppm((!rate.is_nan()).then_some(rate as f64));
+            let input_missing = input.cursor.fill(&mut self.scratch, &input.buffer);
+            if input_missing > 0 && !input.silent.load(Ordering::Relaxed) {
+                input.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+            missing += input_missing;
+            if let Some(sent_us) = input.cursor.take_probe() {
+                input.probe.record_played(sent_us);
+            }
+            let drift_ppm = input.cursor.drift_ppm().map_or(f32::NAN, |ppm| ppm as f32);
+            input.drift.store(drift_ppm.to_bits(), Ordering::Relaxed);
+
+            let gain = f32::from_bits(input.gain.load(Ordering::Relaxed));
+            if input.current_gain == 0.0 && gain == 0.0 {
+                continue;
+            }
+            simd::apply_gain_ramp(&mut self.scratch, self.channels, input.current_gain, gain);
+            input.current_gain = gain;
+            simd::mix_into(out, &self.scratch, 1.0);
+        }
+
+        if let Some(dsp) = &mut self.dsp {
+            dsp.process(out);
+            self.store_gain_reduction();
+        }
+
+        // Several loud tracks (or an EQ boost) can sum above full scale
+        if self.inputs.len() > 1 || self.dsp.is_some() {
+            for sample in out.iter_mut() {
+                *sample = sample.clamp(-1.0, 1.0);
+            }
+        }
+
+        missing
+    }
+}
+
+/// Stream a device mix is played through
+enum DeviceOutput {
+    Device(AudioPlayback),
+    Virtual(VirtualOutput),
+    Null(NullOutput),
+    #[cfg(all(feature = "pipewire", target_os = "linux"))]
+    PwCat(PwCatOutput),
+}
+
+impl DeviceOutput {
+    fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+        match self {
+            Self::Device(playback) => playback.clock_monitor(),
+            Self::Virtual(output) => output.clock_monitor(),
+            Self::Null(output) => output.clock_monitor(),
+            #[cfg(all(feature = "pipewire", target_os = "linux"))]
+            Self::PwCat(output) => output.clock_monitor(),
+        }
+    }
+
+    /// Period and latency of a device stream (None for virtual, null and
+    /// PipeWire outputs, which are not driven by a device buffer)
+    fn timing(&self) -> Option<&Arc<StreamTiming>> {
+        match self {
+            Self::Device(playback) => Some(playback.timing()),
+            _ => None,
+        }
+    }
+}
+
+/// Shared output stream of one device
+struct DeviceMix {
+    /// Device the stream plays to (the mix key can also name the track)
+    device_id: String,
+    /// Channels of the stream (more than the mixer's for a surround track)
+    channels: usize,
+    playback: DeviceOutput,
+    inputs: Arc<Mutex<MixerInputs>>,
+}
+
+/// One output stream per device, shared by all tracks playing to it
+pub struct OutputMixer {
+    sample_rate: u32,
+    channels: u16,
+    playout_config: PlayoutConfig,
+    devices: Mutex<HashMap<String, DeviceMix>>,
+    /// Master processing settings by device ID (kept while no stream is open)
+    dsp: Mutex<HashMap<String, OutputDsp>>,
+}
+
+impl OutputMixer {
+    /// Create a mixer whose device streams use the given format
+    pub fn new(sample_rate: u32, channels: u16) -> Arc<Self> {
+        Arc::new(Self {
+            sample_rate,
+            channels,
+            playout_config: PlayoutConfig::default(),
+            devices: Mutex::new(HashMap::new()),
+            dsp: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Set the master processing of a device (None = bypass). Applies to the
+    /// open streams of the device and to streams opened later.
+    pub fn set_dsp(&self, device_id: &str, dsp: Option<OutputDsp>) {
+        let devices = self.devices.lock();
+        let mut settings = self.dsp.lock();
+        match dsp.filter(|dsp| !dsp.is_bypass()) {
+            Some(dsp) => settings.insert(device_id.to_string(), dsp),
+            None => settings.remove(device_id),
+        };
+        for mix in devices.values().filter(|mix| mix.device_id == device_id) {
+            mix.inputs.lock().set_dsp(self.processor(device_id, mix.channels, &settings));
+        }
+    }
+
+    /// Master processing settings of a device
+    pub fn dsp(&self, device_id: &str) -> Option<OutputDsp> {
+        self.dsp.lock().get(device_id).cloned()
+    }
+
+    fn processor(&self, device_id: &str, channels: usize, settings: &HashMap<String, OutputDsp>) -> Option<OutputProcessor> {
+        settings
+            .get(device_id)
+            .map(|dsp| OutputProcessor::new(dsp, self.sample_rate, channels))
+    }
+
+    /// Attach a track to a device, opening the device stream if this is its
+    /// first track. The track is detached when the returned channel is dropped.
+    ///
+    /// `buffer_frames` is the device buffer the track asks for (None = host
+    /// default); the track that opens a shared stream decides its buffer.
+    pub fn attach(
+        self: &Arc<Self>,
+        track_id: u8,
+        device_id: &str,
+        buffer_frames: Option<u32>,
+    ) -> Result<MixerChannel, AudioError> {
+        self.attach_channels(track_id, device_id, buffer_frames, self.channels)
+    }
+
+    /// Attach a track of `channels` channels. A stream the track opens has
+    /// the track's layout where the output plays that many channels (a
+    /// surround track on a 5.1 or 7.1 device); otherwise, and on a stream
+    /// already open with fewer channels, the track is folded down.
+    pub fn attach_channels(
+        self: &Arc<Self>,
+        track_id: u8,
+        device_id: &str,
+        buffer_frames: Option<u32>,
+        channels: u16,
+    ) -> Result<MixerChannel, AudioError> {
+        let mut devices = self.devices.lock();
+        let mix_key = Self::mix_key(device::backend(), track_id, device_id);
+
+        if !devices.contains_key(&mix_key) {
+            let stream_channels = self.stream_channels(device_id, channels);
+            let mut mix_inputs = MixerInputs::new(stream_channels as usize, self.playout_config);
+            mix_inputs.set_dsp(self.processor(device_id, stream_channels as usize, &self.dsp.lock()));
+            let inputs = Arc::new(Mutex::new(mix_inputs));
+            let playback = self.open_output(track_id, device_id, buffer_frames, stream_channels, inputs.clone())?;
+            tracing::info!("Opened shared output stream on {} ({} channels)", mix_key, stream_channels);
+            devices.insert(
+                mix_key.clone(),
+                DeviceMix {
+                    device_id: device_id.to_string(),
+                    channels: stream_channels as usize,
+                    playback,
+                    inputs,
+                },
+            );
+        }
+
+        let device = &devices[&mix_key];
+        let buffer = create_shared_buffer(INPUT_BUFFER_FRAMES);
+        let gain = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+        let probe = Arc::new(ProbeMeter::new());
+        let underruns = Arc::new(AtomicU64::new(0));
+        let silent = Arc::new(AtomicBool::new(false));
+        let rate = Arc::new(AtomicU32::new(f32::NAN.to_bits()));
+        let mut inputs = device.inputs.lock();
+        let drift = inputs.add(
+            buffer.clone(),
+            gain.clone(),
+            probe.clone(),
+            underruns.clone(),
+            silent.clone(),
+            rate.clone(),
+        );
+        let gain_reduction = inputs.gain_reduction.clone();
+        drop(inputs);
+
+        Ok(MixerChannel {
+            track_id,
+            device_id: device_id.to_string(),
+            mix_key,
+            channels: device.channels,
+            track_channels: channels,
+            channel_map: RwLock::new(Vec::new()),
+            buffer,
+            gain,
+            probe,
+            underruns,
+            silent,
+            gain_reduction,
+            drift,
+            rate,
+            clock: device.playback.clock_monitor().clone(),
+            timing: device.playback.timing().cloned(),
+            monitor: Mutex::new(Monitor::Off),
+            copies: Mutex::new(Vec::new()),
+            mixer: self.clone(),
+        })
+    }
+
+    /// Number of open device streams
+    pub fn device_count(&self) -> usize {
+        self.devices.lock().len()
+    }
+
+    /// Channels to open a stream for a track of `channels` with: the
+    /// track's own if the output plays that many, the mixer's otherwise
+    fn stream_channels(&self, device_id: &str, channels: u16) -> u16 {
+        if channels <= self.channels || virtual_output::is_virtual(device_id) {
+            return self.channels;
+        }
+        if null_output::is_null(device_id) {
+            return channels;
+        }
+        match device::get_device_by_id(device_id) {
+            Ok(device) if device.max_output_channels() >= channels => channels,
+            _ => self.channels,
+        }
+    }
+
+    #[cfg_attr(not(all(feature = "pipewire", target_os = "linux")), allow(unused_variables))]
+    fn open_output(
+        &self,
+        track_id: u8,
+        device_id: &str,
+        buffer_frames: Option<u32>,
+        channels: u16,
+        inputs: Arc<Mutex<MixerInputs>>,
+    ) -> Result<DeviceOutput, AudioError> {
+        if virtual_output::is_virtual(device_id) {
+            return Ok(DeviceOutput::Virtual(VirtualOutput::start(self.sample_rate, channels, inputs)?));
+        }
+        if null_output::is_null(device_id) {
+            return Ok(DeviceOutput::Null(NullOutput::start(self.sample_rate, channels, inputs)?));
+        }
+
+        #[cfg(all(feature = "pipewire", target_os = "linux"))]
+        if device::backend() == AudioBackend::Pipewire {
+            return Ok(DeviceOutput::PwCat(PwCatOutput::start(
+                track_id,
+                device_id,
+                self.sample_rate,
+                channels,
+                inputs,
+            )?));
+        }
+
+        let mut playback = AudioPlayback::mixed(track_id, device_id, Some(self.sample_rate), Some(channels), buffer_frames, inputs)?;
+        playback.start()?;
+        Ok(DeviceOutput::Device(playback))
+    }
+
+    /// Stream a track is mixed into under `backend`: shared per device, own
+    /// stream under JACK and PipeWire
+    fn mix_key(backend: AudioBackend, track_id: u8, device_id: &str) -> String {
+        let per_track = matches!(backend, AudioBackend::Jack | AudioBackend::Pipewire);
+        if per_track && !virtual_output::is_virtual(device_id) && !null_output::is_null(device_id) {
+            format!("{}#{}", device_id, track_id)
+        } else {
+            device_id.to_string()
+        }
+    }
+
+    fn detach(&self, buffer: &SharedRingBuffer, mix_key: &str) {
+        let mut devices = self.devices.lock();
+        let Some(device) = devices.get(mix_key) else {
+            return;
+        };
+
+        let now_empty = {
+            let mut inputs = device.inputs.lock();
+            inputs.remove(buffer);
+            inputs.is_empty()
+        };
+        if now_empty {
+            // Dropping the playback stops the stream
+            devices.remove(mix_key);
+            tracing::info!("Closed shared output stream on {}", mix_key);
+        }
+    }
+}
+
+/// Pre-fader listen copy of a track
+enum Monitor {
+    Off,
+    On(Box<MixerChannel>),
+    /// The monitor output failed to open; not retried until switched off
+    Failed,
+}
+
+/// A track's input into a shared device stream
+pub struct MixerChannel {
+    track_id: u8,
+    device_id: String,
+    /// Key of the stream in the mixer
+    mix_key: String,
+    /// Channels of the device stream
+    channels: usize,
+    /// Channels of the track (copies and the monitor open streams for it)
+    track_channels: u16,
+    /// Source channel of every device channel (empty = automatic)
+    channel_map: RwLock<Vec<usize>>,
+    buffer: SharedRingBuffer,
+    gain: Arc<AtomicU32>,
+    probe: Arc<ProbeMeter>,
+    underruns: Arc<AtomicU64>,
+    silent: Arc<AtomicBool>,
+    gain_reduction: Arc<AtomicU32>,
+    drift: Arc<AtomicU32>,
+    rate: Arc<AtomicU32>,
+    clock: Arc<ClockSkewMonitor>,
+    timing: Option<Arc<StreamTiming>>,
+    monitor: Mutex<Monitor>,
+    /// Copies of the track on further output devices, at the track's gain
+    copies: Mutex<Vec<MixerChannel>>,
+    mixer: Arc<OutputMixer>,
+}
+
+impl MixerChannel {
+    /// Queue a decoded frame for playout (converted to the device channel count)
+    pub fn push_frame(&self, mut frame: AudioFrame) -> bool {
+        self.silent.store(false, Ordering::Relaxed);
+        if let Monitor::On(ref monitor) = *self.monitor.lock() {
+            monitor.push_frame(frame.pooled_copy());
+        }
+        for copy in self.copies.lock().iter() {
+            copy.push_frame(frame.pooled_copy());
+        }
+        let map = self.channel_map.read();
+        if !is_passthrough(frame.channels as usize, self.channels, &map) {
+            let samples = convert_channels(&frame.samples, frame.channels as usize, self.channels, &map);
+            pool::samples().recycle(std::mem::replace(&mut frame.samples, samples));
+            frame.channels = self.channels as u16;
+        }
+        drop(map);
+        self.buffer.push(frame)
+    }
+
+    /// Set the source channel of every device channel (empty = automatic)
+    pub fn set_channel_map(&self, map: Vec<usize>) {
+        for copy in self.copies.lock().iter() {
+            copy.set_channel_map(map.clone());
+        }
+        *self.channel_map.write() = map;
+    }
+
+    /// Set the track's gain in the mix (ramped in the output callback)
+    pub fn set_gain(&self, gain: f32) {
+        for copy in self.copies.lock().iter() {
+            copy.set_gain(gain);
+        }
+        self.gain.store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Current gain of the track in the mix
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    /// Track ID
+    pub fn track_id(&self) -> u8 {
+        self.track_id
+    }
+
+    /// Device the track plays to
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Device clock skew vs host clock in ppm (None until measured)
+    pub fn clock_skew_ppm(&self) -> Option<f64> {
+        self.clock.skew_ppm()
+    }
+
+    /// Clock skew monitor of the shared device stream
+    pub fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+        &self.clock
+    }
+
+    /// Callback period of the shared device stream in ms (None until it
+    /// runs, and for virtual and PipeWire outputs)
+    pub fn device_period_ms(&self) -> Option<f32> {
+        self.timing.as_ref().and_then(|timing| timing.period_ms())
+    }
+
+    /// Output latency the host reports for the shared device stream in ms
+    pub fn device_latency_ms(&self) -> Option<f32> {
+        self.timing.as_ref().and_then(|timing| timing.latency_ms())
+    }
+
+    /// Gain the device's compressor and limiter take off the mix in dB
+    /// (None without either)
+    pub fn gain_reduction_db(&self) -> Option<f32> {
+        let value = f32::from_bits(self.gain_reduction.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+
+    /// Clock drift correction of the track's playout in ppm: positive when
+    /// the sender's clock runs fast against the device (None until estimated)
+    pub fn drift_ppm(&self) -> Option<f32> {
+        let value = f32::from_bits(self.drift.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Play the track at the drift correction of the scheduler releasing its
+    /// frames, in ppm (None: estimate it from the buffer fill). Copies and
+    /// the monitor play on other clocks and keep estimating their own.
+    pub fn set_rate_correction_ppm(&self, ppm: Option<f64>) {
+        let value = ppm.map_or(f32::NAN, |ppm| ppm as f32);
+        self.rate.store(value.to_bits(), Ordering::Relaxed);
+    }
+    
+    /// Latency probes of the track measured at the output
+    pub fn probe_meter(&self) -> &ProbeMeter {
+        &self.probe
+    }
+
+    /// Also play the track on `device_id` at unity gain, independent of the
+    /// track's own gain (pre-fader listen); None stops the copy
+    pub fn set_monitor(&self, device_id: Option<&str>) {
+        let mut monitor = self.monitor.lock();
+        match (device_id, &*monitor) {
+            (None, _) => *monitor = Monitor::Off,
+            (Some(device_id), Monitor::Off) => {
+                *monitor = match self.mixer.attach_channels(self.track_id, device_id, None, self.track_channels) {
+                    Ok(channel) => {
+                        tracing::info!("Track {} monitored on {}", self.track_id, device_id);
+                        Monitor::On(Box::new(channel))
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to monitor track {} on {}: {}", self.track_id, device_id, e);
+                        Monitor::Failed
+                    }
+                };
+            }
+            (Some(_), _) => {}
+        }
+    }
+
+    /// Whether the track is copied to a monitor output
+    pub fn is_monitored(&self) -> bool {
+        matches!(*self.monitor.lock(), Monitor::On(_))
+    }
+
+    /// Also play the track on these devices, following its gain and channel
+    /// map (e.g. headphones next to a virtual cable). Copies on devices no
+    /// longer listed are closed, a device that fails to open is skipped
+    /// until the list is set again.
+    pub fn set_copies(&self, device_ids: &[String], buffer_frames: Option<u32>) {
+        let mut copies = self.copies.lock();
+        copies.retain(|copy| device_ids.contains(&copy.device_id));
+
+        for device_id in device_ids {
+            if *device_id == self.device_id || copies.iter().any(|copy| copy.device_id == *device_id) {
+                continue;
+            }
+            match self.mixer.attach_channels(self.track_id, device_id, buffer_frames, self.track_channels) {
+                Ok(copy) => {
+                    tracing::info!("Track {} also plays on {}", self.track_id, device_id);
+                    copy.set_gain(self.gain());
+                    copy.set_channel_map(self.channel_map.read().clone());
+                    copies.push(copy);
+                }
+                Err(e) => tracing::warn!("Failed to play track {} on {}: {}", self.track_id, device_id, e),
+            }
+        }
+    }
+
+    /// Times the output ran dry on this track since the last call
+    pub fn take_underruns(&self) -> u64 {
+        self.underruns.swap(0, Ordering::Relaxed)
+    }
+    
+    /// The sender went silent after the queued frames: the output plays
+    /// silence once they are out, without counting an underrun, until the
+    /// next frame is pushed
+    pub fn set_silent(&self) {
+        self.silent.store(true, Ordering::Relaxed);
+        if let Monitor::On(ref monitor) = *self.monitor.lock() {
+            monitor.set_silent();
+        }
+        for copy in self.copies.lock().iter() {
+            copy.set_silent();
+        }
+    }
+}
+
+impl Drop for MixerChannel {
+    fn drop(&mut self) {
+        self.mixer.detach(&self.buffer, &self.mix_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::timesync::media_time_us;
+
+    fn push_constant(buffer: &SharedRingBuffer, frames: usize, value: f32, len: usize) {
+        for f in 0..frames {
+            buffer.push(AudioFrame::new(vec![value; len], 2, 0, f as u32));
+        }
+    }
+
+    fn input(inputs: &mut MixerInputs, gain: f32) -> (SharedRingBuffer, Arc<AtomicU32>) {
+        let buffer = create_shared_buffer(INPUT_BUFFER_FRAMES);
+        let gain = Arc::new(AtomicU32::new(gain.to_bits()));
+        inputs.add(
+            buffer.clone(),
+            gain.clone(),
+            Arc::new(ProbeMeter::new()),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+        );
+        (buffer, gain)
+    }
+
+    #[test]
+    fn test_mix_follows_scheduler_rate() {
+        let mut inputs = MixerInputs::new(2, PlayoutConfig::default());
+        let buffer = create_shared_buffer(INPUT_BUFFER_FRAMES);
+        let rate = Arc::new(AtomicU32::new(250.0f32.to_bits()));
+        let drift = inputs.add(
+            buffer.clone(),
+            Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            Arc::new(ProbeMeter::new()),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(AtomicBool::new(false)),
+            rate.clone(),
+        );
+        push_constant(&buffer, 8, 0.2, 64);
+
+        // The cursor reads at the scheduler's rate instead of estimating one
+        let mut out = vec![0.0; 64];
+        inputs.mix(&mut out);
+        assert!((f32::from_bits(drift.load(Ordering::Relaxed)) - 250.0).abs() < 1e-3);
+
+        rate.store(f32::NAN.to_bits(), Ordering::Relaxed);
+        inputs.mix(&mut out);
+        assert_ne!(f32::from_bits(drift.load(Ordering::Relaxed)), 250.0);
+    }
+
+    #[test]
+    fn test_mix_sums_tracks_with_gain() {
+        let mut inputs = MixerInputs::new(2, PlayoutConfig::default());
+        let (a, _) = input(&mut inputs, 1.0);
+        let (b, gain_b) = input(&mut inputs, 0.5);
+        push_constant(&a, 8, 0.2, 64);
+        push_constant(&b, 8, 0.4, 64);
+
+        let mut out = vec![0.0; 64];
+        assert_eq!(inputs.mix(&mut out), 0);
+        // Skip the interpolation warm-up sample frame
+        assert!(out[2..].iter().all(|&s| (s - 0.4).abs() < 1e-6), "{:?}", &out[..8]);
+
+        // Muting a track ramps it out over one block
+        gain_b.store(0.0f32.to_bits(), Ordering::Relaxed);
+        inputs.mix(&mut out);
+        assert!(out[out.len() - 1] < out[0]);
+        inputs.mix(&mut out);
+        assert!(out.iter().all(|&s| (s - 0.2).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_mix_clamps_and_removes() {
+        let mut inputs = MixerInputs::new(2, PlayoutConfig::default());
+        let (a, _) = input(&mut inputs, 1.0);
+        let (b, _) = input(&mut inputs, 1.0);
+        push_constant(&a, 8, 0.8, 64);
+        push_constant(&b, 8, 0.8, 64);
+
+        let mut out = vec![0.0; 64];
+        inputs.mix(&mut out);
+        assert!(out.iter().all(|&s| s <= 1.0));
+
+        assert!(inputs.remove(&b));
+        assert!(!inputs.remove(&b));
+        inputs.mix(&mut out);
+        assert!(out[2..].iter().all(|&s| (s - 0.8).abs() < 1e-6));
+
+        assert!(inputs.remove(&a));
+        assert!(inputs.is_empty());
+        inputs.mix(&mut out);
+        assert!(out.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_mix_applies_dsp_after_summing() {
+        let mut inputs = MixerInputs::new(2, PlayoutConfig::default());
+        let (a, _) = input(&mut inputs, 1.0);
+        let (b, _) = input(&mut inputs, 1.0);
+        push_constant(&a, 8, 0.3, 64);
+        push_constant(&b, 8, 0.3, 64);
+        let dsp = OutputDsp {
+            limiter: Some(crate::protocol::LimiterSettings {
+                ceiling_db: -6.0,
+                release_ms: 100.0,
+            }),
+            ..OutputDsp::default()
+        };
+        inputs.set_dsp(Some(OutputProcessor::new(&dsp, 48_000, 2)));
+
+        // The limiter sees the 0.6 sum, not the 0.3 inputs
+        let mut out = vec![0.0; 64];
+        inputs.mix(&mut out);
+        let ceiling = 10f32.powf(-6.0 / 20.0);
+        assert!(out[2..].iter().all(|&s| (s - ceiling).abs() < 1e-4), "{:?}", &out[..8]);
+        let reduction_db = f32::from_bits(inputs.gain_reduction.load(Ordering::Relaxed));
+        assert!((reduction_db - 20.0 * (0.6 / ceiling).log10()).abs() < 0.01);
+
+        inputs.set_dsp(None);
+        inputs.mix(&mut out);
+        assert!(out.iter().all(|&s| (s - 0.6).abs() < 1e-6));
+        assert!(f32::from_bits(inputs.gain_reduction.load(Ordering::Relaxed)).is_nan());
+    }
+
+    #[test]
+    fn test_mix_counts_underruns() {
+        let mut inputs = MixerInputs::new(2, PlayoutConfig::default());
+        let buffer = create_shared_buffer(INPUT_BUFFER_FRAMES);
+        let underruns = Arc::new(AtomicU64::new(0));
+        let silent = Arc::new(AtomicBool::new(false));
+        inputs.add(
+            buffer.clone(),
+            Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            Arc::new(ProbeMeter::new()),
+            underruns.clone(),
+            silent.clone(),
+            Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+        );
+
+        // Waiting for pre-roll is not an underrun, running dry is (once)
+        let mut out = vec![0.0; 64];
+        inputs.mix(&mut out);
+        assert_eq!(underruns.load(Ordering::Relaxed), 0);
+        push_constant(&buffer, 4, 0.1, 64);
+        for _ in 0..8 {
+            inputs.mix(&mut out);
+        }
+        assert_eq!(underruns.load(Ordering::Relaxed), 1);
+
+        // Nor is running dry after a silence marker
+        silent.store(true, Ordering::Relaxed);
+        push_constant(&buffer, 4, 0.1, 64);
+        for _ in 0..8 {
+            inputs.mix(&mut out);
+        }
+        assert_eq!(underruns.load(Ordering::Relaxed), 1);
+        assert!(out.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_mix_records_probe_playout() {
+        let mut inputs = MixerInputs::new(2, PlayoutConfig::default());
+        let buffer = create_shared_buffer(INPUT_BUFFER_FRAMES);
+        let probe = Arc::new(ProbeMeter::new());
+        inputs.add(
+            buffer.clone(),
+            Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            probe.clone(),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+        );
+
+        push_constant(&buffer, 3, 0.1, 64);
+        let mut frame = AudioFrame::new(vec![0.1; 64], 2, 0, 3);
+        frame.probe_us = Some(media_time_us());
+        buffer.push(frame);
+
+        // Probe latency is measured when the tagged frame starts playing
+        let mut out = vec![0.0; 64];
+        inputs.mix(&mut out);
+        inputs.mix(&mut out);
+        assert!(probe.output_latency_us().is_none());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        inputs.mix(&mut out);
+        inputs.mix(&mut out);
+        assert!(probe.output_latency_us().unwrap() >= 5_000);
+    }
+
+    #[test]
+    fn test_null_output_plays_in_real_time() {
+        let mixer = OutputMixer::new(48_000, 2);
+        let channel = mixer.attach(0, null_output::NULL_DEVICE_ID, None).unwrap();
+        assert_eq!(mixer.device_count(), 1);
+        assert!(channel.device_period_ms().is_none());
+
+        // 50 ms of audio is played out, then the track runs dry
+        for sequence in 0..5 {
+            assert!(channel.push_frame(AudioFrame::new(vec![0.1; 960], 2, 0, sequence)));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert_eq!(channel.take_underruns(), 1);
+
+        drop(channel);
+        assert_eq!(mixer.device_count(), 0);
+    }
+
+    #[test]
+    fn test_own_stream_per_track_under_jack_and_pipewire() {
+        let device_id = "output:Speakers";
+        assert_eq!(OutputMixer::mix_key(AudioBackend::Default, 3, device_id), device_id);
+        for backend in [AudioBackend::Jack, AudioBackend::Pipewire] {
+            assert_eq!(OutputMixer::mix_key(backend, 3, device_id), "output:Speakers#3");
+            assert_ne!(OutputMixer::mix_key(backend, 3, device_id), OutputMixer::mix_key(backend, 4, device_id));
+            // Null and virtual outputs stay shared
+            assert_eq!(
+                OutputMixer::mix_key(backend, 3, null_output::NULL_DEVICE_ID),
+                null_output::NULL_DEVICE_ID
+            );
+        }
+    }
+
+    #[test]
+    fn test_surround_track_opens_surround_stream() {
+        let mixer = OutputMixer::new(48_000, 2);
+        let surround = mixer.attach_channels(1, null_output::NULL_DEVICE_ID, None, 6).unwrap();
+        assert_eq!(surround.channels, 6);
+        // A stereo track joining the 5.1 stream is spread over it
+        let stereo = mixer.attach(2, null_output::NULL_DEVICE_ID, None).unwrap();
+        assert_eq!((stereo.channels, mixer.device_count()), (6, 1));
+        assert!(stereo.push_frame(AudioFrame::new(vec![0.1; 960], 2, 0, 0)));
+        drop((surround, stereo));
+
+        // On a stream opened stereo the surround track is folded down
+        let stereo = mixer.attach(2, null_output::NULL_DEVICE_ID, None).unwrap();
+        let surround = mixer.attach_channels(1, null_output::NULL_DEVICE_ID, None, 6).unwrap();
+        assert_eq!((stereo.channels, surround.channels), (2, 2));
+    }
+}
@@ -2,14 +2,51 @@
 //!
 //! Содержит компоненты для захвата, воспроизведения и измерения аудио.
 
+pub mod agc;
 pub mod capture;
 pub mod playback;
+pub mod mixer;
 pub mod buffer;
+pub mod convert;
+pub mod decode;
 pub mod device;
+pub mod dsp;
+pub mod file_source;
+pub mod generator;
 pub mod level_meter;
+pub mod loudness;
+pub mod null_output;
+pub mod clock;
+pub mod playout;
+pub mod pool;
+pub mod probe;
+pub mod resample;
+pub mod scheduler;
+pub mod silence;
+pub mod simd;
+pub mod stretch;
+pub mod voice;
+pub mod wasapi;
+#[cfg(target_os = "linux")]
+pub(crate) mod pipe;
+#[cfg(all(feature = "pipewire", target_os = "linux"))]
+pub mod pw_cat;
+pub mod virtual_output;
 
+pub use agc::Agc;
 pub use capture::AudioCapture;
 pub use playback::AudioPlayback;
-pub use buffer::RingBuffer;
+pub use mixer::{MixerChannel, OutputMixer};
+pub use buffer::{Playout, RingBuffer};
 pub use device::{list_devices, get_device_by_id, AudioDevice};
+pub use dsp::OutputProcessor;
+pub use file_source::FilePlayer;
 pub use level_meter::{SmoothLevelMeter, MultiChannelLevelMeter, LevelMeterParams};
+pub use loudness::LoudnessMeter;
+pub use clock::{ClockSkewMonitor, StreamTiming};
+pub use playout::{PlayoutConfig, PlayoutCursor};
+pub use probe::{LoopbackProbe, ProbeInjector, ProbeMeter};
+pub use resample::Resampler;
+pub use scheduler::PlayoutScheduler;
+pub use silence::{GateAction, SilenceGate};
+pub use voice::VoiceProcessor;
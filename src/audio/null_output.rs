@@ -0,0 +1,91 @@
+//! Output that plays to nowhere
+//!
+//! The [`NULL_DEVICE_ID`] output mixes its tracks like a device stream, but
+//! paced by the system clock instead of a sound card, and discards the mix.
+//! The whole receive path (jitter buffer, playout cursor, underrun
+//! counting, latency probes) runs as with a device, so a receiver can be
+//! exercised on a headless machine or in CI (see `selftest`).
+
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::audio::clock::ClockSkewMonitor;
+use crate::audio::mixer::MixerInputs;
+use crate::error::AudioError;
+
+/// Device ID of the null output
+pub const NULL_DEVICE_ID: &str = "null";
+
+/// Frames mixed per block (5 ms at 48 kHz)
+const BLOCK_FRAMES: usize = 240;
+
+/// The pace restarts from now when the thread falls this far behind
+/// (after a suspend), instead of mixing the missed blocks in a burst
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// Check whether a device ID refers to the null output
+pub fn is_null(device_id: &str) -> bool {
+    device_id == NULL_DEVICE_ID
+}
+
+/// Output stream pulling a device mix in real time
+pub(crate) struct NullOutput {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    clock: Arc<ClockSkewMonitor>,
+}
+
+impl NullOutput {
+    /// Start mixing `inputs` at `sample_rate`
+    pub(crate) fn start(sample_rate: u32, channels: u16, inputs: Arc<Mutex<MixerInputs>>) -> Result<Self, AudioError> {
+        let running = Arc::new(AtomicBool::new(true));
+        let clock = Arc::new(ClockSkewMonitor::new(sample_rate));
+        let block_duration = Duration::from_secs_f64(BLOCK_FRAMES as f64 / sample_rate.max(1) as f64);
+
+        let thread = {
+            let running = running.clone();
+            let clock = clock.clone();
+            thread::Builder::new()
+                .name("null-output".to_string())
+                .spawn(move || {
+                    let mut block = vec![0.0f32; BLOCK_FRAMES * channels.max(1) as usize];
+                    let mut next = Instant::now();
+                    while running.load(Ordering::Relaxed) {
+                        inputs.lock().mix(&mut block);
+                        clock.record_frames(BLOCK_FRAMES);
+
+                        next += block_duration;
+                        let now = Instant::now();
+                        if now > next + MAX_LAG {
+                            next = now;
+                        }
+                        thread::sleep(next.saturating_duration_since(now));
+                    }
+                })
+                .map_err(|e| AudioError::StreamError(e.to_string()))?
+        };
+
+        Ok(Self {
+            running,
+            thread: Some(thread),
+            clock,
+        })
+    }
+
+    /// Clock skew monitor of the stream
+    pub(crate) fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+        &self.clock
+    }
+}
+
+impl Drop for NullOutput {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Audio streams through a helper process
+//!
+//! Sound servers that are only reachable through their command line tools
+//! (`pacat` for the virtual output, `pw-cat` for the PipeWire backend) are
+//! fed raw interleaved `f32` little-endian samples over stdin and read from
+//! over stdout. The blocking pipe paces the stream: a write returns once the
+//! server has room for the block, a read once it has captured one.
+
+use parking_lot::Mutex;
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::audio::clock::ClockSkewMonitor;
+use crate::audio::mixer::MixerInputs;
+use crate::error::AudioError;
+
+/// Frames per block written to or read from a process (5 ms at 48 kHz)
+pub(crate) const BLOCK_FRAMES: usize = 240;
+
+fn spawn(command: &mut Command, stdin: Stdio, stdout: Stdio) -> Result<Child, AudioError> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    command
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AudioError::DeviceNotFound(format!("{} not found on PATH", program)),
+            _ => AudioError::DeviceNotFound(format!("{} not available: {}", program, e)),
+        })
+}
+
+/// Output stream fed from a device mix into a process's stdin
+pub(crate) struct PipeOutput {
+    child: Child,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    clock: Arc<ClockSkewMonitor>,
+}
+
+impl PipeOutput {
+    /// Spawn `command` and start feeding it from `inputs`
+    pub(crate) fn spawn(
+        mut command: Command,
+        thread_name: String,
+        sample_rate: u32,
+        channels: u16,
+        inputs: Arc<Mutex<MixerInputs>>,
+    ) -> Result<Self, AudioError> {
+        let mut child = spawn(&mut command, Stdio::piped(), Stdio::null())?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+
+        let running = Arc::new(AtomicBool::new(true));
+        let clock = Arc::new(ClockSkewMonitor::new(sample_rate));
+        let samples_per_block = BLOCK_FRAMES * channels.max(1) as usize;
+
+        let spawned = {
+            let running = running.clone();
+            let clock = clock.clone();
+            thread::Builder::new().name(thread_name).spawn(move || {
+                let mut block = vec![0.0f32; samples_per_block];
+                let mut bytes = Vec::with_capacity(samples_per_block * 4);
+                while running.load(Ordering::Relaxed) {
+                    inputs.lock().mix(&mut block);
+                    clock.record_frames(BLOCK_FRAMES);
+
+                    bytes.clear();
+                    bytes.extend(block.iter().flat_map(|sample| sample.to_le_bytes()));
+                    if let Err(e) = stdin.write_all(&bytes) {
+                        if running.load(Ordering::Relaxed) {
+                            tracing::error!("Output process stopped: {}", e);
+                        }
+                        break;
+                    }
+                }
+            })
+        };
+        let thread = match spawned {
+            Ok(thread) => thread,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(AudioError::StreamError(e.to_string()));
+            }
+        };
+
+        Ok(Self {
+            child,
+            running,
+            thread: Some(thread),
+            clock,
+        })
+    }
+
+    /// Clock skew monitor of the stream
+    pub(crate) fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+        &self.clock
+    }
+}
+
+impl Drop for PipeOutput {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        // Closing the process fails the pending write and ends the feed thread
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Run `command` and pass every captured block to `on_block` until
+/// `running` is cleared or the process exits (blocks the calling thread)
+#[cfg_attr(not(feature = "pipewire"), allow(dead_code))]
+pub(crate) fn read_blocks(
+    mut command: Command,
+    channels: u16,
+    running: &AtomicBool,
+    mut on_block: impl FnMut(&[f32]),
+) -> Result<(), AudioError> {
+    let mut child = spawn(&mut command, Stdio::null(), Stdio::piped())?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+
+    let samples_per_block = BLOCK_FRAMES * channels.max(1) as usize;
+    let mut bytes = vec![0u8; samples_per_block * 4];
+    let mut block = vec![0.0f32; samples_per_block];
+
+    let result = loop {
+        if !running.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+        if let Err(e) = stdout.read_exact(&mut bytes) {
+            break Err(AudioError::StreamError(format!("capture process stopped: {}", e)));
+        }
+        decode_samples(&bytes, &mut block);
+        on_block(&block);
+    };
+
+    let _ = child.kill();
+    let _ = child.wait();
+    result
+}
+
+/// Decode little-endian `f32` samples
+#[cfg_attr(not(feature = "pipewire"), allow(dead_code))]
+fn decode_samples(bytes: &[u8], out: &mut [f32]) {
+    for (sample, chunk) in out.iter_mut().zip(bytes.chunks_exact(4)) {
+        *sample = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_samples() {
+        let samples = [0.5f32, -1.0, 0.25];
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut out = [0.0f32; 3];
+        decode_samples(&bytes, &mut out);
+        assert_eq!(out, samples);
+    }
+
+    #[test]
+    fn test_read_blocks_until_exit() {
+        let running = AtomicBool::new(true);
+        // Two stereo blocks of silence, then end of stream
+        let mut command = Command::new("head");
+        command.args(["-c", &(BLOCK_FRAMES * 2 * 4 * 2).to_string(), "/dev/zero"]);
+
+        let mut blocks = 0;
+        let result = read_blocks(command, 2, &running, |block| {
+            assert_eq!(block.len(), BLOCK_FRAMES * 2);
+            assert!(block.iter().all(|&s| s == 0.0));
+            blocks += 1;
+        });
+        assert_eq!(blocks, 2);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_missing_program() {
+        let running = AtomicBool::new(true);
+        let command = Command::new("pw-cat-does-not-exist");
+        let Err(e) = read_blocks(command, 2, &running, |_| {}) else {
+            panic!("spawned a missing program");
+        };
+        assert!(e.to_string().contains("pw-cat-does-not-exist not found on PATH"), "{}", e);
+    }
+}
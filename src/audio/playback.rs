@@ -10,11 +10,27 @@ use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
-use crate::audio::buffer::{AudioFrame, JitterBuffer, SharedRingBuffer};
-use crate::audio::device::get_device_by_id;
+use crate::audio::buffer::{AudioFrame, JitterBuffer, Playout, SharedRingBuffer};
+use crate::audio::clock::{ClockSkewMonitor, StreamTiming};
+use crate::audio::mixer::MixerInputs;
+use crate::audio::playout::{PlayoutConfig, PlayoutCursor};
+use crate::audio::resample::Resampler;
+use crate::audio::scheduler::PlayoutScheduler;
+use crate::audio::simd;
+use crate::audio::wasapi;
+use crate::audio::device::{self, get_track_device};
 use crate::constants::DEFAULT_SAMPLE_RATE;
 use crate::error::AudioError;
 
+/// Where the output callback reads audio from
+#[derive(Clone)]
+enum PlaybackSource {
+    /// Frames of a single track
+    Buffer(SharedRingBuffer),
+    /// Sum of the tracks sharing the device
+    Mixer(Arc<parking_lot::Mutex<MixerInputs>>),
+}
+
 /// Audio playback instance for a single device/track
 pub struct AudioPlayback {
     /// Track ID this playback belongs to
@@ -26,8 +42,8 @@ pub struct AudioPlayback {
     /// Whether playback is running
     running: Arc<AtomicBool>,
     
-    /// Input buffer for frames to play
-    input_buffer: SharedRingBuffer,
+    /// Frames to play
+    source: PlaybackSource,
     
     /// Stream thread handle
     thread_handle: Option<JoinHandle<()>>,
@@ -41,14 +57,32 @@ pub struct AudioPlayback {
     /// Buffer underruns
     underruns: Arc<AtomicU32>,
     
-    /// Stream configuration
+    /// Stream configuration (at the device rate)
     config: StreamConfig,
     
+    /// Sample rate of the played frames (resampled to the device rate)
+    stream_rate: u32,
+    
     /// Muted state
     muted: Arc<AtomicBool>,
     
     /// Volume (0.0 - 1.0)
     volume: Arc<parking_lot::RwLock<f32>>,
+    
+    /// Device clock vs host clock monitor
+    clock: Arc<ClockSkewMonitor>,
+    
+    /// Period and latency the device plays at
+    timing: Arc<StreamTiming>,
+    
+    /// Pre-roll and catch-up behaviour
+    playout_config: PlayoutConfig,
+    
+    /// Playback is currently accelerated to catch up
+    catching_up: Arc<AtomicBool>,
+    
+    /// Clock drift correction in ppm (f32 bits, NaN until estimated)
+    drift_ppm: Arc<AtomicU32>,
 }
 
 impl AudioPlayback {
@@ -61,32 +95,75 @@ impl AudioPlayback {
         buffer_size: Option<u32>,
         input_buffer: SharedRingBuffer,
     ) -> Result<Self, AudioError> {
-        let device = get_device_by_id(device_id)?;
+        Self::open(track_id, device_id, sample_rate, channels, buffer_size, PlaybackSource::Buffer(input_buffer))
+    }
+    
+    /// Create a playback that outputs the mix of several tracks
+    pub(crate) fn mixed(
+        track_id: u8,
+        device_id: &str,
+        sample_rate: Option<u32>,
+        channels: Option<u16>,
+        buffer_size: Option<u32>,
+        inputs: Arc<parking_lot::Mutex<MixerInputs>>,
+    ) -> Result<Self, AudioError> {
+        Self::open(track_id, device_id, sample_rate, channels, buffer_size, PlaybackSource::Mixer(inputs))
+    }
+    
+    fn open(
+        track_id: u8,
+        device_id: &str,
+        sample_rate: Option<u32>,
+        channels: Option<u16>,
+        buffer_size: Option<u32>,
+        source: PlaybackSource,
+    ) -> Result<Self, AudioError> {
+        let device = get_track_device(track_id, device_id, false)?;
         
         // Get default config and override with requested settings
         let default_config = device.default_output_config()?;
         
+        // Devices fixed at another rate are resampled to it
+        let stream_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+        let device_rate = device.output_sample_rate(stream_rate);
+        if device_rate != stream_rate {
+            tracing::info!("Playing to {} at {} Hz, resampled from {} Hz", device_id, device_rate, stream_rate);
+        }
+        
+        // A fixed buffer is kept within what the device supports
+        let requested = match buffer_size {
+            Some(size) => cpal::BufferSize::Fixed(size),
+            None => cpal::BufferSize::Default,
+        };
+        let fitted = device::fit_buffer_size(requested, Some(*default_config.buffer_size()));
+        if fitted != requested {
+            tracing::info!("Buffer of {:?} not supported by {}, using {:?}", requested, device_id, fitted);
+        }
+        
         let config = StreamConfig {
             channels: channels.unwrap_or(default_config.channels()),
-            sample_rate: cpal::SampleRate(sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE)),
-            buffer_size: match buffer_size {
-                Some(size) => cpal::BufferSize::Fixed(size),
-                None => cpal::BufferSize::Default,
-            },
+            sample_rate: cpal::SampleRate(device_rate),
+            buffer_size: fitted,
         };
         
         Ok(Self {
             track_id,
             device_id: device_id.to_string(),
             running: Arc::new(AtomicBool::new(false)),
-            input_buffer,
+            source,
             thread_handle: None,
             error_rx: None,
             samples_played: Arc::new(AtomicU64::new(0)),
             underruns: Arc::new(AtomicU32::new(0)),
-            config,
             muted: Arc::new(AtomicBool::new(false)),
             volume: Arc::new(parking_lot::RwLock::new(1.0)),
+            clock: Arc::new(ClockSkewMonitor::new(config.sample_rate.0)),
+            timing: Arc::new(StreamTiming::new(config.sample_rate.0)),
+            config,
+            stream_rate,
+            playout_config: PlayoutConfig::default(),
+            catching_up: Arc::new(AtomicBool::new(false)),
+            drift_ppm: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
         })
     }
     
@@ -96,79 +173,148 @@ impl AudioPlayback {
             return Ok(());
         }
         
-        let device = get_device_by_id(&self.device_id)?;
+        let device = get_track_device(self.track_id, &self.device_id, false)?;
         let (error_tx, error_rx) = bounded::<AudioError>(16);
         self.error_rx = Some(error_rx);
         
+        // WASAPI: exclusive mode first if asked for, then shared mode
+        let exclusive = wasapi::use_exclusive(&device);
+        let stream_id = wasapi::stream_id(&device);
+        let low_latency = wasapi::low_latency_buffer(
+            &self.config,
+            device.default_output_config().ok().map(|default| *default.buffer_size()),
+        );
+        
         let running = self.running.clone();
         let running_for_loop = self.running.clone();
-        let input_buffer = self.input_buffer.clone();
+        let source = self.source.clone();
         let samples_played = self.samples_played.clone();
         let underruns = self.underruns.clone();
         let config = self.config.clone();
-        let _channels = self.config.channels as usize;
+        let channels = (self.config.channels as usize).max(1);
         let muted = self.muted.clone();
         let volume = self.volume.clone();
+        let clock = self.clock.clone();
+        clock.reset();
+        let timing = self.timing.clone();
+        timing.reset(self.config.sample_rate.0);
+        let timing_for_latency = self.timing.clone();
+        let catching_up = self.catching_up.clone();
+        let drift_ppm = self.drift_ppm.clone();
+        let playout_config = self.playout_config;
+        let mut resampler = (self.config.sample_rate.0 != self.stream_rate)
+            .then(|| Resampler::new(self.stream_rate, self.config.sample_rate.0, channels));
         
         running.store(true, Ordering::SeqCst);
         
+        let thread_name = match self.source {
+            PlaybackSource::Buffer(_) => format!("playback-track-{}", self.track_id),
+            PlaybackSource::Mixer(_) => format!("playback-mix-{}", self.device_id),
+        };
         let handle = thread::Builder::new()
-            .name(format!("playback-track-{}", self.track_id))
+            .name(thread_name)
             .spawn(move || {
-                let cpal_device = device.into_inner();
+                // Read position with pre-roll and catch-up
+                let mut cursor = PlayoutCursor::new(channels, playout_config);
                 
-                // Buffered samples for smooth playback
-                let mut sample_buffer: Vec<f32> = Vec::new();
-                let mut sample_pos = 0;
+                // Underrun samples are output as silence
+                let mut read = move |out: &mut [f32]| match &source {
+                    PlaybackSource::Buffer(input_buffer) => {
+                        let missing = cursor.fill(out, input_buffer);
+                        catching_up.store(cursor.is_catching_up(), Ordering::Relaxed);
+                        let drift = cursor.drift_ppm().map_or(f32::NAN, |ppm| ppm as f32);
+                        drift_ppm.store(drift.to_bits(), Ordering::Relaxed);
+                        missing
+                    }
+                    PlaybackSource::Mixer(inputs) => {
+                        let mut inputs = inputs.lock();
+                        let missing = inputs.mix(out);
+                        catching_up.store(inputs.is_catching_up(), Ordering::Relaxed);
+                        missing
+                    }
+                };
+                // Stream-rate audio read ahead for the resampler, and its
+                // device-rate output not played yet
+                let mut block = Vec::new();
+                let mut resampled = Vec::new();
                 
-                let stream = cpal_device.build_output_stream(
-                    &config,
-                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                        if !running.load(Ordering::Relaxed) {
-                            // Fill with silence
-                            for sample in data.iter_mut() {
-                                *sample = 0.0;
-                            }
-                            return;
-                        }
-                        
-                        let is_muted = muted.load(Ordering::Relaxed);
-                        let vol = *volume.read();
-                        
+                let mut render = move |data: &mut [f32]| {
+                    if !running.load(Ordering::Relaxed) {
+                        // Fill with silence
                         for sample in data.iter_mut() {
-                            // Check if we need more samples
-                            if sample_pos >= sample_buffer.len() {
-                                // Try to get next frame
-                                if let Some(frame) = input_buffer.try_pop() {
-                                    sample_buffer = frame.samples;
-                                    sample_pos = 0;
-                                } else {
-                                    // Underrun - output silence
-                                    underruns.fetch_add(1, Ordering::Relaxed);
-                                    *sample = 0.0;
-                                    continue;
-                                }
-                            }
-                            
-                            // Output sample (with mute and volume)
-                            if is_muted {
-                                *sample = 0.0;
-                            } else {
-                                *sample = sample_buffer[sample_pos] * vol;
-                            }
-                            sample_pos += 1;
+                            *sample = 0.0;
                         }
-                        
-                        samples_played.fetch_add(data.len() as u64, Ordering::Relaxed);
-                    },
-                    move |err| {
-                        let _ = error_tx.try_send(AudioError::StreamError(err.to_string()));
-                    },
-                    None,
-                );
+                        return;
+                    }
+                    
+                    let is_muted = muted.load(Ordering::Relaxed);
+                    let vol = *volume.read();
+                    
+                    let missing = match resampler.as_mut() {
+                        None => read(data),
+                        Some(resampler) => {
+                            let pending = resampled.len() / channels;
+                            let needed = resampler.input_frames_for((data.len() / channels).saturating_sub(pending));
+                            block.resize(needed * channels, 0.0);
+                            let missing = read(&mut block);
+                            resampler.process(&block, &mut resampled);
+                            data.copy_from_slice(&resampled[..data.len()]);
+                            resampled.drain(..data.len());
+                            missing
+                        }
+                    };
+                    if missing > 0 {
+                        underruns.fetch_add(missing as u32, Ordering::Relaxed);
+                    }
+                    
+                    // Apply mute and volume
+                    if is_muted {
+                        data.fill(0.0);
+                    } else if vol != 1.0 {
+                        simd::apply_gain(data, vol);
+                    }
+                    
+                    samples_played.fetch_add(data.len() as u64, Ordering::Relaxed);
+                    clock.record_frames(data.len() / channels);
+                    timing.record_period(data.len() / channels);
+                };
                 
-                match stream {
-                    Ok(stream) => {
+                if exclusive {
+                    match wasapi::play_exclusive(&device.name, &config, &running_for_loop, &mut render) {
+                        Ok(()) => return,
+                        Err(e) => tracing::warn!(
+                            "Exclusive mode failed on {}: {}, falling back to shared mode",
+                            device.name, e
+                        ),
+                    }
+                }
+                
+                let cpal_device = device.into_inner();
+                
+                // Shared by the tries of `build_shared`
+                let render = Arc::new(parking_lot::Mutex::new(render));
+                let built = wasapi::build_shared(&stream_id, &config, low_latency, |config| {
+                    let render = render.clone();
+                    let timing = timing_for_latency.clone();
+                    let error_tx = error_tx.clone();
+                    cpal_device.build_output_stream(
+                        config,
+                        move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                            let timestamp = info.timestamp();
+                            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                                timing.record_latency(latency);
+                            }
+                            (render.lock())(data)
+                        },
+                        move |err| {
+                            let _ = error_tx.try_send(AudioError::StreamError(err.to_string()));
+                        },
+                        None,
+                    )
+                });
+                
+                match built {
+                    Ok((stream, _mode)) => {
                         if let Err(e) = stream.play() {
                             tracing::error!("Failed to start playback stream: {}", e);
                             return;
@@ -234,13 +380,57 @@ impl AudioPlayback {
         self.underruns.load(Ordering::Relaxed)
     }
     
+    /// Set pre-roll and catch-up behaviour (applied on next start)
+    pub fn set_playout_config(&mut self, config: PlayoutConfig) {
+        self.playout_config = config;
+    }
+    
+    /// Get pre-roll and catch-up behaviour
+    pub fn playout_config(&self) -> &PlayoutConfig {
+        &self.playout_config
+    }
+    
+    /// Check if playback is accelerated to catch up to the live edge
+    pub fn is_catching_up(&self) -> bool {
+        self.catching_up.load(Ordering::Relaxed)
+    }
+    
+    /// Get device clock skew vs host clock in ppm (None until measured)
+    pub fn clock_skew_ppm(&self) -> Option<f64> {
+        self.clock.skew_ppm()
+    }
+    
+    /// Get the clock drift correction of a track playback in ppm: positive
+    /// when the sender's clock runs fast (None until estimated, or when
+    /// playing a mix, see `MixerChannel::drift_ppm`)
+    pub fn drift_ppm(&self) -> Option<f32> {
+        let value = f32::from_bits(self.drift_ppm.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Get the clock skew monitor (e.g. for drift compensation)
+    pub fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+        &self.clock
+    }
+    
+    /// Get the period and latency the device plays at
+    pub fn timing(&self) -> &Arc<StreamTiming> {
+        &self.timing
+    }
+    
     /// Get the stream configuration
     pub fn config(&self) -> &StreamConfig {
         &self.config
     }
     
-    /// Get sample rate
+    /// Get sample rate of the played frames
     pub fn sample_rate(&self) -> u32 {
+        self.stream_rate
+    }
+    
+    /// Get the rate the device stream runs at (differs from
+    /// [`sample_rate`](Self::sample_rate) when the device is resampled)
+    pub fn device_sample_rate(&self) -> u32 {
         self.config.sample_rate.0
     }
     
@@ -269,6 +459,9 @@ pub struct NetworkPlayback {
     /// Jitter buffer for reordering
     jitter_buffer: parking_lot::Mutex<JitterBuffer>,
     
+    /// Releases the jitter buffer's frames by the stream clock
+    scheduler: parking_lot::Mutex<PlayoutScheduler>,
+    
     /// Decoded frame buffer
     decoded_buffer: SharedRingBuffer,
 }
@@ -302,6 +495,7 @@ impl NetworkPlayback {
         Ok(Self {
             playback,
             jitter_buffer,
+            scheduler: parking_lot::Mutex::new(PlayoutScheduler::new()),
             decoded_buffer,
         })
     }
@@ -318,15 +512,19 @@ impl NetworkPlayback {
         self.decoded_buffer.push(frame)
     }
     
-    /// Process jitter buffer and push to playback
+    /// Push the next frame of the jitter buffer that is due by the stream
+    /// clock to playback (lost slots are skipped)
     pub fn process(&self) -> Option<AudioFrame> {
         let mut jitter = self.jitter_buffer.lock();
-        if let Some(frame) = jitter.get_next() {
-            let _ = self.decoded_buffer.push(frame.clone());
-            Some(frame)
-        } else {
-            None
+        let mut scheduler = self.scheduler.lock();
+        let now = std::time::Instant::now();
+        while let Some(playout) = scheduler.next_playout(&mut jitter, now) {
+            if let Playout::Frame(frame) = playout {
+                let _ = self.decoded_buffer.push(frame.clone());
+                return Some(frame);
+            }
         }
+        None
     }
     
     /// Start playback
@@ -344,6 +542,11 @@ impl NetworkPlayback {
         self.jitter_buffer.lock().stats()
     }
     
+    /// Get the clock drift correction of the sender vs this output in ppm
+    pub fn drift_ppm(&self) -> Option<f32> {
+        self.playback.drift_ppm()
+    }
+    
     /// Get inner playback
     pub fn playback(&self) -> &AudioPlayback {
         &self.playback
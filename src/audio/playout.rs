@@ -0,0 +1,483 @@
+//! Variable-rate playout from a frame ring buffer
+//!
+//! The output callback reads frames through a `PlayoutCursor`, which can
+//! consume input slightly faster than real time. This is used to catch up
+//! to the live edge when a track joins a stream that is already running,
+//! or when the jitter buffer shrinks and hands over a backlog: instead of
+//! keeping it as permanent extra delay, playback runs a few percent fast
+//! until the buffer is back at its pre-roll level. With `time_stretch`
+//! the cursor also plays slower when the buffer is about to run dry (the
+//! jitter buffer grew and held a frame back) instead of dropping out.
+//!
+//! Time stretching changes the tempo only: frames are shortened or
+//! lengthened by one period of their waveform (`audio::stretch`). Frames
+//! too short to splice, and outputs without time stretching, catch up by
+//! reading faster with linear interpolation, which raises the pitch.
+//!
+//! Sender and receiver sound cards are not sample-locked: a sender whose
+//! clock runs 100 ppm fast delivers an extra frame every 100 s, which
+//! would pile up until catch-up engages, or drain the buffer until it
+//! runs dry. Drift compensation watches the long-term fill of the buffer
+//! and reads it a few hundred ppm faster or slower (a PI controller on the
+//! average fill around the pre-roll level), keeping it centred without
+//! periodic splices. The correction is far too small to hear as pitch.
+//!
+//! Received tracks are released by a `PlayoutScheduler`, which owns the
+//! drift correction: it sets the cursor's rate with `set_drift_ppm` and the
+//! cursor's own estimate stays off. Catch-up and time stretching remain for
+//! the transients only (a backlog handed over at once, a buffer about to
+//! run dry).
+
+use crate::audio::buffer::RingBuffer;
+use crate::audio::pool;
+use crate::audio::stretch;
+
+/// Averaging time of the buffer fill (samples per channel, 5 s at 48 kHz)
+const DRIFT_AVERAGE_SAMPLES: f64 = 240_000.0;
+
+/// Rate correction per frame of fill above or below the pre-roll level
+const DRIFT_GAIN: f64 = 200e-6;
+
+/// Integration time of the drift estimate (100 s at 48 kHz)
+const DRIFT_INTEGRAL_SAMPLES: f64 = 4_800_000.0;
+
+/// Largest rate correction (1000 ppm)
+const MAX_DRIFT: f64 = 1e-3;
+
+/// Playout behaviour of an output stream
+#[derive(Debug, Clone, Copy)]
+pub struct PlayoutConfig {
+    /// Frames to buffer before playback starts (and after an underrun)
+    pub prebuffer_frames: usize,
+    /// Catch up to the live edge when the buffer grows above pre-roll
+    pub catch_up: bool,
+    /// Extra frames above pre-roll before catch-up engages
+    pub catch_up_threshold: usize,
+    /// Speed-up while catching up (0.03 = 3% faster)
+    pub catch_up_speed: f64,
+    /// Adapt the tempo by splicing waveform periods instead of resampling,
+    /// and slow down before the buffer runs dry
+    pub time_stretch: bool,
+    /// Correct clock drift between sender and output by reading slightly
+    /// faster or slower (unless the rate is set with `set_drift_ppm`)
+    pub drift_compensation: bool,
+}
+
+impl Default for PlayoutConfig {
+    fn default() -> Self {
+        Self {
+            prebuffer_frames: 2,
+            catch_up: true,
+            catch_up_threshold: 2,
+            catch_up_speed: 0.03,
+            time_stretch: true,
+            drift_compensation: true,
+        }
+    }
+}
+
+/// Read position in the stream of frames held by a ring buffer
+pub struct PlayoutCursor {
+    channels: usize,
+    config: PlayoutConfig,
+    /// Samples of the frame currently being read
+    frame: Vec<f32>,
+    /// Read position inside `frame` (in samples)
+    frame_pos: usize,
+    /// Previous and current input sample frames (one sample per channel)
+    prev: Vec<f32>,
+    cur: Vec<f32>,
+    /// Fractional position between `prev` and `cur`
+    phase: f64,
+    /// Waiting for pre-roll
+    prebuffering: bool,
+    /// Catch-up currently engaged
+    catching_up: bool,
+    /// Samples per channel the catch-up may still cut from frames
+    stretch_budget: f64,
+    /// Average buffer fill in frames (None until playing)
+    fill_average: Option<f64>,
+    /// Integral part of the drift correction
+    drift_integral: f64,
+    /// Rate correction for clock drift (1e-6 = 1 ppm faster)
+    drift: f64,
+    /// The drift correction is set from outside, not estimated
+    drift_set: bool,
+    /// Send time of a latency probe frame that started playing
+    probe_us: Option<u64>,
+}
+
+impl PlayoutCursor {
+    pub fn new(channels: usize, config: PlayoutConfig) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            config,
+            frame: Vec::new(),
+            frame_pos: 0,
+            prev: vec![0.0; channels],
+            cur: vec![0.0; channels],
+            phase: 1.0,
+            prebuffering: true,
+            catching_up: false,
+            stretch_budget: 0.0,
+            fill_average: None,
+            drift_integral: 0.0,
+            drift: 0.0,
+            drift_set: false,
+            probe_us: None,
+        }
+    }
+
+    /// Whether playback is currently accelerated
+    pub fn is_catching_up(&self) -> bool {
+        self.catching_up
+    }
+
+    /// Whether the cursor is waiting for pre-roll
+    pub fn is_prebuffering(&self) -> bool {
+        self.prebuffering
+    }
+
+    /// Rate correction for clock drift in ppm: positive when the sender's
+    /// clock runs fast against the output (None until estimated)
+    pub fn drift_ppm(&self) -> Option<f64> {
+        (self.drift_set || self.fill_average.is_some()).then_some(self.drift * 1e6)
+    }
+
+    /// Read at the drift correction of the scheduler releasing the frames
+    /// instead of estimating it from the buffer fill (None: estimate it)
+    pub fn set_drift_ppm(&mut self, ppm: Option<f64>) {
+        self.drift_set = ppm.is_some();
+        if let Some(ppm) = ppm {
+            self.drift = (ppm * 1e-6).clamp(-MAX_DRIFT, MAX_DRIFT);
+            self.fill_average = None;
+            self.drift_integral = 0.0;
+        }
+    }
+
+    /// Send time of the latency probe frame that started playing since the
+    /// last call
+    pub fn take_probe(&mut self) -> Option<u64> {
+        self.probe_us.take()
+    }
+
+    /// Advance the input by one sample frame
+    fn advance(&mut self, buffer: &RingBuffer) -> bool {
+        while self.frame_pos + self.channels > self.frame.len() {
+            match buffer.try_pop() {
+                Some(frame) => {
+                    if frame.probe_us.is_some() {
+                        self.probe_us = frame.probe_us;
+                    }
+                    // The played frame's buffer goes back to the pool
+                    // instead of being freed in the audio callback
+                    let samples = self.stretch(frame.samples, buffer.len());
+                    pool::samples().recycle(std::mem::replace(&mut self.frame, samples));
+                    self.frame_pos = 0;
+                }
+                None => return false,
+            }
+        }
+
+        std::mem::swap(&mut self.prev, &mut self.cur);
+        self.cur
+            .copy_from_slice(&self.frame[self.frame_pos..self.frame_pos + self.channels]);
+        self.frame_pos += self.channels;
+        true
+    }
+
+    /// Shorten a frame taken from the buffer while catching up, or
+    /// lengthen it when no other frame is queued behind it
+    fn stretch(&mut self, samples: Vec<f32>, queued: usize) -> Vec<f32> {
+        if !self.config.time_stretch {
+            return samples;
+        }
+
+        if self.catching_up {
+            // Cut at most `catch_up_speed` of the audio played
+            self.stretch_budget += (samples.len() / self.channels) as f64 * self.config.catch_up_speed;
+            if self.stretch_budget >= stretch::MIN_PERIOD as f64 {
+                if let Some(period) = stretch::find_period(&samples, self.channels) {
+                    if self.stretch_budget >= period as f64 {
+                        self.stretch_budget -= period as f64;
+                        return stretch::shorten(&samples, self.channels, period);
+                    }
+                }
+            }
+        } else if queued == 0 {
+            if let Some(period) = stretch::find_period(&samples, self.channels) {
+                return stretch::lengthen(&samples, self.channels, period);
+            }
+        }
+        samples
+    }
+
+    /// Playback rate for the current buffer level
+    fn update_rate(&mut self, level: usize) -> f64 {
+        if !self.config.catch_up {
+            self.catching_up = false;
+            return 1.0;
+        }
+
+        let target = self.config.prebuffer_frames;
+        if level > target + self.config.catch_up_threshold {
+            self.catching_up = true;
+        } else if level <= target {
+            self.catching_up = false;
+            self.stretch_budget = 0.0;
+        }
+
+        // Spliceable frames are shortened instead of read faster
+        let spliced = self.config.time_stretch && stretch::can_splice(self.frame.len(), self.channels);
+        let rate = if self.catching_up && !spliced {
+            1.0 + self.config.catch_up_speed
+        } else {
+            1.0
+        };
+        rate * (1.0 + self.drift)
+    }
+
+    /// Follow the buffer fill after `frames` sample frames were played
+    fn track_drift(&mut self, buffer: &RingBuffer, frames: usize) {
+        let remaining = match self.frame.len() {
+            0 => 0.0,
+            len => (len - self.frame_pos.min(len)) as f64 / len as f64,
+        };
+        let fill = buffer.len() as f64 + remaining;
+        let average = match self.fill_average {
+            Some(average) => average + (fill - average) * (frames as f64 / DRIFT_AVERAGE_SAMPLES).min(1.0),
+            None => fill,
+        };
+        self.fill_average = Some(average);
+
+        let error = average - self.config.prebuffer_frames as f64;
+        self.drift_integral = (self.drift_integral + error * DRIFT_GAIN * frames as f64 / DRIFT_INTEGRAL_SAMPLES)
+            .clamp(-MAX_DRIFT, MAX_DRIFT);
+        self.drift = (self.drift_integral + error * DRIFT_GAIN).clamp(-MAX_DRIFT, MAX_DRIFT);
+    }
+
+    /// Fill an interleaved output buffer.
+    /// Returns the number of samples that could not be filled (underrun).
+    pub fn fill(&mut self, out: &mut [f32], buffer: &RingBuffer) -> usize {
+        if self.prebuffering {
+            if buffer.len() < self.config.prebuffer_frames.max(1) {
+                out.fill(0.0);
+                return 0;
+            }
+            self.prebuffering = false;
+        }
+
+        let rate = self.update_rate(buffer.len());
+        let mut missing = 0;
+
+        for out_frame in out.chunks_mut(self.channels) {
+            let mut ok = true;
+            while self.phase >= 1.0 {
+                if !self.advance(buffer) {
+                    ok = false;
+                    break;
+                }
+                self.phase -= 1.0;
+            }
+
+            if !ok {
+                out_frame.fill(0.0);
+                missing += out_frame.len();
+                continue;
+            }
+
+            let t = self.phase as f32;
+            for (ch, sample) in out_frame.iter_mut().enumerate() {
+                *sample = self.prev[ch] + (self.cur[ch] - self.prev[ch]) * t;
+            }
+            self.phase += rate;
+        }
+
+        // Buffer ran dry: collect a new pre-roll before resuming
+        if missing > 0 {
+            self.prebuffering = true;
+            self.catching_up = false;
+            self.stretch_budget = 0.0;
+        } else if self.config.drift_compensation && !self.drift_set {
+            self.track_drift(buffer, out.len() / self.channels);
+        }
+
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::buffer::AudioFrame;
+
+    fn push_ramp(buffer: &RingBuffer, frames: usize, frame_len: usize) {
+        for f in 0..frames {
+            let samples = (0..frame_len).map(|i| (f * frame_len + i) as f32).collect();
+            buffer.push(AudioFrame::new(samples, 1, 0, f as u32));
+        }
+    }
+
+    #[test]
+    fn test_prebuffer_then_unity_rate() {
+        let buffer = RingBuffer::new(16);
+        let mut cursor = PlayoutCursor::new(1, PlayoutConfig::default());
+        let mut out = vec![1.0; 8];
+
+        // Nothing buffered yet: silence, not an underrun
+        push_ramp(&buffer, 1, 8);
+        assert_eq!(cursor.fill(&mut out, &buffer), 0);
+        assert!(out.iter().all(|&s| s == 0.0));
+        assert!(cursor.is_prebuffering());
+
+        push_ramp(&buffer, 1, 8);
+        assert_eq!(cursor.fill(&mut out, &buffer), 0);
+        assert!(!cursor.is_prebuffering());
+
+        // Unity rate reproduces the input delayed by one sample
+        assert_eq!(out, vec![0.0, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn test_catch_up_consumes_backlog() {
+        let buffer = RingBuffer::new(64);
+        let config = PlayoutConfig {
+            catch_up_speed: 0.1,
+            ..Default::default()
+        };
+        let mut cursor = PlayoutCursor::new(1, config);
+
+        // Late joiner: 20 frames of backlog
+        push_ramp(&buffer, 20, 100);
+        let mut out = vec![0.0; 100];
+        cursor.fill(&mut out, &buffer);
+        assert!(cursor.is_catching_up());
+
+        // Consume while input keeps arriving in real time
+        for _ in 0..300 {
+            push_ramp(&buffer, 1, 100);
+            cursor.fill(&mut out, &buffer);
+        }
+
+        assert!(!cursor.is_catching_up());
+        assert!(buffer.len() <= config.prebuffer_frames + config.catch_up_threshold);
+    }
+
+    /// Frames of a 200 Hz tone at 48 kHz
+    fn push_tone(buffer: &RingBuffer, frames: usize, frame_len: usize) {
+        for f in 0..frames {
+            let samples = (0..frame_len)
+                .map(|i| (2.0 * std::f32::consts::PI * 200.0 * (f * frame_len + i) as f32 / 48_000.0).sin())
+                .collect();
+            buffer.push(AudioFrame::new(samples, 1, 0, f as u32));
+        }
+    }
+
+    #[test]
+    fn test_stretch_before_running_dry() {
+        // A frame held back by the jitter buffer: the last queued frame is
+        // played slower so the next one arrives in time
+        for time_stretch in [true, false] {
+            let buffer = RingBuffer::new(16);
+            let config = PlayoutConfig {
+                time_stretch,
+                ..Default::default()
+            };
+            let mut cursor = PlayoutCursor::new(1, config);
+            push_tone(&buffer, 2, 480);
+            let mut out = vec![0.0; 1000];
+            let missing = cursor.fill(&mut out, &buffer);
+            assert_eq!(missing == 0, time_stretch);
+        }
+    }
+
+    #[test]
+    fn test_time_stretched_catch_up() {
+        let buffer = RingBuffer::new(64);
+        let config = PlayoutConfig {
+            catch_up_speed: 0.1,
+            ..Default::default()
+        };
+        let mut cursor = PlayoutCursor::new(1, config);
+
+        // Backlog handed over by a shrinking jitter buffer
+        push_tone(&buffer, 20, 480);
+        let mut out = vec![0.0; 480];
+        cursor.fill(&mut out, &buffer);
+        assert!(cursor.is_catching_up());
+
+        for _ in 0..300 {
+            push_tone(&buffer, 1, 480);
+            assert_eq!(cursor.fill(&mut out, &buffer), 0);
+        }
+        assert!(!cursor.is_catching_up());
+        assert!(buffer.len() <= config.prebuffer_frames + config.catch_up_threshold);
+    }
+
+    #[test]
+    fn test_drift_compensation_keeps_buffer_centred() {
+        // The sender's clock runs 500 ppm fast or slow against the output
+        for drift_ppm in [500.0, -500.0] {
+            let buffer = RingBuffer::new(64);
+            let mut cursor = PlayoutCursor::new(1, PlayoutConfig::default());
+            let mut out = vec![0.0; 480];
+            let mut sent = 0.0;
+            let mut frames = 0;
+            let mut late_catch_up = false;
+
+            // Ten minutes of 10 ms frames
+            for i in 0..60_000 {
+                sent += 1.0 + drift_ppm * 1e-6;
+                while sent >= frames as f64 + 1.0 {
+                    push_tone(&buffer, 1, 480);
+                    frames += 1;
+                }
+                assert_eq!(cursor.fill(&mut out, &buffer), 0);
+                late_catch_up |= i > 30_000 && cursor.is_catching_up();
+            }
+
+            let estimate = cursor.drift_ppm().unwrap();
+            assert!((estimate - drift_ppm).abs() < 100.0, "{} ppm estimated as {}", drift_ppm, estimate);
+            assert!(!late_catch_up);
+            assert!(buffer.len() <= PlayoutConfig::default().prebuffer_frames + 1);
+        }
+    }
+
+    #[test]
+    fn test_drift_set_by_scheduler() {
+        let buffer = RingBuffer::new(64);
+        let mut cursor = PlayoutCursor::new(1, PlayoutConfig::default());
+        let mut out = vec![0.0; 480];
+        cursor.set_drift_ppm(Some(300.0));
+
+        // A buffer held above pre-roll doesn't move the scheduler's rate
+        push_tone(&buffer, 3, 480);
+        for _ in 0..1_000 {
+            push_tone(&buffer, 1, 480);
+            cursor.fill(&mut out, &buffer);
+        }
+        assert!((cursor.drift_ppm().unwrap() - 300.0).abs() < 1e-6);
+
+        // A wild rate is limited, and without one the cursor estimates again
+        cursor.set_drift_ppm(Some(5_000.0));
+        assert!((cursor.drift_ppm().unwrap() - 1_000.0).abs() < 1e-6);
+        cursor.set_drift_ppm(None);
+        assert_eq!(cursor.drift_ppm(), None);
+        push_tone(&buffer, 1, 480);
+        cursor.fill(&mut out, &buffer);
+        assert!(cursor.drift_ppm().is_some());
+    }
+
+    #[test]
+    fn test_underrun_rearms_prebuffer() {
+        let buffer = RingBuffer::new(16);
+        let mut cursor = PlayoutCursor::new(2, PlayoutConfig::default());
+        let mut out = vec![0.0; 40];
+
+        push_ramp(&buffer, 2, 10);
+        let missing = cursor.fill(&mut out, &buffer);
+        assert!(missing > 0);
+        assert!(cursor.is_prebuffering());
+    }
+}
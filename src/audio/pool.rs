@@ -0,0 +1,156 @@
+//! Reusable sample buffers for audio frames
+//!
+//! Every captured, decoded and played frame carries its samples in a
+//! `Vec<f32>`; at 100 frames per second per track, allocating a fresh one
+//! for each and freeing it on another thread (often the audio callback)
+//! keeps the allocator busy for nothing. Frames take their buffers from a
+//! shared lock-free pool instead and give them back with
+//! [`AudioFrame::recycle`] once their samples have been consumed, so in
+//! steady state no frame allocates. A pool that runs empty falls back to
+//! allocating; one that is full frees what it can't hold.
+
+use crossbeam::queue::ArrayQueue;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::audio::buffer::AudioFrame;
+
+/// Buffers the shared pool keeps (a few hundred ms of frames for a dozen tracks)
+const POOL_CAPACITY: usize = 256;
+
+/// Largest buffer kept for reuse: 120 ms of 7.1 at 48 kHz
+const MAX_POOLED_SAMPLES: usize = 5760 * 8;
+
+/// Lock-free pool of sample buffers
+pub struct SamplePool {
+    buffers: ArrayQueue<Vec<f32>>,
+    reused: AtomicU64,
+    allocated: AtomicU64,
+}
+
+/// Reuse counters of a pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers handed out from the pool
+    pub reused: u64,
+    /// Buffers allocated because the pool was empty
+    pub allocated: u64,
+}
+
+impl SamplePool {
+    /// Create an empty pool holding up to `capacity` buffers
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffers: ArrayQueue::new(capacity.max(1)),
+            reused: AtomicU64::new(0),
+            allocated: AtomicU64::new(0),
+        }
+    }
+
+    /// Empty buffer with room for at least `len` samples
+    pub fn take(&self, len: usize) -> Vec<f32> {
+        match self.buffers.pop() {
+            Some(mut buffer) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                buffer.reserve(len);
+                buffer
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(len)
+            }
+        }
+    }
+
+    /// Buffer holding a copy of `samples`
+    pub fn take_copy(&self, samples: &[f32]) -> Vec<f32> {
+        let mut buffer = self.take(samples.len());
+        buffer.extend_from_slice(samples);
+        buffer
+    }
+
+    /// Give a buffer back for reuse; dropped if the pool is full or the
+    /// buffer has grown too large to be worth keeping
+    pub fn recycle(&self, mut buffer: Vec<f32>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_SAMPLES {
+            return;
+        }
+        buffer.clear();
+        let _ = self.buffers.push(buffer);
+    }
+
+    /// Buffers waiting for reuse
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Whether no buffer is waiting for reuse
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    /// Reuse counters
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            reused: self.reused.load(Ordering::Relaxed),
+            allocated: self.allocated.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Pool shared by capture, codecs and playout
+pub fn samples() -> &'static SamplePool {
+    static POOL: OnceLock<SamplePool> = OnceLock::new();
+    POOL.get_or_init(|| SamplePool::new(POOL_CAPACITY))
+}
+
+impl AudioFrame {
+    /// Copy of the frame with its samples in a pooled buffer
+    pub fn pooled_copy(&self) -> AudioFrame {
+        AudioFrame {
+            samples: samples().take_copy(&self.samples),
+            ..*self
+        }
+    }
+
+    /// Give the frame's sample buffer back to the shared pool
+    pub fn recycle(self) {
+        samples().recycle(self.samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_reused() {
+        let pool = SamplePool::new(4);
+        let first = pool.take_copy(&[0.5; 960]);
+        assert_eq!(first.len(), 960);
+        let ptr = first.as_ptr();
+        pool.recycle(first);
+        assert_eq!(pool.len(), 1);
+
+        // The recycled buffer comes back empty, with its allocation
+        let second = pool.take(960);
+        assert!(second.is_empty());
+        assert_eq!(second.as_ptr(), ptr);
+        assert_eq!(pool.stats(), PoolStats { reused: 1, allocated: 1 });
+    }
+
+    #[test]
+    fn test_pool_bounded() {
+        let pool = SamplePool::new(2);
+        for _ in 0..3 {
+            pool.recycle(vec![0.0; 960]);
+        }
+        assert_eq!(pool.len(), 2);
+
+        // Empty and oversized buffers aren't kept
+        pool.take(0);
+        pool.recycle(Vec::new());
+        pool.recycle(vec![0.0; MAX_POOLED_SAMPLES + 1]);
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -0,0 +1,432 @@
+//! End-to-end latency probe
+//!
+//! In measurement mode the sender periodically replaces the start of a
+//! frame with a short chirp and tags the packet carrying it
+//! (`PacketFlags::PROBE`). The packet timestamp, converted to the local
+//! media clock through clock sync, is the time the chirp was sent.
+//!
+//! The receiver measures the probe in two places:
+//!
+//! - the tagged frame is followed through the jitter buffer and the playout
+//!   buffer; when the output callback starts playing it the [`ProbeMeter`]
+//!   of the track records the wire + buffer latency
+//! - with an analog loopback (the output played back into an input, by
+//!   cable or speaker and microphone) a [`LoopbackProbe`] listens for the
+//!   chirp itself, which adds the device and converter latency on top
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::audio::buffer::{create_shared_buffer, SharedRingBuffer};
+use crate::audio::capture::AudioCapture;
+use crate::error::AudioError;
+use crate::network::timesync::media_time_us;
+
+/// Interval between probes of one track
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Chirp length (5 ms)
+const CHIRP_US: u64 = 5_000;
+
+/// Chirp sweep range (Hz)
+const CHIRP_START_HZ: f32 = 1_000.0;
+const CHIRP_END_HZ: f32 = 8_000.0;
+
+/// Peak amplitude of the chirp
+const CHIRP_LEVEL: f32 = 0.5;
+
+/// Normalized correlation above which the chirp counts as detected
+const DETECT_THRESHOLD: f32 = 0.6;
+
+/// Time after a detection in which no further chirp is reported
+const DETECT_HOLDOFF_US: u64 = 500_000;
+
+/// A loopback detection is matched to a probe played at most this long before
+const LOOPBACK_WINDOW_US: u64 = 1_000_000;
+
+/// Marker value for "not measured" in the atomics below
+const NONE: u64 = u64::MAX;
+
+/// Hann-windowed linear sweep used as the probe marker
+pub fn chirp(sample_rate: u32) -> Vec<f32> {
+    let len = (sample_rate as u64 * CHIRP_US / 1_000_000).max(2) as usize;
+    let duration = len as f32 / sample_rate as f32;
+    let sweep_rate = (CHIRP_END_HZ - CHIRP_START_HZ) / duration;
+
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            let phase = 2.0 * std::f32::consts::PI * (CHIRP_START_HZ * t + 0.5 * sweep_rate * t * t);
+            let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (len - 1) as f32).cos();
+            CHIRP_LEVEL * window * phase.sin()
+        })
+        .collect()
+}
+
+/// Sender side: writes the chirp into a track every [`PROBE_INTERVAL`]
+pub struct ProbeInjector {
+    chirp: Vec<f32>,
+    interval: Duration,
+    next: Instant,
+    /// Position in the chirp that continues in the next frame
+    position: Option<usize>,
+}
+
+impl ProbeInjector {
+    pub fn new(sample_rate: u32) -> Self {
+        Self::with_interval(sample_rate, PROBE_INTERVAL)
+    }
+
+    pub fn with_interval(sample_rate: u32, interval: Duration) -> Self {
+        Self {
+            chirp: chirp(sample_rate),
+            interval,
+            next: Instant::now(),
+            position: None,
+        }
+    }
+
+    /// Write the chirp over an interleaved frame when a probe is due (the
+    /// rest of a chirp longer than one frame goes into the following
+    /// frames). Returns true if the frame starts a probe and its packet
+    /// must be tagged.
+    pub fn inject(&mut self, samples: &mut [f32], channels: usize) -> bool {
+        let channels = channels.max(1);
+        let (start, probe) = match self.position {
+            Some(position) => (position, false),
+            None if Instant::now() >= self.next => {
+                self.next = Instant::now() + self.interval;
+                (0, true)
+            }
+            None => return false,
+        };
+
+        let mut position = start;
+        for frame in samples.chunks_mut(channels) {
+            let Some(&value) = self.chirp.get(position) else {
+                break;
+            };
+            frame.fill(value);
+            position += 1;
+        }
+        self.position = (position < self.chirp.len()).then_some(position);
+        probe
+    }
+}
+
+/// Receiver side: probe latencies of one track, shared with the output
+/// callback that plays the track
+#[derive(Debug)]
+pub struct ProbeMeter {
+    /// Local media time the last played probe was sent at
+    sent_us: AtomicU64,
+    /// Local media time it started playing
+    played_us: AtomicU64,
+    /// Sender to output stream (wire + buffers)
+    output_latency_us: AtomicU64,
+    /// Sender to the loopback input
+    loopback_latency_us: AtomicU64,
+}
+
+impl ProbeMeter {
+    pub fn new() -> Self {
+        Self {
+            sent_us: AtomicU64::new(NONE),
+            played_us: AtomicU64::new(NONE),
+            output_latency_us: AtomicU64::new(NONE),
+            loopback_latency_us: AtomicU64::new(NONE),
+        }
+    }
+
+    /// A probe frame sent at `sent_us` (local media clock) starts playing
+    pub fn record_played(&self, sent_us: u64) {
+        self.record_played_at(sent_us, media_time_us());
+    }
+
+    fn record_played_at(&self, sent_us: u64, now: u64) {
+        self.sent_us.store(sent_us, Ordering::Relaxed);
+        self.played_us.store(now, Ordering::Relaxed);
+        self.output_latency_us.store(now.saturating_sub(sent_us), Ordering::Relaxed);
+    }
+
+    /// Local media time the last probe started playing
+    pub fn last_played_us(&self) -> Option<u64> {
+        load(&self.played_us)
+    }
+
+    /// The chirp was heard on the loopback input at `detected_us`; returns
+    /// the latency if it belongs to this track's last probe
+    pub fn record_loopback(&self, detected_us: u64) -> Option<u64> {
+        let played = self.last_played_us()?;
+        let sent = load(&self.sent_us)?;
+        if played > detected_us || detected_us - played > LOOPBACK_WINDOW_US {
+            return None;
+        }
+        let latency = detected_us.saturating_sub(sent);
+        self.loopback_latency_us.store(latency, Ordering::Relaxed);
+        Some(latency)
+    }
+
+    /// Latency of the last probe from the sender to the output stream (µs)
+    pub fn output_latency_us(&self) -> Option<u64> {
+        load(&self.output_latency_us)
+    }
+
+    /// Latency of the last probe from the sender to the loopback input (µs)
+    pub fn loopback_latency_us(&self) -> Option<u64> {
+        load(&self.loopback_latency_us)
+    }
+
+    /// Forget measurements (stream restarted)
+    pub fn reset(&self) {
+        for value in [&self.sent_us, &self.played_us, &self.output_latency_us, &self.loopback_latency_us] {
+            value.store(NONE, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for ProbeMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn load(value: &AtomicU64) -> Option<u64> {
+    match value.load(Ordering::Relaxed) {
+        NONE => None,
+        value => Some(value),
+    }
+}
+
+/// Matched filter finding the chirp in a mono signal
+pub struct ProbeDetector {
+    template: Vec<f32>,
+    template_norm: f32,
+    /// Last `template.len()` samples (circular)
+    history: Vec<f32>,
+    write_pos: usize,
+    /// Sum of squares of `history`
+    energy: f32,
+    /// Samples processed so far
+    processed: u64,
+    /// Best match of the detection in progress: (correlation, start sample)
+    candidate: Option<(f32, u64)>,
+    /// No detections before this sample
+    holdoff_until: u64,
+    holdoff: u64,
+}
+
+impl ProbeDetector {
+    pub fn new(sample_rate: u32) -> Self {
+        let template = chirp(sample_rate);
+        let template_norm = template.iter().map(|s| s * s).sum::<f32>().sqrt();
+        Self {
+            history: vec![0.0; template.len()],
+            template,
+            template_norm,
+            write_pos: 0,
+            energy: 0.0,
+            processed: 0,
+            candidate: None,
+            holdoff_until: 0,
+            holdoff: sample_rate as u64 * DETECT_HOLDOFF_US / 1_000_000,
+        }
+    }
+
+    /// Feed mono samples. On a detection returns how many samples before
+    /// the end of `samples` the chirp started.
+    pub fn process(&mut self, samples: &[f32]) -> Option<u64> {
+        let len = self.template.len();
+        let mut detected = None;
+
+        for &sample in samples {
+            let old = std::mem::replace(&mut self.history[self.write_pos], sample);
+            self.energy = (self.energy + sample * sample - old * old).max(0.0);
+            self.write_pos = (self.write_pos + 1) % len;
+            self.processed += 1;
+            if self.write_pos == 0 {
+                // Drop the rounding error of the running sum once per window
+                self.energy = self.history.iter().map(|s| s * s).sum();
+            }
+
+            if self.processed < len as u64 || self.processed < self.holdoff_until {
+                continue;
+            }
+
+            let correlation = self.correlation();
+            let start = self.processed - len as u64;
+            match self.candidate {
+                Some((best, _)) if correlation > best => self.candidate = Some((correlation, start)),
+                // Past the peak: report the best alignment
+                Some((_, best_start)) if correlation < DETECT_THRESHOLD || start > best_start + len as u64 => {
+                    self.candidate = None;
+                    self.holdoff_until = self.processed + self.holdoff;
+                    detected = Some(best_start);
+                }
+                Some(_) => {}
+                None if correlation >= DETECT_THRESHOLD => self.candidate = Some((correlation, start)),
+                None => {}
+            }
+        }
+
+        detected.map(|start| self.processed - start)
+    }
+
+    /// Normalized correlation of the history window with the template
+    fn correlation(&self) -> f32 {
+        let norm = self.energy.sqrt() * self.template_norm;
+        if norm < 1e-6 {
+            return 0.0;
+        }
+        // The oldest sample sits at the write position
+        let (newer, older) = self.history.split_at(self.write_pos);
+        let dot: f32 = older
+            .iter()
+            .chain(newer)
+            .zip(&self.template)
+            .map(|(x, t)| x * t)
+            .sum();
+        dot / norm
+    }
+}
+
+/// Listens for the chirp on an input device wired to the output
+pub struct LoopbackProbe {
+    capture: AudioCapture,
+    buffer: SharedRingBuffer,
+    detector: ProbeDetector,
+    sample_rate: u32,
+}
+
+impl LoopbackProbe {
+    /// Start capturing from `device_id` (mixed down to mono)
+    pub fn start(device_id: &str, sample_rate: u32) -> Result<Self, AudioError> {
+        let buffer = create_shared_buffer(64);
+        let mut capture = AudioCapture::new(u8::MAX, device_id, Some(sample_rate), None, None, buffer.clone())?;
+        capture.set_output_channels(1);
+        capture.start()?;
+
+        Ok(Self {
+            capture,
+            buffer,
+            detector: ProbeDetector::new(sample_rate),
+            sample_rate,
+        })
+    }
+
+    /// Local media time of the last chirp heard since the previous call
+    pub fn poll(&mut self) -> Option<u64> {
+        let mut detected = None;
+        while let Some(frame) = self.buffer.try_pop() {
+            if let Some(age) = self.detector.process(&frame.samples) {
+                let age_us = age * 1_000_000 / self.sample_rate as u64;
+                detected = Some(media_time_us().saturating_sub(age_us));
+            }
+        }
+        detected
+    }
+
+    /// Match a detection to the probe the output played last; returns the
+    /// index of the meter and the latency
+    pub fn attribute<'a>(
+        detected_us: u64,
+        meters: impl IntoIterator<Item = &'a ProbeMeter>,
+    ) -> Option<(usize, u64)> {
+        let (index, meter) = meters
+            .into_iter()
+            .enumerate()
+            .filter(|(_, meter)| meter.last_played_us().is_some_and(|played| played <= detected_us))
+            .max_by_key(|(_, meter)| meter.last_played_us())?;
+        meter.record_loopback(detected_us).map(|latency| (index, latency))
+    }
+}
+
+impl Drop for LoopbackProbe {
+    fn drop(&mut self) {
+        self.capture.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic low-level noise
+    fn noise(len: usize, level: f32) -> Vec<f32> {
+        let mut state = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                level * ((state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_injector_spans_frames() {
+        let mut injector = ProbeInjector::new(48_000);
+        let chirp = chirp(48_000);
+
+        // 2.5 ms stereo frames: the 5 ms chirp covers two of them
+        let mut first = vec![0.1f32; 240];
+        let mut second = vec![0.1f32; 240];
+        let mut third = vec![0.1f32; 240];
+        assert!(injector.inject(&mut first, 2));
+        assert!(!injector.inject(&mut second, 2));
+        assert!(!injector.inject(&mut third, 2));
+
+        assert_eq!(first[2 * 10], chirp[10]);
+        assert_eq!(first[2 * 10 + 1], chirp[10]);
+        assert_eq!(second[0], chirp[120]);
+        assert!(third.iter().all(|&s| s == 0.1));
+    }
+
+    #[test]
+    fn test_detector_finds_chirp() {
+        let mut signal = noise(9_600, 0.02);
+        let offset = 3_000;
+        for (sample, value) in signal[offset..].iter_mut().zip(chirp(48_000)) {
+            *sample += value;
+        }
+
+        let mut detector = ProbeDetector::new(48_000);
+        let mut detections = Vec::new();
+        let mut fed = 0u64;
+        for block in signal.chunks(480) {
+            fed += block.len() as u64;
+            if let Some(age) = detector.process(block) {
+                detections.push(fed - age);
+            }
+        }
+        assert_eq!(detections, vec![offset as u64]);
+
+        // Noise and steady tones are not taken for the chirp
+        let mut detector = ProbeDetector::new(48_000);
+        let tone: Vec<f32> = (0..9_600).map(|i| 0.5 * (i as f32 * 0.3).sin()).collect();
+        assert!(detector.process(&noise(9_600, 0.5)).is_none());
+        assert!(detector.process(&tone).is_none());
+    }
+
+    #[test]
+    fn test_meter_loopback_attribution() {
+        let early = ProbeMeter::new();
+        let late = ProbeMeter::new();
+        let silent = ProbeMeter::new();
+        early.record_played_at(100_000, 150_000);
+        late.record_played_at(140_000, 160_000);
+        assert_eq!(late.output_latency_us(), Some(20_000));
+        assert!(silent.output_latency_us().is_none());
+
+        let detected = 165_000;
+        let (index, latency) = LoopbackProbe::attribute(detected, [&early, &late, &silent]).unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(latency, 25_000);
+        assert_eq!(late.loopback_latency_us(), Some(latency));
+        assert!(early.loopback_latency_us().is_none());
+
+        // Nothing played shortly before the detection
+        assert!(LoopbackProbe::attribute(detected + 10 * LOOPBACK_WINDOW_US, [&late]).is_none());
+        late.reset();
+        assert!(late.output_latency_us().is_none());
+    }
+}
@@ -0,0 +1,276 @@
+//! PipeWire backend bridged through `pw-cat` (Linux, `pipewire` feature)
+//!
+//! Every track runs as a PipeWire stream node of its own, so incoming and
+//! outgoing tracks can be routed individually in Helvum or qpwgraph:
+//!
+//! - playback: `lan-audio.track-<id>` ("LAN Audio track <id>")
+//! - capture: `lan-audio.capture-<id>` ("LAN Audio capture <id>")
+//!
+//! This is not a libpipewire client: the crate links no PipeWire library
+//! and drives the server's command line tools instead. Each stream is a
+//! `pw-cat` process fed over a pipe (see [`pipe`](crate::audio::pipe)),
+//! devices are the sinks and sources listed by `pw-dump`, identified as
+//! `output:<node.name>` / `input:<node.name>`, and `pw-cli` checks that a
+//! server is running. The tools ship with PipeWire (`pipewire-bin` on
+//! Debian and Ubuntu, `pipewire-utils` on Fedora); selecting the backend
+//! without one of them on `PATH` fails with an error naming it.
+
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::process::{Command, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::audio::clock::ClockSkewMonitor;
+use crate::audio::device;
+use crate::audio::mixer::MixerInputs;
+use crate::audio::pipe::{self, PipeOutput};
+use crate::constants::{DEFAULT_CHANNELS, DEFAULT_SAMPLE_RATE};
+use crate::error::AudioError;
+use crate::protocol::AudioDeviceInfo;
+
+/// Stream latency requested from `pw-cat`
+const LATENCY: &str = "10ms";
+
+/// PipeWire command line tools the bridge runs
+const TOOLS: [&str; 3] = ["pw-cat", "pw-dump", "pw-cli"];
+
+/// Check that the PipeWire tools are installed and a server is reachable
+pub fn check() -> Result<(), AudioError> {
+    if let Some(tool) = TOOLS.iter().find(|tool| !on_path(tool)) {
+        return Err(missing_tool(tool));
+    }
+    let running = Command::new("pw-cli")
+        .args(["info", "0"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    if running {
+        Ok(())
+    } else {
+        Err(AudioError::DeviceNotFound("PipeWire server is not running".to_string()))
+    }
+}
+
+/// Whether `program` is an executable file in one of the `PATH` directories
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+fn missing_tool(tool: &str) -> AudioError {
+    AudioError::DeviceNotFound(format!(
+        "{} not found on PATH: the PipeWire backend needs the PipeWire command line tools ({})",
+        tool,
+        TOOLS.join(", ")
+    ))
+}
+
+/// List PipeWire sinks and sources
+pub fn list_devices() -> Vec<AudioDeviceInfo> {
+    match Command::new("pw-dump").stderr(Stdio::null()).output() {
+        Ok(output) if output.status.success() => parse_dump(&String::from_utf8_lossy(&output.stdout)),
+        Ok(output) => {
+            tracing::warn!("pw-dump failed: {}", output.status);
+            Vec::new()
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::warn!("{}", missing_tool("pw-dump"));
+            Vec::new()
+        }
+        Err(e) => {
+            tracing::warn!("pw-dump failed to start: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Devices in `pw-dump` output
+fn parse_dump(dump: &str) -> Vec<AudioDeviceInfo> {
+    let Ok(Value::Array(objects)) = serde_json::from_str::<Value>(dump) else {
+        return Vec::new();
+    };
+
+    let default_sink = default_node(&objects, "default.audio.sink");
+    let default_source = default_node(&objects, "default.audio.source");
+
+    objects
+        .iter()
+        .filter(|object| object["type"] == "PipeWire:Interface:Node")
+        .filter_map(|object| {
+            let props = &object["info"]["props"];
+            let is_input = match props["media.class"].as_str()? {
+                "Audio/Sink" => false,
+                "Audio/Source" | "Audio/Source/Virtual" => true,
+                _ => return None,
+            };
+            let node_name = props["node.name"].as_str()?;
+            let name = props["node.description"].as_str().unwrap_or(node_name);
+            let default = if is_input { &default_source } else { &default_sink };
+
+            Some(AudioDeviceInfo {
+                id: format!("{}:{}", if is_input { "input" } else { "output" }, node_name),
+                name: name.to_string(),
+                is_input,
+                is_output: !is_input,
+                is_default: default.as_deref() == Some(node_name),
+                sample_rates: vec![props["audio.rate"].as_u64().map_or(DEFAULT_SAMPLE_RATE, |rate| rate as u32)],
+                channels: vec![props["audio.channels"].as_u64().map_or(DEFAULT_CHANNELS, |ch| ch as u16)],
+                is_loopback: false,
+                capture_mode: None,
+                playback_mode: None,
+            })
+        })
+        .collect()
+}
+
+/// Node name stored under `key` in the "default" metadata
+fn default_node(objects: &[Value], key: &str) -> Option<String> {
+    objects
+        .iter()
+        .filter(|object| object["type"] == "PipeWire:Interface:Metadata" && object["props"]["metadata.name"] == "default")
+        .filter_map(|object| object["metadata"].as_array())
+        .flatten()
+        .find(|entry| entry["key"] == key)
+        .and_then(|entry| entry["value"]["name"].as_str())
+        .map(str::to_string)
+}
+
+/// Target node of a device ID (empty = let PipeWire choose)
+fn target(device_id: &str) -> &str {
+    device_id
+        .strip_prefix("input:")
+        .or_else(|| device_id.strip_prefix("output:"))
+        .unwrap_or(device_id)
+}
+
+/// Channel count of a source (None if it is not listed)
+pub(crate) fn input_channels(device_id: &str) -> Option<u16> {
+    list_devices()
+        .into_iter()
+        .find(|device| device.is_input && device.id == device_id)
+        .and_then(|device| device.channels.first().copied())
+}
+
+fn pw_cat(mode: &str, device_id: &str, node: &str, description: &str, sample_rate: u32, channels: u16) -> Command {
+    let mut command = Command::new("pw-cat");
+    command.args([
+        mode,
+        "--raw",
+        "--format=f32",
+        &format!("--rate={}", sample_rate),
+        &format!("--channels={}", channels),
+        &format!("--latency={}", LATENCY),
+        &format!("--properties={{ node.name = \"{}\" node.description = \"{}\" media.name = \"{}\" }}", node, description, description),
+    ]);
+    let target = target(device_id);
+    if !target.is_empty() {
+        command.arg(format!("--target={}", target));
+    }
+    command.arg("-");
+    command
+}
+
+/// Playback node of one track: a `pw-cat` process fed from the mixer
+pub(crate) struct PwCatOutput {
+    output: PipeOutput,
+}
+
+impl PwCatOutput {
+    /// Start a playback node for `track_id` on `device_id`
+    pub(crate) fn start(
+        track_id: u8,
+        device_id: &str,
+        sample_rate: u32,
+        channels: u16,
+        inputs: Arc<Mutex<MixerInputs>>,
+    ) -> Result<Self, AudioError> {
+        let command = pw_cat(
+            "--playback",
+            device_id,
+            &device::track_node_name(track_id, false),
+            &format!("LAN Audio track {}", track_id),
+            sample_rate,
+            channels,
+        );
+        let output = PipeOutput::spawn(command, format!("playback-track-{}", track_id), sample_rate, channels, inputs)?;
+        Ok(Self { output })
+    }
+
+    /// Clock skew monitor of the node
+    pub(crate) fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+        self.output.clock_monitor()
+    }
+}
+
+/// Run a capture node for `track_id` (a `pw-cat` process) until `running`
+/// is cleared (blocks the calling thread)
+pub(crate) fn capture(
+    track_id: u8,
+    device_id: &str,
+    sample_rate: u32,
+    channels: u16,
+    running: &AtomicBool,
+    on_block: impl FnMut(&[f32]),
+) -> Result<(), AudioError> {
+    let command = pw_cat(
+        "--record",
+        device_id,
+        &device::track_node_name(track_id, true),
+        &format!("LAN Audio capture {}", track_id),
+        sample_rate,
+        channels,
+    );
+    pipe::read_blocks(command, channels, running, on_block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = r#"[
+        { "id": 50, "type": "PipeWire:Interface:Node", "info": { "props": {
+            "media.class": "Audio/Sink", "node.name": "alsa_output.pci-0000_00_1f.3.analog-stereo",
+            "node.description": "Built-in Audio Analog Stereo", "audio.channels": 2 } } },
+        { "id": 51, "type": "PipeWire:Interface:Node", "info": { "props": {
+            "media.class": "Audio/Source", "node.name": "alsa_input.usb-mic",
+            "node.description": "USB Microphone", "audio.channels": 1 } } },
+        { "id": 60, "type": "PipeWire:Interface:Node", "info": { "props": {
+            "media.class": "Stream/Output/Audio", "node.name": "lan-audio.track-1" } } },
+        { "id": 70, "type": "PipeWire:Interface:Metadata", "props": { "metadata.name": "default" },
+          "metadata": [ { "subject": 0, "key": "default.audio.sink",
+                          "value": { "name": "alsa_output.pci-0000_00_1f.3.analog-stereo" } } ] }
+    ]"#;
+
+    #[test]
+    fn test_parse_dump() {
+        let devices = parse_dump(DUMP);
+        assert_eq!(devices.len(), 2, "streams are not devices");
+
+        let sink = &devices[0];
+        assert_eq!(sink.id, "output:alsa_output.pci-0000_00_1f.3.analog-stereo");
+        assert!(sink.is_output && sink.is_default);
+        assert_eq!(sink.sample_rates, vec![DEFAULT_SAMPLE_RATE]);
+
+        let source = &devices[1];
+        assert_eq!(source.name, "USB Microphone");
+        assert!(source.is_input && !source.is_default);
+        assert_eq!(source.channels, vec![1]);
+        assert_eq!(target(&source.id), "alsa_input.usb-mic");
+
+        assert!(parse_dump("not json").is_empty());
+    }
+
+    #[test]
+    fn test_missing_tool_is_named() {
+        assert!(on_path("sh"));
+        assert!(!on_path("pw-cat-does-not-exist"));
+
+        let message = missing_tool("pw-cat").to_string();
+        assert!(message.contains("pw-cat not found on PATH"), "{}", message);
+        assert!(message.contains("pw-dump, pw-cli"), "{}", message);
+    }
+}
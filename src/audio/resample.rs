@@ -0,0 +1,221 @@
+//! Sample-rate conversion for devices that don't run at the stream rate
+//!
+//! Tracks are encoded, decoded and mixed at `DEFAULT_SAMPLE_RATE`. Devices
+//! fixed at another rate (44.1 kHz interfaces, shared-mode endpoints whose
+//! mix format can't be changed) are opened at their own rate instead, and
+//! a [`Resampler`] converts between the two on the capture and playback
+//! paths. It is a windowed-sinc interpolator with a precomputed polyphase
+//! filter bank; the low-pass cutoff follows the lower of the two rates so
+//! downsampling doesn't alias.
+
+use std::f64::consts::PI;
+
+/// Filter length in input frames
+const TAPS: usize = 16;
+
+/// Input frames the filter reads before the interpolated position
+const LEFT: usize = TAPS / 2 - 1;
+
+/// Filter phases between two input frames (interpolated linearly)
+const PHASES: usize = 128;
+
+/// Cutoff relative to the lower Nyquist frequency (room for the transition band)
+const CUTOFF: f64 = 0.95;
+
+/// Streaming sample-rate converter for interleaved audio
+pub struct Resampler {
+    channels: usize,
+    from: u32,
+    to: u32,
+    /// Input frames per output frame
+    step: f64,
+    /// Position of the next output frame in `history`, in input frames
+    position: f64,
+    /// Interleaved input not consumed yet, from `LEFT` frames before `position`
+    history: Vec<f32>,
+    /// `PHASES + 1` rows of `TAPS` coefficients
+    filters: Vec<f32>,
+}
+
+impl Resampler {
+    /// Convert interleaved audio with `channels` channels from `from` Hz to `to` Hz
+    pub fn new(from: u32, to: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let cutoff = CUTOFF * (to as f64 / from as f64).min(1.0);
+
+        let mut filters = Vec::with_capacity((PHASES + 1) * TAPS);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            let row: Vec<f64> = (0..TAPS)
+                .map(|k| {
+                    let x = k as f64 - LEFT as f64 - frac;
+                    cutoff * sinc(cutoff * x) * blackman(x)
+                })
+                .collect();
+            // Unity gain at DC for every phase
+            let sum: f64 = row.iter().sum();
+            filters.extend(row.iter().map(|c| (c / sum) as f32));
+        }
+
+        Self {
+            channels,
+            from,
+            to,
+            step: from as f64 / to as f64,
+            position: LEFT as f64,
+            history: vec![0.0; LEFT * channels],
+            filters,
+        }
+    }
+
+    /// Input rate in Hz
+    pub fn from_rate(&self) -> u32 {
+        self.from
+    }
+
+    /// Output rate in Hz
+    pub fn to_rate(&self) -> u32 {
+        self.to
+    }
+
+    /// Input frames still needed before `output_frames` more frames can be produced
+    pub fn input_frames_for(&self, output_frames: usize) -> usize {
+        if output_frames == 0 {
+            return 0;
+        }
+        let last = self.position + (output_frames - 1) as f64 * self.step;
+        let required = last as usize + TAPS - LEFT;
+        required.saturating_sub(self.history.len() / self.channels)
+    }
+
+    /// Convert interleaved `input` and append every frame that can be
+    /// produced so far to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        self.history.extend_from_slice(input);
+        let frames = self.history.len() / channels;
+
+        let mut coefficients = [0.0f32; TAPS];
+        while self.position as usize + TAPS - LEFT <= frames {
+            let index = self.position as usize;
+            let phase = (self.position - index as f64) * PHASES as f64;
+            let row = (phase as usize).min(PHASES - 1);
+            let blend = (phase - row as f64) as f32;
+            let a = &self.filters[row * TAPS..][..TAPS];
+            let b = &self.filters[(row + 1) * TAPS..][..TAPS];
+            for ((coefficient, a), b) in coefficients.iter_mut().zip(a).zip(b) {
+                *coefficient = a + (b - a) * blend;
+            }
+
+            let window = &self.history[(index - LEFT) * channels..][..TAPS * channels];
+            for channel in 0..channels {
+                let sum: f32 = window[channel..]
+                    .iter()
+                    .step_by(channels)
+                    .zip(&coefficients)
+                    .map(|(sample, coefficient)| sample * coefficient)
+                    .sum();
+                output.push(sum);
+            }
+            self.position += self.step;
+        }
+
+        // Keep the frames the next output still reads
+        let consumed = (self.position as usize).saturating_sub(LEFT).min(frames);
+        self.history.drain(..consumed * channels);
+        self.position -= consumed as f64;
+    }
+
+    /// Forget buffered input (e.g. after a stream restart)
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.history.resize(LEFT * self.channels, 0.0);
+        self.position = LEFT as f64;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over the filter length, centred on 0
+fn blackman(x: f64) -> f64 {
+    let t = 2.0 * PI * x / TAPS as f64;
+    (0.42 + 0.5 * t.cos() + 0.08 * (2.0 * t).cos()).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, frequency: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency * i as f64 / rate as f64).sin() as f32 * 0.5)
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// Rising zero crossings per second
+    fn frequency(samples: &[f32], rate: u32) -> f64 {
+        let crossings = samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        crossings as f64 * rate as f64 / samples.len() as f64
+    }
+
+    #[test]
+    fn test_resample_keeps_pitch_and_level() {
+        for (from, to) in [(44_100, 48_000), (48_000, 44_100)] {
+            let input = sine(from, 1000.0, from as usize);
+            let mut resampler = Resampler::new(from, to, 1);
+            let mut output = Vec::new();
+            resampler.process(&input, &mut output);
+
+            assert!((output.len() as i64 - to as i64).abs() <= TAPS as i64);
+            // Skip the filter's start-up
+            let steady = &output[100..output.len() - 100];
+            assert!((frequency(steady, to) - 1000.0).abs() < 5.0);
+            assert!((rms(steady) - rms(&input)).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_resample_in_chunks_matches_one_pass() {
+        let input: Vec<f32> = sine(44_100, 440.0, 4410)
+            .iter()
+            .flat_map(|s| [*s, -*s])
+            .collect();
+
+        let mut whole = Vec::new();
+        Resampler::new(44_100, 48_000, 2).process(&input, &mut whole);
+
+        let mut resampler = Resampler::new(44_100, 48_000, 2);
+        let mut chunked = Vec::new();
+        for chunk in input.chunks(2 * 441) {
+            resampler.process(chunk, &mut chunked);
+        }
+
+        assert_eq!(whole.len(), chunked.len());
+        assert!(whole.iter().zip(&chunked).all(|(a, b)| (a - b).abs() < 1e-6));
+        // Channels stay apart
+        assert!(chunked.chunks(2).all(|frame| (frame[0] + frame[1]).abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_input_frames_for_is_enough() {
+        let mut resampler = Resampler::new(44_100, 48_000, 1);
+        let mut output = Vec::new();
+        for wanted in [480, 441, 1, 1024] {
+            let needed = resampler.input_frames_for(wanted);
+            output.clear();
+            resampler.process(&vec![0.1; needed], &mut output);
+            assert!(output.len() >= wanted);
+            assert_eq!(resampler.input_frames_for(0), 0);
+        }
+    }
+}
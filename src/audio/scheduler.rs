@@ -0,0 +1,298 @@
+//! Playout of jitter-buffered frames by the stream clock
+//!
+//! Played out as packets arrive, every frame the jitter buffer holds above
+//! its target goes to the output at once, so how much audio waits in the
+//! output ring instead of the jitter buffer depends on when packets happen
+//! to arrive and when the receive loop runs. The scheduler starts playout
+//! once the buffer holds its target delay and from then on releases one
+//! frame per frame duration of the stream, by the local clock: frames wait
+//! in the jitter buffer (where a late packet can still fill its slot), a
+//! missing frame is concealed when its time comes, and the delay through
+//! the buffer stays at the target.
+//!
+//! The output device doesn't consume at exactly the local clock either:
+//! frames are released at the rate its clock skew monitor measures, so the
+//! output ring neither fills up nor runs dry.
+//!
+//! The sender's sound card doesn't run at exactly the output's clock. The
+//! scheduler is the one controller of this long-term drift: a PI controller
+//! on the average jitter-buffer level around its target sets a rate
+//! correction of up to 1000 ppm. It applies both to the release of frames
+//! and to the rate the output reads them at (see
+//! `MixerChannel::set_rate_correction_ppm`), so the output's own drift
+//! compensation stays off.
+//!
+//! A backlog of more than twice the target (a burst after a stall) is
+//! released at once for the output to catch up on, and a buffer that runs
+//! empty stops the clock until the target delay is buffered again. Those
+//! transients are left to the output's catch-up and time stretching.
+
+use std::time::{Duration, Instant};
+
+use crate::audio::buffer::{JitterBuffer, Playout};
+
+/// Averaging time of the buffer level (5 s)
+const LEVEL_AVERAGE: Duration = Duration::from_secs(5);
+
+/// Rate correction per frame of level above or below the target
+const RATE_GAIN: f64 = 200e-6;
+
+/// Integration time of the rate correction (100 s)
+const RATE_INTEGRAL: Duration = Duration::from_secs(100);
+
+/// Largest rate correction (1000 ppm)
+const MAX_RATE_CORRECTION: f64 = 1e-3;
+
+/// Largest output clock skew followed (1%); beyond it the measurement is wrong
+const MAX_OUTPUT_SKEW: f64 = 0.01;
+
+/// Releases the frames of a jitter buffer when they are due
+#[derive(Debug, Default)]
+pub struct PlayoutScheduler {
+    /// When the next frame is due (None until the buffer holds its target)
+    next_due: Option<Instant>,
+    /// Output device rate relative to nominal (None: nominal)
+    output_ratio: Option<f64>,
+    /// Average buffer level in frames (None until playout started)
+    level_average: Option<f64>,
+    /// Integral part of the rate correction
+    rate_integral: f64,
+    /// Rate correction for the sender's clock (1e-6 = 1 ppm faster)
+    rate_correction: f64,
+}
+
+impl PlayoutScheduler {
+    /// Create a scheduler waiting for the jitter buffer to fill
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Next slot of `jitter` due at `now`, None until one is
+    pub fn next_playout(&mut self, jitter: &mut JitterBuffer, now: Instant) -> Option<Playout> {
+        let frame_duration = Duration::from_micros(jitter.frame_duration_us());
+        let Some(due) = self.next_due else {
+            // Pre-roll: the first frame goes once the target delay is buffered
+            let playout = jitter.next_playout()?;
+            self.next_due = Some(now + frame_duration);
+            return Some(playout);
+        };
+
+        let backlog = jitter.level() > jitter.target_delay() * 2;
+        if now < due && !backlog {
+            return None;
+        }
+
+        let Some(playout) = jitter.next_playout_due() else {
+            // Ran dry: wait for the target delay again
+            self.next_due = None;
+            return None;
+        };
+        if now >= due {
+            self.track_level(jitter, frame_duration);
+            let rate = self.output_ratio.unwrap_or(1.0) * (1.0 + self.rate_correction);
+            self.next_due = Some(due + frame_duration.div_f64(rate));
+        }
+        Some(playout)
+    }
+
+    /// Follow the buffer level after a frame duration of playout
+    fn track_level(&mut self, jitter: &JitterBuffer, frame_duration: Duration) {
+        let level = jitter.level() as f64;
+        let weight = (frame_duration.as_secs_f64() / LEVEL_AVERAGE.as_secs_f64()).min(1.0);
+        let average = match self.level_average {
+            Some(average) => average + (level - average) * weight,
+            None => level,
+        };
+        self.level_average = Some(average);
+
+        let error = average - jitter.target_delay() as f64;
+        let step = frame_duration.as_secs_f64() / RATE_INTEGRAL.as_secs_f64();
+        self.rate_integral = (self.rate_integral + error * RATE_GAIN * step)
+            .clamp(-MAX_RATE_CORRECTION, MAX_RATE_CORRECTION);
+        self.rate_correction = (self.rate_integral + error * RATE_GAIN)
+            .clamp(-MAX_RATE_CORRECTION, MAX_RATE_CORRECTION);
+    }
+
+    /// Rate correction for the sender's clock in ppm: positive when it runs
+    /// fast against the output (None until playout started). The output
+    /// reads the released frames at this rate.
+    pub fn rate_correction_ppm(&self) -> Option<f64> {
+        self.level_average.map(|_| self.rate_correction * 1e6)
+    }
+
+    /// Release frames at the output device's measured rate (measured /
+    /// nominal, see `ClockSkewMonitor::rate_ratio`)
+    pub fn set_output_rate(&mut self, ratio: f64) {
+        self.output_ratio = Some(ratio.clamp(1.0 - MAX_OUTPUT_SKEW, 1.0 + MAX_OUTPUT_SKEW));
+    }
+
+    /// Time a frame arriving now spends in the jitter buffer (µs): the
+    /// frames ahead of it plus the wait for the next release; the target
+    /// delay before playout starts
+    pub fn delay_us(&self, jitter: &JitterBuffer, now: Instant) -> u64 {
+        match self.next_due {
+            Some(due) => {
+                let wait_us = due.saturating_duration_since(now).as_micros() as u64;
+                jitter.level() as u64 * jitter.frame_duration_us() + wait_us
+            }
+            None => jitter.delay_us(),
+        }
+    }
+
+    /// Whether frames are being released (the buffer reached its target)
+    pub fn is_playing(&self) -> bool {
+        self.next_due.is_some()
+    }
+
+    /// Wait for the target delay again (the jitter buffer was reset); the
+    /// rate correction is kept, the clocks haven't changed
+    pub fn reset(&mut self) {
+        self.next_due = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::buffer::AudioFrame;
+
+    fn frame(seq: u32) -> AudioFrame {
+        AudioFrame::new(vec![0.0; 960], 2, seq as u64 * 10_000, seq)
+    }
+
+    fn released(scheduler: &mut PlayoutScheduler, jitter: &mut JitterBuffer, now: Instant) -> Vec<u32> {
+        std::iter::from_fn(|| scheduler.next_playout(jitter, now))
+            .map(|playout| match playout {
+                Playout::Frame(frame) => frame.sequence,
+                Playout::Lost { sequence, .. } => sequence,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_frames_released_by_clock() {
+        let mut jitter = JitterBuffer::new(32, 3);
+        let mut scheduler = PlayoutScheduler::new();
+        let start = Instant::now();
+        let ms = |ms: u64| start + Duration::from_millis(ms);
+
+        // Nothing until the target delay is buffered, then one frame
+        jitter.insert(frame(0));
+        jitter.insert(frame(1));
+        assert!(released(&mut scheduler, &mut jitter, start).is_empty());
+        for seq in 2..6 {
+            jitter.insert(frame(seq));
+        }
+        assert_eq!(released(&mut scheduler, &mut jitter, start), vec![0]);
+        assert!(scheduler.is_playing());
+
+        // A burst of arrivals waits in the buffer for its time
+        assert!(released(&mut scheduler, &mut jitter, ms(5)).is_empty());
+        assert_eq!(released(&mut scheduler, &mut jitter, ms(10)), vec![1]);
+        assert_eq!(released(&mut scheduler, &mut jitter, ms(31)), vec![2, 3]);
+
+        // A missing frame is concealed when due, even below the target
+        jitter.insert(frame(7));
+        assert_eq!(released(&mut scheduler, &mut jitter, ms(41)), vec![4]);
+        assert_eq!(released(&mut scheduler, &mut jitter, ms(51)), vec![5]);
+        assert_eq!(released(&mut scheduler, &mut jitter, ms(61)), vec![6]);
+        assert_eq!(jitter.stats().lost, 1);
+
+        // Run dry: wait for the target again
+        assert_eq!(released(&mut scheduler, &mut jitter, ms(71)), vec![7]);
+        assert!(released(&mut scheduler, &mut jitter, ms(81)).is_empty());
+        assert!(!scheduler.is_playing());
+    }
+
+    #[test]
+    fn test_backlog_released_at_once() {
+        let mut jitter = JitterBuffer::new(32, 2);
+        let mut scheduler = PlayoutScheduler::new();
+        let start = Instant::now();
+
+        for seq in 0..10 {
+            jitter.insert(frame(seq));
+        }
+        assert_eq!(released(&mut scheduler, &mut jitter, start), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(jitter.level(), 4);
+
+        // Frames ahead of the next arrival plus the wait for the next release
+        let delay_us = scheduler.delay_us(&jitter, start + Duration::from_millis(4));
+        assert_eq!(delay_us, 4 * 10_000 + 6_000);
+    }
+
+    #[test]
+    fn test_rate_follows_level() {
+        let mut jitter = JitterBuffer::new(32, 2);
+        let mut scheduler = PlayoutScheduler::new();
+        let start = Instant::now();
+        for seq in 0..5 {
+            jitter.insert(frame(seq));
+        }
+        released(&mut scheduler, &mut jitter, start);
+
+        // Above the target the next frame is due a little sooner
+        assert_eq!(jitter.level(), 4);
+        assert_eq!(scheduler.rate_correction_ppm(), None);
+        released(&mut scheduler, &mut jitter, start + Duration::from_millis(10));
+        let next_due = scheduler.next_due.unwrap() - start;
+        assert!(next_due < Duration::from_millis(20) && next_due > Duration::from_micros(19_990));
+        assert!(scheduler.rate_correction_ppm().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_rate_correction_limited() {
+        let mut jitter = JitterBuffer::new(64, 2);
+        let mut scheduler = PlayoutScheduler::new();
+        let start = Instant::now();
+
+        // A sender running 2000 ppm fast for five minutes
+        let mut seq = 0;
+        for step in 0..30_000u64 {
+            let frames = if step % 500 == 0 { 2 } else { 1 };
+            for _ in 0..frames {
+                jitter.insert(frame(seq));
+                seq += 1;
+            }
+            released(&mut scheduler, &mut jitter, start + Duration::from_millis(10 * step));
+        }
+        let correction = scheduler.rate_correction_ppm().unwrap();
+        assert!(correction > 900.0 && correction <= 1000.0, "{correction}");
+
+        // The correction outlives a reset of the buffer
+        scheduler.reset();
+        assert_eq!(scheduler.rate_correction_ppm(), Some(correction));
+    }
+
+    #[test]
+    fn test_rate_follows_output_clock() {
+        let start = Instant::now();
+        let next_due = |ratio: Option<f64>| {
+            let mut jitter = JitterBuffer::new(32, 2);
+            let mut scheduler = PlayoutScheduler::new();
+            if let Some(ratio) = ratio {
+                scheduler.set_output_rate(ratio);
+            }
+            for seq in 0..4 {
+                jitter.insert(frame(seq));
+            }
+            released(&mut scheduler, &mut jitter, start);
+            released(&mut scheduler, &mut jitter, start + Duration::from_millis(10));
+            // At the target level: no correction, only the output clock
+            assert_eq!(jitter.level(), 2);
+            scheduler.next_due.unwrap() - start
+        };
+
+        assert_eq!(next_due(None), Duration::from_millis(20));
+        assert_eq!(next_due(Some(1.0)), Duration::from_millis(20));
+
+        // An output running 500 ppm fast takes the next frame 5 µs sooner
+        let fast = next_due(Some(1.0005));
+        assert!(fast < Duration::from_millis(20) && fast > Duration::from_micros(19_990));
+        let slow = next_due(Some(0.9995));
+        assert!(slow > Duration::from_millis(20) && slow < Duration::from_micros(20_010));
+
+        // A wild measurement is limited to 1%
+        assert_eq!(next_due(Some(2.0)), next_due(Some(1.01)));
+    }
+}
@@ -0,0 +1,160 @@
+//! Silence suppression on capture
+//!
+//! A track with `TrackConfig::dtx` stops sending once its frames stay
+//! below `silence_threshold_db` for `silence_hold_ms`; the hold keeps word
+//! endings and short pauses. The first suppressed frame is replaced by a
+//! silence marker, an audio packet without payload, repeated every
+//! [`MARKER_INTERVAL_MS`] for receivers that join or lose it. Markers take
+//! sequence numbers like frames, so the receiver plays out what it has
+//! buffered and then silence, instead of concealing lost frames. The
+//! first frame above the threshold is sent right away.
+
+use crate::audio::simd;
+use crate::protocol::TrackConfig;
+
+/// Time between silence markers while a track is silent
+pub const MARKER_INTERVAL_MS: f32 = 400.0;
+
+/// What to send for a captured frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateAction {
+    /// Encode and send the frame
+    Send,
+    /// Send a silence marker instead of the frame
+    Marker,
+    /// Send nothing
+    Suppress,
+}
+
+/// Silence gate of one track
+#[derive(Debug, Clone)]
+pub struct SilenceGate {
+    /// Peak below which a frame is silent (linear)
+    threshold: f32,
+    hold_ms: f32,
+    /// Time the input has been below the threshold
+    quiet_ms: f32,
+    /// Time since the last marker while silent
+    since_marker_ms: f32,
+    silent: bool,
+}
+
+impl SilenceGate {
+    pub fn new(threshold_db: f32, hold_ms: u32) -> Self {
+        let mut gate = Self {
+            threshold: 0.0,
+            hold_ms: 0.0,
+            quiet_ms: 0.0,
+            since_marker_ms: 0.0,
+            silent: false,
+        };
+        gate.set_params(threshold_db, hold_ms);
+        gate
+    }
+
+    /// Gate of a track with silence suppression on (None when off)
+    pub fn for_track(config: &TrackConfig) -> Option<Self> {
+        config
+            .dtx
+            .then(|| Self::new(config.silence_threshold_db, config.silence_hold_ms))
+    }
+
+    /// Follow a changed track config, keeping the state of a running gate
+    pub fn reconfigure(gate: &mut Option<Self>, config: &TrackConfig) {
+        match (gate.as_mut(), config.dtx) {
+            (Some(gate), true) => gate.set_params(config.silence_threshold_db, config.silence_hold_ms),
+            (None, true) => *gate = Self::for_track(config),
+            (_, false) => *gate = None,
+        }
+    }
+
+    /// Change the threshold (dBFS) and hold time
+    pub fn set_params(&mut self, threshold_db: f32, hold_ms: u32) {
+        self.threshold = 10f32.powf(threshold_db / 20.0);
+        self.hold_ms = hold_ms as f32;
+    }
+
+    /// Whether frames are currently suppressed
+    pub fn is_silent(&self) -> bool {
+        self.silent
+    }
+
+    /// Decide what to send for a frame lasting `frame_ms`
+    pub fn process(&mut self, samples: &[f32], frame_ms: f32) -> GateAction {
+        if simd::peak_abs(samples) >= self.threshold {
+            self.quiet_ms = 0.0;
+            self.silent = false;
+            return GateAction::Send;
+        }
+
+        self.quiet_ms += frame_ms;
+        if self.quiet_ms <= self.hold_ms {
+            return GateAction::Send;
+        }
+        if !self.silent {
+            self.silent = true;
+            self.since_marker_ms = 0.0;
+            return GateAction::Marker;
+        }
+
+        self.since_marker_ms += frame_ms;
+        if self.since_marker_ms >= MARKER_INTERVAL_MS {
+            self.since_marker_ms = 0.0;
+            GateAction::Marker
+        } else {
+            GateAction::Suppress
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gate_holds_then_marks() {
+        let mut gate = SilenceGate::new(-60.0, 30);
+        let loud = vec![0.1; 960];
+        let quiet = vec![0.0001; 960];
+
+        assert_eq!(gate.process(&loud, 10.0), GateAction::Send);
+
+        // Hold time: three quiet frames still go out
+        for _ in 0..3 {
+            assert_eq!(gate.process(&quiet, 10.0), GateAction::Send);
+        }
+        assert_eq!(gate.process(&quiet, 10.0), GateAction::Marker);
+        assert!(gate.is_silent());
+
+        // A marker every 400 ms while silent
+        let actions: Vec<GateAction> = (0..80).map(|_| gate.process(&quiet, 10.0)).collect();
+        assert_eq!(actions.iter().filter(|&&action| action == GateAction::Marker).count(), 2);
+        assert_eq!(actions[39], GateAction::Marker);
+
+        // Speech resumes at once
+        assert_eq!(gate.process(&loud, 10.0), GateAction::Send);
+        assert!(!gate.is_silent());
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let mut config = TrackConfig::default();
+        let mut gate = SilenceGate::for_track(&config);
+        assert!(gate.is_none());
+
+        config.dtx = true;
+        SilenceGate::reconfigure(&mut gate, &config);
+        assert!(gate.is_some());
+
+        // A signal at -50 dBFS passes a -60 dB threshold, not a -40 dB one
+        let signal = vec![0.003; 960];
+        config.silence_threshold_db = -40.0;
+        config.silence_hold_ms = 0;
+        SilenceGate::reconfigure(&mut gate, &config);
+        assert_eq!(gate.as_mut().unwrap().process(&signal, 10.0), GateAction::Marker);
+
+        config.dtx = false;
+        SilenceGate::reconfigure(&mut gate, &config);
+        assert!(gate.is_none());
+    }
+}
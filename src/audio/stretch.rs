@@ -0,0 +1,140 @@
+//! Time-scale modification of playout frames
+//!
+//! Changing how much audio an output holds means playing it slightly
+//! faster or slower. Resampling does that by shifting the pitch; here a
+//! frame is made shorter or longer by one period of its waveform instead
+//! (the splice of WSOLA, as used for accelerate/expand in VoIP jitter
+//! buffers): the period is where the frame best matches itself shifted,
+//! and the two copies are cross-faded over it, so the pitch stays and the
+//! splice is inaudible for voice and most music.
+
+use std::f32::consts::PI;
+
+/// Shortest period searched (2.5 ms at 48 kHz)
+pub const MIN_PERIOD: usize = 120;
+
+/// Longest period searched (20 ms at 48 kHz)
+pub const MAX_PERIOD: usize = 960;
+
+/// Whether a frame of `len` interleaved samples is long enough to splice
+pub fn can_splice(len: usize, channels: usize) -> bool {
+    len / channels.max(1) / 2 >= MIN_PERIOD
+}
+
+/// Period of the frame in sample frames: the shift at which it best
+/// matches itself (None if the frame is too short to splice)
+pub fn find_period(samples: &[f32], channels: usize) -> Option<usize> {
+    let channels = channels.max(1);
+    if !can_splice(samples.len(), channels) {
+        return None;
+    }
+
+    let mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    let max_period = (mono.len() / 2).min(MAX_PERIOD);
+
+    // Energy of mono[..n] for every n
+    let mut energy = Vec::with_capacity(mono.len() + 1);
+    energy.push(0.0f64);
+    for &sample in &mono {
+        energy.push(energy[energy.len() - 1] + (sample * sample) as f64);
+    }
+
+    let mut best = (MIN_PERIOD, f64::MIN);
+    for period in MIN_PERIOD..=max_period {
+        let cross: f64 = mono[..period]
+            .iter()
+            .zip(&mono[period..2 * period])
+            .map(|(&a, &b)| (a * b) as f64)
+            .sum();
+        let norm = (energy[period] * (energy[2 * period] - energy[period])).sqrt();
+        let score = if norm > 1e-12 { cross / norm } else { 0.0 };
+        if score > best.1 {
+            best = (period, score);
+        }
+    }
+    Some(best.0)
+}
+
+/// Fade-in weight of sample `i` of a cross-fade over `len` samples
+fn fade_in(i: usize, len: usize) -> f32 {
+    0.5 - 0.5 * (PI * (i as f32 + 0.5) / len as f32).cos()
+}
+
+/// Drop one period: the first period fades into the second
+pub fn shorten(samples: &[f32], channels: usize, period: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+    let span = period * channels;
+    let mut out = Vec::with_capacity(samples.len() - span);
+    for i in 0..span {
+        let w = fade_in(i / channels, period);
+        out.push(samples[i] * (1.0 - w) + samples[i + span] * w);
+    }
+    out.extend_from_slice(&samples[2 * span..]);
+    out
+}
+
+/// Repeat one period: after the first period the second fades back into
+/// the first, which then runs on into the rest of the frame
+pub fn lengthen(samples: &[f32], channels: usize, period: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+    let span = period * channels;
+    let mut out = Vec::with_capacity(samples.len() + span);
+    out.extend_from_slice(&samples[..span]);
+    for i in 0..span {
+        let w = fade_in(i / channels, period);
+        out.push(samples[span + i] * (1.0 - w) + samples[i] * w);
+    }
+    out.extend_from_slice(&samples[span..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stereo 200 Hz tone (period of 240 samples at 48 kHz)
+    fn tone(frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let sample = (2.0 * PI * 200.0 * i as f32 / 48_000.0).sin() * 0.5;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    /// Largest step between neighbouring samples of a channel
+    fn max_step(samples: &[f32]) -> f32 {
+        samples
+            .chunks_exact(2)
+            .zip(samples.chunks_exact(2).skip(1))
+            .map(|(a, b)| (a[0] - b[0]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_splices_whole_periods() {
+        let frame = tone(960);
+        let period = find_period(&frame, 2).unwrap();
+        assert!(period % 240 <= 1 || period % 240 >= 239, "{}", period);
+
+        // A tone spliced at its period stays as smooth as the tone itself
+        let limit = max_step(&frame) * 1.05;
+        let shorter = shorten(&frame, 2, period);
+        assert_eq!(shorter.len(), frame.len() - 2 * period);
+        assert!(max_step(&shorter) <= limit);
+
+        let longer = lengthen(&frame, 2, period);
+        assert_eq!(longer.len(), frame.len() + 2 * period);
+        assert!(max_step(&longer) <= limit);
+    }
+
+    #[test]
+    fn test_short_frames_are_not_spliced() {
+        assert!(find_period(&tone(200), 2).is_none());
+        assert!(!can_splice(400, 2));
+        assert!(can_splice(480, 1));
+    }
+}
@@ -0,0 +1,338 @@
+//! Virtual output device for OBS integration
+//!
+//! Received tracks can be played into a virtual device so they show up as
+//! an audio input in OBS (or any other capture software) on the receiving
+//! machine:
+//!
+//! - Linux (PipeWire with pipewire-pulse, or PulseAudio): a null sink is
+//!   created with `pactl` and fed through `pacat`; add its monitor
+//!   ("Monitor of LAN-Audio") as an audio input capture source in OBS.
+//! - Windows: an installed virtual cable (VB-Cable, VoiceMeeter) is used as
+//!   the output; capture its recording end ("CABLE Output") in OBS. Without
+//!   one, the default output can still be picked up with desktop audio
+//!   capture or virtual-audio-capturer.
+//!
+//! The device is listed by [`list_devices`](crate::audio::list_devices)
+//! when available and selected like any other output by
+//! [`VIRTUAL_DEVICE_ID`]. Setting `LAN_AUDIO_VIRTUAL_OUTPUT=1` makes it the
+//! default output of new tracks.
+
+use crate::constants::{DEFAULT_CHANNELS, DEFAULT_SAMPLE_RATE, VIRTUAL_OUTPUT_ENV_VAR};
+use crate::protocol::AudioDeviceInfo;
+
+#[cfg(target_os = "linux")]
+pub(crate) use pulse::VirtualOutput;
+#[cfg(not(target_os = "linux"))]
+pub(crate) use cable::VirtualOutput;
+
+/// Device ID of the virtual output
+pub const VIRTUAL_DEVICE_ID: &str = "virtual:lan-audio";
+
+/// Name the virtual device is shown under in capture software
+pub const VIRTUAL_DEVICE_NAME: &str = "LAN-Audio";
+
+/// Output names of known virtual cable drivers (the end audio is played to)
+const CABLE_NAME_PATTERNS: &[&str] = &["CABLE Input", "VB-Audio", "VoiceMeeter Input", "Virtual Cable"];
+
+/// Check whether a device ID refers to the virtual output
+pub fn is_virtual(device_id: &str) -> bool {
+    device_id == VIRTUAL_DEVICE_ID
+}
+
+/// Whether the virtual output was requested as the default output
+pub fn requested_by_env() -> bool {
+    std::env::var(VIRTUAL_OUTPUT_ENV_VAR)
+        .map(|v| !matches!(v.as_str(), "" | "0" | "false"))
+        .unwrap_or(false)
+}
+
+/// Default output device: the virtual output when requested and available,
+/// otherwise the system default
+pub fn default_output_id(devices: &[AudioDeviceInfo]) -> String {
+    if requested_by_env() {
+        if let Some(device) = devices.iter().find(|d| is_virtual(&d.id)) {
+            return device.id.clone();
+        }
+        tracing::warn!("Virtual output requested but not available, using the default device");
+    }
+    devices
+        .iter()
+        .find(|d| d.is_output && d.is_default)
+        .map(|d| d.id.clone())
+        .unwrap_or_default()
+}
+
+/// Find an installed virtual cable among the output devices
+pub fn find_virtual_cable(devices: &[AudioDeviceInfo]) -> Option<&AudioDeviceInfo> {
+    devices.iter().find(|device| {
+        device.is_output
+            && !is_virtual(&device.id)
+            && CABLE_NAME_PATTERNS
+                .iter()
+                .any(|pattern| device.name.to_lowercase().contains(&pattern.to_lowercase()))
+    })
+}
+
+/// Device list entry for the virtual output, if this machine can provide it
+pub fn device_info(devices: &[AudioDeviceInfo]) -> Option<AudioDeviceInfo> {
+    if !backend_available(devices) {
+        return None;
+    }
+    Some(AudioDeviceInfo {
+        id: VIRTUAL_DEVICE_ID.to_string(),
+        name: format!("{} (virtual, for OBS)", VIRTUAL_DEVICE_NAME),
+        is_input: false,
+        is_output: true,
+        is_default: false,
+        sample_rates: vec![DEFAULT_SAMPLE_RATE],
+        channels: vec![DEFAULT_CHANNELS],
+        is_loopback: false,
+        capture_mode: None,
+        playback_mode: None,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn backend_available(_devices: &[AudioDeviceInfo]) -> bool {
+    pulse::is_available()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn backend_available(devices: &[AudioDeviceInfo]) -> bool {
+    find_virtual_cable(devices).is_some()
+}
+
+/// Module indices of null sinks left behind by an earlier run
+/// (`pactl list short modules` output)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn stale_sink_modules(modules: &str, sink_name: &str) -> Vec<u32> {
+    let argument = format!("sink_name={}", sink_name);
+    modules
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let index = fields.next()?.trim().parse().ok()?;
+            let module = fields.next()?;
+            let arguments = fields.next().unwrap_or_default();
+            (module == "module-null-sink" && arguments.split_whitespace().any(|arg| arg == argument))
+                .then_some(index)
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+mod pulse {
+    use parking_lot::Mutex;
+    use std::process::{Command, Stdio};
+    use std::sync::Arc;
+
+    use super::{stale_sink_modules, VIRTUAL_DEVICE_NAME};
+    use crate::audio::clock::ClockSkewMonitor;
+    use crate::audio::mixer::MixerInputs;
+    use crate::audio::pipe::PipeOutput;
+    use crate::error::AudioError;
+
+    /// Sink name in PipeWire/PulseAudio
+    const SINK_NAME: &str = "lan_audio";
+
+    /// Latency requested from `pacat`
+    const LATENCY_MS: u32 = 20;
+
+    /// Whether a PulseAudio-compatible server is reachable
+    pub(super) fn is_available() -> bool {
+        Command::new("pactl")
+            .arg("info")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+
+    fn pactl(args: &[&str]) -> Result<String, AudioError> {
+        let output = Command::new("pactl")
+            .args(args)
+            .output()
+            .map_err(|e| AudioError::DeviceNotFound(format!("pactl not available: {}", e)))?;
+        if !output.status.success() {
+            return Err(AudioError::StreamError(format!(
+                "pactl {} failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Remove sinks of a previous run that did not shut down cleanly
+    fn unload_stale_sinks() {
+        let Ok(modules) = pactl(&["list", "short", "modules"]) else {
+            return;
+        };
+        for index in stale_sink_modules(&modules, SINK_NAME) {
+            let _ = pactl(&["unload-module", &index.to_string()]);
+        }
+    }
+
+    /// Null sink fed by a `pacat` process from the mixer
+    pub(crate) struct VirtualOutput {
+        module: u32,
+        /// Taken on drop so `pacat` exits before its sink is removed
+        output: Option<PipeOutput>,
+    }
+
+    impl VirtualOutput {
+        /// Create the virtual sink and start feeding it from `inputs`
+        pub(crate) fn start(
+            sample_rate: u32,
+            channels: u16,
+            inputs: Arc<Mutex<MixerInputs>>,
+        ) -> Result<Self, AudioError> {
+            unload_stale_sinks();
+
+            let module = pactl(&[
+                "load-module",
+                "module-null-sink",
+                &format!("sink_name={}", SINK_NAME),
+                "format=float32le",
+                &format!("rate={}", sample_rate),
+                &format!("channels={}", channels),
+                &format!("sink_properties=device.description={}", VIRTUAL_DEVICE_NAME),
+            ])?;
+            let module: u32 = module
+                .trim()
+                .parse()
+                .map_err(|_| AudioError::StreamError(format!("unexpected pactl output: {}", module.trim())))?;
+
+            let mut pacat = Command::new("pacat");
+            pacat.args([
+                "--playback",
+                "--raw",
+                &format!("--device={}", SINK_NAME),
+                "--format=float32le",
+                &format!("--rate={}", sample_rate),
+                &format!("--channels={}", channels),
+                &format!("--latency-msec={}", LATENCY_MS),
+                "--client-name=lan-audio-streamer",
+            ]);
+            let output = match PipeOutput::spawn(pacat, "playback-virtual".to_string(), sample_rate, channels, inputs) {
+                Ok(output) => output,
+                Err(e) => {
+                    let _ = pactl(&["unload-module", &module.to_string()]);
+                    return Err(e);
+                }
+            };
+
+            tracing::info!("Virtual output {} created (sink {})", VIRTUAL_DEVICE_NAME, SINK_NAME);
+            Ok(Self {
+                module,
+                output: Some(output),
+            })
+        }
+
+        /// Clock skew monitor of the virtual stream
+        pub(crate) fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+            self.output.as_ref().expect("output lives until drop").clock_monitor()
+        }
+    }
+
+    impl Drop for VirtualOutput {
+        fn drop(&mut self) {
+            drop(self.output.take());
+            if let Err(e) = pactl(&["unload-module", &self.module.to_string()]) {
+                tracing::warn!("Failed to remove virtual output: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod cable {
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    use super::find_virtual_cable;
+    use crate::audio::clock::ClockSkewMonitor;
+    use crate::audio::device::list_devices;
+    use crate::audio::mixer::MixerInputs;
+    use crate::audio::playback::AudioPlayback;
+    use crate::error::AudioError;
+
+    /// Output stream into an installed virtual cable
+    pub(crate) struct VirtualOutput {
+        playback: AudioPlayback,
+    }
+
+    impl VirtualOutput {
+        /// Open the virtual cable and start feeding it from `inputs`
+        pub(crate) fn start(
+            sample_rate: u32,
+            channels: u16,
+            inputs: Arc<Mutex<MixerInputs>>,
+        ) -> Result<Self, AudioError> {
+            let devices = list_devices();
+            let cable = find_virtual_cable(&devices).ok_or_else(|| {
+                AudioError::DeviceNotFound(
+                    "no virtual audio cable installed (VB-Cable or VoiceMeeter)".to_string(),
+                )
+            })?;
+
+            let mut playback = AudioPlayback::mixed(0, &cable.id, Some(sample_rate), Some(channels), None, inputs)?;
+            playback.start()?;
+            tracing::info!("Virtual output uses {}", cable.name);
+            Ok(Self { playback })
+        }
+
+        /// Clock skew monitor of the cable stream
+        pub(crate) fn clock_monitor(&self) -> &Arc<ClockSkewMonitor> {
+            self.playback.clock_monitor()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(id: &str, name: &str) -> AudioDeviceInfo {
+        AudioDeviceInfo {
+            id: id.to_string(),
+            name: name.to_string(),
+            is_input: false,
+            is_output: true,
+            is_default: false,
+            sample_rates: vec![48000],
+            channels: vec![2],
+            is_loopback: false,
+            capture_mode: None,
+            playback_mode: None,
+        }
+    }
+
+    #[test]
+    fn test_find_virtual_cable() {
+        let devices = vec![
+            output("output:Speakers", "Speakers (Realtek(R) Audio)"),
+            output("output:CABLE Input (VB-Audio Virtual Cable)", "CABLE Input (VB-Audio Virtual Cable)"),
+        ];
+        let cable = find_virtual_cable(&devices).unwrap();
+        assert!(cable.id.contains("CABLE Input"));
+
+        assert!(find_virtual_cable(&devices[..1]).is_none());
+        // The virtual entry itself never counts as a cable
+        let listed = vec![output(VIRTUAL_DEVICE_ID, "LAN-Audio (virtual cable)")];
+        assert!(find_virtual_cable(&listed).is_none());
+        assert!(is_virtual(VIRTUAL_DEVICE_ID));
+    }
+
+    #[test]
+    fn test_stale_sink_modules() {
+        let modules = "\
+22\tmodule-null-sink\tsink_name=lan_audio format=float32le rate=48000 channels=2\t
+23\tmodule-null-sink\tsink_name=other\t
+24\tmodule-native-protocol-unix\t\t
+";
+        assert_eq!(stale_sink_modules(modules, "lan_audio"), vec![22]);
+        assert!(stale_sink_modules("", "lan_audio").is_empty());
+    }
+}
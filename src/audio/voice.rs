@@ -0,0 +1,420 @@
+//! Voice pre-processing on capture
+//!
+//! Voice tracks can clean up their input before it is encoded: a
+//! high-pass filter (`TrackConfig::high_pass_hz`) removes rumble, stand
+//! noise and plosives below the voice, and noise suppression
+//! (`TrackConfig::noise_suppression`) attenuates steady background noise
+//! such as fans or hum. The suppressor works on overlapping windows of
+//! [`WINDOW`] samples: it takes the noise spectrum as the minimum of each
+//! band's smoothed power over about the last second (minimum statistics;
+//! speech has pauses, steady noise does not), and scales every band by
+//! how far it stands above the noise (spectral subtraction), never below
+//! [`NOISE_FLOOR_DB`]. Suppression starts after the first second, and the
+//! track is delayed by one window (about 11 ms at 48 kHz).
+
+use std::f64::consts::PI;
+
+use crate::audio::dsp::{Biquad, BiquadState};
+use crate::protocol::{EqBand, EqBandKind, TrackConfig, TrackType};
+
+/// Analysis window of the noise suppressor in samples
+pub const WINDOW: usize = 512;
+
+/// Attenuation limit of noise suppression
+pub const NOISE_FLOOR_DB: f32 = -20.0;
+
+/// Lowest and highest high-pass corner frequency in Hz
+pub const HIGH_PASS_RANGE: std::ops::RangeInclusive<f32> = 20.0..=500.0;
+
+/// Windows overlap by half
+const HOP: usize = WINDOW / 2;
+
+/// Frequency bands of one window
+const BINS: usize = WINDOW / 2 + 1;
+
+/// Smoothing of the band power between windows
+const POWER_SMOOTHING: f32 = 0.8;
+
+/// The noise minimum is kept over this many sub-windows...
+const SUBWINDOWS: usize = 8;
+
+/// ...of this many windows each (about 1 s in all at 48 kHz)
+const SUBWINDOW_LENGTH: usize = 24;
+
+/// Over-subtraction: the minimum undershoots the average noise power
+const OVER_SUBTRACTION: f32 = 5.0;
+
+/// Gain decay per window after a band falls back into the noise
+const GAIN_RELEASE: f32 = 0.8;
+
+/// Whether a track config asks for any voice processing
+pub fn is_enabled(config: &TrackConfig) -> bool {
+    config.track_type == TrackType::Voice && (config.high_pass_hz > 0.0 || config.noise_suppression)
+}
+
+/// Capture processing chain of one voice track: high-pass, then noise
+/// suppression
+pub struct VoiceProcessor {
+    sample_rate: u32,
+    channels: usize,
+    high_pass_hz: f32,
+    /// Filter and one state per channel
+    high_pass: Option<(Biquad, Vec<BiquadState>)>,
+    suppressor: Option<NoiseSuppressor>,
+}
+
+impl VoiceProcessor {
+    /// Chain of a track with voice processing on (None when off)
+    pub fn for_track(config: &TrackConfig, sample_rate: u32, channels: usize) -> Option<Self> {
+        is_enabled(config).then(|| {
+            let channels = channels.max(1);
+            let mut processor = Self {
+                sample_rate,
+                channels,
+                high_pass_hz: 0.0,
+                high_pass: None,
+                suppressor: config.noise_suppression.then(|| NoiseSuppressor::new(channels)),
+            };
+            processor.set_high_pass(config.high_pass_hz);
+            processor
+        })
+    }
+
+    /// Follow a changed track config, keeping the state of running stages
+    pub fn reconfigure(processor: &mut Option<Self>, config: &TrackConfig, sample_rate: u32, channels: usize) {
+        match processor.as_mut().filter(|_| is_enabled(config)) {
+            Some(current) => {
+                current.set_high_pass(config.high_pass_hz);
+                if config.noise_suppression != current.suppressor.is_some() {
+                    current.suppressor = config.noise_suppression.then(|| NoiseSuppressor::new(current.channels));
+                }
+            }
+            None => *processor = Self::for_track(config, sample_rate, channels),
+        }
+    }
+
+    /// Change the high-pass corner frequency (0 turns the filter off)
+    pub fn set_high_pass(&mut self, frequency_hz: f32) {
+        if frequency_hz == self.high_pass_hz {
+            return;
+        }
+        self.high_pass_hz = frequency_hz;
+        self.high_pass = (frequency_hz > 0.0).then(|| {
+            let band = EqBand {
+                kind: EqBandKind::HighPass,
+                frequency_hz,
+                gain_db: 0.0,
+                q: std::f32::consts::FRAC_1_SQRT_2,
+            };
+            (Biquad::new(&band, self.sample_rate), vec![BiquadState::default(); self.channels])
+        });
+    }
+
+    /// Process an interleaved block in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if let Some((filter, states)) = &mut self.high_pass {
+            for frame in samples.chunks_mut(self.channels) {
+                for (sample, state) in frame.iter_mut().zip(states.iter_mut()) {
+                    *sample = filter.process(state, *sample as f64) as f32;
+                }
+            }
+        }
+
+        if let Some(suppressor) = &mut self.suppressor {
+            suppressor.process(samples);
+        }
+    }
+}
+
+/// Spectral subtraction on overlapping windows, separately per channel
+struct NoiseSuppressor {
+    channels: usize,
+    fft: Fft,
+    /// Square-root Hann window, for analysis and synthesis
+    window: Vec<f32>,
+    /// Position within the current hop
+    position: usize,
+    floor: f32,
+    states: Vec<ChannelState>,
+    /// Spectrum of the window being processed
+    re: Vec<f32>,
+    im: Vec<f32>,
+}
+
+#[derive(Clone)]
+struct ChannelState {
+    /// Last hop followed by the one being filled
+    input: Vec<f32>,
+    /// Processed hop being played out
+    output: Vec<f32>,
+    /// Second half of the last processed window
+    overlap: Vec<f32>,
+    /// Smoothed power of every band
+    power: Vec<f32>,
+    /// Minimum power of every band in the current sub-window
+    minimum: Vec<f32>,
+    /// `SUBWINDOWS` minima of every band (zero until measured)
+    minima: Vec<f32>,
+    /// Windows in the current sub-window, and the slot it goes to
+    windows: usize,
+    slot: usize,
+    gains: Vec<f32>,
+    primed: bool,
+}
+
+impl NoiseSuppressor {
+    fn new(channels: usize) -> Self {
+        let state = ChannelState {
+            input: vec![0.0; WINDOW],
+            output: vec![0.0; HOP],
+            overlap: vec![0.0; HOP],
+            power: vec![0.0; BINS],
+            minimum: vec![f32::MAX; BINS],
+            minima: vec![0.0; BINS * SUBWINDOWS],
+            windows: 0,
+            slot: 0,
+            gains: vec![1.0; BINS],
+            primed: false,
+        };
+
+        Self {
+            channels,
+            fft: Fft::new(WINDOW),
+            window: (0..WINDOW).map(|i| (PI * i as f64 / WINDOW as f64).sin() as f32).collect(),
+            position: 0,
+            floor: 10f32.powf(NOISE_FLOOR_DB / 20.0),
+            states: vec![state; channels],
+            re: vec![0.0; WINDOW],
+            im: vec![0.0; WINDOW],
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.channels) {
+            for (sample, state) in frame.iter_mut().zip(self.states.iter_mut()) {
+                state.input[HOP + self.position] = *sample;
+                *sample = state.output[self.position];
+            }
+
+            self.position += 1;
+            if self.position == HOP {
+                self.position = 0;
+                let mut states = std::mem::take(&mut self.states);
+                for state in &mut states {
+                    self.process_window(state);
+                }
+                self.states = states;
+            }
+        }
+    }
+
+    fn process_window(&mut self, state: &mut ChannelState) {
+        let (re, im) = (&mut self.re, &mut self.im);
+        for ((re, im), (&sample, &window)) in re.iter_mut().zip(im.iter_mut()).zip(state.input.iter().zip(&self.window)) {
+            *re = sample * window;
+            *im = 0.0;
+        }
+        self.fft.transform(re, im, false);
+
+        for bin in 0..BINS {
+            let power = re[bin] * re[bin] + im[bin] * im[bin];
+            state.power[bin] = if state.primed {
+                POWER_SMOOTHING * state.power[bin] + (1.0 - POWER_SMOOTHING) * power
+            } else {
+                power
+            };
+            let smoothed = state.power[bin];
+            state.minimum[bin] = state.minimum[bin].min(smoothed);
+            let noise = state.minima[bin * SUBWINDOWS..][..SUBWINDOWS]
+                .iter()
+                .fold(state.minimum[bin], |noise, &minimum| noise.min(minimum));
+
+            let clean = 1.0 - OVER_SUBTRACTION * noise / (smoothed + f32::MIN_POSITIVE);
+            let gain = clean.max(0.0).sqrt().max(self.floor).max(state.gains[bin] * GAIN_RELEASE);
+            state.gains[bin] = gain;
+
+            re[bin] *= gain;
+            im[bin] *= gain;
+            if bin > 0 && bin < WINDOW / 2 {
+                re[WINDOW - bin] *= gain;
+                im[WINDOW - bin] *= gain;
+            }
+        }
+        state.primed = true;
+
+        state.windows += 1;
+        if state.windows == SUBWINDOW_LENGTH {
+            state.windows = 0;
+            for (bin, minimum) in state.minimum.iter_mut().enumerate() {
+                state.minima[bin * SUBWINDOWS + state.slot] = std::mem::replace(minimum, f32::MAX);
+            }
+            state.slot = (state.slot + 1) % SUBWINDOWS;
+        }
+
+        self.fft.transform(re, im, true);
+        let scale = 1.0 / WINDOW as f32;
+        for i in 0..HOP {
+            state.output[i] = state.overlap[i] + re[i] * self.window[i] * scale;
+            state.overlap[i] = re[HOP + i] * self.window[HOP + i] * scale;
+        }
+        state.input.copy_within(HOP.., 0);
+    }
+}
+
+/// Radix-2 complex FFT of a fixed size
+struct Fft {
+    /// e^(-2πik/N) for k below N/2
+    twiddles: Vec<(f32, f32)>,
+    /// Bit-reversed index of every position
+    reversed: Vec<usize>,
+}
+
+impl Fft {
+    fn new(size: usize) -> Self {
+        debug_assert!(size.is_power_of_two());
+        let bits = size.trailing_zeros();
+        Self {
+            twiddles: (0..size / 2)
+                .map(|k| {
+                    let angle = -2.0 * PI * k as f64 / size as f64;
+                    (angle.cos() as f32, angle.sin() as f32)
+                })
+                .collect(),
+            reversed: (0..size).map(|i| i.reverse_bits() >> (usize::BITS - bits)).collect(),
+        }
+    }
+
+    /// Transform in place; the inverse is not scaled by 1/N
+    fn transform(&self, re: &mut [f32], im: &mut [f32], inverse: bool) {
+        let size = re.len();
+        for (i, &j) in self.reversed.iter().enumerate() {
+            if j > i {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+
+        let mut length = 2;
+        while length <= size {
+            let half = length / 2;
+            let stride = size / length;
+            for start in (0..size).step_by(length) {
+                for k in 0..half {
+                    let (wr, wi) = self.twiddles[k * stride];
+                    let wi = if inverse { -wi } else { wi };
+                    let (a, b) = (start + k, start + k + half);
+                    let tr = re[b] * wr - im[b] * wi;
+                    let ti = re[b] * wi + im[b] * wr;
+                    re[b] = re[a] - tr;
+                    im[b] = im[a] - ti;
+                    re[a] += tr;
+                    im[a] += ti;
+                }
+            }
+            length *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice_config(high_pass_hz: f32, noise_suppression: bool) -> TrackConfig {
+        TrackConfig {
+            high_pass_hz,
+            noise_suppression,
+            ..TrackConfig::talkback("mic")
+        }
+    }
+
+    fn sine(frequency: f64, frames: usize, amplitude: f32) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency * i as f64 / 48_000.0).sin() as f32 * amplitude)
+            .collect()
+    }
+
+    /// Deterministic white noise in [-amplitude, amplitude]
+    fn noise(frames: usize, amplitude: f32) -> Vec<f32> {
+        let mut seed = 0x2545_F491_u32;
+        (0..frames)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                (seed as f32 / u32::MAX as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_high_pass_removes_rumble() {
+        let mut processor = VoiceProcessor::for_track(&voice_config(100.0, false), 48_000, 1).unwrap();
+
+        let mut rumble = sine(30.0, 48_000, 0.5);
+        processor.process(&mut rumble);
+        assert!(rms(&rumble[24_000..]) < 0.05);
+
+        let mut voice = sine(1000.0, 48_000, 0.5);
+        processor.process(&mut voice);
+        assert!((rms(&voice[24_000..]) - 0.5 / 2f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_suppressor_passes_clean_signal() {
+        let mut processor = VoiceProcessor::for_track(&voice_config(0.0, true), 48_000, 2).unwrap();
+
+        // Without noise the audio is only delayed by one window
+        let mut input = vec![0.0; 9_600];
+        input.extend(sine(1000.0, 9_600, 0.5));
+        let mut stereo: Vec<f32> = input.iter().flat_map(|&s| [s, -s]).collect();
+        processor.process(&mut stereo);
+        for i in 0..input.len() - WINDOW {
+            assert!((stereo[2 * (i + WINDOW)] - input[i]).abs() < 1e-3);
+            assert!((stereo[2 * (i + WINDOW) + 1] + input[i]).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_suppressor_attenuates_noise() {
+        let mut processor = VoiceProcessor::for_track(&voice_config(0.0, true), 48_000, 1).unwrap();
+
+        // Two seconds of steady noise are pushed towards the floor
+        let mut background = noise(96_000, 0.1);
+        processor.process(&mut background);
+        let floor = 10f32.powf(NOISE_FLOOR_DB / 20.0);
+        assert!(rms(&background[72_000..]) < 0.1 / 3f32.sqrt() * floor * 2.0);
+
+        // A burst of voice over the same noise still comes through
+        let mut burst: Vec<f32> = sine(500.0, 9_600, 0.5)
+            .iter()
+            .zip(noise(9_600, 0.1))
+            .map(|(tone, noise)| tone + noise)
+            .collect();
+        processor.process(&mut burst);
+        assert!((rms(&burst[4_800..]) - 0.5 / 2f32.sqrt()).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_voice_tracks_only() {
+        let music = TrackConfig {
+            high_pass_hz: 80.0,
+            ..TrackConfig::default()
+        };
+        assert!(!is_enabled(&music));
+        assert!(VoiceProcessor::for_track(&music, 48_000, 2).is_none());
+
+        let mut processor = VoiceProcessor::for_track(&voice_config(80.0, false), 48_000, 2);
+        assert!(processor.as_ref().is_some_and(|p| p.suppressor.is_none()));
+        VoiceProcessor::reconfigure(&mut processor, &voice_config(80.0, true), 48_000, 2);
+        assert!(processor.as_ref().is_some_and(|p| p.suppressor.is_some() && p.high_pass.is_some()));
+        VoiceProcessor::reconfigure(&mut processor, &voice_config(0.0, true), 48_000, 2);
+        assert!(processor.as_ref().is_some_and(|p| p.high_pass.is_none()));
+        VoiceProcessor::reconfigure(&mut processor, &voice_config(0.0, false), 48_000, 2);
+        assert!(processor.is_none());
+    }
+}
@@ -0,0 +1,573 @@
+//! WASAPI stream modes on Windows
+//!
+//! cpal opens every WASAPI stream in shared mode with the engine's default
+//! buffer. `AudioConfig::wasapi_low_latency` asks shared streams for a
+//! buffer of [`LOW_LATENCY_BUFFER_MS`] instead (the engine rounds it up to
+//! its smallest period), and `AudioConfig::wasapi_exclusive` opens capture
+//! and playback streams in exclusive mode: the device is driven directly at
+//! the stream format, bypassing the Windows mixer, with one device period
+//! per buffer (the minimum period in low-latency mode).
+//!
+//! A stream that cannot be opened in the requested mode falls back to the
+//! next one (exclusive, low-latency shared, shared), and the mode each
+//! device ended up in is reported with the device list
+//! (`AudioDeviceInfo::capture_mode` and `playback_mode`). Loopback capture
+//! is always shared. An exclusive stream holds the device: other
+//! applications, and other tracks on the same input, cannot open it.
+
+use cpal::{BufferSize, StreamConfig, SupportedBufferSize};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::audio::device::{self, AudioDevice, LOOPBACK_PREFIX};
+use crate::config::AudioBackend;
+use crate::error::AudioError;
+use crate::protocol::StreamMode;
+
+/// Buffer asked for by low-latency shared streams
+pub const LOW_LATENCY_BUFFER_MS: u32 = 3;
+
+static EXCLUSIVE: AtomicBool = AtomicBool::new(false);
+static LOW_LATENCY: AtomicBool = AtomicBool::new(false);
+
+/// Streams currently open: device ID (`input:`, `output:` or `loopback:`)
+/// and mode
+static OPEN_STREAMS: Mutex<Vec<(String, StreamMode)>> = Mutex::new(Vec::new());
+
+/// Select the modes streams are opened in (`AudioConfig::wasapi_exclusive`
+/// and `wasapi_low_latency`). Must be called before any stream is opened.
+pub fn configure(exclusive: bool, low_latency: bool) {
+    EXCLUSIVE.store(exclusive, Ordering::Relaxed);
+    LOW_LATENCY.store(low_latency, Ordering::Relaxed);
+}
+
+/// Whether device streams go through WASAPI
+pub fn is_active() -> bool {
+    cfg!(windows) && device::backend() == AudioBackend::Default
+}
+
+/// Whether a stream on `device` is tried in exclusive mode first
+pub fn use_exclusive(device: &AudioDevice) -> bool {
+    is_active() && EXCLUSIVE.load(Ordering::Relaxed) && !device.is_loopback
+}
+
+/// ID a stream on `device` is reported under in the device list
+pub fn stream_id(device: &AudioDevice) -> String {
+    if device.is_loopback {
+        format!("{}{}", LOOPBACK_PREFIX, device.name)
+    } else if device.is_output {
+        format!("output:{}", device.name)
+    } else {
+        format!("input:{}", device.name)
+    }
+}
+
+/// Buffer in frames a low-latency shared stream asks for (None when the
+/// mode is off or the stream has a fixed buffer size already)
+pub fn low_latency_buffer(config: &StreamConfig, supported: Option<SupportedBufferSize>) -> Option<u32> {
+    if !is_active() || !LOW_LATENCY.load(Ordering::Relaxed) || config.buffer_size != BufferSize::Default {
+        return None;
+    }
+    Some(low_latency_frames(supported.unwrap_or(SupportedBufferSize::Unknown), config.sample_rate.0))
+}
+
+/// [`LOW_LATENCY_BUFFER_MS`] in frames, within the range the device supports
+pub fn low_latency_frames(supported: SupportedBufferSize, sample_rate: u32) -> u32 {
+    let frames = (sample_rate * LOW_LATENCY_BUFFER_MS / 1000).max(1);
+    match supported {
+        SupportedBufferSize::Range { min, max } if min <= max => frames.clamp(min, max),
+        _ => frames,
+    }
+}
+
+fn describe(mode: StreamMode) -> &'static str {
+    match mode {
+        StreamMode::Shared => "shared",
+        StreamMode::LowLatency => "low-latency shared",
+        StreamMode::Exclusive => "exclusive",
+    }
+}
+
+/// Registration of an open stream in the device list, removed on drop
+#[must_use = "the stream disappears from the device list when the report drops"]
+pub struct ModeReport {
+    stream_id: String,
+    mode: StreamMode,
+}
+
+impl ModeReport {
+    pub fn new(stream_id: &str, mode: StreamMode) -> Self {
+        tracing::info!("Opened {} in {} mode", stream_id, describe(mode));
+        OPEN_STREAMS.lock().push((stream_id.to_string(), mode));
+        Self {
+            stream_id: stream_id.to_string(),
+            mode,
+        }
+    }
+}
+
+impl Drop for ModeReport {
+    fn drop(&mut self) {
+        let mut streams = OPEN_STREAMS.lock();
+        if let Some(index) = streams
+            .iter()
+            .position(|(id, mode)| *id == self.stream_id && *mode == self.mode)
+        {
+            streams.remove(index);
+        }
+    }
+}
+
+/// Mode of the streams open under a device ID (the most recently opened
+/// one if there are several)
+pub fn effective_mode(stream_id: &str) -> Option<StreamMode> {
+    OPEN_STREAMS
+        .lock()
+        .iter()
+        .rev()
+        .find(|(id, _)| id == stream_id)
+        .map(|&(_, mode)| mode)
+}
+
+/// Build a cpal stream with the low-latency buffer first, if one is
+/// asked for, then with the buffer of `config`, and with the default
+/// buffer if the device refuses a fixed size. `build` is called once per try.
+pub fn build_shared<S>(
+    stream_id: &str,
+    config: &StreamConfig,
+    low_latency: Option<u32>,
+    mut build: impl FnMut(&StreamConfig) -> Result<S, cpal::BuildStreamError>,
+) -> Result<(S, Option<ModeReport>), cpal::BuildStreamError> {
+    if let Some(frames) = low_latency {
+        let low_latency_config = StreamConfig {
+            buffer_size: BufferSize::Fixed(frames),
+            ..config.clone()
+        };
+        match build(&low_latency_config) {
+            Ok(stream) => return Ok((stream, Some(ModeReport::new(stream_id, StreamMode::LowLatency)))),
+            Err(e) => tracing::warn!(
+                "Low-latency mode unavailable on {}: {}, using the default buffer",
+                stream_id, e
+            ),
+        }
+    }
+
+    let stream = match build(config) {
+        Ok(stream) => stream,
+        Err(e) if config.buffer_size != BufferSize::Default => {
+            tracing::warn!(
+                "Buffer of {:?} unavailable on {}: {}, using the default buffer",
+                config.buffer_size, stream_id, e
+            );
+            build(&StreamConfig {
+                buffer_size: BufferSize::Default,
+                ..config.clone()
+            })?
+        }
+        Err(e) => return Err(e),
+    };
+    let report = is_active().then(|| ModeReport::new(stream_id, StreamMode::Shared));
+    Ok((stream, report))
+}
+
+/// Capture from `device_name` in exclusive mode until `running` clears.
+/// Fails when the device cannot be opened exclusively at `config` or
+/// stops, so the caller can fall back to a shared stream.
+pub fn capture_exclusive(
+    device_name: &str,
+    config: &StreamConfig,
+    running: &AtomicBool,
+    on_data: &mut dyn FnMut(&[f32]),
+) -> Result<(), AudioError> {
+    #[cfg(windows)]
+    {
+        let stream = imp::ExclusiveStream::open(device_name, false, config, LOW_LATENCY.load(Ordering::Relaxed))?;
+        let _report = ModeReport::new(&format!("input:{}", device_name), StreamMode::Exclusive);
+        stream.capture(running, on_data)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (device_name, config, running, on_data);
+        Err(AudioError::WasapiError("exclusive mode needs WASAPI on Windows".to_string()))
+    }
+}
+
+/// Play to `device_name` in exclusive mode until `running` clears (see
+/// [`capture_exclusive`])
+pub fn play_exclusive(
+    device_name: &str,
+    config: &StreamConfig,
+    running: &AtomicBool,
+    fill: &mut dyn FnMut(&mut [f32]),
+) -> Result<(), AudioError> {
+    #[cfg(windows)]
+    {
+        let stream = imp::ExclusiveStream::open(device_name, true, config, LOW_LATENCY.load(Ordering::Relaxed))?;
+        let _report = ModeReport::new(&format!("output:{}", device_name), StreamMode::Exclusive);
+        stream.render(running, fill)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (device_name, config, running, fill);
+        Err(AudioError::WasapiError("exclusive mode needs WASAPI on Windows".to_string()))
+    }
+}
+
+/// Sample format of an exclusive stream: the device's own, since the
+/// Windows mixer does not convert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    F32,
+    /// 32-bit container with `valid_bits` significant bits (24 or 32)
+    I32 { valid_bits: u16 },
+    I16,
+}
+
+impl SampleFormat {
+    /// Formats tried in exclusive mode, best first
+    pub const PREFERRED: [SampleFormat; 4] = [
+        SampleFormat::F32,
+        SampleFormat::I32 { valid_bits: 32 },
+        SampleFormat::I32 { valid_bits: 24 },
+        SampleFormat::I16,
+    ];
+
+    pub fn bytes(self) -> usize {
+        match self {
+            SampleFormat::F32 | SampleFormat::I32 { .. } => 4,
+            SampleFormat::I16 => 2,
+        }
+    }
+
+    /// Convert device samples to float, replacing the contents of `out`
+    pub fn decode(self, bytes: &[u8], out: &mut Vec<f32>) {
+        out.clear();
+        let chunks = bytes.chunks_exact(self.bytes());
+        match self {
+            SampleFormat::F32 => out.extend(chunks.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))),
+            SampleFormat::I32 { .. } => out.extend(
+                chunks.map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0),
+            ),
+            SampleFormat::I16 => out.extend(chunks.map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0)),
+        }
+    }
+
+    /// Convert float samples to device samples in `bytes`
+    pub fn encode(self, samples: &[f32], bytes: &mut [u8]) {
+        let chunks = bytes.chunks_exact_mut(self.bytes());
+        for (&sample, out) in samples.iter().zip(chunks) {
+            let sample = sample.clamp(-1.0, 1.0);
+            match self {
+                SampleFormat::F32 => out.copy_from_slice(&sample.to_le_bytes()),
+                SampleFormat::I32 { .. } => {
+                    out.copy_from_slice(&((sample as f64 * 2_147_483_647.0) as i32).to_le_bytes())
+                }
+                SampleFormat::I16 => out.copy_from_slice(&((sample * 32_767.0) as i16).to_le_bytes()),
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use cpal::{BufferSize, StreamConfig};
+    use windows::core::PCWSTR;
+    use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
+    use windows::Win32::Foundation::{CloseHandle, HANDLE, S_OK, WAIT_OBJECT_0};
+    use windows::Win32::Media::Audio::{
+        eCapture, eRender, EDataFlow, IAudioCaptureClient, IAudioClient, IAudioRenderClient, IMMDevice,
+        IMMDeviceEnumerator, MMDeviceEnumerator, AUDCLNT_BUFFERFLAGS_SILENT, AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED,
+        AUDCLNT_SHAREMODE_EXCLUSIVE, AUDCLNT_STREAMFLAGS_EVENTCALLBACK, DEVICE_STATE_ACTIVE, WAVEFORMATEX,
+        WAVEFORMATEXTENSIBLE, WAVEFORMATEXTENSIBLE_0,
+    };
+    use windows::Win32::Media::KernelStreaming::{KSDATAFORMAT_SUBTYPE_PCM, WAVE_FORMAT_EXTENSIBLE};
+    use windows::Win32::Media::Multimedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT;
+    use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_MULTITHREADED, STGM_READ};
+    use windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
+
+    use super::SampleFormat;
+    use crate::error::AudioError;
+
+    /// Longest wait for the device to ask for the next buffer
+    const EVENT_TIMEOUT_MS: u32 = 2000;
+
+    fn wasapi_error(e: windows::core::Error) -> AudioError {
+        AudioError::WasapiError(e.to_string())
+    }
+
+    /// An initialized, event-driven exclusive-mode client
+    pub struct ExclusiveStream {
+        client: IAudioClient,
+        event: HANDLE,
+        format: SampleFormat,
+        channels: usize,
+        /// Frames per buffer (one device period)
+        frames: u32,
+    }
+
+    impl ExclusiveStream {
+        pub fn open(name: &str, output: bool, config: &StreamConfig, low_latency: bool) -> Result<Self, AudioError> {
+            let sample_rate = config.sample_rate.0;
+            // SAFETY: COM is initialized on this thread before any call, and
+            // every pointer passed refers to a live local
+            unsafe {
+                let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+                let device = find_device(name, if output { eRender } else { eCapture })?;
+                let mut client: IAudioClient = device.Activate(CLSCTX_ALL, None).map_err(wasapi_error)?;
+
+                let (format, wave) = SampleFormat::PREFERRED
+                    .iter()
+                    .map(|&format| (format, wave_format(format, config)))
+                    .find(|(_, wave)| client.IsFormatSupported(AUDCLNT_SHAREMODE_EXCLUSIVE, &wave.Format, None) == S_OK)
+                    .ok_or_else(|| {
+                        AudioError::UnsupportedFormat(format!(
+                            "{} Hz, {} channels in exclusive mode",
+                            sample_rate, config.channels
+                        ))
+                    })?;
+
+                let (mut default_period, mut min_period) = (0i64, 0i64);
+                client
+                    .GetDevicePeriod(Some(&mut default_period), Some(&mut min_period))
+                    .map_err(wasapi_error)?;
+                // A fixed buffer of the track sets the period (100 ns units)
+                let period = match config.buffer_size {
+                    BufferSize::Fixed(frames) => (10_000_000 * frames as i64 / sample_rate as i64).max(min_period),
+                    BufferSize::Default if low_latency => min_period,
+                    BufferSize::Default => default_period,
+                };
+
+                let initialize = |client: &IAudioClient, period: i64| {
+                    client.Initialize(
+                        AUDCLNT_SHAREMODE_EXCLUSIVE,
+                        AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+                        period,
+                        period,
+                        &wave.Format,
+                        None,
+                    )
+                };
+                if let Err(e) = initialize(&client, period) {
+                    if e.code() != AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED {
+                        return Err(wasapi_error(e));
+                    }
+                    // The buffer has to be aligned: retry with the period of
+                    // the aligned size on a fresh client
+                    let aligned = client.GetBufferSize().map_err(wasapi_error)?;
+                    let period = (10_000_000.0 * aligned as f64 / sample_rate as f64).round() as i64;
+                    client = device.Activate(CLSCTX_ALL, None).map_err(wasapi_error)?;
+                    initialize(&client, period).map_err(wasapi_error)?;
+                }
+
+                let event = CreateEventW(None, false, false, PCWSTR::null()).map_err(wasapi_error)?;
+                if let Err(e) = client.SetEventHandle(event) {
+                    let _ = CloseHandle(event);
+                    return Err(wasapi_error(e));
+                }
+                let frames = client.GetBufferSize().map_err(wasapi_error)?;
+                tracing::debug!(
+                    "Exclusive stream on {}: {:?}, {} frames per period",
+                    name, format, frames
+                );
+
+                Ok(Self {
+                    client,
+                    event,
+                    format,
+                    channels: config.channels as usize,
+                    frames,
+                })
+            }
+        }
+
+        fn wait(&self) -> Result<(), AudioError> {
+            // SAFETY: the event handle stays open for the lifetime of self
+            if unsafe { WaitForSingleObject(self.event, EVENT_TIMEOUT_MS) } == WAIT_OBJECT_0 {
+                Ok(())
+            } else {
+                Err(AudioError::WasapiError("device stopped requesting buffers".to_string()))
+            }
+        }
+
+        pub fn render(&self, running: &AtomicBool, fill: &mut dyn FnMut(&mut [f32])) -> Result<(), AudioError> {
+            let samples_len = self.frames as usize * self.channels;
+            let bytes_len = samples_len * self.format.bytes();
+            let mut samples = vec![0.0f32; samples_len];
+
+            // SAFETY: GetBuffer returns room for `frames` frames of the
+            // stream format, valid until ReleaseBuffer
+            unsafe {
+                let render: IAudioRenderClient = self.client.GetService().map_err(wasapi_error)?;
+
+                // Start on a silent buffer
+                render.GetBuffer(self.frames).map_err(wasapi_error)?;
+                render
+                    .ReleaseBuffer(self.frames, AUDCLNT_BUFFERFLAGS_SILENT.0 as u32)
+                    .map_err(wasapi_error)?;
+                self.client.Start().map_err(wasapi_error)?;
+
+                let result = (|| {
+                    while running.load(Ordering::Relaxed) {
+                        self.wait()?;
+                        fill(&mut samples);
+                        let data = render.GetBuffer(self.frames).map_err(wasapi_error)?;
+                        self.format.encode(&samples, std::slice::from_raw_parts_mut(data, bytes_len));
+                        render.ReleaseBuffer(self.frames, 0).map_err(wasapi_error)?;
+                    }
+                    Ok(())
+                })();
+                let _ = self.client.Stop();
+                result
+            }
+        }
+
+        pub fn capture(&self, running: &AtomicBool, on_data: &mut dyn FnMut(&[f32])) -> Result<(), AudioError> {
+            let frame_bytes = self.channels * self.format.bytes();
+            let mut samples = Vec::with_capacity(self.frames as usize * self.channels);
+
+            // SAFETY: GetBuffer hands out `frames` frames of the stream
+            // format, valid until ReleaseBuffer
+            unsafe {
+                let capture: IAudioCaptureClient = self.client.GetService().map_err(wasapi_error)?;
+                self.client.Start().map_err(wasapi_error)?;
+
+                let result = (|| {
+                    while running.load(Ordering::Relaxed) {
+                        self.wait()?;
+                        while capture.GetNextPacketSize().map_err(wasapi_error)? > 0 {
+                            let mut data = std::ptr::null_mut();
+                            let (mut frames, mut flags) = (0u32, 0u32);
+                            capture
+                                .GetBuffer(&mut data, &mut frames, &mut flags, None, None)
+                                .map_err(wasapi_error)?;
+                            if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 {
+                                samples.clear();
+                                samples.resize(frames as usize * self.channels, 0.0);
+                            } else {
+                                let bytes = std::slice::from_raw_parts(data, frames as usize * frame_bytes);
+                                self.format.decode(bytes, &mut samples);
+                            }
+                            capture.ReleaseBuffer(frames).map_err(wasapi_error)?;
+                            on_data(&samples);
+                        }
+                    }
+                    Ok(())
+                })();
+                let _ = self.client.Stop();
+                result
+            }
+        }
+    }
+
+    impl Drop for ExclusiveStream {
+        fn drop(&mut self) {
+            // SAFETY: the handle came from CreateEventW and is closed once
+            let _ = unsafe { CloseHandle(self.event) };
+        }
+    }
+
+    /// Active endpoint of the data flow with the friendly name cpal reports
+    unsafe fn find_device(name: &str, flow: EDataFlow) -> Result<IMMDevice, AudioError> {
+        let enumerator: IMMDeviceEnumerator =
+            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL).map_err(wasapi_error)?;
+        let devices = enumerator
+            .EnumAudioEndpoints(flow, DEVICE_STATE_ACTIVE)
+            .map_err(wasapi_error)?;
+        for index in 0..devices.GetCount().map_err(wasapi_error)? {
+            let device = devices.Item(index).map_err(wasapi_error)?;
+            if friendly_name(&device).as_deref() == Some(name) {
+                return Ok(device);
+            }
+        }
+        Err(AudioError::DeviceNotFound(name.to_string()))
+    }
+
+    unsafe fn friendly_name(device: &IMMDevice) -> Option<String> {
+        let store = device.OpenPropertyStore(STGM_READ).ok()?;
+        let value = store.GetValue(&PKEY_Device_FriendlyName).ok()?;
+        let name = value.Anonymous.Anonymous.Anonymous.pwszVal;
+        if name.is_null() {
+            return None;
+        }
+        name.to_string().ok()
+    }
+
+    fn wave_format(format: SampleFormat, config: &StreamConfig) -> WAVEFORMATEXTENSIBLE {
+        let channels = config.channels;
+        let bits = format.bytes() as u16 * 8;
+        let block_align = channels * format.bytes() as u16;
+        let (valid_bits, sub_format) = match format {
+            SampleFormat::F32 => (32, KSDATAFORMAT_SUBTYPE_IEEE_FLOAT),
+            SampleFormat::I32 { valid_bits } => (valid_bits, KSDATAFORMAT_SUBTYPE_PCM),
+            SampleFormat::I16 => (16, KSDATAFORMAT_SUBTYPE_PCM),
+        };
+        WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_EXTENSIBLE as u16,
+                nChannels: channels,
+                nSamplesPerSec: config.sample_rate.0,
+                nAvgBytesPerSec: config.sample_rate.0 * block_align as u32,
+                nBlockAlign: block_align,
+                wBitsPerSample: bits,
+                cbSize: (std::mem::size_of::<WAVEFORMATEXTENSIBLE>() - std::mem::size_of::<WAVEFORMATEX>()) as u16,
+            },
+            Samples: WAVEFORMATEXTENSIBLE_0 {
+                wValidBitsPerSample: valid_bits,
+            },
+            // Front left, front right, centre... for the first channels
+            dwChannelMask: if channels < 32 { (1u32 << channels) - 1 } else { 0 },
+            SubFormat: sub_format,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_latency_frames() {
+        assert_eq!(low_latency_frames(SupportedBufferSize::Unknown, 48_000), 144);
+        assert_eq!(low_latency_frames(SupportedBufferSize::Range { min: 0, max: u32::MAX }, 44_100), 132);
+        // Hardware-offloaded streams have limits of their own
+        assert_eq!(low_latency_frames(SupportedBufferSize::Range { min: 480, max: 4800 }, 48_000), 480);
+        assert_eq!(low_latency_frames(SupportedBufferSize::Range { min: 16, max: 64 }, 48_000), 64);
+    }
+
+    #[test]
+    fn test_sample_formats_round_trip() {
+        let samples = [0.0f32, 0.5, -0.5, 0.999, -1.0];
+        for format in SampleFormat::PREFERRED {
+            let mut bytes = vec![0u8; samples.len() * format.bytes()];
+            format.encode(&samples, &mut bytes);
+            let mut decoded = Vec::new();
+            format.decode(&bytes, &mut decoded);
+            assert_eq!(decoded.len(), samples.len());
+            for (a, b) in samples.iter().zip(&decoded) {
+                assert!((a - b).abs() < 1e-4, "{:?}: {} vs {}", format, a, b);
+            }
+        }
+
+        // Out-of-range samples clip instead of wrapping around
+        let mut bytes = [0u8; 2];
+        SampleFormat::I16.encode(&[1.5], &mut bytes);
+        assert_eq!(i16::from_le_bytes(bytes), i16::MAX);
+    }
+
+    #[test]
+    fn test_reports_follow_open_streams() {
+        let id = "output:wasapi test device";
+        assert_eq!(effective_mode(id), None);
+        let shared = ModeReport::new(id, StreamMode::Shared);
+        let exclusive = ModeReport::new(id, StreamMode::Exclusive);
+        assert_eq!(effective_mode(id), Some(StreamMode::Exclusive));
+        assert_eq!(effective_mode("input:wasapi test device"), None);
+
+        drop(exclusive);
+        assert_eq!(effective_mode(id), Some(StreamMode::Shared));
+        drop(shared);
+        assert_eq!(effective_mode(id), None);
+    }
+}
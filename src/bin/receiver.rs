@@ -1,58 +1,137 @@
 //! Audio Receiver Application
 //!
-//! Receives audio streams from sender and outputs to virtual devices.
+//! Receives audio streams from sender and outputs to audio devices, or to a
+//! virtual device for OBS integration (`LAN_AUDIO_VIRTUAL_OUTPUT=1`).
+//! Tracks deleted in the web UI are unsubscribed at the sender, which then
+//! stops encoding and sending them. Latency probes from a sender in
+//! measurement mode are measured at the output, and with
+//! `LAN_AUDIO_PROBE_LOOPBACK=<input device>` also at an analog loopback.
 
 use anyhow::Result;
 use crossbeam_channel::bounded;
 use parking_lot::Mutex;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use std::time::{Duration, Instant};
 
 use lan_audio_streamer::{
     audio::{
-        buffer::{AudioFrame, JitterBuffer},
-        device::list_devices,
-        playback::NetworkPlayback,
+        buffer::{AudioFrame, JitterBuffer, JitterDelay},
+        device::{self, list_devices},
+        mixer::{MixerChannel, OutputMixer},
+        probe::LoopbackProbe,
+        scheduler::PlayoutScheduler,
+        virtual_output,
+        wasapi,
     },
-    codec::OpusDecoder,
-    config::AppConfig,
+    cli::{self, Cli, CliCommand, Mode},
+    codec::{decoder_channels, dred, fec::recover_previous_frame, new_decoder, plc::next_frame_scheduled, AudioDecoder},
+    config::{DeviceProfile, NetworkSimulation, PacketFormat, SoloMode, StatsConfig},
     constants::*,
     network::{
         receiver::{AudioReceiver, ReceivedPacket},
+        timesync::{media_time_us, SuspendDetector, TimeSync},
+        feedback::{LossReporter, TrackFeedback, FEEDBACK_INTERVAL},
+        handshake::{ConnectionEvent, HandshakeManager, HandshakePacket},
+        packet_log,
+        pcap,
+        pairing::Pairing,
+        qos,
+        subscription::{TrackChange, TrackSubscriber},
         discovery::{DiscoveryService, get_best_local_address, get_local_addresses},
     },
-    protocol::TrackConfig,
-    tracks::{TrackManager, TrackEvent},
+    profiling::{self, Stage},
+    protocol::{DropReason, TrackConfig},
+    recording::Recorder,
+    service,
+    tracks::{ActivityKind, TrackManager, TrackEvent},
     ui::WebServer,
 };
 
 /// Per-track receiver state
 struct TrackState {
-    decoder: OpusDecoder,
+    decoder: Box<dyn AudioDecoder>,
     jitter_buffer: JitterBuffer,
-    playback: Option<NetworkPlayback>,
+    /// Releases the jitter buffer's frames to the output by the stream clock
+    scheduler: PlayoutScheduler,
+    /// Track's input into the shared output stream of its device
+    playback: Option<MixerChannel>,
     packets_received: u64,
     packets_lost: u64,
     device_id: String,
-    channels: u16,
+    /// Loss accounting for feedback reports
+    loss_reporter: LossReporter,
+    /// Sender the track is received from (feedback destination)
+    source: Option<SocketAddr>,
+    /// Arrival of the last packet carrying audio (None after a silence marker)
+    last_arrival: Option<Instant>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let cli = Cli::parse(Mode::Recv);
+    let args = match &cli.command {
+        CliCommand::Recv(args) => args.clone(),
+        other => return Ok(cli::run_other(other)?),
+    };
+    
+    // Initialize logging (the journal or the event log when running as a service)
+    service::init_logging(args.as_service);
+    
+    if let Some(action) = args.service_action {
+        return Ok(service::run(action, cli.config_path().as_deref())?);
+    }
+    if args.as_service {
+        service::start_dispatcher()?;
+    }
+    let _signal_handle = service::spawn_signal_listener();
     
     tracing::info!("Starting LAN Audio Receiver");
     
-    // Load or create config
-    let config = AppConfig::default();
+    // Load or create config, then apply the command line
+    let config_store = Arc::new(cli.config_store()?);
+    let mut config = config_store.config();
+    config.stats = StatsConfig::from_env();
+    args.apply(&mut config);
+    config.apply_profile();
+    if config.profile == DeviceProfile::LowPower {
+        tracing::info!("Low-power profile: web UI off, stats every {} s", config.stats.interval_secs);
+    }
+    if config.network.psk.is_some() {
+        tracing::info!("Audio encryption enabled (pre-shared key)");
+        if config.network.allow_plaintext_tracks {
+            tracing::info!("Accepting tracks their sender marks as plaintext");
+        }
+    }
+    if config.network.packet_format != PacketFormat::default() {
+        tracing::info!("Packet format: {:?}", config.network.packet_format);
+    }
+    if packet_log::requested_by_env() {
+        config.network.debug_capture = true;
+    }
+    if config.network.debug_capture {
+        packet_log::set_enabled(true);
+        tracing::info!("Logging control packets at /api/debug/packets");
+    }
+    if let Some(ref path) = config.network.pcap {
+        pcap::start(path).map_err(|e| anyhow::anyhow!("pcap capture {}: {}", path.display(), e))?;
+        tracing::info!("Capturing packets to {} for Wireshark", path.display());
+    }
+    if let Some(simulation) = NetworkSimulation::from_env() {
+        config.network.simulate = simulation;
+    }
+    if config.network.simulate.is_active() {
+        tracing::warn!("Simulating a bad network on received audio: {}", config.network.simulate);
+    }
+    if let Some(ref path) = config.network.packet_dump {
+        tracing::info!("Dumping received packets to {} (play back with `replay`)", path.display());
+    }
+    if let Err(e) = device::set_backend(config.audio.backend) {
+        tracing::warn!("Audio backend {:?} unavailable: {}", config.audio.backend, e);
+    }
+    wasapi::configure(config.audio.wasapi_exclusive, config.audio.wasapi_low_latency);
     
     // List available output devices
     println!("\n=== Available Output Devices ===");
@@ -69,20 +148,49 @@ async fn main() -> Result<()> {
     println!();
     
     // Create track manager
-    let track_manager = Arc::new(TrackManager::new());
+    let track_manager = Arc::new(
+        TrackManager::new()
+            .with_meter_params(config.profile.meter_params())
+            .with_max_tracks(config.profile.max_tracks())
+            .with_solo_mode(config.audio.solo_mode)
+            .with_gap_threshold_ms(config.stats.gap_threshold_ms),
+    );
+    if config.audio.solo_mode == SoloMode::Pfl && config.audio.monitor_device.is_none() {
+        tracing::warn!("PFL solo mode without audio.monitor_device: soloed tracks won't be monitored");
+    }
     
     // Subscribe to track events BEFORE starting web UI
     let mut event_rx = track_manager.subscribe();
     
-    // Start web UI
-    let web_server = WebServer::new(
-        config.ui.clone(),
-        track_manager.clone(),
-        false, // is_receiver
-    );
-    let _web_handle = web_server.start_background();
+    // Master processing of outputs from the config file (applied by the event task)
+    for (device_id, dsp) in &config.audio.output_dsp {
+        if let Err(e) = track_manager.set_output_dsp(device_id, Some(dsp.clone())) {
+            tracing::warn!("Ignoring output DSP for {}: {}", device_id, e);
+        }
+    }
+    
+    // Recording of received audio (controlled from the web UI)
+    let recorder = Arc::new(Recorder::new(config.recordings_dir(), DEFAULT_SAMPLE_RATE));
+    tracing::info!("Recordings are saved to {}", recorder.dir().display());
+    
+    // Allowlist and one-time PIN of peers allowed to connect
+    let pairing = Arc::new(Pairing::new(&config.network.pairing, Some(config_store.clone())));
     
-    tracing::info!("Web UI available at http://{}:{}", config.ui.bind_address, config.ui.http_port);
+    // Start web UI
+    let _web_handle = config.ui.serves().then(|| {
+        let web_server = WebServer::new(
+            config.ui.clone(),
+            track_manager.clone(),
+            false, // is_receiver
+        )
+        .with_recorder(recorder.clone())
+        .with_pairing(pairing.clone())
+        .with_config_store(config_store.clone());
+        if config.ui.enabled {
+            tracing::info!("Web UI available at http://{}:{}", config.ui.bind_address, config.ui.http_port);
+        }
+        web_server.start_background()
+    });
     
     // Display local network addresses for user reference
     println!("\n=== Local Network Addresses ===");
@@ -96,10 +204,17 @@ async fn main() -> Result<()> {
     
     // Start discovery service to announce our presence
     let mut discovery = DiscoveryService::new(false, config.network.udp_port, "Audio Receiver".to_string());
-    discovery.on_peer_discovered(|peer| {
+    discovery.set_mode(config.network.discovery_mode);
+    let track_manager_for_discovery = track_manager.clone();
+    discovery.on_peer_discovered(move |peer| {
         if peer.is_sender {
             tracing::info!("Discovered sender: {} at {}", peer.name, peer.audio_address());
             println!("Discovered sender: {} at {}", peer.name, peer.audio_address());
+            track_manager_for_discovery.timeline().record(
+                ActivityKind::PeerJoined,
+                None,
+                format!("{} ({})", peer.name, peer.audio_address()),
+            );
         }
     });
     if let Err(e) = discovery.start() {
@@ -112,25 +227,58 @@ async fn main() -> Result<()> {
     let (packet_tx, packet_rx) = bounded::<ReceivedPacket>(4096);
     
     // Create and start network receiver
+    // Clock sync with senders for end-to-end latency
+    let time_sync = Arc::new(TimeSync::new());
+    
+    // Subscriptions to the senders' tracks
+    let subscriber = Arc::new(TrackSubscriber::new());
+    subscriber.set_capabilities(config.receiver_capabilities());
+    
     let mut receiver = AudioReceiver::new();
     receiver.set_global_channel(packet_tx);
+    receiver.set_time_sync(time_sync.clone());
+    receiver.set_subscriber(subscriber.clone());
+    // Answer the senders' Hello; they stream only to a receiver that did
+    let handshake = Arc::new(HandshakeManager::new(
+        "Audio Receiver".to_string(),
+        config.network.udp_port,
+        config.handshake_capabilities(config.receiver_capabilities()),
+    ).with_pairing(pairing));
+    receiver.set_handshake(handshake.clone());
     receiver.start(config.network.clone())?;
     
     tracing::info!("Network receiver started on port {}", config.network.udp_port);
     
+    // Analog loopback input listening for the senders' latency probes
+    let mut loopback = config.stats.probe_loopback_device.as_deref().and_then(|device_id| {
+        match LoopbackProbe::start(device_id, DEFAULT_SAMPLE_RATE) {
+            Ok(probe) => {
+                tracing::info!("Listening for latency probes on {}", device_id);
+                Some(probe)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to open loopback input {}: {}", device_id, e);
+                None
+            }
+        }
+    });
+    
     // Track states - shared mutable map for runtime reconfiguration
     let track_states: Arc<Mutex<HashMap<u8, TrackState>>> = Arc::new(Mutex::new(HashMap::new()));
     let track_states_for_events = track_states.clone();
     
+    // One output stream per device, shared by all tracks playing to it
+    let output_mixer = OutputMixer::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS);
+    let output_mixer_for_events = output_mixer.clone();
+    
     // Set of manually deleted tracks - don't auto-recreate these
     let deleted_tracks: Arc<Mutex<HashSet<u8>>> = Arc::new(Mutex::new(HashSet::new()));
     let deleted_tracks_for_events = deleted_tracks.clone();
+    let track_manager_for_events = track_manager.clone();
+    let subscriber_for_events = subscriber.clone();
     
     // Get default output device
-    let default_output = devices.iter()
-        .find(|d| d.is_output && d.is_default)
-        .map(|d| d.id.clone())
-        .unwrap_or_default();
+    let default_output = virtual_output::default_output_id(&devices);
     
     tracing::info!("Default output device: {}", default_output);
     
@@ -148,60 +296,81 @@ async fn main() -> Result<()> {
                             
                             let mut states = track_states_for_events.lock();
                             if let Some(state) = states.get_mut(&track_id) {
-                                // Stop old playback
-                                if let Some(ref mut old_playback) = state.playback {
-                                    old_playback.stop();
+                                // Detach from the old device first (closes its stream if unused)
+                                if state.playback.take().is_some() {
                                     tracing::info!("Stopped old playback for track {}", track_id);
                                 }
                                 
-                                // Create new playback with new device
-                                let channels = state.channels;
-                                match NetworkPlayback::new(
-                                    track_id,
-                                    &new_device,
-                                    Some(DEFAULT_SAMPLE_RATE),
-                                    Some(channels),
-                                    32, // jitter buffer size
-                                    2,  // min delay
-                                ) {
-                                    Ok(mut p) => {
-                                        if let Err(e) = p.start() {
-                                            tracing::error!(
-                                                "Failed to start playback for track {} on {}: {}",
-                                                track_id, new_device, e
-                                            );
-                                            state.playback = None;
-                                        } else {
-                                            tracing::info!(
-                                                "Successfully switched track {} to output device {}",
-                                                track_id, new_device
-                                            );
-                                            state.playback = Some(p);
-                                            state.device_id = new_device.clone();
+                                let buffer_frames = track_manager_for_events
+                                    .get_track(track_id)
+                                    .and_then(|t| t.config.buffer_frames);
+                                let channels = state.decoder.channels();
+                                match output_mixer_for_events.attach_channels(track_id, &new_device, buffer_frames, channels) {
+                                    Ok(channel) => {
+                                        tracing::info!(
+                                            "Successfully switched track {} to output device {}",
+                                            track_id, new_device
+                                        );
+                                        if let Some(track) = track_manager_for_events.get_track(track_id) {
+                                            channel.set_channel_map(track.config.channel_map.clone());
+                                            channel.set_copies(&track.config.output_devices, track.config.buffer_frames);
                                         }
+                                        state.playback = Some(channel);
+                                        state.device_id = new_device.clone();
                                     }
                                     Err(e) => {
                                         tracing::error!(
-                                            "Failed to create playback for track {} on {}: {}",
+                                            "Failed to start playback for track {} on {}: {}",
                                             track_id, new_device, e
                                         );
-                                        state.playback = None;
+                                    }
+                                }
+                            }
+                        }
+                        
+                        TrackEvent::OutputDspChanged(device_id) => {
+                            tracing::info!("Output DSP of {} changed", device_id);
+                            output_mixer_for_events.set_dsp(&device_id, track_manager_for_events.output_dsp(&device_id));
+                        }
+                        
+                        TrackEvent::ConfigUpdated(track_id) => {
+                            // Apply the jitter delay, channel map and further outputs to the running playback
+                            let config = track_manager_for_events.get_track(track_id).map(|t| t.config.clone());
+                            if let Some(config) = config {
+                                let mut states = track_states_for_events.lock();
+                                if let Some(state) = states.get_mut(&track_id) {
+                                    let delay = JitterDelay::from_config(&config);
+                                    if state.jitter_buffer.delay() != delay {
+                                        state.jitter_buffer.set_delay(delay);
+                                        tracing::info!(
+                                            "Track {}: jitter buffer delay {} ms",
+                                            track_id,
+                                            state.jitter_buffer.delay_us() / 1000
+                                        );
+                                    }
+                                    if let Some(playback) = state.playback.as_ref() {
+                                        playback.set_copies(&config.output_devices, config.buffer_frames);
+                                        playback.set_channel_map(config.channel_map);
                                     }
                                 }
                             }
                         }
                         
                         TrackEvent::Removed(track_id) => {
-                            tracing::info!("Track {} removed by user, stopping playback...", track_id);
-                            
-                            // Add to deleted set so it won't be auto-recreated
-                            deleted_tracks_for_events.lock().insert(track_id);
+                            if subscriber_for_events.is_withdrawn(track_id) {
+                                // The sender removed it: take it again if it comes back
+                                tracing::info!("Track {} removed by the sender, stopping playback...", track_id);
+                            } else {
+                                tracing::info!("Track {} removed by user, stopping playback...", track_id);
+                                
+                                // Add to deleted set so it won't be auto-recreated
+                                // and stop the sender from sending it
+                                deleted_tracks_for_events.lock().insert(track_id);
+                                subscriber_for_events.exclude(track_id);
+                            }
                             
                             let mut states = track_states_for_events.lock();
-                            if let Some(mut state) = states.remove(&track_id) {
-                                if let Some(ref mut playback) = state.playback {
-                                    playback.stop();
-                                }
+                            if states.remove(&track_id).is_some() {
                                 tracing::info!("Playback stopped for track {}", track_id);
                             }
                         }
@@ -209,6 +378,7 @@ async fn main() -> Result<()> {
                         TrackEvent::Created(track_id) => {
                             // If user manually creates a track, remove from deleted set
                             deleted_tracks_for_events.lock().remove(&track_id);
+                            subscriber_for_events.include(track_id);
                             tracing::info!("Track {} created by user", track_id);
                         }
                         
@@ -224,12 +394,43 @@ async fn main() -> Result<()> {
         }
     });
     
+    // Tracks saved from the web UI keep their output device and settings
+    // for when their sender starts streaming
+    for track_config in &config.tracks {
+        let name = track_config.name.clone();
+        match track_manager.create_saved_track(track_config.clone(), &devices) {
+            Ok(track_id) => tracing::info!("Created saved track {} ({})", track_id, name),
+            Err(e) => tracing::warn!("Failed to create saved track {}: {}", name, e),
+        }
+    }
+    
+    // Save UI changes and watch the configuration file
+    let _watcher_handle = config_store.spawn_watcher();
+    let mut reload_rx = config_store.subscribe();
+    
     tracing::info!("Waiting for audio streams...");
     
     // Main receiving loop
     let mut last_stats_time = std::time::Instant::now();
+    let mut last_feedback_time = std::time::Instant::now();
+    let mut last_sync_check = std::time::Instant::now();
+    let mut suspend_detector = SuspendDetector::new();
+    let _mmcss = qos::register_thread(&config.network.qos);
     
-    loop {
+    while !service::shutdown_requested() {
+        // Woke up from sleep: buffered audio and clock offsets are stale
+        if let Some(gap) = suspend_detector.check() {
+            tracing::warn!("Resumed from sleep ({:.1}s pause), flushing buffers", gap.as_secs_f32());
+            time_sync.reset_all();
+            flush_tracks(&track_states, |_| true);
+        }
+        
+        // Senders that woke up from sleep restart their streams
+        for ip in time_sync.take_resyncs() {
+            let flushed = flush_tracks(&track_states, |source| source.ip() == ip);
+            tracing::info!("Sender {} resumed from sleep, flushed {} tracks", ip, flushed);
+        }
+        
         // Process received packets - drain the channel efficiently
         let mut processed_count = 0;
         const MAX_BATCH_SIZE: usize = 64; // Process in batches for better efficiency
@@ -242,17 +443,19 @@ async fn main() -> Result<()> {
                     
                     // Skip packets for deleted tracks
                     if deleted_tracks.lock().contains(&track_id) {
+                        track_manager.record_drops(track_id, DropReason::Deleted, 1);
                         continue;
                     }
                     
                     let mut states = track_states.lock();
                     
                     // Initialize track state if new
-                    if !states.contains_key(&track_id) {
+                    if let Entry::Vacant(entry) = states.entry(track_id) {
                         tracing::info!("New track {} detected, initializing...", track_id);
                         
-                        // Determine channel count from packet
-                        let channels = if packet.is_stereo { 2 } else { 1 };
+                        // Determine channel count from packet (and the catalog for surround)
+                        let track_channels = track_manager.get_track(track_id).map_or(0, |track| track.config.channels);
+                        let channels = decoder_channels(packet.is_stereo, track_channels);
                         
                         // Check if track already exists in manager (user may have pre-configured it)
                         let output_device = if let Some(track) = track_manager.get_track(track_id) {
@@ -265,40 +468,39 @@ async fn main() -> Result<()> {
                             default_output.clone()
                         };
                         
-                        // Create decoder
-                        let frame_size = (DEFAULT_SAMPLE_RATE as f32 * DEFAULT_FRAME_SIZE_MS / 1000.0) as usize;
-                        let decoder = match OpusDecoder::new(DEFAULT_SAMPLE_RATE, channels, frame_size) {
+                        // Create decoder for the frames the sender announced
+                        let frame_size_ms = track_manager.get_track(track_id).map_or(DEFAULT_FRAME_SIZE_MS, |track| track.config.frame_size_ms);
+                        let frame_size = (DEFAULT_SAMPLE_RATE as f32 * frame_size_ms / 1000.0) as usize;
+                        let decoder = match new_decoder(packet.codec, DEFAULT_SAMPLE_RATE, channels, frame_size) {
                             Ok(d) => d,
                             Err(e) => {
                                 tracing::error!("Failed to create decoder for track {}: {}", track_id, e);
+                                track_manager.record_drops(track_id, DropReason::DecodeError, 1);
                                 continue;
                             }
                         };
                         
-                        // Create jitter buffer (32 slots, 2 frame minimum delay)
-                        let jitter_buffer = JitterBuffer::new(32, 2);
+                        // Create jitter buffer (32 slots, 2 frame minimum delay) for the
+                        // announced frames; the frames received take over from there
+                        let mut jitter_buffer = JitterBuffer::with_frame_duration(32, 2, (frame_size_ms * 1000.0) as u64);
+                        if let Some(track) = track_manager.get_track(track_id) {
+                            jitter_buffer.set_delay(JitterDelay::from_config(&track.config));
+                        }
                         
-                        // Create playback (optional - may not have output device)
+                        // Attach to the device's shared output stream (optional - may not have output device)
                         let playback = if !output_device.is_empty() {
-                            match NetworkPlayback::new(
-                                track_id,
-                                &output_device,
-                                Some(DEFAULT_SAMPLE_RATE),
-                                Some(channels),
-                                32, // jitter buffer size
-                                2,  // min delay
-                            ) {
-                                Ok(mut p) => {
-                                    if let Err(e) = p.start() {
-                                        tracing::warn!("Failed to start playback for track {}: {}", track_id, e);
-                                        None
-                                    } else {
-                                        tracing::info!("Started playback for track {} on {}", track_id, output_device);
-                                        Some(p)
+                            let buffer_frames = track_manager.get_track(track_id).and_then(|t| t.config.buffer_frames);
+                            match output_mixer.attach_channels(track_id, &output_device, buffer_frames, channels) {
+                                Ok(channel) => {
+                                    tracing::info!("Started playback for track {} on {}", track_id, output_device);
+                                    if let Some(track) = track_manager.get_track(track_id) {
+                                        channel.set_channel_map(track.config.channel_map.clone());
+                                        channel.set_copies(&track.config.output_devices, track.config.buffer_frames);
                                     }
+                                    Some(channel)
                                 }
                                 Err(e) => {
-                                    tracing::warn!("Failed to create playback for track {}: {}", track_id, e);
+                                    tracing::warn!("Failed to start playback for track {}: {}", track_id, e);
                                     None
                                 }
                             }
@@ -315,49 +517,159 @@ async fn main() -> Result<()> {
                                 bitrate: DEFAULT_BITRATE,
                                 frame_size_ms: DEFAULT_FRAME_SIZE_MS,
                                 channels,
+                                codec: packet.codec,
                                 ..Default::default()
                             };
                             let _ = track_manager.create_track(track_config);
                         }
                         
-                        states.insert(track_id, TrackState {
+                        entry.insert(TrackState {
                             decoder,
                             jitter_buffer,
+                            scheduler: PlayoutScheduler::new(),
                             playback,
                             packets_received: 0,
                             packets_lost: 0,
                             device_id: output_device.clone(),
-                            channels,
+                            loss_reporter: LossReporter::new(),
+                            source: None,
+                            last_arrival: None,
                         });
                     }
                     
                     // Process packet
                     if let Some(state) = states.get_mut(&track_id) {
                         state.packets_received += 1;
+                        if packet.source.is_some() {
+                            state.source = packet.source;
+                        }
+                        
+                        // Gap in the packet stream (after a silence marker the sender pauses on purpose)
+                        if let Some(last_arrival) = state.last_arrival {
+                            track_manager.record_arrival_gap(track_id, packet.receive_time.saturating_duration_since(last_arrival));
+                        }
+                        state.last_arrival = (!packet.payload.is_empty()).then_some(packet.receive_time);
+                        
+                        // Volume and mute of the sending peer in the mixer, then the track volume and solo
+                        if let Some(ref playback) = state.playback {
+                            let peer_gain = state.source.map_or(1.0, |source| track_manager.peer_gain(source.ip()));
+                            playback.set_gain(peer_gain * track_manager.output_gain(track_id));
+                            let monitor = config.audio.monitor_device.as_deref().filter(|_| track_manager.is_pfl(track_id));
+                            playback.set_monitor(monitor);
+                        }
                         
                         // Update packet count in track manager
                         if let Some(track) = track_manager.get_track(track_id) {
                             track.increment_packets();
                         }
                         
-                        // Decode audio
-                        match state.decoder.decode(&packet.payload) {
+                        // The sender switched the track to another codec or channel count
+                        let track_channels = track_manager.get_track(track_id).map_or(0, |track| track.config.channels);
+                        let channels = decoder_channels(packet.is_stereo, track_channels);
+                        if state.decoder.codec() != packet.codec || state.decoder.channels() != channels {
+                            match new_decoder(packet.codec, DEFAULT_SAMPLE_RATE, channels, state.decoder.frame_size()) {
+                                Ok(decoder) => {
+                                    tracing::info!("Track {}: codec {:?}, {} channels", track_id, packet.codec, channels);
+                                    state.decoder = decoder;
+                                }
+                                Err(e) => tracing::warn!("Failed to create decoder for track {}: {}", track_id, e),
+                            }
+                        }
+                        
+                        // Restart marker: reset decoder and jitter buffer at this packet
+                        if packet.is_keyframe {
+                            if let Some(flushed) = state.jitter_buffer.restart_at(packet.sequence) {
+                                tracing::info!("Track {}: stream restart at packet {}", track_id, packet.sequence);
+                                track_manager.record_jitter_buffer_reset(track_id, packet.sequence);
+                                state.scheduler.reset();
+                                if let Err(e) = state.decoder.reset() {
+                                    tracing::warn!("Failed to reset decoder for track {}: {}", track_id, e);
+                                }
+                                for frame in flushed.into_iter().filter(|frame| !frame.is_silence_marker()) {
+                                    recorder.push(track_id, &frame.samples, frame.channels);
+                                    if let Some(ref playback) = state.playback {
+                                        playback.push_frame(frame);
+                                    }
+                                }
+                            }
+                        }
+                        
+                        // DRED: rebuild a burst of lost frames from the packet's history
+                        if let Err(e) = dred::recover_lost_frames(
+                            state.decoder.as_mut(),
+                            &mut state.jitter_buffer,
+                            &packet.payload,
+                            packet.sequence,
+                            packet.timestamp,
+                        ) {
+                            tracing::debug!("DRED recovery failed on track {}: {}", track_id, e);
+                        }
+                        
+                        // In-band FEC: rebuild the previous frame if it never arrived
+                        if packet.has_fec {
+                            if let Err(e) = recover_previous_frame(
+                                state.decoder.as_mut(),
+                                &mut state.jitter_buffer,
+                                &packet.payload,
+                                packet.sequence,
+                                packet.timestamp,
+                            ) {
+                                tracing::debug!("FEC recovery failed on track {}: {}", track_id, e);
+                            }
+                        }
+                        
+                        // Decode audio (a silence marker carries no payload)
+                        let silence_marker = packet.payload.is_empty();
+                        let decoded = {
+                            let _stage = profiling::stage(track_id, Stage::Decode);
+                            if silence_marker {
+                                Ok(Vec::new())
+                            } else {
+                                state.decoder.decode(&packet.payload)
+                            }
+                        };
+                        match decoded {
                             Ok(samples) => {
                                 // Update audio level
                                 if let Some(track) = track_manager.get_track(track_id) {
                                     track.update_level_atomic(&samples);
+                                    track.update_loudness(&samples, state.decoder.channels() as usize);
+                                    track.set_silent(silence_marker);
                                 }
+                                track_manager.check_clipping(track_id, &samples);
                                 
                                 // Create audio frame
-                                let frame = AudioFrame::new(
-                                    samples,
-                                    state.decoder.channels(),
-                                    packet.timestamp,
-                                    packet.sequence,
-                                );
+                                let mut frame = if silence_marker {
+                                    AudioFrame::silence_marker(state.decoder.channels(), packet.timestamp, packet.sequence)
+                                } else {
+                                    AudioFrame::new(samples, state.decoder.channels(), packet.timestamp, packet.sequence)
+                                };
+                                
+                                // Transit time for the sender's queuing delay estimate
+                                let arrival_us = media_time_us().saturating_sub(packet.receive_time.elapsed().as_micros() as u64);
+                                state.loss_reporter.note_arrival(packet.timestamp, arrival_us);
+                                
+                                // Latency probe: followed to the output on the synced clock
+                                if packet.is_probe {
+                                    frame.probe_us = packet
+                                        .source
+                                        .and_then(|source| time_sync.to_local_us(source.ip(), packet.timestamp));
+                                }
                                 
                                 // Insert into jitter buffer for reordering
-                                state.jitter_buffer.insert(frame);
+                                let _stage = profiling::stage(track_id, Stage::Playout);
+                                // A new frame duration from the sender re-counts the delay
+                                let frame_duration_us = state.jitter_buffer.frame_duration_us();
+                                if !state.jitter_buffer.insert(frame) {
+                                    track_manager.record_drops(track_id, DropReason::Late, 1);
+                                }
+                                if state.jitter_buffer.frame_duration_us() != frame_duration_us {
+                                    tracing::info!(
+                                        "Track {}: {} µs frames",
+                                        track_id,
+                                        state.jitter_buffer.frame_duration_us()
+                                    );
+                                }
                                 
                                 // Update jitter estimate from jitter buffer stats
                                 let jitter_stats = state.jitter_buffer.stats();
@@ -365,23 +677,39 @@ async fn main() -> Result<()> {
                                     // Jitter estimate is stored in microseconds in the buffer
                                     track.update_jitter(jitter_stats.jitter_us as u32);
                                     
-                                    // Calculate latency based on jitter buffer delay
-                                    // target_delay * frame_duration gives us the buffer-induced latency
-                                    let buffer_latency_us = jitter_stats.target_delay as u32 * 10000; // ~10ms per frame
+                                    // Latency of the jitter buffer: the frames ahead of this one
+                                    // plus the wait for the scheduler's next release
+                                    let buffer_latency_us =
+                                        state.scheduler.delay_us(&state.jitter_buffer, Instant::now()) as u32;
                                     track.update_latency(buffer_latency_us);
-                                }
-                                
-                                // Process jitter buffer and push ready frames to playback
-                                // This handles packet reordering before sending to audio output
-                                while let Some(ready_frame) = state.jitter_buffer.get_next() {
+                                    
+                                    // Capture-to-playback latency: packet age on the synced clock plus buffering
+                                    let e2e_latency_us = packet
+                                        .source
+                                        .and_then(|source| time_sync.age_us(source.ip(), packet.timestamp))
+                                        .map(|age| age + buffer_latency_us as u64);
+                                    track.update_e2e_latency(e2e_latency_us);
+                                    
+                                    // Output device clock vs host clock, probe latencies
                                     if let Some(ref playback) = state.playback {
-                                        playback.push_frame_direct(ready_frame);
+                                        track.update_clock_skew(playback.clock_skew_ppm());
+                                        track.update_output_gain_reduction(playback.gain_reduction_db());
+                                        track.update_playout_drift(playback.drift_ppm());
+                                        track.update_device_timing(playback.device_period_ms(), playback.device_latency_ms());
+                                        let probe = playback.probe_meter();
+                                        track.update_probe_latency(probe.output_latency_us(), probe.loopback_latency_us());
                                     }
                                 }
+                                if let Some(ref playback) = state.playback {
+                                    track_manager.record_drops(track_id, DropReason::Underflow, playback.take_underruns());
+                                }
+                                
+                                // Frames go to the output in play_due_frames when they are due
                             }
                             Err(e) => {
                                 tracing::warn!("Decode error on track {}: {}", track_id, e);
                                 state.packets_lost += 1;
+                                track_manager.record_drops(track_id, DropReason::DecodeError, 1);
                                 
                                 // Update lost packet count
                                 if let Some(track) = track_manager.get_track(track_id) {
@@ -399,8 +727,15 @@ async fn main() -> Result<()> {
             }
         }
         
+        let played = play_due_frames(&track_states, &track_manager, &recorder);
+        
+        // Probe chirp heard on the loopback input
+        if let Some(detected_us) = loopback.as_mut().and_then(|probe| probe.poll()) {
+            attribute_loopback(&track_states, detected_us);
+        }
+        
         // Adaptive sleep based on activity
-        if processed_count > 0 {
+        if processed_count > 0 || played {
             // Active streaming - minimal delay
             tokio::task::yield_now().await;
         } else {
@@ -408,32 +743,238 @@ async fn main() -> Result<()> {
             tokio::time::sleep(Duration::from_micros(250)).await;
         }
         
-        // Periodic stats
-        if last_stats_time.elapsed() >= Duration::from_secs(5) {
-            let _interval_duration = last_stats_time.elapsed();
+        // Loss reports back to senders (drives their adaptive bitrate)
+        if last_feedback_time.elapsed() >= FEEDBACK_INTERVAL {
+            last_feedback_time = std::time::Instant::now();
+            send_feedback(&track_states, &receiver);
+        }
+        
+        // Tracks the senders offer, changes to them and senders that said goodbye
+        if last_sync_check.elapsed() >= Duration::from_secs(1) {
+            last_sync_check = std::time::Instant::now();
+            for event in handshake.take_events() {
+                let ConnectionEvent::Departed(address) = event else {
+                    continue;
+                };
+                let flushed = flush_tracks(&track_states, |source| source == address);
+                tracing::info!("Sender {} disconnected, flushed {} tracks", address, flushed);
+            }
+            for (source, change) in subscriber.take_changes() {
+                match change {
+                    TrackChange::Updated(track) => {
+                        let frame_size_ms = track.frame_duration_us as f32 / 1000.0;
+                        if let Ok(true) = track_manager.apply_sender_config(track.track_id, &track.name, track.bitrate, track.channels, frame_size_ms) {
+                            tracing::info!("Track {} renamed or reconfigured by {}: {}", track.track_id, source, track.name);
+                        }
+                    }
+                    TrackChange::Removed(track_id) => {
+                        if track_manager.remove_track(track_id).is_ok() {
+                            tracing::info!("Track {} removed, {} no longer sends it", track_id, source);
+                        }
+                    }
+                }
+            }
+            for (source, track) in subscriber.subscribed() {
+                if track_manager.get_track(track.track_id).is_some() || deleted_tracks.lock().contains(&track.track_id) {
+                    continue;
+                }
+                let name = track.name.clone();
+                match track_manager.create_track(track.output_config(default_output.clone())) {
+                    Ok(track_id) => tracing::info!("Created track {} ({}) offered by {}", track_id, name, source),
+                    Err(e) => tracing::warn!("Failed to create track {} offered by {}: {}", name, source, e),
+                }
+            }
+        }
+        
+        // Edits of the configuration file
+        while let Ok(reload) = reload_rx.try_recv() {
+            reload.apply_output_dsp(&track_manager);
+            if reload.changed(|saved| &saved.network.allow_plaintext_tracks) {
+                let allow = reload.new.network.allow_plaintext_tracks || args.stream.allow_plaintext;
+                subscriber.set_accept_plaintext(config.network.psk.is_some() && allow);
+            }
+            reload.log_restart_needed();
+        }
+        
+        // Periodic stats (quiet mode leaves them to the web UI/API)
+        if config.stats.should_log() && last_stats_time.elapsed() >= config.stats.interval() {
             last_stats_time = std::time::Instant::now();
             
             let recv_stats = receiver.stats();
             tracing::info!(
-                "Receiver stats: {} packets, {} bytes, {} invalid",
+                "Receiver stats: {} packets, {} bytes, {} invalid, {} duplicates",
                 recv_stats.packets_received,
                 recv_stats.bytes_received,
-                recv_stats.invalid_packets
+                recv_stats.invalid_packets,
+                recv_stats.duplicate_packets
             );
+            if let Some(drops) = recv_stats.socket_drops {
+                tracing::info!(
+                    "Socket: {} dropped by the kernel, buffers {} KiB recv / {} KiB send",
+                    drops,
+                    recv_stats.recv_buffer_size / 1024,
+                    recv_stats.send_buffer_size / 1024
+                );
+            }
             
             let states = track_states.lock();
             for (track_id, state) in states.iter() {
                 let jitter_stats = state.jitter_buffer.stats();
                 tracing::info!(
-                    "Track {} stats: {} received, {} lost ({:.1}% loss), jitter buffer: {}/{}",
+                    "Track {} stats: {} received, {} lost ({:.1}% loss), {} recovered by FEC, {} concealed, jitter buffer: {}/{}",
                     track_id,
                     state.packets_received,
                     state.packets_lost,
                     jitter_stats.loss_rate() * 100.0,
+                    jitter_stats.recovered,
+                    jitter_stats.concealed,
                     jitter_stats.level,
                     jitter_stats.capacity
                 );
+                
+                if let Some(skew) = state.playback.as_ref().and_then(|p| p.clock_skew_ppm()) {
+                    tracing::info!("Track {} output clock skew: {:+.1} ppm", track_id, skew);
+                }
+                
+                if let Some(drift) = state.playback.as_ref().and_then(|p| p.drift_ppm()) {
+                    tracing::info!("Track {} sender clock drift correction: {:+.1} ppm", track_id, drift);
+                }
+                
+                if let Some(latency) = track_manager.get_track(*track_id).and_then(|t| t.e2e_latency_ms()) {
+                    tracing::info!("Track {} end-to-end latency: {:.1} ms", track_id, latency);
+                }
+                
+                if let Some(track) = track_manager.get_track(*track_id) {
+                    if let Some(latency) = track.probe_latency_ms() {
+                        match track.loopback_latency_ms() {
+                            Some(loopback) => tracing::info!(
+                                "Track {} probe latency: {:.1} ms to output, {:.1} ms through loopback",
+                                track_id, latency, loopback
+                            ),
+                            None => tracing::info!("Track {} probe latency: {:.1} ms to output", track_id, latency),
+                        }
+                    }
+                }
             }
         }
     }
+    
+    // Stopped by Ctrl+C, SIGTERM or the service manager: finish the
+    // recording and the configuration file before the process ends
+    if recorder.status().active {
+        recorder.stop();
+    }
+    discovery.stop();
+    config_store.flush();
+    pcap::stop();
+    tracing::info!("Receiver stopped");
+    service::stopped();
+    Ok(())
+}
+
+/// Reset jitter buffer and decoder of tracks received from a matching source
+fn flush_tracks(
+    track_states: &Arc<Mutex<HashMap<u8, TrackState>>>,
+    matches: impl Fn(SocketAddr) -> bool,
+) -> usize {
+    let mut flushed = 0;
+    for (track_id, state) in track_states.lock().iter_mut() {
+        if !state.source.is_some_and(&matches) {
+            continue;
+        }
+        
+        state.jitter_buffer.reset();
+        state.scheduler.reset();
+        if let Err(e) = state.decoder.reset() {
+            tracing::warn!("Failed to reset decoder for track {}: {}", track_id, e);
+        }
+        if let Some(ref playback) = state.playback {
+            playback.clock_monitor().reset();
+            playback.probe_meter().reset();
+        }
+        flushed += 1;
+    }
+    flushed
+}
+
+/// Push the frames of received tracks that are due to their outputs
+/// (lost slots are filled with decoder concealment)
+fn play_due_frames(
+    track_states: &Arc<Mutex<HashMap<u8, TrackState>>>,
+    track_manager: &TrackManager,
+    recorder: &Recorder,
+) -> bool {
+    let now = Instant::now();
+    let mut played = false;
+    for (&track_id, state) in track_states.lock().iter_mut() {
+        // Frames go out at the rate the output device consumes them
+        if let Some(ratio) = state.playback.as_ref().and_then(|playback| playback.clock_monitor().rate_ratio()) {
+            state.scheduler.set_output_rate(ratio);
+        }
+        // The scheduler owns the sender clock drift correction: the output
+        // reads the frames at it instead of estimating its own
+        if let Some(ref playback) = state.playback {
+            playback.set_rate_correction_ppm(state.scheduler.rate_correction_ppm());
+        }
+        while let Some(frame) = next_frame_scheduled(
+            state.decoder.as_mut(),
+            &mut state.jitter_buffer,
+            &mut state.scheduler,
+            now,
+        ) {
+            played = true;
+            // Silence follows: the output plays zeros without counting underruns
+            if frame.is_silence_marker() {
+                if let Some(ref playback) = state.playback {
+                    playback.set_silent();
+                }
+                continue;
+            }
+            recorder.push(track_id, &frame.samples, frame.channels);
+            match state.playback {
+                Some(ref playback) => {
+                    if playback.gain() == 0.0 {
+                        track_manager.record_drops(track_id, DropReason::Muted, 1);
+                    }
+                    playback.push_frame(frame);
+                }
+                None => track_manager.record_drops(track_id, DropReason::NoDevice, 1),
+            }
+        }
+    }
+    played
+}
+
+/// Match a chirp heard on the loopback input to the track whose probe
+/// played last
+fn attribute_loopback(track_states: &Arc<Mutex<HashMap<u8, TrackState>>>, detected_us: u64) {
+    let states = track_states.lock();
+    let meters: Vec<_> = states
+        .iter()
+        .filter_map(|(track_id, state)| state.playback.as_ref().map(|playback| (*track_id, playback.probe_meter())))
+        .collect();
+    if let Some((index, latency_us)) = LoopbackProbe::attribute(detected_us, meters.iter().map(|(_, meter)| *meter)) {
+        tracing::debug!("Track {}: probe heard on loopback after {:.1} ms", meters[index].0, latency_us as f32 / 1000.0);
+    }
+}
+
+/// Send per-track loss reports to the senders of each track
+fn send_feedback(track_states: &Arc<Mutex<HashMap<u8, TrackState>>>, receiver: &AudioReceiver) {
+    let mut reports: HashMap<SocketAddr, Vec<TrackFeedback>> = HashMap::new();
+    
+    for (track_id, state) in track_states.lock().iter_mut() {
+        let Some(source) = state.source else {
+            continue;
+        };
+        if let Some(report) = state.loss_reporter.report(*track_id, &state.jitter_buffer.stats()) {
+            reports.entry(source).or_default().push(report);
+        }
+    }
+    
+    for (source, reports) in reports {
+        let packet = HandshakePacket::feedback(0, &reports).serialize();
+        if let Err(e) = receiver.send_control(&packet, source) {
+            tracing::debug!("Failed to send feedback to {}: {}", source, e);
+        }
+    }
 }
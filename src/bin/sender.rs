@@ -12,45 +12,116 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use lan_audio_streamer::{
     audio::{
+        agc::Agc,
         buffer::{create_shared_buffer, SharedRingBuffer},
         capture::AudioCapture,
-        device::list_devices,
+        convert::convert_channels,
+        device::{self, list_devices},
+        pool,
+        probe::{ProbeInjector, PROBE_INTERVAL},
+        silence::{GateAction, SilenceGate},
+        simd,
+        voice::VoiceProcessor,
+        wasapi,
     },
-    codec::OpusEncoder,
-    config::{AppConfig, OpusConfig},
+    cli::{self, Cli, CliCommand, Mode},
+    codec::{dred, new_encoder, select_channels, select_codec, AdaptiveBitrate, AudioEncoder, BitrateDecision},
+    config::{parse_socket_addr, NetworkConfig, OpusConfig, PacketFormat, StatsConfig},
     constants::*,
+    logs,
     network::{
+        congestion::{CongestionController, TrackDemand},
+        handshake::{HandshakeManager, HandshakeState, PeerCapabilities, TrackInfo},
+        packet_log,
+        pcap,
+        pairing::Pairing,
+        qos,
+        rtp,
         sender::MultiTrackSender,
+        subscription::TrackCatalog,
+        timesync::{media_time_us, SuspendDetector},
+        feedback::{FeedbackInbox, TrackFeedback},
         discovery::{DiscoveryService, get_best_local_address, get_local_addresses},
     },
-    protocol::{TrackConfig, TrackType},
-    tracks::{TrackManager, TrackEvent},
+    profiling::{self, Stage},
+    protocol::{Codec, PacketFlags, RemoteCapabilities, TrackConfig, TrackPriority},
+    tracks::{auto, ActivityKind, TrackManager, TrackEvent},
     ui::WebServer,
 };
 
+/// How long the sender waits for the receiver to answer its Hello
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
 /// Per-track sender state including capture and encoder
 struct TrackSenderState {
     capture: AudioCapture,
     capture_buffer: SharedRingBuffer,
-    encoder: OpusEncoder,
+    encoder: Box<dyn AudioEncoder>,
     sample_buffer: Vec<f32>,
     sequence: u32,
+    /// Mark the next packet as a stream restart (fresh encoder)
+    restart_pending: bool,
+    /// Bitrate controller driven by receiver feedback
+    adaptive: AdaptiveBitrate,
+    /// Sending paused by congestion control
+    congestion_paused: bool,
+    /// Gain applied to the last frame (ducking while talkback is held)
+    gain: f32,
+    /// Latency probe chirps (measurement mode)
+    probe: Option<ProbeInjector>,
+    /// Silence suppression (None when off)
+    silence_gate: Option<SilenceGate>,
+    /// Voice processing on capture (None when off)
+    voice: Option<VoiceProcessor>,
+    /// Automatic gain control (None when off)
+    agc: Option<Agc>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse(Mode::Send);
+    let args = match &cli.command {
+        CliCommand::Send(args) => args.clone(),
+        other => return Ok(cli::run_other(other)?),
+    };
+    
     // Initialize logging
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
             std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
         ))
         .with(tracing_subscriber::fmt::layer())
+        .with(logs::layer())
         .init();
     
     tracing::info!("Starting LAN Audio Sender");
     
-    // Load or create config
-    let config = AppConfig::default();
+    // Load or create config, then apply the command line
+    let config_store = Arc::new(cli.config_store()?);
+    let mut config = config_store.config();
+    config.stats = StatsConfig::from_env();
+    args.stream.apply(&mut config);
+    if config.network.psk.is_some() {
+        tracing::info!("Audio encryption enabled (pre-shared key)");
+    }
+    if packet_log::requested_by_env() {
+        config.network.debug_capture = true;
+    }
+    if config.network.debug_capture {
+        packet_log::set_enabled(true);
+        tracing::info!("Logging control packets at /api/debug/packets");
+    }
+    if let Some(ref path) = config.network.pcap {
+        pcap::start(path).map_err(|e| anyhow::anyhow!("pcap capture {}: {}", path.display(), e))?;
+        tracing::info!("Capturing packets to {} for Wireshark", path.display());
+    }
+    if config.stats.latency_probe {
+        tracing::info!("Latency measurement mode: tracks carry a probe chirp every {:?}", PROBE_INTERVAL);
+    }
+    if let Err(e) = device::set_backend(config.audio.backend) {
+        tracing::warn!("Audio backend {:?} unavailable: {}", config.audio.backend, e);
+    }
+    wasapi::configure(config.audio.wasapi_exclusive, config.audio.wasapi_low_latency);
     
     // List available devices
     println!("\n=== Available Audio Devices ===");
@@ -81,10 +152,13 @@ async fn main() -> Result<()> {
         config.ui.clone(),
         track_manager.clone(),
         true, // is_sender
-    );
+    )
+    .with_config_store(config_store.clone());
     let _web_handle = web_server.start_background();
     
-    tracing::info!("Web UI available at http://{}:{}", config.ui.bind_address, config.ui.http_port);
+    if config.ui.enabled {
+        tracing::info!("Web UI available at http://{}:{}", config.ui.bind_address, config.ui.http_port);
+    }
     
     // Display local network addresses for user reference
     println!("\n=== Local Network Addresses ===");
@@ -97,15 +171,25 @@ async fn main() -> Result<()> {
     println!();
     
     // Get target address - automatic discovery or manual
-    let target_addr: SocketAddr = if let Some(arg) = std::env::args().nth(1) {
+    let mut redundant_paths = Vec::new();
+    let target_addr: SocketAddr = if let Some(target) = &args.target {
         // Manual address provided
-        arg.parse().expect("Invalid target address format. Use: IP:PORT")
+        parse_socket_addr(target, DEFAULT_UDP_PORT)
+            .ok_or_else(|| anyhow::anyhow!("Invalid target address {}. Use: IP[:PORT] or [IPv6]:PORT", target))?
+    } else if let Some(group) = config.network.multicast_socket_addr() {
+        // One stream for every receiver in the group
+        tracing::info!("Multicast streaming to group {}", group);
+        group
+    } else if let Some(addr) = config.network.remote_socket_addr() {
+        // Configured remote address
+        addr
     } else {
         // Automatic discovery
         tracing::info!("No target specified, starting automatic receiver discovery...");
         println!("Searching for receivers on the network...");
         
         let mut discovery = DiscoveryService::new(true, config.network.udp_port, "Audio Sender".to_string());
+        discovery.set_mode(config.network.discovery_mode);
         if let Err(e) = discovery.start() {
             tracing::warn!("Failed to start discovery service: {}", e);
         }
@@ -117,6 +201,8 @@ async fn main() -> Result<()> {
             let addr = peer.audio_address();
             tracing::info!("Discovered receiver: {} ({})", peer.name, addr);
             println!("Found receiver: {} at {}", peer.name, addr);
+            track_manager.timeline().record(ActivityKind::PeerJoined, None, format!("{} ({})", peer.name, addr));
+            redundant_paths = discovery.redundant_paths(&peer);
             discovery.stop();
             addr
         } else {
@@ -141,16 +227,44 @@ async fn main() -> Result<()> {
     
     tracing::info!("Target receiver: {}", target_addr);
     
-    // Create network sender
-    let mut network_sender = MultiTrackSender::new(&config.network, target_addr)?;
-    network_sender.start(config.network.clone())?;
+    // Pair with the receiver if a PIN was given, this proves it once and
+    // puts both sides on each other's allowlist
+    let pairing = Arc::new(Pairing::new(&config.network.pairing, Some(config_store.clone())));
+    if let Some(pin) = &args.pin {
+        pairing.set_peer_pin(target_addr, pin).map_err(|e| anyhow::anyhow!(e))?;
+    }
+    
+    // Handshake with the receiver, then create network sender
+    let handshake = Arc::new(HandshakeManager::new(
+        "Audio Sender".to_string(),
+        config.network.udp_port,
+        config.handshake_capabilities(PeerCapabilities::sender_only()),
+    ).with_pairing(pairing));
+    handshake_with(&handshake, target_addr).await?;
+    let feedback = Arc::new(FeedbackInbox::new());
+    // Tracks the receiver can subscribe to
+    let track_catalog = Arc::new(TrackCatalog::new());
+    let mut network_sender = start_network_sender(&config.network, target_addr, &feedback, &track_catalog, &handshake)?;
+    if !redundant_paths.is_empty() {
+        tracing::info!("Redundant paths to the receiver: {:?}", redundant_paths);
+        network_sender.set_redundant_paths(redundant_paths);
+    }
     
     tracing::info!("Network sender started");
+    if config.network.packet_format == PacketFormat::Rtp {
+        let payload_type = config.network.rtp_payload_type;
+        tracing::info!(
+            "Sending Opus over RTP, SDP for GStreamer/VLC at the receiver:\n{}",
+            rtp::sdp_description(target_addr, payload_type, true)
+        );
+    }
     
     // Track states - shared mutable map for runtime reconfiguration
     let track_states: Arc<Mutex<HashMap<u8, TrackSenderState>>> = Arc::new(Mutex::new(HashMap::new()));
     let track_states_for_events = track_states.clone();
     let track_manager_for_events = track_manager.clone();
+    let track_catalog_for_events = track_catalog.clone();
+    let latency_probe = config.stats.latency_probe;
     
     // Spawn task to handle track events (device changes, track creation/removal)
     tokio::spawn(async move {
@@ -164,12 +278,18 @@ async fn main() -> Result<()> {
                             // Get track config
                             if let Some(track) = track_manager_for_events.get_track(track_id) {
                                 let device_id = track.device_id.clone();
+                                let opus_config = encoder_config(&track.config, &track_manager_for_events.remote_capabilities());
+                                let channel_map = track.config.channel_map.clone();
                                 drop(track); // Release lock
                                 
                                 if let Err(e) = create_capture_for_track(
                                     track_id,
                                     &device_id,
-                                    &track_states_for_events
+                                    opus_config,
+                                    channel_map,
+                                    latency_probe,
+                                    &track_states_for_events,
+                                    &track_manager_for_events
                                 ) {
                                     tracing::error!("Failed to create capture for track {}: {}", track_id, e);
                                 }
@@ -201,10 +321,18 @@ async fn main() -> Result<()> {
                             }
                             
                             // Create new capture with new device
+                            let (opus_config, channel_map) = track_manager_for_events
+                                .get_track(track_id)
+                                .map(|t| (encoder_config(&t.config, &track_manager_for_events.remote_capabilities()), t.config.channel_map.clone()))
+                                .unwrap_or_else(|| (OpusConfig::music(), Vec::new()));
                             if let Err(e) = create_capture_for_track(
                                 track_id,
                                 &new_device,
-                                &track_states_for_events
+                                opus_config,
+                                channel_map,
+                                latency_probe,
+                                &track_states_for_events,
+                                &track_manager_for_events
                             ) {
                                 tracing::error!(
                                     "Failed to create capture for track {} on device {}: {}",
@@ -218,10 +346,34 @@ async fn main() -> Result<()> {
                             }
                         }
                         
+                        TrackEvent::ConfigUpdated(track_id) => {
+                            // Apply codec, bitrate, frame size, FEC toggle, Opus settings and
+                            // channel map to the running capture without recreating it
+                            let config = track_manager_for_events.get_track(track_id).map(|t| t.config.clone());
+                            if let Some(config) = config {
+                                let mut states = track_states_for_events.lock();
+                                if let Some(state) = states.get_mut(&track_id) {
+                                    let receivers = track_manager_for_events.remote_capabilities();
+                                    update_encoder_codec(track_id, state, &config, &receivers);
+                                    update_encoder_bitrate(track_id, state, &config, &receivers, &track_manager_for_events);
+                                    update_encoder_frame_size(track_id, state.encoder.as_mut(), config.frame_size_ms);
+                                    update_encoder_fec(track_id, state.encoder.as_mut(), config.fec_enabled);
+                                    update_encoder_dred(track_id, state.encoder.as_mut(), config.dred);
+                                    update_encoder_settings(track_id, state.encoder.as_mut(), &config);
+                                    let channels = state.encoder.channels() as usize;
+                                    SilenceGate::reconfigure(&mut state.silence_gate, &config);
+                                    VoiceProcessor::reconfigure(&mut state.voice, &config, DEFAULT_SAMPLE_RATE, channels);
+                                    Agc::reconfigure(&mut state.agc, &config, channels);
+                                    state.capture.set_channel_map(config.channel_map);
+                                }
+                            }
+                        }
+                        
                         _ => {
-                            // Other events (Started, Stopped, ConfigUpdated) - handle as needed
+                            // Other events (Started, Stopped) - handle as needed
                         }
                     }
+                    track_catalog_for_events.set_tracks(offered_tracks(&track_manager_for_events));
                 }
                 Err(e) => {
                     tracing::warn!("Event channel error: {}", e);
@@ -231,69 +383,216 @@ async fn main() -> Result<()> {
         }
     });
     
-    // Create initial track from default input device (if available)
-    if let Some(input_device) = devices.iter().find(|d| d.is_input && d.is_default) {
-        let track_config = TrackConfig {
-            track_id: Some(0),
-            name: format!("Default Input - {}", input_device.name),
-            device_id: input_device.id.clone(),
-            bitrate: 128_000,
-            frame_size_ms: 10.0,
-            channels: 2,
-            track_type: TrackType::Music,
-            fec_enabled: false,
-        };
-        
-        let _track_id = track_manager.create_track(track_config)?;
-        tracing::info!("Created initial track for device {}", input_device.name);
-        
-        // Note: The event handler will create the capture automatically
+    // Create the tracks saved from the web UI, then tracks from the
+    // auto-track rules for the other devices
+    // Note: The event handler will create the captures automatically
+    for track_config in &config.tracks {
+        let name = track_config.name.clone();
+        match track_manager.create_saved_track(track_config.clone(), &devices) {
+            Ok(track_id) => tracing::info!("Created saved track {} ({})", track_id, name),
+            Err(e) => tracing::warn!("Failed to create saved track {}: {}", name, e),
+        }
+    }
+    // Saved tracks may have found their device under a new ID
+    let saved_devices: Vec<String> = track_manager
+        .track_ids()
+        .into_iter()
+        .filter_map(|track_id| track_manager.get_track(track_id).map(|track| track.device_id.clone()))
+        .collect();
+    let auto_tracks = auto::plan_tracks(&config.auto_tracks.rules, &devices)
+        .into_iter()
+        .filter(|planned| !saved_devices.contains(&planned.device_id));
+    for track_config in auto_tracks {
+        let name = track_config.name.clone();
+        match track_manager.create_track(track_config) {
+            Ok(track_id) => tracing::info!("Created initial track {} ({})", track_id, name),
+            Err(e) => tracing::warn!("Failed to create initial track {}: {}", name, e),
+        }
     }
     
-    let start_time = Instant::now();
+    // Save UI changes and watch the configuration file; a target that
+    // didn't come from the command line follows `network.remote_address`
+    let _watcher_handle = config_store.spawn_watcher();
+    let mut reload_rx = config_store.subscribe();
+    let follow_remote_address = args.target.is_none() && config.network.multicast_socket_addr().is_none();
+    
+    update_track_destinations(&track_manager, &network_sender);
     let mut last_stats_time = Instant::now();
+    let mut last_capabilities_time = Instant::now();
+    let mut suspend_detector = SuspendDetector::new();
+    
+    // Bandwidth estimate of the link to the receiver, shared by the tracks
+    let congestion_config = &config.network.congestion;
+    let mut congestion = congestion_config.enabled.then(|| CongestionController::new(congestion_config.clone()));
     
     tracing::info!("Starting main loop - press Ctrl+C to stop");
     
     // Main encoding/sending loop
+    let _mmcss = qos::register_thread(&config.network.qos);
     loop {
+        // Woke up from sleep: drop stale audio, restart streams, tell the receiver
+        if let Some(gap) = suspend_detector.check() {
+            tracing::warn!("Resumed from sleep ({:.1}s pause), re-syncing streams", gap.as_secs_f32());
+            for state in track_states.lock().values_mut() {
+                while state.capture_buffer.try_pop().is_some() {}
+                state.sample_buffer.clear();
+                state.restart_pending = true;
+            }
+            if let Err(e) = network_sender.resync() {
+                tracing::warn!("Failed to notify receiver about re-sync: {}", e);
+            }
+        }
+        
         // Process all tracks with minimal blocking
         let has_work = {
             let mut states = track_states.lock();
             let mut work_done = false;
             
+            // Adapt bitrate to the receiver's loss reports, and share the
+            // estimate of a congested link among the tracks
+            let reports: Vec<TrackFeedback> = states.keys().filter_map(|track_id| feedback.take(*track_id)).collect();
+            for report in &reports {
+                if let Some(state) = states.get_mut(&report.track_id) {
+                    apply_feedback(report.track_id, state, report, &track_manager);
+                }
+            }
+            if let Some(congestion) = congestion.as_mut().filter(|_| !reports.is_empty()) {
+                apply_congestion(congestion, &reports, &mut states, &track_manager);
+            }
+            
             for (track_id, state) in states.iter_mut() {
                 let frame_size = state.encoder.samples_per_frame();
                 
+                // Talkback gate and ducking
+                let send_gain = track_manager.send_gain(*track_id);
+                let redundant = track_manager.get_track(*track_id).is_some_and(|t| t.config.redundant);
+                let priority = track_manager
+                    .get_track(*track_id)
+                    .map_or(TrackPriority::Normal, |t| t.config.send_priority());
+                let subscribed = network_sender.is_subscribed(*track_id) && !state.congestion_paused;
+                
                 // Drain all available captured audio
                 while let Some(frame) = state.capture_buffer.try_pop() {
                     work_done = true;
                     
-                    // Accumulate samples
-                    state.sample_buffer.extend_from_slice(&frame.samples);
+                    // Accumulate samples (a surround track is folded down to
+                    // stereo for receivers without surround)
+                    let channels = state.encoder.channels();
+                    if frame.channels == channels {
+                        state.sample_buffer.extend_from_slice(&frame.samples);
+                    } else {
+                        state.sample_buffer.extend(convert_channels(&frame.samples, frame.channels as usize, channels as usize, &[]));
+                    }
                     
                     // Update audio level for the track
                     if let Some(track) = track_manager.get_track(*track_id) {
                         track.update_level_atomic(&frame.samples);
+                        track.update_loudness(&frame.samples, frame.channels as usize);
                     }
+                    track_manager.check_clipping(*track_id, &frame.samples);
+                    frame.recycle();
+                    
+                    // Released talkback track, a track the receiver did not
+                    // subscribe to or one paused by congestion: drop the
+                    // audio, sending again starts a fresh stream on the receiver
+                    let Some(target_gain) = send_gain.filter(|_| subscribed) else {
+                        state.sample_buffer.clear();
+                        state.restart_pending = true;
+                        continue;
+                    };
                     
                     // Process complete frames immediately
                     while state.sample_buffer.len() >= frame_size {
-                        let samples: Vec<f32> = state.sample_buffer.drain(..frame_size).collect();
+                        let mut samples = pool::samples().take_copy(&state.sample_buffer[..frame_size]);
+                        state.sample_buffer.drain(..frame_size);
+                        let capture_stage = profiling::stage(*track_id, Stage::Capture);
+                        
+                        // High-pass filter and noise suppression of voice tracks
+                        if let Some(voice) = state.voice.as_mut() {
+                            voice.process(&mut samples);
+                        }
+                        
+                        // AGC levels the input; its gain is shown in the UI
+                        if let Some(agc) = state.agc.as_mut() {
+                            agc.process(&mut samples, state.encoder.frame_duration_ms());
+                        }
+                        if let Some(track) = track_manager.get_track(*track_id) {
+                            track.update_agc_gain(state.agc.as_ref().map(Agc::gain_db));
+                            let timing = state.capture.timing();
+                            track.update_device_timing(timing.period_ms(), timing.latency_ms());
+                        }
+                        
+                        // Ramp towards the ducking gain to avoid clicks
+                        if state.gain != 1.0 || target_gain != 1.0 {
+                            simd::apply_gain_ramp(&mut samples, state.encoder.channels() as usize, state.gain, target_gain);
+                            state.gain = target_gain;
+                        }
+                        
+                        // Measurement mode: the packet of a frame starting a chirp is tagged
+                        let channels = state.encoder.channels() as usize;
+                        if state.probe.as_mut().is_some_and(|probe| probe.inject(&mut samples, channels)) {
+                            network_sender.mark_probe(*track_id);
+                        }
+                        
+                        drop(capture_stage);
+                        
+                        // Silence suppression: quiet frames are replaced by an
+                        // occasional silence marker the receiver plays out on
+                        if let Some(gate) = state.silence_gate.as_mut() {
+                            let action = gate.process(&samples, state.encoder.frame_duration_ms());
+                            if let Some(track) = track_manager.get_track(*track_id) {
+                                track.set_silent(gate.is_silent());
+                            }
+                            match action {
+                                GateAction::Send => {}
+                                GateAction::Suppress => {
+                                    pool::samples().recycle(samples);
+                                    continue;
+                                }
+                                GateAction::Marker => {
+                                    // Nothing to play out after a restart
+                                    if !state.restart_pending {
+                                        let flags = frame_flags(state.encoder.as_ref());
+                                        if let Err(e) = network_sender.send_silence(*track_id, media_time_us(), flags, priority) {
+                                            tracing::debug!("Failed to send silence marker for track {}: {}", track_id, e);
+                                        }
+                                        state.sequence = state.sequence.wrapping_add(1);
+                                    }
+                                    pool::samples().recycle(samples);
+                                    continue;
+                                }
+                            }
+                        }
                         
                         // Encode
-                        match state.encoder.encode(&samples) {
+                        let encoded = {
+                            let _stage = profiling::stage(*track_id, Stage::Encode);
+                            state.encoder.encode(&samples)
+                        };
+                        pool::samples().recycle(samples);
+                        match encoded {
                             Ok(encoded) => {
-                                // Calculate timestamp from start
-                                let timestamp = start_time.elapsed().as_micros() as u64;
+                                // Timestamp on the shared media clock (answers receivers' sync pings)
+                                let timestamp = media_time_us();
+                                
+                                if state.restart_pending {
+                                    network_sender.mark_restart(*track_id);
+                                    state.restart_pending = false;
+                                }
                                 
                                 // Send over network immediately
-                                if let Err(e) = network_sender.send_audio(
-                                    *track_id,
-                                    encoded,
-                                    timestamp,
-                                    DEFAULT_CHANNELS == 2,
-                                ) {
+                                let sent = {
+                                    let _stage = profiling::stage(*track_id, Stage::Send);
+                                    network_sender.send_audio(
+                                        *track_id,
+                                        encoded,
+                                        timestamp,
+                                        frame_flags(state.encoder.as_ref()),
+                                        priority,
+                                        redundant,
+                                    )
+                                };
+                                if let Err(e) = sent {
                                     // Only log occasionally to prevent spam
                                     if state.sequence % 1000 == 0 {
                                         tracing::warn!("Failed to send packet for track {}: {}", track_id, e);
@@ -330,8 +629,41 @@ async fn main() -> Result<()> {
             tokio::time::sleep(Duration::from_micros(250)).await;
         }
         
-        // Periodic stats logging
-        if last_stats_time.elapsed() >= Duration::from_secs(5) {
+        // Receiver capabilities gate the track controls in the web UI
+        if last_capabilities_time.elapsed() >= Duration::from_secs(1) {
+            last_capabilities_time = Instant::now();
+            track_manager.set_remote_capabilities(PeerCapabilities::combine(&network_sender.peer_capabilities()));
+            if update_track_codecs(&track_manager, &track_states) {
+                track_catalog.set_tracks(offered_tracks(&track_manager));
+            }
+            update_track_destinations(&track_manager, &network_sender);
+            
+            while let Ok(reload) = reload_rx.try_recv() {
+                let new_target = reload.new.network.remote_socket_addr()
+                    .filter(|_| follow_remote_address && reload.changed(|saved| &saved.network.remote_address));
+                if let Some(target) = new_target {
+                    let switched = match handshake_with(&handshake, target).await {
+                        Ok(()) => start_network_sender(&config.network, target, &feedback, &track_catalog, &handshake),
+                        Err(e) => Err(e),
+                    };
+                    match switched {
+                        Ok(sender) => {
+                            tracing::info!("Target receiver from the configuration file: {}", target);
+                            network_sender = sender;
+                            update_track_destinations(&track_manager, &network_sender);
+                            for state in track_states.lock().values_mut() {
+                                state.restart_pending = true;
+                            }
+                        }
+                        Err(e) => tracing::warn!("Failed to switch to receiver {}: {}", target, e),
+                    }
+                }
+                reload.log_restart_needed();
+            }
+        }
+        
+        // Periodic stats logging (quiet mode leaves them to the web UI/API)
+        if config.stats.should_log() && last_stats_time.elapsed() >= config.stats.interval() {
             last_stats_time = Instant::now();
             
             let sender_stats = network_sender.stats();
@@ -346,40 +678,410 @@ async fn main() -> Result<()> {
     }
 }
 
+/// Handshake with a unicast receiver before streaming to it
+///
+/// Fails if the receiver rejects the handshake (e.g. another PSK). A
+/// receiver that doesn't answer may not be running yet or be reachable
+/// only over TCP, so the sender streams to it anyway.
+async fn handshake_with(handshake: &Arc<HandshakeManager>, target: SocketAddr) -> Result<()> {
+    let ip = target.ip().to_canonical();
+    if ip.is_multicast() || matches!(ip, std::net::IpAddr::V4(v4) if v4.is_broadcast()) {
+        return Ok(());
+    }
+    
+    let manager = handshake.clone();
+    let state = tokio::task::spawn_blocking(move || manager.connect(target, HANDSHAKE_TIMEOUT)).await?;
+    match state {
+        Ok(HandshakeState::Connected { .. }) => Ok(()),
+        Ok(HandshakeState::Failed { reason, .. }) => {
+            Err(anyhow::anyhow!("receiver {} rejected the handshake: {}", target, reason))
+        }
+        Ok(_) => {
+            tracing::warn!("No handshake reply from {}, streaming anyway", target);
+            Ok(())
+        }
+        Err(e) => {
+            tracing::warn!("Handshake with {} failed ({}), streaming anyway", target, e);
+            Ok(())
+        }
+    }
+}
+
+/// Create and start the network sender to a receiver
+fn start_network_sender(
+    network: &NetworkConfig,
+    target: SocketAddr,
+    feedback: &Arc<FeedbackInbox>,
+    track_catalog: &Arc<TrackCatalog>,
+    handshake: &Arc<HandshakeManager>,
+) -> Result<MultiTrackSender> {
+    let mut sender = MultiTrackSender::new(network, target)?;
+    sender.set_feedback_inbox(feedback.clone());
+    sender.set_track_catalog(track_catalog.clone());
+    sender.set_handshake(handshake.clone());
+    sender.start(network.clone())?;
+    Ok(sender)
+}
+
+/// Send tracks with a destination of their own to that receiver
+/// (removed tracks go back to the target, stopping unused senders)
+fn update_track_destinations(track_manager: &TrackManager, network_sender: &MultiTrackSender) {
+    let mut track_ids = track_manager.track_ids();
+    track_ids.extend(network_sender.redirected_tracks());
+    track_ids.sort_unstable();
+    track_ids.dedup();
+    
+    for track_id in track_ids {
+        let destination = track_manager.get_track(track_id).and_then(|track| track.config.destination_addr());
+        if let Err(e) = network_sender.set_track_destination(track_id, destination) {
+            tracing::warn!("Failed to send track {} to {:?}: {}", track_id, destination, e);
+        }
+    }
+}
+
+/// Payload flags of the frames a track encoder produces
+fn frame_flags(encoder: &dyn AudioEncoder) -> PacketFlags {
+    PacketFlags::new()
+        .set_stereo(encoder.channels() >= 2)
+        .set_fec(encoder.fec_enabled())
+        .set_codec(encoder.codec())
+}
+
+/// Switch a running track to the codec and channel count its config and
+/// receivers agree on; the receiver restarts the stream at the next packet.
+/// Returns true if the channel count changed (the track catalog reports it).
+fn update_encoder_codec(
+    track_id: u8,
+    state: &mut TrackSenderState,
+    config: &TrackConfig,
+    receivers: &RemoteCapabilities,
+) -> bool {
+    let codec = select_codec(config.codec, receivers);
+    let opus_config = encoder_config(config, receivers);
+    let channels = opus_config.channels;
+    if state.encoder.codec() == codec && state.encoder.channels() == channels {
+        return false;
+    }
+    if codec != config.codec {
+        tracing::warn!("Track {}: the receiver can't decode {:?}, sending {:?}", track_id, config.codec, codec);
+    }
+    if channels != config.channels.max(DEFAULT_CHANNELS) {
+        tracing::warn!("Track {}: the receiver can't play {} channels, sending stereo", track_id, config.channels);
+    }
+    
+    match new_encoder(codec, opus_config) {
+        Ok(encoder) => {
+            let channels_changed = encoder.channels() != state.encoder.channels();
+            state.encoder = encoder;
+            state.sample_buffer.clear();
+            state.restart_pending = true;
+            if channels_changed {
+                state.voice = VoiceProcessor::for_track(config, DEFAULT_SAMPLE_RATE, channels as usize);
+                state.agc = Agc::for_track(config, channels as usize);
+            }
+            tracing::info!("Track {}: codec {:?}, {} channels", track_id, codec, channels);
+            channels_changed
+        }
+        Err(e) => {
+            tracing::warn!("Failed to switch track {} to {:?}: {}", track_id, codec, e);
+            false
+        }
+    }
+}
+
+/// Re-select the codec of every running track after the receivers
+/// changed; true if the channel count of a track changed
+fn update_track_codecs(track_manager: &TrackManager, track_states: &Mutex<HashMap<u8, TrackSenderState>>) -> bool {
+    let receivers = track_manager.remote_capabilities();
+    let mut channels_changed = false;
+    for (&track_id, state) in track_states.lock().iter_mut() {
+        if let Some(track) = track_manager.get_track(track_id) {
+            channels_changed |= update_encoder_codec(track_id, state, &track.config, &receivers);
+        }
+    }
+    channels_changed
+}
+
+/// Enable or disable in-band FEC on a running encoder (Opus only)
+fn update_encoder_fec(track_id: u8, encoder: &mut dyn AudioEncoder, fec_enabled: bool) {
+    let Some(encoder) = encoder.opus_mut() else {
+        return;
+    };
+    if encoder.config().fec == fec_enabled {
+        return;
+    }
+    
+    let packet_loss_perc = encoder.config().packet_loss_perc.max(DEFAULT_FEC_PACKET_LOSS_PERC);
+    match encoder.set_fec(fec_enabled, packet_loss_perc) {
+        Ok(()) => tracing::info!("Track {}: FEC {}", track_id, if fec_enabled { "enabled" } else { "disabled" }),
+        Err(e) => tracing::warn!("Failed to update FEC for track {}: {}", track_id, e),
+    }
+}
+
+/// Carry the track's bitrate over to a running encoder: it becomes the
+/// ceiling adaptation recovers to (Opus only)
+fn update_encoder_bitrate(
+    track_id: u8,
+    state: &mut TrackSenderState,
+    config: &TrackConfig,
+    receivers: &RemoteCapabilities,
+    track_manager: &Arc<TrackManager>,
+) {
+    if state.encoder.opus_mut().is_none() {
+        return;
+    }
+    let bitrate = encoder_config(config, receivers).bitrate;
+    if state.adaptive.max_bitrate() == bitrate {
+        return;
+    }
+    
+    tracing::info!("Track {}: bitrate {} bps", track_id, bitrate);
+    if let Some(decision) = state.adaptive.set_max_bitrate(bitrate) {
+        set_encoder_bitrate(track_id, state, decision, track_manager);
+    }
+}
+
+/// Change the frame size of a running encoder; the capture and the
+/// buffered samples stay, the next frame has the new size
+fn update_encoder_frame_size(track_id: u8, encoder: &mut dyn AudioEncoder, frame_size_ms: f32) {
+    let frame_size = OpusConfig::frame_size_from_ms(DEFAULT_SAMPLE_RATE, frame_size_ms);
+    if encoder.samples_per_frame() == frame_size * encoder.channels() as usize {
+        return;
+    }
+    
+    match encoder.set_frame_size(frame_size) {
+        Ok(()) => tracing::info!("Track {}: {} ms frames", track_id, frame_size_ms),
+        Err(e) => tracing::warn!("Failed to change the frame size of track {}: {}", track_id, e),
+    }
+}
+
+/// Enable or disable deep redundancy (DRED) on a running encoder (Opus only)
+fn update_encoder_dred(track_id: u8, encoder: &mut dyn AudioEncoder, dred_enabled: bool) {
+    let Some(encoder) = encoder.opus_mut() else {
+        return;
+    };
+    let duration_ms = dred::duration_ms(dred_enabled);
+    if encoder.config().dred_duration_ms == duration_ms {
+        return;
+    }
+    
+    match encoder.set_dred_duration(duration_ms) {
+        Ok(()) => tracing::info!("Track {}: DRED {}", track_id, if duration_ms > 0 { "enabled" } else { "disabled" }),
+        Err(e) => tracing::warn!("Failed to update DRED for track {}: {}", track_id, e),
+    }
+}
+
+/// Retune complexity, bitrate mode, signal type and bandwidth of a
+/// running encoder to the track settings (Opus only)
+fn update_encoder_settings(track_id: u8, encoder: &mut dyn AudioEncoder, config: &TrackConfig) {
+    let Some(encoder) = encoder.opus_mut() else {
+        return;
+    };
+    let target = encoder_config(config, &RemoteCapabilities::default());
+    let current = encoder.config();
+    if (current.complexity, current.bitrate_mode(), current.signal, current.max_bandwidth)
+        == (target.complexity, target.bitrate_mode(), target.signal, target.max_bandwidth)
+    {
+        return;
+    }
+    
+    match encoder.set_settings(&target) {
+        Ok(()) => tracing::info!(
+            "Track {}: complexity {}, {:?}, signal {:?}, bandwidth up to {:?}",
+            track_id,
+            target.complexity,
+            target.bitrate_mode(),
+            target.signal,
+            target.max_bandwidth
+        ),
+        Err(e) => tracing::warn!("Failed to retune the encoder of track {}: {}", track_id, e),
+    }
+}
+
+/// Apply a receiver feedback report to the track encoder
+fn apply_feedback(
+    track_id: u8,
+    state: &mut TrackSenderState,
+    report: &TrackFeedback,
+    track_manager: &Arc<TrackManager>,
+) {
+    // Lossless tracks have no bitrate to adapt
+    if state.encoder.opus_mut().is_none() {
+        return;
+    }
+    let Some(decision) = state.adaptive.update(report) else {
+        return;
+    };
+    
+    if set_encoder_bitrate(track_id, state, decision, track_manager) {
+        tracing::debug!(
+            "Track {}: {:.1}% loss reported, bitrate {} bps, loss hint {}%",
+            track_id,
+            report.loss_fraction * 100.0,
+            decision.bitrate,
+            decision.packet_loss_perc
+        );
+    }
+}
+
+/// Share the estimate of a congested link among the tracks: bitrate
+/// ceilings, and pausing the less important tracks
+fn apply_congestion(
+    congestion: &mut CongestionController,
+    reports: &[TrackFeedback],
+    states: &mut HashMap<u8, TrackSenderState>,
+    track_manager: &Arc<TrackManager>,
+) {
+    let sending_bps = states
+        .values()
+        .filter(|state| !state.congestion_paused)
+        .map(|state| state.encoder.bitrate())
+        .sum();
+    congestion.update(reports, sending_bps);
+    
+    let demands: Vec<TrackDemand> = states
+        .iter()
+        .map(|(track_id, state)| {
+            let elastic = state.encoder.codec() == Codec::Opus;
+            TrackDemand {
+                track_id: *track_id,
+                priority: track_manager
+                    .get_track(*track_id)
+                    .map_or(TrackPriority::Normal, |track| track.config.send_priority()),
+                bitrate: if elastic { state.adaptive.uncapped_bitrate() } else { state.encoder.bitrate() },
+                elastic,
+            }
+        })
+        .collect();
+    
+    for (track_id, allocation) in congestion.allocate(&demands) {
+        let Some(state) = states.get_mut(&track_id) else {
+            continue;
+        };
+        state.congestion_paused = allocation.paused;
+        if let Some(track) = track_manager.get_track(track_id) {
+            track.set_congestion_paused(allocation.paused);
+        }
+        if let Some(decision) = state.adaptive.set_cap(allocation.bitrate_cap) {
+            set_encoder_bitrate(track_id, state, decision, track_manager);
+        }
+    }
+}
+
+/// Apply a bitrate and loss hint to the track's Opus encoder
+fn set_encoder_bitrate(
+    track_id: u8,
+    state: &mut TrackSenderState,
+    decision: BitrateDecision,
+    track_manager: &Arc<TrackManager>,
+) -> bool {
+    let Some(encoder) = state.encoder.opus_mut() else {
+        return false;
+    };
+    if let Err(e) = encoder.set_bitrate(decision.bitrate) {
+        tracing::warn!("Failed to set bitrate for track {}: {}", track_id, e);
+        return false;
+    }
+    if let Err(e) = encoder.set_packet_loss_perc(decision.packet_loss_perc) {
+        tracing::warn!("Failed to set packet loss hint for track {}: {}", track_id, e);
+    }
+    
+    if let Some(track) = track_manager.get_track(track_id) {
+        track.update_adaptive_bitrate(decision.bitrate);
+    }
+    true
+}
+
+/// Tracks offered to the receiver in sync responses
+fn offered_tracks(track_manager: &TrackManager) -> Vec<TrackInfo> {
+    let receivers = track_manager.remote_capabilities();
+    track_manager
+        .track_ids()
+        .into_iter()
+        .filter_map(|id| {
+            track_manager.get_track(id).map(|track| TrackInfo {
+                channels: select_channels(track.config.channels, &receivers),
+                ..TrackInfo::from_config(id, &track.config)
+            })
+        })
+        .collect()
+}
+
+/// Encoder settings for a track: voice tuning for talkback, music
+/// otherwise, at the track's bitrate and frame size; surround only if
+/// every receiver plays it
+fn encoder_config(config: &TrackConfig, receivers: &RemoteCapabilities) -> OpusConfig {
+    let base = if config.talkback {
+        OpusConfig::talkback()
+    } else {
+        OpusConfig::music()
+    };
+    // The track's bitrate for every pair of channels
+    let channels = select_channels(config.channels, receivers);
+    OpusConfig {
+        channels,
+        bitrate: config.bitrate * channels.div_ceil(DEFAULT_CHANNELS) as u32,
+        frame_size: OpusConfig::frame_size_from_ms(DEFAULT_SAMPLE_RATE, config.frame_size_ms),
+        ..base.with_fec(config.fec_enabled).with_dred(config.dred).with_track_settings(config)
+    }
+}
+
+/// Channels a track is captured with: all of a surround track (folded
+/// down when sent in stereo), stereo otherwise
+fn capture_channels(config: &TrackConfig) -> u16 {
+    select_channels(config.channels, &RemoteCapabilities::default())
+}
+
 /// Create a new capture instance for a track
 fn create_capture_for_track(
     track_id: u8,
     device_id: &str,
+    opus_config: OpusConfig,
+    channel_map: Vec<usize>,
+    latency_probe: bool,
     track_states: &Arc<Mutex<HashMap<u8, TrackSenderState>>>,
+    track_manager: &TrackManager,
 ) -> Result<()> {
     // Create capture buffer
     let capture_buffer = create_shared_buffer(RING_BUFFER_CAPACITY);
     
-    // Create and start audio capture
+    // Create and start audio capture at the device's own channel count and
+    // the track's buffer size; frames are converted to the track layout
+    let buffer_frames = track_manager.get_track(track_id).and_then(|track| track.config.buffer_frames);
     let mut capture = AudioCapture::new(
         track_id,
         device_id,
         Some(DEFAULT_SAMPLE_RATE),
-        Some(DEFAULT_CHANNELS),
         None,
+        buffer_frames,
         capture_buffer.clone(),
     )?;
+    capture.set_output_channels(track_manager.get_track(track_id).map_or(DEFAULT_CHANNELS, |track| capture_channels(&track.config)));
+    capture.set_channel_map(channel_map);
     
     capture.start()?;
     tracing::info!("Audio capture started for track {} on device {}", track_id, device_id);
+    // A file source takes transport commands from the UI
+    track_manager.set_file_player(track_id, capture.file_player());
     
-    // Create Opus encoder for this track
-    let opus_config = OpusConfig::music();
-    let encoder = OpusEncoder::new(opus_config)?;
+    // Create the encoder for this track
+    let configured = track_manager.get_track(track_id).map_or(Codec::Opus, |track| track.config.codec);
+    let codec = select_codec(configured, &track_manager.remote_capabilities());
+    let fec_enabled = opus_config.fec && codec == Codec::Opus;
+    let adaptive = AdaptiveBitrate::new(opus_config.bitrate, opus_config.packet_loss_perc);
+    let channels = opus_config.channels;
+    let encoder = new_encoder(codec, opus_config)?;
     let frame_size = encoder.samples_per_frame();
     
     tracing::info!(
-        "Opus encoder initialized for track {}: {}Hz, {} channels, {} samples/frame ({:.1}ms)",
+        "{:?} encoder initialized for track {}: {}Hz, {} channels, {} samples/frame ({:.1}ms), FEC {}",
+        codec,
         track_id,
         DEFAULT_SAMPLE_RATE,
-        DEFAULT_CHANNELS,
+        channels,
         frame_size,
-        encoder.frame_duration_ms()
+        encoder.frame_duration_ms(),
+        if fec_enabled { "on" } else { "off" }
     );
     
     // Store state
@@ -387,8 +1089,20 @@ fn create_capture_for_track(
         capture,
         capture_buffer,
         encoder,
+        adaptive,
+        congestion_paused: false,
         sample_buffer: Vec::with_capacity(frame_size * 2),
         sequence: 0,
+        restart_pending: true,
+        gain: 1.0,
+        probe: latency_probe.then(|| ProbeInjector::new(DEFAULT_SAMPLE_RATE)),
+        silence_gate: track_manager.get_track(track_id).and_then(|track| SilenceGate::for_track(&track.config)),
+        voice: track_manager
+            .get_track(track_id)
+            .and_then(|track| VoiceProcessor::for_track(&track.config, DEFAULT_SAMPLE_RATE, channels as usize)),
+        agc: track_manager
+            .get_track(track_id)
+            .and_then(|track| Agc::for_track(&track.config, channels as usize)),
     };
     
     let mut states = track_states.lock();
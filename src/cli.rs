@@ -0,0 +1,934 @@
+//! Command line of the `peer`, `sender` and `receiver` binaries
+//!
+//! The three binaries share one command line: a subcommand picks a mode
+//! (`peer`, `send`, `recv`) or a tool (`devices`, `discover`, `ctl`, `replay`). Without a
+//! subcommand a binary runs its own mode, so `sender 192.168.1.20` keeps
+//! working. A mode implemented by another binary is handed over to that
+//! binary next to this one. Options fall back to their `LAN_AUDIO_*`
+//! environment variables, and `--config` selects the configuration file.
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::audio::device::{list_devices, set_backend};
+use crate::config::{AppConfig, AudioBackend, DeviceProfile, DiscoveryMode, PacketFormat, QosConfig, StatsConfig};
+use crate::constants::*;
+use crate::config_store::ConfigStore;
+use crate::ctl::{self, CtlArgs, CtlCommand};
+use crate::engine::PeerConfig;
+use crate::error::{Error, Result};
+use crate::network::discovery::DiscoveryService;
+use crate::replay::{self, ReplayArgs};
+use crate::selftest::{self, SelfTestArgs};
+use crate::service::ServiceAction;
+
+/// Streaming mode, and the binary that implements it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Peer,
+    Send,
+    Recv,
+}
+
+impl Mode {
+    const ALL: [Mode; 3] = [Mode::Peer, Mode::Send, Mode::Recv];
+
+    /// Subcommand that selects the mode
+    pub fn subcommand(self) -> &'static str {
+        match self {
+            Mode::Peer => "peer",
+            Mode::Send => "send",
+            Mode::Recv => "recv",
+        }
+    }
+
+    /// Binary that runs the mode
+    pub fn binary(self) -> &'static str {
+        match self {
+            Mode::Peer => "peer",
+            Mode::Send => "sender",
+            Mode::Recv => "receiver",
+        }
+    }
+
+    fn about(self) -> &'static str {
+        match self {
+            Mode::Peer => "Send and receive audio with peers found on the network",
+            Mode::Send => "Capture tracks and stream them to a receiver",
+            Mode::Recv => "Receive tracks and play them on output devices",
+        }
+    }
+
+    fn args(self) -> Vec<Arg> {
+        let mut args = match self {
+            Mode::Peer => vec![
+                Arg::new("name")
+                    .short('n')
+                    .long("name")
+                    .value_name("NAME")
+                    .env(NAME_ENV_VAR)
+                    .help("Name shown to other peers [default: Peer-<PID>]"),
+                port_arg(),
+                Arg::new("discovery")
+                    .short('d')
+                    .long("discovery")
+                    .value_name("MODE")
+                    .env(DISCOVERY_ENV_VAR)
+                    .value_parser(parse::<DiscoveryMode>)
+                    .help("Peer discovery: broadcast, mdns or both [default: both]"),
+                Arg::new("talkback")
+                    .short('t')
+                    .long("talkback")
+                    .value_name("DEVICE")
+                    .help("Capture device of a talkback track (sends while the UI button is held)"),
+                Arg::new("tracks")
+                    .long("tracks")
+                    .value_name("ID,...")
+                    .value_parser(parse_track_ids)
+                    .help("Receive only these tracks from peers (subscription)"),
+                Arg::new("no-auto-connect")
+                    .long("no-auto-connect")
+                    .action(ArgAction::SetTrue)
+                    .help("Don't connect to discovered peers until connected in the UI"),
+                profile_arg(),
+            ],
+            Mode::Send => vec![
+                Arg::new("target")
+                    .value_name("TARGET")
+                    .env(TARGET_ENV_VAR)
+                    .help("Receiver address IP[:PORT] [default: from the config file, or discovery]"),
+                Arg::new("pin")
+                    .long("pin")
+                    .value_name("PIN")
+                    .help("Pair with the receiver using the one-time PIN shown in its web UI"),
+            ],
+            Mode::Recv => vec![
+                port_arg(),
+                profile_arg(),
+                Arg::new("install-service")
+                    .long("install-service")
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with("uninstall-service")
+                    .help("Run the receiver at boot with the other options given (Windows service, systemd user unit)"),
+                Arg::new("uninstall-service")
+                    .long("uninstall-service")
+                    .action(ArgAction::SetTrue)
+                    .help("Remove the service installed with --install-service"),
+                // Set by the service manager
+                Arg::new("service").long("service").action(ArgAction::SetTrue).hide(true),
+            ],
+        };
+        args.extend(stream_args(self != Mode::Send));
+        args
+    }
+}
+
+/// Options shared by the streaming modes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamArgs {
+    pub psk: Option<String>,
+    pub allow_plaintext: bool,
+    pub packet_format: Option<PacketFormat>,
+    pub backend: Option<AudioBackend>,
+    pub no_qos: bool,
+    pub stats_interval: Option<u64>,
+    pub quiet: bool,
+    pub latency_probe: bool,
+    pub probe_loopback: Option<String>,
+    /// File every received packet is dumped to
+    pub dump_packets: Option<PathBuf>,
+    /// pcapng file capturing the packets sent and received
+    pub pcap: Option<PathBuf>,
+}
+
+impl StreamArgs {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        Self {
+            psk: value(matches, "psk"),
+            allow_plaintext: flag(matches, "allow-plaintext"),
+            packet_format: value(matches, "packet-format"),
+            backend: value(matches, "backend"),
+            no_qos: flag(matches, "no-qos"),
+            stats_interval: value(matches, "stats-interval"),
+            quiet: flag(matches, "quiet"),
+            latency_probe: flag(matches, "latency-probe"),
+            probe_loopback: value(matches, "probe-loopback"),
+            dump_packets: value(matches, "dump-packets"),
+            pcap: value(matches, "pcap"),
+        }
+    }
+
+    /// Override the settings of the configuration file with the options given
+    pub fn apply(&self, config: &mut AppConfig) {
+        if let Some(psk) = &self.psk {
+            config.network.psk = Some(psk.clone());
+        }
+        config.network.allow_plaintext_tracks |= self.allow_plaintext;
+        if let Some(format) = self.packet_format {
+            config.network.packet_format = format;
+        }
+        if let Some(backend) = self.backend {
+            config.audio.backend = backend;
+        }
+        if self.no_qos || QosConfig::disabled_by_env() {
+            config.network.qos.disable();
+        }
+        if let Some(path) = &self.dump_packets {
+            config.network.packet_dump = Some(path.clone());
+        }
+        if let Some(path) = &self.pcap {
+            config.network.pcap = Some(path.clone());
+        }
+        self.apply_stats(&mut config.stats);
+    }
+
+    fn apply_stats(&self, stats: &mut StatsConfig) {
+        if let Some(secs) = self.stats_interval {
+            stats.interval_secs = secs;
+        }
+        stats.quiet |= self.quiet;
+        stats.latency_probe |= self.latency_probe;
+        if let Some(device) = &self.probe_loopback {
+            stats.probe_loopback_device = Some(device.clone());
+        }
+    }
+}
+
+/// Options of `send`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SendArgs {
+    /// Receiver address as given (`IP[:PORT]`)
+    pub target: Option<String>,
+    /// One-time pairing PIN of the receiver
+    pub pin: Option<String>,
+    pub stream: StreamArgs,
+}
+
+/// Options of `recv`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecvArgs {
+    pub port: Option<u16>,
+    pub profile: Option<DeviceProfile>,
+    /// Install or uninstall the service instead of receiving
+    pub service_action: Option<ServiceAction>,
+    /// Running under the service manager (`--service`)
+    pub as_service: bool,
+    pub stream: StreamArgs,
+}
+
+impl RecvArgs {
+    /// Override the settings of the configuration file with the options given
+    pub fn apply(&self, config: &mut AppConfig) {
+        if let Some(port) = self.port {
+            config.network.udp_port = port;
+        }
+        if let Some(profile) = self.profile {
+            config.profile = profile;
+        }
+        self.stream.apply(config);
+    }
+}
+
+/// What to run
+#[derive(Debug, Clone)]
+pub enum CliCommand {
+    Peer(PeerConfig),
+    Send(SendArgs),
+    Recv(RecvArgs),
+    /// List audio devices with their IDs
+    Devices { backend: Option<AudioBackend> },
+    /// List the peers announcing themselves on the network
+    Discover { mode: DiscoveryMode, timeout: Duration },
+    /// Control a running instance
+    Ctl(CtlArgs),
+    /// Stream a test tone to this machine over localhost and measure it
+    SelfTest(SelfTestArgs),
+    /// Play back a packet dump
+    Replay(ReplayArgs),
+}
+
+impl CliCommand {
+    /// Mode of a streaming command
+    pub fn mode(&self) -> Option<Mode> {
+        match self {
+            CliCommand::Peer(_) => Some(Mode::Peer),
+            CliCommand::Send(_) => Some(Mode::Send),
+            CliCommand::Recv(_) => Some(Mode::Recv),
+            CliCommand::Devices { .. }
+            | CliCommand::Discover { .. }
+            | CliCommand::Ctl(_)
+            | CliCommand::SelfTest(_)
+            | CliCommand::Replay(_) => None,
+        }
+    }
+}
+
+/// Parsed command line
+#[derive(Debug, Clone)]
+pub struct Cli {
+    /// Configuration file given with `--config`
+    pub config: Option<PathBuf>,
+    pub command: CliCommand,
+}
+
+impl Cli {
+    /// Parse the arguments of the binary that runs `mode`; prints help or
+    /// the error and exits when they don't parse
+    pub fn parse(mode: Mode) -> Self {
+        Self::try_parse_from(mode, std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    pub fn try_parse_from<I, T>(mode: Mode, args: I) -> std::result::Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = command(mode).try_get_matches_from(args)?;
+        let (mode_matches, command) = match matches.subcommand() {
+            Some(("devices", sub)) => (
+                sub,
+                CliCommand::Devices {
+                    backend: sub.get_one::<AudioBackend>("backend").copied(),
+                },
+            ),
+            Some(("discover", sub)) => (
+                sub,
+                CliCommand::Discover {
+                    mode: sub.get_one::<DiscoveryMode>("discovery").copied().unwrap_or_default(),
+                    timeout: Duration::from_secs(*sub.get_one::<u64>("timeout").expect("has a default")),
+                },
+            ),
+            Some(("ctl", sub)) => (
+                sub,
+                CliCommand::Ctl(CtlArgs {
+                    address: sub.get_one::<String>("address").cloned(),
+                    config: sub.get_one::<PathBuf>("config").cloned(),
+                    command: ctl_command(sub),
+                }),
+            ),
+            Some(("replay", sub)) => (
+                sub,
+                CliCommand::Replay(ReplayArgs {
+                    file: sub.get_one::<PathBuf>("file").cloned().expect("required"),
+                    output: sub.get_one::<String>("output").cloned(),
+                }),
+            ),
+            Some((name, sub)) => {
+                let mode = Mode::ALL.into_iter().find(|mode| mode.subcommand() == name).expect("known subcommand");
+                (sub, mode_command(mode, sub))
+            }
+            None if flag(&matches, "self-test") => (
+                &matches,
+                CliCommand::SelfTest(SelfTestArgs {
+                    output: matches.get_one::<String>("self-test-output").cloned(),
+                    duration: Duration::from_secs(*matches.get_one::<u64>("self-test-secs").expect("has a default")),
+                }),
+            ),
+            None => (&matches, mode_command(mode, &matches)),
+        };
+        let config = mode_matches.get_one::<PathBuf>("config").cloned();
+
+        let mut cli = Self { config, command };
+        if let CliCommand::Peer(ref mut peer) = cli.command {
+            peer.config_path = cli.config.clone().or_else(AppConfig::default_path);
+        }
+        Ok(cli)
+    }
+
+    /// Configuration file in use: `--config`, or the default one
+    pub fn config_path(&self) -> Option<PathBuf> {
+        self.config.clone().or_else(AppConfig::default_path)
+    }
+
+    /// Open the configuration file
+    ///
+    /// An existing file given with `--config` must parse; a missing one is
+    /// created with the first change saved from the web UI. The default
+    /// file falls back to the defaults with a warning if it doesn't parse.
+    pub fn config_store(&self) -> Result<ConfigStore> {
+        match &self.config {
+            Some(path) if path.exists() => ConfigStore::load(path.clone()),
+            _ => Ok(ConfigStore::open(self.config_path())),
+        }
+    }
+}
+
+/// Run a command other than the binary's own mode: the tools run here,
+/// another mode runs in its binary with the same arguments
+pub fn run_other(command: &CliCommand) -> Result<()> {
+    match *command {
+        CliCommand::Devices { backend } => {
+            if let Some(backend) = backend {
+                set_backend(backend)?;
+            }
+            print_devices();
+            Ok(())
+        }
+        CliCommand::Discover { mode, timeout } => discover(mode, timeout),
+        CliCommand::Ctl(ref args) => ctl::run(args),
+        CliCommand::SelfTest(ref args) => selftest::run(args),
+        CliCommand::Replay(ref args) => replay::run(args),
+        _ => hand_over(command.mode().expect("a streaming command")),
+    }
+}
+
+/// Run the binary of `mode` with this process's arguments and exit with its status
+fn hand_over(mode: Mode) -> Result<()> {
+    let binary = std::env::current_exe()?.with_file_name(format!("{}{}", mode.binary(), std::env::consts::EXE_SUFFIX));
+    let status = std::process::Command::new(&binary)
+        .args(std::env::args_os().skip(1))
+        .status()
+        .map_err(|e| Error::Config(format!("`{}` needs {}: {}", mode.subcommand(), binary.display(), e)))?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Print every audio device with its ID
+pub fn print_devices() {
+    for device in list_devices() {
+        let device_type = match (device.is_input, device.is_output) {
+            (true, true) => "Input/Output",
+            (true, false) => "Input",
+            (false, true) => "Output",
+            _ => "Unknown",
+        };
+        let default_marker = if device.is_default { " [DEFAULT]" } else { "" };
+        println!("{} ({}){}", device.name, device_type, default_marker);
+        println!("    ID: {}", device.id);
+        println!("    Sample rates: {:?}", device.sample_rates);
+        println!("    Channels: {:?}", device.channels);
+    }
+}
+
+/// Listen for peers for `timeout` and print them
+pub fn discover(mode: DiscoveryMode, timeout: Duration) -> Result<()> {
+    let mut discovery = DiscoveryService::new(false, 0, "lan-audio discover".to_string());
+    discovery.set_mode(mode);
+    discovery.start()?;
+    println!("Listening for peers for {} s...", timeout.as_secs());
+    std::thread::sleep(timeout);
+    let mut peers = discovery.get_peers();
+    discovery.stop();
+    peers.retain(|peer| peer.instance_id != discovery.instance_id());
+
+    peers.sort_by_key(|peer| peer.audio_address());
+    for peer in &peers {
+        let role = if peer.is_sender { "sender/peer" } else { "receiver" };
+        println!("{}  {} ({})", peer.audio_address(), peer.name, role);
+    }
+    if peers.is_empty() {
+        println!("No peers found");
+    }
+    Ok(())
+}
+
+fn command(mode: Mode) -> Command {
+    let mut subcommands = vec![
+        Command::new("devices").about("List audio devices with their IDs").arg(backend_arg()),
+        Command::new("discover").about("List the peers announcing themselves on the network").args([
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("SECS")
+                .value_parser(value_parser!(u64))
+                .default_value("5")
+                .help("How long to listen"),
+            Arg::new("discovery")
+                .short('d')
+                .long("discovery")
+                .value_name("MODE")
+                .env(DISCOVERY_ENV_VAR)
+                .value_parser(parse::<DiscoveryMode>)
+                .help("Peer discovery: broadcast, mdns or both [default: both]"),
+        ]),
+        ctl_subcommand(),
+        Command::new("replay").about("Play back a packet dump written with --dump-packets").args([
+            Arg::new("file")
+                .value_name("FILE")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("Packet dump"),
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("DEVICE")
+                .help("Output device, or null for none [default: the default output]"),
+        ]),
+    ];
+    subcommands.splice(0..0, Mode::ALL.map(|mode| Command::new(mode.subcommand()).about(mode.about()).args(mode.args())));
+
+    Command::new(mode.binary())
+        .version(env!("CARGO_PKG_VERSION"))
+        .about(mode.about())
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .env(CONFIG_ENV_VAR)
+                .global(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("Configuration file [default: the per-user config.toml]"),
+        )
+        .args(mode.args())
+        .args([
+            Arg::new("self-test")
+                .long("self-test")
+                .action(ArgAction::SetTrue)
+                .help("Stream a test tone to this machine over localhost and report latency, loss and glitches"),
+            Arg::new("self-test-output")
+                .long("self-test-output")
+                .value_name("DEVICE")
+                .requires("self-test")
+                .help("Play the test tone on this output [default: null, no sound card needed]"),
+            Arg::new("self-test-secs")
+                .long("self-test-secs")
+                .value_name("SECS")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10")
+                .help("Length of the self-test"),
+        ])
+        .args_conflicts_with_subcommands(true)
+        .subcommands(subcommands)
+}
+
+fn ctl_subcommand() -> Command {
+    let track_id = || {
+        Arg::new("id")
+            .value_name("ID")
+            .required(true)
+            .value_parser(value_parser!(u8))
+            .help("Track ID")
+    };
+    let track_commands = [
+        ("mute", "Mute a track"),
+        ("unmute", "Unmute a track"),
+        ("solo", "Solo a track"),
+        ("unsolo", "Take a track out of solo"),
+        ("remove", "Remove a track"),
+    ];
+
+    Command::new("ctl")
+        .about("Control a running instance over its control port")
+        .arg(
+            Arg::new("address")
+                .short('a')
+                .long("address")
+                .value_name("IP[:PORT]")
+                .env(CTL_ADDRESS_ENV_VAR)
+                .global(true)
+                .help("Control port of the instance [default: [ui] rpc_port of the config file]"),
+        )
+        .subcommand_required(true)
+        .subcommands([
+            Command::new("tracks")
+                .about("List the tracks")
+                .subcommand(Command::new("list").about("List the tracks")),
+            Command::new("track")
+                .about("Change a track")
+                .subcommand_required(true)
+                .subcommands(track_commands.map(|(name, about)| Command::new(name).about(about).arg(track_id()))),
+            Command::new("peers").about("List the peers"),
+            Command::new("stats").about("Print track statistics").args([
+                Arg::new("follow")
+                    .short('f')
+                    .long("follow")
+                    .action(ArgAction::SetTrue)
+                    .help("Keep printing until interrupted"),
+                Arg::new("interval")
+                    .long("interval")
+                    .value_name("SECS")
+                    .value_parser(value_parser!(u64))
+                    .default_value("1")
+                    .help("Interval between prints with --follow"),
+            ]),
+            Command::new("call").about("Call a ControlMessage request and print its result").args([
+                Arg::new("method").value_name("METHOD").required(true).help("Request, e.g. GetRouting"),
+                Arg::new("params")
+                    .value_name("PARAMS")
+                    .value_parser(parse_json)
+                    .help("Its data as JSON, e.g. '{\"track_id\":0,\"muted\":true}'"),
+            ]),
+        ])
+}
+
+fn ctl_command(matches: &ArgMatches) -> CtlCommand {
+    match matches.subcommand().expect("subcommand is required") {
+        ("tracks", _) => CtlCommand::Tracks,
+        ("track", sub) => {
+            let (action, sub) = sub.subcommand().expect("subcommand is required");
+            let track_id = *sub.get_one::<u8>("id").expect("required");
+            match action {
+                "mute" | "unmute" => CtlCommand::Mute { track_id, muted: action == "mute" },
+                "solo" | "unsolo" => CtlCommand::Solo { track_id, solo: action == "solo" },
+                _ => CtlCommand::RemoveTrack { track_id },
+            }
+        }
+        ("peers", _) => CtlCommand::Peers,
+        ("stats", sub) => CtlCommand::Stats {
+            follow: flag(sub, "follow"),
+            interval: Duration::from_secs(*sub.get_one::<u64>("interval").expect("has a default")),
+        },
+        (_, sub) => CtlCommand::Call {
+            method: sub.get_one::<String>("method").cloned().expect("required"),
+            params: sub.get_one::<serde_json::Value>("params").cloned(),
+        },
+    }
+}
+
+fn mode_command(mode: Mode, matches: &ArgMatches) -> CliCommand {
+    let stream = StreamArgs::from_matches(matches);
+    match mode {
+        Mode::Peer => {
+            let mut peer = PeerConfig::default();
+            if let Some(name) = matches.get_one::<String>("name") {
+                peer.name = name.clone();
+            }
+            if let Some(port) = matches.get_one::<u16>("port") {
+                peer.preferred_port = *port;
+            }
+            if let Some(mode) = matches.get_one::<DiscoveryMode>("discovery") {
+                peer.discovery_mode = Some(*mode);
+            }
+            if let Some(profile) = matches.get_one::<DeviceProfile>("profile") {
+                peer.profile = Some(*profile);
+            }
+            peer.talkback_device = matches.get_one::<String>("talkback").cloned();
+            peer.subscribed_tracks = matches.get_one::<Vec<u8>>("tracks").cloned();
+            peer.auto_connect &= !flag(matches, "no-auto-connect");
+            if stream.psk.is_some() {
+                peer.psk = stream.psk.clone();
+            }
+            peer.allow_plaintext |= stream.allow_plaintext;
+            peer.packet_format = stream.packet_format.or(peer.packet_format);
+            peer.backend = stream.backend.or(peer.backend);
+            peer.qos &= !stream.no_qos;
+            peer.packet_dump = stream.dump_packets.clone().or(peer.packet_dump);
+            peer.pcap = stream.pcap.clone().or(peer.pcap);
+            stream.apply_stats(&mut peer.stats);
+            CliCommand::Peer(peer)
+        }
+        Mode::Send => CliCommand::Send(SendArgs {
+            target: matches.get_one::<String>("target").cloned(),
+            pin: matches.get_one::<String>("pin").cloned(),
+            stream,
+        }),
+        Mode::Recv => CliCommand::Recv(RecvArgs {
+            port: matches.get_one::<u16>("port").copied(),
+            profile: matches.get_one::<DeviceProfile>("profile").copied(),
+            service_action: if flag(matches, "install-service") {
+                Some(ServiceAction::Install)
+            } else if flag(matches, "uninstall-service") {
+                Some(ServiceAction::Uninstall)
+            } else {
+                None
+            },
+            as_service: flag(matches, "service"),
+            stream,
+        }),
+    }
+}
+
+fn stream_args(receiving: bool) -> Vec<Arg> {
+    let mut args = vec![
+        Arg::new("psk")
+            .short('k')
+            .long("psk")
+            .value_name("KEY")
+            .env(PSK_ENV_VAR)
+            .hide_env_values(true)
+            .help("Pre-shared key encrypting the audio"),
+        Arg::new("packet-format")
+            .long("packet-format")
+            .value_name("FORMAT")
+            .env(PACKET_FORMAT_ENV_VAR)
+            .value_parser(parse::<PacketFormat>)
+            .help("native, or rtp for GStreamer/VLC"),
+        backend_arg(),
+        Arg::new("no-qos")
+            .long("no-qos")
+            .action(ArgAction::SetTrue)
+            .help("No MMCSS, qWave or DSCP marking (or LAN_AUDIO_QOS=0)"),
+        Arg::new("stats-interval")
+            .long("stats-interval")
+            .value_name("SECS")
+            .env(STATS_INTERVAL_ENV_VAR)
+            .value_parser(value_parser!(u64))
+            .help("Interval between stats lines in the log"),
+        Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .env(QUIET_ENV_VAR)
+            .action(ArgAction::SetTrue)
+            .help("No periodic stats in the log"),
+        Arg::new("latency-probe")
+            .long("latency-probe")
+            .env(LATENCY_PROBE_ENV_VAR)
+            .action(ArgAction::SetTrue)
+            .help("Measurement mode: sent tracks carry a latency probe"),
+        Arg::new("pcap")
+            .long("pcap")
+            .value_name("FILE")
+            .env(PCAP_ENV_VAR)
+            .value_parser(value_parser!(PathBuf))
+            .help("Capture the packets sent and received to a pcapng file for Wireshark"),
+    ];
+    if receiving {
+        args.extend([
+            Arg::new("allow-plaintext")
+                .long("allow-plaintext")
+                .env(ALLOW_PLAINTEXT_ENV_VAR)
+                .action(ArgAction::SetTrue)
+                .help("With a key, still accept tracks the sender marks as plaintext"),
+            Arg::new("probe-loopback")
+                .long("probe-loopback")
+                .value_name("DEVICE")
+                .env(PROBE_LOOPBACK_ENV_VAR)
+                .help("Input of an analog loopback listening for the latency probe"),
+            Arg::new("dump-packets")
+                .long("dump-packets")
+                .value_name("FILE")
+                .env(PACKET_DUMP_ENV_VAR)
+                .value_parser(value_parser!(PathBuf))
+                .help("Dump every received audio packet to FILE, for `replay`"),
+        ]);
+    }
+    args
+}
+
+fn port_arg() -> Arg {
+    Arg::new("port")
+        .short('p')
+        .long("port")
+        .value_name("PORT")
+        .env(PORT_ENV_VAR)
+        .value_parser(value_parser!(u16))
+        .help("UDP port for audio [default: 5000]")
+}
+
+fn profile_arg() -> Arg {
+    Arg::new("profile")
+        .long("profile")
+        .value_name("PROFILE")
+        .env(PROFILE_ENV_VAR)
+        .value_parser(parse::<DeviceProfile>)
+        .help("desktop, or low-power for a Raspberry Pi")
+}
+
+fn backend_arg() -> Arg {
+    Arg::new("backend")
+        .short('b')
+        .long("backend")
+        .value_name("BACKEND")
+        .env(AUDIO_BACKEND_ENV_VAR)
+        .value_parser(parse::<AudioBackend>)
+        .help("Audio backend: default, jack or pipewire")
+}
+
+/// Value parser for the `FromStr` settings of the configuration
+fn parse<T: FromStr<Err = String>>(value: &str) -> std::result::Result<T, String> {
+    value.parse()
+}
+
+/// JSON params of `ctl call`
+fn parse_json(value: &str) -> std::result::Result<serde_json::Value, String> {
+    serde_json::from_str(value).map_err(|e| format!("Invalid JSON: {}", e))
+}
+
+/// Track list like "0,2,5"
+fn parse_track_ids(list: &str) -> std::result::Result<Vec<u8>, String> {
+    list.split(',')
+        .map(|id| id.trim().parse().map_err(|_| format!("Invalid track ID: {}", id)))
+        .collect()
+}
+
+/// Value of an option that only some subcommands have
+fn value<T: Clone + Send + Sync + 'static>(matches: &ArgMatches, id: &str) -> Option<T> {
+    matches.try_get_one::<T>(id).ok().flatten().cloned()
+}
+
+fn flag(matches: &ArgMatches, id: &str) -> bool {
+    value(matches, id).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(mode: Mode, args: &[&str]) -> Cli {
+        Cli::try_parse_from(mode, args.iter().copied()).unwrap()
+    }
+
+    #[test]
+    fn test_default_mode() {
+        // A binary runs its own mode without a subcommand
+        let cli = parse(Mode::Send, &["sender", "192.168.1.20:5000", "--psk", "secret", "--pin", "042917"]);
+        match cli.command {
+            CliCommand::Send(args) => {
+                assert_eq!(args.target.as_deref(), Some("192.168.1.20:5000"));
+                assert_eq!(args.stream.psk.as_deref(), Some("secret"));
+                assert_eq!(args.pin.as_deref(), Some("042917"));
+            }
+            other => panic!("expected send, got {:?}", other),
+        }
+
+        let cli = parse(Mode::Peer, &["peer", "-n", "Studio", "--tracks", "0,2", "--no-auto-connect"]);
+        match cli.command {
+            CliCommand::Peer(peer) => {
+                assert_eq!(peer.name, "Studio");
+                assert_eq!(peer.subscribed_tracks, Some(vec![0, 2]));
+                assert!(!peer.auto_connect);
+            }
+            other => panic!("expected peer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let cli = parse(Mode::Peer, &["peer", "recv", "--port", "5100", "--profile", "low-power", "-c", "/etc/lan-audio.toml"]);
+        assert_eq!(cli.config, Some(PathBuf::from("/etc/lan-audio.toml")));
+        match cli.command {
+            CliCommand::Recv(ref args) => {
+                assert_eq!((args.port, args.profile), (Some(5100), Some(DeviceProfile::LowPower)));
+            }
+            ref other => panic!("expected recv, got {:?}", other),
+        }
+        assert_eq!(cli.command.mode(), Some(Mode::Recv));
+        match parse(Mode::Recv, &["receiver", "--install-service", "--port", "5100"]).command {
+            CliCommand::Recv(args) => assert_eq!((args.service_action, args.as_service), (Some(ServiceAction::Install), false)),
+            other => panic!("expected recv, got {:?}", other),
+        }
+        assert!(Cli::try_parse_from(Mode::Recv, ["receiver", "--install-service", "--uninstall-service"]).is_err());
+
+        // A subcommand name isn't taken for the sender's target
+        assert!(matches!(parse(Mode::Send, &["sender", "devices"]).command, CliCommand::Devices { backend: None }));
+        match parse(Mode::Recv, &["receiver", "discover", "--timeout", "2"]).command {
+            CliCommand::Discover { mode, timeout } => {
+                assert_eq!((mode, timeout), (DiscoveryMode::Both, Duration::from_secs(2)));
+            }
+            other => panic!("expected discover, got {:?}", other),
+        }
+
+        // The config file of a peer holds its routing
+        match parse(Mode::Send, &["sender", "peer", "--config", "peer.toml"]).command {
+            CliCommand::Peer(peer) => assert_eq!(peer.config_path, Some(PathBuf::from("peer.toml"))),
+            other => panic!("expected peer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ctl() {
+        let ctl = |args: &[&str]| match parse(Mode::Recv, args).command {
+            CliCommand::Ctl(args) => args,
+            other => panic!("expected ctl, got {:?}", other),
+        };
+
+        let args = ctl(&["receiver", "ctl", "track", "mute", "2", "--address", "192.168.1.20:8081"]);
+        assert_eq!(args.command, CtlCommand::Mute { track_id: 2, muted: true });
+        assert_eq!(args.address.as_deref(), Some("192.168.1.20:8081"));
+        assert_eq!(ctl(&["receiver", "ctl", "tracks", "list"]).command, CtlCommand::Tracks);
+        assert_eq!(ctl(&["receiver", "ctl", "tracks"]).command, CtlCommand::Tracks);
+        assert_eq!(
+            ctl(&["receiver", "ctl", "stats", "--follow", "-c", "pi.toml"]),
+            CtlArgs {
+                address: None,
+                config: Some(PathBuf::from("pi.toml")),
+                command: CtlCommand::Stats { follow: true, interval: Duration::from_secs(1) },
+            }
+        );
+        assert_eq!(
+            ctl(&["receiver", "ctl", "call", "SetRoute", r#"{"track_id":0,"destinations":null}"#]).command,
+            CtlCommand::Call {
+                method: "SetRoute".to_string(),
+                params: Some(serde_json::json!({ "track_id": 0, "destinations": null })),
+            }
+        );
+
+        assert!(Cli::try_parse_from(Mode::Recv, ["receiver", "ctl"]).is_err());
+        assert!(Cli::try_parse_from(Mode::Recv, ["receiver", "ctl", "track", "mute", "x"]).is_err());
+        assert!(Cli::try_parse_from(Mode::Recv, ["receiver", "ctl", "call", "GetStatus", "{"]).is_err());
+    }
+
+    #[test]
+    fn test_self_test() {
+        match parse(Mode::Peer, &["peer", "--self-test"]).command {
+            CliCommand::SelfTest(args) => assert_eq!(args, SelfTestArgs::default()),
+            other => panic!("expected self-test, got {:?}", other),
+        }
+        let cli = parse(Mode::Recv, &["receiver", "--self-test", "--self-test-output", "hw:1", "--self-test-secs", "30"]);
+        assert_eq!(cli.command.mode(), None);
+        match cli.command {
+            CliCommand::SelfTest(args) => {
+                assert_eq!(args.output.as_deref(), Some("hw:1"));
+                assert_eq!(args.duration, Duration::from_secs(30));
+            }
+            other => panic!("expected self-test, got {:?}", other),
+        }
+
+        assert!(Cli::try_parse_from(Mode::Peer, ["peer", "--self-test-output", "hw:1"]).is_err());
+        assert!(Cli::try_parse_from(Mode::Peer, ["peer", "--self-test", "--self-test-secs", "0"]).is_err());
+        assert!(Cli::try_parse_from(Mode::Peer, ["peer", "--self-test", "devices"]).is_err());
+    }
+
+    #[test]
+    fn test_dump_and_replay() {
+        match parse(Mode::Recv, &["receiver", "--dump-packets", "glitch.lapd"]).command {
+            CliCommand::Recv(args) => {
+                let mut config = AppConfig::default();
+                args.apply(&mut config);
+                assert_eq!(config.network.packet_dump, Some(PathBuf::from("glitch.lapd")));
+            }
+            other => panic!("expected recv, got {:?}", other),
+        }
+        match parse(Mode::Peer, &["peer", "--dump-packets", "glitch.lapd"]).command {
+            CliCommand::Peer(peer) => assert_eq!(peer.packet_dump, Some(PathBuf::from("glitch.lapd"))),
+            other => panic!("expected peer, got {:?}", other),
+        }
+        assert!(Cli::try_parse_from(Mode::Send, ["sender", "--dump-packets", "glitch.lapd"]).is_err());
+        match parse(Mode::Send, &["sender", "192.168.1.20", "--pcap", "stream.pcapng"]).command {
+            CliCommand::Send(args) => assert_eq!(args.stream.pcap, Some(PathBuf::from("stream.pcapng"))),
+            other => panic!("expected send, got {:?}", other),
+        }
+
+        let cli = parse(Mode::Send, &["sender", "replay", "glitch.lapd", "--output", "null"]);
+        assert_eq!(cli.command.mode(), None);
+        match cli.command {
+            CliCommand::Replay(args) => {
+                assert_eq!(args.file, PathBuf::from("glitch.lapd"));
+                assert_eq!(args.output.as_deref(), Some("null"));
+            }
+            other => panic!("expected replay, got {:?}", other),
+        }
+        assert!(Cli::try_parse_from(Mode::Recv, ["receiver", "replay"]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(Cli::try_parse_from(Mode::Peer, ["peer", "--tracks", "0,x"]).is_err());
+        assert!(Cli::try_parse_from(Mode::Peer, ["peer", "--discovery", "carrier-pigeon"]).is_err());
+        // Options of another mode
+        assert!(Cli::try_parse_from(Mode::Send, ["sender", "--talkback", "mic"]).is_err());
+    }
+
+    #[test]
+    fn test_overrides_config_file() {
+        let mut config = AppConfig::default();
+        config.stats.quiet = true;
+        let args = RecvArgs {
+            port: Some(5100),
+            stream: StreamArgs {
+                packet_format: Some(PacketFormat::Rtp),
+                stats_interval: Some(30),
+                ..StreamArgs::default()
+            },
+            ..RecvArgs::default()
+        };
+        args.apply(&mut config);
+        assert_eq!(config.network.udp_port, 5100);
+        assert_eq!(config.network.packet_format, PacketFormat::Rtp);
+        // Options not given keep the file's settings
+        assert!(config.stats.quiet);
+        assert_eq!(config.stats.interval_secs, 30);
+    }
+}
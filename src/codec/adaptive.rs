@@ -0,0 +1,222 @@
+//! Adaptive bitrate control
+//!
+//! Adjusts the encoder bitrate and expected packet loss from receiver
+//! feedback: multiplicative decrease on loss, slow additive recovery
+//! towards the configured bitrate after several clean reports. Congestion
+//! control (`network::congestion`) can put a ceiling on top of that.
+
+use crate::network::feedback::TrackFeedback;
+
+/// Lowest bitrate the controller will go down to
+pub const MIN_ADAPTIVE_BITRATE: u32 = 24_000;
+
+/// Loss above this fraction lowers the bitrate
+const LOSS_DECREASE_THRESHOLD: f32 = 0.05;
+
+/// Loss below this fraction counts as a clean report
+const LOSS_CLEAN_THRESHOLD: f32 = 0.01;
+
+/// Clean reports required before raising the bitrate
+const CLEAN_REPORTS_FOR_INCREASE: u32 = 3;
+
+/// Bitrate multiplier on congestion
+const DECREASE_FACTOR: f32 = 0.75;
+
+/// Bitrate step on recovery (fraction of the configured bitrate)
+const INCREASE_STEP: f32 = 0.1;
+
+/// Upper bound for the Opus packet loss hint
+const MAX_PACKET_LOSS_PERC: u8 = 30;
+
+/// Encoder settings chosen by the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitrateDecision {
+    pub bitrate: u32,
+    pub packet_loss_perc: u8,
+}
+
+/// Per-track adaptive bitrate controller
+#[derive(Debug, Clone)]
+pub struct AdaptiveBitrate {
+    max_bitrate: u32,
+    min_bitrate: u32,
+    /// Bitrate chosen from the track's loss reports
+    target: u32,
+    /// Ceiling from congestion control
+    cap: Option<u32>,
+    current: BitrateDecision,
+    clean_reports: u32,
+    /// Smoothed loss fraction
+    loss_estimate: f32,
+}
+
+impl AdaptiveBitrate {
+    /// Create a controller starting at (and capped by) the configured bitrate
+    pub fn new(max_bitrate: u32, packet_loss_perc: u8) -> Self {
+        Self {
+            max_bitrate,
+            min_bitrate: MIN_ADAPTIVE_BITRATE.min(max_bitrate),
+            target: max_bitrate,
+            cap: None,
+            current: BitrateDecision {
+                bitrate: max_bitrate,
+                packet_loss_perc,
+            },
+            clean_reports: 0,
+            loss_estimate: packet_loss_perc as f32 / 100.0,
+        }
+    }
+
+    /// Current encoder settings
+    pub fn current(&self) -> BitrateDecision {
+        self.current
+    }
+
+    /// Configured bitrate the controller recovers to
+    pub fn max_bitrate(&self) -> u32 {
+        self.max_bitrate
+    }
+
+    /// Take a new configured bitrate and start again from it; returns new
+    /// settings if they changed
+    pub fn set_max_bitrate(&mut self, max_bitrate: u32) -> Option<BitrateDecision> {
+        self.max_bitrate = max_bitrate;
+        self.min_bitrate = MIN_ADAPTIVE_BITRATE.min(max_bitrate);
+        self.target = max_bitrate;
+        self.clean_reports = 0;
+        self.decide(self.current.packet_loss_perc)
+    }
+
+    /// Bitrate the track would use without the congestion ceiling
+    pub fn uncapped_bitrate(&self) -> u32 {
+        self.target
+    }
+
+    /// Process a receiver report; returns new settings if they changed
+    pub fn update(&mut self, feedback: &TrackFeedback) -> Option<BitrateDecision> {
+        let loss = feedback.loss_fraction.clamp(0.0, 1.0);
+        self.loss_estimate = 0.5 * self.loss_estimate + 0.5 * loss;
+
+        let mut bitrate = self.target;
+        if loss > LOSS_DECREASE_THRESHOLD {
+            self.clean_reports = 0;
+            bitrate = ((bitrate as f32 * DECREASE_FACTOR) as u32).max(self.min_bitrate);
+        } else if loss < LOSS_CLEAN_THRESHOLD {
+            self.clean_reports += 1;
+            if self.clean_reports >= CLEAN_REPORTS_FOR_INCREASE {
+                self.clean_reports = 0;
+                let step = (self.max_bitrate as f32 * INCREASE_STEP) as u32;
+                bitrate = (bitrate + step.max(1)).min(self.max_bitrate);
+            }
+        } else {
+            self.clean_reports = 0;
+        }
+
+        self.target = bitrate;
+        let packet_loss_perc = ((self.loss_estimate * 100.0).round() as u8).min(MAX_PACKET_LOSS_PERC);
+        self.decide(packet_loss_perc)
+    }
+
+    /// Set the congestion control ceiling (None lifts it); returns new
+    /// settings if they changed
+    pub fn set_cap(&mut self, cap: Option<u32>) -> Option<BitrateDecision> {
+        self.cap = cap;
+        self.decide(self.current.packet_loss_perc)
+    }
+
+    fn decide(&mut self, packet_loss_perc: u8) -> Option<BitrateDecision> {
+        let bitrate = match self.cap {
+            Some(cap) => self.target.min(cap.max(self.min_bitrate)),
+            None => self.target,
+        };
+        let decision = BitrateDecision {
+            bitrate,
+            packet_loss_perc,
+        };
+
+        if decision == self.current {
+            return None;
+        }
+        self.current = decision;
+        Some(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(loss_fraction: f32) -> TrackFeedback {
+        TrackFeedback {
+            track_id: 0,
+            loss_fraction,
+            jitter_us: 0,
+            queuing_delay_us: 0,
+        }
+    }
+
+    #[test]
+    fn test_decrease_on_loss() {
+        let mut abr = AdaptiveBitrate::new(128_000, 0);
+        let decision = abr.update(&report(0.2)).unwrap();
+        assert_eq!(decision.bitrate, 96_000);
+        assert_eq!(decision.packet_loss_perc, 10);
+
+        // Never below the floor
+        for _ in 0..20 {
+            abr.update(&report(0.2));
+        }
+        assert_eq!(abr.current().bitrate, MIN_ADAPTIVE_BITRATE);
+    }
+
+    #[test]
+    fn test_recovery_after_clean_reports() {
+        let mut abr = AdaptiveBitrate::new(100_000, 0);
+        abr.update(&report(0.1));
+        let reduced = abr.current().bitrate;
+
+        for _ in 0..CLEAN_REPORTS_FOR_INCREASE * 10 {
+            abr.update(&report(0.0));
+        }
+        assert!(abr.current().bitrate > reduced);
+        assert_eq!(abr.current().bitrate, 100_000);
+        assert_eq!(abr.current().packet_loss_perc, 0);
+
+        // Stable settings produce no decision
+        assert!(abr.update(&report(0.0)).is_none());
+    }
+
+    #[test]
+    fn test_congestion_cap() {
+        let mut abr = AdaptiveBitrate::new(128_000, 0);
+        assert_eq!(abr.set_cap(Some(64_000)).unwrap().bitrate, 64_000);
+        assert_eq!(abr.uncapped_bitrate(), 128_000);
+
+        // Loss lowers the track below the ceiling, recovery stops at it
+        abr.update(&report(0.2));
+        assert_eq!((abr.current().bitrate, abr.uncapped_bitrate()), (64_000, 96_000));
+        abr.update(&report(0.2));
+        abr.update(&report(0.2));
+        assert_eq!(abr.current().bitrate, 54_000);
+
+        // Never below the floor, and back to the loss-based bitrate
+        assert_eq!(abr.set_cap(Some(1_000)).unwrap().bitrate, MIN_ADAPTIVE_BITRATE);
+        assert_eq!(abr.set_cap(None).unwrap().bitrate, 54_000);
+        assert!(abr.set_cap(None).is_none());
+    }
+
+    #[test]
+    fn test_new_max_bitrate() {
+        let mut abr = AdaptiveBitrate::new(128_000, 0);
+        abr.update(&report(0.2));
+        assert_eq!(abr.current().bitrate, 96_000);
+
+        // A new configured bitrate is taken at once, under the congestion ceiling
+        assert_eq!(abr.set_max_bitrate(64_000).unwrap().bitrate, 64_000);
+        assert_eq!(abr.max_bitrate(), 64_000);
+        abr.set_cap(Some(100_000));
+        assert_eq!(abr.set_max_bitrate(192_000).unwrap().bitrate, 100_000);
+        assert_eq!(abr.uncapped_bitrate(), 192_000);
+        assert!(abr.set_max_bitrate(192_000).is_none());
+    }
+}
@@ -1,13 +1,98 @@
 //! Opus decoder wrapper
 //!
 //! Provides Opus decoding with packet loss concealment.
+//!
+//! Receivers hold their decoder as an [`AudioDecoder`], so lossless tracks
+//! (`codec::flac`) share the jitter buffer, FEC, DRED and concealment
+//! paths with Opus tracks. Surround tracks (more than two channels) are
+//! decoded with the multistream decoder (`codec::multistream`).
 
-use opus::{Channels, Decoder};
+use opus::Channels;
+#[cfg(not(feature = "dred"))]
+use opus::Decoder;
+#[cfg(feature = "dred")]
+use crate::codec::dred::Decoder;
+use crate::audio::pool;
+use crate::codec::multistream::{self, SurroundDecoder};
+use crate::codec::FlacDecoder;
 use crate::error::CodecError;
+use crate::protocol::Codec;
+
+/// Decoder of a received track, whichever its codec
+pub trait AudioDecoder: Send {
+    fn codec(&self) -> Codec;
+    
+    /// Decode a packet to interleaved f32 samples
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<f32>, CodecError>;
+    
+    /// Rebuild the frame before `data` from its in-band FEC
+    fn decode_fec(&mut self, data: &[u8]) -> Result<Vec<f32>, CodecError>;
+    
+    /// Rebuild the frame `frames_back` before `data` from its DRED history
+    fn decode_dred(&mut self, data: &[u8], frames_back: u32) -> Result<Option<Vec<f32>>, CodecError>;
+    
+    /// Conceal a lost frame
+    fn decode_plc(&mut self) -> Result<Vec<f32>, CodecError>;
+    
+    fn reset(&mut self) -> Result<(), CodecError>;
+    
+    fn sample_rate(&self) -> u32;
+    
+    fn channels(&self) -> u16;
+    
+    /// Frame size in samples (per channel)
+    fn frame_size(&self) -> usize;
+}
+
+/// Create the decoder for a track received with `codec`
+pub fn new_decoder(
+    codec: Codec,
+    sample_rate: u32,
+    channels: u16,
+    frame_size: usize,
+) -> Result<Box<dyn AudioDecoder>, CodecError> {
+    Ok(match codec {
+        Codec::Opus => Box::new(OpusDecoder::new(sample_rate, channels, frame_size)?),
+        Codec::Flac => Box::new(FlacDecoder::new(sample_rate, channels, frame_size)?),
+    })
+}
+
+/// libopus decoder of a mono/stereo stream or of a surround layout
+enum Handle {
+    Single(Decoder),
+    Surround(SurroundDecoder),
+}
+
+impl Handle {
+    fn decode_float(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize, String> {
+        match self {
+            Handle::Single(decoder) => decoder.decode_float(input, output, fec).map_err(|e| e.to_string()),
+            Handle::Surround(decoder) => decoder.decode_float(input, output, fec).map_err(|e| e.to_string()),
+        }
+    }
+    
+    fn reset_state(&mut self) -> Result<(), String> {
+        match self {
+            Handle::Single(decoder) => decoder.reset_state().map_err(|e| e.to_string()),
+            Handle::Surround(decoder) => decoder.reset_state().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Channels to decode a track's packets with: its surround layout from
+/// the track catalog (`track_channels`), mono or stereo by the packet flag
+/// otherwise
+pub fn decoder_channels(is_stereo: bool, track_channels: u16) -> u16 {
+    match is_stereo {
+        true if multistream::is_surround(track_channels) => track_channels,
+        true => 2,
+        false => 1,
+    }
+}
 
 /// Opus decoder wrapper
 pub struct OpusDecoder {
-    decoder: Decoder,
+    decoder: Handle,
     sample_rate: u32,
     channels: u16,
     frame_size: usize,
@@ -24,20 +109,23 @@ pub struct OpusDecoder {
 impl OpusDecoder {
     /// Create a new Opus decoder
     pub fn new(sample_rate: u32, channels: u16, frame_size: usize) -> Result<Self, CodecError> {
-        let opus_channels = match channels {
-            1 => Channels::Mono,
-            2 => Channels::Stereo,
+        let decoder = match channels {
+            1 => Handle::Single(Decoder::new(sample_rate, Channels::Mono)
+                .map_err(|e| CodecError::DecoderInit(e.to_string()))?),
+            2 => Handle::Single(Decoder::new(sample_rate, Channels::Stereo)
+                .map_err(|e| CodecError::DecoderInit(e.to_string()))?),
+            channels if multistream::is_surround(channels) => Handle::Surround(
+                SurroundDecoder::new(sample_rate, channels)
+                    .map_err(|e| CodecError::DecoderInit(e.to_string()))?,
+            ),
             _ => return Err(CodecError::DecoderInit(
                 format!("Unsupported channel count: {}", channels)
             )),
         };
         
-        let decoder = Decoder::new(sample_rate, opus_channels)
-            .map_err(|e| CodecError::DecoderInit(e.to_string()))?;
-        
         // Pre-allocate decoding buffer for max frame size
-        // 120ms at 48kHz stereo = 11520 samples
-        let decode_buffer = vec![0.0f32; 48000 * 2 * 120 / 1000];
+        // 120ms at 48kHz = 5760 samples per channel
+        let decode_buffer = vec![0.0f32; 48000 * channels as usize * 120 / 1000];
         
         Ok(Self {
             decoder,
@@ -52,51 +140,91 @@ impl OpusDecoder {
     }
     
     /// Decode Opus packet to audio samples
-    /// Returns interleaved f32 samples
+    /// Returns interleaved f32 samples; the frame size follows the packet
+    /// (Opus packets carry their duration), so concealment after a
+    /// frame size change covers a frame of the new size
     pub fn decode(&mut self, data: &[u8]) -> Result<Vec<f32>, CodecError> {
         let samples = self.decoder
             .decode_float(data, &mut self.decode_buffer, false)
-            .map_err(|e| CodecError::DecodingFailed(e.to_string()))?;
+            .map_err(CodecError::DecodingFailed)?;
         
+        self.frame_size = samples;
         let total_samples = samples * self.channels as usize;
         self.frames_decoded += 1;
         self.samples_produced += total_samples as u64;
         
-        Ok(self.decode_buffer[..total_samples].to_vec())
+        Ok(pool::samples().take_copy(&self.decode_buffer[..total_samples]))
     }
     
     /// Decode with FEC (Forward Error Correction)
-    /// Use when the previous packet was lost
+    /// Use when the previous packet was lost: `data` is the packet following
+    /// the lost one, the returned samples replace the lost frame
     pub fn decode_fec(&mut self, data: &[u8]) -> Result<Vec<f32>, CodecError> {
+        // Output length tells Opus the duration of the lost frame
+        let frame_len = self.frame_len();
         let samples = self.decoder
-            .decode_float(data, &mut self.decode_buffer, true)
-            .map_err(|e| CodecError::DecodingFailed(e.to_string()))?;
+            .decode_float(data, &mut self.decode_buffer[..frame_len], true)
+            .map_err(CodecError::DecodingFailed)?;
         
         let total_samples = samples * self.channels as usize;
         self.frames_decoded += 1;
         self.samples_produced += total_samples as u64;
         
-        Ok(self.decode_buffer[..total_samples].to_vec())
+        Ok(pool::samples().take_copy(&self.decode_buffer[..total_samples]))
+    }
+    
+    /// Decode a lost frame from the DRED history in `data`, the packet
+    /// `frames_back` frames after it. Returns None if the packet carries no
+    /// history that far back (always without the `dred` feature).
+    #[cfg(feature = "dred")]
+    pub fn decode_dred(&mut self, data: &[u8], frames_back: u32) -> Result<Option<Vec<f32>>, CodecError> {
+        let frame_len = self.frame_len();
+        let offset = self.frame_size * frames_back as usize;
+        // Surround streams carry no DRED history
+        let Handle::Single(decoder) = &mut self.decoder else {
+            return Ok(None);
+        };
+        let samples = decoder
+            .decode_dred(data, offset, &mut self.decode_buffer[..frame_len])
+            .map_err(|e| CodecError::DecodingFailed(e.to_string()))?;
+        
+        Ok(samples.map(|samples| {
+            let total_samples = samples * self.channels as usize;
+            self.frames_decoded += 1;
+            self.samples_produced += total_samples as u64;
+            pool::samples().take_copy(&self.decode_buffer[..total_samples])
+        }))
+    }
+    
+    #[cfg(not(feature = "dred"))]
+    pub fn decode_dred(&mut self, _data: &[u8], _frames_back: u32) -> Result<Option<Vec<f32>>, CodecError> {
+        Ok(None)
     }
     
     /// Generate packet loss concealment samples
     /// Use when a packet is lost and no FEC is available
     pub fn decode_plc(&mut self) -> Result<Vec<f32>, CodecError> {
+        let frame_len = self.frame_len();
         let samples = self.decoder
-            .decode_float(&[], &mut self.decode_buffer, false)
-            .map_err(|e| CodecError::DecodingFailed(e.to_string()))?;
+            .decode_float(&[], &mut self.decode_buffer[..frame_len], false)
+            .map_err(CodecError::DecodingFailed)?;
         
         let total_samples = samples * self.channels as usize;
         self.frames_lost += 1;
         self.samples_produced += total_samples as u64;
         
-        Ok(self.decode_buffer[..total_samples].to_vec())
+        Ok(pool::samples().take_copy(&self.decode_buffer[..total_samples]))
+    }
+    
+    /// Interleaved length of one frame, bounded by the decode buffer
+    fn frame_len(&self) -> usize {
+        (self.frame_size * self.channels as usize).min(self.decode_buffer.len())
     }
     
     /// Reset decoder state
     pub fn reset(&mut self) -> Result<(), CodecError> {
         self.decoder.reset_state()
-            .map_err(|e| CodecError::DecoderInit(e.to_string()))
+            .map_err(CodecError::DecoderInit)
     }
     
     /// Get sample rate
@@ -136,6 +264,44 @@ impl OpusDecoder {
     }
 }
 
+impl AudioDecoder for OpusDecoder {
+    fn codec(&self) -> Codec {
+        Codec::Opus
+    }
+    
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<f32>, CodecError> {
+        OpusDecoder::decode(self, data)
+    }
+    
+    fn decode_fec(&mut self, data: &[u8]) -> Result<Vec<f32>, CodecError> {
+        OpusDecoder::decode_fec(self, data)
+    }
+    
+    fn decode_dred(&mut self, data: &[u8], frames_back: u32) -> Result<Option<Vec<f32>>, CodecError> {
+        OpusDecoder::decode_dred(self, data, frames_back)
+    }
+    
+    fn decode_plc(&mut self) -> Result<Vec<f32>, CodecError> {
+        OpusDecoder::decode_plc(self)
+    }
+    
+    fn reset(&mut self) -> Result<(), CodecError> {
+        OpusDecoder::reset(self)
+    }
+    
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+    
+    fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+}
+
 /// Decoder statistics
 #[derive(Debug, Clone)]
 pub struct DecoderStats {
@@ -189,7 +355,40 @@ mod tests {
         let plc_samples = decoder.decode_plc();
         assert!(plc_samples.is_ok());
         
+        // One frame of concealment, not the whole decode buffer
+        assert_eq!(plc_samples.unwrap().len(), 480 * 2);
+        
         let stats = decoder.stats();
         assert_eq!(stats.frames_lost, 1);
     }
+    
+    #[test]
+    fn test_frame_size_follows_packets() {
+        let mut encoder = OpusEncoder::music(48000, 2).unwrap();
+        let mut decoder = OpusDecoder::new(48000, 2, encoder.frame_size()).unwrap();
+        
+        // The sender switches to 2.5 ms frames
+        encoder.set_frame_size(120).unwrap();
+        let decoded = decoder.decode(&encoder.encode(&[0.0; 240]).unwrap()).unwrap();
+        assert_eq!((decoded.len(), decoder.frame_size()), (240, 120));
+        assert_eq!(decoder.decode_plc().unwrap().len(), 240);
+    }
+    
+    #[test]
+    fn test_surround_roundtrip() {
+        let mut encoder = OpusEncoder::music(48000, 8).unwrap();
+        let mut decoder = OpusDecoder::new(48000, 8, encoder.frame_size()).unwrap();
+        assert!(OpusDecoder::new(48000, 9, 480).is_err());
+        
+        let samples = vec![0.1f32; encoder.samples_per_frame()];
+        let encoded = encoder.encode(&samples).unwrap();
+        assert_eq!(decoder.decode(&encoded).unwrap().len(), 480 * 8);
+        assert_eq!(decoder.decode_fec(&encoded).unwrap().len(), 480 * 8);
+        assert_eq!(decoder.decode_plc().unwrap().len(), 480 * 8);
+        assert_eq!(decoder.decode_dred(&encoded, 1).unwrap(), None);
+        
+        assert_eq!(decoder_channels(true, 8), 8);
+        assert_eq!(decoder_channels(true, 2), 2);
+        assert_eq!(decoder_channels(false, 6), 1);
+    }
 }
@@ -0,0 +1,425 @@
+//! Opus deep redundancy (DRED)
+//!
+//! libopus 1.5 can embed a compact, neurally coded history of up to one
+//! second of audio in every packet. In-band FEC only carries the previous
+//! frame, so a burst of losses (typical on Wi-Fi) leaves all but the last
+//! frame to concealment; with DRED the first packet after the gap rebuilds
+//! the whole burst.
+//!
+//! The `opus` crate predates the DRED API and does not expose its raw
+//! encoder/decoder handles, so with the `dred` feature the codec wrappers
+//! use the minimal libopus bindings below instead. The linked libopus must
+//! be 1.5 or newer and built with `--enable-dred` (e.g. a system library
+//! found through pkg-config or `LIBOPUS_LIB_DIR`). Without the feature
+//! DRED is never advertised and the track option has no effect.
+
+use crate::audio::buffer::{AudioFrame, JitterBuffer};
+use crate::codec::AudioDecoder;
+use crate::constants::DEFAULT_DRED_DURATION_MS;
+use crate::error::CodecError;
+
+#[cfg(feature = "dred")]
+pub(crate) use ffi::{Decoder, Encoder};
+
+/// Oldest libopus release with the DRED API
+const MIN_OPUS_VERSION: (u32, u32) = (1, 5);
+
+/// Longest history libopus can carry (DRED duration is set in 10 ms units)
+pub const MAX_DRED_DURATION_MS: u16 = 1000;
+
+/// Check whether DRED can be used: built with the `dred` feature and
+/// linked against a new enough libopus
+pub fn is_available() -> bool {
+    cfg!(feature = "dred") && version_supports_dred(opus::version())
+}
+
+/// History to request for a track with deep redundancy `enabled`
+/// (0 where DRED is not available)
+pub fn duration_ms(enabled: bool) -> u16 {
+    if enabled && is_available() {
+        DEFAULT_DRED_DURATION_MS
+    } else {
+        0
+    }
+}
+
+/// Parse a libopus version string ("libopus 1.5.2", "libopus 1.5-rc1")
+fn version_supports_dred(version: &str) -> bool {
+    let Some(number) = version.split_whitespace().find(|part| part.starts_with(|c: char| c.is_ascii_digit())) else {
+        return false;
+    };
+    let mut parts = number
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().ok());
+    match (parts.next().flatten(), parts.next().flatten()) {
+        (Some(major), Some(minor)) => (major, minor) >= MIN_OPUS_VERSION,
+        _ => false,
+    }
+}
+
+/// Rebuild frames lost before `sequence` from the DRED history in
+/// `payload`, oldest first (the order the decoder state needs). Frames
+/// older than the history are left to concealment. Returns the number of
+/// frames recovered; must be called before decoding `payload` normally
+/// and before classic FEC recovery of the previous frame.
+pub fn recover_lost_frames(
+    decoder: &mut dyn AudioDecoder,
+    jitter_buffer: &mut JitterBuffer,
+    payload: &[u8],
+    sequence: u32,
+    timestamp: u64,
+) -> Result<usize, CodecError> {
+    // Silence markers carry no history
+    if payload.is_empty() {
+        return Ok(0);
+    }
+    let frame_duration_us = decoder.frame_size() as u64 * 1_000_000 / decoder.sample_rate() as u64;
+    let max_frames = (MAX_DRED_DURATION_MS as u64 * 1000 / frame_duration_us.max(1)) as u32;
+
+    // Length of the gap right before this packet
+    let mut gap = 0;
+    while gap < max_frames && jitter_buffer.is_missing(sequence.wrapping_sub(gap + 1)) {
+        gap += 1;
+    }
+
+    let mut recovered = 0;
+    for frames_back in (1..=gap).rev() {
+        let Some(samples) = decoder.decode_dred(payload, frames_back)? else {
+            // Beyond the history, a newer frame may still be covered
+            continue;
+        };
+        let frame = AudioFrame::new(
+            samples,
+            decoder.channels(),
+            timestamp.saturating_sub(frame_duration_us * frames_back as u64),
+            sequence.wrapping_sub(frames_back),
+        );
+        if jitter_buffer.insert_recovered(frame) {
+            recovered += 1;
+        }
+    }
+    Ok(recovered)
+}
+
+#[cfg(feature = "dred")]
+mod ffi {
+    //! Minimal libopus bindings with the same surface as the `opus` crate
+    //! types they replace, plus the DRED calls
+
+    use opus::{Application, Bandwidth, Bitrate, Channels, Signal};
+    use std::ffi::CStr;
+    use std::fmt;
+    use std::os::raw::{c_char, c_int};
+
+    // opus_defines.h
+    const OPUS_OK: c_int = 0;
+    const OPUS_AUTO: c_int = -1000;
+    const OPUS_BITRATE_MAX: c_int = -1;
+    const OPUS_SET_BITRATE_REQUEST: c_int = 4002;
+    const OPUS_SET_VBR_REQUEST: c_int = 4006;
+    const OPUS_SET_BANDWIDTH_REQUEST: c_int = 4008;
+    const OPUS_SET_COMPLEXITY_REQUEST: c_int = 4010;
+    const OPUS_SET_INBAND_FEC_REQUEST: c_int = 4012;
+    const OPUS_SET_PACKET_LOSS_PERC_REQUEST: c_int = 4014;
+    const OPUS_SET_DTX_REQUEST: c_int = 4016;
+    const OPUS_SET_VBR_CONSTRAINT_REQUEST: c_int = 4020;
+    const OPUS_SET_SIGNAL_REQUEST: c_int = 4024;
+    const OPUS_RESET_STATE: c_int = 4028;
+    const OPUS_SET_DRED_DURATION_REQUEST: c_int = 4050;
+
+    #[repr(C)]
+    struct OpusEncoder {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    struct OpusDecoder {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    struct OpusDREDDecoder {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    struct OpusDRED {
+        _private: [u8; 0],
+    }
+
+    extern "C" {
+        fn opus_strerror(error: c_int) -> *const c_char;
+
+        fn opus_encoder_create(fs: i32, channels: c_int, application: c_int, error: *mut c_int) -> *mut OpusEncoder;
+        fn opus_encoder_destroy(st: *mut OpusEncoder);
+        fn opus_encoder_ctl(st: *mut OpusEncoder, request: c_int, ...) -> c_int;
+        fn opus_encode_float(st: *mut OpusEncoder, pcm: *const f32, frame_size: c_int, data: *mut u8, max_data_bytes: i32) -> i32;
+
+        fn opus_decoder_create(fs: i32, channels: c_int, error: *mut c_int) -> *mut OpusDecoder;
+        fn opus_decoder_destroy(st: *mut OpusDecoder);
+        fn opus_decoder_ctl(st: *mut OpusDecoder, request: c_int, ...) -> c_int;
+        fn opus_decode_float(st: *mut OpusDecoder, data: *const u8, len: i32, pcm: *mut f32, frame_size: c_int, decode_fec: c_int) -> c_int;
+
+        fn opus_dred_decoder_create(error: *mut c_int) -> *mut OpusDREDDecoder;
+        fn opus_dred_decoder_destroy(dec: *mut OpusDREDDecoder);
+        fn opus_dred_alloc(error: *mut c_int) -> *mut OpusDRED;
+        fn opus_dred_free(dred: *mut OpusDRED);
+        fn opus_dred_parse(
+            dred_dec: *mut OpusDREDDecoder,
+            dred: *mut OpusDRED,
+            data: *const u8,
+            len: i32,
+            max_dred_samples: i32,
+            sampling_rate: i32,
+            dred_end: *mut c_int,
+            defer_processing: c_int,
+        ) -> c_int;
+        fn opus_decoder_dred_decode_float(st: *mut OpusDecoder, dred: *const OpusDRED, dred_offset: i32, pcm: *mut f32, frame_size: i32) -> c_int;
+    }
+
+    /// libopus error code
+    #[derive(Debug, Clone, Copy)]
+    pub struct Error(c_int);
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let message = unsafe { CStr::from_ptr(opus_strerror(self.0)) };
+            write!(f, "{}", message.to_string_lossy())
+        }
+    }
+
+    fn check(code: c_int) -> Result<c_int, Error> {
+        if code < OPUS_OK {
+            Err(Error(code))
+        } else {
+            Ok(code)
+        }
+    }
+
+    /// Encoder handle (replaces `opus::Encoder` with the `dred` feature)
+    pub struct Encoder {
+        ptr: *mut OpusEncoder,
+        channels: usize,
+    }
+
+    // The handle is only used through `&mut self`
+    unsafe impl Send for Encoder {}
+
+    impl Encoder {
+        pub fn new(sample_rate: u32, channels: Channels, application: Application) -> Result<Self, Error> {
+            let mut error = OPUS_OK;
+            let ptr = unsafe {
+                opus_encoder_create(sample_rate as i32, channels as c_int, application as c_int, &mut error)
+            };
+            if error != OPUS_OK || ptr.is_null() {
+                return Err(Error(error));
+            }
+            Ok(Self { ptr, channels: channels as usize })
+        }
+
+        fn set(&mut self, request: c_int, value: c_int) -> Result<(), Error> {
+            check(unsafe { opus_encoder_ctl(self.ptr, request, value) }).map(|_| ())
+        }
+
+        pub fn set_bitrate(&mut self, bitrate: Bitrate) -> Result<(), Error> {
+            let value = match bitrate {
+                Bitrate::Bits(bits) => bits,
+                Bitrate::Max => OPUS_BITRATE_MAX,
+                Bitrate::Auto => OPUS_AUTO,
+            };
+            self.set(OPUS_SET_BITRATE_REQUEST, value)
+        }
+
+        pub fn set_vbr(&mut self, vbr: bool) -> Result<(), Error> {
+            self.set(OPUS_SET_VBR_REQUEST, vbr as c_int)
+        }
+
+        pub fn set_vbr_constraint(&mut self, constrained: bool) -> Result<(), Error> {
+            self.set(OPUS_SET_VBR_CONSTRAINT_REQUEST, constrained as c_int)
+        }
+
+        pub fn set_complexity(&mut self, complexity: i32) -> Result<(), Error> {
+            self.set(OPUS_SET_COMPLEXITY_REQUEST, complexity)
+        }
+
+        pub fn set_inband_fec(&mut self, enabled: bool) -> Result<(), Error> {
+            self.set(OPUS_SET_INBAND_FEC_REQUEST, enabled as c_int)
+        }
+
+        pub fn set_packet_loss_perc(&mut self, percent: i32) -> Result<(), Error> {
+            self.set(OPUS_SET_PACKET_LOSS_PERC_REQUEST, percent)
+        }
+
+        pub fn set_dtx(&mut self, enabled: bool) -> Result<(), Error> {
+            self.set(OPUS_SET_DTX_REQUEST, enabled as c_int)
+        }
+
+        pub fn set_signal(&mut self, signal: Signal) -> Result<(), Error> {
+            self.set(OPUS_SET_SIGNAL_REQUEST, signal as c_int)
+        }
+
+        pub fn set_bandwidth(&mut self, bandwidth: Bandwidth) -> Result<(), Error> {
+            self.set(OPUS_SET_BANDWIDTH_REQUEST, bandwidth as c_int)
+        }
+
+        /// History carried in every packet, in 10 ms units (0 disables DRED)
+        pub fn set_dred_duration(&mut self, units: i32) -> Result<(), Error> {
+            self.set(OPUS_SET_DRED_DURATION_REQUEST, units)
+        }
+
+        pub fn encode_float(&mut self, input: &[f32], output: &mut [u8]) -> Result<usize, Error> {
+            let frame_size = (input.len() / self.channels) as c_int;
+            let len = unsafe {
+                opus_encode_float(self.ptr, input.as_ptr(), frame_size, output.as_mut_ptr(), output.len() as i32)
+            };
+            check(len).map(|len| len as usize)
+        }
+    }
+
+    impl Drop for Encoder {
+        fn drop(&mut self) {
+            unsafe { opus_encoder_destroy(self.ptr) }
+        }
+    }
+
+    /// Decoder handle (replaces `opus::Decoder` with the `dred` feature)
+    pub struct Decoder {
+        ptr: *mut OpusDecoder,
+        sample_rate: i32,
+        channels: usize,
+        /// DRED parser and parsed history, created on first use
+        dred: Option<(*mut OpusDREDDecoder, *mut OpusDRED)>,
+    }
+
+    // The handles are only used through `&mut self`
+    unsafe impl Send for Decoder {}
+
+    impl Decoder {
+        pub fn new(sample_rate: u32, channels: Channels) -> Result<Self, Error> {
+            let mut error = OPUS_OK;
+            let ptr = unsafe { opus_decoder_create(sample_rate as i32, channels as c_int, &mut error) };
+            if error != OPUS_OK || ptr.is_null() {
+                return Err(Error(error));
+            }
+            Ok(Self {
+                ptr,
+                sample_rate: sample_rate as i32,
+                channels: channels as usize,
+                dred: None,
+            })
+        }
+
+        pub fn decode_float(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize, Error> {
+            let data = if input.is_empty() { std::ptr::null() } else { input.as_ptr() };
+            let frame_size = (output.len() / self.channels) as c_int;
+            let samples = unsafe {
+                opus_decode_float(self.ptr, data, input.len() as i32, output.as_mut_ptr(), frame_size, fec as c_int)
+            };
+            check(samples).map(|samples| samples as usize)
+        }
+
+        pub fn reset_state(&mut self) -> Result<(), Error> {
+            check(unsafe { opus_decoder_ctl(self.ptr, OPUS_RESET_STATE) }).map(|_| ())
+        }
+
+        /// Decode `output.len()` samples of history starting `offset` samples
+        /// before `packet`. Returns None if the packet's DRED data does not
+        /// reach that far back (or carries none).
+        pub fn decode_dred(&mut self, packet: &[u8], offset: usize, output: &mut [f32]) -> Result<Option<usize>, Error> {
+            let (dred_decoder, dred) = match self.dred {
+                Some(handles) => handles,
+                None => {
+                    let mut error = OPUS_OK;
+                    let dred_decoder = unsafe { opus_dred_decoder_create(&mut error) };
+                    if error != OPUS_OK || dred_decoder.is_null() {
+                        return Err(Error(error));
+                    }
+                    let dred = unsafe { opus_dred_alloc(&mut error) };
+                    if error != OPUS_OK || dred.is_null() {
+                        unsafe { opus_dred_decoder_destroy(dred_decoder) };
+                        return Err(Error(error));
+                    }
+                    *self.dred.insert((dred_decoder, dred))
+                }
+            };
+
+            let frame_size = output.len() / self.channels;
+            let mut dred_end: c_int = 0;
+            let available = check(unsafe {
+                opus_dred_parse(
+                    dred_decoder,
+                    dred,
+                    packet.as_ptr(),
+                    packet.len() as i32,
+                    offset as i32,
+                    self.sample_rate,
+                    &mut dred_end,
+                    0,
+                )
+            })? as usize;
+            if available < offset || offset < frame_size + dred_end.max(0) as usize {
+                return Ok(None);
+            }
+
+            let samples = unsafe {
+                opus_decoder_dred_decode_float(self.ptr, dred, offset as i32, output.as_mut_ptr(), frame_size as i32)
+            };
+            check(samples).map(|samples| Some(samples as usize))
+        }
+    }
+
+    impl Drop for Decoder {
+        fn drop(&mut self) {
+            unsafe {
+                if let Some((dred_decoder, dred)) = self.dred.take() {
+                    opus_dred_free(dred);
+                    opus_dred_decoder_destroy(dred_decoder);
+                }
+                opus_decoder_destroy(self.ptr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_check() {
+        assert!(version_supports_dred("libopus 1.5.2"));
+        assert!(version_supports_dred("libopus 1.5-rc1"));
+        assert!(version_supports_dred("libopus 2.0"));
+        assert!(!version_supports_dred("libopus 1.3.1"));
+        assert!(!version_supports_dred("libopus 1.4"));
+        assert!(!version_supports_dred("unknown"));
+
+        if !cfg!(feature = "dred") {
+            assert!(!is_available());
+        }
+    }
+
+    #[test]
+    fn test_no_recovery_without_dred() {
+        use crate::codec::{OpusDecoder, OpusEncoder};
+
+        let mut encoder = OpusEncoder::voice(48000, 1).unwrap();
+        let mut decoder = OpusDecoder::new(48000, 1, encoder.frame_size()).unwrap();
+        let mut jitter = JitterBuffer::new(16, 2);
+        let silence = vec![0.0; encoder.samples_per_frame()];
+
+        for seq in [0u32, 1, 4] {
+            let payload = encoder.encode(&silence).unwrap();
+            let recovered = recover_lost_frames(&mut decoder, &mut jitter, &payload, seq, 0).unwrap();
+            if !is_available() {
+                // Without DRED the gap is left to FEC and concealment
+                assert_eq!(recovered, 0);
+            }
+            let samples = decoder.decode(&payload).unwrap();
+            jitter.insert(AudioFrame::new(samples, 1, 0, seq));
+        }
+        if !is_available() {
+            assert!(jitter.is_missing(2) && jitter.is_missing(3));
+        }
+    }
+}
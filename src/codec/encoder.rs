@@ -1,19 +1,57 @@
 //! Opus encoder wrapper
 //!
 //! Provides low-latency Opus encoding with per-track configuration.
+//! Senders hold their encoder as an [`AudioEncoder`] made by
+//! [`new_encoder`], Opus or the lossless codec (`codec::flac`); the Opus
+//! tuning (bitrate, FEC, DRED) only applies to Opus. Tracks with more
+//! than two channels are coded with the multistream encoder
+//! (`codec::multistream`).
 
-use bytes::Bytes;
-use opus::{Application, Channels, Encoder};
+use bytes::{Bytes, BytesMut};
+use opus::{Application, Channels};
+#[cfg(not(feature = "dred"))]
+use opus::Encoder;
+#[cfg(feature = "dred")]
+use crate::codec::dred::Encoder;
+use crate::codec::dred;
+use crate::codec::multistream::{self, SurroundEncoder};
+use crate::codec::FlacEncoder;
 use crate::config::{OpusConfig, OpusBandwidth, OpusSignal};
 use crate::error::CodecError;
-use crate::protocol::TrackType;
+use crate::constants::DEFAULT_CHANNELS;
+use crate::protocol::{Codec, RemoteCapabilities, TrackType};
+
+/// Frame durations Opus codes (ms)
+pub const FRAME_DURATIONS_MS: [f32; 6] = [2.5, 5.0, 10.0, 20.0, 40.0, 60.0];
+
+/// Packets of the largest size an encoder's arena holds
+const ARENA_PACKETS: usize = 16;
+
+/// libopus encoder of a mono/stereo stream or of a surround layout
+enum Handle {
+    Single(Encoder),
+    Surround(SurroundEncoder),
+}
+
+/// Call a method both handles have, with the error as text
+macro_rules! dispatch {
+    ($handle:expr, $encoder:ident => $call:expr) => {
+        match $handle {
+            Handle::Single($encoder) => $call.map_err(|e| e.to_string()),
+            Handle::Surround($encoder) => $call.map_err(|e| e.to_string()),
+        }
+    };
+}
 
 /// Opus encoder wrapper with optimized settings
 pub struct OpusEncoder {
-    encoder: Encoder,
+    encoder: Handle,
     config: OpusConfig,
-    /// Encoding buffer (reused to avoid allocations)
-    encode_buffer: Vec<u8>,
+    /// Arena packets are encoded into and split off from: the
+    /// allocation is reclaimed once the packets sent from it are dropped
+    encode_buffer: BytesMut,
+    /// Largest packet the encoder can produce
+    max_packet_size: usize,
     /// Frame counter for statistics
     frames_encoded: u64,
     /// Total bytes produced
@@ -23,33 +61,39 @@ pub struct OpusEncoder {
 impl OpusEncoder {
     /// Create a new Opus encoder with the specified configuration
     pub fn new(config: OpusConfig) -> Result<Self, CodecError> {
-        let channels = match config.channels {
-            1 => Channels::Mono,
-            2 => Channels::Stereo,
-            _ => return Err(CodecError::EncoderInit(
-                format!("Unsupported channel count: {}", config.channels)
-            )),
-        };
-        
         let application = match config.application {
             TrackType::Voice => Application::Voip,
             TrackType::Music => Application::Audio,
             TrackType::LowLatency => Application::LowDelay,
         };
         
-        let mut encoder = Encoder::new(config.sample_rate, channels, application)
-            .map_err(|e| CodecError::EncoderInit(e.to_string()))?;
+        let mut encoder = match config.channels {
+            1 => Handle::Single(Encoder::new(config.sample_rate, Channels::Mono, application)
+                .map_err(|e| CodecError::EncoderInit(e.to_string()))?),
+            2 => Handle::Single(Encoder::new(config.sample_rate, Channels::Stereo, application)
+                .map_err(|e| CodecError::EncoderInit(e.to_string()))?),
+            channels if multistream::is_surround(channels) => Handle::Surround(
+                SurroundEncoder::new(config.sample_rate, channels, application)
+                    .map_err(|e| CodecError::EncoderInit(e.to_string()))?,
+            ),
+            _ => return Err(CodecError::EncoderInit(
+                format!("Unsupported channel count: {}", config.channels)
+            )),
+        };
         
         // Configure encoder
         Self::configure_encoder(&mut encoder, &config)?;
         
-        // Pre-allocate encoding buffer (max Opus frame is about 1275 bytes)
-        let encode_buffer = vec![0u8; 4000];
+        // Pre-allocate the packet arena (max Opus frame is about 1275 bytes
+        // per stream)
+        let max_packet_size = 4000 * config.channels.div_ceil(2) as usize;
+        let encode_buffer = BytesMut::with_capacity(max_packet_size * ARENA_PACKETS);
         
         Ok(Self {
             encoder,
             config,
             encode_buffer,
+            max_packet_size,
             frames_encoded: 0,
             bytes_produced: 0,
         })
@@ -83,44 +127,56 @@ impl OpusEncoder {
     }
     
     /// Configure the encoder with all settings
-    fn configure_encoder(encoder: &mut Encoder, config: &OpusConfig) -> Result<(), CodecError> {
+    fn configure_encoder(encoder: &mut Handle, config: &OpusConfig) -> Result<(), CodecError> {
         // Bitrate
-        encoder.set_bitrate(opus::Bitrate::Bits(config.bitrate as i32))
+        dispatch!(encoder, e => e.set_bitrate(opus::Bitrate::Bits(config.bitrate as i32)))
             .map_err(|e| CodecError::EncoderInit(format!("Failed to set bitrate: {}", e)))?;
         
-        // VBR settings
-        encoder.set_vbr(config.vbr)
-            .map_err(|e| CodecError::EncoderInit(format!("Failed to set VBR: {}", e)))?;
-        
-        if config.vbr && config.cvbr {
-            encoder.set_vbr_constraint(true)
-                .map_err(|e| CodecError::EncoderInit(format!("Failed to set CVBR: {}", e)))?;
-        }
-        
-        // Complexity (0-10)
-        encoder.set_complexity(config.complexity as i32)
-            .map_err(|e| CodecError::EncoderInit(format!("Failed to set complexity: {}", e)))?;
+        // VBR, complexity, signal type and bandwidth
+        Self::apply_settings(encoder, config)?;
         
         // FEC
-        encoder.set_inband_fec(config.fec)
+        dispatch!(encoder, e => e.set_inband_fec(config.fec))
             .map_err(|e| CodecError::EncoderInit(format!("Failed to set FEC: {}", e)))?;
         
         if config.fec {
-            encoder.set_packet_loss_perc(config.packet_loss_perc as i32)
+            dispatch!(encoder, e => e.set_packet_loss_perc(config.packet_loss_perc as i32))
                 .map_err(|e| CodecError::EncoderInit(format!("Failed to set packet loss: {}", e)))?;
         }
         
         // DTX
-        encoder.set_dtx(config.dtx)
+        dispatch!(encoder, e => e.set_dtx(config.dtx))
             .map_err(|e| CodecError::EncoderInit(format!("Failed to set DTX: {}", e)))?;
         
+        // Deep redundancy
+        if config.dred_duration_ms > 0 {
+            Self::apply_dred(encoder, config.dred_duration_ms)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Set the settings a track can override: bitrate mode, complexity,
+    /// signal type and maximum bandwidth
+    fn apply_settings(encoder: &mut Handle, config: &OpusConfig) -> Result<(), CodecError> {
+        // VBR settings
+        dispatch!(encoder, e => e.set_vbr(config.vbr))
+            .map_err(|e| CodecError::EncoderInit(format!("Failed to set VBR: {}", e)))?;
+        
+        dispatch!(encoder, e => e.set_vbr_constraint(config.vbr && config.cvbr))
+            .map_err(|e| CodecError::EncoderInit(format!("Failed to set CVBR: {}", e)))?;
+        
+        // Complexity (0-10)
+        dispatch!(encoder, e => e.set_complexity(config.complexity as i32))
+            .map_err(|e| CodecError::EncoderInit(format!("Failed to set complexity: {}", e)))?;
+        
         // Signal type
         let signal = match config.signal {
             OpusSignal::Auto => opus::Signal::Auto,
             OpusSignal::Voice => opus::Signal::Voice,
             OpusSignal::Music => opus::Signal::Music,
         };
-        encoder.set_signal(signal)
+        dispatch!(encoder, e => e.set_signal(signal))
             .map_err(|e| CodecError::EncoderInit(format!("Failed to set signal type: {}", e)))?;
         
         // Bandwidth
@@ -131,9 +187,35 @@ impl OpusEncoder {
             OpusBandwidth::Superwideband => opus::Bandwidth::Superwideband,
             OpusBandwidth::Fullband => opus::Bandwidth::Fullband,
         };
-        encoder.set_bandwidth(bandwidth)
-            .map_err(|e| CodecError::EncoderInit(format!("Failed to set bandwidth: {}", e)))?;
-        
+        dispatch!(encoder, e => e.set_bandwidth(bandwidth))
+            .map_err(|e| CodecError::EncoderInit(format!("Failed to set bandwidth: {}", e)))
+    }
+    
+    /// Set the DRED history length on the encoder (0 disables it).
+    /// Surround streams carry no DRED history.
+    #[cfg(feature = "dred")]
+    fn apply_dred(encoder: &mut Handle, duration_ms: u16) -> Result<(), CodecError> {
+        if duration_ms > 0 && !dred::is_available() {
+            return Err(CodecError::EncoderInit(format!(
+                "DRED needs libopus 1.5 or newer, linked {}",
+                opus::version()
+            )));
+        }
+        let Handle::Single(encoder) = encoder else {
+            return Ok(());
+        };
+        let units = duration_ms.min(dred::MAX_DRED_DURATION_MS) / 10;
+        encoder.set_dred_duration(units as i32)
+            .map_err(|e| CodecError::EncoderInit(format!("Failed to set DRED duration: {}", e)))
+    }
+    
+    #[cfg(not(feature = "dred"))]
+    fn apply_dred(_encoder: &mut Handle, duration_ms: u16) -> Result<(), CodecError> {
+        if duration_ms > 0 && !dred::is_available() {
+            return Err(CodecError::EncoderInit(
+                "DRED support not built (enable the `dred` feature)".to_string(),
+            ));
+        }
         Ok(())
     }
     
@@ -146,19 +228,25 @@ impl OpusEncoder {
             return Err(CodecError::InvalidFrameSize(samples.len()));
         }
         
-        let size = self.encoder
-            .encode_float(samples, &mut self.encode_buffer)
-            .map_err(|e| CodecError::EncodingFailed(e.to_string()))?;
+        // A full arena is reclaimed in place if its packets are gone,
+        // otherwise a new one is allocated
+        if self.encode_buffer.capacity() < self.max_packet_size {
+            self.encode_buffer.reserve(self.max_packet_size * ARENA_PACKETS);
+        }
+        self.encode_buffer.resize(self.max_packet_size, 0);
+        let size = dispatch!(&mut self.encoder, e => e.encode_float(samples, &mut self.encode_buffer))
+            .map_err(CodecError::EncodingFailed)?;
         
         self.frames_encoded += 1;
         self.bytes_produced += size as u64;
         
-        Ok(Bytes::copy_from_slice(&self.encode_buffer[..size]))
+        self.encode_buffer.truncate(size);
+        Ok(self.encode_buffer.split().freeze())
     }
     
     /// Update bitrate dynamically
     pub fn set_bitrate(&mut self, bitrate: u32) -> Result<(), CodecError> {
-        self.encoder.set_bitrate(opus::Bitrate::Bits(bitrate as i32))
+        dispatch!(&mut self.encoder, e => e.set_bitrate(opus::Bitrate::Bits(bitrate as i32)))
             .map_err(|e| CodecError::EncoderInit(format!("Failed to set bitrate: {}", e)))?;
         self.config.bitrate = bitrate;
         Ok(())
@@ -166,11 +254,11 @@ impl OpusEncoder {
     
     /// Update FEC setting dynamically
     pub fn set_fec(&mut self, enabled: bool, packet_loss_perc: u8) -> Result<(), CodecError> {
-        self.encoder.set_inband_fec(enabled)
+        dispatch!(&mut self.encoder, e => e.set_inband_fec(enabled))
             .map_err(|e| CodecError::EncoderInit(format!("Failed to set FEC: {}", e)))?;
         
         if enabled {
-            self.encoder.set_packet_loss_perc(packet_loss_perc as i32)
+            dispatch!(&mut self.encoder, e => e.set_packet_loss_perc(packet_loss_perc as i32))
                 .map_err(|e| CodecError::EncoderInit(format!("Failed to set packet loss: {}", e)))?;
         }
         
@@ -179,6 +267,33 @@ impl OpusEncoder {
         Ok(())
     }
     
+    /// Update DRED history length dynamically (0 disables DRED)
+    pub fn set_dred_duration(&mut self, duration_ms: u16) -> Result<(), CodecError> {
+        Self::apply_dred(&mut self.encoder, duration_ms)?;
+        self.config.dred_duration_ms = duration_ms;
+        Ok(())
+    }
+    
+    /// Update bitrate mode, complexity, signal type and maximum bandwidth
+    /// dynamically to those of `settings`
+    pub fn set_settings(&mut self, settings: &OpusConfig) -> Result<(), CodecError> {
+        Self::apply_settings(&mut self.encoder, settings)?;
+        self.config.vbr = settings.vbr;
+        self.config.cvbr = settings.cvbr;
+        self.config.complexity = settings.complexity;
+        self.config.signal = settings.signal;
+        self.config.max_bandwidth = settings.max_bandwidth;
+        Ok(())
+    }
+    
+    /// Update expected packet loss hint (tunes in-band FEC redundancy)
+    pub fn set_packet_loss_perc(&mut self, packet_loss_perc: u8) -> Result<(), CodecError> {
+        dispatch!(&mut self.encoder, e => e.set_packet_loss_perc(packet_loss_perc.min(100) as i32))
+            .map_err(|e| CodecError::EncoderInit(format!("Failed to set packet loss: {}", e)))?;
+        self.config.packet_loss_perc = packet_loss_perc;
+        Ok(())
+    }
+    
     /// Get current configuration
     pub fn config(&self) -> &OpusConfig {
         &self.config
@@ -189,6 +304,17 @@ impl OpusEncoder {
         self.config.frame_size
     }
     
+    /// Code the following frames with `frame_size` samples per channel;
+    /// Opus frames carry their duration, so the stream goes on unbroken
+    pub fn set_frame_size(&mut self, frame_size: usize) -> Result<(), CodecError> {
+        let duration_ms = frame_size as f32 * 1000.0 / self.config.sample_rate as f32;
+        if !FRAME_DURATIONS_MS.contains(&duration_ms) {
+            return Err(CodecError::InvalidFrameSize(frame_size));
+        }
+        self.config.frame_size = frame_size;
+        Ok(())
+    }
+    
     /// Get expected total samples per frame (including all channels)
     pub fn samples_per_frame(&self) -> usize {
         self.config.frame_size * self.config.channels as usize
@@ -219,6 +345,140 @@ impl OpusEncoder {
     }
 }
 
+/// Encoder of a sent track, whichever its codec
+///
+/// A new codec implements this and [`AudioDecoder`](crate::codec::AudioDecoder),
+/// gets an id in [`Codec`] and an arm in [`new_encoder`] and `new_decoder`;
+/// the capture, packet and playback paths stay as they are.
+pub trait AudioEncoder: Send {
+    fn codec(&self) -> Codec;
+    
+    /// Encode interleaved f32 samples of one frame
+    fn encode(&mut self, samples: &[f32]) -> Result<Bytes, CodecError>;
+    
+    /// Get expected total samples per frame (including all channels)
+    fn samples_per_frame(&self) -> usize;
+    
+    /// Code the following frames with `frame_size` samples per channel
+    fn set_frame_size(&mut self, frame_size: usize) -> Result<(), CodecError>;
+    
+    /// Channels of the encoded frames
+    fn channels(&self) -> u16;
+    
+    /// Get frame duration in milliseconds
+    fn frame_duration_ms(&self) -> f32;
+    
+    /// Bitrate produced (bps): the target of a lossy codec, the average
+    /// so far of a lossless one
+    fn bitrate(&self) -> u32;
+    
+    /// Packets carry in-band FEC
+    fn fec_enabled(&self) -> bool {
+        false
+    }
+    
+    /// Opus encoder for runtime tuning (bitrate, FEC, DRED); None for
+    /// codecs without it
+    fn opus_mut(&mut self) -> Option<&mut OpusEncoder> {
+        None
+    }
+}
+
+impl AudioEncoder for OpusEncoder {
+    fn codec(&self) -> Codec {
+        Codec::Opus
+    }
+    
+    fn encode(&mut self, samples: &[f32]) -> Result<Bytes, CodecError> {
+        OpusEncoder::encode(self, samples)
+    }
+    
+    fn samples_per_frame(&self) -> usize {
+        OpusEncoder::samples_per_frame(self)
+    }
+    
+    fn set_frame_size(&mut self, frame_size: usize) -> Result<(), CodecError> {
+        OpusEncoder::set_frame_size(self, frame_size)
+    }
+    
+    fn channels(&self) -> u16 {
+        self.config.channels
+    }
+    
+    fn frame_duration_ms(&self) -> f32 {
+        OpusEncoder::frame_duration_ms(self)
+    }
+    
+    fn bitrate(&self) -> u32 {
+        self.config.bitrate
+    }
+    
+    fn fec_enabled(&self) -> bool {
+        self.config.fec
+    }
+    
+    fn opus_mut(&mut self) -> Option<&mut OpusEncoder> {
+        Some(self)
+    }
+}
+
+impl AudioEncoder for FlacEncoder {
+    fn codec(&self) -> Codec {
+        Codec::Flac
+    }
+    
+    fn encode(&mut self, samples: &[f32]) -> Result<Bytes, CodecError> {
+        FlacEncoder::encode(self, samples)
+    }
+    
+    fn samples_per_frame(&self) -> usize {
+        FlacEncoder::samples_per_frame(self)
+    }
+    
+    fn set_frame_size(&mut self, frame_size: usize) -> Result<(), CodecError> {
+        FlacEncoder::set_frame_size(self, frame_size)
+    }
+    
+    fn channels(&self) -> u16 {
+        FlacEncoder::channels(self)
+    }
+    
+    fn frame_duration_ms(&self) -> f32 {
+        FlacEncoder::frame_duration_ms(self)
+    }
+    
+    fn bitrate(&self) -> u32 {
+        FlacEncoder::bitrate(self)
+    }
+}
+
+/// Create the encoder for `codec`; the frame layout comes from `config`
+pub fn new_encoder(codec: Codec, config: OpusConfig) -> Result<Box<dyn AudioEncoder>, CodecError> {
+    Ok(match codec {
+        Codec::Opus => Box::new(OpusEncoder::new(config)?),
+        Codec::Flac => Box::new(FlacEncoder::new(config.sample_rate, config.channels, config.frame_size)?),
+    })
+}
+
+/// Codec to send a track with: the configured one if every receiver
+/// decodes it, Opus otherwise
+pub fn select_codec(configured: Codec, receivers: &RemoteCapabilities) -> Codec {
+    match configured {
+        Codec::Flac if !receivers.flac => Codec::Opus,
+        codec => codec,
+    }
+}
+
+/// Channels to send a track with: its configured surround layout if
+/// every receiver plays that many, the default stereo otherwise
+pub fn select_channels(configured: u16, receivers: &RemoteCapabilities) -> u16 {
+    if multistream::is_surround(configured) && configured <= receivers.max_channels as u16 {
+        configured
+    } else {
+        DEFAULT_CHANNELS
+    }
+}
+
 /// Encoder statistics
 #[derive(Debug, Clone)]
 pub struct EncoderStats {
@@ -257,6 +517,25 @@ mod tests {
         assert!(encoded.len() < frame_size * 4); // Should be compressed
     }
     
+    #[test]
+    fn test_packets_outlive_arena() {
+        let mut kept = OpusEncoder::music(48000, 2).unwrap();
+        let mut copied = OpusEncoder::music(48000, 2).unwrap();
+        let frame_size = kept.samples_per_frame();
+        
+        // Packets held past several arenas keep their bytes
+        let mut packets = Vec::new();
+        let mut copies = Vec::new();
+        for i in 0..ARENA_PACKETS * 3 {
+            let samples: Vec<f32> = (0..frame_size)
+                .map(|n| ((i * frame_size + n) as f32 * 0.05).sin() * 0.5)
+                .collect();
+            packets.push(kept.encode(&samples).unwrap());
+            copies.push(copied.encode(&samples).unwrap().to_vec());
+        }
+        assert_eq!(packets, copies);
+    }
+    
     #[test]
     fn test_voice_encoder() {
         let mut encoder = OpusEncoder::voice(48000, 1).unwrap();
@@ -275,4 +554,97 @@ mod tests {
         assert_eq!(encoder.frame_size(), 120);
         assert!((encoder.frame_duration_ms() - 2.5).abs() < 0.1);
     }
+    
+    #[test]
+    fn test_live_settings() {
+        let mut encoder = OpusEncoder::music(48000, 2).unwrap();
+        let frame_size = encoder.samples_per_frame();
+        let tone: Vec<f32> = (0..frame_size).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+        encoder.encode(&tone).unwrap();
+        
+        let settings = OpusConfig {
+            vbr: false,
+            complexity: 2,
+            max_bandwidth: OpusBandwidth::Narrowband,
+            ..OpusConfig::music()
+        };
+        encoder.set_settings(&settings).unwrap();
+        assert_eq!(encoder.config().bitrate_mode(), crate::config::OpusBitrateMode::Cbr);
+        assert_eq!(encoder.config().complexity, 2);
+        
+        // Constant bitrate: every frame of the target size
+        let sizes: Vec<usize> = (0..5).map(|_| encoder.encode(&tone).unwrap().len()).collect();
+        let expected = (encoder.config().bitrate as f32 / 8.0 * encoder.frame_duration_ms() / 1000.0) as usize;
+        assert!(sizes.iter().all(|&size| size == expected), "{:?} != {}", sizes, expected);
+    }
+    
+    #[test]
+    fn test_live_frame_size() {
+        let mut encoder = new_encoder(Codec::Opus, OpusConfig::music()).unwrap();
+        encoder.encode(&vec![0.0; encoder.samples_per_frame()]).unwrap();
+        
+        encoder.set_frame_size(120).unwrap();
+        assert_eq!(encoder.samples_per_frame(), 240);
+        assert!((encoder.frame_duration_ms() - 2.5).abs() < 0.01);
+        encoder.encode(&[0.0; 240]).unwrap();
+        assert!(encoder.set_frame_size(500).is_err());
+        assert_eq!(encoder.samples_per_frame(), 240);
+        
+        let mut flac = new_encoder(Codec::Flac, OpusConfig::music()).unwrap();
+        flac.set_frame_size(960).unwrap();
+        flac.encode(&vec![0.0; 1920]).unwrap();
+    }
+    
+    #[test]
+    fn test_encoder_factory() {
+        let config = OpusConfig::music();
+        let mut opus = new_encoder(Codec::Opus, config.clone()).unwrap();
+        let mut flac = new_encoder(Codec::Flac, config.clone()).unwrap();
+        assert_eq!(flac.codec(), Codec::Flac);
+        assert_eq!(opus.samples_per_frame(), flac.samples_per_frame());
+        assert!(opus.opus_mut().is_some() && flac.opus_mut().is_none());
+        assert!(!flac.fec_enabled());
+        
+        // Noise: far beyond the Opus bitrate when coded losslessly
+        let samples: Vec<f32> = (0..flac.samples_per_frame())
+            .map(|i| ((i * 7919 % 1000) as f32 / 1000.0) - 0.5)
+            .collect();
+        assert!(flac.encode(&samples).unwrap().len() > opus.encode(&samples).unwrap().len());
+    }
+    
+    #[test]
+    fn test_select_codec() {
+        let all = RemoteCapabilities::default();
+        let rtp = RemoteCapabilities { flac: false, ..RemoteCapabilities::default() };
+        assert_eq!(select_codec(Codec::Flac, &all), Codec::Flac);
+        assert_eq!(select_codec(Codec::Flac, &rtp), Codec::Opus);
+        assert_eq!(select_codec(Codec::Opus, &all), Codec::Opus);
+    }
+    
+    #[test]
+    fn test_surround_encoder() {
+        let mut config = OpusConfig::music();
+        config.channels = 6;
+        let mut encoder = new_encoder(Codec::Opus, config.clone()).unwrap();
+        assert_eq!((encoder.channels(), encoder.samples_per_frame()), (6, 480 * 6));
+        assert!(!encoder.encode(&vec![0.0; 480 * 6]).unwrap().is_empty());
+        assert!(encoder.opus_mut().unwrap().set_bitrate(256_000).is_ok());
+        
+        config.channels = 9;
+        assert!(OpusEncoder::new(config).is_err());
+    }
+    
+    #[test]
+    fn test_select_channels() {
+        let all = RemoteCapabilities::default();
+        let stereo_only = RemoteCapabilities { max_channels: 2, ..RemoteCapabilities::default() };
+        let quad = RemoteCapabilities { max_channels: 4, ..RemoteCapabilities::default() };
+        assert_eq!(select_channels(6, &all), 6);
+        assert_eq!(select_channels(8, &quad), DEFAULT_CHANNELS);
+        assert_eq!(select_channels(4, &quad), 4);
+        assert_eq!(select_channels(6, &stereo_only), DEFAULT_CHANNELS);
+        // Mono and stereo tracks are sent as before
+        assert_eq!(select_channels(1, &all), DEFAULT_CHANNELS);
+        assert_eq!(select_channels(2, &all), DEFAULT_CHANNELS);
+    }
 }
@@ -0,0 +1,169 @@
+//! In-band FEC recovery
+//!
+//! With in-band FEC enabled every Opus packet carries a low bitrate copy of
+//! the previous frame. When a packet arrives and the jitter buffer is still
+//! missing the frame before it, that frame is reconstructed from the new
+//! packet before the packet itself is decoded (the order libopus expects).
+
+use crate::audio::buffer::{AudioFrame, JitterBuffer};
+use crate::codec::AudioDecoder;
+use crate::error::CodecError;
+
+/// Recover the frame preceding `sequence` from the FEC data in `payload`
+/// if the jitter buffer has not received it. Returns true if a frame was
+/// recovered. Must be called before decoding `payload` normally.
+pub fn recover_previous_frame(
+    decoder: &mut dyn AudioDecoder,
+    jitter_buffer: &mut JitterBuffer,
+    payload: &[u8],
+    sequence: u32,
+    timestamp: u64,
+) -> Result<bool, CodecError> {
+    let previous = sequence.wrapping_sub(1);
+    if !jitter_buffer.is_missing(previous) {
+        return Ok(false);
+    }
+
+    let samples = decoder.decode_fec(payload)?;
+    let frame_duration_us = decoder.frame_size() as u64 * 1_000_000 / decoder.sample_rate() as u64;
+    let frame = AudioFrame::new(
+        samples,
+        decoder.channels(),
+        timestamp.saturating_sub(frame_duration_us),
+        previous,
+    );
+
+    Ok(jitter_buffer.insert_recovered(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{OpusDecoder, OpusEncoder};
+    use crate::config::OpusConfig;
+
+    const SAMPLE_RATE: u32 = 48000;
+    const CHANNELS: u16 = 2;
+    const FRAME_SIZE: usize = 480;
+
+    fn fec_encoder() -> OpusEncoder {
+        let config = OpusConfig {
+            bitrate: 64_000,
+            fec: true,
+            packet_loss_perc: 20,
+            channels: CHANNELS,
+            frame_size: FRAME_SIZE,
+            ..OpusConfig::default()
+        };
+        OpusEncoder::new(config).unwrap()
+    }
+
+    fn tone_frame(index: usize) -> Vec<f32> {
+        (0..FRAME_SIZE)
+            .flat_map(|i| {
+                let t = (index * FRAME_SIZE + i) as f32 / SAMPLE_RATE as f32;
+                let s = (t * 440.0 * std::f32::consts::TAU).sin() * 0.5;
+                [s; CHANNELS as usize]
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32).sqrt()
+    }
+
+    /// Run `frames` packets through encoder -> lossy network -> jitter buffer.
+    /// Returns (played frames, recovered frames, lost frames, min RMS of played frames).
+    fn run_lossy_stream(frames: u32, drop: impl Fn(u32) -> bool, fec: bool) -> (usize, usize, usize, f32) {
+        let mut encoder = fec_encoder();
+        let mut decoder = OpusDecoder::new(SAMPLE_RATE, CHANNELS, FRAME_SIZE).unwrap();
+        let mut jitter = JitterBuffer::new(64, 2);
+
+        let mut played = Vec::new();
+        for seq in 0..frames {
+            let payload = encoder.encode(&tone_frame(seq as usize)).unwrap();
+            if drop(seq) {
+                continue;
+            }
+
+            let timestamp = seq as u64 * 10_000;
+            if fec {
+                recover_previous_frame(&mut decoder, &mut jitter, &payload, seq, timestamp).unwrap();
+            }
+            let samples = decoder.decode(&payload).unwrap();
+            jitter.insert(AudioFrame::new(samples, CHANNELS, timestamp, seq));
+
+            while let Some(frame) = jitter.get_next() {
+                played.push(frame);
+            }
+        }
+        while jitter.stats().level > 0 {
+            played.extend(jitter.force_get_next());
+        }
+
+        // Skip the encoder warm-up
+        let min_rms = played
+            .iter()
+            .filter(|f| f.sequence >= 5)
+            .map(|f| rms(&f.samples))
+            .fold(f32::MAX, f32::min);
+        let stats = jitter.stats();
+        (played.len(), stats.recovered, stats.lost, min_rms)
+    }
+
+    #[test]
+    fn test_fec_recovers_isolated_losses() {
+        // Every 7th packet lost (isolated losses, 14%)
+        let drop = |seq: u32| seq > 0 && seq.is_multiple_of(7);
+        let total = 300u32;
+        let dropped = (0..total).filter(|&s| drop(s)).count();
+
+        let (played, recovered, lost, min_rms) = run_lossy_stream(total, drop, true);
+        assert_eq!(recovered, dropped);
+        assert_eq!(lost, 0);
+        assert_eq!(played, total as usize);
+        // Concealed frames continue the tone instead of dropping to silence
+        assert!(min_rms > 0.05, "min rms {}", min_rms);
+    }
+
+    #[test]
+    fn test_without_fec_losses_leave_gaps() {
+        let drop = |seq: u32| seq > 0 && seq.is_multiple_of(7);
+        let total = 300u32;
+        let dropped = (0..total).filter(|&s| drop(s)).count();
+
+        let (played, recovered, lost, _) = run_lossy_stream(total, drop, false);
+        assert_eq!(recovered, 0);
+        assert_eq!(lost, dropped);
+        assert_eq!(played, total as usize - dropped);
+    }
+
+    #[test]
+    fn test_fec_burst_loss_recovers_last_frame_only() {
+        // Bursts of 3 lost packets: only the frame right before each
+        // received packet is in its FEC data
+        let drop = |seq: u32| seq >= 10 && seq % 20 < 3;
+        let total = 200u32;
+        let bursts = (0..total).filter(|&s| s >= 10 && s.is_multiple_of(20)).count();
+
+        let (_, recovered, lost, _) = run_lossy_stream(total, drop, true);
+        assert_eq!(recovered, bursts);
+        assert_eq!(lost, bursts * 2);
+    }
+
+    #[test]
+    fn test_no_recovery_without_gap() {
+        let mut encoder = fec_encoder();
+        let mut decoder = OpusDecoder::new(SAMPLE_RATE, CHANNELS, FRAME_SIZE).unwrap();
+        let mut jitter = JitterBuffer::new(16, 2);
+
+        for seq in 0..4u32 {
+            let payload = encoder.encode(&tone_frame(seq as usize)).unwrap();
+            let recovered = recover_previous_frame(&mut decoder, &mut jitter, &payload, seq, 0).unwrap();
+            assert!(!recovered);
+            let samples = decoder.decode(&payload).unwrap();
+            jitter.insert(AudioFrame::new(samples, CHANNELS, 0, seq));
+        }
+        assert_eq!(jitter.stats().recovered, 0);
+    }
+}
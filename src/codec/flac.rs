@@ -0,0 +1,241 @@
+//! Lossless (FLAC) track codec
+//!
+//! For archival-quality streaming when bandwidth allows: every frame of
+//! captured audio is coded as one FLAC frame of 24-bit samples, with the
+//! fixed-predictor coder of the FLAC recorder (`recording::flac`). The
+//! frames are sent without a stream header, marked with codec id 1 in
+//! the packet flags (`Codec::id`), and are usually larger than one datagram, so
+//! they travel fragmented.
+//!
+//! There is no FEC, DRED or bitrate adaptation; a lost frame plays as
+//! silence.
+
+use bytes::Bytes;
+
+use crate::audio::decode::decode_flac_block;
+use crate::codec::AudioDecoder;
+use crate::error::CodecError;
+use crate::protocol::Codec;
+use crate::recording::flac::{encode_frame, BITS_PER_SAMPLE};
+
+/// Largest sample value at 24 bits
+const FULL_SCALE: f32 = 8_388_607.0;
+
+/// FLAC frame numbers are coded in up to 31 bits with fixed blocking
+const FRAME_NUMBER_MASK: u64 = 0x7FFF_FFFF;
+
+/// Lossless encoder of one track
+pub struct FlacEncoder {
+    sample_rate: u32,
+    channels: u16,
+    frame_size: usize,
+    frame_number: u64,
+    /// Conversion buffer (reused to avoid allocations)
+    pcm: Vec<i32>,
+    frames_encoded: u64,
+    bytes_produced: u64,
+}
+
+impl FlacEncoder {
+    /// Create an encoder for frames of `frame_size` samples per channel
+    pub fn new(sample_rate: u32, channels: u16, frame_size: usize) -> Result<Self, CodecError> {
+        if !(1..=8).contains(&channels) {
+            return Err(CodecError::EncoderInit(format!("Unsupported channel count: {}", channels)));
+        }
+        if !(16..=u16::MAX as usize).contains(&frame_size) {
+            return Err(CodecError::InvalidFrameSize(frame_size));
+        }
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            frame_size,
+            frame_number: 0,
+            pcm: Vec::with_capacity(frame_size * channels as usize),
+            frames_encoded: 0,
+            bytes_produced: 0,
+        })
+    }
+
+    /// Encode one frame of interleaved f32 samples
+    pub fn encode(&mut self, samples: &[f32]) -> Result<Bytes, CodecError> {
+        if samples.len() != self.samples_per_frame() {
+            return Err(CodecError::InvalidFrameSize(samples.len()));
+        }
+
+        self.pcm.clear();
+        self.pcm.extend(samples.iter().map(|&sample| (sample.clamp(-1.0, 1.0) * FULL_SCALE).round() as i32));
+        let frame = encode_frame(&self.pcm, self.channels as usize, self.frame_number, self.sample_rate);
+        self.frame_number = (self.frame_number + 1) & FRAME_NUMBER_MASK;
+
+        self.frames_encoded += 1;
+        self.bytes_produced += frame.len() as u64;
+        Ok(Bytes::from(frame))
+    }
+
+    /// Get expected frame size in samples (per channel)
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Code the following frames with `frame_size` samples per channel
+    /// (every FLAC frame states its own block size)
+    pub fn set_frame_size(&mut self, frame_size: usize) -> Result<(), CodecError> {
+        if !(16..=u16::MAX as usize).contains(&frame_size) {
+            return Err(CodecError::InvalidFrameSize(frame_size));
+        }
+        self.frame_size = frame_size;
+        Ok(())
+    }
+
+    /// Get expected total samples per frame (including all channels)
+    pub fn samples_per_frame(&self) -> usize {
+        self.frame_size * self.channels as usize
+    }
+
+    /// Get channel count
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Get frame duration in milliseconds
+    pub fn frame_duration_ms(&self) -> f32 {
+        self.frame_size as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    /// Average bitrate produced so far (bps)
+    pub fn bitrate(&self) -> u32 {
+        if self.frames_encoded == 0 {
+            return 0;
+        }
+        let seconds = self.frames_encoded as f64 * self.frame_size as f64 / self.sample_rate as f64;
+        (self.bytes_produced as f64 * 8.0 / seconds) as u32
+    }
+}
+
+/// Lossless decoder of one track
+pub struct FlacDecoder {
+    sample_rate: u32,
+    channels: u16,
+    /// Block size of the last frame, the length of concealed frames
+    frame_size: usize,
+    frames_decoded: u64,
+    frames_lost: u64,
+}
+
+impl FlacDecoder {
+    pub fn new(sample_rate: u32, channels: u16, frame_size: usize) -> Result<Self, CodecError> {
+        if !(1..=8).contains(&channels) {
+            return Err(CodecError::DecoderInit(format!("Unsupported channel count: {}", channels)));
+        }
+        Ok(Self {
+            sample_rate,
+            channels,
+            frame_size,
+            frames_decoded: 0,
+            frames_lost: 0,
+        })
+    }
+
+    /// Frames decoded and frames concealed with silence
+    pub fn stats(&self) -> (u64, u64) {
+        (self.frames_decoded, self.frames_lost)
+    }
+}
+
+impl AudioDecoder for FlacDecoder {
+    fn codec(&self) -> Codec {
+        Codec::Flac
+    }
+
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<f32>, CodecError> {
+        let samples = decode_flac_block(data, self.channels, BITS_PER_SAMPLE)
+            .map_err(|e| CodecError::DecodingFailed(e.to_string()))?;
+        self.frame_size = samples.len() / self.channels as usize;
+        self.frames_decoded += 1;
+        Ok(samples)
+    }
+
+    fn decode_fec(&mut self, _data: &[u8]) -> Result<Vec<f32>, CodecError> {
+        Err(CodecError::DecodingFailed("lossless tracks carry no FEC".to_string()))
+    }
+
+    fn decode_dred(&mut self, _data: &[u8], _frames_back: u32) -> Result<Option<Vec<f32>>, CodecError> {
+        Ok(None)
+    }
+
+    fn decode_plc(&mut self) -> Result<Vec<f32>, CodecError> {
+        self.frames_lost += 1;
+        Ok(vec![0.0; self.frame_size * self.channels as usize])
+    }
+
+    fn reset(&mut self) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_frame(frame_size: usize, offset: usize) -> Vec<f32> {
+        (0..frame_size)
+            .flat_map(|i| {
+                let t = (offset + i) as f32;
+                [(t * 0.031).sin() * 0.7, (t * 0.007).cos() * 0.2]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lossless_roundtrip() {
+        let mut encoder = FlacEncoder::new(48_000, 2, 480).unwrap();
+        let mut decoder = FlacDecoder::new(48_000, 2, 480).unwrap();
+        assert!((encoder.frame_duration_ms() - 10.0).abs() < 1e-6);
+
+        for frame in 0..5 {
+            let samples = test_frame(480, frame * 480);
+            let encoded = encoder.encode(&samples).unwrap();
+            // Compressed, but over one datagram: sent fragmented
+            assert!(encoded.len() < samples.len() * 3);
+
+            let decoded = decoder.decode(&encoded).unwrap();
+            assert_eq!(decoded.len(), samples.len());
+            for (sample, original) in decoded.iter().zip(&samples) {
+                // Exact at 24 bits
+                assert!((sample - original).abs() < 2.0 / FULL_SCALE);
+            }
+        }
+        // Below raw 24-bit stereo PCM
+        assert!(encoder.bitrate() > 0 && encoder.bitrate() < 48_000 * 2 * 24);
+        assert!(encoder.encode(&[0.0; 10]).is_err());
+    }
+
+    #[test]
+    fn test_loss_is_silent() {
+        let mut encoder = FlacEncoder::new(48_000, 2, 240).unwrap();
+        let mut decoder = FlacDecoder::new(48_000, 2, 480).unwrap();
+
+        // Concealed frames follow the size of the stream
+        decoder.decode(&encoder.encode(&test_frame(240, 0)).unwrap()).unwrap();
+        let concealed = decoder.decode_plc().unwrap();
+        assert_eq!(concealed.len(), 480);
+        assert!(concealed.iter().all(|&sample| sample == 0.0));
+        assert_eq!(decoder.stats(), (1, 1));
+
+        assert!(decoder.decode(&[0xFF, 0xF8, 0x00]).is_err());
+        assert!(decoder.decode_dred(&[], 1).unwrap().is_none());
+    }
+}
@@ -1,10 +1,25 @@
 //! Opus codec wrapper
 //!
 //! Provides per-track Opus encoding and decoding with
-//! configuration optimized for different audio types.
+//! configuration optimized for different audio types, Opus multistream
+//! for surround tracks, and an optional lossless (FLAC) codec for
+//! archival-quality tracks.
+//!
+//! Tracks hold their codec behind the [`AudioEncoder`] and
+//! [`AudioDecoder`] traits, made by [`new_encoder`] and [`new_decoder`]
+//! from the [`Codec`](crate::protocol::Codec) id carried in the packet
+//! flags and the track catalog.
 
 pub mod encoder;
 pub mod decoder;
+pub mod adaptive;
+pub mod fec;
+pub mod plc;
+pub mod dred;
+pub mod flac;
+pub mod multistream;
 
-pub use encoder::OpusEncoder;
-pub use decoder::OpusDecoder;
+pub use encoder::{new_encoder, select_channels, select_codec, AudioEncoder, OpusEncoder};
+pub use decoder::{decoder_channels, new_decoder, AudioDecoder, OpusDecoder};
+pub use flac::{FlacDecoder, FlacEncoder};
+pub use adaptive::{AdaptiveBitrate, BitrateDecision};
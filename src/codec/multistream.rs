@@ -0,0 +1,428 @@
+//! Opus multistream (surround) encoding
+//!
+//! A plain Opus stream carries one or two channels. Tracks with 3 to 8
+//! channels (quad, 5.1, 7.1) are coded as several Opus streams in one
+//! packet with the Vorbis layouts of mapping family 1 (RFC 7845 §5.1.1),
+//! some streams coupling a left/right pair. The `opus` crate only wraps
+//! the single-stream API, so the encoder and decoder below bind the
+//! multistream calls of the libopus it links.
+//!
+//! The pipeline keeps frames in the SMPTE order of `audio::convert`
+//! (L R C LFE Ls Rs [Lb Rb]); the wrappers reorder to and from the Vorbis
+//! order libopus expects, so callers never see it. The layout is fixed by
+//! the channel count, which is how the receiver builds its decoder from
+//! the track catalog without a header in every packet.
+
+use opus::{Application, Bandwidth, Bitrate, Signal};
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::{c_char, c_int, c_uchar};
+
+/// Most channels a surround track can carry (7.1)
+pub const MAX_SURROUND_CHANNELS: u16 = 8;
+
+/// Channel count above which a track needs the multistream codec
+pub const MAX_SINGLE_STREAM_CHANNELS: u16 = 2;
+
+/// Mapping family of the Vorbis surround layouts
+const MAPPING_FAMILY_VORBIS: c_int = 1;
+
+/// Streams, coupled streams and channel mapping of a family 1 layout,
+/// indexed by channel count - 3 (as `opus_multistream_surround_encoder_create`
+/// picks them)
+const LAYOUTS: [(u8, u8, &[u8]); 6] = [
+    (2, 1, &[0, 2, 1]),
+    (2, 2, &[0, 1, 2, 3]),
+    (3, 2, &[0, 4, 1, 2, 3]),
+    (4, 2, &[0, 4, 1, 2, 3, 5]),
+    (4, 3, &[0, 4, 1, 2, 3, 5, 6]),
+    (5, 3, &[0, 6, 1, 2, 3, 4, 5, 7]),
+];
+
+/// For every channel in Vorbis order, the channel it comes from in the
+/// pipeline's SMPTE order, indexed by channel count - 3
+const VORBIS_FROM_SMPTE: [&[usize]; 6] = [
+    // L C R <- L R C
+    &[0, 2, 1],
+    // FL FR RL RR
+    &[0, 1, 2, 3],
+    // FL C FR RL RR <- L R C Ls Rs
+    &[0, 2, 1, 3, 4],
+    // FL C FR RL RR LFE <- L R C LFE Ls Rs
+    &[0, 2, 1, 4, 5, 3],
+    // FL C FR SL SR RC LFE <- L R C LFE Ls Rs Cb
+    &[0, 2, 1, 4, 5, 6, 3],
+    // FL C FR SL SR RL RR LFE <- L R C LFE Ls Rs Lb Rb
+    &[0, 2, 1, 4, 5, 6, 7, 3],
+];
+
+/// Check whether `channels` needs (and can use) the multistream codec
+pub fn is_surround(channels: u16) -> bool {
+    (MAX_SINGLE_STREAM_CHANNELS + 1..=MAX_SURROUND_CHANNELS).contains(&channels)
+}
+
+// opus_defines.h
+const OPUS_OK: c_int = 0;
+const OPUS_AUTO: c_int = -1000;
+const OPUS_BAD_ARG: c_int = -1;
+const OPUS_BITRATE_MAX: c_int = -1;
+const OPUS_SET_BITRATE_REQUEST: c_int = 4002;
+const OPUS_SET_VBR_REQUEST: c_int = 4006;
+const OPUS_SET_BANDWIDTH_REQUEST: c_int = 4008;
+const OPUS_SET_COMPLEXITY_REQUEST: c_int = 4010;
+const OPUS_SET_INBAND_FEC_REQUEST: c_int = 4012;
+const OPUS_SET_PACKET_LOSS_PERC_REQUEST: c_int = 4014;
+const OPUS_SET_DTX_REQUEST: c_int = 4016;
+const OPUS_SET_VBR_CONSTRAINT_REQUEST: c_int = 4020;
+const OPUS_SET_SIGNAL_REQUEST: c_int = 4024;
+const OPUS_RESET_STATE: c_int = 4028;
+
+#[repr(C)]
+struct OpusMSEncoder {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct OpusMSDecoder {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn opus_strerror(error: c_int) -> *const c_char;
+
+    fn opus_multistream_surround_encoder_create(
+        fs: i32,
+        channels: c_int,
+        mapping_family: c_int,
+        streams: *mut c_int,
+        coupled_streams: *mut c_int,
+        mapping: *mut c_uchar,
+        application: c_int,
+        error: *mut c_int,
+    ) -> *mut OpusMSEncoder;
+    fn opus_multistream_encoder_destroy(st: *mut OpusMSEncoder);
+    fn opus_multistream_encoder_ctl(st: *mut OpusMSEncoder, request: c_int, ...) -> c_int;
+    fn opus_multistream_encode_float(
+        st: *mut OpusMSEncoder,
+        pcm: *const f32,
+        frame_size: c_int,
+        data: *mut c_uchar,
+        max_data_bytes: i32,
+    ) -> c_int;
+
+    fn opus_multistream_decoder_create(
+        fs: i32,
+        channels: c_int,
+        streams: c_int,
+        coupled_streams: c_int,
+        mapping: *const c_uchar,
+        error: *mut c_int,
+    ) -> *mut OpusMSDecoder;
+    fn opus_multistream_decoder_destroy(st: *mut OpusMSDecoder);
+    fn opus_multistream_decoder_ctl(st: *mut OpusMSDecoder, request: c_int, ...) -> c_int;
+    fn opus_multistream_decode_float(
+        st: *mut OpusMSDecoder,
+        data: *const c_uchar,
+        len: i32,
+        pcm: *mut f32,
+        frame_size: c_int,
+        decode_fec: c_int,
+    ) -> c_int;
+}
+
+/// libopus error code
+#[derive(Debug, Clone, Copy)]
+pub struct Error(c_int);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = unsafe { CStr::from_ptr(opus_strerror(self.0)) };
+        write!(f, "{}", message.to_string_lossy())
+    }
+}
+
+fn check(code: c_int) -> Result<c_int, Error> {
+    if code < OPUS_OK {
+        Err(Error(code))
+    } else {
+        Ok(code)
+    }
+}
+
+/// Vorbis reordering of a surround channel count, or a bad argument error
+fn vorbis_order(channels: u16) -> Result<&'static [usize], Error> {
+    if !is_surround(channels) {
+        return Err(Error(OPUS_BAD_ARG));
+    }
+    Ok(VORBIS_FROM_SMPTE[channels as usize - 3])
+}
+
+/// Multistream encoder of a 3 to 8 channel layout, with the methods of
+/// `opus::Encoder` the codec wrapper uses
+pub struct SurroundEncoder {
+    ptr: *mut OpusMSEncoder,
+    channels: usize,
+    order: &'static [usize],
+    /// Input reordered to the Vorbis layout
+    reordered: Vec<f32>,
+}
+
+// The handle is only used through `&mut self`
+unsafe impl Send for SurroundEncoder {}
+
+impl SurroundEncoder {
+    pub fn new(sample_rate: u32, channels: u16, application: Application) -> Result<Self, Error> {
+        let order = vorbis_order(channels)?;
+        let mut error = OPUS_OK;
+        let mut streams: c_int = 0;
+        let mut coupled: c_int = 0;
+        let mut mapping = [0u8; MAX_SURROUND_CHANNELS as usize];
+        let ptr = unsafe {
+            opus_multistream_surround_encoder_create(
+                sample_rate as i32,
+                channels as c_int,
+                MAPPING_FAMILY_VORBIS,
+                &mut streams,
+                &mut coupled,
+                mapping.as_mut_ptr(),
+                application as c_int,
+                &mut error,
+            )
+        };
+        if error != OPUS_OK || ptr.is_null() {
+            return Err(Error(error));
+        }
+        Ok(Self {
+            ptr,
+            channels: channels as usize,
+            order,
+            reordered: Vec::new(),
+        })
+    }
+
+    fn set(&mut self, request: c_int, value: c_int) -> Result<(), Error> {
+        check(unsafe { opus_multistream_encoder_ctl(self.ptr, request, value) }).map(|_| ())
+    }
+
+    /// Total bitrate of all streams
+    pub fn set_bitrate(&mut self, bitrate: Bitrate) -> Result<(), Error> {
+        let value = match bitrate {
+            Bitrate::Bits(bits) => bits,
+            Bitrate::Max => OPUS_BITRATE_MAX,
+            Bitrate::Auto => OPUS_AUTO,
+        };
+        self.set(OPUS_SET_BITRATE_REQUEST, value)
+    }
+
+    pub fn set_vbr(&mut self, vbr: bool) -> Result<(), Error> {
+        self.set(OPUS_SET_VBR_REQUEST, vbr as c_int)
+    }
+
+    pub fn set_vbr_constraint(&mut self, constrained: bool) -> Result<(), Error> {
+        self.set(OPUS_SET_VBR_CONSTRAINT_REQUEST, constrained as c_int)
+    }
+
+    pub fn set_complexity(&mut self, complexity: i32) -> Result<(), Error> {
+        self.set(OPUS_SET_COMPLEXITY_REQUEST, complexity)
+    }
+
+    pub fn set_inband_fec(&mut self, enabled: bool) -> Result<(), Error> {
+        self.set(OPUS_SET_INBAND_FEC_REQUEST, enabled as c_int)
+    }
+
+    pub fn set_packet_loss_perc(&mut self, percent: i32) -> Result<(), Error> {
+        self.set(OPUS_SET_PACKET_LOSS_PERC_REQUEST, percent)
+    }
+
+    pub fn set_dtx(&mut self, enabled: bool) -> Result<(), Error> {
+        self.set(OPUS_SET_DTX_REQUEST, enabled as c_int)
+    }
+
+    pub fn set_signal(&mut self, signal: Signal) -> Result<(), Error> {
+        self.set(OPUS_SET_SIGNAL_REQUEST, signal as c_int)
+    }
+
+    pub fn set_bandwidth(&mut self, bandwidth: Bandwidth) -> Result<(), Error> {
+        self.set(OPUS_SET_BANDWIDTH_REQUEST, bandwidth as c_int)
+    }
+
+    /// Encode interleaved samples in the pipeline's channel order
+    pub fn encode_float(&mut self, input: &[f32], output: &mut [u8]) -> Result<usize, Error> {
+        self.reordered.clear();
+        for frame in input.chunks_exact(self.channels) {
+            self.reordered.extend(self.order.iter().map(|&src| frame[src]));
+        }
+        let frame_size = (input.len() / self.channels) as c_int;
+        let len = unsafe {
+            opus_multistream_encode_float(
+                self.ptr,
+                self.reordered.as_ptr(),
+                frame_size,
+                output.as_mut_ptr(),
+                output.len() as i32,
+            )
+        };
+        check(len).map(|len| len as usize)
+    }
+}
+
+impl Drop for SurroundEncoder {
+    fn drop(&mut self) {
+        unsafe { opus_multistream_encoder_destroy(self.ptr) }
+    }
+}
+
+/// Multistream decoder of a 3 to 8 channel layout, with the methods of
+/// `opus::Decoder` the codec wrapper uses
+pub struct SurroundDecoder {
+    ptr: *mut OpusMSDecoder,
+    channels: usize,
+    order: &'static [usize],
+    /// Output of libopus in the Vorbis layout
+    decoded: Vec<f32>,
+}
+
+// The handle is only used through `&mut self`
+unsafe impl Send for SurroundDecoder {}
+
+impl SurroundDecoder {
+    pub fn new(sample_rate: u32, channels: u16) -> Result<Self, Error> {
+        let order = vorbis_order(channels)?;
+        let (streams, coupled, mapping) = LAYOUTS[channels as usize - 3];
+        let mut error = OPUS_OK;
+        let ptr = unsafe {
+            opus_multistream_decoder_create(
+                sample_rate as i32,
+                channels as c_int,
+                streams as c_int,
+                coupled as c_int,
+                mapping.as_ptr(),
+                &mut error,
+            )
+        };
+        if error != OPUS_OK || ptr.is_null() {
+            return Err(Error(error));
+        }
+        Ok(Self {
+            ptr,
+            channels: channels as usize,
+            order,
+            decoded: Vec::new(),
+        })
+    }
+
+    /// Decode to interleaved samples in the pipeline's channel order
+    /// (an empty `input` conceals a lost frame)
+    pub fn decode_float(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize, Error> {
+        let data = if input.is_empty() { std::ptr::null() } else { input.as_ptr() };
+        let frame_size = output.len() / self.channels;
+        self.decoded.resize(frame_size * self.channels, 0.0);
+        let samples = check(unsafe {
+            opus_multistream_decode_float(
+                self.ptr,
+                data,
+                input.len() as i32,
+                self.decoded.as_mut_ptr(),
+                frame_size as c_int,
+                fec as c_int,
+            )
+        })? as usize;
+
+        for (out, frame) in output
+            .chunks_exact_mut(self.channels)
+            .zip(self.decoded.chunks_exact(self.channels))
+            .take(samples)
+        {
+            for (vorbis, &smpte) in self.order.iter().enumerate() {
+                out[smpte] = frame[vorbis];
+            }
+        }
+        Ok(samples)
+    }
+
+    pub fn reset_state(&mut self) -> Result<(), Error> {
+        check(unsafe { opus_multistream_decoder_ctl(self.ptr, OPUS_RESET_STATE) }).map(|_| ())
+    }
+}
+
+impl Drop for SurroundDecoder {
+    fn drop(&mut self) {
+        unsafe { opus_multistream_decoder_destroy(self.ptr) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts_match_libopus() {
+        for channels in 3..=MAX_SURROUND_CHANNELS {
+            let mut error = OPUS_OK;
+            let mut streams: c_int = 0;
+            let mut coupled: c_int = 0;
+            let mut mapping = [0u8; MAX_SURROUND_CHANNELS as usize];
+            let ptr = unsafe {
+                opus_multistream_surround_encoder_create(
+                    48000,
+                    channels as c_int,
+                    MAPPING_FAMILY_VORBIS,
+                    &mut streams,
+                    &mut coupled,
+                    mapping.as_mut_ptr(),
+                    Application::Audio as c_int,
+                    &mut error,
+                )
+            };
+            assert_eq!(error, OPUS_OK);
+            unsafe { opus_multistream_encoder_destroy(ptr) };
+
+            let (expected_streams, expected_coupled, expected_mapping) = LAYOUTS[channels as usize - 3];
+            assert_eq!((streams, coupled), (expected_streams as c_int, expected_coupled as c_int), "{} channels", channels);
+            assert_eq!(&mapping[..channels as usize], expected_mapping, "{} channels", channels);
+        }
+        assert!(!is_surround(2) && is_surround(6) && !is_surround(9));
+        assert!(SurroundDecoder::new(48000, 2).is_err());
+    }
+
+    #[test]
+    fn test_surround_round_trip() {
+        let mut encoder = SurroundEncoder::new(48000, 6, Application::Audio).unwrap();
+        encoder.set_bitrate(Bitrate::Bits(384_000)).unwrap();
+        let mut decoder = SurroundDecoder::new(48000, 6).unwrap();
+
+        // A tone on the centre channel only (L R C LFE Ls Rs)
+        let frame_size = 480;
+        let mut output = vec![0.0f32; frame_size * 6];
+        let mut energy = [0.0f32; 6];
+        for frame in 0..20 {
+            let input: Vec<f32> = (0..frame_size)
+                .flat_map(|i| {
+                    let t = (frame * frame_size + i) as f32 / 48000.0;
+                    let tone = (t * 1000.0 * 2.0 * std::f32::consts::PI).sin() * 0.5;
+                    [0.0, 0.0, tone, 0.0, 0.0, 0.0]
+                })
+                .collect();
+            let mut packet = [0u8; 4000];
+            let len = encoder.encode_float(&input, &mut packet).unwrap();
+            assert_eq!(decoder.decode_float(&packet[..len], &mut output, false).unwrap(), frame_size);
+            if frame >= 10 {
+                for sample in output.chunks_exact(6) {
+                    for (channel, value) in sample.iter().enumerate() {
+                        energy[channel] += value * value;
+                    }
+                }
+            }
+        }
+        // The tone comes back on the centre, not swapped with a side
+        let centre = energy[2];
+        assert!(centre > 1.0, "{:?}", energy);
+        for (channel, &other) in energy.iter().enumerate().filter(|&(channel, _)| channel != 2) {
+            assert!(other < centre * 0.05, "channel {}: {:?}", channel, energy);
+        }
+
+        // Concealment of a lost frame
+        assert_eq!(decoder.decode_float(&[], &mut output, false).unwrap(), frame_size);
+        decoder.reset_state().unwrap();
+    }
+}
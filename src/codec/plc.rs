@@ -0,0 +1,94 @@
+//! Packet loss concealment
+//!
+//! When the jitter buffer reaches a slot whose packet never arrived (and
+//! could not be recovered from FEC), the Opus decoder is run with an empty
+//! payload to synthesize a frame that continues the signal instead of
+//! leaving a gap in the output.
+
+use std::time::Instant;
+
+use crate::audio::buffer::{AudioFrame, JitterBuffer, Playout};
+use crate::audio::scheduler::PlayoutScheduler;
+use crate::codec::AudioDecoder;
+
+/// Next frame in playout order, with lost slots replaced by concealment.
+/// Returns None while the jitter buffer is still filling.
+pub fn next_frame_concealed(decoder: &mut dyn AudioDecoder, jitter_buffer: &mut JitterBuffer) -> Option<AudioFrame> {
+    let playout = jitter_buffer.next_playout()?;
+    conceal(decoder, jitter_buffer, playout)
+}
+
+/// Next frame the scheduler releases at `now`, with lost slots replaced
+/// by concealment. Returns None until another frame is due.
+pub fn next_frame_scheduled(
+    decoder: &mut dyn AudioDecoder,
+    jitter_buffer: &mut JitterBuffer,
+    scheduler: &mut PlayoutScheduler,
+    now: Instant,
+) -> Option<AudioFrame> {
+    let playout = scheduler.next_playout(jitter_buffer, now)?;
+    conceal(decoder, jitter_buffer, playout)
+}
+
+/// The frame of a played-out slot, concealed if it was lost
+fn conceal(decoder: &mut dyn AudioDecoder, jitter_buffer: &mut JitterBuffer, playout: Playout) -> Option<AudioFrame> {
+    match playout {
+        Playout::Frame(frame) => Some(frame),
+        Playout::Lost { sequence, timestamp } => match decoder.decode_plc() {
+            Ok(samples) => {
+                jitter_buffer.record_concealed();
+                Some(AudioFrame::new(samples, decoder.channels(), timestamp, sequence))
+            }
+            Err(e) => {
+                tracing::debug!("PLC failed for frame {}: {}", sequence, e);
+                None
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{OpusDecoder, OpusEncoder};
+
+    #[test]
+    fn test_lost_slot_is_concealed() {
+        let mut encoder = OpusEncoder::music(48000, 2).unwrap();
+        let mut decoder = OpusDecoder::new(48000, 2, encoder.frame_size()).unwrap();
+        let mut jitter = JitterBuffer::new(16, 2);
+        let samples_per_frame = encoder.samples_per_frame();
+
+        let mut played = Vec::new();
+        for seq in 0..40u32 {
+            let samples: Vec<f32> = (0..samples_per_frame)
+                .map(|i| ((seq as usize * samples_per_frame + i) as f32 * 0.03).sin() * 0.5)
+                .collect();
+            let payload = encoder.encode(&samples).unwrap();
+            if seq == 20 || seq == 30 || seq == 31 {
+                continue;
+            }
+
+            let decoded = decoder.decode(&payload).unwrap();
+            jitter.insert(AudioFrame::new(decoded, 2, seq as u64 * 10_000, seq));
+            while let Some(frame) = next_frame_concealed(&mut decoder, &mut jitter) {
+                played.push(frame);
+            }
+        }
+
+        // No gaps in the output sequence
+        for (i, frame) in played.iter().enumerate() {
+            assert_eq!(frame.sequence, i as u32);
+            assert_eq!(frame.samples.len(), samples_per_frame);
+        }
+        assert!(played.len() > 31);
+
+        let concealed = &played[20];
+        assert_eq!(concealed.timestamp, 200_000);
+        assert_eq!(played[31].timestamp, 310_000);
+
+        let stats = jitter.stats();
+        assert_eq!(stats.concealed, 3);
+        assert_eq!(stats.lost, 3);
+    }
+}
@@ -1,13 +1,24 @@
 //! Configuration management
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
+use crate::audio::level_meter::LevelMeterParams;
+use crate::codec::multistream::MAX_SURROUND_CHANNELS;
 use crate::constants::*;
-use crate::protocol::{TrackConfig, TrackType};
+use crate::network::crypto::PacketCipher;
+use crate::network::handshake::PeerCapabilities;
+use crate::protocol::{MidiMapping, OutputDsp, TrackConfig, TrackRoute, TrackType};
 
 /// Application configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AppConfig {
+    /// Hardware profile (see `apply_profile`)
+    #[serde(default)]
+    pub profile: DeviceProfile,
+    
     /// Network configuration
     pub network: NetworkConfig,
     
@@ -17,17 +28,129 @@ pub struct AppConfig {
     /// UI configuration
     pub ui: UiConfig,
     
-    /// Pre-configured tracks
+    /// Statistics logging
+    #[serde(default)]
+    pub stats: StatsConfig,
+    
+    /// Routing of input tracks to peers
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    
+    /// Tracks the sender creates for its input devices at startup
+    #[serde(default)]
+    pub auto_tracks: AutoTrackConfig,
+    
+    /// Peers connected or renamed in the web UI, connected again at startup
+    #[serde(default)]
+    pub peers: Vec<SavedPeer>,
+    
+    /// Pre-configured tracks (also the tracks created or edited in the web UI)
     pub tracks: Vec<TrackConfig>,
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            network: NetworkConfig::default(),
-            audio: AudioConfig::default(),
-            ui: UiConfig::default(),
-            tracks: Vec::new(),
+impl AppConfig {
+    /// What a receiver with this configuration plays, as reported to the
+    /// senders of its tracks
+    pub fn receiver_capabilities(&self) -> PeerCapabilities {
+        PeerCapabilities {
+            supports_stereo: DEFAULT_CHANNELS >= 2,
+            // In-band FEC is not recovered from RTP packets
+            supports_fec: self.network.packet_format == PacketFormat::Native,
+            // RTP has no payload type for the lossless frames
+            supports_flac: self.network.packet_format == PacketFormat::Native,
+            max_tracks: self.profile.max_tracks() as u8,
+            // Nor for Opus multistream
+            max_channels: if self.network.packet_format == PacketFormat::Native {
+                MAX_SURROUND_CHANNELS as u8
+            } else {
+                DEFAULT_CHANNELS as u8
+            },
+            quic_port: (self.network.transport == TransportMode::Quic).then_some(self.network.quic_port),
+            ..PeerCapabilities::receiver_only()
+        }
+    }
+    
+    /// Capabilities announced in the handshake: `base` plus the key
+    /// fingerprint when a PSK is configured
+    pub fn handshake_capabilities(&self, base: PeerCapabilities) -> PeerCapabilities {
+        match self.network.cipher() {
+            Some(cipher) => base.with_encryption(cipher.fingerprint()),
+            None => base,
+        }
+    }
+    
+    /// Adjust the settings to the hardware profile (the low-power profile
+    /// only ever makes them cheaper, explicit cheaper values are kept)
+    pub fn apply_profile(&mut self) {
+        if self.profile != DeviceProfile::LowPower {
+            return;
+        }
+        self.stats.interval_secs = self.stats.interval_secs.max(LOW_POWER_STATS_INTERVAL_SECS);
+        self.network.send_buffer_size = self.network.send_buffer_size.min(LOW_POWER_SOCKET_BUFFER_SIZE);
+        self.network.recv_buffer_size = self.network.recv_buffer_size.min(LOW_POWER_SOCKET_BUFFER_SIZE);
+        self.network.recv_buffer_max_size = self.network.recv_buffer_max_size.min(LOW_POWER_RECV_BUFFER_MAX_SIZE);
+        self.ui.enabled = false;
+    }
+}
+
+/// Hardware profile
+///
+/// `LowPower` lets a Raspberry Pi class ARM board act as the receiving
+/// "PC": no web UI, stats logged rarely, small socket buffers and level
+/// meters that only fold peaks between updates
+/// ([`LevelMeterParams::low_power`]). It is the default on 32/64-bit ARM
+/// Linux; cross-compile for those targets with e.g.
+/// `cargo build --release --target aarch64-unknown-linux-gnu` (or
+/// `armv7-unknown-linux-gnueabihf`) against the target's ALSA and libopus.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceProfile {
+    /// Desktop or laptop PC
+    #[cfg_attr(not(all(target_os = "linux", any(target_arch = "arm", target_arch = "aarch64"))), default)]
+    Desktop,
+    /// Single-board ARM computer
+    #[cfg_attr(all(target_os = "linux", any(target_arch = "arm", target_arch = "aarch64")), default)]
+    LowPower,
+}
+
+impl DeviceProfile {
+    /// Profile requested with `LAN_AUDIO_PROFILE`, or the platform default
+    pub fn from_env() -> Self {
+        match std::env::var(PROFILE_ENV_VAR).ok().map(|value| value.parse()) {
+            Some(Ok(profile)) => profile,
+            Some(Err(e)) => {
+                tracing::warn!("{}", e);
+                Self::default()
+            }
+            None => Self::default(),
+        }
+    }
+    
+    /// Tracks a receiver with this profile plays at once
+    pub fn max_tracks(self) -> usize {
+        match self {
+            Self::Desktop => MAX_TRACKS,
+            Self::LowPower => LOW_POWER_MAX_TRACKS,
+        }
+    }
+    
+    /// Level meter parameters for tracks on this hardware
+    pub fn meter_params(self) -> LevelMeterParams {
+        match self {
+            Self::Desktop => LevelMeterParams::default(),
+            Self::LowPower => LevelMeterParams::low_power(),
+        }
+    }
+}
+
+impl std::str::FromStr for DeviceProfile {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "desktop" => Ok(Self::Desktop),
+            "low-power" | "lowpower" | "pi" => Ok(Self::LowPower),
+            other => Err(format!("Unknown device profile: {}", other)),
         }
     }
 }
@@ -35,13 +158,13 @@ impl Default for AppConfig {
 /// Network configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
-    /// Local bind address
+    /// Local bind address ("0.0.0.0", "::" for dual-stack, or a specific IP)
     pub bind_address: String,
     
     /// UDP port for audio streaming
     pub udp_port: u16,
     
-    /// Remote destination address (for sender)
+    /// Remote destination address (for sender): "IP", "IP:PORT" or "[IPv6]:PORT"
     pub remote_address: Option<String>,
     
     /// Socket send buffer size
@@ -50,8 +173,416 @@ pub struct NetworkConfig {
     /// Socket receive buffer size
     pub recv_buffer_size: usize,
     
+    /// Grow the receive buffer when the kernel reports dropped packets
+    /// (Linux; see `network::buffer_tuning`)
+    #[serde(default = "NetworkConfig::default_recv_buffer_autotune")]
+    pub recv_buffer_autotune: bool,
+    
+    /// Receive buffer size autotuning may grow to
+    #[serde(default = "NetworkConfig::default_recv_buffer_max_size")]
+    pub recv_buffer_max_size: usize,
+    
     /// Enable SO_REUSEADDR
     pub reuse_addr: bool,
+    
+    /// Peer discovery backend
+    #[serde(default)]
+    pub discovery_mode: DiscoveryMode,
+    
+    /// Pre-shared key for audio encryption (None = unencrypted).
+    /// When set, unencrypted packets and peers are rejected.
+    #[serde(default)]
+    pub psk: Option<String>,
+    
+    /// With a PSK, still accept tracks their sender marks as plaintext
+    /// (`TrackConfig::plaintext`) to save decryption CPU
+    #[serde(default)]
+    pub allow_plaintext_tracks: bool,
+    
+    /// Audio packet format on the wire
+    #[serde(default)]
+    pub packet_format: PacketFormat,
+    
+    /// RTP payload type for Opus in the RTP packet format
+    #[serde(default = "NetworkConfig::default_rtp_payload_type")]
+    pub rtp_payload_type: u8,
+    
+    /// Windows thread scheduling and flow tagging
+    #[serde(default)]
+    pub qos: QosConfig,
+    
+    /// IP multicast group for one-to-many streaming ("239.255.77.1",
+    /// "ff02::77"): the sender sends every packet once to the group and
+    /// receivers join it. Receivers must bind an unspecified address.
+    #[serde(default)]
+    pub multicast_group: Option<String>,
+    
+    /// TTL (hop limit for IPv6) of multicast packets; 1 keeps them on
+    /// the local subnet
+    #[serde(default = "NetworkConfig::default_multicast_ttl")]
+    pub multicast_ttl: u32,
+    
+    /// Stream transport for networks that drop UDP, or QUIC
+    #[serde(default)]
+    pub transport: TransportMode,
+    
+    /// UDP port receivers with `TransportMode::Quic` accept QUIC on
+    #[serde(default = "NetworkConfig::default_quic_port")]
+    pub quic_port: u16,
+    
+    /// Keep a log of control packets for debugging pairing (see `network::packet_log`)
+    #[serde(default)]
+    pub debug_capture: bool,
+    
+    /// Where files dropped by peers are saved (default: `received` in the data directory)
+    #[serde(default)]
+    pub received_files_dir: Option<PathBuf>,
+    
+    /// Peer allowlist and PIN pairing
+    #[serde(default)]
+    pub pairing: PairingConfig,
+    
+    /// Sending within the bandwidth of the link to the receivers
+    #[serde(default)]
+    pub congestion: CongestionConfig,
+    
+    /// Simulated loss, delay, duplication and reordering of received
+    /// audio (testing only)
+    #[serde(default)]
+    pub simulate: NetworkSimulation,
+    
+    /// File every received audio packet is dumped to, for `replay`
+    #[serde(default)]
+    pub packet_dump: Option<PathBuf>,
+    
+    /// pcapng file capturing every packet sent and received, for Wireshark
+    #[serde(default)]
+    pub pcap: Option<PathBuf>,
+}
+
+/// Scheduling and network QoS (see `network::qos`; MMCSS and qWave are
+/// Windows only, DSCP marking Linux only)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QosConfig {
+    /// Register the streaming threads with the Multimedia Class Scheduler
+    pub mmcss: bool,
+    
+    /// MMCSS task the threads join ("Pro Audio", "Audio", ...)
+    pub mmcss_task: String,
+    
+    /// Tag outgoing audio flows as audio/video traffic with qWave
+    pub qwave: bool,
+    
+    /// Mark datagrams with the DSCP class of their track's priority
+    /// (IP_TOS / IPV6_TCLASS)
+    pub dscp: bool,
+}
+
+impl Default for QosConfig {
+    fn default() -> Self {
+        Self {
+            mmcss: true,
+            mmcss_task: DEFAULT_MMCSS_TASK.to_string(),
+            qwave: true,
+            dscp: true,
+        }
+    }
+}
+
+impl QosConfig {
+    /// Whether `LAN_AUDIO_QOS` turns MMCSS, qWave and DSCP marking off
+    pub fn disabled_by_env() -> bool {
+        std::env::var(QOS_ENV_VAR).is_ok_and(|qos| matches!(qos.as_str(), "0" | "false"))
+    }
+    
+    /// Leave scheduling and flows to the OS defaults
+    pub fn disable(&mut self) {
+        self.mmcss = false;
+        self.qwave = false;
+        self.dscp = false;
+    }
+}
+
+/// Which peers may connect (see `network::pairing`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PairingConfig {
+    /// Reject Hello from peers that are not on the allowlist
+    pub required: bool,
+    
+    /// Peers paired with a PIN: node key and pairwise secret
+    /// (`key:secret`, hex); entries without a secret are ignored
+    pub allowed_peers: Vec<String>,
+    
+    /// Key of this node announced in Hello (generated and saved on the
+    /// first start)
+    pub key: Option<String>,
+}
+
+/// Congestion control of the sending side (see `network::congestion`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CongestionConfig {
+    /// Share a bandwidth estimate from the receivers' delay and loss
+    /// reports among the tracks
+    pub enabled: bool,
+    
+    /// Queuing delay above the link's base delay that counts as
+    /// congestion (ms)
+    pub max_queuing_delay_ms: u32,
+    
+    /// Pause low and normal priority tracks while the estimate can't
+    /// carry every track at its lowest bitrate
+    pub pause_tracks: bool,
+}
+
+impl Default for CongestionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_queuing_delay_ms: 40,
+            pause_tracks: true,
+        }
+    }
+}
+
+/// Simulated bad network on received audio, for testing the jitter
+/// buffer and FEC (see `network::netsim`; all zero = off)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NetworkSimulation {
+    /// Packets dropped (%)
+    pub loss_percent: f32,
+    
+    /// Delay added to every packet (ms)
+    pub delay_ms: u32,
+    
+    /// Random extra delay of up to this much (ms)
+    pub jitter_ms: u32,
+    
+    /// Packets delivered twice (%)
+    pub duplicate_percent: f32,
+    
+    /// Packets held back behind the next one (%)
+    pub reorder_percent: f32,
+}
+
+impl NetworkSimulation {
+    /// Whether any impairment is configured
+    pub fn is_active(&self) -> bool {
+        *self != Self::default()
+    }
+    
+    /// Settings from `LAN_AUDIO_SIMULATE` (`loss=5,delay=20,jitter=10,duplicate=1,reorder=2`)
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(SIMULATE_ENV_VAR).ok()?;
+        match value.parse() {
+            Ok(simulation) => Some(simulation),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                None
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkSimulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "loss {}%, delay {} ms, jitter {} ms, duplicate {}%, reorder {}%",
+            self.loss_percent, self.delay_ms, self.jitter_ms, self.duplicate_percent, self.reorder_percent
+        )
+    }
+}
+
+impl std::str::FromStr for NetworkSimulation {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut simulation = Self::default();
+        for setting in s.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid network simulation setting: {}", setting))?;
+            let invalid = || format!("Invalid value of {}: {}", name, value);
+            let percent = || match value.trim().trim_end_matches('%').parse::<f32>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent),
+                _ => Err(invalid()),
+            };
+            let ms = || value.trim().trim_end_matches("ms").parse::<u32>().map_err(|_| invalid());
+            match name.trim().to_ascii_lowercase().as_str() {
+                "loss" => simulation.loss_percent = percent()?,
+                "delay" => simulation.delay_ms = ms()?,
+                "jitter" => simulation.jitter_ms = ms()?,
+                "duplicate" => simulation.duplicate_percent = percent()?,
+                "reorder" => simulation.reorder_percent = percent()?,
+                other => return Err(format!("Unknown network simulation setting: {}", other)),
+            }
+        }
+        Ok(simulation)
+    }
+}
+
+/// Audio packet format on the wire
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketFormat {
+    /// Native `AudioPacket` (all features, optional encryption)
+    #[default]
+    Native,
+    /// Opus over RTP with RTCP reports, for GStreamer/VLC and hardware
+    /// receivers (see `network::rtp`)
+    Rtp,
+}
+
+/// How audio reaches the receiver
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportMode {
+    /// UDP only
+    Udp,
+    /// UDP, falling back to TCP when the receiver doesn't answer over UDP
+    /// (see `network::transport`)
+    #[default]
+    Auto,
+    /// TCP from the start
+    Tcp,
+    /// QUIC datagrams to receivers that offer QUIC, UDP to the others;
+    /// receivers listen for QUIC on `quic_port` (see `network::quic`)
+    Quic,
+}
+
+impl PacketFormat {
+    /// Format requested with `LAN_AUDIO_PACKET_FORMAT`, if set
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(PACKET_FORMAT_ENV_VAR).ok()?;
+        match value.parse() {
+            Ok(format) => Some(format),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                None
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for PacketFormat {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "native" => Ok(Self::Native),
+            "rtp" => Ok(Self::Rtp),
+            other => Err(format!("Unknown packet format: {}", other)),
+        }
+    }
+}
+
+/// Peer discovery backend
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscoveryMode {
+    /// UDP broadcast beacons (port 5001)
+    Broadcast,
+    /// mDNS/DNS-SD (`_lanaudio._udp.local`)
+    Mdns,
+    /// Both backends at once
+    #[default]
+    Both,
+}
+
+impl DiscoveryMode {
+    pub fn uses_broadcast(self) -> bool {
+        matches!(self, Self::Broadcast | Self::Both)
+    }
+    
+    pub fn uses_mdns(self) -> bool {
+        matches!(self, Self::Mdns | Self::Both)
+    }
+}
+
+impl std::str::FromStr for DiscoveryMode {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "broadcast" => Ok(Self::Broadcast),
+            "mdns" => Ok(Self::Mdns),
+            "both" => Ok(Self::Both),
+            other => Err(format!("Unknown discovery mode: {}", other)),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Socket address to bind the audio socket to
+    pub fn bind_socket_addr(&self) -> Option<SocketAddr> {
+        parse_socket_addr(&self.bind_address, self.udp_port)
+    }
+    
+    /// Packet cipher for the configured pre-shared key
+    pub fn cipher(&self) -> Option<PacketCipher> {
+        self.psk
+            .as_deref()
+            .filter(|psk| !psk.is_empty())
+            .map(PacketCipher::from_psk)
+    }
+    
+    fn default_rtp_payload_type() -> u8 {
+        crate::network::rtp::DEFAULT_PAYLOAD_TYPE
+    }
+    
+    fn default_recv_buffer_autotune() -> bool {
+        true
+    }
+    
+    fn default_recv_buffer_max_size() -> usize {
+        DEFAULT_RECV_BUFFER_MAX_SIZE
+    }
+    
+    fn default_multicast_ttl() -> u32 {
+        DEFAULT_MULTICAST_TTL
+    }
+    
+    fn default_quic_port() -> u16 {
+        DEFAULT_QUIC_PORT
+    }
+    
+    /// Configured multicast group, if it is a valid multicast address
+    pub fn multicast_group_addr(&self) -> Option<IpAddr> {
+        self.multicast_group
+            .as_deref()
+            .and_then(|group| group.trim().trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok())
+            .filter(IpAddr::is_multicast)
+    }
+    
+    /// Destination of multicast streaming (the group on `udp_port`)
+    pub fn multicast_socket_addr(&self) -> Option<SocketAddr> {
+        self.multicast_group_addr()
+            .map(|group| SocketAddr::new(group, self.udp_port))
+    }
+    
+    /// Configured remote destination, if any (port defaults to `udp_port`)
+    pub fn remote_socket_addr(&self) -> Option<SocketAddr> {
+        self.remote_address
+            .as_deref()
+            .and_then(|addr| parse_socket_addr(addr, self.udp_port))
+    }
+}
+
+/// Parse "IP", "IP:PORT", "[IPv6]" or "[IPv6]:PORT"
+pub fn parse_socket_addr(address: &str, default_port: u16) -> Option<SocketAddr> {
+    let address = address.trim();
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    
+    let ip = address.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>()
+        .ok()
+        .map(|ip| SocketAddr::new(ip, default_port))
 }
 
 impl Default for NetworkConfig {
@@ -62,7 +593,26 @@ impl Default for NetworkConfig {
             remote_address: None,
             send_buffer_size: 4 * 1024 * 1024, // 4 MB - larger to handle bursts
             recv_buffer_size: 4 * 1024 * 1024, // 4 MB - larger to prevent drops
+            recv_buffer_autotune: Self::default_recv_buffer_autotune(),
+            recv_buffer_max_size: Self::default_recv_buffer_max_size(),
             reuse_addr: true,
+            discovery_mode: DiscoveryMode::default(),
+            psk: None,
+            allow_plaintext_tracks: false,
+            packet_format: PacketFormat::default(),
+            rtp_payload_type: Self::default_rtp_payload_type(),
+            qos: QosConfig::default(),
+            multicast_group: None,
+            multicast_ttl: Self::default_multicast_ttl(),
+            transport: TransportMode::default(),
+            quic_port: Self::default_quic_port(),
+            debug_capture: false,
+            received_files_dir: None,
+            pairing: PairingConfig::default(),
+            congestion: CongestionConfig::default(),
+            simulate: NetworkSimulation::default(),
+            packet_dump: None,
+            pcap: None,
         }
     }
 }
@@ -85,11 +635,35 @@ pub struct AudioConfig {
     /// Default jitter buffer size in ms
     pub jitter_buffer_ms: u32,
     
-    /// Enable WASAPI exclusive mode (Windows)
+    /// Open device streams in WASAPI exclusive mode (Windows), falling
+    /// back to shared mode where that fails
     pub wasapi_exclusive: bool,
     
-    /// Use low-latency WASAPI shared mode
+    /// Ask WASAPI for small buffers: a 3 ms buffer in shared mode, the
+    /// device's minimum period in exclusive mode
     pub wasapi_low_latency: bool,
+    
+    /// Audio host backend
+    #[serde(default)]
+    pub backend: AudioBackend,
+    
+    /// What soloing a received track does
+    #[serde(default)]
+    pub solo_mode: SoloMode,
+    
+    /// Output soloed tracks are copied to in PFL mode
+    #[serde(default)]
+    pub monitor_device: Option<String>,
+    
+    /// Master processing per output device ID (also set with
+    /// `PUT /api/outputs/:device_id/dsp`)
+    #[serde(default)]
+    pub output_dsp: BTreeMap<String, OutputDsp>,
+    
+    /// Where recordings of received audio are written (default:
+    /// `recordings` in the data directory)
+    #[serde(default)]
+    pub recordings_dir: Option<PathBuf>,
 }
 
 impl Default for AudioConfig {
@@ -102,6 +676,64 @@ impl Default for AudioConfig {
             jitter_buffer_ms: DEFAULT_JITTER_BUFFER_MS,
             wasapi_exclusive: false,
             wasapi_low_latency: true,
+            backend: AudioBackend::default(),
+            solo_mode: SoloMode::default(),
+            monitor_device: None,
+            output_dsp: BTreeMap::new(),
+            recordings_dir: None,
+        }
+    }
+}
+
+/// What soloing a received track does
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SoloMode {
+    /// Solo-in-place: tracks that aren't soloed go silent on their outputs
+    #[default]
+    InPlace,
+    /// Pre-fader listen: soloed tracks are also played on
+    /// `AudioConfig::monitor_device` at unity gain, other outputs are unchanged
+    Pfl,
+}
+
+/// Audio host backend
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    /// Platform default (WASAPI, ALSA/PipeWire, CoreAudio)
+    #[default]
+    Default = 0,
+    /// JACK: every track gets its own client and ports (needs the `jack` feature)
+    Jack = 1,
+    /// PipeWire through `pw-cat`: every track gets its own node (needs the
+    /// `pipewire` feature and the PipeWire command line tools)
+    Pipewire = 2,
+}
+
+impl AudioBackend {
+    /// Backend requested with `LAN_AUDIO_BACKEND`, if set
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(AUDIO_BACKEND_ENV_VAR).ok()?;
+        match value.parse() {
+            Ok(backend) => Some(backend),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                None
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for AudioBackend {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "default" => Ok(Self::Default),
+            "jack" => Ok(Self::Jack),
+            "pipewire" => Ok(Self::Pipewire),
+            other => Err(format!("Unknown audio backend: {}", other)),
         }
     }
 }
@@ -109,6 +741,10 @@ impl Default for AudioConfig {
 /// UI configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UiConfig {
+    /// Serve the web UI
+    #[serde(default = "UiConfig::default_enabled")]
+    pub enabled: bool,
+    
     /// HTTP server port
     pub http_port: u16,
     
@@ -123,20 +759,296 @@ pub struct UiConfig {
     
     /// Static files directory
     pub static_dir: Option<PathBuf>,
+    
+    /// Level meter updates pushed to WebSocket clients per second (0 = off)
+    #[serde(default = "UiConfig::default_level_push_hz")]
+    pub level_push_hz: f32,
+    
+    /// Full track status pushes per second (0 = clients poll `GetStatus`)
+    #[serde(default = "UiConfig::default_status_push_hz")]
+    pub status_push_hz: f32,
+    
+    /// JSON-RPC control port on `bind_address` for scripts (0 = off); it
+    /// keeps running when the web UI is disabled
+    #[serde(default = "UiConfig::default_rpc_port")]
+    pub rpc_port: u16,
+    
+    /// OSC remote control for control surfaces and TouchOSC
+    #[serde(default)]
+    pub osc: OscConfig,
+    
+    /// MIDI controller input
+    #[serde(default)]
+    pub midi: MidiConfig,
 }
 
 impl Default for UiConfig {
     fn default() -> Self {
         Self {
+            enabled: true,
             http_port: DEFAULT_WS_PORT,
             ws_port: DEFAULT_WS_PORT,
             bind_address: "127.0.0.1".to_string(),
             enable_cors: true,
             static_dir: None,
+            level_push_hz: Self::default_level_push_hz(),
+            status_push_hz: Self::default_status_push_hz(),
+            rpc_port: Self::default_rpc_port(),
+            osc: OscConfig::default(),
+            midi: MidiConfig::default(),
+        }
+    }
+}
+
+impl UiConfig {
+    fn default_enabled() -> bool {
+        true
+    }
+    
+    fn default_level_push_hz() -> f32 {
+        10.0
+    }
+    
+    fn default_status_push_hz() -> f32 {
+        1.0
+    }
+    
+    fn default_rpc_port() -> u16 {
+        DEFAULT_RPC_PORT
+    }
+    
+    /// Whether the server runs at all (web UI, control port, OSC or MIDI)
+    pub fn serves(&self) -> bool {
+        self.enabled || self.rpc_port != 0 || self.osc.enabled || self.midi.enabled
+    }
+}
+
+/// OSC (Open Sound Control) remote control
+///
+/// The addresses are patterns where `{id}` stands for the track ID, e.g.
+/// `/track/{id}/mute`; an empty pattern turns the control off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OscConfig {
+    pub enabled: bool,
+    
+    /// UDP port OSC messages are received on (on the UI's bind address)
+    pub port: u16,
+    
+    /// Further receivers of levels and track state ("IP:port"), e.g. a
+    /// hardware controller; clients that sent a message get them anyway
+    pub feedback: Vec<String>,
+    
+    /// Level updates sent to clients per second (0 = off)
+    pub level_hz: f32,
+    
+    /// Mute a track: 1/true mutes, 0/false unmutes, no argument asks
+    pub mute: String,
+    
+    /// Solo a track
+    pub solo: String,
+    
+    /// Track volume as linear gain (1.0 = unity)
+    pub volume: String,
+    
+    /// Track level, normalized 0..1 (sent to clients, no argument asks)
+    pub level: String,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_OSC_PORT,
+            feedback: Vec::new(),
+            level_hz: 10.0,
+            mute: "/track/{id}/mute".to_string(),
+            solo: "/track/{id}/solo".to_string(),
+            volume: "/track/{id}/volume".to_string(),
+            level: "/track/{id}/level".to_string(),
+        }
+    }
+}
+
+/// MIDI controller input, e.g. a nanoKONTROL running the mixer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiConfig {
+    pub enabled: bool,
+    
+    /// Connect input ports whose name contains this (None = every port)
+    pub port: Option<String>,
+    
+    /// Controls mapped to track volume, mute and solo; controls learned in
+    /// the web UI are added here
+    pub mappings: Vec<MidiMapping>,
+}
+
+/// Periodic statistics logging
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsConfig {
+    /// Interval between stats log lines in seconds
+    pub interval_secs: u64,
+    
+    /// Quiet mode: stats are not logged, only exposed through the web UI/API
+    pub quiet: bool,
+    
+    /// Latency measurement mode: outgoing tracks carry a probe chirp
+    /// every few seconds
+    #[serde(default)]
+    pub latency_probe: bool,
+    
+    /// Input device of an analog loopback that listens for the probe chirp
+    #[serde(default)]
+    pub probe_loopback_device: Option<String>,
+    
+    /// Gap in the packets of a received track (ms) that is logged as an
+    /// incident in the activity timeline
+    #[serde(default = "StatsConfig::default_gap_threshold_ms")]
+    pub gap_threshold_ms: u32,
+    
+    /// Where the history of streaming sessions is kept (default:
+    /// `sessions.jsonl` in the data directory)
+    #[serde(default)]
+    pub sessions_file: Option<PathBuf>,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_STATS_INTERVAL_SECS,
+            quiet: false,
+            latency_probe: false,
+            probe_loopback_device: None,
+            gap_threshold_ms: DEFAULT_GAP_THRESHOLD_MS,
+            sessions_file: None,
         }
     }
 }
 
+impl StatsConfig {
+    fn default_gap_threshold_ms() -> u32 {
+        DEFAULT_GAP_THRESHOLD_MS
+    }
+    
+    /// Defaults overridden by `LAN_AUDIO_STATS_INTERVAL` / `LAN_AUDIO_QUIET` /
+    /// `LAN_AUDIO_LATENCY_PROBE` / `LAN_AUDIO_PROBE_LOOPBACK`
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(secs) = std::env::var(STATS_INTERVAL_ENV_VAR).ok().and_then(|v| v.parse().ok()) {
+            config.interval_secs = secs;
+        }
+        if let Ok(quiet) = std::env::var(QUIET_ENV_VAR) {
+            config.quiet = !matches!(quiet.as_str(), "" | "0" | "false");
+        }
+        if let Ok(probe) = std::env::var(LATENCY_PROBE_ENV_VAR) {
+            config.latency_probe = !matches!(probe.as_str(), "" | "0" | "false");
+        }
+        config.probe_loopback_device = std::env::var(PROBE_LOOPBACK_ENV_VAR).ok().filter(|id| !id.is_empty());
+        config
+    }
+    
+    /// Interval between stats log lines (at least one second)
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+    
+    /// Whether periodic stats should be written to the log
+    pub fn should_log(&self) -> bool {
+        !self.quiet
+    }
+}
+
+/// Peer kept in the configuration file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPeer {
+    /// Audio address ("IP:PORT")
+    pub address: String,
+    
+    /// Display name given in the web UI
+    #[serde(default)]
+    pub name: Option<String>,
+    
+    /// Transport to this peer (default: `network.transport`)
+    #[serde(default)]
+    pub transport: Option<TransportMode>,
+}
+
+/// Routing matrix between input tracks and peers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    /// Restricted tracks; tracks not listed are sent to every peer
+    pub routes: Vec<TrackRoute>,
+}
+
+/// Automatic track creation on the sender
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoTrackConfig {
+    /// Rules evaluated in order against the input devices; a device gets a
+    /// track from the first rule it matches
+    pub rules: Vec<AutoTrackRule>,
+}
+
+impl Default for AutoTrackConfig {
+    fn default() -> Self {
+        Self {
+            rules: vec![AutoTrackRule::default_input()],
+        }
+    }
+}
+
+/// Create a track for every input device matching a name pattern
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoTrackRule {
+    /// Device name pattern, case-insensitive: `*` matches any text, `?` one
+    /// character (`"*USB*"`); `"default"` matches the default input device
+    /// and a test signal ID (`"generator:sine:1000"`) adds a track of it
+    pub device: String,
+    
+    /// Track name, `{device}` is replaced by the device name
+    #[serde(default = "AutoTrackRule::default_name")]
+    pub name: String,
+    
+    /// Track type (affects Opus tuning)
+    #[serde(default)]
+    pub track_type: TrackType,
+    
+    /// Target bitrate in bits per second (track default if unset)
+    pub bitrate: Option<u32>,
+    
+    /// Frame size in milliseconds (track default if unset)
+    pub frame_size_ms: Option<f32>,
+    
+    /// Number of channels (track default if unset)
+    pub channels: Option<u16>,
+    
+    /// Enable FEC
+    #[serde(default)]
+    pub fec_enabled: bool,
+}
+
+impl AutoTrackRule {
+    /// Pattern matching the system default input device
+    pub const DEFAULT_DEVICE: &'static str = "default";
+    
+    /// One track for the default input device
+    pub fn default_input() -> Self {
+        Self {
+            device: Self::DEFAULT_DEVICE.to_string(),
+            name: "Default Input - {device}".to_string(),
+            track_type: TrackType::Music,
+            bitrate: None,
+            frame_size_ms: None,
+            channels: None,
+            fec_enabled: false,
+        }
+    }
+    
+    fn default_name() -> String {
+        "{device}".to_string()
+    }
+}
+
 /// Opus encoder configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpusConfig {
@@ -178,6 +1090,10 @@ pub struct OpusConfig {
     
     /// Maximum bandwidth
     pub max_bandwidth: OpusBandwidth,
+    
+    /// DRED history carried in every packet in ms (0 = off, see `codec::dred`)
+    #[serde(default)]
+    pub dred_duration_ms: u16,
 }
 
 impl Default for OpusConfig {
@@ -196,6 +1112,7 @@ impl Default for OpusConfig {
             cvbr: true,
             signal: OpusSignal::Auto,
             max_bandwidth: OpusBandwidth::Fullband,
+            dred_duration_ms: 0,
         }
     }
 }
@@ -216,6 +1133,23 @@ impl OpusConfig {
         }
     }
     
+    /// Enable or disable in-band FEC (sets a default loss hint so Opus
+    /// actually spends bits on redundancy)
+    pub fn with_fec(mut self, enabled: bool) -> Self {
+        self.fec = enabled;
+        if enabled && self.packet_loss_perc == 0 {
+            self.packet_loss_perc = DEFAULT_FEC_PACKET_LOSS_PERC;
+        }
+        self
+    }
+    
+    /// Enable or disable deep redundancy with the default history length
+    /// (ignored where libopus has no DRED support)
+    pub fn with_dred(mut self, enabled: bool) -> Self {
+        self.dred_duration_ms = crate::codec::dred::duration_ms(enabled);
+        self
+    }
+    
     /// Create config optimized for music
     pub fn music() -> Self {
         Self {
@@ -230,6 +1164,22 @@ impl OpusConfig {
         }
     }
     
+    /// Create config for the talkback voice track: no DTX so every press
+    /// starts transmitting immediately, FEC for the short voice bursts
+    pub fn talkback() -> Self {
+        Self {
+            bitrate: 32_000,
+            application: TrackType::Voice,
+            fec: true,
+            packet_loss_perc: DEFAULT_FEC_PACKET_LOSS_PERC,
+            complexity: 5,
+            dtx: false,
+            signal: OpusSignal::Voice,
+            max_bandwidth: OpusBandwidth::Wideband,
+            ..Default::default()
+        }
+    }
+    
     /// Create config optimized for low latency
     pub fn low_latency() -> Self {
         Self {
@@ -244,6 +1194,34 @@ impl OpusConfig {
         }
     }
     
+    /// Apply a track's complexity, bitrate mode, signal hint and maximum
+    /// bandwidth (those it leaves unset keep the preset)
+    pub fn with_track_settings(mut self, track: &TrackConfig) -> Self {
+        if let Some(complexity) = track.complexity {
+            self.complexity = complexity;
+        }
+        if let Some(mode) = track.bitrate_mode {
+            self.vbr = mode != OpusBitrateMode::Cbr;
+            self.cvbr = mode == OpusBitrateMode::Cvbr;
+        }
+        if let Some(signal) = track.signal {
+            self.signal = signal;
+        }
+        if let Some(max_bandwidth) = track.max_bandwidth {
+            self.max_bandwidth = max_bandwidth;
+        }
+        self
+    }
+    
+    /// Bitrate mode the VBR flags select
+    pub fn bitrate_mode(&self) -> OpusBitrateMode {
+        match (self.vbr, self.cvbr) {
+            (false, _) => OpusBitrateMode::Cbr,
+            (true, false) => OpusBitrateMode::Vbr,
+            (true, true) => OpusBitrateMode::Cvbr,
+        }
+    }
+    
     /// Calculate frame size in samples from milliseconds
     pub fn frame_size_from_ms(sample_rate: u32, ms: f32) -> usize {
         (sample_rate as f32 * ms / 1000.0) as usize
@@ -255,6 +1233,17 @@ impl OpusConfig {
     }
 }
 
+/// Opus bitrate mode
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OpusBitrateMode {
+    /// Constant bitrate
+    Cbr,
+    /// Unconstrained variable bitrate
+    Vbr,
+    /// Variable bitrate that never exceeds the target
+    Cvbr,
+}
+
 /// Opus signal type hint
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum OpusSignal {
@@ -300,4 +1289,31 @@ impl AppConfig {
         directories::ProjectDirs::from("com", "audio-streamer", "lan-audio")
             .map(|dirs| dirs.config_dir().join("config.toml"))
     }
+    
+    /// Directory for files received from peers
+    pub fn received_files_dir(&self) -> PathBuf {
+        self.network.received_files_dir.clone().unwrap_or_else(|| {
+            directories::ProjectDirs::from("com", "audio-streamer", "lan-audio")
+                .map(|dirs| dirs.data_dir().join("received"))
+                .unwrap_or_else(|| std::env::temp_dir().join("lan-audio-received"))
+        })
+    }
+    
+    /// Directory for recordings of received audio
+    pub fn recordings_dir(&self) -> PathBuf {
+        self.audio.recordings_dir.clone().unwrap_or_else(|| {
+            directories::ProjectDirs::from("com", "audio-streamer", "lan-audio")
+                .map(|dirs| dirs.data_dir().join("recordings"))
+                .unwrap_or_else(|| std::env::temp_dir().join("lan-audio-recordings"))
+        })
+    }
+    
+    /// File of the streaming session history
+    pub fn sessions_path(&self) -> PathBuf {
+        self.stats.sessions_file.clone().unwrap_or_else(|| {
+            directories::ProjectDirs::from("com", "audio-streamer", "lan-audio")
+                .map(|dirs| dirs.data_dir().join("sessions.jsonl"))
+                .unwrap_or_else(|| std::env::temp_dir().join("lan-audio-sessions.jsonl"))
+        })
+    }
 }
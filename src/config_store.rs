@@ -0,0 +1,390 @@
+//! Configuration file at runtime
+//!
+//! [`ConfigStore`] holds the contents of the configuration file while a
+//! binary runs. Tracks, peers, routing and output processing changed in
+//! the web UI are written back to the file [`SAVE_DELAY`] after the last
+//! change, so dragging a control doesn't rewrite it on every step. The file
+//! is also watched: an external edit is reloaded and sent to subscribers,
+//! which apply the network and audio settings that can change while
+//! streaming (see [`ConfigReload`]).
+//!
+//! Only the file's own settings are kept here: command line and
+//! environment overrides never end up in the file.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::broadcast;
+
+use crate::config::{AppConfig, SavedPeer};
+use crate::error::{Error, Result};
+use crate::protocol::TrackConfig;
+use crate::tracks::TrackManager;
+
+/// Time without changes before they are written to the file
+pub const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// Time between checks of the file for external edits
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Modification time and length of the file
+type Fingerprint = (SystemTime, u64);
+
+/// Configuration file shared by the parts of a running binary
+pub struct ConfigStore {
+    path: Option<PathBuf>,
+    state: Mutex<StoreState>,
+    reload_tx: broadcast::Sender<ConfigReload>,
+}
+
+struct StoreState {
+    config: AppConfig,
+    /// Time of the last change not written yet
+    changed_at: Option<Instant>,
+    /// The file as last read or written
+    fingerprint: Option<Fingerprint>,
+    /// The file exists but doesn't parse: it is left alone until it does
+    unreadable: bool,
+}
+
+impl ConfigStore {
+    /// Store of the file at `path`; a missing file is created with the
+    /// first change, one that doesn't parse is not overwritten. Without a
+    /// path changes are only kept in memory.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let store = Self::new(path.clone(), AppConfig::default());
+        let Some(path) = path.filter(|path| path.exists()) else {
+            return store;
+        };
+
+        match AppConfig::load(&path) {
+            Ok(config) => {
+                tracing::info!("Loaded configuration from {}", path.display());
+                store.state.lock().config = config;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load configuration from {}, not saving to it: {}", path.display(), e);
+                store.state.lock().unreadable = true;
+            }
+        }
+        store
+    }
+
+    /// Store of an existing file that must parse
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let config = AppConfig::load(&path).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        tracing::info!("Loaded configuration from {}", path.display());
+        Ok(Self::new(Some(path), config))
+    }
+
+    /// Store of `config` as read from `path`
+    pub fn new(path: Option<PathBuf>, config: AppConfig) -> Self {
+        let (reload_tx, _) = broadcast::channel(4);
+        Self {
+            state: Mutex::new(StoreState {
+                config,
+                changed_at: None,
+                fingerprint: path.as_deref().and_then(fingerprint),
+                unreadable: false,
+            }),
+            path,
+            reload_tx,
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Current contents of the file
+    pub fn config(&self) -> AppConfig {
+        self.state.lock().config.clone()
+    }
+
+    /// Receive the file after every external edit
+    pub fn subscribe(&self) -> broadcast::Receiver<ConfigReload> {
+        self.reload_tx.subscribe()
+    }
+
+    /// Change the contents; the file is written [`SAVE_DELAY`] later
+    pub fn update(&self, change: impl FnOnce(&mut AppConfig)) {
+        let mut state = self.state.lock();
+        change(&mut state.config);
+        state.changed_at = Some(Instant::now());
+    }
+
+    /// Save a track created or edited in the UI (replaces the saved track
+    /// with its ID)
+    pub fn save_track(&self, track: &TrackConfig) {
+        self.update(|config| match config.tracks.iter_mut().find(|saved| saved.track_id == track.track_id) {
+            Some(saved) => *saved = track.clone(),
+            None => config.tracks.push(track.clone()),
+        });
+    }
+
+    pub fn forget_track(&self, track_id: u8) {
+        if self.state.lock().config.tracks.iter().any(|saved| saved.track_id == Some(track_id)) {
+            self.update(|config| config.tracks.retain(|saved| saved.track_id != Some(track_id)));
+        }
+    }
+
+    /// Save a peer connected or renamed in the UI by its audio address
+    pub fn save_peer(&self, address: &str, name: Option<&str>) {
+        self.update(|config| match config.peers.iter_mut().find(|saved| saved.address == address) {
+            Some(saved) => saved.name = name.map(str::to_string).or(saved.name.take()),
+            None => config.peers.push(SavedPeer {
+                address: address.to_string(),
+                name: name.map(str::to_string),
+                transport: None,
+            }),
+        });
+    }
+
+    pub fn forget_peer(&self, address: &str) {
+        if self.state.lock().config.peers.iter().any(|saved| saved.address == address) {
+            self.update(|config| config.peers.retain(|saved| saved.address != address));
+        }
+    }
+
+    /// Write pending changes now (at shutdown)
+    pub fn flush(&self) {
+        let mut state = self.state.lock();
+        if state.changed_at.is_some() {
+            self.write(&mut state);
+        }
+    }
+
+    /// Write changes older than [`SAVE_DELAY`] and reload the file if it
+    /// was edited since it was last read or written
+    pub fn poll(&self) -> Option<ConfigReload> {
+        self.poll_at(Instant::now())
+    }
+
+    fn poll_at(&self, now: Instant) -> Option<ConfigReload> {
+        let path = self.path.as_deref()?;
+        let mut state = self.state.lock();
+
+        let current = fingerprint(path);
+        if current.is_some() && current != state.fingerprint {
+            state.fingerprint = current;
+            if state.changed_at.take().is_some() {
+                tracing::warn!("{} was edited, discarding unsaved changes from the UI", path.display());
+            }
+            return match AppConfig::load(&path.to_path_buf()) {
+                Ok(config) => {
+                    tracing::info!("Reloaded configuration from {}", path.display());
+                    state.unreadable = false;
+                    let reload = ConfigReload {
+                        old: std::mem::replace(&mut state.config, config.clone()),
+                        new: config,
+                    };
+                    let _ = self.reload_tx.send(reload.clone());
+                    Some(reload)
+                }
+                Err(e) => {
+                    tracing::warn!("Edited configuration {} not applied: {}", path.display(), e);
+                    state.unreadable = true;
+                    None
+                }
+            };
+        }
+
+        if state.changed_at.is_some_and(|changed_at| now.duration_since(changed_at) >= SAVE_DELAY) {
+            self.write(&mut state);
+        }
+        None
+    }
+
+    fn write(&self, state: &mut StoreState) {
+        state.changed_at = None;
+        let Some(path) = &self.path else {
+            return;
+        };
+        if state.unreadable {
+            tracing::warn!("{} doesn't parse, changes are not saved until it is fixed", path.display());
+            return;
+        }
+
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        match state.config.save(path) {
+            Ok(()) => tracing::debug!("Configuration saved to {}", path.display()),
+            Err(e) => tracing::warn!("Failed to save configuration to {}: {}", path.display(), e),
+        }
+        state.fingerprint = fingerprint(path);
+    }
+
+    /// Save changes and watch the file every [`WATCH_INTERVAL`] in the background
+    pub fn spawn_watcher(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                let poll_store = store.clone();
+                let _ = tokio::task::spawn_blocking(move || poll_store.poll()).await;
+            }
+        })
+    }
+}
+
+fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// The configuration file before and after an external edit
+///
+/// Output processing applies at once ([`apply_output_dsp`](Self::apply_output_dsp)),
+/// the binaries apply the settings they can change while streaming
+/// (the sender's `network.remote_address`, a receiver's
+/// `network.allow_plaintext_tracks`); the rest takes effect after a
+/// restart ([`restart_needed`](Self::restart_needed)).
+#[derive(Debug, Clone)]
+pub struct ConfigReload {
+    pub old: AppConfig,
+    pub new: AppConfig,
+}
+
+impl ConfigReload {
+    /// Whether a part of the configuration changed
+    pub fn changed<T: Serialize>(&self, part: impl Fn(&AppConfig) -> &T) -> bool {
+        !same(part(&self.old), part(&self.new))
+    }
+
+    /// Set the output processing of the new file
+    pub fn apply_output_dsp(&self, track_manager: &TrackManager) {
+        let (old, new) = (&self.old.audio.output_dsp, &self.new.audio.output_dsp);
+        for device_id in old.keys().filter(|device_id| !new.contains_key(*device_id)) {
+            let _ = track_manager.set_output_dsp(device_id, None);
+        }
+        for (device_id, dsp) in new.iter().filter(|(device_id, dsp)| old.get(*device_id) != Some(*dsp)) {
+            if let Err(e) = track_manager.set_output_dsp(device_id, Some(dsp.clone())) {
+                tracing::warn!("Output processing of {} not applied: {}", device_id, e);
+            }
+        }
+    }
+
+    /// Sections with changes that only take effect after a restart
+    pub fn restart_needed(&self) -> Vec<&'static str> {
+        let [old, new] = [&self.old, &self.new].map(|config| {
+            let mut config = config.clone();
+            // Applied while running, or only read at startup and saved by the UI
+            config.audio.output_dsp.clear();
+            config.network.remote_address = None;
+            config.network.allow_plaintext_tracks = false;
+            config.routing = Default::default();
+            config.peers.clear();
+            config.tracks.clear();
+            config
+        });
+
+        let mut sections = Vec::new();
+        for (name, changed) in [
+            ("profile", !same(&old.profile, &new.profile)),
+            ("network", !same(&old.network, &new.network)),
+            ("audio", !same(&old.audio, &new.audio)),
+            ("ui", !same(&old.ui, &new.ui)),
+            ("stats", !same(&old.stats, &new.stats)),
+            ("auto_tracks", !same(&old.auto_tracks, &new.auto_tracks)),
+        ] {
+            if changed {
+                sections.push(name);
+            }
+        }
+        sections
+    }
+
+    /// Log the sections waiting for a restart
+    pub fn log_restart_needed(&self) {
+        let sections = self.restart_needed();
+        if !sections.is_empty() {
+            tracing::warn!("Configuration changes in [{}] take effect after a restart", sections.join("], ["));
+        }
+    }
+}
+
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::OutputDsp;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lan-audio-config-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("config.toml")
+    }
+
+    #[test]
+    fn test_saves_after_delay() {
+        let path = temp_path("save");
+        let store = ConfigStore::open(Some(path.clone()));
+        let track = TrackConfig {
+            track_id: Some(2),
+            name: "Vocals".to_string(),
+            ..TrackConfig::default()
+        };
+        store.save_track(&track);
+        store.save_peer("192.168.1.20:5000", Some("Studio"));
+        store.save_peer("192.168.1.20:5000", None);
+
+        let start = Instant::now();
+        store.poll_at(start);
+        assert!(!path.exists());
+        assert!(store.poll_at(start + SAVE_DELAY).is_none());
+
+        let saved = AppConfig::load(&path).unwrap();
+        assert_eq!(saved.tracks.len(), 1);
+        assert_eq!(saved.tracks[0].name, "Vocals");
+        // Connecting again keeps the name
+        assert_eq!(saved.peers[0].name.as_deref(), Some("Studio"));
+
+        // Our own write isn't taken for an edit
+        assert!(store.poll_at(start + SAVE_DELAY * 2).is_none());
+
+        store.forget_track(2);
+        store.flush();
+        assert!(AppConfig::load(&path).unwrap().tracks.is_empty());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_reloads_external_edit() {
+        let path = temp_path("reload");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        AppConfig::default().save(&path).unwrap();
+        let store = ConfigStore::open(Some(path.clone()));
+        let mut reloads = store.subscribe();
+
+        let mut edited = AppConfig::default();
+        edited.audio.output_dsp.insert("output:Speakers".to_string(), OutputDsp { delay_ms: 12.0, ..OutputDsp::default() });
+        edited.network.udp_port = 5100;
+        edited.save(&path).unwrap();
+
+        let reload = store.poll().expect("edit reloaded");
+        assert!(reload.changed(|config| &config.audio.output_dsp));
+        assert_eq!(reload.restart_needed(), vec!["network"]);
+        assert_eq!(reloads.try_recv().unwrap().new.network.udp_port, 5100);
+        assert_eq!(store.config().network.udp_port, 5100);
+
+        let track_manager = TrackManager::new();
+        reload.apply_output_dsp(&track_manager);
+        assert_eq!(track_manager.output_dsp("output:Speakers").map(|dsp| dsp.delay_ms), Some(12.0));
+
+        // A broken edit is neither applied nor overwritten
+        std::fs::write(&path, "network = [").unwrap();
+        assert!(store.poll().is_none());
+        store.update(|config| config.network.udp_port = 5200);
+        store.flush();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "network = [");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,205 @@
+//! `ctl`: control a running instance from the command line
+//!
+//! Talks to the instance's JSON-RPC control port (`[ui] rpc_port` of the
+//! configuration file, or `--address`), so a host reached over SSH can be
+//! controlled without a browser: `ctl tracks`, `ctl track mute 2`,
+//! `ctl peers`, `ctl stats --follow`, and `ctl call <METHOD> [PARAMS]` for
+//! any other `ControlMessage` request.
+
+use serde_json::Value;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::{parse_socket_addr, AppConfig, UiConfig};
+use crate::constants::DEFAULT_RPC_PORT;
+use crate::error::{Error, Result};
+use crate::protocol::{ControlMessage, PeerStatus, TrackStatus};
+use crate::ui::rpc::RpcClient;
+
+/// What to do on the running instance
+#[derive(Debug, Clone, PartialEq)]
+pub enum CtlCommand {
+    /// List the tracks
+    Tracks,
+    Mute { track_id: u8, muted: bool },
+    Solo { track_id: u8, solo: bool },
+    RemoveTrack { track_id: u8 },
+    /// List the peers
+    Peers,
+    /// Print track statistics, every `interval` with `follow`
+    Stats { follow: bool, interval: Duration },
+    /// Call any method with JSON params and print the result
+    Call { method: String, params: Option<Value> },
+}
+
+/// Options of `ctl`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CtlArgs {
+    /// Control port as given (`IP[:PORT]`), else from the configuration file
+    pub address: Option<String>,
+    /// Configuration file given with `--config`
+    pub config: Option<PathBuf>,
+    pub command: CtlCommand,
+}
+
+/// Run a `ctl` command
+pub fn run(args: &CtlArgs) -> Result<()> {
+    let addr = match &args.address {
+        Some(address) => parse_socket_addr(address, DEFAULT_RPC_PORT)
+            .ok_or_else(|| Error::Config(format!("Invalid control address: {}", address)))?,
+        None => {
+            let path = args.config.clone().or_else(AppConfig::default_path);
+            let config = match path.filter(|path| path.exists()) {
+                Some(path) => AppConfig::load(&path)?,
+                None => AppConfig::default(),
+            };
+            control_address(&config.ui)?
+        }
+    };
+    let mut client = RpcClient::connect(addr)?;
+
+    match &args.command {
+        CtlCommand::Tracks => {
+            let tracks = statuses(&mut client)?;
+            for track in &tracks {
+                println!("{}", format_track(track));
+            }
+            if tracks.is_empty() {
+                println!("No tracks");
+            }
+        }
+        &CtlCommand::Mute { track_id, muted } => {
+            client.request(&ControlMessage::SetMute { track_id, muted })?;
+        }
+        &CtlCommand::Solo { track_id, solo } => {
+            client.request(&ControlMessage::SetSolo { track_id, solo })?;
+        }
+        &CtlCommand::RemoveTrack { track_id } => {
+            client.request(&ControlMessage::RemoveTrack { track_id })?;
+        }
+        CtlCommand::Peers => {
+            let peers: Vec<PeerStatus> = decode(client.request(&ControlMessage::ListPeers)?)?;
+            for peer in &peers {
+                println!("{}", format_peer(peer));
+            }
+            if peers.is_empty() {
+                println!("No peers");
+            }
+        }
+        &CtlCommand::Stats { follow, interval } => loop {
+            let tracks = statuses(&mut client)?;
+            if follow {
+                println!("--- {}", chrono::Local::now().format("%H:%M:%S"));
+            }
+            for track in &tracks {
+                println!("{}", format_stats(track));
+            }
+            if !follow {
+                break;
+            }
+            std::thread::sleep(interval);
+        },
+        CtlCommand::Call { method, params } => {
+            let result = client.call(method, params.clone())?;
+            println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+/// Control port of the instance using this UI configuration; a server on
+/// every interface is reached on the loopback address
+pub fn control_address(ui: &UiConfig) -> Result<SocketAddr> {
+    if ui.rpc_port == 0 {
+        return Err(Error::Config("the control port is off ([ui] rpc_port = 0)".to_string()));
+    }
+    let addr = parse_socket_addr(&ui.bind_address, ui.rpc_port)
+        .ok_or_else(|| Error::Config(format!("Invalid bind address: {}", ui.bind_address)))?;
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    Ok(SocketAddr::new(ip, ui.rpc_port))
+}
+
+fn statuses(client: &mut RpcClient) -> Result<Vec<TrackStatus>> {
+    decode(client.request(&ControlMessage::GetStatus)?)
+}
+
+fn decode<T: serde::de::DeserializeOwned>(value: Value) -> Result<T> {
+    serde_json::from_value(value).map_err(|e| Error::Config(format!("Unexpected reply: {}", e)))
+}
+
+fn format_track(track: &TrackStatus) -> String {
+    let mut flags = vec![if track.active { "active" } else { "stopped" }];
+    if track.muted {
+        flags.push("muted");
+    }
+    if track.solo {
+        flags.push("solo");
+    }
+    format!(
+        "{:>3}  {:<24} {:<20} {:?} {} kbps  {}",
+        track.track_id,
+        track.name,
+        flags.join(","),
+        track.codec,
+        track.bitrate / 1000,
+        track.device_id,
+    )
+}
+
+fn format_peer(peer: &PeerStatus) -> String {
+    format!(
+        "{:<22} {:<20} {:<12} {:<8} up {:.0} kbps, down {:.0} kbps, seen {} ms ago",
+        peer.id,
+        peer.name,
+        format!("{:?}", peer.connection).to_lowercase(),
+        if peer.active { "sending" } else { "idle" },
+        peer.bandwidth.up_kbps,
+        peer.bandwidth.down_kbps,
+        peer.last_seen_ms,
+    )
+}
+
+fn format_stats(track: &TrackStatus) -> String {
+    let received = track.packets_received + track.packets_lost;
+    let loss = if received > 0 { track.packets_lost as f32 * 100.0 / received as f32 } else { 0.0 };
+    format!(
+        "{:>3}  {:<24} sent {:>8}  received {:>8}  lost {:>6} ({:.1}%)  latency {:.1} ms  jitter {:.1} ms  level {:.1} dB",
+        track.track_id,
+        track.name,
+        track.packets_sent,
+        track.packets_received,
+        track.packets_lost,
+        loss,
+        track.current_latency_ms,
+        track.jitter_ms,
+        track.level_db,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_address() {
+        let mut ui = UiConfig::default();
+        assert_eq!(control_address(&ui).unwrap(), "127.0.0.1:8081".parse().unwrap());
+
+        // A server on every interface is reached on loopback
+        ui.bind_address = "0.0.0.0".to_string();
+        ui.rpc_port = 9000;
+        assert_eq!(control_address(&ui).unwrap(), "127.0.0.1:9000".parse().unwrap());
+        ui.bind_address = "::".to_string();
+        assert_eq!(control_address(&ui).unwrap(), "[::1]:9000".parse().unwrap());
+        ui.bind_address = "192.168.1.20".to_string();
+        assert_eq!(control_address(&ui).unwrap(), "192.168.1.20:9000".parse().unwrap());
+
+        ui.rpc_port = 0;
+        assert!(control_address(&ui).is_err());
+    }
+}
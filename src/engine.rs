@@ -0,0 +1,2479 @@
+//! Движок симметричного пира
+//!
+//! Вся работа приложения `peer`: захват и кодирование своих треков с
+//! отправкой подключённым пирам, приём, декодирование и воспроизведение
+//! их треков, обнаружение пиров, отчёты о потерях и веб-интерфейс.
+//! Бинарник только разбирает аргументы и запускает [`PeerEngine`].
+
+use crossbeam_channel::bounded;
+use parking_lot::Mutex;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::audio::{
+    agc::Agc,
+    buffer::{create_shared_buffer, AudioFrame, JitterBuffer, JitterDelay, SharedRingBuffer},
+    capture::AudioCapture,
+    convert::convert_channels,
+    device::{self, list_devices},
+    mixer::{MixerChannel, OutputMixer},
+    pool,
+    probe::{LoopbackProbe, ProbeInjector},
+    scheduler::PlayoutScheduler,
+    silence::{GateAction, SilenceGate},
+    simd,
+    virtual_output,
+    voice::VoiceProcessor,
+    wasapi,
+};
+use crate::codec::{
+    decoder_channels, dred, fec::recover_previous_frame, new_decoder, new_encoder, plc::next_frame_scheduled, select_channels, select_codec,
+    AdaptiveBitrate,
+    AudioDecoder, AudioEncoder, BitrateDecision,
+};
+use crate::config::{AppConfig, AudioBackend, DeviceProfile, DiscoveryMode, NetworkConfig, NetworkSimulation, OpusConfig, PacketFormat, QosConfig, SoloMode, StatsConfig, TransportMode};
+use crate::config_store::ConfigStore;
+use crate::constants::*;
+use crate::error::{Error, Result};
+use crate::network::{
+    congestion::{CongestionController, TrackDemand},
+    discovery::{DiscoveredPeer, DiscoveryService},
+    feedback::{FeedbackInbox, LossReporter, TrackFeedback, FEEDBACK_INTERVAL},
+    file_transfer::FileTransfers,
+    handshake::{
+        ConnectionEvent, HandshakeManager, HandshakePacket, HandshakeState, PeerCapabilities, TrackInfo, HELLO_TIMEOUT,
+    },
+    packet_dump,
+    packet_log,
+    pcap,
+    pairing::Pairing,
+    peers::PeerRegistry,
+    qos,
+    receiver::{AudioReceiver, ReceivedPacket},
+    sender::MultiTrackSender,
+    subscription::{TrackCatalog, TrackChange, TrackSubscriber},
+    timesync::{media_time_us, SuspendDetector, TimeSync},
+};
+use crate::profiling::{self, Stage};
+use crate::protocol::{
+    Codec, DropReason, PacketFlags, PeerConnection, PeerStatus, RemoteCapabilities, TrackConfig, TrackPriority,
+    HEADER_SIZE,
+};
+use crate::recording::Recorder;
+use crate::routing::RoutingMatrix;
+use crate::sessions::SessionStore;
+use crate::tracks::{ActivityKind, TrackEvent, TrackManager};
+use crate::ui::WebServer;
+
+/// Состояние входящего трека (для отправки аудио)
+struct InputTrackState {
+    capture: AudioCapture,
+    capture_buffer: SharedRingBuffer,
+    encoder: Box<dyn AudioEncoder>,
+    sample_buffer: Vec<f32>,
+    sequence: u32,
+    /// Следующий пакет помечается как перезапуск потока (новый энкодер)
+    restart_pending: bool,
+    /// Регулятор битрейта по отчётам получателей
+    adaptive: AdaptiveBitrate,
+    /// Отправка приостановлена контролем перегрузки
+    congestion_paused: bool,
+    /// Усиление последнего кадра (приглушение на время talkback)
+    gain: f32,
+    /// Пробы задержки (режим измерения)
+    probe: Option<ProbeInjector>,
+    /// Подавление тишины (None — выключено)
+    silence_gate: Option<SilenceGate>,
+    /// Обработка голоса на захвате (None — выключена)
+    voice: Option<VoiceProcessor>,
+    /// Автоматическая регулировка усиления (None — выключена)
+    agc: Option<Agc>,
+}
+
+/// Состояние выходящего трека (для получения аудио)
+#[allow(dead_code)]
+struct OutputTrackState {
+    decoder: Box<dyn AudioDecoder>,
+    jitter_buffer: JitterBuffer,
+    /// Выдаёт кадры джиттер-буфера на вывод по часам потока
+    scheduler: PlayoutScheduler,
+    /// Вход трека в общий поток устройства вывода
+    playback: Option<MixerChannel>,
+    packets_received: u64,
+    packets_lost: u64,
+    device_id: String,
+    channels: u16,
+    /// Учёт потерь для отчётов отправителю
+    loss_reporter: LossReporter,
+    /// Пир, от которого приходит трек (адресат отчётов)
+    source: Option<SocketAddr>,
+    /// Приход последнего пакета с аудио (None после маркера тишины)
+    last_arrival: Option<Instant>,
+}
+
+/// Вывод входящих треков: один поток на устройство, общий для всех треков
+struct PlaybackOutputs {
+    mixer: Arc<OutputMixer>,
+    /// Устройство для треков без явно выбранного
+    default_device: String,
+    /// Устройство прослушивания (PFL) для треков в соло
+    monitor_device: Option<String>,
+    /// Запись принятых треков в файлы
+    recorder: Arc<Recorder>,
+}
+
+/// Конфигурация пира
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    /// Имя этого пира (отображается другим пирам)
+    pub name: String,
+    /// Предпочтительный порт для аудио
+    pub preferred_port: u16,
+    /// Автоматическое подключение к обнаруженным пирам
+    pub auto_connect: bool,
+    /// Способ обнаружения пиров (None = из файла конфигурации)
+    pub discovery_mode: Option<DiscoveryMode>,
+    /// Общий ключ шифрования аудио
+    pub psk: Option<String>,
+    /// Принимать треки, которые отправитель не шифрует (`plaintext`)
+    pub allow_plaintext: bool,
+    /// Устройство захвата для трека внутренней связи (talkback)
+    pub talkback_device: Option<String>,
+    /// Периодическая статистика в логе
+    pub stats: StatsConfig,
+    /// Аудио-бэкенд (None = из файла конфигурации)
+    pub backend: Option<AudioBackend>,
+    /// Принимать от пиров только эти треки (None = все)
+    pub subscribed_tracks: Option<Vec<u8>>,
+    /// Профиль оборудования (None = из файла конфигурации)
+    pub profile: Option<DeviceProfile>,
+    /// Формат аудио-пакетов, RTP для GStreamer/VLC (None = из файла конфигурации)
+    pub packet_format: Option<PacketFormat>,
+    /// MMCSS и qWave в Windows
+    pub qos: bool,
+    /// Файл конфигурации: настройки, сохраняемые треки, пиры и
+    /// маршрутизация (None = без файла)
+    pub config_path: Option<PathBuf>,
+    /// Файл, в который записываются принятые пакеты для `replay`
+    pub packet_dump: Option<PathBuf>,
+    /// Файл pcapng с захватом отправленных и принятых пакетов (Wireshark)
+    pub pcap: Option<PathBuf>,
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        Self {
+            name: format!("Peer-{}", std::process::id()),
+            preferred_port: DEFAULT_UDP_PORT,
+            auto_connect: true,
+            discovery_mode: None,
+            psk: std::env::var(PSK_ENV_VAR).ok(),
+            allow_plaintext: std::env::var(ALLOW_PLAINTEXT_ENV_VAR)
+                .is_ok_and(|allow| !matches!(allow.as_str(), "" | "0" | "false")),
+            talkback_device: None,
+            stats: StatsConfig::from_env(),
+            backend: AudioBackend::from_env(),
+            subscribed_tracks: None,
+            profile: std::env::var_os(PROFILE_ENV_VAR).map(|_| DeviceProfile::from_env()),
+            packet_format: PacketFormat::from_env(),
+            qos: !QosConfig::disabled_by_env(),
+            config_path: AppConfig::default_path(),
+            packet_dump: packet_dump::path_from_env(),
+            pcap: pcap::path_from_env(),
+        }
+    }
+}
+
+/// Состояние работающего движка
+#[derive(Debug, Clone)]
+pub struct PeerEngineStatus {
+    /// Имя этого пира
+    pub name: String,
+    /// Порт, на котором принимается аудио
+    pub audio_port: u16,
+    /// Основной цикл работает
+    pub running: bool,
+    /// Захватываемые треки (отправка)
+    pub input_tracks: Vec<u8>,
+    /// Принимаемые треки (получение)
+    pub output_tracks: Vec<u8>,
+    pub peers: Vec<PeerStatus>,
+}
+
+/// Симметричный пир: захват и отправка своих треков, приём и
+/// воспроизведение треков пиров, обнаружение и веб-интерфейс
+///
+/// Движок запускается один раз: [`run`](Self::run) работает до вызова
+/// [`stop`](Self::stop) из другой задачи или обработчика сигнала.
+pub struct PeerEngine {
+    config: AppConfig,
+    peer_config: PeerConfig,
+    config_store: Arc<ConfigStore>,
+    audio_port: u16,
+    track_manager: Arc<TrackManager>,
+    peers: Arc<PeerRegistry>,
+    routing: Arc<RoutingMatrix>,
+    file_transfers: Arc<FileTransfers>,
+    recorder: Arc<Recorder>,
+    sessions: Arc<SessionStore>,
+    input_states: Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    output_states: Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    started: AtomicBool,
+    running: AtomicBool,
+}
+
+impl PeerEngine {
+    /// Подготовить движок: настройки из файла конфигурации и параметров
+    /// пира, свободный порт, менеджер треков, маршрутизация
+    pub fn new(peer_config: PeerConfig) -> Result<Self> {
+        // Файл конфигурации; параметры пира имеют приоритет и в файл не попадают
+        let config_store = Arc::new(ConfigStore::open(peer_config.config_path.clone()));
+        let mut config = config_store.config();
+        if let Some(profile) = peer_config.profile {
+            config.profile = profile;
+        }
+        
+        // Определяем доступный порт
+        let audio_port = find_available_port(peer_config.preferred_port)?;
+        config.network.udp_port = audio_port;
+        if let Some(mode) = peer_config.discovery_mode {
+            config.network.discovery_mode = mode;
+        }
+        if let Some(psk) = &peer_config.psk {
+            config.network.psk = Some(psk.clone());
+        }
+        config.network.allow_plaintext_tracks |= peer_config.allow_plaintext;
+        if let Some(format) = peer_config.packet_format {
+            config.network.packet_format = format;
+        }
+        if !peer_config.qos {
+            config.network.qos.disable();
+        }
+        if packet_log::requested_by_env() {
+            config.network.debug_capture = true;
+            packet_log::set_enabled(true);
+            tracing::info!("Журнал управляющих пакетов: /api/debug/packets");
+        }
+        if let Some(simulation) = NetworkSimulation::from_env() {
+            config.network.simulate = simulation;
+        }
+        if config.network.simulate.is_active() {
+            tracing::warn!("Имитация плохой сети на приёме аудио: {}", config.network.simulate);
+        }
+        if let Some(ref path) = peer_config.packet_dump {
+            config.network.packet_dump = Some(path.clone());
+            tracing::info!("Принятые пакеты записываются в {} (воспроизведение: `replay`)", path.display());
+        }
+        if let Some(ref path) = peer_config.pcap {
+            config.network.pcap = Some(path.clone());
+        }
+        if let Some(ref path) = config.network.pcap {
+            pcap::start(path).map_err(|e| Error::Config(format!("pcap capture {}: {}", path.display(), e)))?;
+            tracing::info!("Захват пакетов в {} для Wireshark", path.display());
+        }
+        if config.network.packet_format == PacketFormat::Rtp {
+            tracing::info!("Аудио передаётся в формате RTP (Opus, RFC 7587)");
+        }
+        config.stats = peer_config.stats.clone();
+        config.apply_profile();
+        if config.profile == DeviceProfile::LowPower {
+            tracing::info!("Профиль слабого устройства: без веб-интерфейса, статистика раз в {} с", config.stats.interval_secs);
+        }
+        if config.network.psk.is_some() {
+            tracing::info!("Шифрование аудио включено (общий ключ)");
+            if config.network.allow_plaintext_tracks {
+                tracing::info!("Принимаются треки без шифрования, помеченные отправителем");
+            }
+        }
+        if let Some(backend) = peer_config.backend {
+            config.audio.backend = backend;
+        }
+        if let Err(e) = device::set_backend(config.audio.backend) {
+            tracing::warn!("Аудио-бэкенд {:?} недоступен: {}", config.audio.backend, e);
+        }
+        wasapi::configure(config.audio.wasapi_exclusive, config.audio.wasapi_low_latency);
+        
+        tracing::info!("Имя пира: {}", peer_config.name);
+        tracing::info!("Аудио порт: {}", audio_port);
+        
+        // Создаём менеджер треков (общий для входящих и выходящих)
+        let track_manager = Arc::new(
+            TrackManager::new()
+                .with_meter_params(config.profile.meter_params())
+                .with_max_tracks(config.profile.max_tracks())
+                .with_solo_mode(config.audio.solo_mode)
+                .with_gap_threshold_ms(config.stats.gap_threshold_ms),
+        );
+        if config.audio.solo_mode == SoloMode::Pfl && config.audio.monitor_device.is_none() {
+            tracing::warn!("Режим соло PFL без audio.monitor_device: треки в соло не будут прослушиваться");
+        }
+        
+        // Файлы, пересылаемые пирам из веб-интерфейса и принимаемые от них
+        let file_transfers = Arc::new(FileTransfers::new(config.received_files_dir()));
+        tracing::info!("Принятые файлы сохраняются в {}", file_transfers.dir().display());
+        
+        // Запись принятого аудио (управляется из веб-интерфейса)
+        let recorder = Arc::new(Recorder::new(config.recordings_dir(), DEFAULT_SAMPLE_RATE));
+        tracing::info!("Записи сохраняются в {}", recorder.dir().display());
+        
+        // История сеансов с пирами (потери, джиттер и сбои каждого сеанса)
+        let sessions_path = config.sessions_path();
+        let sessions = Arc::new(SessionStore::open(&sessions_path));
+        tracing::info!("История сеансов сохраняется в {}", sessions_path.display());
+        
+        // Маршрутизация треков по пирам (сохраняется в файле конфигурации)
+        if !config.routing.routes.is_empty() {
+            tracing::info!("Маршрутизация из файла конфигурации: {} треков", config.routing.routes.len());
+        }
+        let routing = Arc::new(RoutingMatrix::from_config(&config.routing));
+        
+        Ok(Self {
+            config,
+            peer_config,
+            config_store,
+            audio_port,
+            track_manager,
+            // Реестр пиров (общий с веб-интерфейсом для учёта трафика)
+            peers: Arc::new(PeerRegistry::new()),
+            routing,
+            file_transfers,
+            recorder,
+            sessions,
+            input_states: Arc::new(Mutex::new(HashMap::new())),
+            output_states: Arc::new(Mutex::new(HashMap::new())),
+            started: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+        })
+    }
+    
+    /// Итоговые настройки (после профиля и параметров пира)
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+    
+    /// Файл конфигурации, в который сохраняются изменения из веб-интерфейса
+    pub fn config_store(&self) -> &Arc<ConfigStore> {
+        &self.config_store
+    }
+    
+    /// Порт, на котором принимается аудио
+    pub fn audio_port(&self) -> u16 {
+        self.audio_port
+    }
+    
+    pub fn track_manager(&self) -> &Arc<TrackManager> {
+        &self.track_manager
+    }
+    
+    pub fn peers(&self) -> &Arc<PeerRegistry> {
+        &self.peers
+    }
+    
+    pub fn routing(&self) -> &Arc<RoutingMatrix> {
+        &self.routing
+    }
+    
+    /// Остановить основной цикл (`run` завершится после текущей итерации)
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+    
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+    
+    /// Текущее состояние движка
+    pub fn status(&self) -> PeerEngineStatus {
+        let sorted = |mut ids: Vec<u8>| {
+            ids.sort_unstable();
+            ids
+        };
+        PeerEngineStatus {
+            name: self.peer_config.name.clone(),
+            audio_port: self.audio_port,
+            running: self.is_running(),
+            input_tracks: sorted(self.input_states.lock().keys().copied().collect()),
+            output_tracks: sorted(self.output_states.lock().keys().copied().collect()),
+            peers: self.peers.statuses(),
+        }
+    }
+    
+    /// Запустить веб-интерфейс, обнаружение и приёмник и работать до `stop`
+    pub async fn run(&self) -> Result<()> {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return Err(Error::Config("peer engine can only run once".to_string()));
+        }
+        self.running.store(true, Ordering::SeqCst);
+        let config = &self.config;
+        let peer_config = &self.peer_config;
+        let track_manager = &self.track_manager;
+        let peers = &self.peers;
+        let routing = &self.routing;
+        let file_transfers = &self.file_transfers;
+        let recorder = &self.recorder;
+        let sessions = &self.sessions;
+        let input_states = &self.input_states;
+        let output_states = &self.output_states;
+        
+        // Подписываемся на события треков
+        let mut event_rx = track_manager.subscribe();
+        
+        // Обработка выходов из файла конфигурации (применяется обработчиком событий)
+        for (device_id, dsp) in &config.audio.output_dsp {
+            if let Err(e) = track_manager.set_output_dsp(device_id, Some(dsp.clone())) {
+                tracing::warn!("Обработка выхода {} пропущена: {}", device_id, e);
+            }
+        }
+        
+        // Allowlist и PIN сопряжения: общие для рукопожатия и веб-интерфейса
+        let pairing = Arc::new(Pairing::new(&config.network.pairing, Some(self.config_store.clone())));
+        file_transfers.set_security(config.network.cipher(), Some(pairing.clone()));
+        
+        // Запускаем веб-интерфейс
+        let web_handle = config.ui.serves().then(|| {
+            let web_server = WebServer::with_routing(
+                config.ui.clone(),
+                track_manager.clone(),
+                peers.clone(),
+                routing.clone(),
+                true, // is_sender - показываем обе функции
+            )
+            .with_file_transfers(file_transfers.clone())
+            .with_recorder(recorder.clone())
+            .with_peer_control()
+            .with_pairing(pairing.clone())
+            .with_config_store(self.config_store.clone())
+            .with_sessions(sessions.clone());
+            if config.ui.enabled {
+                tracing::info!(
+                    "Web UI доступен: http://{}:{}",
+                    config.ui.bind_address,
+                    config.ui.http_port
+                );
+            }
+            web_server.start_background()
+        });
+        
+        // Пиры, подключённые в веб-интерфейсе в прошлый раз
+        let mut peer_transports = HashMap::new();
+        for saved in &config.peers {
+            let Some(key) = peers.connect(&saved.address) else {
+                tracing::warn!("Некорректный адрес пира в файле конфигурации: {}", saved.address);
+                continue;
+            };
+            if let Some(name) = &saved.name {
+                peers.rename(&key, name);
+            }
+            if let Some(transport) = saved.transport {
+                peer_transports.insert(key.clone(), transport);
+            }
+            tracing::info!("Пир {} из файла конфигурации", key);
+        }
+        
+        // Создаём и запускаем сервис обнаружения
+        let peers_for_discovery = peers.clone();
+        let track_manager_for_discovery = track_manager.clone();
+        let auto_connect = peer_config.auto_connect;
+        
+        let mut discovery = DiscoveryService::new(
+            true, // Оба режима - и отправитель, и получатель
+            self.audio_port,
+            peer_config.name.clone(),
+        );
+        discovery.set_mode(config.network.discovery_mode);
+        
+        // Обрабатываем обнаруженные пиры
+        discovery.on_peer_discovered(move |peer| {
+            handle_peer_discovered(&peers_for_discovery, &track_manager_for_discovery, peer, auto_connect);
+        });
+        
+        if let Err(e) = discovery.start() {
+            tracing::warn!("Не удалось запустить сервис обнаружения: {}", e);
+        } else {
+            tracing::info!("Сервис обнаружения запущен");
+        }
+        
+        // Создаём канал для приёма пакетов
+        let (packet_tx, packet_rx) = bounded::<ReceivedPacket>(4096);
+        
+        // Запускаем сетевой приёмник
+        // Синхронизация часов с пирами (общая для приёмника и отправителей)
+        let time_sync = Arc::new(TimeSync::new());
+        
+        // Отчёты о потерях от пиров (могут прийти на любой из сокетов аудио-порта)
+        let feedback = Arc::new(FeedbackInbox::new());
+        
+        // Подписка на треки пиров и список своих треков для их подписки
+        let subscriber = Arc::new(TrackSubscriber::new());
+        subscriber.set_wanted(peer_config.subscribed_tracks.clone());
+        subscriber.set_capabilities(config.receiver_capabilities());
+        let track_catalog = Arc::new(TrackCatalog::new());
+        
+        // Рукопожатие с пирами: отправитель пиру создаётся только после него
+        let handshake = Arc::new(HandshakeManager::new(
+            peer_config.name.clone(),
+            self.audio_port,
+            config.handshake_capabilities(PeerCapabilities { can_send: true, ..config.receiver_capabilities() }),
+        ).with_pairing(pairing));
+        
+        let mut receiver = AudioReceiver::new();
+        receiver.set_global_channel(packet_tx);
+        receiver.set_time_sync(time_sync.clone());
+        receiver.set_feedback_inbox(feedback.clone());
+        receiver.set_subscriber(subscriber.clone());
+        receiver.set_file_transfers(file_transfers.clone());
+        receiver.set_handshake(handshake.clone());
+        if let Err(e) = receiver.start(config.network.clone()) {
+            discovery.stop();
+            if let Some(handle) = web_handle {
+                handle.abort();
+            }
+            self.running.store(false, Ordering::SeqCst);
+            return Err(e.into());
+        }
+        tracing::info!("Сетевой приёмник запущен на порту {}", config.network.udp_port);
+        
+        if config.stats.latency_probe {
+            tracing::info!("Режим измерения задержки: пробы в отправляемых треках");
+        }
+        // Вход аналоговой петли, слушающий пробы пиров
+        let mut loopback = config.stats.probe_loopback_device.as_deref().and_then(|device_id| {
+            match LoopbackProbe::start(device_id, DEFAULT_SAMPLE_RATE) {
+                Ok(probe) => {
+                    tracing::info!("Приём проб задержки на {}", device_id);
+                    Some(probe)
+                }
+                Err(e) => {
+                    tracing::warn!("Не удалось открыть вход петли {}: {}", device_id, e);
+                    None
+                }
+            }
+        });
+        
+        // Множество удалённых треков (не пересоздавать автоматически)
+        let deleted_output_tracks: Arc<Mutex<HashSet<u8>>> = Arc::new(Mutex::new(HashSet::new()));
+        
+        // Получаем устройство вывода по умолчанию
+        let devices = list_devices();
+        let default_output = virtual_output::default_output_id(&devices);
+        
+        tracing::info!("Устройство вывода по умолчанию: {}", default_output);
+        
+        let outputs = PlaybackOutputs {
+            mixer: OutputMixer::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS),
+            default_device: default_output,
+            monitor_device: config.audio.monitor_device.clone(),
+            recorder: recorder.clone(),
+        };
+        
+        // Клонируем для обработчика событий
+        let input_states_for_events = input_states.clone();
+        let output_states_for_events = output_states.clone();
+        let track_manager_for_events = track_manager.clone();
+        let routing_for_events = routing.clone();
+        let track_catalog_for_events = track_catalog.clone();
+        let mixer_for_events = outputs.mixer.clone();
+        let latency_probe = config.stats.latency_probe;
+        
+        // Обработчик событий треков
+        let event_handle = tokio::spawn(async move {
+            loop {
+                match event_rx.recv().await {
+                    Ok(event) => {
+                        // Мастер-обработка выхода применяется к его общему потоку
+                        if let TrackEvent::OutputDspChanged(ref device_id) = event {
+                            tracing::info!("Обработка выхода {} изменена", device_id);
+                            mixer_for_events.set_dsp(device_id, track_manager_for_events.output_dsp(device_id));
+                        }
+                        // Принятый трек переподключается к устройству вывода
+                        if let TrackEvent::DeviceChanged(track_id, _, ref new_device) = event {
+                            if reattach_output(
+                                track_id,
+                                new_device,
+                                &output_states_for_events,
+                                &mixer_for_events,
+                                &track_manager_for_events,
+                            ) {
+                                continue;
+                            }
+                        }
+                        handle_track_event(
+                            event,
+                            &input_states_for_events,
+                            &output_states_for_events,
+                            &track_manager_for_events,
+                            &routing_for_events,
+                            &track_catalog_for_events,
+                            latency_probe,
+                        );
+                    }
+                    Err(e) => {
+                        tracing::warn!("Ошибка канала событий: {}", e);
+                    }
+                }
+            }
+        });
+        
+        // Треки, созданные или настроенные в веб-интерфейсе в прошлый раз
+        for track_config in &config.tracks {
+            let name = track_config.name.clone();
+            if let Err(e) = track_manager.create_saved_track(track_config.clone(), &devices) {
+                tracing::warn!("Трек {} из файла конфигурации не создан: {}", name, e);
+            }
+        }
+        
+        // Трек внутренней связи: заглушен, пока в UI удерживается кнопка
+        if let Some(device_id) = &peer_config.talkback_device {
+            match track_manager.create_track(TrackConfig::talkback(device_id.clone())) {
+                Ok(track_id) => tracing::info!("Talkback трек {} на устройстве {}", track_id, device_id),
+                Err(e) => tracing::error!("Не удалось создать talkback трек: {}", e),
+            }
+        }
+        
+        // Создаём сетевой отправитель (будет обновляться при обнаружении пиров)
+        let network_senders: Arc<Mutex<HashMap<String, MultiTrackSender>>> = Arc::new(Mutex::new(HashMap::new()));
+        
+        let mut last_stats_time = Instant::now();
+        let mut last_peer_check_time = Instant::now();
+        let mut last_feedback_time = Instant::now();
+        let mut suspend_detector = SuspendDetector::new();
+        
+        // Сокеты отправителей делят порт с приёмником, и ответы на UDP-проверку
+        // могут прийти в приёмник: сами отправители на TCP не переходят
+        // (приёмник принимает TCP от отправителей без общего порта)
+        let mut sender_network = config.network.clone();
+        if sender_network.transport == TransportMode::Auto {
+            sender_network.transport = TransportMode::Udp;
+        }
+        let sender_shared = SenderShared {
+            network: sender_network,
+            time_sync: time_sync.clone(),
+            feedback: feedback.clone(),
+            track_catalog: track_catalog.clone(),
+            file_transfers: file_transfers.clone(),
+            handshake: handshake.clone(),
+            subscriber: subscriber.clone(),
+            peer_transports,
+        };
+        
+        // Сохранение изменений и слежение за правкой файла конфигурации
+        let config_store = &self.config_store;
+        let watcher_handle = config_store.spawn_watcher();
+        let mut reload_rx = config_store.subscribe();
+        
+        tracing::info!("Запуск основного цикла - нажмите Ctrl+C для остановки");
+        
+        // Оценка канала по отчётам пиров, общая для всех входных треков
+        let congestion_config = &config.network.congestion;
+        let mut congestion = congestion_config.enabled.then(|| CongestionController::new(congestion_config.clone()));
+        
+        // Основной цикл (в Windows поток входит в задачу MMCSS)
+        let _mmcss = qos::register_thread(&config.network.qos);
+        while self.running.load(Ordering::Relaxed) {
+            // Выход из сна: перезапускаем потоки и сбрасываем буферы
+            if let Some(gap) = suspend_detector.check() {
+                resync_after_suspend(gap, input_states, output_states, &network_senders, &time_sync);
+            }
+            
+            // Пиры, вышедшие из сна: их буферизованное аудио устарело
+            for ip in time_sync.take_resyncs() {
+                let flushed = flush_output_tracks(output_states, |source| source.ip() == ip);
+                tracing::info!("Пир {} вышел из сна, сброшено треков: {}", ip, flushed);
+            }
+            
+            // Периодическая проверка пиров и создание отправителей
+            if last_peer_check_time.elapsed() >= Duration::from_secs(1) {
+                last_peer_check_time = Instant::now();
+                
+                // Keepalive: пиры, переставшие отвечать (упавший процесс не
+                // присылает Goodbye), теряются, их отправители удаляются ниже
+                for (address, ping) in handshake.due_keepalives() {
+                    if let Err(e) = receiver.send_control(&ping, address) {
+                        tracing::debug!("Keepalive пиру {} не отправлен: {}", address, e);
+                    }
+                }
+                for event in handshake.take_events() {
+                    handle_connection_event(event, peers, track_manager, output_states, &network_senders);
+                }
+                connect_track_destinations(track_manager, input_states, peers);
+                update_peer_connections(peers, &network_senders, &sender_shared, &receiver);
+                
+                // Выходные треки для треков, предложенных пирами в SyncResponse
+                apply_track_changes(&subscriber, track_manager, input_states);
+                create_offered_output_tracks(&subscriber, track_manager, &deleted_output_tracks, &outputs);
+                
+                if routing.take_changed() {
+                    config_store.update(|saved| saved.routing = routing.to_config());
+                }
+                
+                // Файл конфигурации изменён извне
+                while let Ok(reload) = reload_rx.try_recv() {
+                    reload.apply_output_dsp(track_manager);
+                    if reload.changed(|saved| &saved.network.allow_plaintext_tracks) {
+                        let allow = reload.new.network.allow_plaintext_tracks || peer_config.allow_plaintext;
+                        subscriber.set_accept_plaintext(config.network.psk.is_some() && allow);
+                    }
+                    reload.log_restart_needed();
+                }
+                
+                // Возможности получателей для блокировки настроек в UI
+                let capabilities: Vec<PeerCapabilities> = network_senders
+                    .lock()
+                    .values()
+                    .filter_map(|sender| sender.peer_capabilities())
+                    .collect();
+                track_manager.set_remote_capabilities(PeerCapabilities::combine(&capabilities));
+                if update_track_codecs(track_manager, input_states) {
+                    track_catalog.set_tracks(offered_tracks(input_states, track_manager));
+                }
+            }
+            
+            // Обрабатываем входящие треки (отправка)
+            let has_send_work = process_input_tracks(
+                input_states,
+                track_manager,
+                &network_senders,
+                peers,
+                routing,
+                &feedback,
+                &mut congestion,
+            );
+            
+            // Обрабатываем входящие пакеты (получение)
+            let has_recv_work = process_received_packets(
+                &packet_rx,
+                peers,
+                output_states,
+                &deleted_output_tracks,
+                track_manager,
+                &outputs,
+                &time_sync,
+            );
+            let has_playout_work = play_due_frames(output_states, track_manager, &outputs);
+            
+            // Проба, услышанная на входе петли
+            if let Some(detected_us) = loopback.as_mut().and_then(|probe| probe.poll()) {
+                attribute_loopback(output_states, detected_us);
+            }
+            
+            // Адаптивный сон
+            if has_send_work || has_recv_work || has_playout_work {
+                tokio::task::yield_now().await;
+            } else {
+                tokio::time::sleep(Duration::from_micros(250)).await;
+            }
+            
+            // Отчёты о потерях отправителям (адаптивный битрейт на их стороне)
+            if last_feedback_time.elapsed() >= FEEDBACK_INTERVAL {
+                last_feedback_time = Instant::now();
+                send_feedback(output_states, &receiver);
+            }
+            
+            // Периодическая статистика (в тихом режиме - только веб-интерфейс/API)
+            if config.stats.should_log() && last_stats_time.elapsed() >= config.stats.interval() {
+                last_stats_time = Instant::now();
+                print_stats(input_states, output_states, peers, &receiver);
+            }
+        }
+        
+        tracing::info!("Завершение работы...");
+        event_handle.abort();
+        watcher_handle.abort();
+        config_store.flush();
+        if let Some(handle) = web_handle {
+            handle.abort();
+        }
+        // Дописываем заголовки файлов идущей записи
+        recorder.stop();
+        sessions.finish_all();
+        discovery.stop();
+        
+        // Захват останавливается, остаток аудио кодируется и уходит пирам
+        flush_input_tracks(input_states, track_manager, &network_senders, peers, routing, &feedback);
+        drain_senders(&network_senders).await;
+        
+        // Goodbye после последних пакетов: пиры сразу освобождают наши
+        // треки, не дожидаясь таймаутов
+        for (address, _, _) in handshake.connected_peers() {
+            let goodbye = handshake.goodbye(address).serialize();
+            if let Err(e) = receiver.send_control(&goodbye, address) {
+                tracing::debug!("Goodbye пиру {} не отправлен: {}", address, e);
+            }
+        }
+        
+        // Вывод затухает вместо щелчка на обрыве
+        fade_out_outputs(output_states).await;
+        receiver.stop();
+        // Освобождаем устройства захвата и вывода
+        network_senders.lock().clear();
+        input_states.lock().clear();
+        output_states.lock().clear();
+        pcap::stop();
+        
+        Ok(())
+    }
+}
+
+/// Найти доступный порт
+fn find_available_port(preferred: u16) -> Result<u16> {
+    use std::net::UdpSocket;
+    
+    // Сначала пробуем предпочтительный порт
+    if UdpSocket::bind(format!("0.0.0.0:{}", preferred)).is_ok() {
+        return Ok(preferred);
+    }
+    
+    tracing::warn!("Порт {} занят, ищем свободный порт...", preferred);
+    
+    // Ищем свободный порт в диапазоне
+    for port in (preferred + 1)..=(preferred + 100) {
+        if UdpSocket::bind(format!("0.0.0.0:{}", port)).is_ok() {
+            return Ok(port);
+        }
+    }
+    
+    // Последняя попытка - любой свободный порт
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let port = socket.local_addr()?.port();
+    drop(socket);
+    
+    Ok(port)
+}
+
+/// Обработать обнаруженный пир
+fn handle_peer_discovered(
+    peers: &PeerRegistry,
+    track_manager: &TrackManager,
+    peer: DiscoveredPeer,
+    auto_connect: bool,
+) {
+    if peers.upsert(peer.audio_address(), &peer.name, &peer.instance_id, auto_connect) {
+        tracing::info!(
+            "Обнаружен новый пир: {} ({}:{})",
+            peer.name,
+            peer.address.ip(),
+            peer.audio_port
+        );
+        track_manager.timeline().record(
+            ActivityKind::PeerJoined,
+            None,
+            format!("{} ({})", peer.name, peer.audio_address()),
+        );
+    }
+}
+
+/// Обработать изменение соединения с пиром: состояние в реестре (UI
+/// получает его в `Peers`) и событие в хронологии
+///
+/// Треки попрощавшегося или потерянного пира больше не придут, их буферы
+/// сбрасываются; отправитель пиру удаляет `update_peer_connections`.
+fn handle_connection_event(
+    event: ConnectionEvent,
+    peers: &PeerRegistry,
+    track_manager: &TrackManager,
+    output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    network_senders: &Arc<Mutex<HashMap<String, MultiTrackSender>>>,
+) {
+    let (address, connection, kind) = match &event {
+        ConnectionEvent::Connected { peer_addr, .. } => (*peer_addr, PeerConnection::Connected, ActivityKind::PeerConnected),
+        ConnectionEvent::Departed(address) => (*address, PeerConnection::Left, ActivityKind::PeerLeft),
+        ConnectionEvent::Lost(address) => (*address, PeerConnection::Lost, ActivityKind::PeerLost),
+        ConnectionEvent::Interrupted(address) => (*address, PeerConnection::Interrupted, ActivityKind::PeerInterrupted),
+        ConnectionEvent::Resumed(address) => (*address, PeerConnection::Connected, ActivityKind::PeerResumed),
+    };
+    let key = PeerRegistry::key_for(address);
+    match event {
+        ConnectionEvent::Departed(_) | ConnectionEvent::Lost(_) => {
+            let flushed = flush_output_tracks(output_states, |source| source == address);
+            tracing::info!("Соединение с пиром {} закрыто, сброшено треков: {}", address, flushed);
+        }
+        // Пакеты в пропавшую сеть не отправляем
+        ConnectionEvent::Interrupted(_) => {
+            if let Some(sender) = network_senders.lock().get(&key) {
+                sender.set_paused(true);
+            }
+        }
+        // Потоки в обе стороны начинаются заново: свои последовательности
+        // с нуля (пир сбрасывает буферы по Resync), его треки - с чистых
+        // буферов
+        ConnectionEvent::Resumed(_) => {
+            if let Some(sender) = network_senders.lock().get(&key) {
+                sender.set_paused(false);
+                if let Err(e) = sender.resync() {
+                    tracing::warn!("Не удалось уведомить пира {} о пересинхронизации: {}", key, e);
+                }
+            }
+            let flushed = flush_output_tracks(output_states, |source| source == address);
+            tracing::info!("Связь с пиром {} восстановлена, сброшено треков: {}", address, flushed);
+        }
+        ConnectionEvent::Connected { .. } => {}
+    }
+    
+    if peers.set_connection(&key, connection) {
+        let name = peers.get(&key).map(|peer| peer.name.clone()).unwrap_or_default();
+        track_manager.timeline().record(kind, None, format!("{} ({})", name, key));
+    }
+}
+
+/// Общее состояние, подключаемое к каждому отправителю пиру
+struct SenderShared {
+    network: NetworkConfig,
+    time_sync: Arc<TimeSync>,
+    feedback: Arc<FeedbackInbox>,
+    track_catalog: Arc<TrackCatalog>,
+    file_transfers: Arc<FileTransfers>,
+    handshake: Arc<HandshakeManager>,
+    subscriber: Arc<TrackSubscriber>,
+    /// Транспорт пиров, для которых он задан в файле конфигурации
+    peer_transports: HashMap<String, TransportMode>,
+}
+
+impl SenderShared {
+    /// Настройки сети отправителя пиру: транспорт пира, если он задан
+    /// (QUIC включается, только если пир его предложит в рукопожатии)
+    fn network_for(&self, key: &str) -> NetworkConfig {
+        let mut network = self.network.clone();
+        match self.peer_transports.get(key) {
+            // Auto, как и в общих настройках, остаётся UDP
+            Some(TransportMode::Auto) => network.transport = TransportMode::Udp,
+            Some(&transport) => network.transport = transport,
+            None => {}
+        }
+        network
+    }
+}
+
+/// Обновить соединения с пирами
+///
+/// Активному пиру без рукопожатия отправляется Hello (с сокета приёмника,
+/// чтобы ответ пришёл на аудио-порт); отправитель создаётся, когда пир
+/// ответил HelloAck и может принимать аудио.
+fn update_peer_connections(
+    peers: &PeerRegistry,
+    senders: &Arc<Mutex<HashMap<String, MultiTrackSender>>>,
+    shared: &SenderShared,
+    receiver: &AudioReceiver,
+) {
+    let handshake = &shared.handshake;
+    handshake.cleanup_stale(HELLO_TIMEOUT);
+    let mut senders_guard = senders.lock();
+    
+    for (key, address, name) in peers.active_peers() {
+        let Entry::Vacant(entry) = senders_guard.entry(key) else {
+            continue;
+        };
+        let key = entry.key();
+        match handshake.get_state(&address) {
+            None | Some(HandshakeState::Idle) => {
+                // Потерянному пиру Hello повторяется с растущим интервалом
+                if !handshake.hello_due(&address) {
+                    continue;
+                }
+                let hello = handshake.initiate(address).serialize();
+                if let Err(e) = receiver.send_control(&hello, address) {
+                    tracing::debug!("Hello пиру {} не отправлен: {}", key, e);
+                }
+                continue;
+            }
+            Some(HandshakeState::Connected { peer_name, peer_caps, .. }) => {
+                if !peer_caps.can_receive {
+                    continue;
+                }
+                // Пир, добавленный по адресу, получает своё имя
+                if name == *key {
+                    peers.rename(key, &peer_name);
+                }
+            }
+            Some(_) => continue,
+        }
+        
+        // Создаём новый отправитель для этого пира
+        let network = shared.network_for(key);
+        match MultiTrackSender::new(&network, address) {
+            Ok(mut sender) => {
+                sender.set_time_sync(shared.time_sync.clone());
+                sender.set_feedback_inbox(shared.feedback.clone());
+                sender.set_track_catalog(shared.track_catalog.clone());
+                sender.set_file_transfers(shared.file_transfers.clone());
+                sender.set_handshake(handshake.clone());
+                sender.set_subscriber(shared.subscriber.clone());
+                if let Err(e) = sender.start(network) {
+                    tracing::error!("Не удалось запустить отправитель для {}: {}", key, e);
+                } else {
+                    tracing::info!("Создан отправитель для пира {}: {}", name, key);
+                    entry.insert(sender);
+                }
+            }
+            Err(e) => {
+                tracing::error!("Не удалось создать отправитель для {}: {}", key, e);
+            }
+        }
+    }
+    
+    // Резервные пути (тот же пир в другой сети) могут появиться позже
+    for (key, sender) in senders_guard.iter() {
+        sender.set_redundant_paths(peers.paths(key));
+    }
+    
+    // Удаляем отправители для неактивных и попрощавшихся пиров
+    let inactive_keys: Vec<String> = senders_guard
+        .keys()
+        .filter(|k| !peers.get(k).is_some_and(|p| p.is_active() && handshake.is_connected(&p.address)))
+        .cloned()
+        .collect();
+    
+    for key in inactive_keys {
+        senders_guard.remove(&key);
+        tracing::info!("Удалён отправитель для пира: {}", key);
+    }
+}
+
+/// Добавить пиров, которым входные треки отправляются напрямую
+/// (`TrackConfig::destination`), если их ещё нет в списке
+fn connect_track_destinations(
+    track_manager: &TrackManager,
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    peers: &PeerRegistry,
+) {
+    let track_ids: Vec<u8> = input_states.lock().keys().copied().collect();
+    for track_id in track_ids {
+        let Some(address) = track_manager.get_track(track_id).and_then(|t| t.config.destination_addr()) else {
+            continue;
+        };
+        let key = PeerRegistry::key_for(address);
+        let known = peers.get(&key).is_some();
+        if !known && peers.connect(&key).is_some() {
+            tracing::info!("Пир {} добавлен как назначение трека {}", key, track_id);
+        }
+    }
+}
+
+/// Применить изменения треков пиров к выходным трекам: имя, битрейт,
+/// число каналов и длительность кадров отправителя; трек, удалённый отправителем, удаляется
+/// (новые треки создаёт `create_offered_output_tracks`)
+fn apply_track_changes(
+    subscriber: &TrackSubscriber,
+    track_manager: &TrackManager,
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+) {
+    for (source, change) in subscriber.take_changes() {
+        match change {
+            TrackChange::Updated(track) => {
+                if input_states.lock().contains_key(&track.track_id) {
+                    continue;
+                }
+                let frame_size_ms = track.frame_duration_us as f32 / 1000.0;
+                if let Ok(true) = track_manager.apply_sender_config(track.track_id, &track.name, track.bitrate, track.channels, frame_size_ms) {
+                    tracing::info!("Выходной трек {} обновлён по {}: {}", track.track_id, source, track.name);
+                }
+            }
+            TrackChange::Removed(track_id) => {
+                if input_states.lock().contains_key(&track_id) || track_manager.get_track(track_id).is_none() {
+                    continue;
+                }
+                match track_manager.remove_track(track_id) {
+                    Ok(_) => tracing::info!("Выходной трек {} удалён: {} его больше не отправляет", track_id, source),
+                    Err(e) => tracing::warn!("Выходной трек {} не удалён: {}", track_id, e),
+                }
+            }
+        }
+    }
+}
+
+/// Создать выходные треки для треков, которые пиры предложили в
+/// SyncResponse, с их именами и числом каналов (до прихода аудио)
+fn create_offered_output_tracks(
+    subscriber: &TrackSubscriber,
+    track_manager: &TrackManager,
+    deleted_tracks: &Mutex<HashSet<u8>>,
+    outputs: &PlaybackOutputs,
+) {
+    for (source, track) in subscriber.subscribed() {
+        if track_manager.get_track(track.track_id).is_some() || deleted_tracks.lock().contains(&track.track_id) {
+            continue;
+        }
+        let name = track.name.clone();
+        match track_manager.create_track(track.output_config(outputs.default_device.clone())) {
+            Ok(track_id) => tracing::info!("Создан выходной трек {} ({}) для {}", track_id, name, source),
+            Err(e) => tracing::warn!("Выходной трек {} для {} не создан: {}", name, source, e),
+        }
+    }
+}
+
+/// Подключить принятый трек к новому устройству вывода (или к тому же
+/// устройству с новым размером буфера). Возвращает false, если трек не
+/// принимается
+fn reattach_output(
+    track_id: u8,
+    device_id: &str,
+    output_states: &Mutex<HashMap<u8, OutputTrackState>>,
+    mixer: &Arc<OutputMixer>,
+    track_manager: &TrackManager,
+) -> bool {
+    let mut states = output_states.lock();
+    let Some(state) = states.get_mut(&track_id) else {
+        return false;
+    };
+    
+    // Отключение закрывает старый поток, если он больше не нужен
+    state.playback = None;
+    let buffer_frames = track_manager.get_track(track_id).and_then(|t| t.config.buffer_frames);
+    match mixer.attach_channels(track_id, device_id, buffer_frames, state.channels) {
+        Ok(channel) => {
+            tracing::info!("Трек {}: вывод на {}", track_id, device_id);
+            if let Some(track) = track_manager.get_track(track_id) {
+                channel.set_channel_map(track.config.channel_map.clone());
+                channel.set_copies(&track.config.output_devices, track.config.buffer_frames);
+            }
+            state.playback = Some(channel);
+            state.device_id = device_id.to_string();
+        }
+        Err(e) => tracing::error!(
+            "Не удалось запустить воспроизведение для трека {} на {}: {}",
+            track_id,
+            device_id,
+            e
+        ),
+    }
+    true
+}
+
+/// Обработать событие трека
+fn handle_track_event(
+    event: TrackEvent,
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    track_manager: &Arc<TrackManager>,
+    routing: &RoutingMatrix,
+    track_catalog: &TrackCatalog,
+    latency_probe: bool,
+) {
+    let offered_changed = matches!(
+        event,
+        TrackEvent::Created(_) | TrackEvent::Removed(_) | TrackEvent::ConfigUpdated(_)
+    );
+    
+    match event {
+        TrackEvent::Created(track_id) => {
+            tracing::info!("Трек {} создан, инициализация захвата...", track_id);
+            
+            if let Some(track) = track_manager.get_track(track_id) {
+                let device_id = track.device_id.clone();
+                let opus_config = encoder_config(&track.config, &track_manager.remote_capabilities());
+                let channel_map = track.config.channel_map.clone();
+                drop(track);
+                
+                if let Err(e) = create_capture_for_track(
+                    track_id,
+                    &device_id,
+                    opus_config,
+                    channel_map,
+                    latency_probe,
+                    input_states,
+                    track_manager,
+                ) {
+                    tracing::error!("Не удалось создать захват для трека {}: {}", track_id, e);
+                }
+            }
+        }
+        
+        TrackEvent::Removed(track_id) => {
+            tracing::info!("Трек {} удалён, остановка захвата...", track_id);
+            routing.remove_track(track_id);
+            let mut states = input_states.lock();
+            if let Some(mut state) = states.remove(&track_id) {
+                state.capture.stop();
+                tracing::info!("Захват остановлен для трека {}", track_id);
+            }
+        }
+        
+        TrackEvent::DeviceChanged(track_id, old_device, new_device) => {
+            tracing::info!(
+                "Трек {}: устройство изменено {} -> {}",
+                track_id,
+                old_device,
+                new_device
+            );
+            
+            // Останавливаем старый захват
+            {
+                let mut states = input_states.lock();
+                if let Some(mut state) = states.remove(&track_id) {
+                    state.capture.stop();
+                }
+            }
+            
+            // Создаём новый захват
+            let (opus_config, channel_map) = track_manager
+                .get_track(track_id)
+                .map(|t| (encoder_config(&t.config, &track_manager.remote_capabilities()), t.config.channel_map.clone()))
+                .unwrap_or_else(|| (OpusConfig::music(), Vec::new()));
+            if let Err(e) = create_capture_for_track(
+                track_id,
+                &new_device,
+                opus_config,
+                channel_map,
+                latency_probe,
+                input_states,
+                track_manager,
+            ) {
+                tracing::error!(
+                    "Не удалось создать захват для трека {} на устройстве {}: {}",
+                    track_id,
+                    new_device,
+                    e
+                );
+            }
+        }
+        
+        TrackEvent::ConfigUpdated(track_id) => {
+            // Кодек, битрейт, размер кадра, включение/выключение FEC, настройки
+            // Opus и карта каналов на работающем захвате без его пересоздания;
+            // карта каналов, дополнительные устройства и задержка джиттер-буфера
+            // на выводе
+            let config = track_manager.get_track(track_id).map(|t| t.config.clone());
+            if let Some(config) = config {
+                if let Some(state) = input_states.lock().get_mut(&track_id) {
+                    let receivers = track_manager.remote_capabilities();
+                    update_encoder_codec(track_id, state, &config, &receivers);
+                    update_encoder_bitrate(track_id, state, &config, &receivers, track_manager);
+                    update_encoder_frame_size(track_id, state.encoder.as_mut(), config.frame_size_ms);
+                    update_encoder_fec(track_id, state.encoder.as_mut(), config.fec_enabled);
+                    update_encoder_dred(track_id, state.encoder.as_mut(), config.dred);
+                    update_encoder_settings(track_id, state.encoder.as_mut(), &config);
+                    let channels = state.encoder.channels() as usize;
+                    SilenceGate::reconfigure(&mut state.silence_gate, &config);
+                    VoiceProcessor::reconfigure(&mut state.voice, &config, DEFAULT_SAMPLE_RATE, channels);
+                    Agc::reconfigure(&mut state.agc, &config, channels);
+                    state.capture.set_channel_map(config.channel_map.clone());
+                }
+                if let Some(state) = output_states.lock().get_mut(&track_id) {
+                    let delay = JitterDelay::from_config(&config);
+                    if state.jitter_buffer.delay() != delay {
+                        state.jitter_buffer.set_delay(delay);
+                        tracing::info!(
+                            "Трек {}: задержка джиттер-буфера {} мс",
+                            track_id,
+                            state.jitter_buffer.delay_us() / 1000
+                        );
+                    }
+                    if let Some(playback) = state.playback.as_ref() {
+                        playback.set_copies(&config.output_devices, config.buffer_frames);
+                        playback.set_channel_map(config.channel_map);
+                    }
+                }
+            }
+        }
+        
+        _ => {}
+    }
+    
+    if offered_changed {
+        track_catalog.set_tracks(offered_tracks(input_states, track_manager));
+    }
+}
+
+/// Треки, которые пиры могут выбрать в подписке (захватываемые этим
+/// пиром), с числом каналов и длительностью кадров, с которыми они
+/// отправляются
+fn offered_tracks(
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    track_manager: &TrackManager,
+) -> Vec<TrackInfo> {
+    let mut encoded: Vec<(u8, u16, u32)> = input_states
+        .lock()
+        .iter()
+        .map(|(&id, state)| (id, state.encoder.channels(), (state.encoder.frame_duration_ms() * 1000.0) as u32))
+        .collect();
+    encoded.sort_unstable();
+    encoded
+        .into_iter()
+        .filter_map(|(id, channels, frame_duration_us)| {
+            track_manager
+                .get_track(id)
+                .map(|track| TrackInfo { channels, frame_duration_us, ..TrackInfo::from_config(id, &track.config) })
+        })
+        .collect()
+}
+
+/// Флаги полезной нагрузки кадров энкодера трека
+fn frame_flags(encoder: &dyn AudioEncoder) -> PacketFlags {
+    PacketFlags::new()
+        .set_stereo(encoder.channels() >= 2)
+        .set_fec(encoder.fec_enabled())
+        .set_codec(encoder.codec())
+}
+
+/// Переключить работающий трек на кодек и число каналов, о которых
+/// договорились его настройки и получатели; получатели начинают поток
+/// заново со следующего пакета. Возвращает true, если сменилось число
+/// каналов (его сообщает список треков).
+fn update_encoder_codec(
+    track_id: u8,
+    state: &mut InputTrackState,
+    config: &TrackConfig,
+    receivers: &RemoteCapabilities,
+) -> bool {
+    let codec = select_codec(config.codec, receivers);
+    let opus_config = encoder_config(config, receivers);
+    let channels = opus_config.channels;
+    if state.encoder.codec() == codec && state.encoder.channels() == channels {
+        return false;
+    }
+    if codec != config.codec {
+        tracing::warn!("Трек {}: получатель не декодирует {:?}, отправляем {:?}", track_id, config.codec, codec);
+    }
+    if channels != config.channels.max(DEFAULT_CHANNELS) {
+        tracing::warn!("Трек {}: получатель не воспроизводит {} каналов, отправляем стерео", track_id, config.channels);
+    }
+    
+    match new_encoder(codec, opus_config) {
+        Ok(encoder) => {
+            let channels_changed = encoder.channels() != state.encoder.channels();
+            state.encoder = encoder;
+            state.sample_buffer.clear();
+            state.restart_pending = true;
+            if channels_changed {
+                state.voice = VoiceProcessor::for_track(config, DEFAULT_SAMPLE_RATE, channels as usize);
+                state.agc = Agc::for_track(config, channels as usize);
+            }
+            tracing::info!("Трек {}: кодек {:?}, {} каналов", track_id, codec, channels);
+            channels_changed
+        }
+        Err(e) => {
+            tracing::warn!("Не удалось переключить трек {} на {:?}: {}", track_id, codec, e);
+            false
+        }
+    }
+}
+
+/// Заново выбрать кодек работающих треков после смены получателей;
+/// true, если у какого-то трека сменилось число каналов
+fn update_track_codecs(track_manager: &TrackManager, input_states: &Mutex<HashMap<u8, InputTrackState>>) -> bool {
+    let receivers = track_manager.remote_capabilities();
+    let mut channels_changed = false;
+    for (&track_id, state) in input_states.lock().iter_mut() {
+        if let Some(track) = track_manager.get_track(track_id) {
+            channels_changed |= update_encoder_codec(track_id, state, &track.config, &receivers);
+        }
+    }
+    channels_changed
+}
+
+/// Перенести битрейт из настроек трека на работающий энкодер: он
+/// становится потолком адаптации, к которому она возвращается (только Opus)
+fn update_encoder_bitrate(
+    track_id: u8,
+    state: &mut InputTrackState,
+    config: &TrackConfig,
+    receivers: &RemoteCapabilities,
+    track_manager: &Arc<TrackManager>,
+) {
+    if state.encoder.opus_mut().is_none() {
+        return;
+    }
+    let bitrate = encoder_config(config, receivers).bitrate;
+    if state.adaptive.max_bitrate() == bitrate {
+        return;
+    }
+    
+    tracing::info!("Трек {}: битрейт {} бит/с", track_id, bitrate);
+    if let Some(decision) = state.adaptive.set_max_bitrate(bitrate) {
+        set_encoder_bitrate(track_id, state, decision, track_manager);
+    }
+}
+
+/// Сменить размер кадра работающего энкодера; захват и накопленные
+/// семплы остаются, следующий кадр уже нового размера
+fn update_encoder_frame_size(track_id: u8, encoder: &mut dyn AudioEncoder, frame_size_ms: f32) {
+    let frame_size = OpusConfig::frame_size_from_ms(DEFAULT_SAMPLE_RATE, frame_size_ms);
+    if encoder.samples_per_frame() == frame_size * encoder.channels() as usize {
+        return;
+    }
+    
+    match encoder.set_frame_size(frame_size) {
+        Ok(()) => tracing::info!("Трек {}: кадры по {} мс", track_id, frame_size_ms),
+        Err(e) => tracing::warn!("Не удалось сменить размер кадра трека {}: {}", track_id, e),
+    }
+}
+
+/// Включить или выключить встроенный FEC работающего энкодера (только Opus)
+fn update_encoder_fec(track_id: u8, encoder: &mut dyn AudioEncoder, fec_enabled: bool) {
+    let Some(encoder) = encoder.opus_mut() else {
+        return;
+    };
+    if encoder.config().fec == fec_enabled {
+        return;
+    }
+    
+    let packet_loss_perc = encoder.config().packet_loss_perc.max(DEFAULT_FEC_PACKET_LOSS_PERC);
+    match encoder.set_fec(fec_enabled, packet_loss_perc) {
+        Ok(()) => tracing::info!("Трек {}: FEC {}", track_id, if fec_enabled { "включён" } else { "выключен" }),
+        Err(e) => tracing::warn!("Не удалось изменить FEC трека {}: {}", track_id, e),
+    }
+}
+
+/// Включить или выключить глубокую избыточность (DRED) работающего энкодера (только Opus)
+fn update_encoder_dred(track_id: u8, encoder: &mut dyn AudioEncoder, dred_enabled: bool) {
+    let Some(encoder) = encoder.opus_mut() else {
+        return;
+    };
+    let duration_ms = dred::duration_ms(dred_enabled);
+    if encoder.config().dred_duration_ms == duration_ms {
+        return;
+    }
+    
+    match encoder.set_dred_duration(duration_ms) {
+        Ok(()) => tracing::info!("Трек {}: DRED {}", track_id, if duration_ms > 0 { "включён" } else { "выключен" }),
+        Err(e) => tracing::warn!("Не удалось изменить DRED трека {}: {}", track_id, e),
+    }
+}
+
+/// Перенастроить сложность, режим битрейта, тип сигнала и полосу
+/// работающего энкодера по настройкам трека (только Opus)
+fn update_encoder_settings(track_id: u8, encoder: &mut dyn AudioEncoder, config: &TrackConfig) {
+    let Some(encoder) = encoder.opus_mut() else {
+        return;
+    };
+    let target = encoder_config(config, &RemoteCapabilities::default());
+    let current = encoder.config();
+    if (current.complexity, current.bitrate_mode(), current.signal, current.max_bandwidth)
+        == (target.complexity, target.bitrate_mode(), target.signal, target.max_bandwidth)
+    {
+        return;
+    }
+    
+    match encoder.set_settings(&target) {
+        Ok(()) => tracing::info!(
+            "Трек {}: сложность {}, {:?}, сигнал {:?}, полоса до {:?}",
+            track_id,
+            target.complexity,
+            target.bitrate_mode(),
+            target.signal,
+            target.max_bandwidth
+        ),
+        Err(e) => tracing::warn!("Не удалось перенастроить кодер трека {}: {}", track_id, e),
+    }
+}
+
+/// Настройки кодера для трека: голосовые для talkback, музыкальные для
+/// остальных, с битрейтом и размером кадра трека; surround, только если
+/// его воспроизводят все получатели
+fn encoder_config(config: &TrackConfig, receivers: &RemoteCapabilities) -> OpusConfig {
+    let base = if config.talkback {
+        OpusConfig::talkback()
+    } else {
+        OpusConfig::music()
+    };
+    // Битрейт трека на каждую пару каналов
+    let channels = select_channels(config.channels, receivers);
+    OpusConfig {
+        channels,
+        bitrate: config.bitrate * channels.div_ceil(DEFAULT_CHANNELS) as u32,
+        frame_size: OpusConfig::frame_size_from_ms(DEFAULT_SAMPLE_RATE, config.frame_size_ms),
+        ..base.with_fec(config.fec_enabled).with_dred(config.dred).with_track_settings(config)
+    }
+}
+
+/// Каналы захвата трека: все каналы surround-трека (при отправке в стерео
+/// они сводятся), стерео для остальных
+fn capture_channels(config: &TrackConfig) -> u16 {
+    select_channels(config.channels, &RemoteCapabilities::default())
+}
+
+/// Создать захват для трека
+fn create_capture_for_track(
+    track_id: u8,
+    device_id: &str,
+    opus_config: OpusConfig,
+    channel_map: Vec<usize>,
+    latency_probe: bool,
+    track_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    track_manager: &TrackManager,
+) -> Result<()> {
+    let capture_buffer = create_shared_buffer(RING_BUFFER_CAPACITY);
+    
+    // Устройство открывается со своим числом каналов и буфером трека,
+    // кадры сводятся к каналам трека
+    let buffer_frames = track_manager.get_track(track_id).and_then(|track| track.config.buffer_frames);
+    let mut capture = AudioCapture::new(
+        track_id,
+        device_id,
+        Some(DEFAULT_SAMPLE_RATE),
+        None,
+        buffer_frames,
+        capture_buffer.clone(),
+    )?;
+    capture.set_output_channels(track_manager.get_track(track_id).map_or(DEFAULT_CHANNELS, |track| capture_channels(&track.config)));
+    capture.set_channel_map(channel_map);
+    
+    capture.start()?;
+    tracing::info!("Захват аудио запущен для трека {} на устройстве {}", track_id, device_id);
+    // Файловый источник управляется из UI
+    track_manager.set_file_player(track_id, capture.file_player());
+    
+    let configured = track_manager.get_track(track_id).map_or(Codec::Opus, |track| track.config.codec);
+    let codec = select_codec(configured, &track_manager.remote_capabilities());
+    let adaptive = AdaptiveBitrate::new(opus_config.bitrate, opus_config.packet_loss_perc);
+    let channels = opus_config.channels;
+    let encoder = new_encoder(codec, opus_config)?;
+    let frame_size = encoder.samples_per_frame();
+    
+    tracing::info!(
+        "{:?} кодер инициализирован для трека {}: {}Hz, {} каналов, {} семплов/кадр ({:.1}ms)",
+        codec,
+        track_id,
+        DEFAULT_SAMPLE_RATE,
+        channels,
+        frame_size,
+        encoder.frame_duration_ms()
+    );
+    
+    let state = InputTrackState {
+        capture,
+        capture_buffer,
+        encoder,
+        adaptive,
+        congestion_paused: false,
+        sample_buffer: Vec::with_capacity(frame_size * 2),
+        sequence: 0,
+        restart_pending: true,
+        gain: 1.0,
+        probe: latency_probe.then(|| ProbeInjector::new(DEFAULT_SAMPLE_RATE)),
+        silence_gate: track_manager.get_track(track_id).and_then(|track| SilenceGate::for_track(&track.config)),
+        voice: track_manager
+            .get_track(track_id)
+            .and_then(|track| VoiceProcessor::for_track(&track.config, DEFAULT_SAMPLE_RATE, channels as usize)),
+        agc: track_manager
+            .get_track(track_id)
+            .and_then(|track| Agc::for_track(&track.config, channels as usize)),
+    };
+    
+    let mut states = track_states.lock();
+    states.insert(track_id, state);
+    
+    Ok(())
+}
+
+/// Обработать входящие треки (отправка)
+fn process_input_tracks(
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    track_manager: &Arc<TrackManager>,
+    network_senders: &Arc<Mutex<HashMap<String, MultiTrackSender>>>,
+    peers: &PeerRegistry,
+    routing: &RoutingMatrix,
+    feedback: &FeedbackInbox,
+    congestion: &mut Option<CongestionController>,
+) -> bool {
+    let mut states = input_states.lock();
+    let mut work_done = false;
+    
+    // Подстраиваем битрейт под худший отчёт среди пиров, а при перегрузке
+    // канала делим его оценку между треками
+    let reports: Vec<TrackFeedback> = states.keys().filter_map(|track_id| feedback.take(*track_id)).collect();
+    for report in &reports {
+        if let Some(state) = states.get_mut(&report.track_id) {
+            apply_feedback(report.track_id, state, report, track_manager);
+        }
+    }
+    if let Some(congestion) = congestion.as_mut().filter(|_| !reports.is_empty()) {
+        apply_congestion(congestion, &reports, &mut states, track_manager);
+    }
+    
+    for (track_id, state) in states.iter_mut() {
+        let frame_size = state.encoder.samples_per_frame();
+        
+        // Кнопка talkback и приглушение остальных треков
+        let send_gain = track_manager.send_gain(*track_id);
+        let redundant = track_manager.get_track(*track_id).is_some_and(|t| t.config.redundant);
+        let priority = track_manager
+            .get_track(*track_id)
+            .map_or(TrackPriority::Normal, |t| t.config.send_priority());
+        // Трек со своим назначением идёт только этому пиру, минуя маршрутизацию
+        let destination = track_manager
+            .get_track(*track_id)
+            .and_then(|t| t.config.destination_addr())
+            .map(PeerRegistry::key_for);
+        let is_routed = |key: &str| match destination {
+            Some(ref destination) => destination == key,
+            None => routing.is_routed(*track_id, key),
+        };
+        // Трек не нужен ни одному подключённому пиру
+        let unsubscribed = {
+            let senders = network_senders.lock();
+            !senders.is_empty() && !senders.values().any(|sender| sender.is_subscribed(*track_id))
+        };
+        let congestion_paused = state.congestion_paused;
+        
+        // Извлекаем все доступные захваченные данные
+        while let Some(frame) = state.capture_buffer.try_pop() {
+            work_done = true;
+            // Surround-трек для получателей без surround сводится в стерео
+            let channels = state.encoder.channels();
+            if frame.channels == channels {
+                state.sample_buffer.extend_from_slice(&frame.samples);
+            } else {
+                state.sample_buffer.extend(convert_channels(&frame.samples, frame.channels as usize, channels as usize, &[]));
+            }
+            
+            // Обновляем уровень аудио для трека
+            if let Some(track) = track_manager.get_track(*track_id) {
+                track.update_level_atomic(&frame.samples);
+                track.update_loudness(&frame.samples, frame.channels as usize);
+            }
+            track_manager.check_clipping(*track_id, &frame.samples);
+            frame.recycle();
+            
+            // Отпущенный talkback, трек без подписчиков или приостановленный
+            // перегрузкой: аудио отбрасывается, следующая отправка начинает
+            // у получателя новый поток
+            let Some(target_gain) = send_gain.filter(|_| !unsubscribed && !congestion_paused) else {
+                state.sample_buffer.clear();
+                state.restart_pending = true;
+                continue;
+            };
+            
+            // Обрабатываем полные кадры
+            while state.sample_buffer.len() >= frame_size {
+                let mut samples = pool::samples().take_copy(&state.sample_buffer[..frame_size]);
+                state.sample_buffer.drain(..frame_size);
+                let capture_stage = profiling::stage(*track_id, Stage::Capture);
+                
+                // Фильтр высоких частот и шумоподавление голосового трека
+                if let Some(voice) = state.voice.as_mut() {
+                    voice.process(&mut samples);
+                }
+                
+                // АРУ: выравнивание громкости входа, усиление показывается в UI
+                if let Some(agc) = state.agc.as_mut() {
+                    agc.process(&mut samples, state.encoder.frame_duration_ms());
+                }
+                if let Some(track) = track_manager.get_track(*track_id) {
+                    track.update_agc_gain(state.agc.as_ref().map(Agc::gain_db));
+                    let timing = state.capture.timing();
+                    track.update_device_timing(timing.period_ms(), timing.latency_ms());
+                }
+                
+                // Плавный переход к усилению приглушения без щелчков
+                if state.gain != 1.0 || target_gain != 1.0 {
+                    simd::apply_gain_ramp(&mut samples, state.encoder.channels() as usize, state.gain, target_gain);
+                    state.gain = target_gain;
+                }
+                
+                // Режим измерения: пакет кадра, с которого начинается проба, помечается
+                let probe = state
+                    .probe
+                    .as_mut()
+                    .is_some_and(|probe| probe.inject(&mut samples, state.encoder.channels() as usize));
+                drop(capture_stage);
+                
+                // Подавление тишины: вместо тихих кадров изредка уходит маркер
+                // тишины, по которому получатель доигрывает буфер без потерь
+                if let Some(gate) = state.silence_gate.as_mut() {
+                    let action = gate.process(&samples, state.encoder.frame_duration_ms());
+                    if let Some(track) = track_manager.get_track(*track_id) {
+                        track.set_silent(gate.is_silent());
+                    }
+                    match action {
+                        GateAction::Send => {}
+                        GateAction::Suppress => {
+                            pool::samples().recycle(samples);
+                            continue;
+                        }
+                        GateAction::Marker => {
+                            // После перерыва у получателя нечего доигрывать
+                            if !state.restart_pending {
+                                let timestamp = media_time_us();
+                                let senders = network_senders.lock();
+                                for (key, sender) in senders.iter() {
+                                    if sender.is_paused() || !is_routed(key) || !sender.is_subscribed(*track_id) {
+                                        continue;
+                                    }
+                                    let flags = frame_flags(state.encoder.as_ref());
+                                    if sender.send_silence(*track_id, timestamp, flags, priority).is_ok() {
+                                        peers.record_sent(key, HEADER_SIZE);
+                                    }
+                                }
+                                state.sequence = state.sequence.wrapping_add(1);
+                            }
+                            pool::samples().recycle(samples);
+                            continue;
+                        }
+                    }
+                }
+                
+                let encoded = {
+                    let _stage = profiling::stage(*track_id, Stage::Encode);
+                    state.encoder.encode(&samples)
+                };
+                pool::samples().recycle(samples);
+                match encoded {
+                    Ok(encoded) => {
+                        let timestamp = media_time_us();
+                        
+                        // Отправляем подключённым пирам согласно маршрутизации
+                        let send_stage = profiling::stage(*track_id, Stage::Send);
+                        let senders = network_senders.lock();
+                        for (key, sender) in senders.iter() {
+                            // Пропущенные кадры: при возврате маршрута, подписки
+                            // или связи поток начнётся заново
+                            if sender.is_paused() || !is_routed(key) || !sender.is_subscribed(*track_id) {
+                                sender.mark_restart(*track_id);
+                                continue;
+                            }
+                            if state.restart_pending {
+                                sender.mark_restart(*track_id);
+                            }
+                            if probe {
+                                sender.mark_probe(*track_id);
+                            }
+                            let wire_size = HEADER_SIZE + encoded.len();
+                            match sender.send_audio(
+                                *track_id,
+                                encoded.clone(),
+                                timestamp,
+                                frame_flags(state.encoder.as_ref()),
+                                priority,
+                                redundant,
+                            ) {
+                                Ok(_) => peers.record_sent(key, wire_size),
+                                Err(e) if state.sequence % 1000 == 0 => {
+                                    tracing::warn!(
+                                        "Не удалось отправить пакет для трека {}: {}",
+                                        track_id,
+                                        e
+                                    );
+                                }
+                                Err(_) => {}
+                            }
+                        }
+                        drop(send_stage);
+                        
+                        // Обновляем счётчик пакетов
+                        if let Some(track) = track_manager.get_track(*track_id) {
+                            track.increment_packets();
+                            let encode_time_us = (state.encoder.frame_duration_ms() * 1000.0) as u32;
+                            track.update_latency(encode_time_us);
+                        }
+                        
+                        state.sequence = state.sequence.wrapping_add(1);
+                        state.restart_pending = false;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Ошибка кодирования для трека {}: {}", track_id, e);
+                    }
+                }
+            }
+        }
+    }
+    
+    work_done
+}
+
+/// Обработать полученные пакеты (получение)
+fn process_received_packets(
+    packet_rx: &crossbeam_channel::Receiver<ReceivedPacket>,
+    peers: &PeerRegistry,
+    output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    deleted_tracks: &Arc<Mutex<HashSet<u8>>>,
+    track_manager: &Arc<TrackManager>,
+    outputs: &PlaybackOutputs,
+    time_sync: &TimeSync,
+) -> bool {
+    let mut processed_count = 0;
+    const MAX_BATCH_SIZE: usize = 64;
+    
+    while processed_count < MAX_BATCH_SIZE {
+        match packet_rx.try_recv() {
+            Ok(packet) => {
+                processed_count += 1;
+                let track_id = packet.track_id;
+                
+                // Учитываем входящий трафик пира
+                if let Some(source) = packet.source {
+                    peers.record_received(source, HEADER_SIZE + packet.payload.len());
+                }
+                
+                // Пропускаем пакеты для удалённых треков
+                if deleted_tracks.lock().contains(&track_id) {
+                    track_manager.record_drops(track_id, DropReason::Deleted, 1);
+                    continue;
+                }
+                
+                let mut states = output_states.lock();
+                
+                // Инициализируем состояние если трек новый
+                if let Entry::Vacant(entry) = states.entry(track_id) {
+                    tracing::info!("Обнаружен новый входящий трек {}, инициализация...", track_id);
+                    
+                    let track_channels = track_manager.get_track(track_id).map_or(0, |track| track.config.channels);
+                    let channels = decoder_channels(packet.is_stereo, track_channels);
+                    let output_device = if let Some(track) = track_manager.get_track(track_id) {
+                        if !track.device_id.is_empty() {
+                            track.device_id.clone()
+                        } else {
+                            outputs.default_device.clone()
+                        }
+                    } else {
+                        outputs.default_device.clone()
+                    };
+                    
+                    // Создаём декодер на кадры, объявленные отправителем
+                    let frame_size_ms = track_manager.get_track(track_id).map_or(DEFAULT_FRAME_SIZE_MS, |track| track.config.frame_size_ms);
+                    let frame_size = OpusConfig::frame_size_from_ms(DEFAULT_SAMPLE_RATE, frame_size_ms);
+                    let decoder = match new_decoder(packet.codec, DEFAULT_SAMPLE_RATE, channels, frame_size) {
+                        Ok(d) => d,
+                        Err(e) => {
+                            tracing::error!(
+                                "Не удалось создать декодер для трека {}: {}",
+                                track_id,
+                                e
+                            );
+                            track_manager.record_drops(track_id, DropReason::DecodeError, 1);
+                            continue;
+                        }
+                    };
+                    
+                    // Джиттер-буфер для объявленных кадров; дальше длительность
+                    // берётся из самих кадров
+                    let mut jitter_buffer = JitterBuffer::with_frame_duration(32, 2, (frame_size_ms * 1000.0) as u64);
+                    if let Some(track) = track_manager.get_track(track_id) {
+                        jitter_buffer.set_delay(JitterDelay::from_config(&track.config));
+                    }
+                    
+                    // Подключаем трек к общему потоку устройства вывода
+                    let playback = if !output_device.is_empty() {
+                        let buffer_frames = track_manager.get_track(track_id).and_then(|t| t.config.buffer_frames);
+                        match outputs.mixer.attach_channels(track_id, &output_device, buffer_frames, channels) {
+                            Ok(channel) => {
+                                tracing::info!(
+                                    "Воспроизведение запущено для трека {} на {}",
+                                    track_id,
+                                    output_device
+                                );
+                                if let Some(track) = track_manager.get_track(track_id) {
+                                    channel.set_channel_map(track.config.channel_map.clone());
+                                    channel.set_copies(&track.config.output_devices, track.config.buffer_frames);
+                                }
+                                Some(channel)
+                            }
+                            Err(e) => {
+                                tracing::warn!(
+                                    "Не удалось запустить воспроизведение для трека {}: {}",
+                                    track_id,
+                                    e
+                                );
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    
+                    // Создаём трек в менеджере
+                    if track_manager.get_track(track_id).is_none() {
+                        let track_config = TrackConfig {
+                            track_id: Some(track_id),
+                            name: format!("Входящий трек {}", track_id),
+                            device_id: output_device.clone(),
+                            bitrate: DEFAULT_BITRATE,
+                            frame_size_ms: DEFAULT_FRAME_SIZE_MS,
+                            channels,
+                            codec: packet.codec,
+                            ..Default::default()
+                        };
+                        let _ = track_manager.create_track(track_config);
+                    }
+                    
+                    entry.insert(OutputTrackState {
+                        decoder,
+                        jitter_buffer,
+                        scheduler: PlayoutScheduler::new(),
+                        playback,
+                        packets_received: 0,
+                        packets_lost: 0,
+                        device_id: output_device,
+                        channels,
+                        loss_reporter: LossReporter::new(),
+                        source: None,
+                        last_arrival: None,
+                    });
+                }
+                
+                // Обрабатываем пакет
+                if let Some(state) = states.get_mut(&track_id) {
+                    state.packets_received += 1;
+                    if packet.source.is_some() {
+                        state.source = packet.source;
+                    }
+                    
+                    // Перерыв в потоке пакетов (после маркера тишины отправитель молчит намеренно)
+                    if let Some(last_arrival) = state.last_arrival {
+                        track_manager.record_arrival_gap(track_id, packet.receive_time.saturating_duration_since(last_arrival));
+                    }
+                    state.last_arrival = (!packet.payload.is_empty()).then_some(packet.receive_time);
+                    
+                    // Громкость и заглушение пира-источника в микшере, затем громкость трека и соло
+                    if let Some(ref playback) = state.playback {
+                        let peer_gain = state.source.map_or(1.0, |source| track_manager.peer_gain(source.ip()));
+                        playback.set_gain(peer_gain * track_manager.output_gain(track_id));
+                        let monitor = outputs.monitor_device.as_deref().filter(|_| track_manager.is_pfl(track_id));
+                        playback.set_monitor(monitor);
+                    }
+                    
+                    if let Some(track) = track_manager.get_track(track_id) {
+                        track.increment_packets();
+                    }
+                    
+                    // Отправитель сменил кодек или число каналов трека
+                    let track_channels = track_manager.get_track(track_id).map_or(0, |track| track.config.channels);
+                    let channels = decoder_channels(packet.is_stereo, track_channels);
+                    if state.decoder.codec() != packet.codec || state.decoder.channels() != channels {
+                        match new_decoder(packet.codec, DEFAULT_SAMPLE_RATE, channels, state.decoder.frame_size()) {
+                            Ok(decoder) => {
+                                tracing::info!("Трек {}: кодек {:?}, {} каналов", track_id, packet.codec, channels);
+                                state.decoder = decoder;
+                                state.channels = channels;
+                            }
+                            Err(e) => tracing::warn!("Не удалось создать декодер трека {}: {}", track_id, e),
+                        }
+                    }
+                    
+                    // Маркер перезапуска: сбрасываем декодер и джиттер-буфер с этого пакета
+                    if packet.is_keyframe {
+                        if let Some(flushed) = state.jitter_buffer.restart_at(packet.sequence) {
+                            tracing::info!(
+                                "Трек {}: перезапуск потока на пакете {}",
+                                track_id,
+                                packet.sequence
+                            );
+                            track_manager.record_jitter_buffer_reset(track_id, packet.sequence);
+                            state.scheduler.reset();
+                            if let Err(e) = state.decoder.reset() {
+                                tracing::warn!("Не удалось сбросить декодер трека {}: {}", track_id, e);
+                            }
+                            for frame in flushed.into_iter().filter(|frame| !frame.is_silence_marker()) {
+                                outputs.recorder.push(track_id, &frame.samples, frame.channels);
+                                if let Some(ref playback) = state.playback {
+                                    playback.push_frame(frame);
+                                }
+                            }
+                        }
+                    }
+                    
+                    // DRED: восстанавливаем серию потерянных кадров из истории пакета
+                    if let Err(e) = dred::recover_lost_frames(
+                        state.decoder.as_mut(),
+                        &mut state.jitter_buffer,
+                        &packet.payload,
+                        packet.sequence,
+                        packet.timestamp,
+                    ) {
+                        tracing::debug!("Не удалось восстановить кадры трека {} через DRED: {}", track_id, e);
+                    }
+                    
+                    // Встроенный FEC: восстанавливаем потерянный предыдущий кадр
+                    if packet.has_fec {
+                        if let Err(e) = recover_previous_frame(
+                            state.decoder.as_mut(),
+                            &mut state.jitter_buffer,
+                            &packet.payload,
+                            packet.sequence,
+                            packet.timestamp,
+                        ) {
+                            tracing::debug!("Не удалось восстановить кадр трека {} через FEC: {}", track_id, e);
+                        }
+                    }
+                    
+                    // Декодируем аудио (маркер тишины приходит без данных)
+                    let silence_marker = packet.payload.is_empty();
+                    let decoded = {
+                        let _stage = profiling::stage(track_id, Stage::Decode);
+                        if silence_marker {
+                            Ok(Vec::new())
+                        } else {
+                            state.decoder.decode(&packet.payload)
+                        }
+                    };
+                    match decoded {
+                        Ok(samples) => {
+                            if let Some(track) = track_manager.get_track(track_id) {
+                                track.update_level_atomic(&samples);
+                                track.update_loudness(&samples, state.decoder.channels() as usize);
+                                track.set_silent(silence_marker);
+                            }
+                            track_manager.check_clipping(track_id, &samples);
+                            
+                            let mut frame = if silence_marker {
+                                AudioFrame::silence_marker(state.decoder.channels(), packet.timestamp, packet.sequence)
+                            } else {
+                                AudioFrame::new(samples, state.decoder.channels(), packet.timestamp, packet.sequence)
+                            };
+                            
+                            // Время в пути для оценки очереди в канале (отчёт отправителю)
+                            let arrival_us = media_time_us().saturating_sub(packet.receive_time.elapsed().as_micros() as u64);
+                            state.loss_reporter.note_arrival(packet.timestamp, arrival_us);
+                            
+                            // Проба задержки: отслеживается до вывода по синхронизированным часам
+                            if packet.is_probe {
+                                frame.probe_us = packet
+                                    .source
+                                    .and_then(|source| time_sync.to_local_us(source.ip(), packet.timestamp));
+                            }
+                            
+                            let _stage = profiling::stage(track_id, Stage::Playout);
+                            // Отправитель сменил длительность кадров: джиттер-буфер
+                            // пересчитывает задержку в новых кадрах
+                            let frame_duration_us = state.jitter_buffer.frame_duration_us();
+                            if !state.jitter_buffer.insert(frame) {
+                                track_manager.record_drops(track_id, DropReason::Late, 1);
+                            }
+                            if state.jitter_buffer.frame_duration_us() != frame_duration_us {
+                                tracing::info!(
+                                    "Трек {}: кадры по {} мкс",
+                                    track_id,
+                                    state.jitter_buffer.frame_duration_us()
+                                );
+                            }
+                            
+                            // Обновляем метрики
+                            let jitter_stats = state.jitter_buffer.stats();
+                            if let Some(track) = track_manager.get_track(track_id) {
+                                track.update_jitter(jitter_stats.jitter_us as u32);
+                                // Сколько кадр ждёт в джиттер-буфере своей очереди
+                                let buffer_latency_us =
+                                    state.scheduler.delay_us(&state.jitter_buffer, Instant::now()) as u32;
+                                track.update_latency(buffer_latency_us);
+                                
+                                // Задержка от захвата на пире: возраст пакета по синхронизированным часам + буфер
+                                let e2e_latency_us = packet
+                                    .source
+                                    .and_then(|source| time_sync.age_us(source.ip(), packet.timestamp))
+                                    .map(|age| age + buffer_latency_us as u64);
+                                track.update_e2e_latency(e2e_latency_us);
+                                
+                                // Расхождение часов устройства вывода с часами хоста, задержки проб
+                                if let Some(ref playback) = state.playback {
+                                    track.update_clock_skew(playback.clock_skew_ppm());
+                                    track.update_output_gain_reduction(playback.gain_reduction_db());
+                                    track.update_playout_drift(playback.drift_ppm());
+                                    track.update_device_timing(playback.device_period_ms(), playback.device_latency_ms());
+                                    let probe = playback.probe_meter();
+                                    track.update_probe_latency(probe.output_latency_us(), probe.loopback_latency_us());
+                                }
+                            }
+                            if let Some(ref playback) = state.playback {
+                                track_manager.record_drops(track_id, DropReason::Underflow, playback.take_underruns());
+                            }
+                            // Кадры уходят на вывод в play_due_frames, когда подходит их время
+                        }
+                        Err(e) => {
+                            tracing::warn!("Ошибка декодирования трека {}: {}", track_id, e);
+                            state.packets_lost += 1;
+                            track_manager.record_drops(track_id, DropReason::DecodeError, 1);
+                            
+                            if let Some(track) = track_manager.get_track(track_id) {
+                                track.increment_lost();
+                            }
+                        }
+                    }
+                }
+            }
+            Err(crossbeam_channel::TryRecvError::Empty) => break,
+            Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                tracing::error!("Канал пакетов отключён");
+                break;
+            }
+        }
+    }
+    
+    processed_count > 0
+}
+
+/// Выдать на вывод кадры принятых треков, время которых подошло
+/// (потерянные слоты заполняются маскированием декодера)
+fn play_due_frames(
+    output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    track_manager: &TrackManager,
+    outputs: &PlaybackOutputs,
+) -> bool {
+    let now = Instant::now();
+    let mut played = false;
+    for (&track_id, state) in output_states.lock().iter_mut() {
+        // Кадры уходят с той скоростью, с которой их забирает устройство вывода
+        if let Some(ratio) = state.playback.as_ref().and_then(|playback| playback.clock_monitor().rate_ratio()) {
+            state.scheduler.set_output_rate(ratio);
+        }
+        // Поправку дрейфа часов отправителя ведёт планировщик, вывод читает
+        // кадры с ней и не оценивает свою
+        if let Some(ref playback) = state.playback {
+            playback.set_rate_correction_ppm(state.scheduler.rate_correction_ppm());
+        }
+        while let Some(frame) = next_frame_scheduled(
+            state.decoder.as_mut(),
+            &mut state.jitter_buffer,
+            &mut state.scheduler,
+            now,
+        ) {
+            played = true;
+            // Дальше тишина: вывод играет нули, не считая опустошений
+            if frame.is_silence_marker() {
+                if let Some(ref playback) = state.playback {
+                    playback.set_silent();
+                }
+                continue;
+            }
+            outputs.recorder.push(track_id, &frame.samples, frame.channels);
+            match state.playback {
+                Some(ref playback) => {
+                    if playback.gain() == 0.0 {
+                        track_manager.record_drops(track_id, DropReason::Muted, 1);
+                    }
+                    playback.push_frame(frame);
+                }
+                None => track_manager.record_drops(track_id, DropReason::NoDevice, 1),
+            }
+        }
+    }
+    played
+}
+
+/// Применить отчёт о приёме к энкодеру трека
+fn apply_feedback(
+    track_id: u8,
+    state: &mut InputTrackState,
+    report: &TrackFeedback,
+    track_manager: &Arc<TrackManager>,
+) {
+    // У lossless-треков нет битрейта для подстройки
+    if state.encoder.opus_mut().is_none() {
+        return;
+    }
+    let Some(decision) = state.adaptive.update(report) else {
+        return;
+    };
+    
+    if set_encoder_bitrate(track_id, state, decision, track_manager) {
+        tracing::debug!(
+            "Трек {}: потери {:.1}%, битрейт {} бит/с, ожидаемые потери {}%",
+            track_id,
+            report.loss_fraction * 100.0,
+            decision.bitrate,
+            decision.packet_loss_perc
+        );
+    }
+}
+
+/// Разделить оценку перегруженного канала между треками: потолки
+/// битрейта и приостановка менее важных треков
+fn apply_congestion(
+    congestion: &mut CongestionController,
+    reports: &[TrackFeedback],
+    states: &mut HashMap<u8, InputTrackState>,
+    track_manager: &Arc<TrackManager>,
+) {
+    let sending_bps = states
+        .values()
+        .filter(|state| !state.congestion_paused)
+        .map(|state| state.encoder.bitrate())
+        .sum();
+    congestion.update(reports, sending_bps);
+    
+    let demands: Vec<TrackDemand> = states
+        .iter()
+        .map(|(track_id, state)| {
+            let elastic = state.encoder.codec() == Codec::Opus;
+            TrackDemand {
+                track_id: *track_id,
+                priority: track_manager
+                    .get_track(*track_id)
+                    .map_or(TrackPriority::Normal, |track| track.config.send_priority()),
+                bitrate: if elastic { state.adaptive.uncapped_bitrate() } else { state.encoder.bitrate() },
+                elastic,
+            }
+        })
+        .collect();
+    
+    for (track_id, allocation) in congestion.allocate(&demands) {
+        let Some(state) = states.get_mut(&track_id) else {
+            continue;
+        };
+        state.congestion_paused = allocation.paused;
+        if let Some(track) = track_manager.get_track(track_id) {
+            track.set_congestion_paused(allocation.paused);
+        }
+        if let Some(decision) = state.adaptive.set_cap(allocation.bitrate_cap) {
+            set_encoder_bitrate(track_id, state, decision, track_manager);
+        }
+    }
+}
+
+/// Применить к Opus-кодеру трека битрейт и ожидаемые потери
+fn set_encoder_bitrate(
+    track_id: u8,
+    state: &mut InputTrackState,
+    decision: BitrateDecision,
+    track_manager: &Arc<TrackManager>,
+) -> bool {
+    let Some(encoder) = state.encoder.opus_mut() else {
+        return false;
+    };
+    if let Err(e) = encoder.set_bitrate(decision.bitrate) {
+        tracing::warn!("Не удалось изменить битрейт трека {}: {}", track_id, e);
+        return false;
+    }
+    if let Err(e) = encoder.set_packet_loss_perc(decision.packet_loss_perc) {
+        tracing::warn!("Не удалось изменить ожидаемые потери трека {}: {}", track_id, e);
+    }
+    
+    if let Some(track) = track_manager.get_track(track_id) {
+        track.update_adaptive_bitrate(decision.bitrate);
+    }
+    true
+}
+
+/// Восстановить потоки после сна машины
+fn resync_after_suspend(
+    gap: Duration,
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    network_senders: &Arc<Mutex<HashMap<String, MultiTrackSender>>>,
+    time_sync: &TimeSync,
+) {
+    tracing::warn!("Обнаружен выход из сна (пауза {:.1} с), пересинхронизация", gap.as_secs_f32());
+    
+    // Захваченное до сна аудио не отправляем
+    for state in input_states.lock().values_mut() {
+        while state.capture_buffer.try_pop().is_some() {}
+        state.sample_buffer.clear();
+        state.restart_pending = true;
+    }
+    
+    // Новые последовательности и уведомление получателей
+    for (key, sender) in network_senders.lock().iter() {
+        if let Err(e) = sender.resync() {
+            tracing::warn!("Не удалось уведомить пира {} о пересинхронизации: {}", key, e);
+        }
+    }
+    
+    // Локальные медиа-часы прыгнули относительно всех пиров
+    time_sync.reset_all();
+    flush_output_tracks(output_states, |_| true);
+}
+
+/// Остановить захват и отправить пирам остаток аудио входных треков
+///
+/// Неполный последний кадр дополняется тишиной, а ещё один кадр тишины
+/// выталкивает из кодера его задержку (lookahead), так что до пиров
+/// доходит всё захваченное.
+fn flush_input_tracks(
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    track_manager: &Arc<TrackManager>,
+    network_senders: &Arc<Mutex<HashMap<String, MultiTrackSender>>>,
+    peers: &PeerRegistry,
+    routing: &RoutingMatrix,
+    feedback: &FeedbackInbox,
+) {
+    for state in input_states.lock().values_mut() {
+        state.capture.stop();
+    }
+    process_input_tracks(input_states, track_manager, network_senders, peers, routing, feedback, &mut None);
+    
+    for state in input_states.lock().values_mut() {
+        let frame_size = state.encoder.samples_per_frame();
+        let padding = (frame_size - state.sample_buffer.len() % frame_size) % frame_size + frame_size;
+        state.capture_buffer.push(AudioFrame::new(vec![0.0; padding], state.encoder.channels(), media_time_us(), 0));
+    }
+    process_input_tracks(input_states, track_manager, network_senders, peers, routing, feedback, &mut None);
+}
+
+/// Дождаться отправки пакетов из очередей отправителей (не дольше
+/// `SHUTDOWN_DRAIN_MS`)
+async fn drain_senders(network_senders: &Arc<Mutex<HashMap<String, MultiTrackSender>>>) {
+    let deadline = Instant::now() + Duration::from_millis(SHUTDOWN_DRAIN_MS);
+    loop {
+        let queued: usize = network_senders.lock().values().map(MultiTrackSender::queued_packets).sum();
+        if queued == 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            tracing::warn!("Не отправлено пакетов при завершении: {}", queued);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+/// Плавно свести вывод принятых треков к тишине
+async fn fade_out_outputs(output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>) {
+    let mut faded = false;
+    for playback in output_states.lock().values().filter_map(|state| state.playback.as_ref()) {
+        // Микшер доводит усиление до нуля за блок вывода
+        playback.set_gain(0.0);
+        faded = true;
+    }
+    if faded {
+        tokio::time::sleep(Duration::from_millis(SHUTDOWN_FADE_MS)).await;
+    }
+}
+
+/// Сбросить джиттер-буфер и декодер треков, пришедших от подходящего источника
+fn flush_output_tracks(
+    output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    matches: impl Fn(SocketAddr) -> bool,
+) -> usize {
+    let mut flushed = 0;
+    for (track_id, state) in output_states.lock().iter_mut() {
+        if !state.source.is_some_and(&matches) {
+            continue;
+        }
+        
+        state.jitter_buffer.reset();
+        state.scheduler.reset();
+        if let Err(e) = state.decoder.reset() {
+            tracing::warn!("Не удалось сбросить декодер трека {}: {}", track_id, e);
+        }
+        if let Some(ref playback) = state.playback {
+            playback.clock_monitor().reset();
+            playback.probe_meter().reset();
+        }
+        flushed += 1;
+    }
+    flushed
+}
+
+/// Сопоставить пробу, услышанную на входе петли, с треком, проба
+/// которого последней ушла на вывод
+fn attribute_loopback(output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>, detected_us: u64) {
+    let states = output_states.lock();
+    let meters: Vec<_> = states
+        .iter()
+        .filter_map(|(track_id, state)| state.playback.as_ref().map(|playback| (*track_id, playback.probe_meter())))
+        .collect();
+    if let Some((index, latency_us)) = LoopbackProbe::attribute(detected_us, meters.iter().map(|(_, meter)| *meter)) {
+        tracing::debug!("Трек {}: проба услышана на петле через {:.1} мс", meters[index].0, latency_us as f32 / 1000.0);
+    }
+}
+
+/// Отправить отчёты о потерях пирам, от которых приходят треки
+fn send_feedback(output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>, receiver: &AudioReceiver) {
+    let mut reports: HashMap<SocketAddr, Vec<TrackFeedback>> = HashMap::new();
+    
+    for (track_id, state) in output_states.lock().iter_mut() {
+        let Some(source) = state.source else {
+            continue;
+        };
+        if let Some(report) = state.loss_reporter.report(*track_id, &state.jitter_buffer.stats()) {
+            reports.entry(source).or_default().push(report);
+        }
+    }
+    
+    for (source, reports) in reports {
+        let packet = HandshakePacket::feedback(0, &reports).serialize();
+        if let Err(e) = receiver.send_control(&packet, source) {
+            tracing::debug!("Не удалось отправить отчёт пиру {}: {}", source, e);
+        }
+    }
+}
+
+/// Вывести статистику
+fn print_stats(
+    input_states: &Arc<Mutex<HashMap<u8, InputTrackState>>>,
+    output_states: &Arc<Mutex<HashMap<u8, OutputTrackState>>>,
+    peers: &PeerRegistry,
+    receiver: &AudioReceiver,
+) {
+    let input_count = input_states.lock().len();
+    let output_count = output_states.lock().len();
+    let peer_count = peers.len();
+    let recv_stats = receiver.stats();
+    
+    tracing::info!(
+        "Статистика: {} входящих треков, {} выходящих треков, {} пиров, {} принято пакетов",
+        input_count,
+        output_count,
+        peer_count,
+        recv_stats.packets_received
+    );
+    if let Some(drops) = recv_stats.socket_drops.filter(|&drops| drops > 0) {
+        tracing::info!(
+            "  Сокет: {} пакетов отброшено ядром, буфер приёма {} КиБ",
+            drops,
+            recv_stats.recv_buffer_size / 1024
+        );
+    }
+    
+    for (track_id, state) in output_states.lock().iter() {
+        let Some(ref playback) = state.playback else {
+            continue;
+        };
+        if let Some(skew) = playback.clock_skew_ppm() {
+            tracing::info!("  Выход трека {}: расхождение часов {:+.1} ppm", track_id, skew);
+        }
+        if let Some(drift) = playback.drift_ppm() {
+            tracing::info!("  Выход трека {}: коррекция дрейфа отправителя {:+.1} ppm", track_id, drift);
+        }
+        if let Some(latency_us) = playback.probe_meter().output_latency_us() {
+            let loopback = playback
+                .probe_meter()
+                .loopback_latency_us()
+                .map(|us| format!(", через петлю {:.1} мс", us as f32 / 1000.0))
+                .unwrap_or_default();
+            tracing::info!("  Выход трека {}: задержка пробы {:.1} мс{}", track_id, latency_us as f32 / 1000.0, loopback);
+        }
+    }
+    
+    for peer in peers.statuses() {
+        let bw = &peer.bandwidth;
+        tracing::info!(
+            "  Пир {} ({}): ↑ {:.1} kbps, ↓ {:.1} kbps, всего ↑ {:.2} MB / ↓ {:.2} MB",
+            peer.name,
+            peer.id,
+            bw.up_kbps,
+            bw.down_kbps,
+            bw.bytes_sent as f64 / 1_000_000.0,
+            bw.bytes_received as f64 / 1_000_000.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_until_stopped() {
+        // Профиль слабого устройства - без веб-интерфейса
+        let engine = PeerEngine::new(PeerConfig {
+            name: "engine-test".to_string(),
+            preferred_port: 47_310,
+            auto_connect: false,
+            profile: Some(DeviceProfile::LowPower),
+            config_path: None,
+            ..PeerConfig::default()
+        })
+        .unwrap();
+        assert!(!engine.status().running);
+
+        let stopper = async {
+            while !engine.is_running() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let status = engine.status();
+            assert_eq!((status.name.as_str(), status.audio_port), ("engine-test", engine.audio_port()));
+            assert!(status.input_tracks.is_empty() && status.output_tracks.is_empty());
+            engine.stop();
+        };
+        let (result, ()) = tokio::join!(engine.run(), stopper);
+        result.unwrap();
+        assert!(!engine.status().running);
+
+        // Движок запускается один раз
+        assert!(engine.run().await.is_err());
+    }
+}
@@ -20,6 +20,12 @@ pub enum Error {
     #[error("Configuration error: {0}")]
     Config(String),
     
+    #[error("Service error: {0}")]
+    Service(String),
+    
+    #[error("Self-test failed: {0}")]
+    SelfTest(String),
+    
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -91,6 +97,12 @@ pub enum NetworkError {
     
     #[error("Timeout")]
     Timeout,
+    
+    #[error("Unsupported configuration: {0}")]
+    Unsupported(String),
+    
+    #[error("Request failed: {0}")]
+    RequestFailed(String),
 }
 
 /// Track management errors
@@ -110,6 +122,9 @@ pub enum TrackError {
     
     #[error("Track is not active")]
     NotActive,
+    
+    #[error("No talkback track configured")]
+    NoTalkback,
 }
 
 /// Result type alias for the application
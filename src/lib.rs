@@ -77,11 +77,24 @@
 //! ```
 
 pub mod audio;
+pub mod cli;
 pub mod codec;
 pub mod config;
+pub mod config_store;
+pub mod ctl;
+pub mod engine;
 pub mod error;
+pub mod logs;
 pub mod network;
+pub mod profiling;
 pub mod protocol;
+pub mod recording;
+pub mod replay;
+pub mod routing;
+pub mod selftest;
+pub mod service;
+pub mod sessions;
+pub mod stats;
 pub mod tracks;
 pub mod ui;
 
@@ -107,15 +120,142 @@ pub mod constants {
     /// Default UDP port for audio streaming
     pub const DEFAULT_UDP_PORT: u16 = 5000;
     
+    /// Default UDP port receivers accept QUIC connections on
+    pub const DEFAULT_QUIC_PORT: u16 = 5003;
+    
+    /// Default multicast TTL (1 = the local subnet only)
+    pub const DEFAULT_MULTICAST_TTL: u32 = 1;
+    
     /// Default WebSocket port for control
     pub const DEFAULT_WS_PORT: u16 = 8080;
     
+    /// Default JSON-RPC control port
+    pub const DEFAULT_RPC_PORT: u16 = 8081;
+    
+    /// Default port for OSC remote control
+    pub const DEFAULT_OSC_PORT: u16 = 9000;
+    
     /// Default jitter buffer size in milliseconds
     pub const DEFAULT_JITTER_BUFFER_MS: u32 = 20;
     
     /// Maximum packet size for UDP
     pub const MAX_PACKET_SIZE: usize = 1472; // MTU - IP/UDP headers
     
+    /// Expected packet loss hint for Opus when FEC is enabled without a measured value
+    pub const DEFAULT_FEC_PACKET_LOSS_PERC: u8 = 10;
+    
+    /// DRED history per packet when deep redundancy is enabled (ms)
+    pub const DEFAULT_DRED_DURATION_MS: u16 = 500;
+    
+    /// Gain applied to the other outgoing tracks while talkback is held (-12 dB)
+    pub const TALKBACK_DUCK_GAIN: f32 = 0.25;
+    
+    /// Highest gain allowed for audio received from one peer (+12 dB)
+    pub const MAX_PEER_GAIN: f32 = 4.0;
+    
+    /// Highest volume of a track (+12 dB)
+    pub const MAX_TRACK_VOLUME: f32 = 4.0;
+    
+    /// Highest jitter-buffer delay a track can be set to (ms)
+    pub const MAX_JITTER_DELAY_MS: u32 = 1000;
+    
     /// Lock-free ring buffer capacity (in frames)
     pub const RING_BUFFER_CAPACITY: usize = 256;
+    
+    /// Longest wait on shutdown for queued packets to go out (ms)
+    pub const SHUTDOWN_DRAIN_MS: u64 = 250;
+    
+    /// Fade-out of the playback on shutdown (ms)
+    pub const SHUTDOWN_FADE_MS: u64 = 50;
+    
+    /// Environment variable holding the audio encryption pre-shared key
+    pub const PSK_ENV_VAR: &str = "LAN_AUDIO_PSK";
+    
+    /// Environment variable letting an encrypted receiver accept plaintext tracks
+    pub const ALLOW_PLAINTEXT_ENV_VAR: &str = "LAN_AUDIO_ALLOW_PLAINTEXT";
+    
+    /// Default interval between periodic stats log lines (seconds)
+    pub const DEFAULT_STATS_INTERVAL_SECS: u64 = 5;
+    
+    /// Default gap in the packets of a received track logged as an incident (ms)
+    pub const DEFAULT_GAP_THRESHOLD_MS: u32 = 200;
+    
+    /// Environment variable overriding the stats log interval (seconds)
+    pub const STATS_INTERVAL_ENV_VAR: &str = "LAN_AUDIO_STATS_INTERVAL";
+    
+    /// Environment variable enabling quiet mode (no periodic stats in the log)
+    pub const QUIET_ENV_VAR: &str = "LAN_AUDIO_QUIET";
+    
+    /// Environment variable enabling the latency probe on the sending side
+    pub const LATENCY_PROBE_ENV_VAR: &str = "LAN_AUDIO_LATENCY_PROBE";
+    
+    /// Environment variable naming the input device of an analog loopback
+    /// that listens for the latency probe on the receiving side
+    pub const PROBE_LOOPBACK_ENV_VAR: &str = "LAN_AUDIO_PROBE_LOOPBACK";
+    
+    /// Environment variable making the virtual output (for OBS) the default output
+    pub const VIRTUAL_OUTPUT_ENV_VAR: &str = "LAN_AUDIO_VIRTUAL_OUTPUT";
+    
+    /// Environment variable selecting the audio backend ("default", "jack" or "pipewire")
+    pub const AUDIO_BACKEND_ENV_VAR: &str = "LAN_AUDIO_BACKEND";
+    
+    /// Environment variable selecting the hardware profile ("desktop" or "low-power")
+    pub const PROFILE_ENV_VAR: &str = "LAN_AUDIO_PROFILE";
+    
+    /// Environment variable selecting the packet format ("native" or "rtp")
+    pub const PACKET_FORMAT_ENV_VAR: &str = "LAN_AUDIO_PACKET_FORMAT";
+    
+    /// Environment variable turning off MMCSS and qWave on Windows ("0")
+    pub const QOS_ENV_VAR: &str = "LAN_AUDIO_QOS";
+    
+    /// Environment variable turning on the control packet log ("1")
+    pub const DEBUG_CAPTURE_ENV_VAR: &str = "LAN_AUDIO_DEBUG_CAPTURE";
+    
+    /// Environment variable simulating a bad network on received audio
+    /// ("loss=5,delay=20,jitter=10,duplicate=1,reorder=2")
+    pub const SIMULATE_ENV_VAR: &str = "LAN_AUDIO_SIMULATE";
+    
+    /// Environment variable naming a file the receiver dumps its packets to
+    /// (`--dump-packets`)
+    pub const PACKET_DUMP_ENV_VAR: &str = "LAN_AUDIO_PACKET_DUMP";
+    
+    /// Environment variable naming a pcapng file capturing the audio
+    /// protocol (`--pcap`)
+    pub const PCAP_ENV_VAR: &str = "LAN_AUDIO_PCAP";
+    
+    /// Environment variable selecting the configuration file (`--config`)
+    pub const CONFIG_ENV_VAR: &str = "LAN_AUDIO_CONFIG";
+    
+    /// Environment variable naming a peer (`--name`)
+    pub const NAME_ENV_VAR: &str = "LAN_AUDIO_NAME";
+    
+    /// Environment variable selecting the audio UDP port (`--port`)
+    pub const PORT_ENV_VAR: &str = "LAN_AUDIO_PORT";
+    
+    /// Environment variable selecting the peer discovery mode (`--discovery`)
+    pub const DISCOVERY_ENV_VAR: &str = "LAN_AUDIO_DISCOVERY";
+    
+    /// Environment variable holding the sender's receiver address (`sender <TARGET>`)
+    pub const TARGET_ENV_VAR: &str = "LAN_AUDIO_TARGET";
+    
+    /// Environment variable holding the control port `ctl` talks to (`--address`)
+    pub const CTL_ADDRESS_ENV_VAR: &str = "LAN_AUDIO_CTL_ADDRESS";
+    
+    /// MMCSS task the streaming threads join on Windows
+    pub const DEFAULT_MMCSS_TASK: &str = "Pro Audio";
+    
+    /// Stats log interval floor in the low-power profile (seconds)
+    pub const LOW_POWER_STATS_INTERVAL_SECS: u64 = 30;
+    
+    /// Socket buffer size cap in the low-power profile
+    pub const LOW_POWER_SOCKET_BUFFER_SIZE: usize = 512 * 1024;
+    
+    /// Tracks a low-power receiver plays at once
+    pub const LOW_POWER_MAX_TRACKS: usize = 4;
+    
+    /// Receive buffer autotuning cap in the low-power profile
+    pub const LOW_POWER_RECV_BUFFER_MAX_SIZE: usize = 2 * 1024 * 1024;
+    
+    /// Default cap for receive buffer autotuning
+    pub const DEFAULT_RECV_BUFFER_MAX_SIZE: usize = 16 * 1024 * 1024;
 }
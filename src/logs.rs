@@ -0,0 +1,206 @@
+//! Log records for the web UI
+//!
+//! Every binary installs [`layer`] next to its terminal output. It keeps the
+//! newest [`CAPACITY`] records in memory; they are served at `GET /api/logs`
+//! and pushed to WebSocket clients as they come, so "no sound" on the other
+//! PC can be diagnosed from the browser without a terminal on it.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::network::timesync::unix_time_ms;
+
+/// Records kept in memory
+pub const CAPACITY: usize = 2000;
+
+static LOG: LogBuffer = LogBuffer::new();
+
+/// Severity of a record, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<tracing::Level> for LogLevel {
+    fn from(level: tracing::Level) -> Self {
+        match level {
+            tracing::Level::ERROR => LogLevel::Error,
+            tracing::Level::WARN => LogLevel::Warn,
+            tracing::Level::INFO => LogLevel::Info,
+            tracing::Level::DEBUG => LogLevel::Debug,
+            tracing::Level::TRACE => LogLevel::Trace,
+        }
+    }
+}
+
+/// One log line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Number of the record, increasing from 1
+    pub seq: u64,
+    /// Wall-clock time (ms since the Unix epoch)
+    pub time_ms: u64,
+    pub level: LogLevel,
+    /// Module that logged it
+    pub target: String,
+    /// The message followed by the other fields as `name=value`
+    pub message: String,
+}
+
+/// Tracing layer keeping records in memory
+pub struct LogLayer;
+
+/// The layer to install with the binary's subscriber
+pub fn layer() -> LogLayer {
+    LogLayer
+}
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        LOG.push((*metadata.level()).into(), metadata.target(), visitor.finish());
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl MessageVisitor {
+    fn finish(mut self) -> String {
+        self.message.push_str(&self.fields);
+        self.message
+    }
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Records after `since` (a `seq`, None = all) at `level` or more severe,
+/// the newest `limit` of them, oldest first
+pub fn records(since: Option<u64>, level: Option<LogLevel>, limit: Option<usize>) -> Vec<LogRecord> {
+    LOG.records(since, level, limit)
+}
+
+/// Number of the newest record (0 before the first)
+pub fn last_seq() -> u64 {
+    LOG.records.lock().1
+}
+
+/// Ring of the newest [`CAPACITY`] records
+struct LogBuffer {
+    /// Records and the number of the last one
+    records: Mutex<(VecDeque<LogRecord>, u64)>,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            records: Mutex::new((VecDeque::new(), 0)),
+        }
+    }
+
+    fn push(&self, level: LogLevel, target: &str, message: String) {
+        let time_ms = unix_time_ms();
+        let mut guard = self.records.lock();
+        let (records, seq) = &mut *guard;
+        *seq += 1;
+        if records.len() == CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(LogRecord {
+            seq: *seq,
+            time_ms,
+            level,
+            target: target.to_string(),
+            message,
+        });
+    }
+
+    fn records(&self, since: Option<u64>, level: Option<LogLevel>, limit: Option<usize>) -> Vec<LogRecord> {
+        let guard = self.records.lock();
+        let mut matching: Vec<LogRecord> = guard
+            .0
+            .iter()
+            .filter(|record| since.is_none_or(|since| record.seq > since))
+            .filter(|record| level.is_none_or(|level| record.level <= level))
+            .cloned()
+            .collect();
+        if let Some(limit) = limit {
+            matching.drain(..matching.len().saturating_sub(limit));
+        }
+        matching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_buffer() {
+        let buffer = LogBuffer::new();
+        for i in 0..CAPACITY + 5 {
+            let level = if i % 2 == 0 { LogLevel::Info } else { LogLevel::Warn };
+            buffer.push(level, "lan_audio_streamer::test", format!("line {}", i));
+        }
+
+        // The oldest records made room
+        let all = buffer.records(None, None, None);
+        assert_eq!(all.len(), CAPACITY);
+        assert_eq!((all[0].seq, all[0].message.as_str()), (6, "line 5"));
+
+        let new = buffer.records(Some(CAPACITY as u64 + 3), None, None);
+        assert_eq!(new.iter().map(|record| record.seq).collect::<Vec<_>>(), vec![2004, 2005]);
+        let warnings = buffer.records(None, Some(LogLevel::Warn), Some(3));
+        assert_eq!(warnings.iter().map(|record| record.seq).collect::<Vec<_>>(), vec![2000, 2002, 2004]);
+        assert!(warnings.iter().all(|record| record.level == LogLevel::Warn));
+    }
+
+    #[test]
+    fn test_layer() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry().with(layer());
+        let before = last_seq();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(track_id = 2, "Track {} underrun", 2);
+        });
+        let record = records(Some(before), None, None)
+            .into_iter()
+            .find(|record| record.message.starts_with("Track 2 underrun"))
+            .unwrap();
+        assert_eq!(record.level, LogLevel::Warn);
+        assert_eq!(record.message, "Track 2 underrun track_id=2");
+        assert_eq!(record.target, "lan_audio_streamer::logs::tests");
+    }
+}
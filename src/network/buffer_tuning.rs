@@ -0,0 +1,172 @@
+//! Receive buffer autotuning
+//!
+//! Receive buffers start at `NetworkConfig::recv_buffer_size`. The receiver
+//! thread polls the kernel's drop counter for its socket and doubles the
+//! buffer whenever datagrams were dropped, up to
+//! `NetworkConfig::recv_buffer_max_size`. The kernel may grant less than
+//! requested (Linux clamps to `net.core.rmem_max`); growing stops once a
+//! request doesn't enlarge the effective size.
+//!
+//! Drop counters are read with `getsockopt(SO_MEMINFO)` on Linux. Other
+//! platforms don't report drops, so their buffers keep the configured size.
+
+use socket2::SockRef;
+use std::net::UdpSocket as StdUdpSocket;
+use std::time::{Duration, Instant};
+
+/// How often the drop counter is checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Datagrams the kernel dropped on `socket` because its receive buffer was
+/// full, if the platform reports it
+#[cfg(target_os = "linux")]
+pub fn socket_drops(socket: &StdUdpSocket) -> Option<u64> {
+    use std::os::fd::AsRawFd;
+
+    // SK_MEMINFO_VARS: length of the SO_MEMINFO array since Linux 4.6
+    let mut meminfo = [0u32; 9];
+    let mut len = std::mem::size_of_val(&meminfo) as libc::socklen_t;
+    // SAFETY: the buffer and its length describe a valid, writable array
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MEMINFO,
+            meminfo.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    let drops = libc::SK_MEMINFO_DROPS as usize;
+    (result == 0 && len as usize > drops * 4).then(|| meminfo[drops] as u64)
+}
+
+/// Datagrams the kernel dropped on `socket` because its receive buffer was
+/// full, if the platform reports it
+#[cfg(not(target_os = "linux"))]
+pub fn socket_drops(_socket: &StdUdpSocket) -> Option<u64> {
+    None
+}
+
+/// Effective receive buffer size of `socket` as reported by the kernel
+pub fn recv_buffer_size(socket: &StdUdpSocket) -> Option<usize> {
+    SockRef::from(socket).recv_buffer_size().ok()
+}
+
+/// Effective send buffer size of `socket` as reported by the kernel
+pub fn send_buffer_size(socket: &StdUdpSocket) -> Option<usize> {
+    SockRef::from(socket).send_buffer_size().ok()
+}
+
+/// Decides when to grow a socket's receive buffer
+#[derive(Debug)]
+pub struct BufferTuner {
+    requested: usize,
+    max: usize,
+    last_drops: Option<u64>,
+    last_check: Option<Instant>,
+    /// The kernel stopped granting larger buffers
+    limited: bool,
+}
+
+impl BufferTuner {
+    pub fn new(initial: usize, max: usize) -> Self {
+        Self {
+            requested: initial,
+            max,
+            last_drops: None,
+            last_check: None,
+            limited: false,
+        }
+    }
+
+    /// Whether the drop counter should be checked again
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.last_check.is_none_or(|last| now.duration_since(last) >= CHECK_INTERVAL)
+    }
+
+    /// Buffer size to request after reading the socket's cumulative drop
+    /// counter, if it should grow
+    pub fn observe(&mut self, drops: u64, now: Instant) -> Option<usize> {
+        self.last_check = Some(now);
+        let previous = self.last_drops.replace(drops)?;
+        if drops <= previous || self.limited || self.requested >= self.max {
+            return None;
+        }
+        self.requested = self.requested.saturating_mul(2).min(self.max);
+        Some(self.requested)
+    }
+
+    /// Record the effective size before and after the last request;
+    /// returns false once the kernel refuses to grow the buffer
+    pub fn granted(&mut self, before: usize, after: usize) -> bool {
+        self.limited = after <= before;
+        !self.limited
+    }
+}
+
+/// Grow the receive buffer of `socket` if its drop counter rose since the
+/// last check; returns the effective size after growing
+pub fn autotune(tuner: &mut BufferTuner, socket: &StdUdpSocket, drops: u64, now: Instant) -> Option<usize> {
+    let size = tuner.observe(drops, now)?;
+    let before = recv_buffer_size(socket).unwrap_or(0);
+    if let Err(e) = SockRef::from(socket).set_recv_buffer_size(size) {
+        tracing::warn!("Failed to grow receive buffer to {} bytes: {}", size, e);
+        return None;
+    }
+    let after = recv_buffer_size(socket).unwrap_or(before);
+    if tuner.granted(before, after) {
+        tracing::info!("Socket dropped packets, receive buffer grown to {} KiB", after / 1024);
+    } else {
+        tracing::warn!(
+            "Socket dropped packets but the receive buffer stays at {} KiB (raise net.core.rmem_max)",
+            after / 1024
+        );
+    }
+    Some(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tuner_grows_on_drops_up_to_cap() {
+        let start = Instant::now();
+        let mut tuner = BufferTuner::new(1024, 3000);
+
+        // The first reading is only a baseline
+        assert_eq!(tuner.observe(5, start), None);
+        assert!(!tuner.is_due(start));
+        // No new drops
+        assert_eq!(tuner.observe(5, start + CHECK_INTERVAL), None);
+        assert!(tuner.is_due(start + CHECK_INTERVAL * 2));
+
+        assert_eq!(tuner.observe(9, start), Some(2048));
+        assert!(tuner.granted(2048, 4096));
+        assert_eq!(tuner.observe(12, start), Some(3000));
+        assert!(tuner.granted(4096, 6000));
+        // At the cap
+        assert_eq!(tuner.observe(20, start), None);
+    }
+
+    #[test]
+    fn test_tuner_stops_when_kernel_refuses() {
+        let now = Instant::now();
+        let mut tuner = BufferTuner::new(1024, 1 << 20);
+        tuner.observe(0, now);
+
+        assert_eq!(tuner.observe(1, now), Some(2048));
+        assert!(!tuner.granted(2048, 2048));
+        assert_eq!(tuner.observe(2, now), None);
+    }
+
+    #[test]
+    fn test_socket_buffer_sizes() {
+        let socket = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(recv_buffer_size(&socket).is_some_and(|size| size > 0));
+        assert!(send_buffer_size(&socket).is_some_and(|size| size > 0));
+        if cfg!(target_os = "linux") {
+            assert_eq!(socket_drops(&socket), Some(0));
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Контроль перегрузки канала
+//!
+//! Адаптация битрейта (`codec::adaptive`) реагирует на потери каждого трека
+//! по отдельности, а слабый Wi-Fi перегружают все треки вместе. Здесь по
+//! отчётам получателей (`network::feedback`) оценивается пропускная
+//! способность канала целиком, по образцу GCC:
+//!
+//! - задержка в очереди выше порога или растущая несколько отчётов подряд,
+//!   либо большие потери — перегрузка: оценка падает ниже текущего битрейта;
+//! - убывающая задержка — очередь рассасывается, оценка держится;
+//! - чистые отчёты — оценка медленно растёт, пока не вместит все треки.
+//!
+//! Каждый трек получает из оценки хотя бы минимальный битрейт Opus, а
+//! остаток достаётся трекам по приоритету: сначала высокому (голос), затем
+//! обычному (музыка), затем низкому (фон), внутри одного приоритета —
+//! пропорционально битрейту. Так битрейт срезается прежде всего у фоновых
+//! треков. Когда и минимумы не помещаются, треки низкого, затем обычного
+//! приоритета приостанавливаются и возобновляются с запасом, когда оценка
+//! вырастет. Треки высокого приоритета и самый
+//! важный из треков не приостанавливаются никогда. Битрейты считаются по
+//! кодеку, без заголовков пакетов.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::codec::adaptive::MIN_ADAPTIVE_BITRATE;
+use crate::config::CongestionConfig;
+use crate::network::feedback::TrackFeedback;
+use crate::protocol::TrackPriority;
+
+/// Рост задержки за отчёт, считающийся трендом (мкс)
+const DELAY_GRADIENT_US: i64 = 5_000;
+
+/// Отчётов с ростом задержки подряд до перегрузки
+const RISING_REPORTS_FOR_OVERUSE: u32 = 2;
+
+/// Потери, при которых канал перегружен независимо от задержки
+const OVERUSE_LOSS: f32 = 0.10;
+
+/// Потери ниже этой доли не мешают росту оценки
+const CLEAN_LOSS: f32 = 0.02;
+
+/// Чистых отчётов подряд до роста оценки
+const CLEAN_REPORTS_FOR_INCREASE: u32 = 3;
+
+/// Оценка при перегрузке относительно текущего битрейта
+const DECREASE_FACTOR: f32 = 0.85;
+
+/// Рост оценки после чистых отчётов
+const INCREASE_FACTOR: f32 = 1.08;
+
+/// Наименьший шаг роста оценки (бит/с)
+const MIN_INCREASE_BPS: u32 = 8_000;
+
+/// Запас оценки для возобновления приостановленного трека
+const RESUME_HEADROOM: f32 = 1.25;
+
+/// Состояние канала по последним отчётам
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkUsage {
+    /// Канал справляется
+    Normal,
+    /// Очередь растёт или пакеты теряются: отправлять меньше
+    Overuse,
+    /// Очередь рассасывается
+    Underuse,
+}
+
+/// Что нужно от канала одному треку
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackDemand {
+    pub track_id: u8,
+    pub priority: TrackPriority,
+    /// Битрейт трека без ограничения канала (бит/с)
+    pub bitrate: u32,
+    /// Битрейт можно снизить (Opus); lossless-трек можно только приостановить
+    pub elastic: bool,
+}
+
+impl TrackDemand {
+    /// Наименьший битрейт, с которым трек ещё отправляется
+    fn min_bitrate(&self) -> u32 {
+        if self.elastic {
+            MIN_ADAPTIVE_BITRATE.min(self.bitrate)
+        } else {
+            self.bitrate
+        }
+    }
+}
+
+/// Решение для трека
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    /// Потолок битрейта трека (None — без ограничения)
+    pub bitrate_cap: Option<u32>,
+    /// Отправка трека приостановлена
+    pub paused: bool,
+}
+
+impl Allocation {
+    const UNLIMITED: Allocation = Allocation { bitrate_cap: None, paused: false };
+}
+
+/// Оценка канала и распределение её между треками
+#[derive(Debug)]
+pub struct CongestionController {
+    config: CongestionConfig,
+    /// Оценка пропускной способности (бит/с), None вне перегрузки
+    estimate: Option<u32>,
+    usage: LinkUsage,
+    last_delay_us: Option<u32>,
+    rising_reports: u32,
+    clean_reports: u32,
+    paused: BTreeSet<u8>,
+}
+
+impl CongestionController {
+    pub fn new(config: CongestionConfig) -> Self {
+        Self {
+            config,
+            estimate: None,
+            usage: LinkUsage::Normal,
+            last_delay_us: None,
+            rising_reports: 0,
+            clean_reports: 0,
+            paused: BTreeSet::new(),
+        }
+    }
+
+    /// Текущая оценка канала (бит/с), None пока перегрузки не было
+    pub fn estimate(&self) -> Option<u32> {
+        self.estimate
+    }
+
+    pub fn usage(&self) -> LinkUsage {
+        self.usage
+    }
+
+    /// Учесть отчёты за интервал (худший трек решает); `sending_bps` —
+    /// суммарный битрейт отправляемых сейчас треков
+    pub fn update(&mut self, reports: &[TrackFeedback], sending_bps: u32) -> LinkUsage {
+        if reports.is_empty() {
+            return self.usage;
+        }
+        let loss = reports.iter().map(|report| report.loss_fraction).fold(0.0, f32::max);
+        let delay_us = reports.iter().map(|report| report.queuing_delay_us).max().unwrap_or(0);
+        let threshold_us = self.config.max_queuing_delay_ms as i64 * 1000;
+
+        let gradient = self.last_delay_us.map_or(0, |last| delay_us as i64 - last as i64);
+        self.last_delay_us = Some(delay_us);
+        if gradient > DELAY_GRADIENT_US && delay_us as i64 > threshold_us / 4 {
+            self.rising_reports += 1;
+        } else {
+            self.rising_reports = 0;
+        }
+
+        // Высокая, но убывающая задержка — очередь уже рассасывается
+        let overuse = loss > OVERUSE_LOSS
+            || self.rising_reports >= RISING_REPORTS_FOR_OVERUSE
+            || (delay_us as i64 > threshold_us && gradient >= 0);
+        self.usage = if overuse {
+            LinkUsage::Overuse
+        } else if gradient < -DELAY_GRADIENT_US {
+            LinkUsage::Underuse
+        } else {
+            LinkUsage::Normal
+        };
+
+        match self.usage {
+            LinkUsage::Overuse => {
+                self.clean_reports = 0;
+                self.rising_reports = 0;
+                let current = self.estimate.map_or(sending_bps, |estimate| estimate.min(sending_bps));
+                let estimate = ((current as f32 * DECREASE_FACTOR) as u32).max(MIN_ADAPTIVE_BITRATE);
+                if self.estimate.is_none() {
+                    tracing::info!(
+                        "Канал перегружен (задержка в очереди {} мс, потери {:.1}%): оценка {} кбит/с",
+                        delay_us / 1000,
+                        loss * 100.0,
+                        estimate / 1000
+                    );
+                }
+                self.estimate = Some(estimate);
+            }
+            LinkUsage::Underuse => {
+                self.clean_reports = 0;
+            }
+            LinkUsage::Normal if loss < CLEAN_LOSS => {
+                self.clean_reports += 1;
+                if self.clean_reports >= CLEAN_REPORTS_FOR_INCREASE {
+                    self.clean_reports = 0;
+                    self.estimate = self.estimate.map(|estimate| {
+                        ((estimate as f32 * INCREASE_FACTOR) as u32).max(estimate + MIN_INCREASE_BPS)
+                    });
+                }
+            }
+            LinkUsage::Normal => {
+                self.clean_reports = 0;
+            }
+        }
+        self.usage
+    }
+
+    /// Распределить оценку между треками
+    pub fn allocate(&mut self, demands: &[TrackDemand]) -> BTreeMap<u8, Allocation> {
+        self.paused.retain(|track_id| demands.iter().any(|demand| demand.track_id == *track_id));
+        let total: u64 = demands.iter().map(|demand| demand.bitrate as u64).sum();
+
+        // Все треки снова помещаются: перегрузка закончилась
+        let Some(estimate) = self.estimate.filter(|&estimate| estimate as u64 <= total) else {
+            if self.estimate.take().is_some() || !self.paused.is_empty() {
+                tracing::info!("Перегрузка канала закончилась, треки отправляются полностью");
+            }
+            self.paused.clear();
+            return demands.iter().map(|demand| (demand.track_id, Allocation::UNLIMITED)).collect();
+        };
+
+        // Самые важные треки первыми; среди равных — меньший номер
+        let mut order: Vec<&TrackDemand> = demands.iter().collect();
+        order.sort_by_key(|demand| (std::cmp::Reverse(demand.priority), demand.track_id));
+
+        let mut needed = 0u64;
+        let mut kept = Vec::with_capacity(order.len());
+        for (index, demand) in order.iter().enumerate() {
+            let min = demand.min_bitrate() as u64;
+            let headroom = if self.paused.contains(&demand.track_id) { RESUME_HEADROOM } else { 1.0 };
+            let fits = needed + (min as f32 * headroom) as u64 <= estimate as u64;
+            let keep = fits || !self.config.pause_tracks || index == 0 || demand.priority == TrackPriority::High;
+            if keep {
+                needed += min;
+                kept.push(**demand);
+                if self.paused.remove(&demand.track_id) {
+                    tracing::info!("Трек {} снова отправляется", demand.track_id);
+                }
+            } else if self.paused.insert(demand.track_id) {
+                tracing::info!("Трек {} приостановлен: канал не вмещает его", demand.track_id);
+            }
+        }
+
+        // Lossless-треки идут как есть, Opus-треки получают минимумы, а
+        // остаток делится по приоритетам, начиная с высокого
+        let mut remaining = (estimate as u64).saturating_sub(needed);
+        let mut allocations: BTreeMap<u8, Allocation> = demands
+            .iter()
+            .map(|demand| (demand.track_id, Allocation { bitrate_cap: None, paused: true }))
+            .collect();
+        for tier in kept.chunk_by(|a, b| a.priority == b.priority) {
+            let extra: u64 = tier.iter().map(|demand| (demand.bitrate - demand.min_bitrate()) as u64).sum();
+            let granted = remaining.min(extra);
+            remaining -= granted;
+            for demand in tier {
+                let min = demand.min_bitrate();
+                let share = min as u64 + (demand.bitrate - min) as u64 * granted / extra.max(1);
+                let bitrate_cap = (share < demand.bitrate as u64).then_some(share as u32);
+                allocations.insert(demand.track_id, Allocation { bitrate_cap, paused: false });
+            }
+        }
+        allocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(loss_fraction: f32, queuing_delay_us: u32) -> TrackFeedback {
+        TrackFeedback { track_id: 0, loss_fraction, jitter_us: 0, queuing_delay_us }
+    }
+
+    fn demand(track_id: u8, priority: TrackPriority, bitrate: u32) -> TrackDemand {
+        TrackDemand { track_id, priority, bitrate, elastic: true }
+    }
+
+    #[test]
+    fn test_delay_trend_detects_overuse() {
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        assert_eq!(controller.update(&[report(0.0, 0)], 256_000), LinkUsage::Normal);
+        assert!(controller.estimate().is_none());
+
+        // Очередь растёт два отчёта подряд, ещё до порога
+        assert_eq!(controller.update(&[report(0.0, 12_000)], 256_000), LinkUsage::Normal);
+        assert_eq!(controller.update(&[report(0.0, 24_000)], 256_000), LinkUsage::Overuse);
+        assert_eq!(controller.estimate(), Some(217_600));
+
+        // Очередь рассасывается: оценка держится, хотя задержка выше порога
+        controller.update(&[report(0.0, 45_000)], 256_000);
+        let estimate = controller.estimate();
+        assert_eq!(controller.update(&[report(0.0, 41_000)], 217_600), LinkUsage::Normal);
+        assert_eq!(controller.update(&[report(0.0, 20_000)], 217_600), LinkUsage::Underuse);
+        assert_eq!(controller.estimate(), estimate);
+
+        // Чистые отчёты после опустевшей очереди поднимают оценку
+        let estimate = controller.estimate().unwrap();
+        controller.update(&[report(0.0, 0)], estimate);
+        for _ in 0..CLEAN_REPORTS_FOR_INCREASE {
+            controller.update(&[report(0.0, 0)], estimate);
+        }
+        assert_eq!(controller.estimate(), Some((estimate as f32 * INCREASE_FACTOR) as u32));
+
+        // Большие потери — перегрузка при любой задержке
+        assert_eq!(controller.update(&[report(0.0, 0), report(0.2, 0)], 100_000), LinkUsage::Overuse);
+    }
+
+    #[test]
+    fn test_allocation_by_priority() {
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        let demands = [
+            demand(0, TrackPriority::High, 64_000),
+            demand(1, TrackPriority::Normal, 128_000),
+            demand(2, TrackPriority::Low, 128_000),
+        ];
+        assert!(controller.allocate(&demands).values().all(|allocation| *allocation == Allocation::UNLIMITED));
+
+        // Сверх минимумов: сначала высокий приоритет, затем обычный
+        controller.estimate = Some(160_000);
+        let allocations = controller.allocate(&demands);
+        assert_eq!(allocations[&0].bitrate_cap, None);
+        assert_eq!(allocations[&1].bitrate_cap, Some(72_000));
+        assert_eq!(allocations[&2].bitrate_cap, Some(MIN_ADAPTIVE_BITRATE));
+        assert!(allocations.values().all(|allocation| !allocation.paused));
+
+        // Минимумы не помещаются: сначала низкий приоритет
+        controller.estimate = Some(60_000);
+        let allocations = controller.allocate(&demands);
+        assert!(allocations[&2].paused && !allocations[&1].paused);
+        assert_eq!(allocations[&0].bitrate_cap, Some(36_000));
+        assert_eq!(allocations[&1].bitrate_cap, Some(MIN_ADAPTIVE_BITRATE));
+        controller.estimate = Some(30_000);
+        let allocations = controller.allocate(&demands);
+        assert!(allocations[&1].paused && allocations[&2].paused && !allocations[&0].paused);
+
+        // Возобновление только с запасом
+        controller.estimate = Some(50_000);
+        assert!(controller.allocate(&demands)[&1].paused);
+        controller.estimate = Some(60_000);
+        assert!(!controller.allocate(&demands)[&1].paused);
+
+        // Все треки помещаются: ограничения снимаются
+        controller.estimate = Some(400_000);
+        assert!(controller.allocate(&demands).values().all(|allocation| *allocation == Allocation::UNLIMITED));
+        assert!(controller.estimate().is_none());
+    }
+
+    #[test]
+    fn test_lossless_and_pause_disabled() {
+        let config = CongestionConfig { pause_tracks: false, ..CongestionConfig::default() };
+        let mut controller = CongestionController::new(config);
+        let demands = [
+            TrackDemand { track_id: 0, priority: TrackPriority::Normal, bitrate: 1_200_000, elastic: false },
+            demand(1, TrackPriority::Low, 128_000),
+        ];
+
+        // Lossless-трек не ограничивается, Opus получает остаток
+        controller.estimate = Some(1_000_000);
+        let allocations = controller.allocate(&demands);
+        assert_eq!(allocations[&0], Allocation::UNLIMITED);
+        assert_eq!(allocations[&1].bitrate_cap, Some(MIN_ADAPTIVE_BITRATE));
+        assert!(!allocations[&1].paused);
+
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        controller.estimate = Some(1_000_000);
+        assert!(controller.allocate(&demands)[&1].paused);
+    }
+}
@@ -0,0 +1,431 @@
+//! Optional audio payload encryption with a pre-shared key
+//!
+//! Packets are sealed with ChaCha20-Poly1305 (RFC 8439, the
+//! `chacha20poly1305` crate). The 16-byte audio header stays in clear text
+//! (the receiver needs track and sequence before decrypting) but is
+//! authenticated as associated data, so flags, sequence and timestamp
+//! cannot be tampered with.
+//!
+//! ```text
+//! ┌──────────────┬──────────────┬──────────────────────┬──────────┐
+//! │ Header (16)  │  Nonce (12)  │ Ciphertext (payload) │ Tag (16) │
+//! │ ENCRYPTED=1  │ salt|counter │                      │ Poly1305 │
+//! └──────────────┴──────────────┴──────────────────────┴──────────┘
+//! ```
+//!
+//! The key is derived from the PSK with PBKDF2-HMAC-SHA256. Both ends
+//! derive it on their own, so the salt is fixed for the application: it
+//! keeps tables precomputed for other uses of the passphrase useless, and
+//! the iteration count makes every guess at it slow.
+//!
+//! Nonces are a random per-sender salt plus a packet counter, so they never
+//! repeat for a key even when sequence numbers restart. The receiver keeps
+//! a window of the counters it accepted from each sender and drops a
+//! packet whose counter it has seen or that is too far behind, so a
+//! captured packet can't be played again by resending it. The window is
+//! checked after the tag, so a copy of a packet sent over a redundant path
+//! is told apart from a forgery ([`OpenError::Replayed`]).
+//!
+//! Control packets that change what a sender does (`SyncRequest`,
+//! `Subscribe`) stay readable but get a nonce and a Poly1305 tag over the
+//! whole packet appended ([`PacketCipher::sign_control`]); their counters
+//! go through the same replay window.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+use parking_lot::Mutex;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::protocol::{AudioPacket, PacketFlags};
+
+/// Key size (ChaCha20)
+pub const KEY_SIZE: usize = 32;
+
+/// Nonce size (IETF ChaCha20-Poly1305)
+pub const NONCE_SIZE: usize = 12;
+
+/// Authentication tag size (Poly1305)
+pub const TAG_SIZE: usize = 16;
+
+/// Bytes added to every encrypted packet
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Bytes appended to a signed control packet
+pub const CONTROL_TAG_SIZE: usize = NONCE_SIZE + TAG_SIZE;
+
+/// PBKDF2 salt of the key derivation
+const KEY_SALT: &[u8] = b"lan-audio-streamer/psk/v2";
+
+/// PBKDF2 iterations (OWASP recommendation for HMAC-SHA256)
+const KEY_ITERATIONS: u32 = 600_000;
+
+/// Domain separation for the advertised key fingerprint
+const FINGERPRINT_CONTEXT: &[u8] = b"lan-audio-streamer/fingerprint/v1";
+
+/// Counters a packet may lag behind the newest one of its sender
+/// (reordering across tracks and paths)
+const REPLAY_WINDOW: u64 = 1024;
+
+/// Senders whose replay windows are kept
+const MAX_REPLAY_SENDERS: usize = 64;
+
+/// Why a packet couldn't be opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    /// Unencrypted, truncated or forged
+    Unauthenticated,
+    /// Authentic, but its counter was already accepted (a replay, or a
+    /// copy of the packet over another path)
+    Replayed,
+}
+
+/// Packet cipher keyed by a pre-shared key
+pub struct PacketCipher {
+    aead: ChaCha20Poly1305,
+    fingerprint: u32,
+    /// Random per-instance nonce salt
+    salt: u32,
+    /// Packet counter (nonce low 64 bits)
+    counter: AtomicU64,
+    /// Counters accepted from each sender, by nonce salt
+    replay: Mutex<HashMap<u32, ReplayWindow>>,
+}
+
+impl PacketCipher {
+    /// Derive the cipher from a pre-shared key (passphrase). Derivation is
+    /// slow on purpose, so keys are kept per passphrase for the process.
+    pub fn from_psk(psk: &str) -> Self {
+        static KEYS: OnceLock<Mutex<HashMap<String, [u8; KEY_SIZE]>>> = OnceLock::new();
+        let key = *KEYS
+            .get_or_init(Default::default)
+            .lock()
+            .entry(psk.to_string())
+            .or_insert_with(|| derive_key(psk));
+        Self::from_key(&key)
+    }
+
+    /// Create the cipher from a raw 256-bit key
+    pub fn from_key(key: &[u8; KEY_SIZE]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(FINGERPRINT_CONTEXT);
+        hasher.update(key);
+        let digest = hasher.finalize();
+
+        let mut rng = rand::thread_rng();
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(key)),
+            fingerprint: u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]),
+            salt: rng.next_u32(),
+            // Random start with room to count up without wrapping
+            counter: AtomicU64::new(rng.next_u64() >> 1),
+            replay: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Short key fingerprint, safe to advertise (peers compare it to
+    /// reject mismatched keys before streaming)
+    pub fn fingerprint(&self) -> u32 {
+        self.fingerprint
+    }
+
+    /// Next unique nonce
+    fn next_nonce(&self) -> [u8; NONCE_SIZE] {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[..4].copy_from_slice(&self.salt.to_le_bytes());
+        nonce[4..].copy_from_slice(&counter.to_le_bytes());
+        nonce
+    }
+
+    /// Encrypt an audio packet in place (sets the ENCRYPTED flag)
+    pub fn seal_packet(&self, packet: &mut AudioPacket) {
+        packet.flags = packet.flags.set_encrypted(true);
+        let aad = header_aad(packet);
+        packet.payload = self.seal_payload(&aad, &packet.payload);
+    }
+
+    /// Decrypt an audio packet in place
+    pub fn open_packet(&self, packet: &mut AudioPacket) -> Result<(), OpenError> {
+        if !packet.flags.is_encrypted() || packet.payload.len() < ENCRYPTION_OVERHEAD {
+            return Err(OpenError::Unauthenticated);
+        }
+
+        let aad = header_aad(packet);
+        let plaintext = self.open_payload(&aad, &packet.payload)?;
+        packet.payload = Bytes::from(plaintext);
+        packet.flags = packet.flags.set_encrypted(false);
+        Ok(())
+    }
+
+    /// Encrypt a payload as `[nonce][ciphertext][tag]`, authenticating
+    /// `aad` (the header of its packet) along with it
+    pub fn seal_payload(&self, aad: &[u8], payload: &[u8]) -> Bytes {
+        let nonce = self.next_nonce();
+        let mut sealed = BytesMut::with_capacity(ENCRYPTION_OVERHEAD + payload.len());
+        sealed.put_slice(&nonce);
+        sealed.put_slice(payload);
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), aad, &mut sealed[NONCE_SIZE..])
+            .expect("payload within the ChaCha20 limit");
+        sealed.put_slice(&tag);
+        sealed.freeze()
+    }
+
+    /// Decrypt a payload sealed by `seal_payload`
+    pub fn open_payload(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, OpenError> {
+        if sealed.len() < ENCRYPTION_OVERHEAD {
+            return Err(OpenError::Unauthenticated);
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_SIZE);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_SIZE);
+
+        let mut plaintext = ciphertext.to_vec();
+        self.aead
+            .decrypt_in_place_detached(Nonce::from_slice(nonce), aad, &mut plaintext, Tag::from_slice(tag))
+            .map_err(|_| OpenError::Unauthenticated)?;
+        self.accept_nonce(nonce)?;
+        Ok(plaintext)
+    }
+
+    /// Append a nonce and a tag authenticating a control packet
+    pub fn sign_control(&self, packet: &[u8]) -> Bytes {
+        let nonce = self.next_nonce();
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), packet, &mut [])
+            .expect("empty payload");
+
+        let mut signed = BytesMut::with_capacity(packet.len() + CONTROL_TAG_SIZE);
+        signed.put_slice(packet);
+        signed.put_slice(&nonce);
+        signed.put_slice(&tag);
+        signed.freeze()
+    }
+
+    /// Check the tag of a signed control packet; returns the packet
+    /// without it
+    pub fn verify_control<'a>(&self, data: &'a [u8]) -> Result<&'a [u8], OpenError> {
+        let packet_len = data.len().checked_sub(CONTROL_TAG_SIZE).ok_or(OpenError::Unauthenticated)?;
+        let (packet, trailer) = data.split_at(packet_len);
+        let (nonce, tag) = trailer.split_at(NONCE_SIZE);
+        self.aead
+            .decrypt_in_place_detached(Nonce::from_slice(nonce), packet, &mut [], Tag::from_slice(tag))
+            .map_err(|_| OpenError::Unauthenticated)?;
+        self.accept_nonce(nonce)?;
+        Ok(packet)
+    }
+
+    /// Move the replay window of the sender of an authenticated packet
+    fn accept_nonce(&self, nonce: &[u8]) -> Result<(), OpenError> {
+        let salt = u32::from_le_bytes(nonce[..4].try_into().unwrap());
+        let counter = u64::from_le_bytes(nonce[4..NONCE_SIZE].try_into().unwrap());
+
+        let mut replay = self.replay.lock();
+        if replay.get(&salt).is_some_and(|window| !window.is_fresh(counter)) {
+            return Err(OpenError::Replayed);
+        }
+        if !replay.contains_key(&salt) && replay.len() >= MAX_REPLAY_SENDERS {
+            let oldest = replay.iter().min_by_key(|(_, window)| window.last_used).map(|(salt, _)| *salt);
+            if let Some(oldest) = oldest {
+                replay.remove(&oldest);
+            }
+        }
+        replay.entry(salt).or_insert_with(|| ReplayWindow::new(counter)).accept(counter);
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for PacketCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketCipher")
+            .field("fingerprint", &self.fingerprint)
+            .finish_non_exhaustive()
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 key of a passphrase
+fn derive_key(psk: &str) -> [u8; KEY_SIZE] {
+    let mut key = [0u8; KEY_SIZE];
+    pbkdf2::pbkdf2_hmac::<Sha256>(psk.as_bytes(), KEY_SALT, KEY_ITERATIONS, &mut key);
+    key
+}
+
+/// Header bytes authenticated as associated data
+fn header_aad(packet: &AudioPacket) -> [u8; 16] {
+    let mut aad = [0u8; 16];
+    aad[..2].copy_from_slice(&crate::protocol::PACKET_MAGIC.to_le_bytes());
+    aad[2] = packet.track_id;
+    aad[3] = packet.flags.as_byte() | PacketFlags::ENCRYPTED;
+    aad[4..8].copy_from_slice(&packet.sequence.to_le_bytes());
+    aad[8..].copy_from_slice(&packet.timestamp.to_le_bytes());
+    aad
+}
+
+/// Nonce counters accepted from one sender: the newest one and which of
+/// the `REPLAY_WINDOW` before it were seen
+struct ReplayWindow {
+    highest: u64,
+    seen: [u64; (REPLAY_WINDOW / 64) as usize],
+    last_used: Instant,
+}
+
+impl ReplayWindow {
+    fn new(counter: u64) -> Self {
+        Self {
+            highest: counter,
+            seen: [0; (REPLAY_WINDOW / 64) as usize],
+            last_used: Instant::now(),
+        }
+    }
+
+    /// Bit of a counter in `seen`
+    fn slot(counter: u64) -> (usize, u64) {
+        let index = counter % REPLAY_WINDOW;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+
+    /// Whether a counter is neither seen nor too old
+    fn is_fresh(&self, counter: u64) -> bool {
+        if counter > self.highest {
+            return true;
+        }
+        if self.highest - counter >= REPLAY_WINDOW {
+            return false;
+        }
+        let (word, bit) = Self::slot(counter);
+        self.seen[word] & bit == 0
+    }
+
+    /// Mark a counter as seen
+    fn accept(&mut self, counter: u64) {
+        if counter > self.highest {
+            // Slots of the counters skipped over are free again
+            if counter - self.highest >= REPLAY_WINDOW {
+                self.seen = [0; (REPLAY_WINDOW / 64) as usize];
+            } else {
+                for skipped in self.highest + 1..counter {
+                    let (word, bit) = Self::slot(skipped);
+                    self.seen[word] &= !bit;
+                }
+            }
+            self.highest = counter;
+        }
+        let (word, bit) = Self::slot(counter);
+        self.seen[word] |= bit;
+        self.last_used = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let sender = PacketCipher::from_psk("correct horse battery staple");
+        let receiver = PacketCipher::from_psk("correct horse battery staple");
+        let other = PacketCipher::from_psk("wrong key");
+        assert_eq!(sender.fingerprint(), receiver.fingerprint());
+        assert_ne!(sender.fingerprint(), other.fingerprint());
+
+        let payload = Bytes::from_static(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut packet = AudioPacket::new(3, 42, 123_456, payload.clone());
+        sender.seal_packet(&mut packet);
+        assert!(packet.flags.is_encrypted());
+        assert_eq!(packet.payload.len(), payload.len() + ENCRYPTION_OVERHEAD);
+
+        // Over the wire and back
+        let mut received = AudioPacket::deserialize(packet.serialize()).unwrap();
+        let mut forged = received.clone();
+        assert_eq!(other.open_packet(&mut received.clone()), Err(OpenError::Unauthenticated));
+        assert!(receiver.open_packet(&mut received).is_ok());
+        assert_eq!(received.payload, payload);
+        assert!(!received.flags.is_encrypted());
+
+        // Header is authenticated
+        forged.sequence = 43;
+        assert_eq!(receiver.open_packet(&mut forged), Err(OpenError::Unauthenticated));
+
+        // Unencrypted packets are rejected
+        let mut plain = AudioPacket::new(3, 44, 0, payload);
+        assert_eq!(receiver.open_packet(&mut plain), Err(OpenError::Unauthenticated));
+    }
+
+    #[test]
+    fn test_replayed_packet_rejected() {
+        let key = [7u8; KEY_SIZE];
+        let sender = PacketCipher::from_key(&key);
+        let receiver = PacketCipher::from_key(&key);
+
+        let sealed: Vec<AudioPacket> = (0..3)
+            .map(|sequence| {
+                let mut packet = AudioPacket::new(1, sequence, 0, Bytes::from_static(&[0x5a; 20]));
+                sender.seal_packet(&mut packet);
+                packet
+            })
+            .collect();
+
+        // Reordered packets are fine, a captured one sent again is not
+        assert!(receiver.open_packet(&mut sealed[1].clone()).is_ok());
+        assert!(receiver.open_packet(&mut sealed[0].clone()).is_ok());
+        assert_eq!(receiver.open_packet(&mut sealed[1].clone()), Err(OpenError::Replayed));
+        assert_eq!(receiver.open_packet(&mut sealed[0].clone()), Err(OpenError::Replayed));
+        assert!(receiver.open_packet(&mut sealed[2].clone()).is_ok());
+
+        // A forged packet doesn't use up its counter
+        let mut next = AudioPacket::new(1, 3, 0, Bytes::from_static(&[0x5a; 20]));
+        sender.seal_packet(&mut next);
+        let mut forged = next.clone();
+        forged.sequence += 1;
+        assert_eq!(receiver.open_packet(&mut forged), Err(OpenError::Unauthenticated));
+        assert!(receiver.open_packet(&mut next).is_ok());
+    }
+
+    #[test]
+    fn test_signed_control_packet() {
+        let key = [9u8; KEY_SIZE];
+        let sender = PacketCipher::from_key(&key);
+        let receiver = PacketCipher::from_key(&key);
+        let other = PacketCipher::from_key(&[1u8; KEY_SIZE]);
+
+        let packet = b"LAHSsubscribe";
+        let signed = sender.sign_control(packet);
+        assert_eq!(signed.len(), packet.len() + CONTROL_TAG_SIZE);
+        assert_eq!(other.verify_control(&signed), Err(OpenError::Unauthenticated));
+        assert_eq!(receiver.verify_control(&signed), Ok(&packet[..]));
+        assert_eq!(receiver.verify_control(&signed), Err(OpenError::Replayed));
+
+        // Unsigned, truncated or altered packets are rejected
+        assert_eq!(receiver.verify_control(packet), Err(OpenError::Unauthenticated));
+        let mut altered = sender.sign_control(packet).to_vec();
+        altered[4] ^= 1;
+        assert_eq!(receiver.verify_control(&altered), Err(OpenError::Unauthenticated));
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::new(100);
+        window.accept(100);
+        assert!(!window.is_fresh(100));
+        assert!(window.is_fresh(99));
+        assert!(window.is_fresh(101));
+
+        // Counters too far behind the newest are dropped, seen or not
+        window.accept(100 + REPLAY_WINDOW);
+        assert!(!window.is_fresh(100));
+        assert!(window.is_fresh(101));
+        assert!(!window.is_fresh(100 + REPLAY_WINDOW));
+
+        // Slots reused by newer counters start out unseen
+        window.accept(100 + 2 * REPLAY_WINDOW - 1);
+        assert!(window.is_fresh(100 + REPLAY_WINDOW * 2 - 2));
+        assert!(!window.is_fresh(100 + REPLAY_WINDOW - 1));
+    }
+}
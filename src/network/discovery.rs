@@ -2,16 +2,22 @@
 //!
 //! Provides automatic discovery of local network interfaces and peer devices
 //! without requiring manual IP configuration.
+//!
+//! Two backends are available (see [`DiscoveryMode`]): UDP broadcast beacons
+//! and mDNS/DNS-SD (`_lanaudio._udp.local`) for networks where broadcast
+//! is filtered by the router or switch.
 
 use socket2::{Domain, Protocol, Socket, Type};
-use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
+use crate::config::DiscoveryMode;
 use crate::error::NetworkError;
+use crate::network::packet_log::{self, Direction};
 
 /// Discovery service port (separate from audio streaming)
 pub const DISCOVERY_PORT: u16 = 5001;
@@ -55,11 +61,18 @@ impl TryFrom<u8> for DiscoveryPacketType {
 
 /// Discovery packet structure
 /// Format: [MAGIC(4)][TYPE(1)][AUDIO_PORT(2)][NAME_LEN(1)][NAME(variable)]
+///         [IPV6_COUNT(1)][IPV6(16) * count][ID_LEN(1)][ID(variable)]
+///
+/// The IPv6 and instance id tails are optional; older peers ignore them.
 #[derive(Debug, Clone)]
 pub struct DiscoveryPacket {
     pub packet_type: DiscoveryPacketType,
     pub audio_port: u16,
     pub name: String,
+    /// IPv6 addresses the sender can be reached at
+    pub ipv6_addresses: Vec<Ipv6Addr>,
+    /// Random per-process id; the same on every interface the beacon goes out on
+    pub instance_id: String,
 }
 
 impl DiscoveryPacket {
@@ -68,9 +81,23 @@ impl DiscoveryPacket {
             packet_type,
             audio_port,
             name: name.chars().take(255).collect(), // Limit name length
+            ipv6_addresses: Vec::new(),
+            instance_id: String::new(),
         }
     }
     
+    /// Advertise IPv6 addresses (at most 8 fit a beacon)
+    pub fn with_ipv6_addresses(mut self, addresses: Vec<Ipv6Addr>) -> Self {
+        self.ipv6_addresses = addresses.into_iter().take(8).collect();
+        self
+    }
+    
+    /// Advertise the instance id (lets peers recognize several paths to us)
+    pub fn with_instance_id(mut self, instance_id: String) -> Self {
+        self.instance_id = instance_id.chars().take(64).collect();
+        self
+    }
+    
     pub fn serialize(&self) -> Vec<u8> {
         let name_bytes = self.name.as_bytes();
         let mut data = Vec::with_capacity(8 + name_bytes.len());
@@ -81,6 +108,18 @@ impl DiscoveryPacket {
         data.push(name_bytes.len() as u8);
         data.extend_from_slice(name_bytes);
         
+        if !self.ipv6_addresses.is_empty() || !self.instance_id.is_empty() {
+            data.push(self.ipv6_addresses.len() as u8);
+            for addr in &self.ipv6_addresses {
+                data.extend_from_slice(&addr.octets());
+            }
+        }
+        
+        if !self.instance_id.is_empty() {
+            data.push(self.instance_id.len() as u8);
+            data.extend_from_slice(self.instance_id.as_bytes());
+        }
+        
         data
     }
     
@@ -104,10 +143,29 @@ impl DiscoveryPacket {
         
         let name = String::from_utf8_lossy(&data[8..8 + name_len]).to_string();
         
+        let mut ipv6_addresses = Vec::new();
+        let mut instance_id = String::new();
+        let tail = &data[8 + name_len..];
+        if let Some((&count, rest)) = tail.split_first() {
+            for chunk in rest.chunks_exact(16).take(count as usize) {
+                let octets: [u8; 16] = chunk.try_into().ok()?;
+                ipv6_addresses.push(Ipv6Addr::from(octets));
+            }
+            
+            let rest = rest.get(16 * ipv6_addresses.len()..).unwrap_or_default();
+            if let Some((&id_len, id)) = rest.split_first() {
+                if let Some(id) = id.get(..id_len as usize) {
+                    instance_id = String::from_utf8_lossy(id).to_string();
+                }
+            }
+        }
+        
         Some(Self {
             packet_type,
             audio_port,
             name,
+            ipv6_addresses,
+            instance_id,
         })
     }
 }
@@ -120,6 +178,11 @@ pub struct DiscoveredPeer {
     pub name: String,
     pub is_sender: bool,
     pub last_seen: Instant,
+    /// IPv6 addresses advertised by the peer
+    pub ipv6_addresses: Vec<Ipv6Addr>,
+    /// Instance id advertised by the peer (empty for older peers).
+    /// Beacons that arrive over several networks share it.
+    pub instance_id: String,
 }
 
 impl DiscoveredPeer {
@@ -127,6 +190,16 @@ impl DiscoveredPeer {
     pub fn audio_address(&self) -> SocketAddr {
         SocketAddr::new(self.address.ip(), self.audio_port)
     }
+    
+    /// Audio streaming address over IPv6, if the peer advertised one
+    pub fn audio_address_v6(&self) -> Option<SocketAddr> {
+        match self.address.ip() {
+            IpAddr::V6(v6) if v6.to_ipv4_mapped().is_none() => Some(SocketAddr::new(IpAddr::V6(v6), self.audio_port)),
+            _ => self.ipv6_addresses
+                .first()
+                .map(|v6| SocketAddr::new(IpAddr::V6(*v6), self.audio_port)),
+        }
+    }
 }
 
 /// Get all local network interface addresses
@@ -135,14 +208,19 @@ pub fn get_local_addresses() -> Vec<IpAddr> {
     
     // Try to get addresses by connecting to a remote address
     // This gives us the default outbound interface
-    if let Ok(socket) = StdUdpSocket::bind("0.0.0.0:0") {
-        // Try multiple well-known addresses to find local IPs
-        for target in &["8.8.8.8:53", "1.1.1.1:53", "208.67.222.222:53"] {
-            if socket.connect(target).is_ok() {
-                if let Ok(local_addr) = socket.local_addr() {
-                    let ip = local_addr.ip();
-                    if !addresses.contains(&ip) && !ip.is_loopback() {
-                        addresses.push(ip);
+    for (bind, targets) in [
+        ("0.0.0.0:0", &["8.8.8.8:53", "1.1.1.1:53", "208.67.222.222:53"][..]),
+        ("[::]:0", &["[2001:4860:4860::8888]:53", "[2606:4700:4700::1111]:53"][..]),
+    ] {
+        if let Ok(socket) = StdUdpSocket::bind(bind) {
+            // Try multiple well-known addresses to find local IPs
+            for target in targets {
+                if socket.connect(target).is_ok() {
+                    if let Ok(local_addr) = socket.local_addr() {
+                        let ip = local_addr.ip();
+                        if !addresses.contains(&ip) && !ip.is_loopback() {
+                            addresses.push(ip);
+                        }
                     }
                 }
             }
@@ -178,6 +256,19 @@ pub fn get_local_addresses() -> Vec<IpAddr> {
     unique
 }
 
+/// IPv6 link-local addresses (fe80::/10) need a scope id and are not advertised
+fn is_ipv6_link_local(ip: &Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xffc0) == 0xfe80
+}
+
+/// Parse an interface address as printed by ip/ifconfig/ipconfig
+/// ("fd00::1/64", "fe80::1%en0", "2001:db8::1(Preferred)")
+fn parse_interface_address(text: &str) -> Option<IpAddr> {
+    let text = text.trim();
+    let end = text.find(['/', '%', '(']).unwrap_or(text.len());
+    text[..end].parse().ok()
+}
+
 /// Score IP addresses for priority (higher = better for LAN)
 fn ip_priority_score(ip: &IpAddr) -> u8 {
     match ip {
@@ -217,11 +308,11 @@ fn get_windows_interfaces() -> Vec<IpAddr> {
         if let Ok(text) = String::from_utf8(output.stdout) {
             for line in text.lines() {
                 let line = line.trim();
-                // Look for IPv4 addresses
-                if line.contains("IPv4") || line.contains("IP Address") {
-                    if let Some(addr_str) = line.split(':').nth(1) {
-                        if let Ok(addr) = addr_str.trim().parse::<Ipv4Addr>() {
-                            addresses.push(IpAddr::V4(addr));
+                // Look for IPv4 and IPv6 addresses
+                if line.contains("IPv4") || line.contains("IPv6") || line.contains("IP Address") {
+                    if let Some((_, addr_str)) = line.split_once(": ") {
+                        if let Some(addr) = parse_interface_address(addr_str) {
+                            addresses.push(addr);
                         }
                     }
                 }
@@ -242,13 +333,10 @@ fn get_unix_interfaces() -> Vec<IpAddr> {
     if let Ok(output) = Command::new("ip").args(["addr", "show"]).output() {
         if let Ok(text) = String::from_utf8(output.stdout) {
             for line in text.lines() {
-                if line.contains("inet ") && !line.contains("inet6") {
-                    if let Some(addr_part) = line.split_whitespace().nth(1) {
-                        if let Some(addr_str) = addr_part.split('/').next() {
-                            if let Ok(addr) = addr_str.parse::<Ipv4Addr>() {
-                                addresses.push(IpAddr::V4(addr));
-                            }
-                        }
+                let line = line.trim();
+                if line.starts_with("inet ") || line.starts_with("inet6 ") {
+                    if let Some(addr) = line.split_whitespace().nth(1).and_then(parse_interface_address) {
+                        addresses.push(addr);
                     }
                 }
             }
@@ -258,10 +346,10 @@ fn get_unix_interfaces() -> Vec<IpAddr> {
     else if let Ok(output) = Command::new("ifconfig").output() {
         if let Ok(text) = String::from_utf8(output.stdout) {
             for line in text.lines() {
-                if line.contains("inet ") && !line.contains("inet6") {
+                if line.contains("inet ") || line.contains("inet6 ") {
                     for part in line.split_whitespace() {
-                        if let Ok(addr) = part.parse::<Ipv4Addr>() {
-                            addresses.push(IpAddr::V4(addr));
+                        if let Some(addr) = parse_interface_address(part) {
+                            addresses.push(addr);
                             break;
                         }
                     }
@@ -278,6 +366,17 @@ pub fn get_best_local_address() -> Option<IpAddr> {
     get_local_addresses().into_iter().next()
 }
 
+/// Get routable local IPv6 addresses (global and unique-local)
+pub fn get_local_ipv6_addresses() -> Vec<Ipv6Addr> {
+    get_local_addresses()
+        .into_iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V6(v6) if !is_ipv6_link_local(&v6) && v6.to_ipv4_mapped().is_none() => Some(v6),
+            _ => None,
+        })
+        .collect()
+}
+
 /// Get broadcast addresses for all local subnets
 pub fn get_broadcast_addresses() -> Vec<Ipv4Addr> {
     let mut broadcasts = Vec::new();
@@ -302,6 +401,394 @@ pub fn get_broadcast_addresses() -> Vec<Ipv4Addr> {
     broadcasts
 }
 
+/// mDNS multicast group
+pub const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// mDNS port
+pub const MDNS_PORT: u16 = 5353;
+
+/// DNS-SD service type labels (`_lanaudio._udp.local`)
+const MDNS_SERVICE: [&str; 3] = ["_lanaudio", "_udp", "local"];
+
+/// TTL of announced records in seconds
+const MDNS_TTL: u32 = 120;
+
+/// Number of initial PTR queries sent after start
+const MDNS_INITIAL_QUERIES: u32 = 3;
+
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_PTR: u16 = 12;
+const DNS_TYPE_TXT: u16 = 16;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_TYPE_SRV: u16 = 33;
+const DNS_TYPE_ANY: u16 = 255;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_CACHE_FLUSH: u16 = 0x8000;
+const DNS_FLAG_RESPONSE: u16 = 0x8400;
+
+/// DNS-SD announcement of a single service instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsAnnouncement {
+    /// Instance name (peer display name)
+    pub instance: String,
+    /// Random per-process id, used to ignore our own announcements
+    pub instance_id: String,
+    /// Audio streaming port (SRV)
+    pub audio_port: u16,
+    /// Sender or receiver role (TXT `role=`)
+    pub is_sender: bool,
+    /// Host address (A record), if known
+    pub address: Option<Ipv4Addr>,
+    /// Host IPv6 address (AAAA record), if known
+    pub address_v6: Option<Ipv6Addr>,
+}
+
+/// Parsed mDNS message (only the parts relevant for discovery)
+#[derive(Debug, Default)]
+pub struct MdnsMessage {
+    pub is_response: bool,
+    /// The message asks for `_lanaudio._udp.local`
+    pub queries_service: bool,
+    pub announcements: Vec<MdnsAnnouncement>,
+}
+
+/// Minimal DNS message writer (no name compression)
+struct DnsWriter {
+    buf: Vec<u8>,
+}
+
+impl DnsWriter {
+    fn new(flags: u16, questions: u16, answers: u16) -> Self {
+        let mut buf = Vec::with_capacity(256);
+        buf.extend_from_slice(&0u16.to_be_bytes()); // ID is 0 in mDNS
+        buf.extend_from_slice(&flags.to_be_bytes());
+        buf.extend_from_slice(&questions.to_be_bytes());
+        buf.extend_from_slice(&answers.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        Self { buf }
+    }
+
+    fn encode_name(out: &mut Vec<u8>, labels: &[&str]) {
+        for label in labels {
+            let bytes = label.as_bytes();
+            let len = bytes.len().min(63);
+            out.push(len as u8);
+            out.extend_from_slice(&bytes[..len]);
+        }
+        out.push(0);
+    }
+
+    fn question(&mut self, labels: &[&str], qtype: u16) {
+        Self::encode_name(&mut self.buf, labels);
+        self.buf.extend_from_slice(&qtype.to_be_bytes());
+        self.buf.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    }
+
+    fn record(&mut self, labels: &[&str], rtype: u16, class: u16, rdata: &[u8]) {
+        Self::encode_name(&mut self.buf, labels);
+        self.buf.extend_from_slice(&rtype.to_be_bytes());
+        self.buf.extend_from_slice(&class.to_be_bytes());
+        self.buf.extend_from_slice(&MDNS_TTL.to_be_bytes());
+        self.buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(rdata);
+    }
+}
+
+/// Minimal DNS message reader (supports name compression)
+struct DnsReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> DnsReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.data.get(self.pos..self.pos + 2)?;
+        self.pos += 2;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.data.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    /// Read a (possibly compressed) name as a list of labels
+    fn name(&mut self) -> Option<Vec<String>> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut jumped = false;
+
+        // Guard against pointer loops
+        for _ in 0..128 {
+            let len = *self.data.get(pos)? as usize;
+            if len == 0 {
+                if !jumped {
+                    self.pos = pos + 1;
+                }
+                return Some(labels);
+            }
+
+            if len & 0xC0 == 0xC0 {
+                let low = *self.data.get(pos + 1)? as usize;
+                if !jumped {
+                    self.pos = pos + 2;
+                }
+                jumped = true;
+                pos = ((len & 0x3F) << 8) | low;
+                continue;
+            }
+
+            let label = self.data.get(pos + 1..pos + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).to_string());
+            pos += 1 + len;
+        }
+
+        None
+    }
+}
+
+fn is_service_name(labels: &[String]) -> bool {
+    labels.len() == MDNS_SERVICE.len()
+        && labels
+            .iter()
+            .zip(MDNS_SERVICE.iter())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+impl MdnsAnnouncement {
+    /// Host name used for the SRV target and A record
+    fn host_labels(&self) -> [&str; 2] {
+        [self.instance_id.as_str(), "local"]
+    }
+
+    fn instance_labels(&self) -> [&str; 4] {
+        [
+            self.instance.as_str(),
+            MDNS_SERVICE[0],
+            MDNS_SERVICE[1],
+            MDNS_SERVICE[2],
+        ]
+    }
+
+    /// Serialize as an unsolicited mDNS response (PTR + SRV + TXT + A + AAAA)
+    pub fn serialize(&self) -> Vec<u8> {
+        let answers = 3 + self.address.is_some() as u16 + self.address_v6.is_some() as u16;
+        let mut writer = DnsWriter::new(DNS_FLAG_RESPONSE, 0, answers);
+
+        let mut ptr = Vec::new();
+        DnsWriter::encode_name(&mut ptr, &self.instance_labels());
+        writer.record(&MDNS_SERVICE, DNS_TYPE_PTR, DNS_CLASS_IN, &ptr);
+
+        let mut srv = Vec::new();
+        srv.extend_from_slice(&0u16.to_be_bytes()); // priority
+        srv.extend_from_slice(&0u16.to_be_bytes()); // weight
+        srv.extend_from_slice(&self.audio_port.to_be_bytes());
+        DnsWriter::encode_name(&mut srv, &self.host_labels());
+        writer.record(
+            &self.instance_labels(),
+            DNS_TYPE_SRV,
+            DNS_CLASS_IN | DNS_CACHE_FLUSH,
+            &srv,
+        );
+
+        let mut txt = Vec::new();
+        let role = if self.is_sender { "role=sender" } else { "role=receiver" };
+        let id = format!("id={}", self.instance_id);
+        for entry in [role, id.as_str()] {
+            txt.push(entry.len() as u8);
+            txt.extend_from_slice(entry.as_bytes());
+        }
+        writer.record(
+            &self.instance_labels(),
+            DNS_TYPE_TXT,
+            DNS_CLASS_IN | DNS_CACHE_FLUSH,
+            &txt,
+        );
+
+        if let Some(address) = self.address {
+            writer.record(
+                &self.host_labels(),
+                DNS_TYPE_A,
+                DNS_CLASS_IN | DNS_CACHE_FLUSH,
+                &address.octets(),
+            );
+        }
+
+        if let Some(address) = self.address_v6 {
+            writer.record(
+                &self.host_labels(),
+                DNS_TYPE_AAAA,
+                DNS_CLASS_IN | DNS_CACHE_FLUSH,
+                &address.octets(),
+            );
+        }
+
+        writer.buf
+    }
+}
+
+/// Build a PTR query for `_lanaudio._udp.local`
+pub fn mdns_query() -> Vec<u8> {
+    let mut writer = DnsWriter::new(0, 1, 0);
+    writer.question(&MDNS_SERVICE, DNS_TYPE_PTR);
+    writer.buf
+}
+
+/// Parse an mDNS message. Returns None for malformed packets.
+pub fn parse_mdns_message(data: &[u8]) -> Option<MdnsMessage> {
+    let mut reader = DnsReader::new(data);
+    let _id = reader.u16()?;
+    let flags = reader.u16()?;
+    let questions = reader.u16()?;
+    let answers = reader.u16()?;
+    let authority = reader.u16()?;
+    let additional = reader.u16()?;
+
+    let mut message = MdnsMessage {
+        is_response: flags & 0x8000 != 0,
+        ..Default::default()
+    };
+
+    for _ in 0..questions {
+        let name = reader.name()?;
+        let qtype = reader.u16()?;
+        let _qclass = reader.u16()?;
+        if is_service_name(&name) && (qtype == DNS_TYPE_PTR || qtype == DNS_TYPE_ANY) {
+            message.queries_service = true;
+        }
+    }
+
+    let mut instances: Vec<Vec<String>> = Vec::new();
+    let mut srv: HashMap<Vec<String>, (u16, Vec<String>)> = HashMap::new();
+    let mut txt: HashMap<Vec<String>, Vec<String>> = HashMap::new();
+    let mut hosts: HashMap<Vec<String>, Ipv4Addr> = HashMap::new();
+    let mut hosts_v6: HashMap<Vec<String>, Ipv6Addr> = HashMap::new();
+
+    let records = answers as usize + authority as usize + additional as usize;
+    for _ in 0..records {
+        let name = reader.name()?;
+        let rtype = reader.u16()?;
+        let _class = reader.u16()?;
+        let _ttl = reader.u32()?;
+        let rdlen = reader.u16()? as usize;
+        let rdata_start = reader.pos;
+        let rdata = reader.bytes(rdlen)?;
+
+        // Names inside rdata may point anywhere in the message
+        let mut rdata_reader = DnsReader {
+            data,
+            pos: rdata_start,
+        };
+
+        match rtype {
+            DNS_TYPE_PTR if is_service_name(&name) => {
+                instances.push(rdata_reader.name()?);
+            }
+            DNS_TYPE_SRV => {
+                let _priority = rdata_reader.u16()?;
+                let _weight = rdata_reader.u16()?;
+                let port = rdata_reader.u16()?;
+                let target = rdata_reader.name()?;
+                srv.insert(lowercase(&name), (port, lowercase(&target)));
+            }
+            DNS_TYPE_TXT => {
+                let mut entries = Vec::new();
+                let mut i = 0;
+                while i < rdata.len() {
+                    let len = rdata[i] as usize;
+                    let entry = rdata.get(i + 1..i + 1 + len)?;
+                    entries.push(String::from_utf8_lossy(entry).to_string());
+                    i += 1 + len;
+                }
+                txt.insert(lowercase(&name), entries);
+            }
+            DNS_TYPE_A if rdata.len() == 4 => {
+                hosts.insert(
+                    lowercase(&name),
+                    Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]),
+                );
+            }
+            DNS_TYPE_AAAA if rdata.len() == 16 => {
+                let octets: [u8; 16] = rdata.try_into().ok()?;
+                hosts_v6.insert(lowercase(&name), Ipv6Addr::from(octets));
+            }
+            _ => {}
+        }
+    }
+
+    for instance in instances {
+        let key = lowercase(&instance);
+        let Some((port, target)) = srv.get(&key) else {
+            continue;
+        };
+        let entries = txt.get(&key).cloned().unwrap_or_default();
+        let txt_value = |k: &str| {
+            entries
+                .iter()
+                .find_map(|e| e.strip_prefix(k).and_then(|v| v.strip_prefix('=')))
+                .map(str::to_string)
+        };
+
+        message.announcements.push(MdnsAnnouncement {
+            instance: instance.first().cloned().unwrap_or_default(),
+            instance_id: txt_value("id").unwrap_or_default(),
+            audio_port: *port,
+            is_sender: txt_value("role").as_deref() == Some("sender"),
+            address: hosts.get(target).copied(),
+            address_v6: hosts_v6.get(target).copied(),
+        });
+    }
+
+    Some(message)
+}
+
+fn lowercase(labels: &[String]) -> Vec<String> {
+    labels.iter().map(|l| l.to_ascii_lowercase()).collect()
+}
+
+/// Create the mDNS socket (bound to 5353, joined to 224.0.0.251)
+fn create_mdns_socket() -> Result<StdUdpSocket, NetworkError> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    socket.set_reuse_address(true)
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    // Other responders (avahi, Bonjour) usually share the port
+    #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+    let _ = socket.set_reuse_port(true);
+
+    let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MDNS_PORT);
+    socket.bind(&bind_addr.into())
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    socket.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::UNSPECIFIED)
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    socket.set_multicast_loop_v4(true)
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    socket.set_multicast_ttl_v4(255)
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    socket.set_nonblocking(true)
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    Ok(socket.into())
+}
+
 /// Network discovery service for automatic peer detection
 pub struct DiscoveryService {
     /// Is this a sender (true) or receiver (false)
@@ -325,10 +812,22 @@ pub struct DiscoveryService {
     /// Listener thread handle
     listener_handle: Option<JoinHandle<()>>,
     
+    /// mDNS responder/browser thread handle
+    mdns_handle: Option<JoinHandle<()>>,
+    
+    /// Discovery backend(s) to use
+    mode: DiscoveryMode,
+    
+    /// Random id of this instance (filters own mDNS announcements)
+    instance_id: String,
+    
     /// Callback for new peer discovery
     on_peer_discovered: Option<Arc<dyn Fn(DiscoveredPeer) + Send + Sync>>,
 }
 
+/// Peer discovery callback
+type PeerCallback = Option<Arc<dyn Fn(DiscoveredPeer) + Send + Sync>>;
+
 impl DiscoveryService {
     /// Create a new discovery service
     pub fn new(is_sender: bool, audio_port: u16, name: String) -> Self {
@@ -340,10 +839,28 @@ impl DiscoveryService {
             peers: Arc::new(parking_lot::RwLock::new(Vec::new())),
             beacon_handle: None,
             listener_handle: None,
+            mdns_handle: None,
+            mode: DiscoveryMode::Broadcast,
+            instance_id: uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
             on_peer_discovered: None,
         }
     }
     
+    /// Select discovery backend(s) (must be called before `start`)
+    pub fn set_mode(&mut self, mode: DiscoveryMode) {
+        self.mode = mode;
+    }
+    
+    /// Get the configured discovery backend(s)
+    pub fn mode(&self) -> DiscoveryMode {
+        self.mode
+    }
+    
+    /// Random ID this service announces (tells its own beacons apart)
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+    
     /// Set callback for peer discovery
     pub fn on_peer_discovered<F>(&mut self, callback: F)
     where
@@ -360,6 +877,41 @@ impl DiscoveryService {
         
         self.running.store(true, Ordering::SeqCst);
         
+        // Succeed if at least one configured backend started
+        let mut started = false;
+        let mut last_error = None;
+        
+        if self.mode.uses_broadcast() {
+            match self.start_broadcast() {
+                Ok(()) => started = true,
+                Err(e) => {
+                    tracing::warn!("Broadcast discovery failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        
+        if self.mode.uses_mdns() {
+            match self.start_mdns() {
+                Ok(()) => started = true,
+                Err(e) => {
+                    tracing::warn!("mDNS discovery failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        
+        match last_error {
+            Some(e) if !started => {
+                self.running.store(false, Ordering::SeqCst);
+                Err(e)
+            }
+            _ => Ok(()),
+        }
+    }
+    
+    /// Start the UDP broadcast backend
+    fn start_broadcast(&mut self) -> Result<(), NetworkError> {
         // Create UDP socket for discovery
         let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
             .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
@@ -386,12 +938,13 @@ impl DiscoveryService {
         let is_sender = self.is_sender;
         let audio_port = self.audio_port;
         let name = self.name.clone();
+        let instance_id = self.instance_id.clone();
         
         let beacon_socket = std_socket;
         self.beacon_handle = Some(thread::Builder::new()
             .name("discovery-beacon".to_string())
             .spawn(move || {
-                Self::beacon_loop(beacon_socket, running, is_sender, audio_port, name);
+                Self::beacon_loop(beacon_socket, running, is_sender, audio_port, name, instance_id);
             })
             .map_err(|e| NetworkError::BindFailed(e.to_string()))?);
         
@@ -410,6 +963,142 @@ impl DiscoveryService {
         Ok(())
     }
     
+    /// Start the mDNS/DNS-SD backend
+    fn start_mdns(&mut self) -> Result<(), NetworkError> {
+        let socket = create_mdns_socket()?;
+        
+        let address = get_best_local_address().and_then(|ip| match ip {
+            IpAddr::V4(v4) => Some(v4),
+            IpAddr::V6(_) => None,
+        });
+        
+        let announcement = MdnsAnnouncement {
+            instance: self.name.clone(),
+            instance_id: self.instance_id.clone(),
+            audio_port: self.audio_port,
+            is_sender: self.is_sender,
+            address,
+            address_v6: get_local_ipv6_addresses().into_iter().next(),
+        };
+        
+        let running = self.running.clone();
+        let peers = self.peers.clone();
+        let callback = self.on_peer_discovered.clone();
+        
+        self.mdns_handle = Some(thread::Builder::new()
+            .name("discovery-mdns".to_string())
+            .spawn(move || {
+                Self::mdns_loop(socket, running, announcement, peers, callback);
+            })
+            .map_err(|e| NetworkError::BindFailed(e.to_string()))?);
+        
+        Ok(())
+    }
+    
+    /// Add or refresh a peer, notifying the callback for new ones
+    fn register_peer(
+        peers: &parking_lot::RwLock<Vec<DiscoveredPeer>>,
+        callback: &PeerCallback,
+        peer: DiscoveredPeer,
+    ) {
+        let mut peers_guard = peers.write();
+        let mut found = false;
+        for existing in peers_guard.iter_mut() {
+            if existing.address.ip() == peer.address.ip() && existing.is_sender == peer.is_sender {
+                existing.last_seen = Instant::now();
+                existing.audio_port = peer.audio_port;
+                existing.name = peer.name.clone();
+                existing.ipv6_addresses = peer.ipv6_addresses.clone();
+                existing.instance_id = peer.instance_id.clone();
+                found = true;
+                break;
+            }
+        }
+        
+        if !found {
+            peers_guard.push(peer.clone());
+            drop(peers_guard);
+            
+            // Notify callback
+            if let Some(ref cb) = callback {
+                cb(peer);
+            }
+        }
+    }
+    
+    /// mDNS loop - announce ourselves, answer queries and collect peers
+    fn mdns_loop(
+        socket: StdUdpSocket,
+        running: Arc<AtomicBool>,
+        announcement: MdnsAnnouncement,
+        peers: Arc<parking_lot::RwLock<Vec<DiscoveredPeer>>>,
+        callback: PeerCallback,
+    ) {
+        let group = SocketAddr::new(IpAddr::V4(MDNS_ADDR), MDNS_PORT);
+        let response = announcement.serialize();
+        let query = mdns_query();
+        
+        let mut buffer = [0u8; 1500];
+        let mut queries_sent = 0u32;
+        let mut last_beacon: Option<Instant> = None;
+        
+        while running.load(Ordering::Relaxed) {
+            let due = last_beacon
+                .map(|t| t.elapsed() >= Duration::from_millis(BEACON_INTERVAL_MS))
+                .unwrap_or(true);
+            if due {
+                if queries_sent < MDNS_INITIAL_QUERIES {
+                    packet_log::record(Direction::Sent, group, &query);
+                    let _ = socket.send_to(&query, group);
+                    queries_sent += 1;
+                }
+                packet_log::record(Direction::Sent, group, &response);
+                let _ = socket.send_to(&response, group);
+                last_beacon = Some(Instant::now());
+            }
+            
+            match socket.recv_from(&mut buffer) {
+                Ok((size, addr)) => {
+                    packet_log::record(Direction::Received, addr, &buffer[..size]);
+                    if let Some(message) = parse_mdns_message(&buffer[..size]) {
+                        if !message.is_response && message.queries_service {
+                            packet_log::record(Direction::Sent, group, &response);
+                            let _ = socket.send_to(&response, group);
+                        }
+                        
+                        for found in message.announcements {
+                            if found.instance_id == announcement.instance_id {
+                                continue;
+                            }
+                            
+                            let ip = found.address.map(IpAddr::V4).unwrap_or(addr.ip());
+                            let peer = DiscoveredPeer {
+                                address: SocketAddr::new(ip, addr.port()),
+                                audio_port: found.audio_port,
+                                name: found.instance,
+                                is_sender: found.is_sender,
+                                last_seen: Instant::now(),
+                                ipv6_addresses: found.address_v6.into_iter().collect(),
+                                instance_id: found.instance_id,
+                            };
+                            Self::register_peer(&peers, &callback, peer);
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+            
+            // Clean up stale peers (not seen for 10 seconds)
+            let mut peers_guard = peers.write();
+            peers_guard.retain(|p| p.last_seen.elapsed() < Duration::from_secs(10));
+        }
+    }
+    
     /// Beacon loop - broadcast presence periodically
     fn beacon_loop(
         socket: StdUdpSocket,
@@ -417,6 +1106,7 @@ impl DiscoveryService {
         is_sender: bool,
         audio_port: u16,
         name: String,
+        instance_id: String,
     ) {
         let packet_type = if is_sender {
             DiscoveryPacketType::SenderBeacon
@@ -424,7 +1114,9 @@ impl DiscoveryService {
             DiscoveryPacketType::ReceiverBeacon
         };
         
-        let packet = DiscoveryPacket::new(packet_type, audio_port, name);
+        let packet = DiscoveryPacket::new(packet_type, audio_port, name)
+            .with_ipv6_addresses(get_local_ipv6_addresses())
+            .with_instance_id(instance_id);
         let data = packet.serialize();
         
         let broadcasts = get_broadcast_addresses();
@@ -433,6 +1125,7 @@ impl DiscoveryService {
             // Send beacon to all broadcast addresses
             for broadcast in &broadcasts {
                 let addr = SocketAddr::new(IpAddr::V4(*broadcast), DISCOVERY_PORT);
+                packet_log::record(Direction::Sent, addr, &data);
                 let _ = socket.send_to(&data, addr);
             }
             
@@ -445,13 +1138,14 @@ impl DiscoveryService {
         socket: StdUdpSocket,
         running: Arc<AtomicBool>,
         peers: Arc<parking_lot::RwLock<Vec<DiscoveredPeer>>>,
-        callback: Option<Arc<dyn Fn(DiscoveredPeer) + Send + Sync>>,
+        callback: PeerCallback,
     ) {
         let mut buffer = [0u8; 512];
         
         while running.load(Ordering::Relaxed) {
             match socket.recv_from(&mut buffer) {
                 Ok((size, addr)) => {
+                    packet_log::record(Direction::Received, addr, &buffer[..size]);
                     if let Some(packet) = DiscoveryPacket::deserialize(&buffer[..size]) {
                         let is_sender = matches!(
                             packet.packet_type,
@@ -464,30 +1158,12 @@ impl DiscoveryService {
                             name: packet.name,
                             is_sender,
                             last_seen: Instant::now(),
+                            ipv6_addresses: packet.ipv6_addresses,
+                            instance_id: packet.instance_id,
                         };
                         
                         // Update or add peer
-                        let mut peers_guard = peers.write();
-                        let mut found = false;
-                        for existing in peers_guard.iter_mut() {
-                            if existing.address.ip() == addr.ip() && existing.is_sender == is_sender {
-                                existing.last_seen = Instant::now();
-                                existing.audio_port = peer.audio_port;
-                                existing.name = peer.name.clone();
-                                found = true;
-                                break;
-                            }
-                        }
-                        
-                        if !found {
-                            peers_guard.push(peer.clone());
-                            drop(peers_guard);
-                            
-                            // Notify callback
-                            if let Some(ref cb) = callback {
-                                cb(peer);
-                            }
-                        }
+                        Self::register_peer(&peers, &callback, peer);
                     }
                 }
                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
@@ -515,6 +1191,10 @@ impl DiscoveryService {
         if let Some(handle) = self.listener_handle.take() {
             let _ = handle.join();
         }
+        
+        if let Some(handle) = self.mdns_handle.take() {
+            let _ = handle.join();
+        }
     }
     
     /// Get discovered peers
@@ -540,6 +1220,20 @@ impl DiscoveryService {
             .collect()
     }
     
+    /// Other audio addresses of `peer`: the same instance discovered over
+    /// another network
+    pub fn redundant_paths(&self, peer: &DiscoveredPeer) -> Vec<SocketAddr> {
+        if peer.instance_id.is_empty() {
+            return Vec::new();
+        }
+        self.peers.read()
+            .iter()
+            .filter(|p| p.instance_id == peer.instance_id && p.is_sender == peer.is_sender)
+            .map(|p| p.audio_address())
+            .filter(|addr| *addr != peer.audio_address())
+            .collect()
+    }
+    
     /// Wait for a peer of the specified type
     pub fn wait_for_peer(&self, is_sender: bool, timeout: Duration) -> Option<DiscoveredPeer> {
         let start = Instant::now();
@@ -608,6 +1302,88 @@ mod tests {
         assert_eq!(parsed.packet_type, DiscoveryPacketType::SenderBeacon);
         assert_eq!(parsed.audio_port, 5000);
         assert_eq!(parsed.name, "Test Sender");
+        assert!(parsed.ipv6_addresses.is_empty());
+        assert!(parsed.instance_id.is_empty());
+    }
+    
+    #[test]
+    fn test_discovery_packet_instance_id() {
+        let packet = DiscoveryPacket::new(DiscoveryPacketType::ReceiverBeacon, 5000, "Rx".to_string())
+            .with_instance_id("3f2a9c0d41be".to_string());
+        let parsed = DiscoveryPacket::deserialize(&packet.serialize()).unwrap();
+        assert!(parsed.ipv6_addresses.is_empty());
+        assert_eq!(parsed.instance_id, "3f2a9c0d41be");
+        
+        let v6: Ipv6Addr = "fd00::10".parse().unwrap();
+        let parsed = DiscoveryPacket::deserialize(&packet.with_ipv6_addresses(vec![v6]).serialize()).unwrap();
+        assert_eq!(parsed.ipv6_addresses, vec![v6]);
+        assert_eq!(parsed.instance_id, "3f2a9c0d41be");
+    }
+    
+    #[test]
+    fn test_discovery_packet_ipv6() {
+        let addresses: Vec<Ipv6Addr> = vec!["fd00::10".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        let packet = DiscoveryPacket::new(DiscoveryPacketType::ReceiverBeacon, 5000, "Rx".to_string())
+            .with_ipv6_addresses(addresses.clone());
+        
+        let parsed = DiscoveryPacket::deserialize(&packet.serialize()).unwrap();
+        assert_eq!(parsed.name, "Rx");
+        assert_eq!(parsed.ipv6_addresses, addresses);
+        
+        let peer = DiscoveredPeer {
+            address: "192.168.1.5:5001".parse().unwrap(),
+            audio_port: 5000,
+            name: parsed.name,
+            is_sender: false,
+            last_seen: Instant::now(),
+            ipv6_addresses: parsed.ipv6_addresses,
+            instance_id: parsed.instance_id,
+        };
+        assert_eq!(peer.audio_address_v6(), Some("[fd00::10]:5000".parse().unwrap()));
+    }
+    
+    #[test]
+    fn test_parse_interface_address() {
+        assert_eq!(parse_interface_address("192.168.1.2/24"), Some("192.168.1.2".parse().unwrap()));
+        assert_eq!(parse_interface_address("fe80::1%en0"), Some("fe80::1".parse().unwrap()));
+        assert_eq!(parse_interface_address(" 2001:db8::5(Preferred) "), Some("2001:db8::5".parse().unwrap()));
+        assert!(parse_interface_address("prefixlen").is_none());
+        assert!(is_ipv6_link_local(&"fe80::1".parse().unwrap()));
+        assert!(!is_ipv6_link_local(&"fd00::1".parse().unwrap()));
+    }
+    
+    #[test]
+    fn test_mdns_announcement_roundtrip() {
+        let announcement = MdnsAnnouncement {
+            instance: "Studio B.mixer".to_string(),
+            instance_id: "abc123".to_string(),
+            audio_port: 5002,
+            is_sender: true,
+            address: Some(Ipv4Addr::new(192, 168, 1, 42)),
+            address_v6: Some("fd00::42".parse().unwrap()),
+        };
+        
+        let message = parse_mdns_message(&announcement.serialize()).unwrap();
+        assert!(message.is_response);
+        assert_eq!(message.announcements, vec![announcement]);
+    }
+    
+    #[test]
+    fn test_mdns_query() {
+        let message = parse_mdns_message(&mdns_query()).unwrap();
+        assert!(!message.is_response);
+        assert!(message.queries_service);
+        assert!(message.announcements.is_empty());
+    }
+    
+    #[test]
+    fn test_mdns_malformed() {
+        // Truncated header
+        assert!(parse_mdns_message(&[0, 0, 0]).is_none());
+        
+        // Question whose name is a pointer to itself
+        let looped = [0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 12, 0, 12, 0, 1];
+        assert!(parse_mdns_message(&looped).is_none());
     }
     
     #[test]
@@ -0,0 +1,346 @@
+//! Обратная связь о качестве приёма
+//!
+//! Получатель раз в [`FEEDBACK_INTERVAL`] отправляет источнику аудио
+//! handshake-пакет `Feedback` с потерями и джиттером по каждому треку.
+//! Отправитель складывает отчёты в [`FeedbackInbox`], откуда их забирает
+//! адаптивный регулятор битрейта кодека и контроль перегрузки
+//! (`network::congestion`).
+//!
+//! Формат полезной нагрузки:
+//!
+//! ```text
+//! [COUNT:1] { [TRACK_ID:1][LOSS_PERMILLE:2][JITTER_US:4] } * COUNT
+//! { [QUEUING_DELAY_US:4] } * COUNT
+//! ```
+//!
+//! Задержки в очереди идут после отчётов: получатели старых версий их не
+//! шлют, а старые отправители не читают.
+//!
+//! Отчёты меняют битрейт и приоритеты отправки, поэтому принимаются только
+//! от получателя потока (или доверенного пира рукопожатия), как и другие
+//! управляющие пакеты.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::audio::buffer::JitterBufferStats;
+use crate::network::handshake::{HandshakePacket, HandshakePacketType};
+
+/// Интервал между отчётами получателя
+pub const FEEDBACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Размер одного отчёта в пакете
+const REPORT_SIZE: usize = 7;
+
+/// Интервалов в окне, по минимуму которого оценивается базовая задержка
+const BASE_DELAY_INTERVALS: usize = 10;
+
+/// Отчёт о приёме одного трека за интервал
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackFeedback {
+    pub track_id: u8,
+    /// Доля потерянных пакетов (0.0 - 1.0)
+    pub loss_fraction: f32,
+    /// Оценка джиттера (мкс)
+    pub jitter_us: u32,
+    /// Задержка в очереди: превышение односторонней задержки за интервал
+    /// над её минимумом за последние секунды (мкс). Растёт, когда канал
+    /// не справляется и пакеты копятся в буферах по пути
+    pub queuing_delay_us: u32,
+}
+
+impl TrackFeedback {
+    /// Объединить два отчёта по одному треку (худший из двух, например от разных пиров)
+    pub fn merge(&self, other: &TrackFeedback) -> TrackFeedback {
+        TrackFeedback {
+            track_id: self.track_id,
+            loss_fraction: self.loss_fraction.max(other.loss_fraction),
+            jitter_us: self.jitter_us.max(other.jitter_us),
+            queuing_delay_us: self.queuing_delay_us.max(other.queuing_delay_us),
+        }
+    }
+}
+
+/// Сериализовать отчёты в полезную нагрузку пакета
+pub fn encode_reports(reports: &[TrackFeedback]) -> Bytes {
+    let count = reports.len().min(u8::MAX as usize);
+    let mut buf = BytesMut::with_capacity(1 + count * (REPORT_SIZE + 4));
+    buf.put_u8(count as u8);
+    for report in &reports[..count] {
+        let permille = (report.loss_fraction.clamp(0.0, 1.0) * 1000.0).round() as u16;
+        buf.put_u8(report.track_id);
+        buf.put_u16_le(permille);
+        buf.put_u32_le(report.jitter_us);
+    }
+    for report in &reports[..count] {
+        buf.put_u32_le(report.queuing_delay_us);
+    }
+    buf.freeze()
+}
+
+/// Разобрать полезную нагрузку пакета
+pub fn decode_reports(payload: &[u8]) -> Option<Vec<TrackFeedback>> {
+    let mut buf = payload;
+    if buf.is_empty() {
+        return None;
+    }
+    let count = buf.get_u8() as usize;
+    if buf.len() < count * REPORT_SIZE {
+        return None;
+    }
+
+    let mut reports: Vec<TrackFeedback> = (0..count)
+        .map(|_| {
+            let track_id = buf.get_u8();
+            let permille = buf.get_u16_le();
+            let jitter_us = buf.get_u32_le();
+            TrackFeedback {
+                track_id,
+                loss_fraction: (permille as f32 / 1000.0).min(1.0),
+                jitter_us,
+                queuing_delay_us: 0,
+            }
+        })
+        .collect();
+    if buf.len() >= count * 4 {
+        for report in &mut reports {
+            report.queuing_delay_us = buf.get_u32_le();
+        }
+    }
+    Some(reports)
+}
+
+/// Последние отчёты по трекам на стороне отправителя
+pub struct FeedbackInbox {
+    reports: DashMap<u8, TrackFeedback>,
+}
+
+impl FeedbackInbox {
+    pub fn new() -> Self {
+        Self {
+            reports: DashMap::new(),
+        }
+    }
+
+    /// Обработать handshake-пакет; true если это был Feedback.
+    /// `from_receiver` - пакет пришёл от получателя потока; отчёты от
+    /// других адресов отбрасываются.
+    pub fn handle_packet(&self, data: &[u8], from: SocketAddr, from_receiver: bool) -> bool {
+        let Some(packet) = HandshakePacket::deserialize(data) else {
+            return false;
+        };
+        if packet.packet_type != HandshakePacketType::Feedback {
+            return false;
+        }
+        if !from_receiver {
+            tracing::debug!("Отчёт о приёме не от получателя ({}) отброшен", from);
+            return true;
+        }
+
+        for report in packet.parse_feedback().unwrap_or_default() {
+            self.record(report);
+        }
+        true
+    }
+
+    /// Добавить отчёт; ещё не забранный отчёт объединяется с новым
+    pub fn record(&self, report: TrackFeedback) {
+        self.reports
+            .entry(report.track_id)
+            .and_modify(|existing| *existing = existing.merge(&report))
+            .or_insert(report);
+    }
+
+    /// Забрать накопленный отчёт по треку
+    pub fn take(&self, track_id: u8) -> Option<TrackFeedback> {
+        self.reports.remove(&track_id).map(|(_, report)| report)
+    }
+}
+
+impl Default for FeedbackInbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Потери за интервал по накопительной статистике джиттер-буфера и
+/// задержка в очереди по временам прихода пакетов
+#[derive(Debug, Default)]
+pub struct LossReporter {
+    last_received: usize,
+    last_lost: usize,
+    /// Минимальная относительная задержка (приход минус метка захвата,
+    /// мкс) за текущий интервал; часы пиров не сравниваются, важна только
+    /// её динамика
+    interval_min_delay: Option<i64>,
+    /// Минимумы предыдущих интервалов
+    min_delays: VecDeque<i64>,
+}
+
+impl LossReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Учесть пакет с меткой захвата `capture_us` (часы отправителя),
+    /// пришедший в `arrival_us` (свои часы)
+    pub fn note_arrival(&mut self, capture_us: u64, arrival_us: u64) {
+        let delay = arrival_us as i64 - capture_us as i64;
+        self.interval_min_delay = Some(self.interval_min_delay.map_or(delay, |min| min.min(delay)));
+    }
+    
+    /// Отчёт за время с предыдущего вызова
+    pub fn report(&mut self, track_id: u8, stats: &JitterBufferStats) -> Option<TrackFeedback> {
+        // Восстановленные через FEC кадры тоже потеряны сетью
+        let total_lost = stats.lost + stats.recovered;
+
+        // Счётчики сбрасываются при пересоздании буфера
+        if stats.received < self.last_received || total_lost < self.last_lost {
+            self.last_received = 0;
+            self.last_lost = 0;
+        }
+
+        let received = stats.received - self.last_received;
+        let lost = total_lost - self.last_lost;
+        self.last_received = stats.received;
+        self.last_lost = total_lost;
+
+        let queuing_delay_us = self.queuing_delay_us();
+        if received + lost == 0 {
+            return None;
+        }
+
+        Some(TrackFeedback {
+            track_id,
+            loss_fraction: lost as f32 / (received + lost) as f32,
+            jitter_us: stats.jitter_us as u32,
+            queuing_delay_us,
+        })
+    }
+
+    /// Минимум задержки за интервал над базовой (минимумом окна)
+    ///
+    /// Минимум за интервал не зависит от джиттера; линия с очередью
+    /// задерживает и самые быстрые пакеты.
+    fn queuing_delay_us(&mut self) -> u32 {
+        let Some(delay) = self.interval_min_delay.take() else {
+            return 0;
+        };
+        if self.min_delays.len() == BASE_DELAY_INTERVALS {
+            self.min_delays.pop_front();
+        }
+        self.min_delays.push_back(delay);
+        let base = self.min_delays.iter().copied().min().unwrap_or(delay);
+        (delay - base).clamp(0, u32::MAX as i64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feedback_packet_roundtrip() {
+        let reports = vec![
+            TrackFeedback { track_id: 0, loss_fraction: 0.125, jitter_us: 2500, queuing_delay_us: 31_000 },
+            TrackFeedback { track_id: 3, loss_fraction: 0.0, jitter_us: 0, queuing_delay_us: 0 },
+        ];
+        let packet = HandshakePacket::feedback(7, &reports).serialize();
+
+        let inbox = FeedbackInbox::new();
+        let receiver: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        assert!(inbox.handle_packet(&packet, receiver, true));
+        assert!(!inbox.handle_packet(&HandshakePacket::ping(7).serialize(), receiver, true));
+
+        assert_eq!(inbox.take(0), Some(reports[0]));
+        assert_eq!(inbox.take(3), Some(reports[1]));
+        assert_eq!(inbox.take(0), None);
+
+        // Отчёт без задержек в очереди (получатель старой версии)
+        let old = &encode_reports(&reports)[..1 + 2 * REPORT_SIZE];
+        let decoded = decode_reports(old).unwrap();
+        assert_eq!((decoded[0].jitter_us, decoded[0].queuing_delay_us), (2500, 0));
+    }
+
+    #[test]
+    fn test_feedback_from_others_rejected() {
+        let report = TrackFeedback { track_id: 2, loss_fraction: 0.5, jitter_us: 40_000, queuing_delay_us: 90_000 };
+        let packet = HandshakePacket::feedback(7, &[report]).serialize();
+        let stranger: SocketAddr = "192.168.1.66:5000".parse().unwrap();
+
+        let inbox = FeedbackInbox::new();
+        assert!(inbox.handle_packet(&packet, stranger, false));
+        assert_eq!(inbox.take(2), None);
+    }
+
+    #[test]
+    fn test_inbox_keeps_worst_report() {
+        let inbox = FeedbackInbox::new();
+        inbox.record(TrackFeedback { track_id: 1, loss_fraction: 0.02, jitter_us: 9000, queuing_delay_us: 0 });
+        inbox.record(TrackFeedback { track_id: 1, loss_fraction: 0.10, jitter_us: 1000, queuing_delay_us: 5000 });
+
+        let report = inbox.take(1).unwrap();
+        assert_eq!(report.loss_fraction, 0.10);
+        assert_eq!(report.jitter_us, 9000);
+        assert_eq!(report.queuing_delay_us, 5000);
+    }
+
+    #[test]
+    fn test_loss_reporter_interval() {
+        let mut reporter = LossReporter::new();
+        let mut stats = JitterBufferStats {
+            level: 0,
+            capacity: 16,
+            target_delay: 2,
+            received: 90,
+            lost: 6,
+            recovered: 4,
+            concealed: 6,
+            late: 0,
+            out_of_order: 0,
+            jitter_us: 1500.0,
+        };
+        assert_eq!(reporter.report(0, &stats).unwrap().loss_fraction, 0.1);
+
+        // Следующий интервал без потерь
+        stats.received = 190;
+        assert_eq!(reporter.report(0, &stats).unwrap().loss_fraction, 0.0);
+        assert!(reporter.report(0, &stats).is_none());
+    }
+
+    #[test]
+    fn test_queuing_delay() {
+        let mut reporter = LossReporter::new();
+        let mut stats = JitterBufferStats {
+            level: 0,
+            capacity: 16,
+            target_delay: 2,
+            received: 0,
+            lost: 0,
+            recovered: 0,
+            concealed: 0,
+            late: 0,
+            out_of_order: 0,
+            jitter_us: 0.0,
+        };
+        // Часы получателя на 5 с впереди, задержка сети 2 мс с джиттером
+        let offset = 5_000_000;
+        let interval = |reporter: &mut LossReporter, stats: &mut JitterBufferStats, start: u64, extra_us: u64| {
+            for frame in 0..100 {
+                let capture = start + frame * 10_000;
+                reporter.note_arrival(capture, capture + offset + 2_000 + extra_us + (frame % 3) * 700);
+            }
+            stats.received += 100;
+            reporter.report(0, stats).unwrap().queuing_delay_us
+        };
+
+        assert_eq!(interval(&mut reporter, &mut stats, 0, 0), 0);
+        assert_eq!(interval(&mut reporter, &mut stats, 1_000_000, 0), 0);
+        // Очередь в канале: задержка растёт над базовой
+        assert_eq!(interval(&mut reporter, &mut stats, 2_000_000, 30_000), 30_000);
+        assert_eq!(interval(&mut reporter, &mut stats, 3_000_000, 0), 0);
+    }
+}
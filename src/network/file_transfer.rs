@@ -0,0 +1,909 @@
+//! Передача файлов между пирами
+//!
+//! Сохранённую конфигурацию или законченную запись можно переслать на
+//! второй компьютер из веб-интерфейса, даже если у машин нет другого
+//! общего канала. Файл идёт handshake-пакетами по тому же пути, что и
+//! аудио (в том числе по запасному TCP), с подтверждениями и повтором:
+//!
+//! ```text
+//! Отправитель                          Получатель
+//!   │──── FILE_OFFER (имя, размер, SHA-256) ──>│
+//!   │<─── FILE_ACK (0) ────────────────────────│
+//!   │──── FILE_CHUNK (смещение, данные) × окно>│
+//!   │<─── FILE_ACK (принято байт) ─────────────│
+//!   │                 ...                      │
+//!   │<─── FILE_ACK (всё, DONE) ────────────────│  хеш совпал, файл сохранён
+//! ```
+//!
+//! Блоки, не подтверждённые за [`RETRY_INTERVAL`], повторяются с
+//! последнего подтверждённого смещения (go-back-N). Получатель пишет блоки
+//! во временный файл `.part` и переименовывает его, только если SHA-256
+//! совпал.
+//!
+//! С общим ключом (`network.psk`) полезная нагрузка пакетов файлов
+//! шифруется шифром сессии (заголовок пакета аутентифицируется вместе с
+//! ней), повторы отбрасываются. Сопряжённый пир подписывает пакеты
+//! секретом пары (см. `pairing`), и получатель проверяет подпись, а не
+//! адрес. Если сопряжение требуется, неподписанные предложения и блоки
+//! отклоняются; иначе они принимаются от пиров, с которыми завершено
+//! рукопожатие. Остальным отвечает Error, и диск не заполнить с чужого
+//! адреса в сети.
+//!
+//! Форматы полезной нагрузки:
+//!
+//! ```text
+//! FILE_OFFER: [ID:4][SIZE:8][SHA256:32][NAME_LEN:1][NAME]
+//! FILE_CHUNK: [ID:4][OFFSET:8][DATA]
+//! FILE_ACK:   [ID:4][RECEIVED:8][STATUS:1]
+//! ```
+//!
+//! С PSK полезная нагрузка - `[NONCE:12][...][TAG:16]`, с подписью за
+//! пакетом идут `[KEY:8][MAC:16]`.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::network::crypto::PacketCipher;
+use crate::network::handshake::{HandshakeManager, HandshakePacket, HandshakePacketType};
+use crate::network::pairing::Pairing;
+use crate::network::udp::canonical_addr;
+
+/// Данных в одном блоке (пакет помещается в буфер приёма аудио-порта)
+pub const CHUNK_SIZE: usize = 1024;
+
+/// Наибольший передаваемый файл
+pub const MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Неподтверждённых байтов в пути
+const WINDOW_BYTES: u64 = 32 * CHUNK_SIZE as u64;
+
+/// Через сколько без подтверждения блоки повторяются
+pub const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Передача без единого ответа столько времени считается неудачной
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Законченных передач в истории (в каждом направлении)
+const HISTORY: usize = 32;
+
+/// Статусы в FILE_ACK
+const ACK_RECEIVING: u8 = 0;
+const ACK_DONE: u8 = 1;
+const ACK_FAILED: u8 = 2;
+
+/// Предложение файла
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOffer {
+    pub transfer_id: u32,
+    pub size: u64,
+    pub sha256: [u8; 32],
+    pub name: String,
+}
+
+impl FileOffer {
+    pub fn encode(&self) -> Bytes {
+        let name = truncate_utf8(&self.name, u8::MAX as usize);
+        let mut buf = BytesMut::with_capacity(45 + name.len());
+        buf.put_u32_le(self.transfer_id);
+        buf.put_u64_le(self.size);
+        buf.put_slice(&self.sha256);
+        buf.put_u8(name.len() as u8);
+        buf.put_slice(name.as_bytes());
+        buf.freeze()
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let mut buf = payload;
+        if buf.len() < 45 {
+            return None;
+        }
+        let transfer_id = buf.get_u32_le();
+        let size = buf.get_u64_le();
+        let mut sha256 = [0u8; 32];
+        buf.copy_to_slice(&mut sha256);
+        let name_len = buf.get_u8() as usize;
+        let name = std::str::from_utf8(buf.get(..name_len)?).ok()?.to_string();
+        Some(Self {
+            transfer_id,
+            size,
+            sha256,
+            name,
+        })
+    }
+}
+
+/// Подтверждение: сколько байтов файла принято подряд
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAck {
+    pub transfer_id: u32,
+    pub received: u64,
+    status: u8,
+}
+
+impl FileAck {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(13);
+        buf.put_u32_le(self.transfer_id);
+        buf.put_u64_le(self.received);
+        buf.put_u8(self.status);
+        buf.freeze()
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let mut buf = payload;
+        if buf.len() < 13 {
+            return None;
+        }
+        Some(Self {
+            transfer_id: buf.get_u32_le(),
+            received: buf.get_u64_le(),
+            status: buf.get_u8(),
+        })
+    }
+}
+
+/// Сериализовать блок файла
+pub fn encode_chunk(transfer_id: u32, offset: u64, data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(12 + data.len());
+    buf.put_u32_le(transfer_id);
+    buf.put_u64_le(offset);
+    buf.put_slice(data);
+    buf.freeze()
+}
+
+/// Разобрать блок файла: (ID передачи, смещение, данные)
+pub fn decode_chunk(payload: &[u8]) -> Option<(u32, u64, &[u8])> {
+    let mut buf = payload;
+    if buf.len() < 12 {
+        return None;
+    }
+    let transfer_id = buf.get_u32_le();
+    let offset = buf.get_u64_le();
+    Some((transfer_id, offset, buf))
+}
+
+/// Направление передачи
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Sent,
+    Received,
+}
+
+/// Состояние передачи
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    /// Идёт передача
+    Active,
+    /// Файл доставлен и проверен
+    Done,
+    Failed,
+}
+
+/// Передача для веб-интерфейса
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferStatus {
+    pub id: u32,
+    pub direction: TransferDirection,
+    /// Ключ пира (отправка) или адрес источника (приём)
+    pub peer: String,
+    pub name: String,
+    pub size: u64,
+    /// Подтверждено или принято байтов
+    pub transferred: u64,
+    pub state: TransferState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Куда сохранён принятый файл
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+/// Отправляемый файл
+struct Outgoing {
+    peer: String,
+    destination: SocketAddr,
+    offer: FileOffer,
+    data: Bytes,
+    /// Подтверждено получателем
+    acked: u64,
+    /// Следующий отправляемый байт
+    next: u64,
+    offer_acked: bool,
+    /// Когда повторить неподтверждённое
+    retry_at: Instant,
+    last_reply: Instant,
+    state: TransferState,
+    error: Option<String>,
+}
+
+/// Принимаемый файл
+struct Incoming {
+    transfer_id: u32,
+    source: SocketAddr,
+    name: String,
+    size: u64,
+    sha256: [u8; 32],
+    received: u64,
+    writer: Option<BufWriter<File>>,
+    hasher: Sha256,
+    part_path: PathBuf,
+    path: PathBuf,
+    state: TransferState,
+    error: Option<String>,
+}
+
+impl Incoming {
+    fn ack(&self) -> HandshakePacket {
+        let status = match self.state {
+            TransferState::Active => ACK_RECEIVING,
+            TransferState::Done => ACK_DONE,
+            TransferState::Failed => ACK_FAILED,
+        };
+        let ack = FileAck {
+            transfer_id: self.transfer_id,
+            received: self.received,
+            status,
+        };
+        HandshakePacket::file_ack(0, ack)
+    }
+
+    fn fail(&mut self, error: String) {
+        tracing::warn!("Файл {} от {} не принят: {}", self.name, self.source, error);
+        self.writer = None;
+        let _ = std::fs::remove_file(&self.part_path);
+        self.state = TransferState::Failed;
+        self.error = Some(error);
+    }
+
+    /// Дописать блок; блоки не по порядку отбрасываются (их повторят)
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if offset != self.received || self.received + data.len() as u64 > self.size {
+            return;
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(e) = writer.write_all(data) {
+                self.fail(e.to_string());
+                return;
+            }
+        }
+        self.hasher.update(data);
+        self.received += data.len() as u64;
+        if self.received == self.size {
+            self.finish();
+        }
+    }
+
+    /// Проверить хеш и переименовать `.part` в итоговое имя
+    fn finish(&mut self) {
+        let flushed = match self.writer.take() {
+            Some(mut writer) => writer.flush(),
+            None => Ok(()),
+        };
+        if let Err(e) = flushed {
+            return self.fail(e.to_string());
+        }
+        let digest: [u8; 32] = std::mem::take(&mut self.hasher).finalize().into();
+        if digest != self.sha256 {
+            return self.fail("контрольная сумма не совпала".to_string());
+        }
+        if let Err(e) = std::fs::rename(&self.part_path, &self.path) {
+            return self.fail(e.to_string());
+        }
+        tracing::info!("Принят файл {} от {}: {}", self.name, self.source, self.path.display());
+        self.state = TransferState::Done;
+    }
+
+    fn status(&self) -> TransferStatus {
+        TransferStatus {
+            id: self.transfer_id,
+            direction: TransferDirection::Received,
+            peer: self.source.ip().to_string(),
+            name: self.name.clone(),
+            size: self.size,
+            transferred: self.received,
+            state: self.state,
+            error: self.error.clone(),
+            path: (self.state == TransferState::Done).then(|| self.path.clone()),
+        }
+    }
+}
+
+/// Отправляемые и принимаемые файлы (общие для приёмника и отправителей)
+pub struct FileTransfers {
+    /// Куда сохраняются принятые файлы
+    dir: PathBuf,
+    next_id: AtomicU32,
+    outgoing: Mutex<Vec<Outgoing>>,
+    incoming: Mutex<Vec<Incoming>>,
+    /// Шифр сессии для полезной нагрузки (None - без PSK)
+    cipher: RwLock<Option<PacketCipher>>,
+    /// Секреты пар, которыми подписываются и проверяются пакеты
+    pairing: RwLock<Option<Arc<Pairing>>>,
+}
+
+impl FileTransfers {
+    /// Принятые файлы сохраняются в `dir` (создаётся при первом приёме)
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            // Случайное начало: ID не повторяются после перезапуска
+            next_id: AtomicU32::new(rand::random::<u32>() >> 1),
+            outgoing: Mutex::new(Vec::new()),
+            incoming: Mutex::new(Vec::new()),
+            cipher: RwLock::new(None),
+            pairing: RwLock::new(None),
+        }
+    }
+
+    /// Шифровать пакеты шифром сессии и подписывать секретами пар
+    pub fn set_security(&self, cipher: Option<PacketCipher>, pairing: Option<Arc<Pairing>>) {
+        *self.cipher.write() = cipher;
+        *self.pairing.write() = pairing;
+    }
+
+    /// Каталог принятых файлов
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Отправить файл пиру `peer`, аудио которого идёт на `destination`.
+    /// Возвращает ID передачи.
+    pub fn send(&self, peer: &str, destination: SocketAddr, name: &str, data: Bytes) -> Result<u32, String> {
+        let name = sanitize_name(name).ok_or_else(|| format!("Invalid file name: {:?}", name))?;
+        if data.len() as u64 > MAX_FILE_SIZE {
+            return Err(format!("File is larger than {} MB", MAX_FILE_SIZE / (1024 * 1024)));
+        }
+
+        let transfer_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let offer = FileOffer {
+            transfer_id,
+            size: data.len() as u64,
+            sha256: Sha256::digest(&data).into(),
+            name,
+        };
+        tracing::info!("Отправка файла {} ({} байт) пиру {}", offer.name, offer.size, peer);
+
+        let mut outgoing = self.outgoing.lock();
+        prune(&mut outgoing, |transfer| transfer.state);
+        outgoing.push(Outgoing {
+            peer: peer.to_string(),
+            destination: canonical_addr(destination),
+            offer,
+            data,
+            acked: 0,
+            next: 0,
+            offer_acked: false,
+            retry_at: now,
+            last_reply: now,
+            state: TransferState::Active,
+            error: None,
+        });
+        Ok(transfer_id)
+    }
+
+    /// Пакеты, которые пора отправить на `destination`
+    pub fn due_packets(&self, destination: SocketAddr, now: Instant) -> Vec<Bytes> {
+        let destination = canonical_addr(destination);
+        let mut packets = Vec::new();
+
+        for transfer in self.outgoing.lock().iter_mut() {
+            if transfer.destination != destination || transfer.state != TransferState::Active {
+                continue;
+            }
+            if now.saturating_duration_since(transfer.last_reply) >= TRANSFER_TIMEOUT {
+                tracing::warn!("Файл {} не доставлен пиру {}: нет ответа", transfer.offer.name, transfer.peer);
+                transfer.state = TransferState::Failed;
+                transfer.error = Some("no reply from peer".to_string());
+                continue;
+            }
+
+            if now >= transfer.retry_at {
+                transfer.retry_at = now + RETRY_INTERVAL;
+                transfer.next = transfer.acked;
+                // Предложение повторяется и за итогом, если последний ответ потерялся
+                if !transfer.offer_acked || transfer.acked == transfer.offer.size {
+                    packets.push(self.protect(HandshakePacket::file_offer(0, &transfer.offer), destination));
+                }
+            }
+            if !transfer.offer_acked {
+                continue;
+            }
+
+            let size = transfer.offer.size;
+            while transfer.next < size && transfer.next < transfer.acked + WINDOW_BYTES {
+                let start = transfer.next as usize;
+                let end = (start + CHUNK_SIZE).min(size as usize);
+                let chunk = &transfer.data[start..end];
+                let packet = HandshakePacket::file_chunk(0, transfer.offer.transfer_id, transfer.next, chunk);
+                packets.push(self.protect(packet, destination));
+                transfer.next = end as u64;
+            }
+        }
+        packets
+    }
+
+    /// Обработать handshake-пакет; `Some` если это был пакет передачи
+    /// файла (внутри - ответ, который нужно отправить обратно). Файлы
+    /// принимаются от пиров с верной подписью секретом пары, а если
+    /// сопряжение не требуется - и от подключённых; остальным уходит Error.
+    pub fn handle_packet(&self, data: &[u8], from: SocketAddr, handshake: &HandshakeManager) -> Option<Option<Bytes>> {
+        let packet = HandshakePacket::deserialize(data)?;
+        if !matches!(
+            packet.packet_type,
+            HandshakePacketType::FileOffer | HandshakePacketType::FileChunk | HandshakePacketType::FileAck
+        ) {
+            return None;
+        }
+        let from = canonical_addr(from);
+
+        let pairing = self.pairing.read().clone();
+        let signed = pairing.as_ref().and_then(|pairing| pairing.verify_packet(data));
+        let required = pairing.as_ref().is_some_and(|pairing| pairing.is_required());
+        if signed.is_none() && (required || !handshake.is_trusted(&from)) {
+            if packet.packet_type == HandshakePacketType::FileAck {
+                return Some(None);
+            }
+            tracing::debug!("Файл от {} отклонён: пир не подключён", from);
+            return Some(Some(
+                HandshakePacket::error(packet.session_id, "Файлы принимаются только от подключённых пиров").serialize(),
+            ));
+        }
+        let mut packet = match signed {
+            Some(data) => match HandshakePacket::deserialize(data) {
+                Some(packet) => packet,
+                None => return Some(None),
+            },
+            None => packet,
+        };
+        if let Some(cipher) = self.cipher.read().as_ref() {
+            match cipher.open_payload(&packet_aad(&packet), &packet.payload) {
+                Ok(payload) => packet.payload = Bytes::from(payload),
+                Err(e) => {
+                    tracing::debug!("Пакет файла от {} отброшен: {:?}", from, e);
+                    return Some(None);
+                }
+            }
+        }
+
+        match packet.packet_type {
+            HandshakePacketType::FileOffer => {
+                Some(packet.parse_file_offer().map(|offer| self.protect(self.accept(offer, from), from)))
+            }
+            HandshakePacketType::FileChunk => {
+                let Some((transfer_id, offset, chunk)) = packet.parse_file_chunk() else {
+                    return Some(None);
+                };
+                let ack = {
+                    let mut incoming = self.incoming.lock();
+                    let transfer = incoming
+                        .iter_mut()
+                        .find(|transfer| transfer.transfer_id == transfer_id && transfer.source == from);
+                    transfer.map(|transfer| {
+                        if transfer.state == TransferState::Active {
+                            transfer.write(offset, chunk);
+                        }
+                        transfer.ack()
+                    })
+                };
+                Some(ack.map(|ack| self.protect(ack, from)))
+            }
+            _ => {
+                if let Some(ack) = packet.parse_file_ack() {
+                    self.acknowledged(ack, from);
+                }
+                Some(None)
+            }
+        }
+    }
+
+    /// Запечатать полезную нагрузку шифром сессии и подписать пакет
+    /// секретом пары с `peer_addr`, если они есть
+    fn protect(&self, mut packet: HandshakePacket, peer_addr: SocketAddr) -> Bytes {
+        if let Some(cipher) = self.cipher.read().as_ref() {
+            packet.payload = cipher.seal_payload(&packet_aad(&packet), &packet.payload);
+        }
+        let data = packet.serialize();
+        match self.pairing.read().as_ref().and_then(|pairing| pairing.sign_packet(&peer_addr, &data)) {
+            Some(signed) => Bytes::from(signed),
+            None => data,
+        }
+    }
+
+    /// Начать приём предложенного файла (повторное предложение - только ответ)
+    fn accept(&self, offer: FileOffer, from: SocketAddr) -> HandshakePacket {
+        let mut incoming = self.incoming.lock();
+        if let Some(transfer) = incoming
+            .iter()
+            .find(|transfer| transfer.transfer_id == offer.transfer_id && transfer.source == from)
+        {
+            return transfer.ack();
+        }
+        prune(&mut incoming, |transfer| transfer.state);
+
+        let name = sanitize_name(&offer.name).unwrap_or_else(|| "file".to_string());
+        let mut transfer = Incoming {
+            transfer_id: offer.transfer_id,
+            source: from,
+            name: name.clone(),
+            size: offer.size,
+            sha256: offer.sha256,
+            received: 0,
+            writer: None,
+            hasher: Sha256::new(),
+            part_path: PathBuf::new(),
+            path: PathBuf::new(),
+            state: TransferState::Active,
+            error: None,
+        };
+
+        if offer.size > MAX_FILE_SIZE {
+            transfer.fail(format!("размер {} байт больше допустимого", offer.size));
+        } else {
+            transfer.path = unique_path(&self.dir, &name);
+            transfer.part_path = part_path(&transfer.path);
+            let created = std::fs::create_dir_all(&self.dir).and_then(|()| File::create(&transfer.part_path));
+            match created {
+                Ok(file) => {
+                    tracing::info!("Приём файла {} ({} байт) от {}", name, offer.size, from);
+                    transfer.writer = Some(BufWriter::new(file));
+                    if offer.size == 0 {
+                        transfer.finish();
+                    }
+                }
+                Err(e) => transfer.fail(e.to_string()),
+            }
+        }
+
+        let ack = transfer.ack();
+        incoming.push(transfer);
+        ack
+    }
+
+    fn acknowledged(&self, ack: FileAck, from: SocketAddr) {
+        let mut outgoing = self.outgoing.lock();
+        let Some(transfer) = outgoing.iter_mut().find(|transfer| {
+            transfer.offer.transfer_id == ack.transfer_id && transfer.destination == from
+        }) else {
+            return;
+        };
+        if transfer.state != TransferState::Active {
+            return;
+        }
+
+        let now = Instant::now();
+        transfer.last_reply = now;
+        transfer.offer_acked = true;
+        if ack.received > transfer.acked {
+            transfer.acked = ack.received.min(transfer.offer.size);
+            transfer.next = transfer.next.max(transfer.acked);
+            transfer.retry_at = now + RETRY_INTERVAL;
+        }
+        match ack.status {
+            ACK_DONE => {
+                tracing::info!("Файл {} доставлен пиру {}", transfer.offer.name, transfer.peer);
+                transfer.acked = transfer.offer.size;
+                transfer.state = TransferState::Done;
+                transfer.data = Bytes::new();
+            }
+            ACK_FAILED => {
+                tracing::warn!("Пир {} не принял файл {}", transfer.peer, transfer.offer.name);
+                transfer.state = TransferState::Failed;
+                transfer.error = Some("rejected by peer".to_string());
+                transfer.data = Bytes::new();
+            }
+            _ => {}
+        }
+    }
+
+    /// Все передачи: отправленные, затем принятые (новые последними)
+    pub fn transfers(&self) -> Vec<TransferStatus> {
+        let mut transfers: Vec<TransferStatus> = self
+            .outgoing
+            .lock()
+            .iter()
+            .map(|transfer| TransferStatus {
+                id: transfer.offer.transfer_id,
+                direction: TransferDirection::Sent,
+                peer: transfer.peer.clone(),
+                name: transfer.offer.name.clone(),
+                size: transfer.offer.size,
+                transferred: transfer.acked,
+                state: transfer.state,
+                error: transfer.error.clone(),
+                path: None,
+            })
+            .collect();
+        transfers.extend(self.incoming.lock().iter().map(Incoming::status));
+        transfers
+    }
+}
+
+/// Заголовок пакета, аутентифицируемый вместе с запечатанной нагрузкой
+fn packet_aad(packet: &HandshakePacket) -> [u8; 5] {
+    let mut aad = [0u8; 5];
+    aad[0] = packet.packet_type as u8;
+    aad[1..].copy_from_slice(&packet.session_id.to_le_bytes());
+    aad
+}
+
+/// Забыть самые старые законченные передачи сверх [`HISTORY`]
+fn prune<T>(transfers: &mut Vec<T>, state: impl Fn(&T) -> TransferState) {
+    let finished = transfers.iter().filter(|transfer| state(transfer) != TransferState::Active).count();
+    let mut excess = finished.saturating_sub(HISTORY - 1);
+    transfers.retain(|transfer| {
+        if excess > 0 && state(transfer) != TransferState::Active {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+/// Имя файла без каталогов (имена от пира не могут выйти из каталога приёма)
+fn sanitize_name(name: &str) -> Option<String> {
+    let name: String = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+        .collect();
+    let name = truncate_utf8(name.trim(), u8::MAX as usize);
+    (!name.is_empty() && name != "." && name != "..").then(|| name.to_string())
+}
+
+/// Свободное имя в каталоге: "name.ext", "name (1).ext", ...
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let candidate = dir.join(name);
+    if !candidate.exists() && !part_path(&candidate).exists() {
+        return candidate;
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists() && !part_path(path).exists())
+        .unwrap_or(candidate)
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+fn truncate_utf8(text: &str, max_len: usize) -> &str {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PairingConfig;
+    use crate::network::handshake::PeerCapabilities;
+    use crate::network::pairing::Pairing;
+    use std::sync::Arc;
+
+    /// Рукопожатие получателя, завершённое с `peer_addr`
+    fn connected(peer_addr: SocketAddr) -> HandshakeManager {
+        let us = HandshakeManager::new("Studio".to_string(), 5000, PeerCapabilities::full());
+        let peer = HandshakeManager::new("Stage".to_string(), 5000, PeerCapabilities::full());
+        let our_addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        us.handle_packet(&peer.initiate(our_addr).serialize(), peer_addr);
+        assert!(us.is_trusted(&peer_addr));
+        us
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lan-audio-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_transfer_survives_loss() {
+        let sender = FileTransfers::new(temp_dir("unused"));
+        let dir = temp_dir("received");
+        let receiver = FileTransfers::new(dir.clone());
+        let receiver_addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let sender_addr: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let handshake = connected(sender_addr);
+        let sender_handshake = connected(receiver_addr);
+
+        let data: Bytes = (0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>().into();
+        let id = sender.send("192.168.1.20:5000", receiver_addr, "../session.toml", data.clone()).unwrap();
+
+        // Every seventh packet to the receiver is lost
+        let mut now = Instant::now();
+        let mut sent = 0;
+        while sender.transfers()[0].state == TransferState::Active {
+            assert!(sent < 1000, "transfer stalled");
+            for packet in sender.due_packets(receiver_addr, now) {
+                sent += 1;
+                if sent % 7 == 0 {
+                    continue;
+                }
+                if let Some(Some(ack)) = receiver.handle_packet(&packet, sender_addr, &handshake) {
+                    assert_eq!(sender.handle_packet(&ack, receiver_addr, &sender_handshake), Some(None));
+                }
+            }
+            now += RETRY_INTERVAL;
+        }
+
+        let sent = &sender.transfers()[0];
+        assert_eq!((sent.id, sent.state, sent.transferred), (id, TransferState::Done, 10_000));
+        let received = &receiver.transfers()[0];
+        assert_eq!(received.state, TransferState::Done);
+        assert_eq!(received.path.as_deref(), Some(dir.join("session.toml").as_path()));
+        assert_eq!(std::fs::read(dir.join("session.toml")).unwrap(), data.to_vec());
+        assert!(!part_path(&dir.join("session.toml")).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_corrupt_file_is_rejected() {
+        let dir = temp_dir("corrupt");
+        let receiver = FileTransfers::new(dir.clone());
+        let from: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let handshake = connected(from);
+        let offer = FileOffer {
+            transfer_id: 7,
+            size: 4,
+            sha256: Sha256::digest(b"good").into(),
+            name: "take.wav".to_string(),
+        };
+
+        receiver.handle_packet(&HandshakePacket::file_offer(0, &offer).serialize(), from, &handshake);
+        let reply = receiver
+            .handle_packet(&HandshakePacket::file_chunk(0, 7, 0, b"evil").serialize(), from, &handshake)
+            .flatten()
+            .unwrap();
+        let ack = HandshakePacket::deserialize(&reply).unwrap().parse_file_ack().unwrap();
+        assert_eq!((ack.received, ack.status), (4, ACK_FAILED));
+        assert!(!dir.join("take.wav").exists() && !dir.join("take.wav.part").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_unpaired_offer_is_refused() {
+        let dir = temp_dir("unpaired");
+        let receiver = FileTransfers::new(dir.clone());
+        let stranger: SocketAddr = "192.168.1.66:40000".parse().unwrap();
+        let offer = FileOffer {
+            transfer_id: 9,
+            size: 4,
+            sha256: Sha256::digest(b"junk").into(),
+            name: "junk.bin".to_string(),
+        };
+        let is_error = |reply: Option<Option<Bytes>>| {
+            let reply = reply.flatten().unwrap();
+            HandshakePacket::deserialize(&reply).unwrap().packet_type == HandshakePacketType::ErrorPacket
+        };
+
+        // No handshake with the source: neither offer nor chunks are taken
+        let handshake = HandshakeManager::new("Studio".to_string(), 5000, PeerCapabilities::full());
+        let packet = HandshakePacket::file_offer(0, &offer).serialize();
+        assert!(is_error(receiver.handle_packet(&packet, stranger, &handshake)));
+        let chunk = HandshakePacket::file_chunk(0, 9, 0, b"junk").serialize();
+        assert!(is_error(receiver.handle_packet(&chunk, stranger, &handshake)));
+
+        // Connected by our Hello, but not paired while pairing is required
+        let required = PairingConfig { required: true, ..PairingConfig::default() };
+        let pairing = Arc::new(Pairing::new(&required, None));
+        receiver.set_security(None, Some(pairing.clone()));
+        let handshake = HandshakeManager::new("Studio".to_string(), 5000, PeerCapabilities::full())
+            .with_pairing(pairing);
+        let hello = handshake.initiate(stranger);
+        let ack = HandshakePacket::hello_ack(hello.session_id, "Stranger", 5000, PeerCapabilities::full());
+        handshake.handle_packet(&ack.serialize(), stranger);
+        assert!(handshake.is_connected(&stranger) && !handshake.is_trusted(&stranger));
+        assert!(is_error(receiver.handle_packet(&packet, stranger, &handshake)));
+
+        assert!(receiver.transfers().is_empty());
+        assert!(!dir.exists());
+    }
+
+    /// Pairings of a sender and a receiver (which requires pairing),
+    /// paired with a PIN over a handshake
+    fn paired(sender_addr: SocketAddr, receiver_addr: SocketAddr) -> (Arc<Pairing>, Arc<Pairing>) {
+        let required = PairingConfig { required: true, ..PairingConfig::default() };
+        let sender_pairing = Arc::new(Pairing::new(&PairingConfig::default(), None));
+        let receiver_pairing = Arc::new(Pairing::new(&required, None));
+        let sender = HandshakeManager::new("Stage".to_string(), 5000, PeerCapabilities::full())
+            .with_pairing(sender_pairing.clone());
+        let receiver = HandshakeManager::new("Studio".to_string(), 5000, PeerCapabilities::full())
+            .with_pairing(receiver_pairing.clone());
+        sender_pairing.set_peer_pin(receiver_addr, &receiver_pairing.new_pin(Instant::now())).unwrap();
+
+        let mut packet = sender.initiate(receiver_addr).serialize();
+        while let Some(reply) = receiver.handle_packet(&packet, sender_addr).flatten() {
+            match sender.handle_packet(&reply, receiver_addr).flatten() {
+                Some(next) => packet = next,
+                None => break,
+            }
+        }
+        assert!(sender.is_trusted(&receiver_addr) && receiver.is_trusted(&sender_addr));
+        (sender_pairing, receiver_pairing)
+    }
+
+    #[test]
+    fn test_paired_transfer_is_sealed_and_signed() {
+        let receiver_addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let sender_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let (sender_pairing, receiver_pairing) = paired(sender_addr, receiver_addr);
+        let sender = FileTransfers::new(temp_dir("unused-sealed"));
+        sender.set_security(Some(PacketCipher::from_key(&[3; 32])), Some(sender_pairing));
+        let dir = temp_dir("sealed");
+        let receiver = FileTransfers::new(dir.clone());
+        receiver.set_security(Some(PacketCipher::from_key(&[3; 32])), Some(receiver_pairing));
+
+        // No handshake here: the signature vouches for the peer, not its address
+        let handshake = HandshakeManager::new("Studio".to_string(), 5000, PeerCapabilities::full());
+        let data: &[u8] = b"mixer settings that stay off the LAN";
+        sender.send("Studio", receiver_addr, "mix.toml", Bytes::from_static(data)).unwrap();
+        let mut now = Instant::now();
+
+        // An acknowledgement from another port of the receiver's host doesn't count
+        let offer = sender.due_packets(receiver_addr, now).remove(0);
+        let ack = receiver.handle_packet(&offer, sender_addr, &handshake).flatten().unwrap();
+        let other_port: SocketAddr = "192.168.1.20:6000".parse().unwrap();
+        assert_eq!(sender.handle_packet(&ack, other_port, &handshake), Some(None));
+        assert!(sender.due_packets(receiver_addr, now).is_empty());
+
+        let mut rounds = 0;
+        while sender.transfers()[0].state == TransferState::Active {
+            assert!(rounds < 10, "transfer stalled");
+            rounds += 1;
+            now += RETRY_INTERVAL;
+            for packet in sender.due_packets(receiver_addr, now) {
+                assert!(!packet.windows(data.len()).any(|window| window == data));
+
+                // A tampered packet is refused before it is opened
+                let mut forged = packet.to_vec();
+                forged[HandshakePacket::deserialize(&packet).unwrap().payload.len() / 2] ^= 1;
+                let reply = receiver.handle_packet(&forged, sender_addr, &handshake).flatten().unwrap();
+                assert_eq!(HandshakePacket::deserialize(&reply).unwrap().packet_type, HandshakePacketType::ErrorPacket);
+
+                if let Some(Some(ack)) = receiver.handle_packet(&packet, sender_addr, &handshake) {
+                    sender.handle_packet(&ack, receiver_addr, &handshake);
+                }
+            }
+        }
+        assert_eq!(sender.transfers()[0].state, TransferState::Done);
+        assert_eq!(std::fs::read(dir.join("mix.toml")).unwrap(), data);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sanitize_and_unique_names() {
+        assert_eq!(sanitize_name("C:\\Users\\me\\config.toml").as_deref(), Some("config.toml"));
+        assert_eq!(sanitize_name("/etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_name(".."), None);
+        assert_eq!(sanitize_name("dir/"), None);
+
+        let dir = temp_dir("names");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("take.wav"), b"").unwrap();
+        assert_eq!(unique_path(&dir, "take.wav"), dir.join("take (1).wav"));
+        assert_eq!(unique_path(&dir, "other"), dir.join("other"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -19,21 +19,97 @@
 //!   │                                 │
 //!   │<─── SYNC_RESPONSE (tracks) ────│
 //!   │                                 │
+//!   │──── SUBSCRIBE (выбранные треки)>│  только нужные получателю треки
+//!   │                                 │
+//!   │<─── TRACK_ADDED / TRACK_REMOVED │  треки отправителя изменились
+//!   │                                 │
 //!   │<───── AUDIO STREAMING ────────>│
 //!   │                                 │
+//!   │<──── PING (t0) / PONG (t0,t1,t2)│  синхронизация часов
+//!   │                                 │
+//!   │<──── FEEDBACK (потери, джиттер) │  адаптивный битрейт
+//!   │                                 │
+//!   │──── RESYNC ───────────────────>│  выход из сна: сброс буферов
+//!   │                                 │
+//!   │──── FILE_OFFER / FILE_CHUNK ──>│  передача файла (см. `file_transfer`)
+//!   │<─── FILE_ACK ──────────────────│
+//!   │                                 │
+//!   │──── PROBE_REQUEST × N ────────>│  тест связи (см. `link_test`)
+//!   │<─── PROBE_ECHO × N ────────────│
+//!   │                                 │
+//!   │──── PING ─────────────────────>│  keepalive каждую секунду
+//!   │<─── PONG ──────────────────────│
+//!   │                                 │
+//!   │──── GOODBYE ──────────────────>│  отключение: пир сразу освобождает треки
 //! ```
+//!
+//! Пир отправляет аудио другому пиру только после HELLO/HELLO_ACK: так
+//! несовместимые пиры (другой ключ шифрования, два отправителя) не
+//! получают поток, который не смогут воспроизвести.
+//!
+//! Упавший процесс не присылает GOODBYE, поэтому подключённые пиры
+//! пингуются (`due_keepalives`): после [`MAX_MISSED_PONGS`] пингов без
+//! ответа подряд пир считается потерянным. Подключения, потери и уходы
+//! пиров забираются приложением через `take_events`.
+//!
+//! Короткий обрыв сети (пропал Wi-Fi) не рвёт соединение: после
+//! [`INTERRUPT_AFTER_MISSED`] пингов без ответа связь считается прерванной
+//! (`Interrupted`: приложение приостанавливает отправку пиру), первый же
+//! ответ её восстанавливает (`Resumed`: потоки начинаются заново). Пиру,
+//! потерянному совсем, Hello повторяется с растущим интервалом
+//! (`hello_due`), пока он не ответит.
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crate::codec::dred;
+use crate::codec::encoder::FRAME_DURATIONS_MS;
+use crate::codec::multistream::MAX_SURROUND_CHANNELS;
+use crate::constants::{DEFAULT_CHANNELS, DEFAULT_FRAME_SIZE_MS};
+use crate::network::feedback::{decode_reports, encode_reports, TrackFeedback};
+use crate::network::file_transfer::{decode_chunk, encode_chunk, FileAck, FileOffer};
+use crate::network::pairing::{HelloCheck, Pairing, PairingChallenge, PairingHello};
+use crate::network::subscription::Subscription;
+use crate::network::timesync::{media_time_us, respond_to_ping};
+use crate::network::udp::canonical_addr;
+use crate::protocol::{Codec, RemoteCapabilities, TrackConfig};
+
 /// Магические байты для пакетов рукопожатия
 const HANDSHAKE_MAGIC: &[u8; 4] = b"LAHS"; // LAN Audio HandShake
 
 /// Версия протокола
 const PROTOCOL_VERSION: u8 = 1;
 
+/// Заголовок пакета: магия, версия, тип и ID сессии
+pub const HEADER_SIZE: usize = 10;
+
+/// Hello без ответа повторяется через это время
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Повтор Hello при рукопожатии до запуска отправителя (`connect`)
+const HELLO_RETRY: Duration = Duration::from_millis(500);
+
+/// Пир, отклонивший рукопожатие, снова получает Hello через это время
+const FAILED_RETRY: Duration = Duration::from_secs(60);
+
+/// Интервал keepalive-пингов подключённым пирам
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Пингов без ответа подряд, после которых пир считается потерянным
+pub const MAX_MISSED_PONGS: u32 = 5;
+
+/// Пингов без ответа подряд, после которых связь с пиром считается прерванной
+pub const INTERRUPT_AFTER_MISSED: u32 = 2;
+
+/// Первый интервал повтора Hello потерянному пиру (дальше удваивается)
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Наибольший интервал повтора Hello потерянному пиру
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
 /// Типы пакетов рукопожатия
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -52,6 +128,28 @@ pub enum HandshakePacketType {
     Pong = 0x06,
     /// Уведомление об отключении
     Goodbye = 0x07,
+    /// Отчёт получателя о потерях и джиттере по трекам
+    Feedback = 0x08,
+    /// Отправитель проснулся после сна: получатель сбрасывает буферы
+    Resync = 0x09,
+    /// Получатель выбирает треки, которые хочет принимать
+    Subscribe = 0x0A,
+    /// Предложение файла: имя, размер и SHA-256
+    FileOffer = 0x0B,
+    /// Блок данных файла
+    FileChunk = 0x0C,
+    /// Подтверждение принятых байтов файла
+    FileAck = 0x0D,
+    /// Отправитель добавил или перенастроил трек
+    TrackAdded = 0x0E,
+    /// Отправитель удалил трек
+    TrackRemoved = 0x0F,
+    /// Зонд теста связи: номер, время отправки и заполнение до размера
+    ProbeRequest = 0x10,
+    /// Ответ на зонд того же размера с временем приёма
+    ProbeEcho = 0x11,
+    /// Вызов сопряжения: Hello нужно повторить с подтверждением
+    PairingChallenge = 0x12,
     /// Уведомление об ошибке
     ErrorPacket = 0xFF,
 }
@@ -68,6 +166,17 @@ impl TryFrom<u8> for HandshakePacketType {
             0x05 => Ok(Self::Ping),
             0x06 => Ok(Self::Pong),
             0x07 => Ok(Self::Goodbye),
+            0x08 => Ok(Self::Feedback),
+            0x09 => Ok(Self::Resync),
+            0x0A => Ok(Self::Subscribe),
+            0x0B => Ok(Self::FileOffer),
+            0x0C => Ok(Self::FileChunk),
+            0x0D => Ok(Self::FileAck),
+            0x0E => Ok(Self::TrackAdded),
+            0x0F => Ok(Self::TrackRemoved),
+            0x10 => Ok(Self::ProbeRequest),
+            0x11 => Ok(Self::ProbeEcho),
+            0x12 => Ok(Self::PairingChallenge),
             0xFF => Ok(Self::ErrorPacket),
             _ => Err(()),
         }
@@ -87,8 +196,20 @@ pub struct PeerCapabilities {
     pub supports_fec: bool,
     /// Поддерживает стерео
     pub supports_stereo: bool,
+    /// Декодирует глубокую избыточность Opus (DRED, libopus >= 1.5)
+    pub supports_dred: bool,
+    /// Декодирует lossless-треки (FLAC)
+    pub supports_flac: bool,
     /// Максимальное количество треков
     pub max_tracks: u8,
+    /// Сколько каналов воспроизводит трек (больше 2 - surround, Opus multistream)
+    pub max_channels: u8,
+    /// Аудио шифруется общим ключом (PSK)
+    pub encryption: bool,
+    /// Отпечаток ключа (`PacketCipher::fingerprint`), 0 без шифрования
+    pub key_fingerprint: u32,
+    /// UDP-порт, на котором пир принимает аудио по QUIC (см. `network::quic`)
+    pub quic_port: Option<u16>,
 }
 
 impl PeerCapabilities {
@@ -100,7 +221,13 @@ impl PeerCapabilities {
             supports_opus: true,
             supports_fec: true,
             supports_stereo: true,
+            supports_dred: dred::is_available(),
+            supports_flac: true,
             max_tracks: 16,
+            max_channels: MAX_SURROUND_CHANNELS as u8,
+            encryption: false,
+            key_fingerprint: 0,
+            quic_port: None,
         }
     }
     
@@ -112,7 +239,13 @@ impl PeerCapabilities {
             supports_opus: true,
             supports_fec: true,
             supports_stereo: true,
+            supports_dred: dred::is_available(),
+            supports_flac: true,
             max_tracks: 16,
+            max_channels: DEFAULT_CHANNELS as u8,
+            encryption: false,
+            key_fingerprint: 0,
+            quic_port: None,
         }
     }
     
@@ -124,11 +257,40 @@ impl PeerCapabilities {
             supports_opus: true,
             supports_fec: true,
             supports_stereo: true,
+            supports_dred: dred::is_available(),
+            supports_flac: true,
             max_tracks: 16,
+            max_channels: MAX_SURROUND_CHANNELS as u8,
+            encryption: false,
+            key_fingerprint: 0,
+            quic_port: None,
         }
     }
     
-    /// Сериализовать в байты
+    /// Общие возможности получателей: элемент доступен, только если его
+    /// поддерживают все
+    pub fn combine<'a>(receivers: impl IntoIterator<Item = &'a PeerCapabilities>) -> RemoteCapabilities {
+        receivers.into_iter().fold(RemoteCapabilities::default(), |mut combined, caps| {
+            combined.peers += 1;
+            combined.stereo &= caps.supports_stereo;
+            combined.fec &= caps.supports_fec;
+            combined.dred &= caps.supports_dred;
+            combined.flac &= caps.supports_flac;
+            combined.max_tracks = combined.max_tracks.min(caps.max_tracks);
+            combined.max_channels = combined.max_channels.min(caps.max_channels);
+            combined
+        })
+    }
+    
+    /// Включить шифрование с ключом, имеющим данный отпечаток
+    pub fn with_encryption(mut self, key_fingerprint: u32) -> Self {
+        self.encryption = true;
+        self.key_fingerprint = key_fingerprint;
+        self
+    }
+    
+    /// Сериализовать в байты (отпечаток ключа и порт QUIC передаются
+    /// отдельно в Hello, порт QUIC и число каналов - в SyncRequest)
     pub fn to_bytes(&self) -> [u8; 2] {
         let mut flags = 0u8;
         if self.can_send { flags |= 0x01; }
@@ -136,24 +298,39 @@ impl PeerCapabilities {
         if self.supports_opus { flags |= 0x04; }
         if self.supports_fec { flags |= 0x08; }
         if self.supports_stereo { flags |= 0x10; }
+        if self.encryption { flags |= 0x20; }
+        if self.supports_dred { flags |= 0x40; }
+        if self.supports_flac { flags |= 0x80; }
         
         [flags, self.max_tracks]
     }
     
-    /// Десериализовать из байтов
+    /// Десериализовать из байтов (третий и четвёртый байты - порт QUIC,
+    /// 0 или без них - без QUIC; пятый - число каналов, без него пир
+    /// воспроизводит моно или стерео)
     pub fn from_bytes(data: &[u8]) -> Option<Self> {
         if data.len() < 2 {
             return None;
         }
         
         let flags = data[0];
+        let stereo_channels = if flags & 0x10 != 0 { 2 } else { 1 };
         Some(Self {
             can_send: flags & 0x01 != 0,
             can_receive: flags & 0x02 != 0,
             supports_opus: flags & 0x04 != 0,
             supports_fec: flags & 0x08 != 0,
             supports_stereo: flags & 0x10 != 0,
+            supports_dred: flags & 0x40 != 0,
+            supports_flac: flags & 0x80 != 0,
             max_tracks: data[1],
+            max_channels: data.get(4).copied().unwrap_or(stereo_channels),
+            encryption: flags & 0x20 != 0,
+            key_fingerprint: 0,
+            quic_port: data
+                .get(2..4)
+                .map(|port| u16::from_le_bytes([port[0], port[1]]))
+                .filter(|&port| port != 0),
         })
     }
     
@@ -166,12 +343,28 @@ impl PeerCapabilities {
         // Оба должны поддерживать Opus
         let codec_compatible = self.supports_opus && other.supports_opus;
         
-        can_stream && codec_compatible
+        can_stream && codec_compatible && self.is_encryption_compatible_with(other)
+    }
+    
+    /// Использовать ли DRED для треков, отправляемых этому пиру: оба
+    /// должны поддерживать (старые пиры игнорируют DRED, но биты тратятся зря)
+    pub fn uses_dred_with(&self, other: &Self) -> bool {
+        self.supports_dred && other.supports_dred
+    }
+    
+    /// Проверить согласованность шифрования: либо оба без шифрования,
+    /// либо оба с одним и тем же ключом
+    pub fn is_encryption_compatible_with(&self, other: &Self) -> bool {
+        match (self.encryption, other.encryption) {
+            (false, false) => true,
+            (true, true) => self.key_fingerprint == other.key_fingerprint,
+            _ => false,
+        }
     }
 }
 
 /// Информация о треке для синхронизации
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TrackInfo {
     /// ID трека
     pub track_id: u8,
@@ -183,9 +376,91 @@ pub struct TrackInfo {
     pub channels: u16,
     /// Включён FEC
     pub fec_enabled: bool,
+    /// Трек отправляется без шифрования (см. `TrackConfig::plaintext`)
+    pub plaintext: bool,
+    /// Кодек трека
+    pub codec: Codec,
+    /// Длительность кадров трека в мкс: по ней получатель настраивает
+    /// декодер и джиттер-буфер
+    pub frame_duration_us: u32,
+}
+
+/// Флаги трека в `TrackInfo` (старые версии знали только FEC = 1)
+const TRACK_FLAG_FEC: u8 = 0x01;
+const TRACK_FLAG_PLAINTEXT: u8 = 0x02;
+/// Биты 2-3: id кодека (`Codec::id`, 0 - Opus)
+const TRACK_CODEC_MASK: u8 = 0x0C;
+const TRACK_CODEC_SHIFT: u8 = 2;
+/// Биты 4-6: номер длительности кадра в `FRAME_DURATIONS_MS` плюс один
+/// (0 - старый отправитель, кадры по `DEFAULT_FRAME_SIZE_MS`)
+const TRACK_FRAME_MASK: u8 = 0x70;
+const TRACK_FRAME_SHIFT: u8 = 4;
+
+/// Номер длительности кадра для флагов трека (0 - нестандартная)
+fn frame_duration_code(frame_duration_us: u32) -> u8 {
+    FRAME_DURATIONS_MS
+        .iter()
+        .position(|&ms| (ms * 1000.0) as u32 == frame_duration_us)
+        .map_or(0, |index| index as u8 + 1)
+}
+
+/// Длительность кадра (мкс) по номеру из флагов трека
+fn frame_duration_from_code(code: u8) -> u32 {
+    let ms = match code {
+        1..=6 => FRAME_DURATIONS_MS[code as usize - 1],
+        _ => DEFAULT_FRAME_SIZE_MS,
+    };
+    (ms * 1000.0) as u32
+}
+
+/// Флаг `SyncRequest`: получатель принимает треки без шифрования
+const SYNC_ACCEPTS_PLAINTEXT: u8 = 0x01;
+
+/// Полезная нагрузка зонда без заполнения: `[SEQ:4][T0:8][T1:8]`
+const PROBE_SIZE: usize = 20;
+
+/// Нагрузка зонда, дополненная нулями до `size` байт пакета
+fn encode_probe(seq: u32, t0: u64, t1: u64, size: usize) -> Bytes {
+    let len = size.saturating_sub(HEADER_SIZE).max(PROBE_SIZE);
+    let mut payload = BytesMut::with_capacity(len);
+    payload.put_u32_le(seq);
+    payload.put_u64_le(t0);
+    payload.put_u64_le(t1);
+    payload.resize(len, 0);
+    payload.freeze()
 }
 
 impl TrackInfo {
+    /// Информация о треке с данной конфигурацией
+    pub fn from_config(track_id: u8, config: &TrackConfig) -> Self {
+        Self {
+            track_id,
+            name: config.name.clone(),
+            bitrate: config.bitrate,
+            channels: config.channels,
+            fec_enabled: config.fec_enabled,
+            plaintext: config.plaintext,
+            codec: config.codec,
+            frame_duration_us: (config.frame_size_ms * 1000.0) as u32,
+        }
+    }
+    
+    /// Конфигурация выходного трека, принимающего этот трек на `device_id`
+    pub fn output_config(&self, device_id: String) -> TrackConfig {
+        TrackConfig {
+            track_id: Some(self.track_id),
+            name: self.name.clone(),
+            device_id,
+            bitrate: self.bitrate,
+            channels: self.channels,
+            fec_enabled: self.fec_enabled,
+            plaintext: self.plaintext,
+            codec: self.codec,
+            frame_size_ms: self.frame_duration_us as f32 / 1000.0,
+            ..Default::default()
+        }
+    }
+    
     /// Сериализовать в байты
     pub fn serialize(&self) -> Vec<u8> {
         let name_bytes = self.name.as_bytes();
@@ -195,7 +470,12 @@ impl TrackInfo {
         buf.push(self.track_id);
         buf.extend_from_slice(&self.bitrate.to_le_bytes());
         buf.extend_from_slice(&self.channels.to_le_bytes());
-        buf.push(if self.fec_enabled { 1 } else { 0 });
+        let mut flags = 0u8;
+        if self.fec_enabled { flags |= TRACK_FLAG_FEC; }
+        if self.plaintext { flags |= TRACK_FLAG_PLAINTEXT; }
+        flags |= self.codec.id() << TRACK_CODEC_SHIFT;
+        flags |= frame_duration_code(self.frame_duration_us) << TRACK_FRAME_SHIFT;
+        buf.push(flags);
         buf.push(name_len);
         buf.extend_from_slice(&name_bytes[..name_len as usize]);
         
@@ -204,14 +484,18 @@ impl TrackInfo {
     
     /// Десериализовать из байтов
     pub fn deserialize(data: &[u8]) -> Option<(Self, usize)> {
-        if data.len() < 8 {
+        if data.len() < 9 {
             return None;
         }
         
         let track_id = data[0];
         let bitrate = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
         let channels = u16::from_le_bytes([data[5], data[6]]);
-        let fec_enabled = data[7] != 0;
+        let fec_enabled = data[7] & TRACK_FLAG_FEC != 0;
+        let plaintext = data[7] & TRACK_FLAG_PLAINTEXT != 0;
+        // Неизвестный кодек: пакеты трека всё равно отбрасываются при приёме
+        let codec = Codec::from_id((data[7] & TRACK_CODEC_MASK) >> TRACK_CODEC_SHIFT).unwrap_or_default();
+        let frame_duration_us = frame_duration_from_code((data[7] & TRACK_FRAME_MASK) >> TRACK_FRAME_SHIFT);
         let name_len = data[8] as usize;
         
         if data.len() < 9 + name_len {
@@ -227,6 +511,9 @@ impl TrackInfo {
                 bitrate,
                 channels,
                 fec_enabled,
+                plaintext,
+                codec,
+                frame_duration_us,
             },
             9 + name_len,
         ))
@@ -250,11 +537,19 @@ impl HandshakePacket {
         let name_bytes = name.as_bytes();
         let name_len = name_bytes.len().min(255) as u8;
         
-        let mut payload = BytesMut::with_capacity(5 + name_len as usize);
+        let mut payload = BytesMut::with_capacity(9 + name_len as usize);
         payload.put_slice(&audio_port.to_le_bytes());
         payload.put_slice(&capabilities.to_bytes());
         payload.put_u8(name_len);
         payload.put_slice(&name_bytes[..name_len as usize]);
+        // Отпечаток ключа и порт QUIC - необязательные хвосты (между ними
+        // встаёт хвост сопряжения, см. `with_pairing`)
+        if capabilities.encryption {
+            payload.put_u32_le(capabilities.key_fingerprint);
+        }
+        if let Some(port) = capabilities.quic_port {
+            payload.put_u16_le(port);
+        }
         
         Self {
             packet_type: HandshakePacketType::Hello,
@@ -270,7 +565,7 @@ impl HandshakePacket {
         }
         
         let audio_port = u16::from_le_bytes([self.payload[0], self.payload[1]]);
-        let capabilities = PeerCapabilities::from_bytes(&self.payload[2..4])?;
+        let mut capabilities = PeerCapabilities::from_bytes(&self.payload[2..4])?;
         let name_len = self.payload[4] as usize;
         
         if self.payload.len() < 5 + name_len {
@@ -279,9 +574,88 @@ impl HandshakePacket {
         
         let name = String::from_utf8_lossy(&self.payload[5..5 + name_len]).to_string();
         
+        if capabilities.encryption {
+            let tail = self.payload.get(5 + name_len..9 + name_len)?;
+            capabilities.key_fingerprint = u32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]);
+        }
+        
+        // Порт QUIC - последние два байта после хвоста сопряжения
+        let mut offset = Self::pairing_offset(name_len, &capabilities);
+        if let Some(pairing) = self.payload.get(offset..).and_then(PairingHello::decode) {
+            offset += pairing.encode().len();
+        }
+        if let Some(tail) = self.payload.get(offset..).filter(|tail| tail.len() == 2) {
+            capabilities.quic_port = Some(u16::from_le_bytes([tail[0], tail[1]])).filter(|&port| port != 0);
+        }
+        
         Some((audio_port, capabilities, name))
     }
     
+    /// Начало хвоста сопряжения: после имени и отпечатка ключа
+    fn pairing_offset(name_len: usize, capabilities: &PeerCapabilities) -> usize {
+        5 + name_len + if capabilities.encryption { 4 } else { 0 }
+    }
+    
+    /// Добавить ключ узла и его подтверждение в хвост Hello (см. `pairing`);
+    /// хвост встаёт перед портом QUIC, который пиры без сопряжения ищут
+    /// сразу после отпечатка ключа
+    pub fn with_pairing(mut self, pairing: PairingHello) -> Self {
+        let Some((_, capabilities, _)) = self.parse_hello() else {
+            return self;
+        };
+        let offset = Self::pairing_offset(self.payload[4] as usize, &capabilities);
+        let mut payload = BytesMut::from(&self.payload[..offset]);
+        payload.put_slice(&pairing.encode());
+        payload.put_slice(&self.payload[offset..]);
+        self.payload = payload.freeze();
+        self
+    }
+    
+    /// Ключ узла и его подтверждение из Hello (None от пиров без сопряжения)
+    pub fn parse_pairing(&self) -> Option<PairingHello> {
+        let (_, capabilities, _) = self.parse_hello()?;
+        let offset = Self::pairing_offset(self.payload[4] as usize, &capabilities);
+        PairingHello::decode(self.payload.get(offset..)?)
+    }
+    
+    /// Что подписывает хвост сопряжения: тип, ID сессии и полезная
+    /// нагрузка без самого хвоста
+    pub fn signed_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(5 + self.payload.len());
+        data.push(self.packet_type as u8);
+        data.extend_from_slice(&self.session_id.to_le_bytes());
+        let tail = self.parse_hello().and_then(|(_, capabilities, _)| {
+            let offset = Self::pairing_offset(self.payload[4] as usize, &capabilities);
+            let pairing = PairingHello::decode(self.payload.get(offset..)?)?;
+            Some(offset..offset + pairing.encode().len())
+        });
+        match tail {
+            Some(tail) => {
+                data.extend_from_slice(&self.payload[..tail.start]);
+                data.extend_from_slice(&self.payload[tail.end..]);
+            }
+            None => data.extend_from_slice(&self.payload),
+        }
+        data
+    }
+    
+    /// Создать пакет PairingChallenge в ответ на Hello
+    pub fn pairing_challenge(session_id: u32, challenge: &PairingChallenge) -> Self {
+        Self {
+            packet_type: HandshakePacketType::PairingChallenge,
+            session_id,
+            payload: Bytes::from(challenge.encode()),
+        }
+    }
+    
+    /// Разобрать вызов сопряжения
+    pub fn parse_pairing_challenge(&self) -> Option<PairingChallenge> {
+        if self.packet_type != HandshakePacketType::PairingChallenge {
+            return None;
+        }
+        PairingChallenge::decode(&self.payload)
+    }
+    
     /// Создать пакет HelloAck
     pub fn hello_ack(session_id: u32, name: &str, audio_port: u16, capabilities: PeerCapabilities) -> Self {
         let mut packet = Self::hello(session_id, name, audio_port, capabilities);
@@ -289,15 +663,37 @@ impl HandshakePacket {
         packet
     }
     
-    /// Создать пакет SyncRequest
-    pub fn sync_request(session_id: u32) -> Self {
+    /// Создать пакет SyncRequest (`accepts_plaintext` - получатель
+    /// разрешает отправителю не шифровать треки с `plaintext`;
+    /// `capabilities` - что получатель умеет воспроизводить)
+    ///
+    /// Полезная нагрузка: `[FLAGS:1] [CAPABILITIES:2] [QUIC_PORT:2]
+    /// [MAX_CHANNELS:1]`; старые версии отправляли только флаги (или
+    /// ничего), затем без порта QUIC, затем без числа каналов
+    pub fn sync_request(session_id: u32, accepts_plaintext: bool, capabilities: PeerCapabilities) -> Self {
+        let flags = if accepts_plaintext { SYNC_ACCEPTS_PLAINTEXT } else { 0 };
+        let mut payload = BytesMut::with_capacity(6);
+        payload.put_u8(flags);
+        payload.put_slice(&capabilities.to_bytes());
+        payload.put_u16_le(capabilities.quic_port.unwrap_or(0));
+        payload.put_u8(capabilities.max_channels);
         Self {
             packet_type: HandshakePacketType::SyncRequest,
             session_id,
-            payload: Bytes::new(),
+            payload: payload.freeze(),
         }
     }
     
+    /// Разрешает ли получатель в SyncRequest треки без шифрования
+    pub fn accepts_plaintext(&self) -> bool {
+        self.payload.first().is_some_and(|flags| flags & SYNC_ACCEPTS_PLAINTEXT != 0)
+    }
+    
+    /// Возможности получателя из SyncRequest (None от старых версий)
+    pub fn sync_capabilities(&self) -> Option<PeerCapabilities> {
+        PeerCapabilities::from_bytes(self.payload.get(1..)?)
+    }
+    
     /// Создать пакет SyncResponse с информацией о треках
     pub fn sync_response(session_id: u32, tracks: &[TrackInfo]) -> Self {
         let mut payload = BytesMut::new();
@@ -359,6 +755,181 @@ impl HandshakePacket {
         }
     }
     
+    /// Создать Ping с временем отправки t0 (синхронизация часов)
+    pub fn time_ping(session_id: u32, t0: u64) -> Self {
+        Self {
+            packet_type: HandshakePacketType::Ping,
+            session_id,
+            payload: Bytes::copy_from_slice(&t0.to_le_bytes()),
+        }
+    }
+    
+    /// Создать Pong с временными метками t0 (из пинга), t1 (приём), t2 (ответ)
+    pub fn time_pong(session_id: u32, t0: u64, t1: u64, t2: u64) -> Self {
+        let mut payload = BytesMut::with_capacity(24);
+        payload.put_u64_le(t0);
+        payload.put_u64_le(t1);
+        payload.put_u64_le(t2);
+        
+        Self {
+            packet_type: HandshakePacketType::Pong,
+            session_id,
+            payload: payload.freeze(),
+        }
+    }
+    
+    /// Разобрать время отправки из Ping (None для пинга без времени)
+    pub fn parse_time_ping(&self) -> Option<u64> {
+        let bytes = self.payload.get(..8)?;
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+    
+    /// Разобрать временные метки (t0, t1, t2) из Pong
+    pub fn parse_time_pong(&self) -> Option<(u64, u64, u64)> {
+        if self.payload.len() < 24 {
+            return None;
+        }
+        let word = |i: usize| u64::from_le_bytes(self.payload[i * 8..i * 8 + 8].try_into().unwrap());
+        Some((word(0), word(1), word(2)))
+    }
+    
+    /// Создать отчёт о приёме треков
+    pub fn feedback(session_id: u32, reports: &[TrackFeedback]) -> Self {
+        Self {
+            packet_type: HandshakePacketType::Feedback,
+            session_id,
+            payload: encode_reports(reports),
+        }
+    }
+    
+    /// Разобрать отчёты о приёме из Feedback
+    pub fn parse_feedback(&self) -> Option<Vec<TrackFeedback>> {
+        decode_reports(&self.payload)
+    }
+    
+    /// Создать уведомление о пересинхронизации (после сна/пробуждения)
+    pub fn resync(session_id: u32) -> Self {
+        Self {
+            packet_type: HandshakePacketType::Resync,
+            session_id,
+            payload: Bytes::new(),
+        }
+    }
+    
+    /// Создать подписку на треки
+    pub fn subscribe(session_id: u32, subscription: &Subscription) -> Self {
+        Self {
+            packet_type: HandshakePacketType::Subscribe,
+            session_id,
+            payload: subscription.encode(),
+        }
+    }
+    
+    /// Разобрать подписку из Subscribe
+    pub fn parse_subscribe(&self) -> Option<Subscription> {
+        Subscription::decode(&self.payload)
+    }
+    
+    /// Создать предложение файла
+    pub fn file_offer(session_id: u32, offer: &FileOffer) -> Self {
+        Self {
+            packet_type: HandshakePacketType::FileOffer,
+            session_id,
+            payload: offer.encode(),
+        }
+    }
+    
+    /// Разобрать предложение файла из FileOffer
+    pub fn parse_file_offer(&self) -> Option<FileOffer> {
+        FileOffer::decode(&self.payload)
+    }
+    
+    /// Создать блок файла, начинающийся с `offset`
+    pub fn file_chunk(session_id: u32, transfer_id: u32, offset: u64, data: &[u8]) -> Self {
+        Self {
+            packet_type: HandshakePacketType::FileChunk,
+            session_id,
+            payload: encode_chunk(transfer_id, offset, data),
+        }
+    }
+    
+    /// Разобрать блок файла: (ID передачи, смещение, данные)
+    pub fn parse_file_chunk(&self) -> Option<(u32, u64, &[u8])> {
+        decode_chunk(&self.payload)
+    }
+    
+    /// Создать подтверждение файла
+    pub fn file_ack(session_id: u32, ack: FileAck) -> Self {
+        Self {
+            packet_type: HandshakePacketType::FileAck,
+            session_id,
+            payload: ack.encode(),
+        }
+    }
+    
+    /// Разобрать подтверждение из FileAck
+    pub fn parse_file_ack(&self) -> Option<FileAck> {
+        FileAck::decode(&self.payload)
+    }
+    
+    /// Создать уведомление о новом или перенастроенном треке
+    pub fn track_added(session_id: u32, track: &TrackInfo) -> Self {
+        Self {
+            packet_type: HandshakePacketType::TrackAdded,
+            session_id,
+            payload: Bytes::from(track.serialize()),
+        }
+    }
+    
+    /// Разобрать трек из TrackAdded
+    pub fn parse_track_added(&self) -> Option<TrackInfo> {
+        TrackInfo::deserialize(&self.payload).map(|(track, _)| track)
+    }
+    
+    /// Создать уведомление об удалённом треке
+    pub fn track_removed(session_id: u32, track_id: u8) -> Self {
+        Self {
+            packet_type: HandshakePacketType::TrackRemoved,
+            session_id,
+            payload: Bytes::copy_from_slice(&[track_id]),
+        }
+    }
+    
+    /// Разобрать ID трека из TrackRemoved
+    pub fn parse_track_removed(&self) -> Option<u8> {
+        self.payload.first().copied()
+    }
+    
+    /// Создать зонд теста связи номер `seq` с временем отправки `t0`,
+    /// дополненный нулями до `size` байт пакета
+    pub fn probe_request(test_id: u32, seq: u32, t0: u64, size: usize) -> Self {
+        Self {
+            packet_type: HandshakePacketType::ProbeRequest,
+            session_id: test_id,
+            payload: encode_probe(seq, t0, 0, size),
+        }
+    }
+    
+    /// Ответ на зонд с временем его приёма `t1`, того же размера (None,
+    /// если это не зонд)
+    pub fn probe_echo(&self, t1: u64) -> Option<Self> {
+        if self.packet_type != HandshakePacketType::ProbeRequest {
+            return None;
+        }
+        let (seq, t0, _) = self.parse_probe()?;
+        Some(Self {
+            packet_type: HandshakePacketType::ProbeEcho,
+            session_id: self.session_id,
+            payload: encode_probe(seq, t0, t1, HEADER_SIZE + self.payload.len()),
+        })
+    }
+    
+    /// Разобрать (номер, t0, t1) из ProbeRequest или ProbeEcho
+    pub fn parse_probe(&self) -> Option<(u32, u64, u64)> {
+        let mut data = self.payload.get(..PROBE_SIZE)?;
+        Some((data.get_u32_le(), data.get_u64_le(), data.get_u64_le()))
+    }
+    
     /// Создать пакет Goodbye
     pub fn goodbye(session_id: u32) -> Self {
         Self {
@@ -400,7 +971,7 @@ impl HandshakePacket {
     
     /// Сериализовать пакет
     pub fn serialize(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(10 + self.payload.len());
+        let mut buf = BytesMut::with_capacity(HEADER_SIZE + self.payload.len());
         
         // Магические байты
         buf.put_slice(HANDSHAKE_MAGIC);
@@ -416,6 +987,11 @@ impl HandshakePacket {
         buf.freeze()
     }
     
+    /// Начинаются ли данные с магических байтов рукопожатия
+    pub fn has_magic(data: &[u8]) -> bool {
+        data.len() >= 4 && &data[0..4] == HANDSHAKE_MAGIC
+    }
+    
     /// Десериализовать пакет
     pub fn deserialize(data: &[u8]) -> Option<Self> {
         if data.len() < 10 {
@@ -460,9 +1036,43 @@ pub enum HandshakeState {
         peer_caps: PeerCapabilities,
         audio_port: u16,
         connected_at: Instant,
+        /// Пир в allowlist сопряжения (или сопряжение не требуется)
+        allowed: bool,
     },
     /// Ошибка рукопожатия
-    Failed { reason: String },
+    Failed { reason: String, failed_at: Instant },
+}
+
+/// Изменение состояния соединения с пиром
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// Рукопожатие завершено
+    Connected { peer_addr: SocketAddr, peer_name: String },
+    /// Пир прислал Goodbye
+    Departed(SocketAddr),
+    /// Пир не ответил на [`MAX_MISSED_PONGS`] пингов подряд
+    Lost(SocketAddr),
+    /// Пир не ответил на [`INTERRUPT_AFTER_MISSED`] пингов подряд
+    Interrupted(SocketAddr),
+    /// Прерванная связь восстановилась без нового рукопожатия
+    Resumed(SocketAddr),
+}
+
+/// Keepalive подключённого пира
+#[derive(Debug, Clone, Copy)]
+struct Keepalive {
+    last_ping: Option<Instant>,
+    /// Пинги без ответа с последнего Pong
+    missed: u32,
+    /// Связь прервана (отправлено событие `Interrupted`)
+    interrupted: bool,
+}
+
+/// Повторы Hello потерянному пиру
+#[derive(Debug, Clone, Copy)]
+struct Reconnect {
+    attempts: u32,
+    next_hello: Instant,
 }
 
 /// Менеджер рукопожатия с пирами
@@ -477,6 +1087,14 @@ pub struct HandshakeManager {
     states: parking_lot::RwLock<HashMap<SocketAddr, HandshakeState>>,
     /// ID сессии (инкрементируется для каждого нового рукопожатия)
     next_session_id: std::sync::atomic::AtomicU32,
+    /// Keepalive подключённых пиров
+    keepalive: parking_lot::Mutex<HashMap<SocketAddr, Keepalive>>,
+    /// Изменения соединений (забираются `take_events`)
+    events: parking_lot::Mutex<Vec<ConnectionEvent>>,
+    /// Потерянные пиры, которым повторяется Hello
+    reconnects: parking_lot::Mutex<HashMap<SocketAddr, Reconnect>>,
+    /// Allowlist и PIN сопряжения (None - принимаются все пиры)
+    pairing: Option<Arc<Pairing>>,
 }
 
 impl HandshakeManager {
@@ -488,9 +1106,19 @@ impl HandshakeManager {
             our_capabilities: capabilities,
             states: parking_lot::RwLock::new(HashMap::new()),
             next_session_id: std::sync::atomic::AtomicU32::new(1),
+            keepalive: parking_lot::Mutex::new(HashMap::new()),
+            events: parking_lot::Mutex::new(Vec::new()),
+            reconnects: parking_lot::Mutex::new(HashMap::new()),
+            pairing: None,
         }
     }
     
+    /// Проверять Hello по allowlist и PIN сопряжения
+    pub fn with_pairing(mut self, pairing: Arc<Pairing>) -> Self {
+        self.pairing = Some(pairing);
+        self
+    }
+    
     /// Получить новый ID сессии
     fn new_session_id(&self) -> u32 {
         self.next_session_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
@@ -499,18 +1127,27 @@ impl HandshakeManager {
     /// Инициировать рукопожатие с пиром
     pub fn initiate(&self, peer_addr: SocketAddr) -> HandshakePacket {
         let session_id = self.new_session_id();
+        let now = Instant::now();
         
         self.states.write().insert(
             peer_addr,
-            HandshakeState::HelloSent { sent_at: Instant::now() },
+            HandshakeState::HelloSent { sent_at: now },
         );
+        if let Some(reconnect) = self.reconnects.lock().get_mut(&peer_addr) {
+            reconnect.attempts += 1;
+            reconnect.next_hello = now + reconnect_backoff(reconnect.attempts);
+        }
         
-        HandshakePacket::hello(
+        let hello = HandshakePacket::hello(
             session_id,
             &self.our_name,
             self.our_audio_port,
             self.our_capabilities,
-        )
+        );
+        match &self.pairing {
+            Some(pairing) => hello.with_pairing(pairing.hello(&peer_addr)),
+            None => hello,
+        }
     }
     
     /// Обработать входящий пакет рукопожатия
@@ -523,6 +1160,14 @@ impl HandshakeManager {
             HandshakePacketType::Hello => {
                 // Получили приветствие - отвечаем HelloAck
                 if let Some((audio_port, peer_caps, peer_name)) = packet.parse_hello() {
+                    // Незашифрованные пиры и пиры с другим ключом отклоняются
+                    if !self.our_capabilities.is_encryption_compatible_with(&peer_caps) {
+                        return Some(HandshakePacket::error(
+                            packet.session_id,
+                            "Несовпадение ключа шифрования",
+                        ));
+                    }
+                    
                     // Проверяем совместимость
                     if !self.our_capabilities.is_compatible_with(&peer_caps) {
                         return Some(HandshakePacket::error(
@@ -531,58 +1176,116 @@ impl HandshakeManager {
                         ));
                     }
                     
-                    // Обновляем состояние
-                    self.states.write().insert(
-                        peer_addr,
-                        HandshakeState::Connected {
-                            peer_name,
-                            peer_caps,
-                            audio_port,
-                            connected_at: Instant::now(),
-                        },
-                    );
+                    // Сопряжённый пир подтверждает Hello ответом на вызов,
+                    // незнакомые отклоняются, если требуется сопряжение
+                    let mut ack_key = None;
+                    if let Some(pairing) = &self.pairing {
+                        let hello = packet.parse_pairing();
+                        match pairing.check_hello(&peer_addr, hello, &packet.signed_data(), Instant::now()) {
+                            HelloCheck::Accept(key) => ack_key = key,
+                            HelloCheck::Challenge(challenge) => {
+                                return Some(HandshakePacket::pairing_challenge(packet.session_id, &challenge));
+                            }
+                            HelloCheck::Reject(reason) => {
+                                return Some(HandshakePacket::error(packet.session_id, reason));
+                            }
+                        }
+                    }
+                    
+                    // Обновляем состояние (проверку сопряжения пир прошёл)
+                    self.set_connected(peer_addr, peer_name, peer_caps, audio_port, true);
                     
                     // Отвечаем HelloAck
-                    return Some(HandshakePacket::hello_ack(
+                    let ack = HandshakePacket::hello_ack(
                         packet.session_id,
                         &self.our_name,
                         self.our_audio_port,
                         self.our_capabilities,
-                    ));
+                    );
+                    return Some(match &self.pairing {
+                        Some(pairing) => {
+                            let signed = ack.signed_data();
+                            ack.with_pairing(pairing.ack(ack_key, &signed))
+                        }
+                        None => ack,
+                    });
                 }
             }
             
             HandshakePacketType::HelloAck => {
                 // Получили подтверждение - рукопожатие завершено
                 if let Some((audio_port, peer_caps, peer_name)) = packet.parse_hello() {
-                    self.states.write().insert(
-                        peer_addr,
-                        HandshakeState::Connected {
-                            peer_name,
-                            peer_caps,
-                            audio_port,
-                            connected_at: Instant::now(),
-                        },
-                    );
+                    if !self.our_capabilities.is_encryption_compatible_with(&peer_caps) {
+                        self.states.write().insert(
+                            peer_addr,
+                            HandshakeState::Failed {
+                                reason: "Несовпадение ключа шифрования".to_string(),
+                                failed_at: Instant::now(),
+                            },
+                        );
+                        return None;
+                    }
+                    
+                    let allowed = match &self.pairing {
+                        Some(pairing) => pairing.check_ack(&peer_addr, packet.parse_pairing(), &packet.signed_data()),
+                        None => true,
+                    };
+                    self.set_connected(peer_addr, peer_name, peer_caps, audio_port, allowed);
+                }
+            }
+            
+            HandshakePacketType::PairingChallenge => {
+                // Повторяем свой Hello с подтверждением (вызов принимается
+                // только в ответ на наш Hello)
+                let (Some(pairing), Some(challenge)) = (&self.pairing, packet.parse_pairing_challenge()) else {
+                    return None;
+                };
+                if !matches!(self.get_state(&peer_addr), Some(HandshakeState::HelloSent { .. })) {
+                    return None;
+                }
+                let hello = HandshakePacket::hello(
+                    packet.session_id,
+                    &self.our_name,
+                    self.our_audio_port,
+                    self.our_capabilities,
+                );
+                match pairing.answer(&peer_addr, &challenge, &hello.signed_data()) {
+                    Some(tail) => return Some(hello.with_pairing(tail)),
+                    None => {
+                        self.states.write().insert(
+                            peer_addr,
+                            HandshakeState::Failed {
+                                reason: "Пир требует сопряжения: введите его PIN".to_string(),
+                                failed_at: Instant::now(),
+                            },
+                        );
+                    }
                 }
             }
             
             HandshakePacketType::Ping => {
-                // Отвечаем на пинг
-                return Some(HandshakePacket::pong(packet.session_id));
+                // Отвечаем на пинг (с временными метками, если пинг их содержит)
+                return Some(respond_to_ping(&packet, media_time_us()));
             }
             
             HandshakePacketType::Goodbye => {
                 // Пир отключается
-                self.states.write().remove(&peer_addr);
+                let known = self.states.write().remove(&peer_addr).is_some();
+                self.reconnects.lock().remove(&peer_addr);
+                if known {
+                    self.events.lock().push(ConnectionEvent::Departed(peer_addr));
+                }
             }
             
             HandshakePacketType::ErrorPacket => {
-                // Получили ошибку
+                // Получили ошибку; введённый PIN пира одноразовый
+                if let Some(pairing) = &self.pairing {
+                    pairing.forget_peer_pin(&peer_addr);
+                }
                 let reason = packet.parse_error().unwrap_or_default();
                 self.states.write().insert(
                     peer_addr,
-                    HandshakeState::Failed { reason },
+                    HandshakeState::Failed { reason, failed_at: Instant::now() },
                 );
             }
             
@@ -592,6 +1295,218 @@ impl HandshakeManager {
         None
     }
     
+    /// Записать завершённое рукопожатие (событие - только для нового
+    /// подключения, повторный Hello его не порождает)
+    fn set_connected(
+        &self,
+        peer_addr: SocketAddr,
+        peer_name: String,
+        peer_caps: PeerCapabilities,
+        audio_port: u16,
+        allowed: bool,
+    ) {
+        let state = HandshakeState::Connected {
+            peer_name: peer_name.clone(),
+            peer_caps,
+            audio_port,
+            connected_at: Instant::now(),
+            allowed,
+        };
+        let previous = self.states.write().insert(peer_addr, state);
+        let keepalive = self.keepalive.lock().remove(&peer_addr);
+        if let Some(reconnect) = self.reconnects.lock().remove(&peer_addr) {
+            tracing::info!("Связь с {} восстановлена (попыток: {})", peer_addr, reconnect.attempts);
+        }
+        if !matches!(previous, Some(HandshakeState::Connected { .. })) {
+            self.events.lock().push(ConnectionEvent::Connected { peer_addr, peer_name });
+        } else if keepalive.is_some_and(|peer| peer.interrupted) {
+            // Пир сам переподключился, пока мы ждали ответа на пинги
+            self.events.lock().push(ConnectionEvent::Resumed(peer_addr));
+        }
+    }
+    
+    /// Обработать пакет, пришедший на сокет аудио; `Some` если это был
+    /// пакет рукопожатия (внутри - ответ, который нужно отправить обратно).
+    /// Пинги и остальные пакеты оставляются их обработчикам; Pong только
+    /// отмечает, что пир жив (замер часов делает `TimeSync`)
+    pub fn handle_packet(&self, data: &[u8], from: SocketAddr) -> Option<Option<Bytes>> {
+        let packet = HandshakePacket::deserialize(data)?;
+        let from = canonical_addr(from);
+        let packet_type = packet.packet_type;
+        if packet_type == HandshakePacketType::Pong {
+            if let Some(peer) = self.keepalive.lock().get_mut(&from) {
+                peer.missed = 0;
+                if std::mem::take(&mut peer.interrupted) {
+                    tracing::info!("Связь с {} восстановлена", from);
+                    self.events.lock().push(ConnectionEvent::Resumed(from));
+                }
+            }
+            return None;
+        }
+        if !matches!(
+            packet_type,
+            HandshakePacketType::Hello
+                | HandshakePacketType::HelloAck
+                | HandshakePacketType::PairingChallenge
+                | HandshakePacketType::Goodbye
+                | HandshakePacketType::ErrorPacket
+        ) {
+            return None;
+        }
+        
+        let was_connected = self.is_connected(&from);
+        let reply = self.process_packet(from, packet);
+        match self.get_state(&from) {
+            Some(HandshakeState::Connected { peer_name, .. }) if !was_connected => {
+                tracing::info!("Рукопожатие с {} ({}) завершено", peer_name, from);
+            }
+            Some(HandshakeState::Failed { reason, .. }) => {
+                tracing::warn!("Рукопожатие с {} не удалось: {}", from, reason);
+            }
+            None if packet_type == HandshakePacketType::Goodbye => {
+                tracing::info!("Пир {} отключился", from);
+            }
+            _ => {}
+        }
+        if let Some(ref reply) = reply {
+            if reply.packet_type == HandshakePacketType::ErrorPacket {
+                tracing::warn!("Рукопожатие от {} отклонено: {}", from, reply.parse_error().unwrap_or_default());
+            }
+        }
+        Some(reply.map(|reply| reply.serialize()))
+    }
+    
+    /// Рукопожатие до запуска отправителя: Hello с временного сокета (с
+    /// повтором), пока не придёт HelloAck или ошибка, но не дольше
+    /// `timeout`. Возвращает итоговое состояние (`HelloSent`, если пир не
+    /// ответил)
+    pub fn connect(&self, peer_addr: SocketAddr, timeout: Duration) -> std::io::Result<HandshakeState> {
+        let peer_addr = canonical_addr(peer_addr);
+        let local: SocketAddr = if peer_addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+        
+        let deadline = Instant::now() + timeout;
+        let mut last_hello: Option<Instant> = None;
+        let mut buf = [0u8; 512];
+        while Instant::now() < deadline {
+            if last_hello.is_none_or(|sent| sent.elapsed() >= HELLO_RETRY) {
+                socket.send_to(&self.initiate(peer_addr).serialize(), peer_addr)?;
+                last_hello = Some(Instant::now());
+            }
+            match socket.recv_from(&mut buf) {
+                Ok((size, from)) if canonical_addr(from) == peer_addr => {
+                    if let Some(Some(reply)) = self.handle_packet(&buf[..size], from) {
+                        socket.send_to(&reply, from)?;
+                    }
+                    if let Some(state @ (HandshakeState::Connected { .. } | HandshakeState::Failed { .. })) =
+                        self.get_state(&peer_addr)
+                    {
+                        return Ok(state);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                // Порт пира закрыт (ICMP): он ещё может запуститься
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.get_state(&peer_addr).unwrap_or(HandshakeState::Idle))
+    }
+    
+    /// Пакет Goodbye пиру (рукопожатие с ним забывается)
+    pub fn goodbye(&self, peer_addr: SocketAddr) -> HandshakePacket {
+        self.states.write().remove(&peer_addr);
+        HandshakePacket::goodbye(self.new_session_id())
+    }
+    
+    /// Keepalive-пинги, которые пора отправить подключённым пирам:
+    /// (адрес, сериализованный пакет). Пиры, не ответившие на
+    /// [`INTERRUPT_AFTER_MISSED`] пингов подряд, получают событие
+    /// `Interrupted`, на [`MAX_MISSED_PONGS`] - забываются с событием `Lost`
+    /// (и им повторяется Hello, см. `hello_due`).
+    ///
+    /// Пиры, подключившиеся с временного сокета (отправитель перед
+    /// запуском, см. `connect`), не пингуются: сокет уже закрыт.
+    pub fn due_keepalives(&self) -> Vec<(SocketAddr, Bytes)> {
+        self.due_keepalives_at(Instant::now())
+    }
+    
+    fn due_keepalives_at(&self, now: Instant) -> Vec<(SocketAddr, Bytes)> {
+        let connected: Vec<SocketAddr> = self
+            .connected_peers()
+            .into_iter()
+            .filter(|(addr, _, audio_port)| addr.port() == *audio_port)
+            .map(|(addr, _, _)| addr)
+            .collect();
+        
+        let mut pings = Vec::new();
+        let mut lost = Vec::new();
+        let mut keepalive = self.keepalive.lock();
+        keepalive.retain(|addr, _| connected.contains(addr));
+        for addr in connected {
+            let peer = keepalive.entry(addr).or_insert(Keepalive { last_ping: None, missed: 0, interrupted: false });
+            if peer.last_ping.is_some_and(|sent| now.saturating_duration_since(sent) < KEEPALIVE_INTERVAL) {
+                continue;
+            }
+            if peer.missed >= MAX_MISSED_PONGS {
+                lost.push(addr);
+                continue;
+            }
+            if peer.missed >= INTERRUPT_AFTER_MISSED && !peer.interrupted {
+                peer.interrupted = true;
+                tracing::warn!("Пир {} не ответил на {} пинга подряд, связь прервана", addr, peer.missed);
+                self.events.lock().push(ConnectionEvent::Interrupted(addr));
+            }
+            peer.missed += 1;
+            peer.last_ping = Some(now);
+            pings.push((addr, HandshakePacket::ping(self.new_session_id()).serialize()));
+        }
+        for addr in &lost {
+            keepalive.remove(addr);
+        }
+        drop(keepalive);
+        
+        for addr in lost {
+            self.states.write().remove(&addr);
+            self.reconnects.lock().insert(addr, Reconnect { attempts: 0, next_hello: now });
+            tracing::warn!("Пир {} не ответил на {} пингов подряд, соединение потеряно", addr, MAX_MISSED_PONGS);
+            self.events.lock().push(ConnectionEvent::Lost(addr));
+        }
+        pings
+    }
+    
+    /// Пора ли отправить Hello пиру: потерянному пиру - с растущим
+    /// интервалом после каждой попытки, остальным - всегда
+    pub fn hello_due(&self, peer_addr: &SocketAddr) -> bool {
+        self.hello_due_at(peer_addr, Instant::now())
+    }
+    
+    fn hello_due_at(&self, peer_addr: &SocketAddr, now: Instant) -> bool {
+        self.reconnects
+            .lock()
+            .get(peer_addr)
+            .is_none_or(|reconnect| now >= reconnect.next_hello)
+    }
+    
+    /// Изменения соединений с прошлого вызова
+    pub fn take_events(&self) -> Vec<ConnectionEvent> {
+        std::mem::take(&mut *self.events.lock())
+    }
+    
+    /// Возможности пира, с которым завершено рукопожатие
+    pub fn peer_capabilities(&self, peer_addr: &SocketAddr) -> Option<PeerCapabilities> {
+        match self.states.read().get(peer_addr) {
+            Some(HandshakeState::Connected { peer_caps, .. }) => Some(*peer_caps),
+            _ => None,
+        }
+    }
+    
     /// Получить состояние рукопожатия с пиром
     pub fn get_state(&self, peer_addr: &SocketAddr) -> Option<HandshakeState> {
         self.states.read().get(peer_addr).cloned()
@@ -605,6 +1520,15 @@ impl HandshakeManager {
         )
     }
     
+    /// Подключён ли пир и есть ли он в allowlist, если требуется сопряжение
+    /// (только такие пиры могут присылать файлы)
+    pub fn is_trusted(&self, peer_addr: &SocketAddr) -> bool {
+        matches!(
+            self.states.read().get(peer_addr),
+            Some(HandshakeState::Connected { allowed: true, .. })
+        )
+    }
+    
     /// Получить список подключённых пиров
     pub fn connected_peers(&self) -> Vec<(SocketAddr, String, u16)> {
         self.states
@@ -623,7 +1547,7 @@ impl HandshakeManager {
     /// Очистить устаревшие состояния
     pub fn cleanup_stale(&self, timeout: Duration) {
         let mut states = self.states.write();
-        states.retain(|_, state| {
+        states.retain(|peer_addr, state| {
             match state {
                 HandshakeState::HelloSent { sent_at } => {
                     sent_at.elapsed() < timeout
@@ -632,9 +1556,11 @@ impl HandshakeManager {
                     // Подключённые пиры не удаляем по таймауту
                     true
                 }
-                HandshakeState::Failed { .. } => {
-                    // Удаляем неудачные через минуту
-                    false
+                HandshakeState::Failed { failed_at, .. } => {
+                    // Удаляем неудачные через минуту; с введённым PIN
+                    // пира Hello повторяется сразу
+                    failed_at.elapsed() < FAILED_RETRY
+                        && !self.pairing.as_ref().is_some_and(|pairing| pairing.has_peer_pin(peer_addr))
                 }
                 _ => true,
             }
@@ -642,9 +1568,17 @@ impl HandshakeManager {
     }
 }
 
+/// Интервал до следующего Hello после `attempts` попыток
+fn reconnect_backoff(attempts: u32) -> Duration {
+    RECONNECT_BACKOFF
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX_BACKOFF)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::PairingConfig;
     
     #[test]
     fn test_capabilities_serialization() {
@@ -655,9 +1589,54 @@ mod tests {
         assert_eq!(caps.can_send, restored.can_send);
         assert_eq!(caps.can_receive, restored.can_receive);
         assert_eq!(caps.supports_opus, restored.supports_opus);
+        assert_eq!(caps.supports_dred, restored.supports_dred);
+        assert!(restored.supports_flac);
         assert_eq!(caps.max_tracks, restored.max_tracks);
     }
     
+    #[test]
+    fn test_quic_port_advertised() {
+        let quic = PeerCapabilities { quic_port: Some(5003), ..PeerCapabilities::receiver_only() };
+        
+        // В SyncRequest порт идёт после возможностей; старые версии его не шлют
+        let sync = HandshakePacket::sync_request(0, false, quic);
+        assert_eq!(sync.sync_capabilities().unwrap().quic_port, Some(5003));
+        let legacy = PeerCapabilities::from_bytes(&[0x02, 16]).unwrap();
+        assert_eq!(legacy.quic_port, None);
+        let sync = HandshakePacket::sync_request(0, false, PeerCapabilities::receiver_only());
+        assert_eq!(sync.sync_capabilities().unwrap().quic_port, None);
+        
+        // В Hello - после отпечатка ключа и хвоста сопряжения
+        let pairing = Pairing::new(&PairingConfig::default(), None);
+        let peer_addr: SocketAddr = "192.168.1.20:5002".parse().unwrap();
+        pairing.set_peer_pin(peer_addr, "123456").unwrap();
+        let tail = pairing.hello(&peer_addr);
+        let hello = HandshakePacket::hello(1, "Studio", 5000, quic.with_encryption(7)).with_pairing(tail);
+        let (_, parsed, name) = hello.parse_hello().unwrap();
+        assert_eq!((parsed.quic_port, parsed.key_fingerprint, name.as_str()), (Some(5003), 7, "Studio"));
+        assert_eq!(hello.parse_pairing(), Some(tail));
+        let (_, parsed, _) = HandshakePacket::hello(1, "Studio", 5000, quic).parse_hello().unwrap();
+        assert_eq!(parsed.quic_port, Some(5003));
+        let plain = HandshakePacket::hello(1, "Studio", 5000, PeerCapabilities::full()).with_pairing(tail);
+        assert_eq!(plain.parse_hello().unwrap().1.quic_port, None);
+    }
+    
+    #[test]
+    fn test_dred_negotiation() {
+        let dred = PeerCapabilities { supports_dred: true, ..PeerCapabilities::full() };
+        let legacy = PeerCapabilities { supports_dred: false, ..PeerCapabilities::full() };
+        
+        let restored = PeerCapabilities::from_bytes(&dred.to_bytes()).unwrap();
+        assert!(restored.supports_dred);
+        
+        // DRED только если оба пира его декодируют
+        assert!(dred.uses_dred_with(&restored));
+        assert!(!dred.uses_dred_with(&legacy));
+        assert!(!legacy.uses_dred_with(&dred));
+        // Совместимость соединения от DRED не зависит
+        assert!(dred.is_compatible_with(&legacy));
+    }
+    
     #[test]
     fn test_hello_packet() {
         let packet = HandshakePacket::hello(
@@ -688,6 +1667,9 @@ mod tests {
             bitrate: 128000,
             channels: 2,
             fec_enabled: true,
+            plaintext: true,
+            codec: Codec::Flac,
+            frame_duration_us: 2_500,
         };
         
         let bytes = track.serialize();
@@ -698,6 +1680,52 @@ mod tests {
         assert_eq!(track.bitrate, restored.bitrate);
         assert_eq!(track.channels, restored.channels);
         assert_eq!(track.fec_enabled, restored.fec_enabled);
+        assert_eq!(track.plaintext, restored.plaintext);
+        assert_eq!(restored.codec, Codec::Flac);
+        assert_eq!(restored.frame_duration_us, 2_500);
+        assert_eq!(restored.output_config(String::new()).frame_size_ms, 2.5);
+        
+        // Старые версии пишут FEC как 1 и не сообщают длительность кадра
+        let mut legacy = bytes.clone();
+        legacy[7] = 1;
+        let (restored, _) = TrackInfo::deserialize(&legacy).unwrap();
+        assert!(restored.fec_enabled && !restored.plaintext && restored.codec == Codec::Opus);
+        assert_eq!(restored.frame_duration_us, 10_000);
+        assert!(TrackInfo::deserialize(&bytes[..8]).is_none());
+        
+        // Уведомления об изменении треков
+        let added = HandshakePacket::deserialize(&HandshakePacket::track_added(1, &track).serialize()).unwrap();
+        assert_eq!(added.parse_track_added(), Some(track.clone()));
+        let removed = HandshakePacket::deserialize(&HandshakePacket::track_removed(1, 5).serialize()).unwrap();
+        assert_eq!((removed.packet_type, removed.parse_track_removed()), (HandshakePacketType::TrackRemoved, Some(5)));
+        
+        let caps = PeerCapabilities { supports_stereo: false, max_tracks: 4, ..PeerCapabilities::receiver_only() };
+        let request = HandshakePacket::deserialize(&HandshakePacket::sync_request(1, true, caps).serialize()).unwrap();
+        assert!(request.accepts_plaintext());
+        let restored = request.sync_capabilities().unwrap();
+        assert!(!restored.supports_stereo && restored.supports_fec);
+        assert_eq!((restored.max_tracks, restored.max_channels), (4, 8));
+        assert!(!HandshakePacket::sync_request(1, false, caps).accepts_plaintext());
+        
+        // Без числа каналов: моно или стерео по флагу
+        let mut payload = HandshakePacket::sync_request(1, true, caps).payload.to_vec();
+        payload.pop();
+        let no_channels = HandshakePacket {
+            packet_type: HandshakePacketType::SyncRequest,
+            session_id: 1,
+            payload: Bytes::from(payload),
+        };
+        assert_eq!(no_channels.sync_capabilities().unwrap().max_channels, 1);
+        assert_eq!(PeerCapabilities::from_bytes(&PeerCapabilities::full().to_bytes()).unwrap().max_channels, 2);
+        
+        // Старые версии: только флаги
+        let legacy = HandshakePacket {
+            packet_type: HandshakePacketType::SyncRequest,
+            session_id: 1,
+            payload: Bytes::from_static(&[SYNC_ACCEPTS_PLAINTEXT]),
+        };
+        assert!(legacy.accepts_plaintext());
+        assert!(legacy.sync_capabilities().is_none());
     }
     
     #[test]
@@ -721,4 +1749,295 @@ mod tests {
         // Два получателя несовместимы
         assert!(!receiver.is_compatible_with(&receiver));
     }
+    
+    #[test]
+    fn test_combine_receiver_capabilities() {
+        // Без получателей ограничений нет
+        assert_eq!(PeerCapabilities::combine(&[]), RemoteCapabilities::default());
+        
+        let mono = PeerCapabilities { supports_stereo: false, max_tracks: 4, ..PeerCapabilities::receiver_only() };
+        let rtp = PeerCapabilities { supports_fec: false, supports_flac: false, max_channels: 6, ..PeerCapabilities::receiver_only() };
+        let combined = PeerCapabilities::combine(&[mono, rtp]);
+        assert_eq!(combined.peers, 2);
+        assert!(!combined.stereo && !combined.fec && !combined.flac);
+        assert!(PeerCapabilities::combine(&[mono]).flac);
+        assert_eq!((combined.max_tracks, combined.max_channels), (4, 6));
+    }
+    
+    #[test]
+    fn test_encryption_negotiation() {
+        let encrypted = PeerCapabilities::full().with_encryption(0xDEADBEEF);
+        
+        // Отпечаток ключа передаётся в Hello
+        let packet = HandshakePacket::hello(1, "Secure", 5000, encrypted);
+        let (_, caps, name) = HandshakePacket::deserialize(&packet.serialize())
+            .unwrap()
+            .parse_hello()
+            .unwrap();
+        assert_eq!(name, "Secure");
+        assert!(caps.encryption);
+        assert_eq!(caps.key_fingerprint, 0xDEADBEEF);
+        
+        // Незашифрованный пир и пир с другим ключом отклоняются
+        let manager = HandshakeManager::new("Us".to_string(), 5000, encrypted);
+        let addr: SocketAddr = "192.168.1.2:5000".parse().unwrap();
+        
+        let plain = HandshakePacket::hello(1, "Plain", 5000, PeerCapabilities::full());
+        let reply = manager.process_packet(addr, plain).unwrap();
+        assert_eq!(reply.packet_type, HandshakePacketType::ErrorPacket);
+        
+        let other_key = HandshakePacket::hello(2, "Other", 5000, PeerCapabilities::full().with_encryption(1));
+        let reply = manager.process_packet(addr, other_key).unwrap();
+        assert_eq!(reply.packet_type, HandshakePacketType::ErrorPacket);
+        assert!(!manager.is_connected(&addr));
+        
+        let same_key = HandshakePacket::hello(3, "Friend", 5000, encrypted);
+        let reply = manager.process_packet(addr, same_key).unwrap();
+        assert_eq!(reply.packet_type, HandshakePacketType::HelloAck);
+        assert!(manager.is_connected(&addr));
+    }
+    
+    #[test]
+    fn test_handle_packet_and_goodbye() {
+        let us = HandshakeManager::new("Us".to_string(), 5000, PeerCapabilities::full());
+        let peer = HandshakeManager::new("Studio".to_string(), 5002, PeerCapabilities::receiver_only());
+        let our_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let peer_addr: SocketAddr = "192.168.1.20:5002".parse().unwrap();
+        
+        let hello = us.initiate(peer_addr).serialize();
+        let ack = peer.handle_packet(&hello, our_addr).unwrap().unwrap();
+        assert_eq!(us.handle_packet(&ack, peer_addr), Some(None));
+        assert!(us.is_connected(&peer_addr) && peer.is_connected(&our_addr));
+        assert!(us.peer_capabilities(&peer_addr).unwrap().can_receive);
+        
+        // Пинги остаются синхронизации часов
+        assert!(peer.handle_packet(&HandshakePacket::ping(1).serialize(), our_addr).is_none());
+        
+        let goodbye = us.goodbye(peer_addr).serialize();
+        assert!(!us.is_connected(&peer_addr));
+        assert_eq!(peer.handle_packet(&goodbye, our_addr), Some(None));
+        assert_eq!(
+            peer.take_events(),
+            vec![
+                ConnectionEvent::Connected { peer_addr: our_addr, peer_name: "Us".to_string() },
+                ConnectionEvent::Departed(our_addr),
+            ]
+        );
+        assert!(peer.take_events().is_empty());
+    }
+    
+    /// Рукопожатие `sender` с `receiver` до последнего ответа; пакеты,
+    /// отправленные `sender`
+    fn exchange(
+        sender: &HandshakeManager,
+        sender_addr: SocketAddr,
+        receiver: &HandshakeManager,
+        receiver_addr: SocketAddr,
+    ) -> Vec<Bytes> {
+        let mut sent = vec![sender.initiate(receiver_addr).serialize()];
+        while let Some(reply) = receiver.handle_packet(sent.last().unwrap(), sender_addr).flatten() {
+            match sender.handle_packet(&reply, receiver_addr).flatten() {
+                Some(packet) => sent.push(packet),
+                None => break,
+            }
+        }
+        sent
+    }
+    
+    #[test]
+    fn test_pairing_rejects_unknown_peers() {
+        let required = PairingConfig { required: true, ..PairingConfig::default() };
+        let receiver_pairing = Arc::new(Pairing::new(&required, None));
+        let sender_pairing = Arc::new(Pairing::new(&PairingConfig::default(), None));
+        let receiver = HandshakeManager::new("Studio".to_string(), 5002, PeerCapabilities::receiver_only())
+            .with_pairing(receiver_pairing.clone());
+        let sender = HandshakeManager::new("Stage".to_string(), 5000, PeerCapabilities::sender_only())
+            .with_pairing(sender_pairing.clone());
+        let sender_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let receiver_addr: SocketAddr = "192.168.1.20:5002".parse().unwrap();
+        
+        // Незнакомый пир получает Error
+        let reply = receiver.handle_packet(&sender.initiate(receiver_addr).serialize(), sender_addr).unwrap().unwrap();
+        assert_eq!(HandshakePacket::deserialize(&reply).unwrap().packet_type, HandshakePacketType::ErrorPacket);
+        sender.handle_packet(&reply, receiver_addr);
+        assert!(!receiver.is_connected(&sender_addr));
+        assert!(matches!(sender.get_state(&receiver_addr), Some(HandshakeState::Failed { .. })));
+        
+        // С PIN приёмника Hello повторяется сразу и проходит после вызова
+        let pin = receiver_pairing.new_pin(Instant::now());
+        sender_pairing.set_peer_pin(receiver_addr, &pin).unwrap();
+        sender.cleanup_stale(HELLO_TIMEOUT);
+        assert!(sender.get_state(&receiver_addr).is_none());
+        exchange(&sender, sender_addr, &receiver, receiver_addr);
+        assert!(receiver.is_connected(&sender_addr) && sender.is_connected(&receiver_addr));
+        assert_eq!(sender_pairing.status(Instant::now()).allowed_peers, vec![receiver_pairing.key()]);
+        
+        // Дальше Hello подписывается секретом пары; записанный Hello не повторить
+        receiver.process_packet(sender_addr, HandshakePacket::goodbye(0));
+        let sent = exchange(&sender, sender_addr, &receiver, receiver_addr);
+        assert_eq!(sent.len(), 2);
+        assert!(receiver.is_connected(&sender_addr));
+        receiver.process_packet(sender_addr, HandshakePacket::goodbye(0));
+        let reply = receiver.handle_packet(&sent[1], sender_addr).unwrap().unwrap();
+        assert_eq!(HandshakePacket::deserialize(&reply).unwrap().packet_type, HandshakePacketType::ErrorPacket);
+        assert!(!receiver.is_connected(&sender_addr));
+        
+        // Хвост сопряжения читается и после ключа шифрования
+        let tail = sender_pairing.hello(&receiver_addr);
+        let hello = HandshakePacket::hello(1, "Stage", 5000, PeerCapabilities::full().with_encryption(7))
+            .with_pairing(tail);
+        assert_eq!(hello.parse_pairing(), Some(tail));
+        assert_eq!(HandshakePacket::hello(2, "Stage", 5000, PeerCapabilities::full()).parse_pairing(), None);
+    }
+    
+    #[test]
+    fn test_keepalive_detects_lost_peer() {
+        let us = HandshakeManager::new("Us".to_string(), 5000, PeerCapabilities::full());
+        let peer = HandshakeManager::new("Studio".to_string(), 5002, PeerCapabilities::full());
+        let our_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let peer_addr: SocketAddr = "192.168.1.20:5002".parse().unwrap();
+        let ack = peer.handle_packet(&us.initiate(peer_addr).serialize(), our_addr).unwrap().unwrap();
+        us.handle_packet(&ack, peer_addr);
+        us.take_events();
+        
+        // Пир отвечает: пинг раз в интервал, счётчик пропусков сбрасывается
+        let start = Instant::now();
+        let pings = us.due_keepalives_at(start);
+        assert_eq!(pings.len(), 1);
+        assert!(us.due_keepalives_at(start + KEEPALIVE_INTERVAL / 2).is_empty());
+        let pong = peer.process_packet(our_addr, HandshakePacket::deserialize(&pings[0].1).unwrap()).unwrap();
+        assert!(us.handle_packet(&pong.serialize(), peer_addr).is_none());
+        
+        // Пир замолчал: теряется после MAX_MISSED_PONGS пингов без ответа
+        let mut now = start;
+        for _ in 0..MAX_MISSED_PONGS {
+            now += KEEPALIVE_INTERVAL;
+            assert_eq!(us.due_keepalives_at(now).len(), 1);
+            assert!(us.is_connected(&peer_addr));
+        }
+        assert!(us.due_keepalives_at(now + KEEPALIVE_INTERVAL).is_empty());
+        assert!(!us.is_connected(&peer_addr));
+        assert_eq!(
+            us.take_events(),
+            vec![ConnectionEvent::Interrupted(peer_addr), ConnectionEvent::Lost(peer_addr)]
+        );
+        
+        // Пир с временного сокета (порт не его аудио-порт) не пингуется
+        let ephemeral: SocketAddr = "192.168.1.30:40123".parse().unwrap();
+        let sender = HandshakeManager::new("Sender".to_string(), 5000, PeerCapabilities::sender_only());
+        us.handle_packet(&sender.initiate(our_addr).serialize(), ephemeral);
+        assert!(us.is_connected(&ephemeral));
+        assert!(us.due_keepalives_at(now + KEEPALIVE_INTERVAL * 10).is_empty());
+    }
+    
+    #[test]
+    fn test_interrupted_link_resumes() {
+        let us = HandshakeManager::new("Us".to_string(), 5000, PeerCapabilities::full());
+        let peer = HandshakeManager::new("Studio".to_string(), 5002, PeerCapabilities::full());
+        let our_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let peer_addr: SocketAddr = "192.168.1.20:5002".parse().unwrap();
+        let ack = peer.handle_packet(&us.initiate(peer_addr).serialize(), our_addr).unwrap().unwrap();
+        us.handle_packet(&ack, peer_addr);
+        us.take_events();
+        
+        // Пропал Wi-Fi: после INTERRUPT_AFTER_MISSED пингов связь прервана
+        let mut now = Instant::now();
+        let mut pings = Vec::new();
+        for _ in 0..=INTERRUPT_AFTER_MISSED {
+            pings = us.due_keepalives_at(now);
+            now += KEEPALIVE_INTERVAL;
+        }
+        assert_eq!(us.take_events(), vec![ConnectionEvent::Interrupted(peer_addr)]);
+        assert!(us.is_connected(&peer_addr));
+        
+        // Первый же ответ восстанавливает связь, событие - одно
+        let pong = peer.process_packet(our_addr, HandshakePacket::deserialize(&pings[0].1).unwrap()).unwrap();
+        us.handle_packet(&pong.serialize(), peer_addr);
+        us.handle_packet(&pong.serialize(), peer_addr);
+        assert_eq!(us.take_events(), vec![ConnectionEvent::Resumed(peer_addr)]);
+        
+        // Пир переподключился сам, пока связь была прервана
+        for _ in 0..=INTERRUPT_AFTER_MISSED {
+            us.due_keepalives_at(now);
+            now += KEEPALIVE_INTERVAL;
+        }
+        us.handle_packet(&peer.initiate(our_addr).serialize(), peer_addr);
+        assert_eq!(
+            us.take_events(),
+            vec![ConnectionEvent::Interrupted(peer_addr), ConnectionEvent::Resumed(peer_addr)]
+        );
+    }
+    
+    #[test]
+    fn test_reconnect_backoff() {
+        assert_eq!(reconnect_backoff(1), RECONNECT_BACKOFF);
+        assert_eq!(reconnect_backoff(3), RECONNECT_BACKOFF * 4);
+        assert_eq!(reconnect_backoff(40), RECONNECT_MAX_BACKOFF);
+        
+        let us = HandshakeManager::new("Us".to_string(), 5000, PeerCapabilities::full());
+        let peer = HandshakeManager::new("Studio".to_string(), 5002, PeerCapabilities::full());
+        let our_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let peer_addr: SocketAddr = "192.168.1.20:5002".parse().unwrap();
+        us.handle_packet(&peer.initiate(our_addr).serialize(), peer_addr);
+        let mut now = Instant::now();
+        for _ in 0..=MAX_MISSED_PONGS {
+            us.due_keepalives_at(now);
+            now += KEEPALIVE_INTERVAL;
+        }
+        assert!(!us.is_connected(&peer_addr));
+        
+        // Потерянному пиру Hello сразу, дальше - с растущим интервалом
+        assert!(us.hello_due_at(&peer_addr, now));
+        us.initiate(peer_addr);
+        let sent = Instant::now();
+        assert!(!us.hello_due_at(&peer_addr, sent));
+        assert!(us.hello_due_at(&peer_addr, sent + RECONNECT_BACKOFF));
+        us.initiate(peer_addr);
+        assert!(!us.hello_due_at(&peer_addr, Instant::now() + RECONNECT_BACKOFF));
+        
+        // Ответ пира завершает повторы
+        let ack = peer.handle_packet(&us.initiate(peer_addr).serialize(), our_addr).unwrap().unwrap();
+        us.handle_packet(&ack, peer_addr);
+        assert!(us.is_connected(&peer_addr));
+        assert!(us.hello_due_at(&peer_addr, Instant::now()));
+    }
+    
+    #[test]
+    fn test_connect_before_streaming() {
+        let key = 0x1234_5678;
+        let receiver = Arc::new(HandshakeManager::new(
+            "Receiver".to_string(),
+            5000,
+            PeerCapabilities::receiver_only().with_encryption(key),
+        ));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let receiver_addr = socket.local_addr().unwrap();
+        let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let responder = {
+            let (receiver, running) = (receiver.clone(), running.clone());
+            std::thread::spawn(move || {
+                let mut buf = [0u8; 512];
+                while running.load(std::sync::atomic::Ordering::Relaxed) {
+                    if let Ok((size, from)) = socket.recv_from(&mut buf) {
+                        if let Some(Some(reply)) = receiver.handle_packet(&buf[..size], from) {
+                            socket.send_to(&reply, from).unwrap();
+                        }
+                    }
+                }
+            })
+        };
+        
+        let sender = HandshakeManager::new("Sender".to_string(), 5000, PeerCapabilities::sender_only().with_encryption(key));
+        let state = sender.connect(receiver_addr, Duration::from_secs(5)).unwrap();
+        assert!(matches!(state, HandshakeState::Connected { ref peer_name, .. } if peer_name == "Receiver"));
+        
+        // Другой ключ: получатель отклоняет рукопожатие
+        let stranger = HandshakeManager::new("Stranger".to_string(), 5000, PeerCapabilities::sender_only().with_encryption(1));
+        let state = stranger.connect(receiver_addr, Duration::from_secs(5)).unwrap();
+        assert!(matches!(state, HandshakeState::Failed { .. }));
+        
+        running.store(false, std::sync::atomic::Ordering::Relaxed);
+        responder.join().unwrap();
+    }
 }
@@ -0,0 +1,326 @@
+//! Тест связи между пирами
+//!
+//! Перед началом трансляции веб-интерфейс может проверить канал до пира:
+//! с временного сокета на его аудиопорт идут зонды `ProbeRequest`
+//! несколькими ступенями ([`STAGES`]: частота и размер пакетов как у аудио
+//! с разной длиной кадра и битрейтом), а пир отвечает `ProbeEcho` того же
+//! размера со временем приёма (отвечает любой аудиосокет, см.
+//! `timesync::answer_ping`). По эхо считаются RTT, потери туда и обратно
+//! и джиттер в каждую сторону отдельно: разность часов пиров в разностях
+//! задержек сокращается. По худшим ступеням рекомендуются длина кадра,
+//! битрейт и размер джиттер-буфера.
+//!
+//! ```text
+//! PROBE_REQUEST: [SEQ:4][T0:8][0:8][ЗАПОЛНЕНИЕ]
+//! PROBE_ECHO:    [SEQ:4][T0:8][T1:8][ЗАПОЛНЕНИЕ]
+//! ```
+//!
+//! ID сессии зонда - ID ступени, так что опоздавшее эхо прошлой ступени
+//! не засчитывается следующей.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::constants::DEFAULT_BITRATE;
+use crate::error::NetworkError;
+use crate::network::handshake::{HandshakePacket, HandshakePacketType};
+use crate::network::timesync::media_time_us;
+use crate::network::udp::canonical_addr;
+use crate::protocol::{LinkRecommendation, LinkTestReport, LinkTestStage};
+
+/// Частота и размер зондов одной ступени
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeStage {
+    pub rate_pps: u32,
+    pub size_bytes: usize,
+}
+
+/// Ступени теста: кадры 20, 10 и 5 мс при обычном битрейте и 10 мс при
+/// высоком (около 1 Мбит/с)
+pub const STAGES: [ProbeStage; 4] = [
+    ProbeStage { rate_pps: 50, size_bytes: 200 },
+    ProbeStage { rate_pps: 100, size_bytes: 200 },
+    ProbeStage { rate_pps: 200, size_bytes: 120 },
+    ProbeStage { rate_pps: 100, size_bytes: 1200 },
+];
+
+/// Длительность одной ступени
+pub const STAGE_DURATION: Duration = Duration::from_secs(1);
+
+/// Ожидание опоздавших эхо после последнего зонда ступени
+const LATE_ECHO_WAIT: Duration = Duration::from_millis(300);
+
+/// Наибольший рекомендуемый джиттер-буфер (мс)
+const MAX_JITTER_BUFFER_MS: f32 = 200.0;
+
+/// Времена одного зонда (мкс): отправка, приём пиром (его часы), возврат эхо
+#[derive(Debug, Clone, Copy)]
+struct Echo {
+    t0: u64,
+    t1: u64,
+    t2: u64,
+}
+
+/// Проверить связь с пиром по аудиопорту `target` всеми ступенями
+/// [`STAGES`] (около пяти секунд, блокирует поток)
+pub fn run(target: SocketAddr) -> Result<LinkTestReport, NetworkError> {
+    run_stages(target, &STAGES, STAGE_DURATION)
+}
+
+/// Проверить связь заданными ступенями
+pub fn run_stages(target: SocketAddr, stages: &[ProbeStage], duration: Duration) -> Result<LinkTestReport, NetworkError> {
+    let target = canonical_addr(target);
+    let local: SocketAddr = if target.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+    socket
+        .set_read_timeout(Some(Duration::from_millis(1)))
+        .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+
+    let first_id = rand::random::<u32>() >> 1;
+    let mut results = Vec::with_capacity(stages.len());
+    for (index, stage) in stages.iter().enumerate() {
+        let echoes = probe(&socket, target, first_id.wrapping_add(index as u32), *stage, duration)?;
+        results.push(measure(*stage, &echoes));
+    }
+    if results.iter().all(|stage| stage.received == 0) {
+        return Err(NetworkError::ConnectionFailed(format!("{} did not answer the probes", target)));
+    }
+
+    let report = LinkTestReport {
+        address: target.to_string(),
+        recommendation: recommend(&results),
+        stages: results,
+    };
+    tracing::info!("Тест связи с {}: {:?}", target, report.recommendation);
+    Ok(report)
+}
+
+/// Отправить зонды одной ступени и собрать эхо (None - зонд потерян)
+fn probe(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    stage_id: u32,
+    stage: ProbeStage,
+    duration: Duration,
+) -> Result<Vec<Option<Echo>>, NetworkError> {
+    let count = (stage.rate_pps as f64 * duration.as_secs_f64()).round().max(1.0) as u32;
+    let interval = Duration::from_secs(1) / stage.rate_pps.max(1);
+    let mut echoes = vec![None; count as usize];
+    let mut buf = vec![0u8; 2048];
+
+    let start = Instant::now();
+    let deadline = start + interval * count + LATE_ECHO_WAIT;
+    let mut sent = 0;
+    while Instant::now() < deadline {
+        let now = Instant::now();
+        while sent < count && now >= start + interval * sent {
+            let probe = HandshakePacket::probe_request(stage_id, sent, media_time_us(), stage.size_bytes);
+            match socket.send_to(&probe.serialize(), target) {
+                Ok(_) => {}
+                // Порт пира закрыт (ICMP): зонд считается потерянным
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {}
+                Err(e) => return Err(NetworkError::SendFailed(e.to_string())),
+            }
+            sent += 1;
+        }
+        match socket.recv_from(&mut buf) {
+            Ok((size, from)) if canonical_addr(from) == target => {
+                let t2 = media_time_us();
+                let Some(packet) = HandshakePacket::deserialize(&buf[..size]) else { continue };
+                if packet.packet_type != HandshakePacketType::ProbeEcho || packet.session_id != stage_id {
+                    continue;
+                }
+                if let Some((seq, t0, t1)) = packet.parse_probe() {
+                    if let Some(slot) = echoes.get_mut(seq as usize) {
+                        slot.get_or_insert(Echo { t0, t1, t2 });
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::ConnectionRefused
+                ) => {}
+            Err(e) => return Err(NetworkError::ReceiveFailed(e.to_string())),
+        }
+    }
+    Ok(echoes)
+}
+
+/// Итоги ступени по эхо её зондов
+fn measure(stage: ProbeStage, echoes: &[Option<Echo>]) -> LinkTestStage {
+    let received: Vec<Echo> = echoes.iter().flatten().copied().collect();
+    let rtts: Vec<f32> = received.iter().map(|echo| echo.t2.saturating_sub(echo.t0) as f32 / 1000.0).collect();
+    // Задержка в каждую сторону с точностью до разности часов пиров
+    let up: Vec<i64> = received.iter().map(|echo| echo.t1 as i64 - echo.t0 as i64).collect();
+    let down: Vec<i64> = received.iter().map(|echo| echo.t2 as i64 - echo.t1 as i64).collect();
+
+    let sent = echoes.len() as u32;
+    let lost = sent - received.len() as u32;
+    LinkTestStage {
+        rate_pps: stage.rate_pps,
+        size_bytes: stage.size_bytes,
+        sent,
+        received: received.len() as u32,
+        loss_percent: if sent > 0 { lost as f32 * 100.0 / sent as f32 } else { 0.0 },
+        rtt_min_ms: rtts.iter().copied().reduce(f32::min).unwrap_or(0.0),
+        rtt_avg_ms: if rtts.is_empty() { 0.0 } else { rtts.iter().sum::<f32>() / rtts.len() as f32 },
+        rtt_max_ms: rtts.iter().copied().reduce(f32::max).unwrap_or(0.0),
+        jitter_up_ms: jitter_ms(&up),
+        jitter_down_ms: jitter_ms(&down),
+        delay_spread_ms: spread_ms(&up).max(spread_ms(&down)),
+    }
+}
+
+/// Джиттер по RFC 3550: сглаженное изменение задержки между соседними пакетами
+fn jitter_ms(transit_us: &[i64]) -> f32 {
+    let jitter = transit_us.windows(2).fold(0.0f64, |jitter, pair| {
+        let change = (pair[1] - pair[0]).abs() as f64;
+        jitter + (change - jitter) / 16.0
+    });
+    (jitter / 1000.0) as f32
+}
+
+/// 95-й перцентиль задержки сверх минимальной
+fn spread_ms(transit_us: &[i64]) -> f32 {
+    let Some(&min) = transit_us.iter().min() else {
+        return 0.0;
+    };
+    let mut excess: Vec<i64> = transit_us.iter().map(|transit| transit - min).collect();
+    excess.sort_unstable();
+    let index = ((excess.len() - 1) as f32 * 0.95).round() as usize;
+    excess[index] as f32 / 1000.0
+}
+
+/// Настройки по худшим ступеням, на которые пир ответил
+fn recommend(stages: &[LinkTestStage]) -> LinkRecommendation {
+    let answered = || stages.iter().filter(|stage| stage.received > 0);
+    let worst_loss = answered().map(|stage| stage.loss_percent).fold(0.0, f32::max);
+    let jitter = answered().map(|stage| stage.jitter_up_ms.max(stage.jitter_down_ms)).fold(0.0, f32::max);
+    let spread = answered().map(|stage| stage.delay_spread_ms).fold(0.0, f32::max);
+
+    // Короткий кадр - только на чистом канале: пакетов вдвое больше
+    let frame_size_ms = if worst_loss < 0.5 && jitter < 1.0 {
+        5.0
+    } else if worst_loss < 3.0 && jitter < 5.0 {
+        10.0
+    } else {
+        20.0
+    };
+    // Битрейт - по потерям крупных пакетов
+    let large_loss = answered().max_by_key(|stage| stage.size_bytes).map_or(worst_loss, |stage| stage.loss_percent);
+    let bitrate = if large_loss < 1.0 {
+        256_000
+    } else if large_loss < 5.0 {
+        DEFAULT_BITRATE
+    } else {
+        64_000
+    };
+    // Не меньше двух кадров, с запасом на разброс задержки, кратно 5 мс
+    let buffer = (frame_size_ms + spread).max(2.0 * frame_size_ms).min(MAX_JITTER_BUFFER_MS);
+
+    LinkRecommendation {
+        frame_size_ms,
+        bitrate,
+        jitter_buffer_ms: ((buffer / 5.0).ceil() * 5.0) as u32,
+        fec: worst_loss > 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::timesync::answer_ping;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_probe_packets() {
+        let request = HandshakePacket::probe_request(9, 3, 1_000, 200);
+        let data = request.serialize();
+        assert_eq!(data.len(), 200);
+
+        let echo = HandshakePacket::deserialize(&answer_ping(&data).unwrap()).unwrap();
+        assert_eq!((echo.packet_type, echo.session_id), (HandshakePacketType::ProbeEcho, 9));
+        assert_eq!(echo.serialize().len(), 200);
+        let (seq, t0, t1) = echo.parse_probe().unwrap();
+        assert_eq!((seq, t0), (3, 1_000));
+        assert!(t1 > 0);
+
+        // Echoes aren't echoed back; tiny probes still carry the times
+        assert!(answer_ping(&echo.serialize()).is_none());
+        assert_eq!(HandshakePacket::probe_request(1, 0, 0, 0).serialize().len(), 30);
+    }
+
+    #[test]
+    fn test_measure_and_recommend() {
+        let stage = ProbeStage { rate_pps: 100, size_bytes: 200 };
+        // Peer clock 5 s ahead; every 10th probe lost, every 4th delayed 8 ms upstream
+        let echoes: Vec<Option<Echo>> = (0..100u64)
+            .map(|i| {
+                let t0 = i * 10_000;
+                let delay = if i % 4 == 0 { 9_000 } else { 1_000 };
+                (i % 10 != 9).then_some(Echo { t0, t1: t0 + delay + 5_000_000, t2: t0 + delay + 1_000 })
+            })
+            .collect();
+        let result = measure(stage, &echoes);
+        assert_eq!((result.sent, result.received), (100, 90));
+        assert!((result.loss_percent - 10.0).abs() < 1e-3);
+        assert!((result.rtt_min_ms - 2.0).abs() < 1e-3);
+        assert!((result.rtt_max_ms - 10.0).abs() < 1e-3);
+        assert!((result.delay_spread_ms - 8.0).abs() < 1e-3);
+        assert!(result.jitter_up_ms > 1.0);
+        assert_eq!(result.jitter_down_ms, 0.0);
+
+        let recommendation = recommend(&[result]);
+        assert_eq!(recommendation.frame_size_ms, 20.0);
+        assert_eq!(recommendation.bitrate, 64_000);
+        assert_eq!(recommendation.jitter_buffer_ms, 40);
+        assert!(recommendation.fec);
+
+        // A clean link gets short frames and a high bitrate
+        let clean: Vec<Option<Echo>> = (0..50u64).map(|i| Some(Echo { t0: i * 20_000, t1: i * 20_000 + 300, t2: i * 20_000 + 600 })).collect();
+        let recommendation = recommend(&[measure(stage, &clean)]);
+        assert_eq!(recommendation, LinkRecommendation { frame_size_ms: 5.0, bitrate: 256_000, jitter_buffer_ms: 10, fec: false });
+    }
+
+    #[test]
+    fn test_run_against_responder() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        responder.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let target = responder.local_addr().unwrap();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let running = running.clone();
+            std::thread::spawn(move || {
+                let mut buf = [0u8; 2048];
+                while running.load(Ordering::Relaxed) {
+                    if let Ok((size, from)) = responder.recv_from(&mut buf) {
+                        if let Some(reply) = answer_ping(&buf[..size]) {
+                            let _ = responder.send_to(&reply, from);
+                        }
+                    }
+                }
+            })
+        };
+
+        let stages = [ProbeStage { rate_pps: 100, size_bytes: 200 }, ProbeStage { rate_pps: 50, size_bytes: 1200 }];
+        let report = run_stages(target, &stages, Duration::from_millis(200)).unwrap();
+        running.store(false, Ordering::Relaxed);
+        thread.join().unwrap();
+
+        assert_eq!(report.address, target.to_string());
+        assert_eq!(report.stages.len(), 2);
+        assert_eq!((report.stages[0].sent, report.stages[1].sent), (20, 10));
+        assert!(report.stages.iter().all(|stage| stage.received > 0));
+
+        // Nobody answers on a closed port
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        assert!(run_stages(closed, &stages[..1], Duration::from_millis(50)).is_err());
+    }
+}
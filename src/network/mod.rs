@@ -5,15 +5,57 @@
 //! - Отправки и приёма аудио
 //! - Автоматического обнаружения пиров
 //! - Протокола рукопожатия для синхронизации
+//! - Учёта трафика по пирам
+//! - Шифрования аудио общим ключом (PSK)
+//! - Синхронизации часов для измерения сквозной задержки
+//! - Обратной связи о потерях для адаптивного битрейта
+//! - Контроля перегрузки канала по задержке и потерям
+//! - Подписки получателя на выбранные треки
+//! - Совместимого режима RTP/RTCP (Opus по RFC 7587)
+//! - Автоподстройки буфера приёма по счётчику потерь сокета
+//! - Планирования потоков (MMCSS) и QoS-разметки (qWave) в Windows
+//! - Запасного транспорта по TCP для сетей, где UDP блокируется
+//! - Транспорта QUIC (датаграммы с шифрованием и контролем перегрузки)
+//! - Журнала управляющих пакетов для отладки сопряжения
+//! - Передачи файлов (конфигураций, записей) между пирами
+//! - Сопряжения пиров (allowlist, одноразовый PIN)
+//! - Теста связи с пиром перед трансляцией (RTT, потери, джиттер)
+//! - Имитации плохой сети на приёме (потери, задержка, дубли, переупорядочивание)
+//! - Записи принятых пакетов в файл для воспроизведения (`replay`)
+//! - Захвата пакетов в pcapng для анализа в Wireshark
 
 pub mod udp;
 pub mod sender;
 pub mod receiver;
 pub mod discovery;
 pub mod handshake;
+pub mod peers;
+pub mod crypto;
+pub mod timesync;
+pub mod feedback;
+pub mod congestion;
+pub mod subscription;
+pub mod rtp;
+pub mod buffer_tuning;
+pub mod qos;
+pub mod transport;
+pub mod quic;
+pub mod packet_log;
+pub mod netsim;
+pub mod packet_dump;
+pub mod pcap;
+pub mod file_transfer;
+pub mod pairing;
+pub mod link_test;
 
-pub use udp::{UdpSocket, create_socket};
+pub use udp::{UdpSocket, create_socket, canonical_addr, target_for_socket};
 pub use sender::AudioSender;
 pub use receiver::AudioReceiver;
 pub use discovery::{DiscoveryService, DiscoveredPeer, get_local_addresses, get_best_local_address};
 pub use handshake::{HandshakeManager, HandshakePacket, PeerCapabilities, HandshakeState};
+pub use peers::{PeerRegistry, BandwidthMeter};
+pub use crypto::PacketCipher;
+pub use timesync::{media_time_us, TimeSync};
+pub use feedback::{FeedbackInbox, TrackFeedback};
+pub use file_transfer::FileTransfers;
+pub use subscription::{Subscription, TrackCatalog, TrackSubscriber};
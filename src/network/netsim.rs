@@ -0,0 +1,271 @@
+//! Simulated bad network for testing
+//!
+//! With `[network.simulate]` in the configuration file (or
+//! `LAN_AUDIO_SIMULATE=loss=5,delay=20,jitter=10,duplicate=1,reorder=2`)
+//! the receiver passes every audio datagram through a [`NetworkSimulator`]
+//! before handling it: packets are dropped, delayed by a fixed time plus
+//! random jitter, delivered twice or held back behind the next packet, with
+//! the configured probabilities. The jitter buffer, FEC, DRED and loss
+//! reports can then be watched at work on a clean LAN or over localhost.
+//! Handshake and control packets are not touched, so peers still connect.
+
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::NetworkSimulation;
+
+/// A packet held back for reordering is released after this long even if
+/// no packet follows it
+const MAX_REORDER_HOLD: Duration = Duration::from_millis(50);
+
+/// Datagram waiting for its release time
+struct Delayed {
+    release_at: Instant,
+    /// Arrival order, keeps packets released at the same time in order
+    order: u64,
+    data: Bytes,
+    addr: SocketAddr,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        (self.release_at, self.order) == (other.release_at, other.order)
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.release_at, self.order).cmp(&(other.release_at, other.order))
+    }
+}
+
+/// Counters of what the simulator did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulatorStats {
+    pub passed: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+/// Impairs a stream of datagrams as configured
+pub struct NetworkSimulator {
+    config: NetworkSimulation,
+    rng: StdRng,
+    queue: BinaryHeap<Reverse<Delayed>>,
+    /// Packet waiting for the next one to overtake it, and since when
+    held: Option<(Bytes, SocketAddr, Instant)>,
+    next_order: u64,
+    stats: SimulatorStats,
+}
+
+impl NetworkSimulator {
+    pub fn new(config: NetworkSimulation) -> Self {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    fn with_rng(config: NetworkSimulation, rng: StdRng) -> Self {
+        Self {
+            config,
+            rng,
+            queue: BinaryHeap::new(),
+            held: None,
+            next_order: 0,
+            stats: SimulatorStats::default(),
+        }
+    }
+
+    /// A datagram arrived from the socket at `now`
+    pub fn push(&mut self, data: &[u8], addr: SocketAddr, now: Instant) {
+        if self.chance(self.config.loss_percent) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let data = Bytes::copy_from_slice(data);
+
+        // Held back: released right after the next packet
+        if self.held.is_none() && self.chance(self.config.reorder_percent) {
+            self.stats.reordered += 1;
+            self.held = Some((data, addr, now));
+            return;
+        }
+
+        if self.chance(self.config.duplicate_percent) {
+            self.stats.duplicated += 1;
+            self.schedule(data.clone(), addr, now);
+        }
+        let release_at = self.schedule(data, addr, now);
+        if let Some((held, held_addr, _)) = self.held.take() {
+            self.enqueue(held, held_addr, release_at);
+        }
+    }
+
+    /// Next datagram due by `now`
+    pub fn pop_ready(&mut self, now: Instant) -> Option<(Bytes, SocketAddr)> {
+        if let Some((_, _, since)) = self.held {
+            if now.saturating_duration_since(since) >= MAX_REORDER_HOLD {
+                let (data, addr, _) = self.held.take().expect("checked above");
+                self.enqueue(data, addr, now);
+            }
+        }
+        if self.queue.peek().is_some_and(|Reverse(next)| next.release_at <= now) {
+            let Reverse(packet) = self.queue.pop().expect("peeked above");
+            self.stats.passed += 1;
+            return Some((packet.data, packet.addr));
+        }
+        None
+    }
+
+    /// Datagrams not yet released
+    pub fn pending(&self) -> usize {
+        self.queue.len() + usize::from(self.held.is_some())
+    }
+
+    pub fn stats(&self) -> SimulatorStats {
+        self.stats
+    }
+
+    /// Queue with the configured delay and jitter; returns the release time
+    fn schedule(&mut self, data: Bytes, addr: SocketAddr, now: Instant) -> Instant {
+        let jitter_ms = match self.config.jitter_ms {
+            0 => 0.0,
+            jitter => self.rng.gen_range(0.0..jitter as f64),
+        };
+        let delay = Duration::from_secs_f64((self.config.delay_ms as f64 + jitter_ms) / 1000.0);
+        let release_at = now + delay;
+        self.enqueue(data, addr, release_at);
+        release_at
+    }
+
+    fn enqueue(&mut self, data: Bytes, addr: SocketAddr, release_at: Instant) {
+        self.queue.push(Reverse(Delayed {
+            release_at,
+            order: self.next_order,
+            data,
+            addr,
+        }));
+        self.next_order += 1;
+    }
+
+    fn chance(&mut self, percent: f32) -> bool {
+        percent > 0.0 && self.rng.gen_bool((percent as f64 / 100.0).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulator(config: NetworkSimulation) -> NetworkSimulator {
+        NetworkSimulator::with_rng(config, StdRng::seed_from_u64(7))
+    }
+
+    fn addr() -> SocketAddr {
+        "192.168.1.20:5000".parse().unwrap()
+    }
+
+    /// Push packets 0..count one ms apart, then collect them in release order
+    fn run(simulator: &mut NetworkSimulator, count: u8) -> Vec<u8> {
+        let start = Instant::now();
+        for i in 0..count {
+            simulator.push(&[i], addr(), start + Duration::from_millis(i as u64));
+        }
+        let mut released = Vec::new();
+        let end = start + Duration::from_secs(1);
+        while let Some((data, _)) = simulator.pop_ready(end) {
+            released.push(data[0]);
+        }
+        released
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let simulation: NetworkSimulation = "loss=5%, delay=20ms,jitter=10,duplicate=1,reorder=2.5".parse().unwrap();
+        assert_eq!(
+            simulation,
+            NetworkSimulation {
+                loss_percent: 5.0,
+                delay_ms: 20,
+                jitter_ms: 10,
+                duplicate_percent: 1.0,
+                reorder_percent: 2.5,
+            }
+        );
+        assert!(simulation.is_active());
+        assert!(!"".parse::<NetworkSimulation>().unwrap().is_active());
+        assert!("loss=150".parse::<NetworkSimulation>().is_err());
+        assert!("delay=-1".parse::<NetworkSimulation>().is_err());
+        assert!("latency=5".parse::<NetworkSimulation>().is_err());
+        assert!("loss".parse::<NetworkSimulation>().is_err());
+    }
+
+    #[test]
+    fn test_delay_keeps_order() {
+        let mut sim = simulator(NetworkSimulation {
+            delay_ms: 20,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        sim.push(&[1, 2, 3], addr(), start);
+        assert!(sim.pop_ready(start + Duration::from_millis(19)).is_none());
+        let (data, from) = sim.pop_ready(start + Duration::from_millis(20)).unwrap();
+        assert_eq!((&data[..], from), (&[1u8, 2, 3][..], addr()));
+        assert_eq!(sim.pending(), 0);
+
+        assert_eq!(run(&mut sim, 50), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_loss_and_duplicates() {
+        let mut sim = simulator(NetworkSimulation {
+            loss_percent: 20.0,
+            duplicate_percent: 10.0,
+            ..Default::default()
+        });
+        let released = run(&mut sim, 200);
+        let stats = sim.stats();
+        assert!((20..60).contains(&stats.dropped), "{:?}", stats);
+        assert!((5..35).contains(&stats.duplicated), "{:?}", stats);
+        assert_eq!(released.len() as u64, 200 - stats.dropped + stats.duplicated);
+        assert_eq!(stats.passed, released.len() as u64);
+    }
+
+    #[test]
+    fn test_reorder_and_jitter() {
+        // A held packet comes right after the next one
+        let mut sim = simulator(NetworkSimulation {
+            reorder_percent: 100.0,
+            ..Default::default()
+        });
+        assert_eq!(run(&mut sim, 4), vec![1, 0, 3, 2]);
+
+        // Or on its own once nothing follows
+        let start = Instant::now();
+        sim.push(&[9], addr(), start);
+        assert!(sim.pop_ready(start + Duration::from_millis(10)).is_none());
+        assert_eq!(sim.pop_ready(start + MAX_REORDER_HOLD).unwrap().0[0], 9);
+
+        // Jitter larger than the packet interval reorders too
+        let mut sim = simulator(NetworkSimulation {
+            delay_ms: 5,
+            jitter_ms: 30,
+            ..Default::default()
+        });
+        let released = run(&mut sim, 100);
+        assert_eq!(released.len(), 100);
+        assert_ne!(released, (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,215 @@
+//! Dump of received audio packets for offline replay
+//!
+//! With `NetworkConfig::packet_dump` (`--dump-packets FILE`,
+//! `LAN_AUDIO_PACKET_DUMP`) the receiver writes every audio packet it
+//! accepts to a file, after reassembly and decryption, together with the
+//! time it arrived and its source. `replay FILE` feeds the dump back
+//! through decoding, the jitter buffer and playback at the original timing
+//! (see `replay`), so a glitch a user reports can be reproduced from their
+//! dump on another machine.
+//!
+//! File layout: the [`MAGIC`] and a version byte, then one record per
+//! packet: arrival time since the dump started (µs, u64 LE), source
+//! address as text with a length byte, packet length (u32 LE) and the
+//! packet serialized as an `AudioPacket`.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::constants::PACKET_DUMP_ENV_VAR;
+use crate::network::receiver::ReceivedPacket;
+use crate::protocol::{AudioPacket, PacketFlags};
+
+/// Start of a dump file
+pub const MAGIC: &[u8; 4] = b"LAPD";
+
+/// Version of the record layout
+const VERSION: u8 = 1;
+
+/// Largest packet a record may hold (a reassembled frame)
+const MAX_PACKET_SIZE: usize = 1 << 20;
+
+/// Dump file requested with `LAN_AUDIO_PACKET_DUMP`
+pub fn path_from_env() -> Option<std::path::PathBuf> {
+    std::env::var_os(PACKET_DUMP_ENV_VAR).filter(|path| !path.is_empty()).map(Into::into)
+}
+
+/// One packet read back from a dump
+#[derive(Debug, Clone)]
+pub struct DumpedPacket {
+    /// Arrival time since the dump started
+    pub offset: Duration,
+    pub packet: ReceivedPacket,
+}
+
+/// Writes received packets to a dump file
+pub struct PacketDump {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl PacketDump {
+    /// Create (or truncate) the dump file
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    /// Append a packet (its `receive_time` is the arrival time)
+    pub fn write(&mut self, packet: &ReceivedPacket) -> io::Result<()> {
+        let offset = packet.receive_time.saturating_duration_since(self.started);
+        self.writer.write_all(&encode_record(offset, packet))
+    }
+
+    /// Write out buffered records
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for PacketDump {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Read every packet of a dump file, in arrival order
+pub fn read(path: &Path) -> io::Result<Vec<DumpedPacket>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    decode(Bytes::from(data))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn encode_record(offset: Duration, packet: &ReceivedPacket) -> Bytes {
+    let serialized = AudioPacket {
+        track_id: packet.track_id,
+        flags: PacketFlags::new()
+            .set_stereo(packet.is_stereo)
+            .set_fec(packet.has_fec)
+            .set_keyframe(packet.is_keyframe)
+            .set_probe(packet.is_probe)
+            .set_codec(packet.codec),
+        sequence: packet.sequence,
+        timestamp: packet.timestamp,
+        payload: packet.payload.clone(),
+    }
+    .serialize();
+    let source = packet.source.map(|source| source.to_string()).unwrap_or_default();
+
+    let mut buf = BytesMut::with_capacity(8 + 1 + source.len() + 4 + serialized.len());
+    buf.put_u64_le(offset.as_micros() as u64);
+    buf.put_u8(source.len() as u8);
+    buf.put_slice(source.as_bytes());
+    buf.put_u32_le(serialized.len() as u32);
+    buf.put_slice(&serialized);
+    buf.freeze()
+}
+
+fn decode(mut data: Bytes) -> io::Result<Vec<DumpedPacket>> {
+    if data.len() < MAGIC.len() + 1 || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a packet dump"));
+    }
+    data.advance(MAGIC.len());
+    if data.get_u8() != VERSION {
+        return Err(invalid("unsupported packet dump version"));
+    }
+
+    // Arrival times are relative to the replay start
+    let replay_start = Instant::now();
+    let mut packets = Vec::new();
+    while data.has_remaining() {
+        if data.remaining() < 9 {
+            break;
+        }
+        let offset = Duration::from_micros(data.get_u64_le());
+        let source_len = data.get_u8() as usize;
+        if data.remaining() < source_len + 4 {
+            break;
+        }
+        let source = std::str::from_utf8(&data[..source_len])
+            .ok()
+            .and_then(|source| source.parse::<SocketAddr>().ok());
+        data.advance(source_len);
+        let len = data.get_u32_le() as usize;
+        if len > MAX_PACKET_SIZE {
+            return Err(invalid("corrupt packet dump record"));
+        }
+        // The last record is cut short when the receiver was killed
+        if data.remaining() < len {
+            break;
+        }
+        let packet = AudioPacket::deserialize(data.split_to(len)).ok_or_else(|| invalid("corrupt audio packet in dump"))?;
+        let mut packet = ReceivedPacket::from(packet);
+        packet.source = source;
+        packet.receive_time = replay_start + offset;
+        packets.push(DumpedPacket { offset, packet });
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Codec;
+
+    fn packet(sequence: u32, receive_time: Instant) -> ReceivedPacket {
+        let mut packet = ReceivedPacket::from(AudioPacket::new(2, sequence, 1_000 * sequence as u64, Bytes::from(vec![sequence as u8; 40])));
+        packet.is_stereo = true;
+        packet.has_fec = true;
+        packet.is_probe = sequence == 1;
+        packet.codec = Codec::Opus;
+        packet.receive_time = receive_time;
+        packet.source = Some("192.168.1.20:5000".parse().unwrap());
+        packet
+    }
+
+    #[test]
+    fn test_dump_round_trip() {
+        let path = std::env::temp_dir().join(format!("lan-audio-dump-{}.lapd", std::process::id()));
+        let mut dump = PacketDump::create(&path).unwrap();
+        for sequence in 0..3 {
+            dump.write(&packet(sequence, dump.started + Duration::from_millis(10 * sequence as u64))).unwrap();
+        }
+        let mut local = packet(3, dump.started + Duration::from_millis(35));
+        local.source = None;
+        local.payload = Bytes::new();
+        dump.write(&local).unwrap();
+        drop(dump);
+
+        let packets = read(&path).unwrap();
+        assert_eq!(packets.len(), 4);
+        let offsets: Vec<u64> = packets.iter().map(|dumped| dumped.offset.as_millis() as u64).collect();
+        assert_eq!(offsets, vec![0, 10, 20, 35]);
+        let probe = &packets[1].packet;
+        assert_eq!((probe.track_id, probe.sequence, probe.timestamp), (2, 1, 1_000));
+        assert!(probe.is_stereo && probe.has_fec && probe.is_probe && !probe.is_keyframe);
+        assert_eq!(&probe.payload[..], &[1u8; 40][..]);
+        assert_eq!(probe.source, Some("192.168.1.20:5000".parse().unwrap()));
+        assert_eq!((packets[3].packet.source, packets[3].packet.payload.len()), (None, 0));
+        assert_eq!(packets[2].packet.receive_time - packets[0].packet.receive_time, Duration::from_millis(20));
+
+        // A record cut short by a kill is dropped, the rest is read
+        let mut data = std::fs::read(&path).unwrap();
+        data.truncate(data.len() - 5);
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(read(&path).unwrap().len(), 3);
+
+        std::fs::write(&path, b"RIFF....").unwrap();
+        assert!(read(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
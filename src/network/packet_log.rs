@@ -0,0 +1,262 @@
+//! Control-plane packet log for debugging pairing
+//!
+//! When two machines don't find or pair with each other, the log shows
+//! which discovery beacons, mDNS messages and handshake packets (Hello,
+//! SyncRequest, Subscribe, pings, feedback...) actually went out and came
+//! in, and from which address. Only control packets are recorded, by
+//! their header fields; audio packets and payloads never are.
+//!
+//! Recording is off by default. `NetworkConfig::debug_capture` or
+//! `LAN_AUDIO_DEBUG_CAPTURE=1` turns it on; the newest [`CAPACITY`]
+//! packets are kept in memory and served at `GET /api/debug/packets`
+//! (`DELETE` clears them).
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::constants::DEBUG_CAPTURE_ENV_VAR;
+use crate::network::discovery::{parse_mdns_message, DiscoveryPacket};
+use crate::network::handshake::{HandshakePacket, HandshakePacketType};
+
+/// Packets kept in the log
+pub const CAPACITY: usize = 512;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LOG: PacketLog = PacketLog::new();
+
+/// Whether a packet was sent or received
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Protocol of a logged packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlProtocol {
+    Handshake,
+    Discovery,
+    Mdns,
+}
+
+/// Header of one control packet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedPacket {
+    /// Wall-clock time (µs since the Unix epoch)
+    pub time_us: u64,
+    pub direction: Direction,
+    /// Address the packet went to or came from
+    pub peer: SocketAddr,
+    pub protocol: ControlProtocol,
+    /// Packet type ("Hello", "SenderBeacon", "query"...)
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<u32>,
+    /// Datagram size in bytes
+    pub size: usize,
+    /// Decoded header fields
+    pub detail: String,
+}
+
+/// Whether `LAN_AUDIO_DEBUG_CAPTURE` asks for the packet log
+pub fn requested_by_env() -> bool {
+    std::env::var(DEBUG_CAPTURE_ENV_VAR).is_ok_and(|value| !matches!(value.as_str(), "" | "0" | "false"))
+}
+
+/// Start or stop recording
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether packets are being recorded
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record `data` if recording is on and it is a control packet
+/// (anything else is ignored)
+pub fn record(direction: Direction, peer: SocketAddr, data: &[u8]) {
+    if is_enabled() {
+        LOG.record(direction, peer, data);
+    }
+}
+
+/// Logged packets, oldest first
+pub fn snapshot() -> Vec<LoggedPacket> {
+    LOG.snapshot()
+}
+
+/// Forget all logged packets
+pub fn clear() {
+    LOG.clear();
+}
+
+/// Ring of the newest [`CAPACITY`] control packets
+struct PacketLog {
+    packets: Mutex<VecDeque<LoggedPacket>>,
+}
+
+impl PacketLog {
+    const fn new() -> Self {
+        Self {
+            packets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn record(&self, direction: Direction, peer: SocketAddr, data: &[u8]) {
+        let Some((protocol, kind, session_id, detail)) = describe(data) else {
+            return;
+        };
+        let packet = LoggedPacket {
+            time_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_micros() as u64),
+            direction,
+            peer,
+            protocol,
+            kind,
+            session_id,
+            size: data.len(),
+            detail,
+        };
+        let mut packets = self.packets.lock();
+        if packets.len() == CAPACITY {
+            packets.pop_front();
+        }
+        packets.push_back(packet);
+    }
+
+    fn snapshot(&self) -> Vec<LoggedPacket> {
+        self.packets.lock().iter().cloned().collect()
+    }
+
+    fn clear(&self) {
+        self.packets.lock().clear();
+    }
+}
+
+type Description = (ControlProtocol, String, Option<u32>, String);
+
+fn describe(data: &[u8]) -> Option<Description> {
+    if HandshakePacket::has_magic(data) {
+        return Some(describe_handshake(data));
+    }
+    if let Some(packet) = DiscoveryPacket::deserialize(data) {
+        let detail = format!(
+            "name={:?} audio_port={} ipv6={:?} instance={}",
+            packet.name, packet.audio_port, packet.ipv6_addresses, packet.instance_id
+        );
+        return Some((ControlProtocol::Discovery, format!("{:?}", packet.packet_type), None, detail));
+    }
+    let message = parse_mdns_message(data)?;
+    // Other services on the mDNS group are not ours to log
+    if !message.queries_service && message.announcements.is_empty() {
+        return None;
+    }
+    let kind = if message.is_response { "response" } else { "query" };
+    let detail = message
+        .announcements
+        .iter()
+        .map(|found| {
+            format!(
+                "{:?} audio_port={} sender={} address={:?}",
+                found.instance, found.audio_port, found.is_sender, found.address
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    Some((ControlProtocol::Mdns, kind.to_string(), None, detail))
+}
+
+fn describe_handshake(data: &[u8]) -> Description {
+    let Some(packet) = HandshakePacket::deserialize(data) else {
+        // The magic matched but the peer speaks another version or type
+        let detail = format!("version={:?} type={:?}", data.get(4), data.get(5));
+        return (ControlProtocol::Handshake, "Unknown".to_string(), None, detail);
+    };
+    let detail = match packet.packet_type {
+        HandshakePacketType::Hello | HandshakePacketType::HelloAck => match packet.parse_hello() {
+            Some((audio_port, capabilities, name)) => {
+                format!("name={:?} audio_port={} {:?}", name, audio_port, capabilities)
+            }
+            None => "malformed".to_string(),
+        },
+        HandshakePacketType::SyncRequest => format!(
+            "accepts_plaintext={} {:?}",
+            packet.accepts_plaintext(),
+            packet.sync_capabilities()
+        ),
+        HandshakePacketType::SyncResponse => match packet.parse_sync_response() {
+            Some(tracks) => format!("tracks={:?}", tracks.iter().map(|track| track.track_id).collect::<Vec<_>>()),
+            None => "malformed".to_string(),
+        },
+        HandshakePacketType::Subscribe => format!("{:?}", packet.parse_subscribe()),
+        HandshakePacketType::TrackAdded => match packet.parse_track_added() {
+            Some(track) => format!("track={} name={:?}", track.track_id, track.name),
+            None => "malformed".to_string(),
+        },
+        HandshakePacketType::TrackRemoved => format!("track={:?}", packet.parse_track_removed()),
+        HandshakePacketType::Feedback => match packet.parse_feedback() {
+            Some(reports) => format!("reports={}", reports.len()),
+            None => "malformed".to_string(),
+        },
+        HandshakePacketType::ErrorPacket => format!("message={:?}", packet.parse_error()),
+        _ => format!("payload={} bytes", packet.payload.len()),
+    };
+    (ControlProtocol::Handshake, format!("{:?}", packet.packet_type), Some(packet.session_id), detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::discovery::DiscoveryPacketType;
+    use crate::network::handshake::PeerCapabilities;
+
+    #[test]
+    fn test_describes_control_packets_only() {
+        let hello = HandshakePacket::hello(7, "Studio", 5000, PeerCapabilities::full()).serialize();
+        let (protocol, kind, session_id, detail) = describe(&hello).unwrap();
+        assert_eq!(protocol, ControlProtocol::Handshake);
+        assert_eq!(kind, "Hello");
+        assert_eq!(session_id, Some(7));
+        assert!(detail.contains("\"Studio\"") && detail.contains("audio_port=5000"));
+
+        let beacon = DiscoveryPacket::new(DiscoveryPacketType::ReceiverBeacon, 5000, "Desk".to_string()).serialize();
+        let (protocol, kind, _, _) = describe(&beacon).unwrap();
+        assert_eq!(protocol, ControlProtocol::Discovery);
+        assert_eq!(kind, "ReceiverBeacon");
+
+        // Another protocol version is still logged
+        let mut future = HandshakePacket::ping(1).serialize().to_vec();
+        future[4] = 9;
+        assert_eq!(describe(&future).unwrap().1, "Unknown");
+
+        // Audio is never logged
+        assert!(describe(&[0u8; 64]).is_none());
+    }
+
+    #[test]
+    fn test_log_keeps_newest_packets() {
+        let peer: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let log = PacketLog::new();
+
+        log.record(Direction::Received, peer, &HandshakePacket::pong(0).serialize());
+        for _ in 0..CAPACITY {
+            log.record(Direction::Sent, peer, &HandshakePacket::ping(0).serialize());
+        }
+        log.record(Direction::Received, peer, &[0u8; 32]);
+
+        let packets = log.snapshot();
+        assert_eq!(packets.len(), CAPACITY);
+        assert!(packets.iter().all(|packet| packet.direction == Direction::Sent && packet.kind == "Ping"));
+        assert_eq!(packets[0].peer, peer);
+        log.clear();
+        assert!(log.snapshot().is_empty());
+    }
+}
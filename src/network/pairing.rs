@@ -0,0 +1,899 @@
+//! Сопряжение пиров: allowlist и одноразовый PIN
+//!
+//! По умолчанию рукопожатие проходит любой совместимый пир. С
+//! `network.pairing.required` Hello принимается только от сопряжённых
+//! пиров, незнакомому пиру отвечается пакетом Error.
+//!
+//! У каждого узла есть ключ - случайный идентификатор (`pairing.key`,
+//! создаётся при первом запуске и сохраняется в файл конфигурации), он
+//! передаётся в хвосте Hello и HelloAck. Ключ виден в сети и пира не
+//! подтверждает: подтверждает секрет пары, который узлы получают при
+//! сопряжении по PIN и хранят в allowlist (`allowed_peers`: ключ пира и
+//! секрет пары, `ключ:секрет`).
+//!
+//! На Hello с ключом сопряжённого пира узел отвечает вызовом
+//! (`PairingChallenge`) со случайным nonce, и пир повторяет Hello с
+//! HMAC-SHA256 на секрете пары от пакета, nonce вызова и своего nonce.
+//! Вызов одноразовый, так что перехваченный Hello повторить нельзя.
+//! HelloAck подписывается тем же секретом: инициатор тоже проверяет, с
+//! кем соединился.
+//!
+//! Сопряжение по PIN: узел показывает PIN в веб-интерфейсе и в логе, на
+//! втором узле PIN вводится для этого пира. Его Hello несёт эфемерный
+//! ключ X25519, вызов - эфемерный ключ ответчика; из общего секрета
+//! Диффи-Хеллмана, PIN и ключей обоих узлов выводятся секрет пары и ключ
+//! доказательства, и повторный Hello несёт доказательство знания PIN.
+//! PIN по сети не передаётся, и подобрать его по записанным пакетам
+//! нельзя: для проверки догадки нужен секрет Диффи-Хеллмана. Принятое
+//! доказательство гасит PIN (как и несколько неверных), и оба узла
+//! сохраняют секрет пары. От активного посредника, через которого идёт
+//! само сопряжение, PIN не защищает - сопрягайте узлы в доверенной сети.
+//!
+//! Пакеты вне рукопожатия (передача файлов) пир, чей Hello подтверждён,
+//! подписывает тем же секретом (`sign_packet`): получатель проверяет
+//! подпись, а не адрес отправителя.
+//!
+//! ```text
+//! Hello:  ... [Name] [Fingerprint(4), с PSK] [Key(8)] [Mode(1)] [...] [QUIC port(2)]
+//!   Mode 0: -                          только ключ
+//!   Mode 1: [Ephemeral(32)]            начало сопряжения по PIN
+//!   Mode 2: [Nonce(8)] [Proof(16)]     доказательство PIN
+//!   Mode 3: [Nonce(8)] [Mac(16)]       подпись секретом пары (и в HelloAck)
+//! PairingChallenge: [Key(8)] [Nonce(8)] [Ephemeral(32), при сопряжении по PIN]
+//! Подписанный пакет: [Packet] [Key(8)] [Mac(16)]
+//! ```
+
+use hmac::{Hmac, Mac};
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::PairingConfig;
+use crate::config_store::ConfigStore;
+use crate::protocol::PairingStatus;
+
+/// Сколько действует PIN
+pub const PIN_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// Цифр в PIN
+pub const PIN_DIGITS: usize = 6;
+
+/// Размер ключа узла
+pub const KEY_SIZE: usize = 8;
+
+/// Размер секрета пары
+pub const SECRET_SIZE: usize = 32;
+
+/// Размер nonce вызова и Hello
+pub const NONCE_SIZE: usize = 8;
+
+/// Размер подписи и доказательства PIN
+pub const TAG_SIZE: usize = 16;
+
+/// Размер эфемерного ключа X25519
+pub const EPHEMERAL_SIZE: usize = 32;
+
+/// Неверных доказательств, после которых PIN гасится
+const MAX_PIN_ATTEMPTS: u32 = 5;
+
+/// Сколько вызов ждёт повторного Hello
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(10);
+
+/// Вызовов, ждущих ответа (новый вытесняет самый старый)
+const MAX_CHALLENGES: usize = 32;
+
+/// Разделение доменов для ключей сопряжения
+const PAIRING_CONTEXT: &[u8] = b"lan-audio-streamer/pairing/v2";
+
+const MODE_NONE: u8 = 0;
+const MODE_START: u8 = 1;
+const MODE_PROOF: u8 = 2;
+const MODE_MAC: u8 = 3;
+
+/// Чем подтверждён хвост Hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingAuth {
+    /// Только ключ
+    None,
+    /// Эфемерный ключ X25519: начало сопряжения по PIN
+    Start([u8; EPHEMERAL_SIZE]),
+    /// Доказательство знания PIN
+    Proof { nonce: [u8; NONCE_SIZE], tag: [u8; TAG_SIZE] },
+    /// Подпись секретом пары
+    Mac { nonce: [u8; NONCE_SIZE], tag: [u8; TAG_SIZE] },
+}
+
+/// Ключ узла и подтверждение из хвоста Hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairingHello {
+    pub key: [u8; KEY_SIZE],
+    pub auth: PairingAuth,
+}
+
+impl PairingHello {
+    /// Байты хвоста Hello
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(KEY_SIZE + 1 + EPHEMERAL_SIZE);
+        data.extend_from_slice(&self.key);
+        match self.auth {
+            PairingAuth::None => data.push(MODE_NONE),
+            PairingAuth::Start(ephemeral) => {
+                data.push(MODE_START);
+                data.extend_from_slice(&ephemeral);
+            }
+            PairingAuth::Proof { nonce, tag } => {
+                data.push(MODE_PROOF);
+                data.extend_from_slice(&nonce);
+                data.extend_from_slice(&tag);
+            }
+            PairingAuth::Mac { nonce, tag } => {
+                data.push(MODE_MAC);
+                data.extend_from_slice(&nonce);
+                data.extend_from_slice(&tag);
+            }
+        }
+        data
+    }
+
+    /// Разобрать хвост Hello (None - пир без сопряжения, старая версия)
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let key = data.get(..KEY_SIZE)?.try_into().ok()?;
+        let mode = *data.get(KEY_SIZE)?;
+        let body = &data[KEY_SIZE + 1..];
+        let auth = match mode {
+            MODE_NONE => PairingAuth::None,
+            MODE_START => PairingAuth::Start(body.get(..EPHEMERAL_SIZE)?.try_into().ok()?),
+            MODE_PROOF | MODE_MAC => {
+                let nonce = body.get(..NONCE_SIZE)?.try_into().ok()?;
+                let tag = body.get(NONCE_SIZE..NONCE_SIZE + TAG_SIZE)?.try_into().ok()?;
+                if mode == MODE_PROOF {
+                    PairingAuth::Proof { nonce, tag }
+                } else {
+                    PairingAuth::Mac { nonce, tag }
+                }
+            }
+            _ => return None,
+        };
+        Some(Self { key, auth })
+    }
+}
+
+/// Вызов узла, которому нужно подтверждение Hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairingChallenge {
+    /// Ключ узла, выдавшего вызов
+    pub key: [u8; KEY_SIZE],
+    pub nonce: [u8; NONCE_SIZE],
+    /// Эфемерный ключ ответчика, если начато сопряжение по PIN
+    pub ephemeral: Option<[u8; EPHEMERAL_SIZE]>,
+}
+
+impl PairingChallenge {
+    /// Полезная нагрузка пакета PairingChallenge
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(KEY_SIZE + NONCE_SIZE + EPHEMERAL_SIZE);
+        data.extend_from_slice(&self.key);
+        data.extend_from_slice(&self.nonce);
+        if let Some(ephemeral) = self.ephemeral {
+            data.extend_from_slice(&ephemeral);
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            key: data.get(..KEY_SIZE)?.try_into().ok()?,
+            nonce: data.get(KEY_SIZE..KEY_SIZE + NONCE_SIZE)?.try_into().ok()?,
+            ephemeral: data
+                .get(KEY_SIZE + NONCE_SIZE..KEY_SIZE + NONCE_SIZE + EPHEMERAL_SIZE)
+                .and_then(|ephemeral| ephemeral.try_into().ok()),
+        })
+    }
+}
+
+/// Итог проверки Hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelloCheck {
+    /// Принять; HelloAck подписывается секретом пары, если он есть
+    Accept(Option<AckKey>),
+    /// Ответить вызовом и ждать повторного Hello
+    Challenge(PairingChallenge),
+    /// Отклонить с причиной для пакета Error
+    Reject(&'static str),
+}
+
+/// Чем подписать HelloAck: секрет пары и nonce принятого Hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckKey {
+    secret: [u8; SECRET_SIZE],
+    hello_nonce: [u8; NONCE_SIZE],
+}
+
+/// Показанный PIN
+struct Pin {
+    code: String,
+    expires_at: Instant,
+    failed_attempts: u32,
+}
+
+/// Ключи, выведенные при сопряжении по PIN
+#[derive(Clone, Copy)]
+struct PinKeys {
+    secret: [u8; SECRET_SIZE],
+    proof: [u8; SECRET_SIZE],
+}
+
+/// Вызов, выданный пиру с ключом `peer_key`
+struct Challenge {
+    peer_key: [u8; KEY_SIZE],
+    nonce: [u8; NONCE_SIZE],
+    /// Ключи сопряжения по PIN (None - ждём подписи секретом пары)
+    pin_keys: Option<PinKeys>,
+    expires_at: Instant,
+}
+
+/// PIN, введённый для пира, к которому подключаемся мы
+struct EnteredPin {
+    code: String,
+    /// Эфемерный ключ из последнего Hello
+    ephemeral: Option<(EphemeralPrivateKey, [u8; EPHEMERAL_SIZE])>,
+}
+
+/// Наш ответ на вызов пира: чем должен быть подписан его HelloAck
+struct Answer {
+    peer_key: [u8; KEY_SIZE],
+    hello_nonce: [u8; NONCE_SIZE],
+    secret: [u8; SECRET_SIZE],
+    /// Секрет получен по PIN и сохраняется после HelloAck
+    by_pin: bool,
+}
+
+/// Allowlist пиров и PIN сопряжения (общие для рукопожатия и веб-интерфейса)
+pub struct Pairing {
+    required: bool,
+    key: [u8; KEY_SIZE],
+    /// Ключи сопряжённых пиров и секреты пар
+    allowed: RwLock<Vec<([u8; KEY_SIZE], [u8; SECRET_SIZE])>>,
+    /// Наш PIN для сопряжения с нами
+    pin: Mutex<Option<Pin>>,
+    /// Вызовы, ждущие повторного Hello
+    challenges: Mutex<Vec<Challenge>>,
+    /// PIN, введённые для пиров, к которым подключаемся мы
+    entered: Mutex<HashMap<SocketAddr, EnteredPin>>,
+    /// Ответы на вызовы, ждущие HelloAck
+    answers: Mutex<HashMap<SocketAddr, Answer>>,
+    /// Ключи пиров, чей Hello или HelloAck подтверждён секретом пары
+    verified: Mutex<HashMap<SocketAddr, [u8; KEY_SIZE]>>,
+    /// Куда сохраняются ключ и сопряжённые пиры
+    store: Option<Arc<ConfigStore>>,
+}
+
+impl Pairing {
+    /// Сопряжение с настройками `config`; новый ключ узла и сопряжённые
+    /// пиры сохраняются в `store`
+    pub fn new(config: &PairingConfig, store: Option<Arc<ConfigStore>>) -> Self {
+        let key = match config.key.as_deref().and_then(parse_hex) {
+            Some(key) => key,
+            None => {
+                if config.key.is_some() {
+                    tracing::warn!("Некорректный ключ сопряжения в файле конфигурации, создан новый");
+                }
+                let key: [u8; KEY_SIZE] = rand::random();
+                if let Some(store) = &store {
+                    store.update(|saved| saved.network.pairing.key = Some(hex(&key)));
+                }
+                key
+            }
+        };
+
+        // Имя или ключ без секрета пира не подтверждают
+        let mut allowed = Vec::new();
+        for entry in &config.allowed_peers {
+            match parse_entry(entry) {
+                Some(peer) => allowed.push(peer),
+                None => tracing::warn!(
+                    "Запись allowlist «{}» без секрета пары пропущена: сопрягите этого пира по PIN заново",
+                    entry.split(':').next().unwrap_or_default()
+                ),
+            }
+        }
+
+        let pairing = Self {
+            required: config.required,
+            key,
+            allowed: RwLock::new(allowed),
+            pin: Mutex::new(None),
+            challenges: Mutex::new(Vec::new()),
+            entered: Mutex::new(HashMap::new()),
+            answers: Mutex::new(HashMap::new()),
+            verified: Mutex::new(HashMap::new()),
+            store,
+        };
+        if pairing.required {
+            let pin = pairing.new_pin(Instant::now());
+            tracing::info!(
+                "Подключаются только сопряжённые пиры; PIN для сопряжения: {} (действует {} мин)",
+                pin,
+                PIN_LIFETIME.as_secs() / 60
+            );
+        }
+        pairing
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// Ключ этого узла (hex)
+    pub fn key(&self) -> String {
+        hex(&self.key)
+    }
+
+    /// Показать новый PIN (прежний перестаёт действовать)
+    pub fn new_pin(&self, now: Instant) -> String {
+        let code = format!("{:0width$}", rand::thread_rng().gen_range(0..10u32.pow(PIN_DIGITS as u32)), width = PIN_DIGITS);
+        *self.pin.lock() = Some(Pin {
+            code: code.clone(),
+            expires_at: now + PIN_LIFETIME,
+            failed_attempts: 0,
+        });
+        code
+    }
+
+    /// Состояние для веб-интерфейса (секреты пар не показываются)
+    pub fn status(&self, now: Instant) -> PairingStatus {
+        let pin = self.pin.lock();
+        let pin = pin.as_ref().filter(|pin| pin.expires_at > now);
+        PairingStatus {
+            required: self.required,
+            key: self.key(),
+            pin: pin.map(|pin| pin.code.clone()),
+            pin_expires_in_secs: pin.map_or(0, |pin| (pin.expires_at - now).as_secs()),
+            allowed_peers: self.allowed.read().iter().map(|(key, _)| hex(key)).collect(),
+        }
+    }
+
+    /// Подключаться к пиру с его PIN: следующий Hello начинает сопряжение
+    pub fn set_peer_pin(&self, peer_addr: SocketAddr, pin: &str) -> Result<(), String> {
+        let pin = pin.trim();
+        if pin.len() != PIN_DIGITS || !pin.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(format!("PIN must be {} digits", PIN_DIGITS));
+        }
+        self.entered.lock().insert(peer_addr, EnteredPin { code: pin.to_string(), ephemeral: None });
+        Ok(())
+    }
+
+    /// Введён ли PIN пира, который ещё не проверен
+    pub fn has_peer_pin(&self, peer_addr: &SocketAddr) -> bool {
+        self.entered.lock().contains_key(peer_addr)
+    }
+
+    /// Хвост первого Hello пиру (с эфемерным ключом, если введён его PIN)
+    pub fn hello(&self, peer_addr: &SocketAddr) -> PairingHello {
+        self.answers.lock().remove(peer_addr);
+        let mut entered = self.entered.lock();
+        let auth = match entered.get_mut(peer_addr) {
+            Some(pin) => {
+                pin.ephemeral = ephemeral_key();
+                pin.ephemeral.as_ref().map_or(PairingAuth::None, |(_, public)| PairingAuth::Start(*public))
+            }
+            None => PairingAuth::None,
+        };
+        PairingHello { key: self.key, auth }
+    }
+
+    /// Принять ли Hello от пира; `signed` - пакет без хвоста сопряжения
+    /// (см. `HandshakePacket::signed_data`)
+    pub fn check_hello(&self, peer_addr: &SocketAddr, hello: Option<PairingHello>, signed: &[u8], now: Instant) -> HelloCheck {
+        self.verified.lock().remove(peer_addr);
+        let Some(hello) = hello else {
+            return self.unpaired();
+        };
+        let secret = self.secret(&hello.key);
+        match hello.auth {
+            PairingAuth::None => match secret {
+                Some(_) => HelloCheck::Challenge(self.challenge(hello.key, None, now)),
+                None => self.unpaired(),
+            },
+            PairingAuth::Start(peer_ephemeral) => {
+                let code = {
+                    let pin = self.pin.lock();
+                    pin.as_ref().filter(|pin| pin.expires_at > now).map(|pin| pin.code.clone())
+                };
+                match (code, secret) {
+                    (Some(code), _) => {
+                        let Some((private, public)) = ephemeral_key() else {
+                            return HelloCheck::Reject("Не удалось начать сопряжение");
+                        };
+                        let Some(shared) = agree(private, &peer_ephemeral) else {
+                            return HelloCheck::Reject("Некорректный ключ сопряжения");
+                        };
+                        let keys = pin_keys(&shared, &code, (&hello.key, &peer_ephemeral), (&self.key, &public));
+                        HelloCheck::Challenge(self.challenge(hello.key, Some((public, keys)), now))
+                    }
+                    (None, Some(_)) => HelloCheck::Challenge(self.challenge(hello.key, None, now)),
+                    (None, None) if self.required => HelloCheck::Reject("PIN сопряжения недействителен: покажите новый"),
+                    (None, None) => HelloCheck::Accept(None),
+                }
+            }
+            PairingAuth::Proof { nonce, tag } => {
+                let Some(keys) = self.take_challenge(&hello.key, now).and_then(|challenge| {
+                    let keys = challenge.pin_keys?;
+                    let expected = hello_tag(&keys.proof, signed, &challenge.nonce, &nonce);
+                    Some(keys).filter(|_| constant_time_eq(&expected, &tag))
+                }) else {
+                    self.failed_pin_attempt();
+                    return HelloCheck::Reject("Неверный PIN сопряжения");
+                };
+
+                // PIN одноразовый
+                *self.pin.lock() = None;
+                tracing::info!("Пир {} сопряжён по PIN (ключ {})", peer_addr, hex(&hello.key));
+                self.allow(hello.key, keys.secret);
+                self.verified.lock().insert(*peer_addr, hello.key);
+                HelloCheck::Accept(Some(AckKey { secret: keys.secret, hello_nonce: nonce }))
+            }
+            PairingAuth::Mac { nonce, tag } => {
+                let (Some(secret), Some(challenge)) = (secret, self.take_challenge(&hello.key, now)) else {
+                    return HelloCheck::Reject("Hello без действующего вызова сопряжения");
+                };
+                if !constant_time_eq(&hello_tag(&secret, signed, &challenge.nonce, &nonce), &tag) {
+                    return HelloCheck::Reject("Неверная подпись Hello");
+                }
+                self.verified.lock().insert(*peer_addr, hello.key);
+                HelloCheck::Accept(Some(AckKey { secret, hello_nonce: nonce }))
+            }
+        }
+    }
+
+    /// Хвост HelloAck: подпись секретом пары или только ключ
+    pub fn ack(&self, key: Option<AckKey>, signed: &[u8]) -> PairingHello {
+        let auth = match key {
+            Some(key) => PairingAuth::Mac { nonce: key.hello_nonce, tag: ack_tag(&key.secret, signed, &key.hello_nonce) },
+            None => PairingAuth::None,
+        };
+        PairingHello { key: self.key, auth }
+    }
+
+    /// Хвост повторного Hello в ответ на вызов пира: доказательство PIN
+    /// или подпись секретом пары (None - подтвердить нечем)
+    pub fn answer(&self, peer_addr: &SocketAddr, challenge: &PairingChallenge, signed: &[u8]) -> Option<PairingHello> {
+        let hello_nonce: [u8; NONCE_SIZE] = rand::random();
+        let ephemeral = challenge.ephemeral.and_then(|peer_ephemeral| {
+            let mut entered = self.entered.lock();
+            let pin = entered.get_mut(peer_addr)?;
+            let (private, public) = pin.ephemeral.take()?;
+            let shared = agree(private, &peer_ephemeral)?;
+            Some(pin_keys(&shared, &pin.code, (&self.key, &public), (&challenge.key, &peer_ephemeral)))
+        });
+
+        let (auth, secret, by_pin) = match (ephemeral, self.secret(&challenge.key)) {
+            (Some(keys), _) => {
+                let tag = hello_tag(&keys.proof, signed, &challenge.nonce, &hello_nonce);
+                (PairingAuth::Proof { nonce: hello_nonce, tag }, keys.secret, true)
+            }
+            (None, Some(secret)) => {
+                let tag = hello_tag(&secret, signed, &challenge.nonce, &hello_nonce);
+                (PairingAuth::Mac { nonce: hello_nonce, tag }, secret, false)
+            }
+            (None, None) => return None,
+        };
+        self.answers.lock().insert(*peer_addr, Answer { peer_key: challenge.key, hello_nonce, secret, by_pin });
+        Some(PairingHello { key: self.key, auth })
+    }
+
+    /// Пир ответил HelloAck: можно ли с ним работать. Подпись HelloAck
+    /// подтверждает пира; после сопряжения по PIN его секрет сохраняется
+    pub fn check_ack(&self, peer_addr: &SocketAddr, ack: Option<PairingHello>, signed: &[u8]) -> bool {
+        self.entered.lock().remove(peer_addr);
+        self.verified.lock().remove(peer_addr);
+        let answer = self.answers.lock().remove(peer_addr);
+        let verified = match (answer, ack) {
+            (Some(answer), Some(PairingHello { key, auth: PairingAuth::Mac { nonce, tag } }))
+                if key == answer.peer_key
+                    && nonce == answer.hello_nonce
+                    && constant_time_eq(&ack_tag(&answer.secret, signed, &nonce), &tag) =>
+            {
+                if answer.by_pin {
+                    tracing::info!("Сопряжение с {} завершено (ключ {})", peer_addr, hex(&key));
+                    self.allow(key, answer.secret);
+                }
+                self.verified.lock().insert(*peer_addr, key);
+                true
+            }
+            _ => false,
+        };
+        !self.required || verified
+    }
+
+    /// Пир отклонил Hello: введённый PIN больше не отправляется
+    pub fn forget_peer_pin(&self, peer_addr: &SocketAddr) {
+        self.entered.lock().remove(peer_addr);
+        self.answers.lock().remove(peer_addr);
+    }
+
+    /// Подписать пакет пиру секретом пары (None - Hello пира не
+    /// подтверждён секретом)
+    pub fn sign_packet(&self, peer_addr: &SocketAddr, packet: &[u8]) -> Option<Vec<u8>> {
+        let peer_key = *self.verified.lock().get(peer_addr)?;
+        let secret = self.secret(&peer_key)?;
+        let mut signed = Vec::with_capacity(packet.len() + KEY_SIZE + TAG_SIZE);
+        signed.extend_from_slice(packet);
+        signed.extend_from_slice(&self.key);
+        signed.extend_from_slice(&packet_tag(&secret, packet));
+        Some(signed)
+    }
+
+    /// Проверить подпись пакета сопряжённого пира; возвращает пакет без
+    /// неё (None - подписи нет или она неверна)
+    pub fn verify_packet<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let packet_len = data.len().checked_sub(KEY_SIZE + TAG_SIZE)?;
+        let (packet, trailer) = data.split_at(packet_len);
+        let (key, tag) = trailer.split_at(KEY_SIZE);
+        let secret = self.secret(key.try_into().ok()?)?;
+        constant_time_eq(&packet_tag(&secret, packet), tag).then_some(packet)
+    }
+
+    /// Незнакомый пир: Error, если требуется сопряжение
+    fn unpaired(&self) -> HelloCheck {
+        if self.required {
+            HelloCheck::Reject("Требуется сопряжение: введите PIN этого узла")
+        } else {
+            HelloCheck::Accept(None)
+        }
+    }
+
+    fn secret(&self, key: &[u8; KEY_SIZE]) -> Option<[u8; SECRET_SIZE]> {
+        self.allowed.read().iter().find(|(peer, _)| peer == key).map(|(_, secret)| *secret)
+    }
+
+    /// Выдать вызов пиру (прежний вызов того же пира заменяется)
+    fn challenge(
+        &self,
+        peer_key: [u8; KEY_SIZE],
+        pin: Option<([u8; EPHEMERAL_SIZE], PinKeys)>,
+        now: Instant,
+    ) -> PairingChallenge {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let mut challenges = self.challenges.lock();
+        challenges.retain(|challenge| challenge.peer_key != peer_key && challenge.expires_at > now);
+        if challenges.len() >= MAX_CHALLENGES {
+            challenges.remove(0);
+        }
+        challenges.push(Challenge {
+            peer_key,
+            nonce,
+            pin_keys: pin.map(|(_, keys)| keys),
+            expires_at: now + CHALLENGE_LIFETIME,
+        });
+        PairingChallenge { key: self.key, nonce, ephemeral: pin.map(|(public, _)| public) }
+    }
+
+    /// Забрать действующий вызов пира (каждый принимает один ответ)
+    fn take_challenge(&self, peer_key: &[u8; KEY_SIZE], now: Instant) -> Option<Challenge> {
+        let mut challenges = self.challenges.lock();
+        let index = challenges.iter().position(|challenge| &challenge.peer_key == peer_key)?;
+        Some(challenges.remove(index)).filter(|challenge| challenge.expires_at > now)
+    }
+
+    fn failed_pin_attempt(&self) {
+        let mut pin = self.pin.lock();
+        if let Some(current) = pin.as_mut() {
+            current.failed_attempts += 1;
+            if current.failed_attempts >= MAX_PIN_ATTEMPTS {
+                *pin = None;
+            }
+        }
+    }
+
+    /// Сохранить секрет пары с пиром (прежний секрет того же ключа заменяется)
+    fn allow(&self, key: [u8; KEY_SIZE], secret: [u8; SECRET_SIZE]) {
+        {
+            let mut allowed = self.allowed.write();
+            allowed.retain(|(peer, _)| *peer != key);
+            allowed.push((key, secret));
+        }
+        if let Some(store) = &self.store {
+            let prefix = hex(&key);
+            store.update(|saved| {
+                let entries = &mut saved.network.pairing.allowed_peers;
+                entries.retain(|entry| entry.split(':').next() != Some(prefix.as_str()));
+                entries.push(format!("{}:{}", prefix, hex(&secret)));
+            });
+        }
+    }
+}
+
+/// Эфемерный ключ X25519 (закрытый и открытый)
+fn ephemeral_key() -> Option<(EphemeralPrivateKey, [u8; EPHEMERAL_SIZE])> {
+    let private = EphemeralPrivateKey::generate(&X25519, &ring::rand::SystemRandom::new()).ok()?;
+    let public = private.compute_public_key().ok()?.as_ref().try_into().ok()?;
+    Some((private, public))
+}
+
+/// Общий секрет Диффи-Хеллмана с эфемерным ключом пира
+fn agree(private: EphemeralPrivateKey, peer: &[u8; EPHEMERAL_SIZE]) -> Option<[u8; SECRET_SIZE]> {
+    agreement::agree_ephemeral(private, &UnparsedPublicKey::new(&X25519, peer), |shared| shared.try_into().ok())
+        .ok()
+        .flatten()
+}
+
+/// Секрет пары и ключ доказательства из секрета Диффи-Хеллмана, PIN и
+/// ключей обоих узлов (инициатор - узел, которому ввели PIN)
+fn pin_keys(
+    shared: &[u8],
+    pin: &str,
+    initiator: (&[u8; KEY_SIZE], &[u8; EPHEMERAL_SIZE]),
+    responder: (&[u8; KEY_SIZE], &[u8; EPHEMERAL_SIZE]),
+) -> PinKeys {
+    let prk = hmac(PAIRING_CONTEXT, &[shared]);
+    let transcript: [&[u8]; 5] = [pin.as_bytes(), initiator.0, initiator.1, responder.0, responder.1];
+    let derive = |label: &'static [u8]| {
+        let parts: Vec<&[u8]> = std::iter::once(label).chain(transcript).collect();
+        hmac(&prk, &parts)
+    };
+    PinKeys { secret: derive(b"secret"), proof: derive(b"proof") }
+}
+
+/// Подпись Hello: пакет, nonce вызова и nonce Hello
+fn hello_tag(key: &[u8], signed: &[u8], challenge: &[u8; NONCE_SIZE], nonce: &[u8; NONCE_SIZE]) -> [u8; TAG_SIZE] {
+    truncate(hmac(key, &[b"hello", signed, challenge, nonce]))
+}
+
+/// Подпись HelloAck: пакет и nonce принятого Hello
+fn ack_tag(key: &[u8], signed: &[u8], nonce: &[u8; NONCE_SIZE]) -> [u8; TAG_SIZE] {
+    truncate(hmac(key, &[b"ack", signed, nonce]))
+}
+
+/// Подпись пакета вне рукопожатия
+fn packet_tag(key: &[u8], packet: &[u8]) -> [u8; TAG_SIZE] {
+    truncate(hmac(key, &[b"packet", packet]))
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; SECRET_SIZE] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC принимает ключ любой длины");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn truncate(digest: [u8; SECRET_SIZE]) -> [u8; TAG_SIZE] {
+    let mut tag = [0u8; TAG_SIZE];
+    tag.copy_from_slice(&digest[..TAG_SIZE]);
+    tag
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Запись allowlist `ключ:секрет`
+fn parse_entry(entry: &str) -> Option<([u8; KEY_SIZE], [u8; SECRET_SIZE])> {
+    let (key, secret) = entry.trim().split_once(':')?;
+    Some((parse_hex(key)?, parse_hex(secret)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn required(allowed: &[&str]) -> Pairing {
+        let config = PairingConfig {
+            required: true,
+            allowed_peers: allowed.iter().map(|entry| entry.to_string()).collect(),
+            key: None,
+        };
+        Pairing::new(&config, None)
+    }
+
+    fn open() -> Pairing {
+        Pairing::new(&PairingConfig::default(), None)
+    }
+
+    /// Рукопожатие `sender` с `receiver` на `addr` от первого Hello до
+    /// HelloAck; true - оба узла приняли друг друга
+    fn pair(sender: &Pairing, receiver: &Pairing, addr: SocketAddr, now: Instant) -> bool {
+        let sender_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let HelloCheck::Challenge(challenge) = receiver.check_hello(&sender_addr, Some(sender.hello(&addr)), b"hello", now) else {
+            return false;
+        };
+        let Some(hello) = sender.answer(&addr, &challenge, b"hello again") else {
+            return false;
+        };
+        let HelloCheck::Accept(key) = receiver.check_hello(&sender_addr, Some(hello), b"hello again", now) else {
+            return false;
+        };
+        key.is_some() && sender.check_ack(&addr, Some(receiver.ack(key, b"ack")), b"ack")
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let now = Instant::now();
+        assert_eq!(open().check_hello(&addr, None, b"hello", now), HelloCheck::Accept(None));
+
+        // Имя и ключ без секрета пира не подтверждают
+        let studio = open();
+        let secret = [7u8; SECRET_SIZE];
+        let entry = format!("{}:{}", studio.key(), hex(&secret));
+        let pairing = required(&["Stage", &studio.key(), &entry]);
+        assert_eq!(pairing.status(now).allowed_peers, vec![studio.key()]);
+        assert!(matches!(pairing.check_hello(&addr, None, b"hello", now), HelloCheck::Reject(_)));
+        let stranger = open();
+        assert!(matches!(pairing.check_hello(&addr, Some(stranger.hello(&addr)), b"hello", now), HelloCheck::Reject(_)));
+
+        // Известный ключ получает вызов, а не допуск
+        let HelloCheck::Challenge(challenge) = pairing.check_hello(&addr, Some(studio.hello(&addr)), b"hello", now) else {
+            panic!("ожидался вызов");
+        };
+        assert_eq!(challenge.ephemeral, None);
+        assert_eq!(PairingChallenge::decode(&challenge.encode()), Some(challenge));
+
+        // Ключ из файла конфигурации сохраняется
+        let config = PairingConfig { key: Some(studio.key()), ..PairingConfig::default() };
+        assert_eq!(Pairing::new(&config, None).key(), studio.key());
+    }
+
+    #[test]
+    fn test_pin_pairing() {
+        let receiver = required(&[]);
+        let sender = open();
+        let addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let now = Instant::now();
+        let pin = receiver.new_pin(now);
+        assert_eq!(receiver.status(now).pin.as_deref(), Some(pin.as_str()));
+
+        // Неверный PIN отклоняется, PIN не передаётся по сети
+        assert!(sender.set_peer_pin(addr, "12ab").is_err());
+        let wrong = if pin == "000000" { "111111" } else { "000000" };
+        sender.set_peer_pin(addr, wrong).unwrap();
+        let hello = sender.hello(&addr);
+        assert!(matches!(hello.auth, PairingAuth::Start(_)));
+        let HelloCheck::Challenge(challenge) = receiver.check_hello(&addr, Some(hello), b"hello", now) else {
+            panic!("ожидался вызов");
+        };
+        let proof = sender.answer(&addr, &challenge, b"hello again").unwrap();
+        assert!(!proof.encode().windows(PIN_DIGITS).any(|window| window == wrong.as_bytes()));
+        assert_eq!(receiver.check_hello(&addr, Some(proof), b"hello again", now), HelloCheck::Reject("Неверный PIN сопряжения"));
+
+        // Доказательство привязано к пакету
+        sender.set_peer_pin(addr, &pin).unwrap();
+        let HelloCheck::Challenge(challenge) = receiver.check_hello(&addr, Some(sender.hello(&addr)), b"hello", now) else {
+            panic!("ожидался вызов");
+        };
+        let proof = PairingHello::decode(&sender.answer(&addr, &challenge, b"hello again").unwrap().encode()).unwrap();
+        assert!(matches!(receiver.check_hello(&addr, Some(proof), b"other hello", now), HelloCheck::Reject(_)));
+
+        // PIN одноразовый, секрет пары у обоих узлов
+        assert!(pair(&sender, &receiver, addr, now));
+        assert!(receiver.status(now).pin.is_none());
+        assert!(!sender.has_peer_pin(&addr));
+        assert_eq!(receiver.status(now).allowed_peers, vec![sender.key()]);
+        assert_eq!(sender.status(now).allowed_peers, vec![receiver.key()]);
+        assert_eq!(sender.secret(&receiver.key), receiver.secret(&sender.key));
+
+        // Дальше Hello подписывается секретом пары
+        let hello = sender.hello(&addr);
+        assert_eq!(hello.auth, PairingAuth::None);
+        let HelloCheck::Challenge(challenge) = receiver.check_hello(&addr, Some(hello), b"hello", now) else {
+            panic!("ожидался вызов");
+        };
+        let signed = sender.answer(&addr, &challenge, b"hello again").unwrap();
+        assert!(matches!(signed.auth, PairingAuth::Mac { .. }));
+        let HelloCheck::Accept(key) = receiver.check_hello(&addr, Some(signed), b"hello again", now) else {
+            panic!("Hello сопряжённого пира отклонён");
+        };
+        assert!(sender.check_ack(&addr, Some(receiver.ack(key, b"ack")), b"ack"));
+
+        // Вызов одноразовый: перехваченный Hello не повторить
+        assert!(matches!(receiver.check_hello(&addr, Some(signed), b"hello again", now), HelloCheck::Reject(_)));
+    }
+
+    #[test]
+    fn test_signed_packets() {
+        let receiver = required(&[]);
+        let sender = open();
+        let receiver_addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let sender_addr: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let now = Instant::now();
+
+        // Не сопряжённому пиру пакет не подписывается
+        assert!(sender.sign_packet(&receiver_addr, b"file").is_none());
+        sender.set_peer_pin(receiver_addr, &receiver.new_pin(now)).unwrap();
+        assert!(pair(&sender, &receiver, receiver_addr, now));
+
+        // Подпись проверяется секретом пары, откуда бы ни пришёл пакет
+        let signed = sender.sign_packet(&receiver_addr, b"file").unwrap();
+        assert_eq!(receiver.verify_packet(&signed), Some(&b"file"[..]));
+        let mut forged = signed.clone();
+        forged[0] ^= 1;
+        assert_eq!(receiver.verify_packet(&forged), None);
+        assert_eq!(receiver.verify_packet(b"file"), None);
+        assert_eq!(open().verify_packet(&signed), None);
+
+        // Ответ подписывает узел, проверивший Hello пира
+        let reply = receiver.sign_packet(&sender_addr, b"ack").unwrap();
+        assert_eq!(sender.verify_packet(&reply), Some(&b"ack"[..]));
+        assert!(receiver.sign_packet(&receiver_addr, b"ack").is_none());
+    }
+
+    #[test]
+    fn test_ack_must_be_signed() {
+        let receiver = required(&[]);
+        let sender = required(&[]);
+        let addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let now = Instant::now();
+        sender.set_peer_pin(addr, &receiver.new_pin(now)).unwrap();
+        assert!(pair(&sender, &receiver, addr, now));
+
+        // Узел с тем же ключом, но без секрета пары, не проходит за приёмник
+        let HelloCheck::Challenge(challenge) = receiver.check_hello(&addr, Some(sender.hello(&addr)), b"hello", now) else {
+            panic!("ожидался вызов");
+        };
+        sender.answer(&addr, &challenge, b"hello again").unwrap();
+        let forged = PairingHello { key: receiver.key, auth: PairingAuth::None };
+        assert!(!sender.check_ack(&addr, Some(forged), b"ack"));
+        assert!(!sender.check_ack(&addr, None, b"ack"));
+    }
+
+    #[test]
+    fn test_pin_expires_and_locks() {
+        let receiver = required(&[]);
+        let sender = open();
+        let addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let now = Instant::now();
+
+        let pin = receiver.new_pin(now);
+        sender.set_peer_pin(addr, &pin).unwrap();
+        let late = now + PIN_LIFETIME + Duration::from_secs(1);
+        assert!(matches!(receiver.check_hello(&addr, Some(sender.hello(&addr)), b"hello", late), HelloCheck::Reject(_)));
+        assert_eq!(receiver.status(late).pin_expires_in_secs, 0);
+
+        // Вызов действует недолго
+        let HelloCheck::Challenge(challenge) = receiver.check_hello(&addr, Some(sender.hello(&addr)), b"hello", now) else {
+            panic!("ожидался вызов");
+        };
+        let proof = sender.answer(&addr, &challenge, b"hello again").unwrap();
+        let stale = now + CHALLENGE_LIFETIME + Duration::from_secs(1);
+        assert!(matches!(receiver.check_hello(&addr, Some(proof), b"hello again", stale), HelloCheck::Reject(_)));
+
+        // Подбор PIN: после нескольких ошибок PIN гасится
+        let pin = receiver.new_pin(now);
+        let wrong = if pin == "000000" { "111111" } else { "000000" };
+        sender.set_peer_pin(addr, wrong).unwrap();
+        for _ in 0..MAX_PIN_ATTEMPTS {
+            assert!(!pair(&sender, &receiver, addr, now));
+        }
+        sender.set_peer_pin(addr, &pin).unwrap();
+        assert!(!pair(&sender, &receiver, addr, now));
+
+        assert!(PairingHello::decode(&[0; KEY_SIZE]).is_none());
+        let mut bad_mode = [0; KEY_SIZE + 1];
+        bad_mode[KEY_SIZE] = 5;
+        assert!(PairingHello::decode(&bad_mode).is_none());
+        let mut short_proof = [0; KEY_SIZE + 1 + NONCE_SIZE];
+        short_proof[KEY_SIZE] = MODE_PROOF;
+        assert!(PairingHello::decode(&short_proof).is_none());
+    }
+}
@@ -0,0 +1,376 @@
+//! pcapng capture of the audio protocol for Wireshark
+//!
+//! With `NetworkConfig::pcap` (`--pcap FILE`, `LAN_AUDIO_PCAP`) every
+//! datagram the audio sockets send and receive (audio packets, handshake,
+//! clock sync, feedback, RTP) is written to a pcapng file as it goes over
+//! the wire, encrypted payloads included. Packets are wrapped in an
+//! IPv4/IPv6 and UDP header built from the real addresses and ports (link
+//! type raw IP), stamped with the wall clock and flagged inbound or
+//! outbound, so Wireshark opens the file as ordinary UDP traffic. Frames of
+//! the TCP fallback appear as one datagram each.
+//!
+//! `contrib/wireshark/lan_audio.lua` dissects the audio and handshake
+//! headers (`lanaudio.seq`, `lanaudio.timestamp`, flags...), which is what
+//! the I/O graphs and the delta-time columns need to look into stream
+//! timing problems.
+
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::constants::PCAP_ENV_VAR;
+use crate::network::packet_log::Direction;
+use crate::network::transport::Transport;
+
+/// LINKTYPE_RAW: packets start with their IPv4 or IPv6 header
+const LINKTYPE_RAW: u16 = 101;
+
+/// Buffered packets reach the file at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Largest datagram that fits a made-up IP packet
+const MAX_DATAGRAM: usize = 65_000;
+
+const UDP_PROTOCOL: u8 = 17;
+const TTL: u8 = 64;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Option<PcapWriter<BufWriter<File>>>> = Mutex::new(None);
+
+/// Capture file requested with `LAN_AUDIO_PCAP`
+pub fn path_from_env() -> Option<PathBuf> {
+    std::env::var_os(PCAP_ENV_VAR).filter(|path| !path.is_empty()).map(Into::into)
+}
+
+/// Start capturing to `path` (replaces a capture in progress)
+pub fn start(path: &Path) -> io::Result<()> {
+    let writer = PcapWriter::new(BufWriter::new(File::create(path)?))?;
+    *CAPTURE.lock() = Some(writer);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stop capturing and write out what is buffered
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+    if let Some(mut writer) = CAPTURE.lock().take() {
+        let _ = writer.flush();
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Capture a datagram sent from or received on `local`
+pub fn record(direction: Direction, local: Option<SocketAddr>, peer: SocketAddr, data: &[u8]) {
+    if !is_enabled() {
+        return;
+    }
+    let local = local.unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+    let time_us = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_micros() as u64)
+        .unwrap_or(0);
+
+    let mut capture = CAPTURE.lock();
+    let Some(writer) = capture.as_mut() else {
+        return;
+    };
+    if let Err(e) = writer.write_packet(time_us, direction, local, peer, data) {
+        tracing::warn!("pcap capture stopped: {}", e);
+        ENABLED.store(false, Ordering::Relaxed);
+        *capture = None;
+    }
+}
+
+/// Capture a datagram sent or received through `transport`
+pub fn record_on(direction: Direction, transport: &dyn Transport, peer: SocketAddr, data: &[u8]) {
+    if is_enabled() {
+        record(direction, transport.local_addr().ok(), peer, data);
+    }
+}
+
+/// pcapng writer with one raw-IP interface
+struct PcapWriter<W: Write> {
+    writer: W,
+    last_flush: Instant,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the section header and interface description
+    fn new(mut writer: W) -> io::Result<Self> {
+        let mut options = Vec::new();
+        push_option(&mut options, 4, concat!("lan-audio-streamer ", env!("CARGO_PKG_VERSION")).as_bytes());
+        let mut body = Vec::new();
+        body.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        body.extend_from_slice(&options);
+        write_block(&mut writer, 0x0A0D_0D0A, &body)?;
+
+        let mut options = Vec::new();
+        push_option(&mut options, 2, b"lan-audio");
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&options);
+        write_block(&mut writer, 1, &body)?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Write one datagram as an enhanced packet block
+    fn write_packet(
+        &mut self,
+        time_us: u64,
+        direction: Direction,
+        local: SocketAddr,
+        peer: SocketAddr,
+        data: &[u8],
+    ) -> io::Result<()> {
+        if data.len() > MAX_DATAGRAM {
+            return Ok(());
+        }
+        let (src, dst) = match direction {
+            Direction::Sent => (local, peer),
+            Direction::Received => (peer, local),
+        };
+        let packet = udp_packet(src, dst, data);
+
+        // epb_flags: inbound 1, outbound 2
+        let mut options = Vec::new();
+        let flags: u32 = match direction {
+            Direction::Received => 1,
+            Direction::Sent => 2,
+        };
+        push_option(&mut options, 2, &flags.to_le_bytes());
+
+        let mut body = Vec::with_capacity(20 + packet.len() + 3 + options.len());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((time_us >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(time_us as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&packet);
+        body.resize(body.len().next_multiple_of(4), 0);
+        body.extend_from_slice(&options);
+        write_block(&mut self.writer, 6, &body)?;
+
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+}
+
+/// Block: type, total length, body (padded to 32 bits), total length again
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let padded = body.len().next_multiple_of(4);
+    let total = (12 + padded) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&[0u8; 3][..padded - body.len()])?;
+    writer.write_all(&total.to_le_bytes())
+}
+
+/// Append an option and the end-of-options marker
+fn push_option(options: &mut Vec<u8>, code: u16, value: &[u8]) {
+    options.extend_from_slice(&code.to_le_bytes());
+    options.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    options.extend_from_slice(value);
+    options.resize(options.len().next_multiple_of(4), 0);
+    options.extend_from_slice(&[0u8; 4]);
+}
+
+/// Both addresses in one family: IPv4 where possible (a dual-stack socket
+/// bound to `[::]` receiving from an IPv4 peer), IPv4-mapped IPv6 otherwise
+fn same_family(src: IpAddr, dst: IpAddr) -> (IpAddr, IpAddr) {
+    let to_v4 = |ip: IpAddr| match ip {
+        IpAddr::V6(v6) if v6.is_unspecified() => Some(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(v6) => v6.to_ipv4_mapped(),
+        IpAddr::V4(v4) => Some(v4),
+    };
+    match (to_v4(src), to_v4(dst)) {
+        (Some(src), Some(dst)) => (IpAddr::V4(src), IpAddr::V4(dst)),
+        _ => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(v4) => IpAddr::V6(v4.to_ipv6_mapped()),
+                v6 => v6,
+            };
+            (to_v6(src), to_v6(dst))
+        }
+    }
+}
+
+/// IP packet carrying `data` in a UDP datagram from `src` to `dst`
+fn udp_packet(src: SocketAddr, dst: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + data.len();
+    let mut udp = Vec::with_capacity(udp_len);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(data);
+
+    let mut pseudo = Vec::with_capacity(40);
+    let mut packet = match same_family(src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut header = Vec::with_capacity(20 + udp_len);
+            header.extend_from_slice(&[0x45, 0]);
+            header.extend_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0x40, 0, TTL, UDP_PROTOCOL, 0, 0]);
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            let checksum = internet_checksum(&[&header]);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dst.octets());
+            pseudo.extend_from_slice(&[0, UDP_PROTOCOL]);
+            pseudo.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            header
+        }
+        (src, dst) => {
+            let octets = |ip: IpAddr| match ip {
+                IpAddr::V6(v6) => v6.octets(),
+                IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+            };
+            let mut header = Vec::with_capacity(40 + udp_len);
+            header.extend_from_slice(&[0x60, 0, 0, 0]);
+            header.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            header.extend_from_slice(&[UDP_PROTOCOL, TTL]);
+            header.extend_from_slice(&octets(src));
+            header.extend_from_slice(&octets(dst));
+
+            pseudo.extend_from_slice(&octets(src));
+            pseudo.extend_from_slice(&octets(dst));
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, UDP_PROTOCOL]);
+            header
+        }
+    };
+
+    // A computed zero goes out as all ones (zero means "no checksum")
+    let checksum = match internet_checksum(&[&pseudo, &udp]) {
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+/// RFC 1071 checksum over the concatenated parts (each of even length
+/// except the last)
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for chunk in part.chunks(2) {
+            let word = match *chunk {
+                [high, low] => u16::from_be_bytes([high, low]),
+                [high] => u16::from_be_bytes([high, 0]),
+                _ => 0,
+            };
+            sum += word as u32;
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (type, body) of every block
+    fn blocks(mut data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let block_type = u32::from_le_bytes(data[0..4].try_into().unwrap());
+            let total = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+            assert_eq!(total % 4, 0);
+            assert_eq!(&data[total - 4..total], &data[4..8]);
+            blocks.push((block_type, data[8..total - 4].to_vec()));
+            data = &data[total..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_pcapng_blocks() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        let local: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let peer: SocketAddr = "192.168.1.20:41000".parse().unwrap();
+        writer
+            .write_packet(1_700_000_000_123_456, Direction::Received, local, peer, b"\x01\xafaudio")
+            .unwrap();
+        writer.write_packet(1_700_000_000_133_456, Direction::Sent, local, peer, b"LAHS").unwrap();
+
+        let blocks = blocks(&writer.writer);
+        assert_eq!(blocks.iter().map(|(kind, _)| *kind).collect::<Vec<_>>(), vec![0x0A0D_0D0A, 1, 6, 6]);
+        assert_eq!(&blocks[0].1[..4], &0x1A2B_3C4Du32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([blocks[1].1[0], blocks[1].1[1]]), LINKTYPE_RAW);
+
+        let received = &blocks[2].1;
+        let time_us = (u32::from_le_bytes(received[4..8].try_into().unwrap()) as u64) << 32
+            | u32::from_le_bytes(received[8..12].try_into().unwrap()) as u64;
+        assert_eq!(time_us, 1_700_000_000_123_456);
+        let len = u32::from_le_bytes(received[12..16].try_into().unwrap()) as usize;
+        assert_eq!(len, 20 + 8 + 7);
+        let packet = &received[20..20 + len];
+        // From the peer to this socket, with a valid IP header checksum
+        assert_eq!(&packet[12..16], &[192, 168, 1, 20]);
+        assert_eq!(&packet[16..20], &[192, 168, 1, 10]);
+        assert_eq!(internet_checksum(&[&packet[..20]]), 0);
+        assert_eq!((u16::from_be_bytes([packet[20], packet[21]]), u16::from_be_bytes([packet[22], packet[23]])), (41000, 5000));
+        assert_eq!(&packet[28..], b"\x01\xafaudio");
+        // Inbound in epb_flags
+        let options = &received[20 + len.next_multiple_of(4)..];
+        assert_eq!(&options[..8], &[2, 0, 4, 0, 1, 0, 0, 0]);
+
+        let sent = &blocks[3].1;
+        let packet = &sent[20..20 + 32];
+        assert_eq!(&packet[12..16], &[192, 168, 1, 10]);
+        assert_eq!(&sent[20 + 32..20 + 40], &[2, 0, 4, 0, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_udp_checksums() {
+        // A dual-stack socket receiving from IPv4 stays IPv4
+        let local: SocketAddr = "[::]:5000".parse().unwrap();
+        let peer: SocketAddr = "10.0.0.2:6000".parse().unwrap();
+        let packet = udp_packet(peer, local, b"odd");
+        assert_eq!(packet[0] >> 4, 4);
+        assert_eq!(&packet[16..20], &[0, 0, 0, 0]);
+        let mut pseudo = packet[12..20].to_vec();
+        pseudo.extend_from_slice(&[0, UDP_PROTOCOL, 0, 11]);
+        assert_eq!(internet_checksum(&[&pseudo, &packet[20..]]), 0);
+
+        let local: SocketAddr = "[fe80::1]:5000".parse().unwrap();
+        let peer: SocketAddr = "[fe80::2]:6000".parse().unwrap();
+        let packet = udp_packet(local, peer, b"audio");
+        assert_eq!(packet.len(), 40 + 8 + 5);
+        assert_eq!(packet[0] >> 4, 6);
+        assert_eq!(packet[6], UDP_PROTOCOL);
+        let mut pseudo = packet[8..40].to_vec();
+        pseudo.extend_from_slice(&[0, 0, 0, 13, 0, 0, 0, UDP_PROTOCOL]);
+        assert_eq!(internet_checksum(&[&pseudo, &packet[40..]]), 0);
+    }
+}
@@ -0,0 +1,542 @@
+//! Peer registry with per-peer bandwidth accounting
+//!
+//! Keeps the list of known peers together with traffic counters so the
+//! UI can show how much of a (possibly metered) link each peer uses.
+//!
+//! A peer reachable over several networks (e.g. Ethernet and Wi-Fi) is
+//! announced once per network with the same instance id; the extra
+//! addresses are kept as redundant paths of a single entry.
+//!
+//! Each entry also carries the state of the link to the peer (handshake
+//! done, goodbye received, keepalive pings unanswered); its changes are
+//! broadcast to [`PeerRegistry::subscribe`], which the web UI pushes on.
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::constants::DEFAULT_UDP_PORT;
+use crate::protocol::{PeerBandwidth, PeerConnection, PeerStatus};
+
+/// Window over which current kbps values are computed
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Rate window state (last sample point and cached rates)
+#[derive(Debug)]
+struct RateWindow {
+    started: Instant,
+    bytes_sent: u64,
+    bytes_received: u64,
+    up_kbps: f32,
+    down_kbps: f32,
+}
+
+/// Traffic counters for a single peer
+#[derive(Debug)]
+pub struct BandwidthMeter {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    packets_sent: AtomicU64,
+    packets_received: AtomicU64,
+    session_start: Instant,
+    window: Mutex<RateWindow>,
+}
+
+impl BandwidthMeter {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            packets_sent: AtomicU64::new(0),
+            packets_received: AtomicU64::new(0),
+            session_start: now,
+            window: Mutex::new(RateWindow {
+                started: now,
+                bytes_sent: 0,
+                bytes_received: 0,
+                up_kbps: 0.0,
+                down_kbps: 0.0,
+            }),
+        }
+    }
+
+    /// Account an outgoing packet (size on the wire, header included)
+    pub fn record_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account an incoming packet (size on the wire, header included)
+    pub fn record_received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Close the rate window if it is older than `RATE_WINDOW`
+    fn roll(&self, now: Instant) {
+        let mut window = self.window.lock();
+        let elapsed = now.saturating_duration_since(window.started);
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+
+        let sent = self.bytes_sent.load(Ordering::Relaxed);
+        let received = self.bytes_received.load(Ordering::Relaxed);
+        let secs = elapsed.as_secs_f32();
+
+        window.up_kbps = (sent - window.bytes_sent) as f32 * 8.0 / 1000.0 / secs;
+        window.down_kbps = (received - window.bytes_received) as f32 * 8.0 / 1000.0 / secs;
+        window.started = now;
+        window.bytes_sent = sent;
+        window.bytes_received = received;
+    }
+
+    /// Get a snapshot of current rates and session totals
+    pub fn snapshot(&self) -> PeerBandwidth {
+        self.snapshot_at(Instant::now())
+    }
+
+    fn snapshot_at(&self, now: Instant) -> PeerBandwidth {
+        self.roll(now);
+        let window = self.window.lock();
+
+        PeerBandwidth {
+            up_kbps: window.up_kbps,
+            down_kbps: window.down_kbps,
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            session_secs: now.saturating_duration_since(self.session_start).as_secs(),
+        }
+    }
+}
+
+impl Default for BandwidthMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Known peer
+#[derive(Debug)]
+pub struct PeerEntry {
+    /// Address to send audio to
+    pub address: SocketAddr,
+    /// Peer display name
+    pub name: String,
+    /// Instance id advertised by the peer (empty if unknown)
+    pub instance_id: String,
+    /// Other audio addresses of the same peer (redundant paths)
+    paths: Mutex<Vec<SocketAddr>>,
+    /// Last time the peer was seen
+    last_seen: Mutex<Instant>,
+    /// Whether audio is sent to this peer
+    active: AtomicBool,
+    /// State of the link to the peer
+    connection: Mutex<PeerConnection>,
+    /// Traffic counters
+    pub bandwidth: BandwidthMeter,
+}
+
+impl PeerEntry {
+    fn new(address: SocketAddr, name: String, instance_id: String, active: bool) -> Self {
+        Self {
+            address,
+            name,
+            instance_id,
+            paths: Mutex::new(Vec::new()),
+            last_seen: Mutex::new(Instant::now()),
+            active: AtomicBool::new(active),
+            connection: Mutex::new(PeerConnection::Connecting),
+            bandwidth: BandwidthMeter::new(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn last_seen(&self) -> Instant {
+        *self.last_seen.lock()
+    }
+
+    pub fn connection(&self) -> PeerConnection {
+        *self.connection.lock()
+    }
+
+    /// Audio addresses of the peer other than `address`
+    pub fn paths(&self) -> Vec<SocketAddr> {
+        self.paths.lock().clone()
+    }
+
+    /// Whether `ip` is one of the peer's addresses
+    fn has_ip(&self, ip: IpAddr) -> bool {
+        self.address.ip() == ip || self.paths.lock().iter().any(|path| path.ip() == ip)
+    }
+}
+
+/// Registry of known peers, keyed by "ip:audio_port"
+pub struct PeerRegistry {
+    peers: DashMap<String, PeerEntry>,
+    /// Keys of peers whose connection state changed
+    connection_tx: broadcast::Sender<String>,
+}
+
+impl PeerRegistry {
+    pub fn new() -> Self {
+        let (connection_tx, _) = broadcast::channel(64);
+        Self {
+            peers: DashMap::new(),
+            connection_tx,
+        }
+    }
+
+    /// Keys of peers whose connection state changes from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.connection_tx.subscribe()
+    }
+
+    /// Key used for a peer's audio address
+    pub fn key_for(address: SocketAddr) -> String {
+        format!("{}:{}", address.ip(), address.port())
+    }
+
+    /// IP address of a peer given by key ("ip:audio_port") or plain address
+    pub fn resolve_ip(&self, peer: &str) -> Option<IpAddr> {
+        if let Some(entry) = self.peers.get(peer) {
+            return Some(entry.address.ip());
+        }
+        peer.parse::<SocketAddr>()
+            .map(|address| address.ip())
+            .or_else(|_| peer.parse::<IpAddr>())
+            .ok()
+    }
+
+    /// Audio address of a peer given by key, or an address (a plain IP
+    /// uses the default audio port)
+    pub fn resolve_address(&self, peer: &str) -> Option<SocketAddr> {
+        if let Some(entry) = self.peers.get(peer) {
+            return Some(entry.address);
+        }
+        parse_address(peer)
+    }
+
+    /// Register a peer or refresh its last-seen time.
+    /// An address announced with the instance id of a known peer becomes a
+    /// redundant path of that peer. Returns true if the peer is new.
+    pub fn upsert(&self, address: SocketAddr, name: &str, instance_id: &str, active: bool) -> bool {
+        let key = Self::key_for(address);
+        if let Some(peer) = self.peers.get(&key) {
+            *peer.last_seen.lock() = Instant::now();
+            return false;
+        }
+
+        if !instance_id.is_empty() {
+            if let Some(peer) = self.peers.iter().find(|p| p.instance_id == instance_id) {
+                let mut paths = peer.paths.lock();
+                if !paths.contains(&address) {
+                    tracing::info!("Redundant path to {} ({}): {}", peer.name, peer.key(), address);
+                    paths.push(address);
+                }
+                *peer.last_seen.lock() = Instant::now();
+                return false;
+            }
+        }
+
+        self.peers.insert(
+            key,
+            PeerEntry::new(address, name.to_string(), instance_id.to_string(), active),
+        );
+        true
+    }
+
+    /// Redundant paths of a peer (empty if it has only one address)
+    pub fn paths(&self, key: &str) -> Vec<SocketAddr> {
+        self.peers.get(key).map(|p| p.paths()).unwrap_or_default()
+    }
+
+    /// Enable or disable sending to a peer
+    pub fn set_active(&self, key: &str, active: bool) -> bool {
+        match self.peers.get(key) {
+            Some(peer) => {
+                peer.active.store(active, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start sending to a peer given by key or audio address
+    ///
+    /// An address that isn't known yet (a peer on another subnet that
+    /// discovery can't reach) is added as a new peer. A plain IP uses the
+    /// default audio port. Returns the key, None if `peer` is neither.
+    pub fn connect(&self, peer: &str) -> Option<String> {
+        if self.set_active(peer, true) {
+            return Some(peer.to_string());
+        }
+        let address = parse_address(peer)?;
+        let key = Self::key_for(address);
+        if !self.upsert(address, &key, "", true) {
+            self.set_active(&key, true);
+        }
+        Some(key)
+    }
+
+    /// Record the state of the link to a peer
+    ///
+    /// A peer that answers again is seen now. Returns true if the peer is
+    /// known and its state changed.
+    pub fn set_connection(&self, key: &str, connection: PeerConnection) -> bool {
+        let Some(peer) = self.peers.get(key) else {
+            return false;
+        };
+        let previous = std::mem::replace(&mut *peer.connection.lock(), connection);
+        if previous == connection {
+            return false;
+        }
+        if connection == PeerConnection::Connected {
+            *peer.last_seen.lock() = Instant::now();
+        }
+        drop(peer);
+        let _ = self.connection_tx.send(key.to_string());
+        true
+    }
+
+    /// Change the display name of a peer
+    pub fn rename(&self, key: &str, name: &str) -> bool {
+        match self.peers.get_mut(key) {
+            Some(mut peer) => {
+                peer.name = name.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a peer
+    pub fn remove(&self, key: &str) -> bool {
+        self.peers.remove(key).is_some()
+    }
+
+    /// Get a peer by key
+    pub fn get(&self, key: &str) -> Option<dashmap::mapref::one::Ref<'_, String, PeerEntry>> {
+        self.peers.get(key)
+    }
+
+    /// Check whether a peer with this key is known and active
+    pub fn is_active(&self, key: &str) -> bool {
+        self.peers.get(key).map(|p| p.is_active()).unwrap_or(false)
+    }
+
+    /// Keys and addresses of active peers
+    pub fn active_peers(&self) -> Vec<(String, SocketAddr, String)> {
+        self.peers
+            .iter()
+            .filter(|p| p.is_active())
+            .map(|p| (p.key().clone(), p.address, p.name.clone()))
+            .collect()
+    }
+
+    /// Account bytes sent to a peer
+    pub fn record_sent(&self, key: &str, bytes: usize) {
+        if let Some(peer) = self.peers.get(key) {
+            peer.bandwidth.record_sent(bytes);
+        }
+    }
+
+    /// Account bytes received from a source address.
+    /// Audio arrives from the remote sender socket, whose port differs from
+    /// the advertised audio port, so the peer is matched by IP (of any path).
+    pub fn record_received(&self, source: SocketAddr, bytes: usize) -> bool {
+        match self.peers.iter().find(|p| p.has_ip(source.ip())) {
+            Some(peer) => {
+                peer.bandwidth.record_received(bytes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of known peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Get status of all peers
+    pub fn statuses(&self) -> Vec<PeerStatus> {
+        let mut statuses: Vec<PeerStatus> = self
+            .peers
+            .iter()
+            .map(|p| Self::entry_status(p.key(), &p))
+            .collect();
+        statuses.sort_by(|a, b| a.id.cmp(&b.id));
+        statuses
+    }
+
+    /// Get status of one peer
+    pub fn status(&self, key: &str) -> Option<PeerStatus> {
+        self.peers.get(key).map(|p| Self::entry_status(key, &p))
+    }
+
+    fn entry_status(key: &str, peer: &PeerEntry) -> PeerStatus {
+        PeerStatus {
+            id: key.to_string(),
+            name: peer.name.clone(),
+            address: peer.address.to_string(),
+            paths: peer.paths().iter().map(|path| path.to_string()).collect(),
+            active: peer.is_active(),
+            connection: peer.connection(),
+            last_seen_ms: peer.last_seen().elapsed().as_millis() as u64,
+            bandwidth: peer.bandwidth.snapshot(),
+        }
+    }
+}
+
+impl Default for PeerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Audio address given as `IP:PORT` or a plain IP (default audio port)
+fn parse_address(peer: &str) -> Option<SocketAddr> {
+    peer.parse::<SocketAddr>()
+        .or_else(|_| peer.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DEFAULT_UDP_PORT)))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth_totals_and_rate() {
+        let meter = BandwidthMeter::new();
+        let start = meter.session_start;
+
+        for _ in 0..100 {
+            meter.record_sent(250);
+            meter.record_received(125);
+        }
+
+        // 25000 bytes up / 12500 bytes down over 2 s
+        let snapshot = meter.snapshot_at(start + Duration::from_secs(2));
+        assert_eq!(snapshot.bytes_sent, 25_000);
+        assert_eq!(snapshot.bytes_received, 12_500);
+        assert_eq!(snapshot.packets_sent, 100);
+        assert_eq!(snapshot.packets_received, 100);
+        assert_eq!(snapshot.session_secs, 2);
+        assert!((snapshot.up_kbps - 100.0).abs() < 0.01);
+        assert!((snapshot.down_kbps - 50.0).abs() < 0.01);
+
+        // No traffic in the next window
+        let snapshot = meter.snapshot_at(start + Duration::from_secs(4));
+        assert_eq!(snapshot.up_kbps, 0.0);
+        assert_eq!(snapshot.bytes_sent, 25_000);
+    }
+
+    #[test]
+    fn test_registry_accounting() {
+        let registry = PeerRegistry::new();
+        let addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+
+        assert!(registry.upsert(addr, "Studio", "", true));
+        assert!(!registry.upsert(addr, "Studio", "", true));
+        assert_eq!(registry.len(), 1);
+
+        let key = PeerRegistry::key_for(addr);
+        registry.record_sent(&key, 100);
+
+        // Source port differs from the audio port
+        assert!(registry.record_received("192.168.1.20:40123".parse().unwrap(), 80));
+        assert!(!registry.record_received("192.168.1.99:5000".parse().unwrap(), 80));
+
+        let statuses = registry.statuses();
+        assert_eq!(statuses[0].bandwidth.bytes_sent, 100);
+        assert_eq!(statuses[0].bandwidth.bytes_received, 80);
+
+        registry.set_active(&key, false);
+        assert!(registry.active_peers().is_empty());
+    }
+
+    #[test]
+    fn test_registry_redundant_paths() {
+        let registry = PeerRegistry::new();
+        let wired: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let wireless: SocketAddr = "10.0.0.20:5000".parse().unwrap();
+
+        assert!(registry.upsert(wired, "Studio", "3f2a9c0d41be", true));
+        assert!(!registry.upsert(wireless, "Studio", "3f2a9c0d41be", true));
+        assert!(!registry.upsert(wireless, "Studio", "3f2a9c0d41be", true));
+        assert_eq!(registry.len(), 1);
+
+        let key = PeerRegistry::key_for(wired);
+        assert_eq!(registry.paths(&key), vec![wireless]);
+        assert_eq!(registry.statuses()[0].paths, vec!["10.0.0.20:5000".to_string()]);
+
+        // Audio over either network counts for the same peer
+        assert!(registry.record_received("10.0.0.20:40123".parse().unwrap(), 80));
+        assert_eq!(registry.statuses()[0].bandwidth.bytes_received, 80);
+
+        // Another instance on a known network is a different peer
+        assert!(registry.upsert("10.0.0.30:5000".parse().unwrap(), "Booth", "77d0e51a9c13", true));
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn test_registry_connect_and_rename() {
+        let registry = PeerRegistry::new();
+        let addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        registry.upsert(addr, "Studio", "", false);
+        let key = PeerRegistry::key_for(addr);
+
+        assert_eq!(registry.connect(&key), Some(key.clone()));
+        assert!(registry.is_active(&key));
+
+        // Unknown addresses are added, a plain IP gets the default port
+        assert_eq!(registry.connect("10.8.0.5"), Some("10.8.0.5:5000".to_string()));
+        assert_eq!(registry.active_peers().len(), 2);
+        assert_eq!(registry.connect("studio"), None);
+        assert_eq!(registry.resolve_address(&key), Some(addr));
+        assert_eq!(registry.resolve_address("10.8.0.9"), Some("10.8.0.9:5000".parse().unwrap()));
+        assert_eq!(registry.resolve_address("studio"), None);
+
+        assert!(registry.rename(&key, "Control room"));
+        assert!(!registry.rename("10.0.0.1:5000", "Nobody"));
+        // Rediscovery keeps the new name
+        registry.upsert(addr, "Studio", "", true);
+        assert_eq!(registry.status(&key).unwrap().name, "Control room");
+    }
+
+    #[test]
+    fn test_registry_connection_state() {
+        let registry = PeerRegistry::new();
+        let addr: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        registry.upsert(addr, "Studio", "", true);
+        let key = PeerRegistry::key_for(addr);
+        let mut changes = registry.subscribe();
+        assert_eq!(registry.status(&key).unwrap().connection, PeerConnection::Connecting);
+
+        assert!(registry.set_connection(&key, PeerConnection::Connected));
+        assert!(!registry.set_connection(&key, PeerConnection::Connected));
+        assert!(registry.set_connection(&key, PeerConnection::Lost));
+        assert!(!registry.set_connection("10.0.0.1:5000", PeerConnection::Lost));
+
+        // A lost peer stays enabled so it reconnects once it answers again
+        let status = registry.status(&key).unwrap();
+        assert_eq!((status.connection, status.active), (PeerConnection::Lost, true));
+        assert_eq!(changes.try_recv().unwrap(), key);
+        assert_eq!(changes.try_recv().unwrap(), key);
+        assert!(changes.try_recv().is_err());
+    }
+}
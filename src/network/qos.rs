@@ -0,0 +1,156 @@
+//! Windows thread scheduling and network QoS
+//!
+//! Games on the sending PC compete with the streaming threads for the
+//! CPU and the network card. On Windows the streaming threads join a
+//! Multimedia Class Scheduler (MMCSS) task, "Pro Audio" by default, which
+//! keeps them scheduled ahead of ordinary threads, and the audio flows
+//! are registered with qWave as audio/video traffic, which tags them
+//! with a DSCP class without administrator rights.
+//!
+//! Both are selected in `NetworkConfig::qos` and do nothing on other
+//! platforms.
+
+use std::net::{SocketAddr, UdpSocket as StdUdpSocket};
+
+use crate::config::QosConfig;
+
+/// MMCSS registration of the current thread, reverted on drop
+#[must_use = "the thread leaves its MMCSS task when the registration drops"]
+pub struct ThreadRegistration {
+    #[cfg(windows)]
+    _registration: Option<imp::Registration>,
+}
+
+/// Join the configured MMCSS task with the current thread
+pub fn register_thread(config: &QosConfig) -> ThreadRegistration {
+    #[cfg(windows)]
+    return ThreadRegistration {
+        _registration: config.mmcss.then(|| imp::Registration::new(&config.mmcss_task)).flatten(),
+    };
+    #[cfg(not(windows))]
+    {
+        let _ = config;
+        ThreadRegistration {}
+    }
+}
+
+/// Audio flows registered with qWave, removed on drop
+pub struct QosFlows {
+    #[cfg(windows)]
+    handle: Option<imp::Flows>,
+}
+
+impl QosFlows {
+    pub fn new(config: &QosConfig) -> Self {
+        #[cfg(windows)]
+        return Self {
+            handle: config.qwave.then(imp::Flows::new).flatten(),
+        };
+        #[cfg(not(windows))]
+        {
+            let _ = config;
+            Self {}
+        }
+    }
+
+    /// Tag datagrams from `socket` to `destination` as audio/video traffic
+    pub fn add(&self, socket: &StdUdpSocket, destination: SocketAddr) {
+        #[cfg(windows)]
+        if let Some(ref flows) = self.handle {
+            flows.add(socket, destination);
+        }
+        #[cfg(not(windows))]
+        let _ = (socket, destination);
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::net::{SocketAddr, UdpSocket as StdUdpSocket};
+    use std::os::windows::io::AsRawSocket;
+
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::NetworkManagement::QoS::{
+        QOSAddSocketToFlow, QOSCloseHandle, QOSCreateHandle, QOSTrafficTypeAudioVideo,
+        QOS_NON_ADAPTIVE_FLOW, QOS_VERSION,
+    };
+    use windows::Win32::Networking::WinSock::{SOCKADDR, SOCKET};
+    use windows::Win32::System::Threading::{
+        AvRevertMmThreadCharacteristics, AvSetMmThreadCharacteristicsW,
+    };
+
+    pub struct Registration(HANDLE);
+
+    impl Registration {
+        pub fn new(task: &str) -> Option<Self> {
+            let name: Vec<u16> = task.encode_utf16().chain(std::iter::once(0)).collect();
+            let mut task_index = 0u32;
+            // SAFETY: `name` is a NUL-terminated UTF-16 string that outlives the call
+            match unsafe { AvSetMmThreadCharacteristicsW(PCWSTR(name.as_ptr()), &mut task_index) } {
+                Ok(handle) => {
+                    tracing::debug!("Thread joined MMCSS task \"{}\"", task);
+                    Some(Self(handle))
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to join MMCSS task \"{}\": {}", task, e);
+                    None
+                }
+            }
+        }
+    }
+
+    impl Drop for Registration {
+        fn drop(&mut self) {
+            // SAFETY: the handle came from AvSetMmThreadCharacteristicsW on this thread
+            let _ = unsafe { AvRevertMmThreadCharacteristics(self.0) };
+        }
+    }
+
+    pub struct Flows(HANDLE);
+
+    impl Flows {
+        pub fn new() -> Option<Self> {
+            let version = QOS_VERSION {
+                MajorVersion: 1,
+                MinorVersion: 0,
+            };
+            let mut handle = HANDLE::default();
+            // SAFETY: both pointers refer to live locals
+            match unsafe { QOSCreateHandle(&version, &mut handle) } {
+                Ok(()) => Some(Self(handle)),
+                Err(e) => {
+                    tracing::warn!("qWave unavailable, audio flows stay untagged: {}", e);
+                    None
+                }
+            }
+        }
+
+        pub fn add(&self, socket: &StdUdpSocket, destination: SocketAddr) {
+            let address = socket2::SockAddr::from(destination);
+            let mut flow_id = 0u32;
+            // SAFETY: `address` holds a valid socket address for the duration of the call
+            let result = unsafe {
+                QOSAddSocketToFlow(
+                    self.0,
+                    SOCKET(socket.as_raw_socket() as usize),
+                    Some(address.as_ptr().cast::<SOCKADDR>()),
+                    QOSTrafficTypeAudioVideo,
+                    QOS_NON_ADAPTIVE_FLOW,
+                    &mut flow_id,
+                )
+            };
+            match result {
+                Ok(()) => tracing::debug!("qWave flow {} to {}", flow_id, destination),
+                Err(e) => tracing::warn!("Failed to tag audio flow to {}: {}", destination, e),
+            }
+        }
+    }
+
+    impl Drop for Flows {
+        fn drop(&mut self) {
+            // SAFETY: the handle came from QOSCreateHandle; closing it removes its flows
+            let _ = unsafe { QOSCloseHandle(self.0) };
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! QUIC transport: audio packets in QUIC datagrams
+//!
+//! A receiver with `TransportMode::Quic` accepts QUIC connections on
+//! `quic_port` next to its UDP socket and advertises the port in the
+//! handshake (`PeerCapabilities::quic_port`, in Hello and SyncRequest).
+//! A sender in `Quic` mode streams over UDP until the receiver has
+//! advertised the port, then connects and sends every packet unchanged as
+//! an unreliable QUIC datagram (RFC 9221): nothing is retransmitted or
+//! held back behind a lost packet, but the link is encrypted and the
+//! datagrams are paced by QUIC's congestion controller. Receivers that
+//! don't offer QUIC keep getting UDP.
+//!
+//! Receivers present a self-signed certificate that senders don't verify:
+//! QUIC encrypts the link, while which peer is on the other end is still
+//! settled by the handshake (PSK, pairing). Frames that don't fit the
+//! connection's datagram size are fragmented by the sender
+//! ([`Transport::max_datagram_size`]).
+
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use parking_lot::Mutex;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ClientConfig, Connection, Endpoint, EndpointConfig, SendDatagramError, ServerConfig, TokioRuntime, TransportConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket};
+use std::sync::{Arc, OnceLock};
+use tokio::runtime::Runtime;
+
+use crate::network::transport::{Transport, CONNECT_TIMEOUT, FALLBACK_TIMEOUT, PROBE_INTERVAL};
+use crate::network::udp::canonical_addr;
+
+/// Name in the receivers' certificates (not verified)
+const SERVER_NAME: &str = "lan-audio-streamer";
+
+/// Received datagrams waiting for the audio thread; further ones are
+/// dropped, like datagrams a full socket buffer can't hold
+const INCOMING_QUEUE: usize = 1024;
+
+/// Queued outgoing datagram bytes per connection; the oldest datagrams
+/// are dropped rather than sent late
+const DATAGRAM_SEND_BUFFER: usize = 64 * 1024;
+
+/// Received datagram and the address it is reported from
+type Datagram = (Bytes, SocketAddr);
+
+/// Runtime driving all QUIC endpoints (the audio threads stay synchronous)
+fn runtime() -> io::Result<&'static Runtime> {
+    static RUNTIME: OnceLock<io::Result<Runtime>> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("quic")
+                .enable_all()
+                .build()
+        })
+        .as_ref()
+        .map_err(|e| io::Error::new(e.kind(), e.to_string()))
+}
+
+/// Connections close like a TCP fallback connection: after the same
+/// silence, with keepalives in between
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config
+        .max_idle_timeout(FALLBACK_TIMEOUT.try_into().ok())
+        .keep_alive_interval(Some(PROBE_INTERVAL))
+        .datagram_send_buffer_size(DATAGRAM_SEND_BUFFER);
+    Arc::new(config)
+}
+
+/// Non-blocking UDP socket on `addr` (dual-stack like the audio socket)
+fn bind(addr: SocketAddr) -> io::Result<StdUdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+fn send_datagram(connection: &Connection, data: &[u8]) -> io::Result<usize> {
+    connection.send_datagram(Bytes::copy_from_slice(data)).map_err(|e| match e {
+        SendDatagramError::ConnectionLost(e) => io::Error::new(io::ErrorKind::ConnectionReset, e),
+        SendDatagramError::TooLarge => io::Error::new(io::ErrorKind::InvalidInput, e),
+        e => io::Error::new(io::ErrorKind::Unsupported, e),
+    })?;
+    Ok(data.len())
+}
+
+/// Next received datagram, truncated to `buf`
+fn recv_datagram(incoming: &Receiver<Datagram>, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    let (data, from) = incoming.try_recv().map_err(|e| match e {
+        TryRecvError::Empty => io::Error::from(io::ErrorKind::WouldBlock),
+        TryRecvError::Disconnected => io::Error::from(io::ErrorKind::NotConnected),
+    })?;
+    let size = data.len().min(buf.len());
+    buf[..size].copy_from_slice(&data[..size]);
+    Ok((size, from))
+}
+
+/// Forward a connection's datagrams, reported from `peer`, until it closes
+async fn read_datagrams(connection: Connection, peer: SocketAddr, incoming: Sender<Datagram>) -> quinn::ConnectionError {
+    loop {
+        match connection.read_datagram().await {
+            Ok(data) => {
+                let _ = incoming.try_send((data, peer));
+            }
+            Err(e) => return e,
+        }
+    }
+}
+
+/// Accepts any certificate: peers are authenticated by the handshake
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn client_config() -> io::Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+    let crypto = QuicClientConfig::try_from(crypto).map_err(io::Error::other)?;
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn server_config() -> io::Result<ServerConfig> {
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).map_err(io::Error::other)?;
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let mut config =
+        ServerConfig::with_single_cert(vec![certified.cert.der().clone()], key.into()).map_err(io::Error::other)?;
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+/// Sender side: packets in datagrams of a QUIC connection to the receiver
+pub struct QuicTransport {
+    endpoint: Endpoint,
+    connection: Connection,
+    /// Audio address of the receiver: packets are sent to it and
+    /// datagrams reported from it
+    peer: SocketAddr,
+    incoming: Receiver<Datagram>,
+}
+
+impl QuicTransport {
+    /// Connect to the QUIC port of the receiver with audio address `peer`
+    pub fn connect(peer: SocketAddr, quic_port: u16) -> io::Result<Self> {
+        let runtime = runtime()?;
+        let peer = canonical_addr(peer);
+        let server = SocketAddr::new(peer.ip(), quic_port);
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+
+        let _runtime = runtime.enter();
+        let mut endpoint = Endpoint::new(EndpointConfig::default(), None, bind(local)?, Arc::new(TokioRuntime))?;
+        endpoint.set_default_client_config(client_config()?);
+        let connecting = endpoint
+            .connect(server, SERVER_NAME)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let (result_tx, result_rx) = crossbeam_channel::bounded(1);
+        runtime.spawn(async move {
+            let _ = result_tx.send(connecting.await);
+        });
+        let connection = match result_rx.recv_timeout(CONNECT_TIMEOUT) {
+            Ok(result) => result?,
+            Err(_) => {
+                endpoint.close(0u32.into(), b"");
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        };
+
+        let (incoming_tx, incoming) = crossbeam_channel::bounded(INCOMING_QUEUE);
+        runtime.spawn(read_datagrams(connection.clone(), peer, incoming_tx));
+        Ok(Self {
+            endpoint,
+            connection,
+            peer,
+            incoming,
+        })
+    }
+}
+
+impl Transport for QuicTransport {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if canonical_addr(addr) != self.peer {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "no connection to this address"));
+        }
+        send_datagram(&self.connection, data)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        recv_datagram(&self.incoming, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    fn max_datagram_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size()
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        self.connection.close(0u32.into(), b"");
+    }
+}
+
+/// Receiver side: QUIC connections from senders; datagrams are reported
+/// from the sender's QUIC address and replies to it go back the same way
+pub struct QuicListener {
+    endpoint: Endpoint,
+    connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    incoming: Receiver<Datagram>,
+}
+
+impl QuicListener {
+    /// Accept QUIC on `addr` with a fresh self-signed certificate
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let runtime = runtime()?;
+        let config = server_config()?;
+        let _runtime = runtime.enter();
+        let endpoint = Endpoint::new(EndpointConfig::default(), Some(config), bind(addr)?, Arc::new(TokioRuntime))?;
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let (incoming_tx, incoming) = crossbeam_channel::bounded(INCOMING_QUEUE);
+        runtime.spawn(Self::accept(endpoint.clone(), connections.clone(), incoming_tx));
+        Ok(Self {
+            endpoint,
+            connections,
+            incoming,
+        })
+    }
+
+    /// Addresses of senders connected over QUIC
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        self.connections.lock().keys().copied().collect()
+    }
+
+    /// Whether a sender with this address is connected over QUIC
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.connections.lock().contains_key(&canonical_addr(addr))
+    }
+
+    async fn accept(
+        endpoint: Endpoint,
+        connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+        incoming: Sender<Datagram>,
+    ) {
+        while let Some(connecting) = endpoint.accept().await {
+            let connections = connections.clone();
+            let incoming = incoming.clone();
+            tokio::spawn(async move {
+                let connection = match connecting.await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        tracing::debug!("QUIC handshake failed: {}", e);
+                        return;
+                    }
+                };
+                let peer = canonical_addr(connection.remote_address());
+                tracing::info!("Sender {} connected over QUIC", peer);
+                connections.lock().insert(peer, connection.clone());
+                let error = read_datagrams(connection.clone(), peer, incoming).await;
+                let mut connections = connections.lock();
+                if connections.get(&peer).is_some_and(|current| current.stable_id() == connection.stable_id()) {
+                    connections.remove(&peer);
+                }
+                tracing::info!("QUIC connection from {} closed: {}", peer, error);
+            });
+        }
+    }
+}
+
+impl Transport for QuicListener {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let connection = self.connections.lock().get(&canonical_addr(addr)).cloned();
+        match connection {
+            Some(connection) => send_datagram(&connection, data),
+            None => Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "no connection to this address")),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        recv_datagram(&self.incoming, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+}
+
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        self.endpoint.close(0u32.into(), b"");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait_recv(transport: &dyn Transport, buf: &mut [u8]) -> (usize, SocketAddr) {
+        for _ in 0..200 {
+            if let Ok(received) = transport.recv_from(buf) {
+                return received;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no datagram received");
+    }
+
+    #[test]
+    fn test_quic_roundtrip() {
+        let listener = QuicListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let quic_port = listener.local_addr().unwrap().port();
+        let receiver: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        let sender = QuicTransport::connect(receiver, quic_port).unwrap();
+        assert!(sender.max_datagram_size().is_some_and(|size| size >= 1000));
+        sender.send_to(b"first", receiver).unwrap();
+        sender.send_to(b"second", receiver).unwrap();
+        assert!(sender.send_to(b"elsewhere", "127.0.0.1:9".parse().unwrap()).is_err());
+
+        // Datagrams may be reordered, not merged
+        let mut buf = [0u8; 64];
+        let (size, from) = wait_recv(&listener, &mut buf);
+        let first = buf[..size].to_vec();
+        let (size, _) = wait_recv(&listener, &mut buf);
+        let mut received = vec![first, buf[..size].to_vec()];
+        received.sort();
+        assert_eq!(received, vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(from.port(), sender.local_addr().unwrap().port());
+        assert!(listener.is_connected(from));
+        assert_eq!(listener.peers(), vec![from]);
+
+        // Replies take the connection and come from the receiver's audio address
+        listener.send_to(b"reply", from).unwrap();
+        let (size, reply_from) = wait_recv(&sender, &mut buf);
+        assert_eq!(&buf[..size], b"reply");
+        assert_eq!(reply_from, receiver);
+        assert!(listener.send_to(b"reply", "127.0.0.1:9".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_connect_without_listener_fails() {
+        let unused = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = unused.local_addr().unwrap().port();
+        drop(unused);
+        assert!(QuicTransport::connect("127.0.0.1:5000".parse().unwrap(), port).is_err());
+    }
+}
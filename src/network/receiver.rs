@@ -1,18 +1,49 @@
 //! Audio packet receiver
 //!
 //! Handles receiving audio packets and demultiplexing by track ID.
+//!
+//! A redundant track arrives once per network path; the first copy of
+//! every packet is passed on and later copies are dropped before they
+//! reach a decoder. With a PSK the copies are caught by the replay window
+//! of the cipher and counted as duplicates too.
+//!
+//! In the RTP packet format the socket takes RTP and RTCP instead of
+//! `AudioPacket`s and sends receiver reports back to every source.
+//!
+//! Senders that can't reach the socket over UDP connect over TCP instead
+//! (see `network::transport`); their packets take the same path.
+//!
+//! Frames too large for one datagram arrive as fragments (see
+//! `PacketFlags::FRAGMENT`) and are reassembled before decryption; a frame
+//! with a missing fragment is dropped after [`FRAGMENT_TIMEOUT`], like a
+//! lost packet.
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use crossbeam_channel::Sender;
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
 use crate::error::NetworkError;
-use crate::network::udp::create_socket;
-use crate::protocol::AudioPacket;
-use crate::config::NetworkConfig;
+use crate::network::buffer_tuning::{self, BufferTuner};
+use crate::network::crypto::OpenError;
+use crate::network::feedback::FeedbackInbox;
+use crate::network::file_transfer::FileTransfers;
+use crate::network::handshake::HandshakeManager;
+use crate::network::netsim::NetworkSimulator;
+use crate::network::packet_dump::PacketDump;
+use crate::network::packet_log::{self, Direction};
+use crate::network::qos;
+use crate::network::rtp::{self, RtpPacket, RtpReceiver};
+use crate::network::subscription::TrackSubscriber;
+use crate::network::timesync::{handle_socket_packet, is_handshake_packet, TimeSync};
+use crate::network::transport::{ReceiverTransport, Transport};
+use crate::network::udp::{canonical_addr, create_socket, target_for_socket};
+use crate::protocol::{AudioPacket, Codec};
+use crate::config::{NetworkConfig, PacketFormat};
 
 /// Received packet ready for decoding
 #[derive(Debug, Clone)]
@@ -23,7 +54,15 @@ pub struct ReceivedPacket {
     pub payload: Bytes,
     pub is_stereo: bool,
     pub has_fec: bool,
+    /// Stream restart marker (see `PacketFlags::KEYFRAME`)
+    pub is_keyframe: bool,
+    /// Latency probe frame (see `PacketFlags::PROBE`)
+    pub is_probe: bool,
+    /// Codec of the payload (see `PacketFlags::CODEC_MASK`)
+    pub codec: Codec,
     pub receive_time: std::time::Instant,
+    /// Source address (set by the receiver thread)
+    pub source: Option<SocketAddr>,
 }
 
 impl From<AudioPacket> for ReceivedPacket {
@@ -35,8 +74,127 @@ impl From<AudioPacket> for ReceivedPacket {
             payload: packet.payload,
             is_stereo: packet.flags.is_stereo(),
             has_fec: packet.flags.has_fec(),
+            is_keyframe: packet.flags.is_keyframe(),
+            is_probe: packet.flags.is_probe(),
+            codec: packet.flags.codec().unwrap_or_default(),
             receive_time: std::time::Instant::now(),
+            source: None,
+        }
+    }
+}
+
+/// Largest datagram received (MTU plus headers, with room to spare)
+const MAX_DATAGRAM_SIZE: usize = 2048;
+
+/// Size of the arena datagrams are received into
+const RECV_ARENA_SIZE: usize = MAX_DATAGRAM_SIZE * 32;
+
+/// Packets remembered per track for duplicate detection
+/// (well over the reordering a second network path can add)
+const DUPLICATE_WINDOW: usize = 64;
+
+/// Drops the second copy of packets sent over redundant paths
+#[derive(Debug, Default)]
+pub struct DuplicateFilter {
+    /// Recent (sequence, timestamp) pairs per track
+    recent: HashMap<u8, VecDeque<(u32, u64)>>,
+}
+
+impl DuplicateFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Remember a packet; returns true if it was already seen.
+    /// The timestamp tells a copy apart from a restarted stream that
+    /// reuses the sequence number.
+    pub fn is_duplicate(&mut self, track_id: u8, sequence: u32, timestamp: u64) -> bool {
+        let recent = self.recent.entry(track_id).or_default();
+        if recent.contains(&(sequence, timestamp)) {
+            return true;
+        }
+        if recent.len() == DUPLICATE_WINDOW {
+            recent.pop_front();
+        }
+        recent.push_back((sequence, timestamp));
+        false
+    }
+}
+
+/// Time to wait for the missing fragments of a frame
+pub const FRAGMENT_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Frames reassembled at a time (older ones are given up)
+const MAX_PENDING_FRAMES: usize = 64;
+
+/// Fragments received so far of one frame
+#[derive(Debug)]
+struct PendingFrame {
+    /// Header of the frame (FRAGMENT cleared)
+    header: AudioPacket,
+    chunks: Vec<Option<Bytes>>,
+    received: usize,
+    first_seen: std::time::Instant,
+}
+
+/// Puts fragmented frames back together
+#[derive(Debug, Default)]
+pub struct FragmentAssembler {
+    /// Frames by source, track, sequence and timestamp
+    pending: HashMap<(SocketAddr, u8, u32, u64), PendingFrame>,
+}
+
+impl FragmentAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add a fragment; returns the whole frame once its last fragment
+    /// arrived. Repeated fragments (redundant paths) are ignored.
+    pub fn push(&mut self, source: SocketAddr, fragment: AudioPacket, now: std::time::Instant) -> Option<AudioPacket> {
+        let (index, count, chunk) = fragment.fragment_info()?;
+        self.pending.retain(|_, frame| now.duration_since(frame.first_seen) < FRAGMENT_TIMEOUT);
+        
+        let key = (source, fragment.track_id, fragment.sequence, fragment.timestamp);
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING_FRAMES {
+            let oldest = self.pending.iter().min_by_key(|(_, frame)| frame.first_seen).map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.pending.remove(&oldest);
+            }
+        }
+        let frame = self.pending.entry(key).or_insert_with(|| PendingFrame {
+            header: AudioPacket {
+                flags: fragment.flags.set_fragment(false),
+                payload: Bytes::new(),
+                ..fragment
+            },
+            chunks: vec![None; count as usize],
+            received: 0,
+            first_seen: now,
+        });
+        let slot = frame.chunks.get_mut(index as usize)?;
+        if slot.is_none() {
+            *slot = Some(chunk);
+            frame.received += 1;
+        }
+        if frame.received < frame.chunks.len() {
+            return None;
+        }
+        
+        let frame = self.pending.remove(&key)?;
+        let mut payload = bytes::BytesMut::new();
+        for chunk in frame.chunks.into_iter().flatten() {
+            payload.extend_from_slice(&chunk);
         }
+        Some(AudioPacket {
+            payload: payload.freeze(),
+            ..frame.header
+        })
+    }
+    
+    /// Frames still waiting for fragments
+    pub fn pending(&self) -> usize {
+        self.pending.len()
     }
 }
 
@@ -60,11 +218,39 @@ pub struct AudioReceiver {
     /// Invalid packets counter
     invalid_packets: Arc<AtomicU64>,
     
+    /// Second copies of packets (redundant paths) counter
+    duplicate_packets: Arc<AtomicU64>,
+    
+    /// Packets the kernel dropped on the socket (`u64::MAX` = not reported)
+    socket_drops: Arc<AtomicU64>,
+    
+    /// Effective socket buffer sizes
+    recv_buffer_size: Arc<AtomicUsize>,
+    send_buffer_size: Arc<AtomicUsize>,
+    
     /// Per-track packet channels
     track_channels: Arc<DashMap<u8, Sender<ReceivedPacket>>>,
     
     /// Global packet channel (for all tracks)
     global_tx: Option<Sender<ReceivedPacket>>,
+    
+    /// Clock synchronization with senders
+    time_sync: Option<Arc<TimeSync>>,
+    
+    /// Feedback from remote receivers arriving on this socket
+    feedback: Option<Arc<FeedbackInbox>>,
+    
+    /// Track subscriptions with senders
+    subscriber: Option<Arc<TrackSubscriber>>,
+    
+    /// Files received from senders
+    files: Option<Arc<FileTransfers>>,
+    
+    /// Handshakes with peers (Hello is answered on the audio port)
+    handshake: Option<Arc<HandshakeManager>>,
+    
+    /// Receiving transport, shared for control packets
+    transport: Option<Arc<ReceiverTransport>>,
 }
 
 impl AudioReceiver {
@@ -76,11 +262,61 @@ impl AudioReceiver {
             packets_received: Arc::new(AtomicU64::new(0)),
             bytes_received: Arc::new(AtomicU64::new(0)),
             invalid_packets: Arc::new(AtomicU64::new(0)),
+            duplicate_packets: Arc::new(AtomicU64::new(0)),
+            socket_drops: Arc::new(AtomicU64::new(u64::MAX)),
+            recv_buffer_size: Arc::new(AtomicUsize::new(0)),
+            send_buffer_size: Arc::new(AtomicUsize::new(0)),
             track_channels: Arc::new(DashMap::new()),
             global_tx: None,
+            time_sync: None,
+            feedback: None,
+            subscriber: None,
+            files: None,
+            handshake: None,
+            transport: None,
         }
     }
     
+    /// Enable clock synchronization with senders (pings every audio source)
+    pub fn set_time_sync(&mut self, time_sync: Arc<TimeSync>) {
+        self.time_sync = Some(time_sync);
+    }
+    
+    /// Collect feedback packets arriving on the audio port (peer mode)
+    pub fn set_feedback_inbox(&mut self, inbox: Arc<FeedbackInbox>) {
+        self.feedback = Some(inbox);
+    }
+    
+    /// Subscribe to the tracks selected in `subscriber` at every audio source
+    pub fn set_subscriber(&mut self, subscriber: Arc<TrackSubscriber>) {
+        self.subscriber = Some(subscriber);
+    }
+    
+    /// Accept files offered by senders (peer mode)
+    pub fn set_file_transfers(&mut self, files: Arc<FileTransfers>) {
+        self.files = Some(files);
+    }
+    
+    /// Answer and track handshakes arriving on the audio port
+    pub fn set_handshake(&mut self, handshake: Arc<HandshakeManager>) {
+        self.handshake = Some(handshake);
+    }
+    
+    /// Send a control packet (e.g. receiver feedback) from the audio port
+    pub fn send_control(&self, data: &[u8], addr: SocketAddr) -> Result<usize, NetworkError> {
+        let transport = self.transport
+            .as_ref()
+            .ok_or_else(|| NetworkError::SendFailed("receiver not started".to_string()))?;
+        let target = match transport.local_addr() {
+            Ok(local) => target_for_socket(local, addr),
+            Err(_) => addr,
+        };
+        packet_log::record(Direction::Sent, addr, data);
+        transport
+            .send_to(data, target)
+            .map_err(|e| NetworkError::SendFailed(e.to_string()))
+    }
+    
     /// Set global packet channel
     pub fn set_global_channel(&mut self, tx: Sender<ReceivedPacket>) {
         self.global_tx = Some(tx);
@@ -102,41 +338,222 @@ impl AudioReceiver {
             return Ok(());
         }
         
+        rtp::check_config(&config)?;
         let socket = create_socket(&config)?;
+        let cipher = config.cipher();
+        let mut rtp = (config.packet_format == PacketFormat::Rtp).then(RtpReceiver::new);
+        if let Some(ref subscriber) = self.subscriber {
+            subscriber.set_accept_plaintext(cipher.is_some() && config.allow_plaintext_tracks);
+            subscriber.set_cipher(config.cipher());
+        }
+        let max_recv_buffer = if config.recv_buffer_autotune {
+            config.recv_buffer_max_size
+        } else {
+            config.recv_buffer_size
+        };
+        let mut buffer_tuner = BufferTuner::new(config.recv_buffer_size, max_recv_buffer);
+        let mut dump = match config.packet_dump {
+            Some(ref path) => Some(PacketDump::create(path).map_err(|e| {
+                NetworkError::ReceiveFailed(format!("packet dump {}: {}", path.display(), e))
+            })?),
+            None => None,
+        };
+        self.recv_buffer_size.store(buffer_tuning::recv_buffer_size(&socket).unwrap_or(0), Ordering::Relaxed);
+        self.send_buffer_size.store(buffer_tuning::send_buffer_size(&socket).unwrap_or(0), Ordering::Relaxed);
         
         let running = self.running.clone();
         let packets_received = self.packets_received.clone();
         let bytes_received = self.bytes_received.clone();
         let invalid_packets = self.invalid_packets.clone();
+        let duplicate_packets = self.duplicate_packets.clone();
+        let socket_drops = self.socket_drops.clone();
+        let recv_buffer_size = self.recv_buffer_size.clone();
         let track_channels = self.track_channels.clone();
         let global_tx = self.global_tx.clone();
+        let time_sync = self.time_sync.clone();
+        let feedback = self.feedback.clone();
+        let subscriber = self.subscriber.clone();
+        let files = self.files.clone();
+        let handshake = self.handshake.clone();
+        let local_addr = socket.local_addr()
+            .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
+        let transport = Arc::new(ReceiverTransport::new(socket, &config));
+        self.transport = Some(transport.clone());
         
         running.store(true, Ordering::SeqCst);
         
         let handle = thread::Builder::new()
             .name("audio-receiver".to_string())
             .spawn(move || {
-                // Use larger buffer to handle MTU + headers
-                let mut recv_buffer = vec![0u8; 2048];
+                let _mmcss = qos::register_thread(&config.qos);
+                
+                // Datagrams are received into an arena and audio packets
+                // split off it without a copy; the arena is reused once the
+                // packets taken from it are dropped
+                let mut recv_buffer = BytesMut::with_capacity(RECV_ARENA_SIZE);
                 
                 // Adaptive backoff for empty reads
                 let mut empty_reads = 0u32;
                 const MAX_EMPTY_READS: u32 = 100;
                 
+                let mut last_ping_check = std::time::Instant::now();
+                let mut duplicates = DuplicateFilter::new();
+                let mut fragments = FragmentAssembler::new();
+                let mut simulator = config.simulate.is_active().then(|| NetworkSimulator::new(config.simulate.clone()));
+                
                 while running.load(Ordering::Relaxed) {
-                    match socket.recv_from(&mut recv_buffer) {
-                        Ok((size, _addr)) => {
+                    // Periodic clock-sync pings and track subscriptions to audio sources
+                    if last_ping_check.elapsed() >= std::time::Duration::from_millis(100) {
+                        last_ping_check = std::time::Instant::now();
+                        let pings = time_sync.as_ref().map(|sync| sync.due_pings()).unwrap_or_default();
+                        let requests = subscriber.as_ref().map(|s| s.due_packets()).unwrap_or_default();
+                        let reports = rtp.as_mut().map(|rtp| rtp.due_reports(last_ping_check)).unwrap_or_default();
+                        for (addr, packet) in pings.into_iter().chain(requests).chain(reports) {
+                            packet_log::record(Direction::Sent, addr, &packet);
+                            let _ = transport.send_to(&packet, target_for_socket(local_addr, addr));
+                        }
+                        
+                        // The dump on disk stays at most this tick behind
+                        if let Some(Err(e)) = dump.as_mut().map(|dump| dump.flush()) {
+                            tracing::warn!("Packet dump stopped: {}", e);
+                            dump = None;
+                        }
+                        
+                        // Kernel drop counter; grows the receive buffer while it rises
+                        if buffer_tuner.is_due(last_ping_check) {
+                            let socket = transport.udp();
+                            if let Some(drops) = buffer_tuning::socket_drops(socket) {
+                                socket_drops.store(drops, Ordering::Relaxed);
+                                if let Some(size) = buffer_tuning::autotune(&mut buffer_tuner, socket, drops, last_ping_check) {
+                                    recv_buffer_size.store(size, Ordering::Relaxed);
+                                }
+                            }
+                        }
+                    }
+                    
+                    // Use larger buffer to handle MTU + headers
+                    recv_buffer.resize(MAX_DATAGRAM_SIZE, 0);
+                    
+                    // Datagrams the network simulator releases come first
+                    let simulated = simulator.as_mut().and_then(|simulator| simulator.pop_ready(std::time::Instant::now()));
+                    let received = match simulated {
+                        Some((ref data, addr)) => {
+                            recv_buffer[..data.len()].copy_from_slice(data);
+                            Ok((data.len(), addr))
+                        }
+                        None => transport.recv_from(&mut recv_buffer),
+                    };
+                    match received {
+                        Ok((size, addr)) => {
                             // Reset empty read counter on successful receive
                             empty_reads = 0;
                             
+                            // Clock-sync ping/pong share the audio port
+                            if is_handshake_packet(&recv_buffer[..size]) {
+                                let addr = canonical_addr(addr);
+                                packet_log::record(Direction::Received, addr, &recv_buffer[..size]);
+                                let trusted = handshake.as_ref().is_some_and(|handshake| handshake.is_trusted(&addr));
+                                if feedback.as_ref().is_some_and(|inbox| inbox.handle_packet(&recv_buffer[..size], addr, trusted)) {
+                                    continue;
+                                }
+                                if subscriber.as_ref().is_some_and(|s| s.handle_packet(&recv_buffer[..size], addr)) {
+                                    continue;
+                                }
+                                let reply = handshake.as_ref().and_then(|handshake| {
+                                    handshake.handle_packet(&recv_buffer[..size], addr).or_else(|| {
+                                        files.as_ref().and_then(|files| files.handle_packet(&recv_buffer[..size], addr, handshake))
+                                    })
+                                })
+                                    .unwrap_or_else(|| handle_socket_packet(time_sync.as_deref(), &recv_buffer[..size], addr));
+                                if let Some(reply) = reply {
+                                    packet_log::record(Direction::Sent, addr, &reply);
+                                    let _ = transport.send_to(&reply, target_for_socket(local_addr, addr));
+                                }
+                                continue;
+                            }
+                            
+                            // Audio goes through the simulated network first
+                            if let Some(simulator) = simulator.as_mut().filter(|_| simulated.is_none()) {
+                                simulator.push(&recv_buffer[..size], addr, std::time::Instant::now());
+                                continue;
+                            }
+                            
+                            if let Some(ref mut rtp) = rtp {
+                                if rtp::is_rtcp(&recv_buffer[..size]) {
+                                    rtp.handle_rtcp(&recv_buffer[..size], canonical_addr(addr), std::time::Instant::now());
+                                    continue;
+                                }
+                            }
+                            
                             bytes_received.fetch_add(size as u64, Ordering::Relaxed);
                             
-                            // Parse packet
-                            let data = Bytes::copy_from_slice(&recv_buffer[..size]);
-                            if let Some(packet) = AudioPacket::deserialize(data) {
+                            // Parse packet; with a PSK configured only packets
+                            // sealed with the same key are accepted, plus
+                            // plaintext tracks negotiated with their source
+                            recv_buffer.truncate(size);
+                            let data = recv_buffer.split().freeze();
+                            let mut replayed = false;
+                            let packet = match rtp {
+                                Some(ref mut rtp) => RtpPacket::deserialize(data).map(|packet| {
+                                    rtp.receive(packet, canonical_addr(addr), std::time::Instant::now())
+                                }),
+                                None => {
+                                    // Fragments wait for the rest of their frame
+                                    let packet = match AudioPacket::deserialize(data) {
+                                        Some(packet) if packet.flags.is_fragment() => {
+                                            match fragments.push(canonical_addr(addr), packet, std::time::Instant::now()) {
+                                                Some(frame) => Some(frame),
+                                                None => continue,
+                                            }
+                                        }
+                                        packet => packet,
+                                    };
+                                    packet
+                                        .and_then(|mut packet| match cipher {
+                                            Some(ref cipher) if packet.flags.is_encrypted() => {
+                                                match cipher.open_packet(&mut packet) {
+                                                    Ok(()) => Some(packet),
+                                                    Err(e) => {
+                                                        replayed = e == OpenError::Replayed;
+                                                        None
+                                                    }
+                                                }
+                                            }
+                                            Some(_) => subscriber
+                                                .as_ref()
+                                                .is_some_and(|s| s.is_plaintext_track(canonical_addr(addr), packet.track_id))
+                                                .then_some(packet),
+                                            None => (!packet.flags.is_encrypted()).then_some(packet),
+                                        })
+                                        // A codec this version can't decode
+                                        .filter(|packet| packet.flags.codec().is_some())
+                                        .map(ReceivedPacket::from)
+                                }
+                            };
+                            
+                            // The same sealed packet over a redundant path
+                            if replayed {
+                                duplicate_packets.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                            if let Some(mut received) = packet {
+                                if duplicates.is_duplicate(received.track_id, received.sequence, received.timestamp) {
+                                    duplicate_packets.fetch_add(1, Ordering::Relaxed);
+                                    continue;
+                                }
                                 packets_received.fetch_add(1, Ordering::Relaxed);
                                 
-                                let received = ReceivedPacket::from(packet);
+                                received.source = Some(canonical_addr(addr));
+                                if let Some(ref sync) = time_sync {
+                                    sync.note_source(canonical_addr(addr));
+                                }
+                                if let Some(ref subscriber) = subscriber {
+                                    subscriber.note_source(canonical_addr(addr));
+                                }
+                                if let Some(Err(e)) = dump.as_mut().map(|dump| dump.write(&received)) {
+                                    tracing::warn!("Packet dump stopped: {}", e);
+                                    dump = None;
+                                }
                                 let track_id = received.track_id;
                                 
                                 // Send to track-specific channel (non-blocking)
@@ -149,7 +566,10 @@ impl AudioReceiver {
                                     let _ = tx.try_send(received);
                                 }
                             } else {
-                                invalid_packets.fetch_add(1, Ordering::Relaxed);
+                                let invalid = invalid_packets.fetch_add(1, Ordering::Relaxed);
+                                if cipher.is_some() && invalid.is_multiple_of(1000) {
+                                    tracing::warn!("Rejected unencrypted or unauthenticated packet from {}", addr);
+                                }
                             }
                         }
                         Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
@@ -189,6 +609,7 @@ impl AudioReceiver {
         if let Some(handle) = self.thread_handle.take() {
             let _ = handle.join();
         }
+        self.transport = None;
     }
     
     /// Check if running
@@ -211,13 +632,29 @@ impl AudioReceiver {
         self.invalid_packets.load(Ordering::Relaxed)
     }
     
+    /// Get duplicate packets count (copies that arrived over a redundant path)
+    pub fn duplicate_packets(&self) -> u64 {
+        self.duplicate_packets.load(Ordering::Relaxed)
+    }
+    
+    /// Get packets the kernel dropped because the receive buffer was full
+    /// (None where the platform doesn't report it)
+    pub fn socket_drops(&self) -> Option<u64> {
+        let drops = self.socket_drops.load(Ordering::Relaxed);
+        (drops != u64::MAX).then_some(drops)
+    }
+    
     /// Get statistics
     pub fn stats(&self) -> ReceiverStats {
         ReceiverStats {
             packets_received: self.packets_received(),
             bytes_received: self.bytes_received(),
             invalid_packets: self.invalid_packets(),
+            duplicate_packets: self.duplicate_packets(),
             registered_tracks: self.track_channels.len(),
+            socket_drops: self.socket_drops(),
+            recv_buffer_size: self.recv_buffer_size.load(Ordering::Relaxed),
+            send_buffer_size: self.send_buffer_size.load(Ordering::Relaxed),
         }
     }
 }
@@ -240,7 +677,13 @@ pub struct ReceiverStats {
     pub packets_received: u64,
     pub bytes_received: u64,
     pub invalid_packets: u64,
+    pub duplicate_packets: u64,
     pub registered_tracks: usize,
+    /// Packets dropped by the kernel (full receive buffer), where reported
+    pub socket_drops: Option<u64>,
+    /// Effective socket buffer sizes in bytes (after autotuning)
+    pub recv_buffer_size: usize,
+    pub send_buffer_size: usize,
 }
 
 /// Per-track receiver that processes packets for a single track
@@ -345,3 +788,119 @@ pub struct TrackReceiverStats {
     pub out_of_order: u64,
     pub loss_rate: f32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PacketFlags, MAX_PAYLOAD_SIZE};
+    
+    #[test]
+    fn test_duplicate_filter() {
+        let mut filter = DuplicateFilter::new();
+        assert!(!filter.is_duplicate(1, 10, 1_000));
+        assert!(!filter.is_duplicate(1, 11, 11_000));
+        // Copy over the second path, possibly late
+        assert!(filter.is_duplicate(1, 10, 1_000));
+        assert!(filter.is_duplicate(1, 11, 11_000));
+        // Other tracks and restarted streams are not copies
+        assert!(!filter.is_duplicate(2, 10, 1_000));
+        assert!(!filter.is_duplicate(1, 10, 500_000));
+        
+        // Only the latest packets are remembered
+        for sequence in 100..100 + DUPLICATE_WINDOW as u32 {
+            filter.is_duplicate(1, sequence, sequence as u64);
+        }
+        assert!(!filter.is_duplicate(1, 11, 11_000));
+    }
+    
+    #[test]
+    fn test_sealed_copy_over_second_path() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut config = NetworkConfig {
+            bind_address: "127.0.0.1".to_string(),
+            udp_port: port,
+            psk: Some("two paths".to_string()),
+            ..NetworkConfig::default()
+        };
+        config.qos.disable();
+        let (packet_tx, packet_rx) = crossbeam_channel::bounded(16);
+        let mut receiver = AudioReceiver::new();
+        receiver.set_global_channel(packet_tx);
+        receiver.start(config.clone()).unwrap();
+        
+        let payload = Bytes::from_static(&[0x11; 40]);
+        let mut packet = AudioPacket::new(1, 7, 1_000, payload.clone());
+        config.cipher().unwrap().seal_packet(&mut packet);
+        let datagram = packet.serialize();
+        for _ in 0..2 {
+            let path = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            path.send_to(&datagram, ("127.0.0.1", port)).unwrap();
+        }
+        
+        let received = packet_rx.recv_timeout(std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(received.payload, payload);
+        assert!(packet_rx.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+        assert_eq!(receiver.duplicate_packets(), 1);
+        assert_eq!(receiver.invalid_packets(), 0);
+        receiver.stop();
+    }
+    
+    #[test]
+    fn test_fragment_reassembly() {
+        let source: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let payload: Vec<u8> = (0..4000u32).map(|i| (i % 251) as u8).collect();
+        let mut frame = AudioPacket::new(3, 42, 420_000, Bytes::from(payload.clone()));
+        frame.flags = PacketFlags::new().set_stereo(true);
+        let fragments = frame.fragment(MAX_PAYLOAD_SIZE).unwrap();
+        assert_eq!(fragments.len(), 3);
+        
+        // Out of order, with a copy over a second path
+        let mut assembler = FragmentAssembler::new();
+        let now = std::time::Instant::now();
+        assert!(assembler.push(source, fragments[2].clone(), now).is_none());
+        assert!(assembler.push(source, fragments[0].clone(), now).is_none());
+        assert!(assembler.push(source, fragments[0].clone(), now).is_none());
+        let whole = assembler.push(source, fragments[1].clone(), now).unwrap();
+        assert_eq!(whole.payload.as_ref(), payload.as_slice());
+        assert_eq!((whole.track_id, whole.sequence, whole.timestamp), (3, 42, 420_000));
+        assert!(whole.flags.is_stereo() && !whole.flags.is_fragment());
+        assert_eq!(assembler.pending(), 0);
+        
+        // The same sequence from another source is another frame
+        let other: SocketAddr = "192.168.1.30:5000".parse().unwrap();
+        assert!(assembler.push(source, fragments[0].clone(), now).is_none());
+        assert!(assembler.push(other, fragments[1].clone(), now).is_none());
+        assert_eq!(assembler.pending(), 2);
+    }
+    
+    #[test]
+    fn test_incomplete_fragments_expire() {
+        let source: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        let frame = AudioPacket::new(1, 7, 70_000, Bytes::from(vec![9u8; 3000]));
+        let fragments = frame.fragment(MAX_PAYLOAD_SIZE).unwrap();
+        
+        let mut assembler = FragmentAssembler::new();
+        let now = std::time::Instant::now();
+        assert!(assembler.push(source, fragments[0].clone(), now).is_none());
+        
+        // The rest arrives too late: the frame counts as lost
+        let late = now + FRAGMENT_TIMEOUT;
+        for fragment in &fragments[1..] {
+            assert!(assembler.push(source, fragment.clone(), late).is_none());
+        }
+        assert_eq!(assembler.pending(), 1);
+        
+        // Malformed fragments are ignored
+        let mut broken = fragments[0].clone();
+        broken.payload = Bytes::from_static(&[5, 2, 0]);
+        assert!(assembler.push(source, broken, late).is_none());
+        
+        // Only so many frames wait at a time
+        for sequence in 100..100 + MAX_PENDING_FRAMES as u32 * 2 {
+            let mut fragment = fragments[0].clone();
+            fragment.sequence = sequence;
+            assembler.push(source, fragment, late);
+        }
+        assert_eq!(assembler.pending(), MAX_PENDING_FRAMES);
+    }
+}
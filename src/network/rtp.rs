@@ -0,0 +1,705 @@
+//! RTP/RTCP packet mode
+//!
+//! An alternative to the native `AudioPacket` format for interoperating
+//! with GStreamer, VLC and hardware RTP receivers: Opus over RTP
+//! (RFC 7587) with basic RTCP sender and receiver reports (RFC 3550).
+//!
+//! ```text
+//! ┌──────────────────────────────────────────────────────┬────────────┐
+//! │ V=2 P X CC │ M │ PT │ Sequence (16) │ Timestamp (32) │ SSRC (32)  │
+//! └──────────────────────────────────────────────────────┴────────────┘
+//! ```
+//!
+//! Every track is its own RTP stream: the low byte of the SSRC is the
+//! track ID and all streams of a sender share one CNAME. The marker bit
+//! carries the stream restart (`PacketFlags::KEYFRAME`); the RTP clock is
+//! always 48 kHz and derived from the media clock. RTCP is multiplexed
+//! on the audio port (RFC 5761) and told apart by its packet type.
+//!
+//! RTP packets carry no FEC or probe flags (stereo is read from the Opus
+//! TOC byte) and cannot be encrypted, so the mode is refused together
+//! with a PSK. Clock sync and subscriptions only work between our own
+//! peers, other RTP senders ignore them.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::{NetworkConfig, PacketFormat};
+use crate::error::NetworkError;
+use crate::network::receiver::ReceivedPacket;
+use crate::network::timesync::media_time_us;
+use crate::protocol::Codec;
+
+/// RTP protocol version
+pub const RTP_VERSION: u8 = 2;
+
+/// RTP clock rate of Opus streams (RFC 7587: always 48 kHz)
+pub const RTP_CLOCK_RATE: u64 = 48_000;
+
+/// Dynamic payload type used for Opus unless configured otherwise
+pub const DEFAULT_PAYLOAD_TYPE: u8 = 111;
+
+/// Interval between RTCP reports
+pub const RTCP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Fixed RTP header size (no CSRCs)
+const RTP_HEADER_SIZE: usize = 12;
+
+/// RTCP packet types
+const RTCP_SR: u8 = 200;
+const RTCP_RR: u8 = 201;
+const RTCP_SDES: u8 = 202;
+
+/// SDES item type carrying the canonical name
+const SDES_CNAME: u8 = 1;
+
+/// Report block size in SR/RR packets
+const REPORT_BLOCK_SIZE: usize = 24;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Streams silent for longer than this are forgotten
+const STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// RTP data packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacket {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload: Bytes,
+}
+
+impl RtpPacket {
+    /// Serialize with the fixed 12-byte header
+    pub fn serialize(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(RTP_HEADER_SIZE + self.payload.len());
+        buf.put_u8(RTP_VERSION << 6);
+        buf.put_u8(((self.marker as u8) << 7) | (self.payload_type & 0x7F));
+        buf.put_u16(self.sequence);
+        buf.put_u32(self.timestamp);
+        buf.put_u32(self.ssrc);
+        buf.put_slice(&self.payload);
+        buf.freeze()
+    }
+
+    /// Parse a packet, skipping CSRCs, header extension and padding
+    pub fn deserialize(data: Bytes) -> Option<Self> {
+        if data.len() < RTP_HEADER_SIZE || data[0] >> 6 != RTP_VERSION || is_rtcp(&data) {
+            return None;
+        }
+        let padding = data[0] & 0x20 != 0;
+        let extension = data[0] & 0x10 != 0;
+        let csrc_count = (data[0] & 0x0F) as usize;
+
+        let mut start = RTP_HEADER_SIZE + 4 * csrc_count;
+        if extension {
+            let words = data.get(start + 2..start + 4)?;
+            start += 4 + 4 * u16::from_be_bytes([words[0], words[1]]) as usize;
+        }
+        let mut end = data.len();
+        if padding {
+            end = end.checked_sub(*data.last()? as usize)?;
+        }
+        if start > end {
+            return None;
+        }
+
+        Some(Self {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7F,
+            sequence: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            payload: data.slice(start..end),
+        })
+    }
+}
+
+/// Refuse configurations the RTP packet format cannot serve
+pub fn check_config(config: &NetworkConfig) -> Result<(), NetworkError> {
+    if config.packet_format == PacketFormat::Rtp && config.cipher().is_some() {
+        return Err(NetworkError::Unsupported(
+            "the RTP packet format cannot be encrypted, remove the PSK".to_string(),
+        ));
+    }
+    if config.rtp_payload_type > 127 {
+        return Err(NetworkError::Unsupported(format!(
+            "RTP payload type {} out of range",
+            config.rtp_payload_type
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a datagram is an RTCP packet (RFC 5761 demultiplexing)
+pub fn is_rtcp(data: &[u8]) -> bool {
+    data.len() >= 8 && data[0] >> 6 == RTP_VERSION && (RTCP_SR..=204).contains(&data[1])
+}
+
+/// Whether a datagram is an RTP data packet
+pub fn is_rtp(data: &[u8]) -> bool {
+    data.len() >= RTP_HEADER_SIZE && data[0] >> 6 == RTP_VERSION && !is_rtcp(data)
+}
+
+/// RTP timestamp (48 kHz) of a media clock time
+pub fn rtp_timestamp(media_us: u64) -> u32 {
+    (media_us * RTP_CLOCK_RATE / 1_000_000) as u32
+}
+
+/// Track carried by an RTP stream
+pub fn track_for_ssrc(ssrc: u32) -> u8 {
+    ssrc as u8
+}
+
+/// Current wall clock as a 64-bit NTP timestamp
+fn ntp_now() -> u64 {
+    let since_unix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_unix.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_unix.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (secs << 32) | fraction
+}
+
+/// Middle 32 bits of an NTP timestamp (LSR/DLSR units: 1/65536 s)
+fn ntp_middle(ntp: u64) -> u32 {
+    (ntp >> 16) as u32
+}
+
+/// Write an RTCP common header; `length` is in 32-bit words minus one
+fn put_rtcp_header(buf: &mut BytesMut, count: u8, packet_type: u8, length: u16) {
+    buf.put_u8((RTP_VERSION << 6) | (count & 0x1F));
+    buf.put_u8(packet_type);
+    buf.put_u16(length);
+}
+
+/// SDES packet with one CNAME chunk (null-terminated, padded to 32 bits)
+fn put_sdes_cname(buf: &mut BytesMut, ssrc: u32, cname: &str) {
+    let cname = &cname.as_bytes()[..cname.len().min(255)];
+    let chunk_len = 4 + 2 + cname.len() + 1;
+    let padded = chunk_len.div_ceil(4) * 4;
+    put_rtcp_header(buf, 1, RTCP_SDES, (padded / 4) as u16);
+    buf.put_u32(ssrc);
+    buf.put_u8(SDES_CNAME);
+    buf.put_u8(cname.len() as u8);
+    buf.put_slice(cname);
+    buf.put_bytes(0, padded - chunk_len + 1);
+}
+
+/// Reception report about one stream (RTCP report block)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportBlock {
+    pub ssrc: u32,
+    /// Packets lost since the previous report, in 1/256
+    pub fraction_lost: u8,
+    pub cumulative_lost: i32,
+    pub highest_sequence: u32,
+    /// Interarrival jitter in RTP timestamp units
+    pub jitter: u32,
+    pub last_sr: u32,
+    pub delay_since_last_sr: u32,
+}
+
+impl ReportBlock {
+    fn put(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.ssrc);
+        buf.put_u32(((self.fraction_lost as u32) << 24) | (self.cumulative_lost as u32 & 0x00FF_FFFF));
+        buf.put_u32(self.highest_sequence);
+        buf.put_u32(self.jitter);
+        buf.put_u32(self.last_sr);
+        buf.put_u32(self.delay_since_last_sr);
+    }
+
+    fn parse(data: &[u8]) -> Self {
+        let word = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let lost = word(4);
+        Self {
+            ssrc: word(0),
+            fraction_lost: (lost >> 24) as u8,
+            // Sign-extend the 24-bit count
+            cumulative_lost: ((lost << 8) as i32) >> 8,
+            highest_sequence: word(8),
+            jitter: word(12),
+            last_sr: word(16),
+            delay_since_last_sr: word(20),
+        }
+    }
+}
+
+/// Parsed RTCP packet of interest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtcpReport {
+    /// Sender report: NTP and RTP time of the same instant
+    Sender { ssrc: u32, ntp: u64, rtp_timestamp: u32, blocks: Vec<ReportBlock> },
+    /// Receiver report
+    Receiver { ssrc: u32, blocks: Vec<ReportBlock> },
+}
+
+/// Parse the SR and RR packets of a compound RTCP packet
+pub fn parse_rtcp(data: &[u8]) -> Vec<RtcpReport> {
+    let mut reports = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= data.len() && is_rtcp(&data[offset..]) {
+        let count = (data[offset] & 0x1F) as usize;
+        let packet_type = data[offset + 1];
+        let length = 4 * (u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize + 1);
+        let Some(packet) = data.get(offset..offset + length) else {
+            break;
+        };
+        let ssrc = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        let blocks_at = |start: usize| -> Vec<ReportBlock> {
+            (0..count)
+                .map(|i| start + i * REPORT_BLOCK_SIZE)
+                .take_while(|at| at + REPORT_BLOCK_SIZE <= packet.len())
+                .map(|at| ReportBlock::parse(&packet[at..]))
+                .collect()
+        };
+        match packet_type {
+            RTCP_SR if packet.len() >= 28 => {
+                let ntp = u64::from_be_bytes(packet[8..16].try_into().unwrap());
+                let rtp_timestamp = u32::from_be_bytes(packet[16..20].try_into().unwrap());
+                reports.push(RtcpReport::Sender { ssrc, ntp, rtp_timestamp, blocks: blocks_at(28) });
+            }
+            RTCP_RR => reports.push(RtcpReport::Receiver { ssrc, blocks: blocks_at(8) }),
+            _ => {}
+        }
+        offset += length;
+    }
+    reports
+}
+
+/// Counters of one outgoing stream
+#[derive(Debug, Default)]
+struct OutgoingStream {
+    packets: u32,
+    octets: u32,
+    /// Sequence numbers taken by silence markers, which RTP doesn't send
+    silent_slots: u32,
+    /// The next packet starts a talkspurt (marker bit, RFC 3551)
+    talkspurt: bool,
+}
+
+/// Sending side: turns encoded frames into RTP packets and writes the
+/// sender reports
+#[derive(Debug)]
+pub struct RtpSender {
+    payload_type: u8,
+    /// Random high bytes of every SSRC
+    ssrc_base: u32,
+    cname: String,
+    streams: HashMap<u8, OutgoingStream>,
+    last_report: Option<Instant>,
+}
+
+impl RtpSender {
+    pub fn new(payload_type: u8) -> Self {
+        let ssrc_base = rand::thread_rng().next_u32() & !0xFF;
+        Self {
+            payload_type,
+            ssrc_base,
+            cname: format!("lan-audio-{:06x}", ssrc_base >> 8),
+            streams: HashMap::new(),
+            last_report: None,
+        }
+    }
+
+    /// SSRC of a track's stream
+    pub fn ssrc(&self, track_id: u8) -> u32 {
+        self.ssrc_base | track_id as u32
+    }
+
+    /// RTP packet for an encoded frame (`restart` sets the marker bit)
+    pub fn packetize(&mut self, track_id: u8, sequence: u32, timestamp_us: u64, restart: bool, payload: Bytes) -> Bytes {
+        let stream = self.streams.entry(track_id).or_default();
+        stream.packets = stream.packets.wrapping_add(1);
+        stream.octets = stream.octets.wrapping_add(payload.len() as u32);
+        RtpPacket {
+            marker: restart || std::mem::take(&mut stream.talkspurt),
+            payload_type: self.payload_type,
+            sequence: sequence.wrapping_sub(stream.silent_slots) as u16,
+            timestamp: rtp_timestamp(timestamp_us),
+            ssrc: self.ssrc(track_id),
+            payload,
+        }
+        .serialize()
+    }
+
+    /// A silence marker took the track's next sequence number: nothing is
+    /// sent, and the RTP sequence continues without a gap
+    pub fn silence(&mut self, track_id: u8) {
+        let stream = self.streams.entry(track_id).or_default();
+        stream.silent_slots = stream.silent_slots.wrapping_add(1);
+        stream.talkspurt = true;
+    }
+    
+    /// Sender reports (one compound packet per stream) when due
+    pub fn due_reports(&mut self, now: Instant) -> Vec<Bytes> {
+        if self.last_report.is_some_and(|t| now.duration_since(t) < RTCP_INTERVAL) {
+            return Vec::new();
+        }
+        self.last_report = Some(now);
+
+        let ntp = ntp_now();
+        let rtp_now = rtp_timestamp(media_time_us());
+        let mut track_ids: Vec<u8> = self.streams.keys().copied().collect();
+        track_ids.sort_unstable();
+        track_ids
+            .into_iter()
+            .map(|track_id| {
+                let stream = &self.streams[&track_id];
+                let ssrc = self.ssrc(track_id);
+                let mut buf = BytesMut::with_capacity(64);
+                put_rtcp_header(&mut buf, 0, RTCP_SR, 6);
+                buf.put_u32(ssrc);
+                buf.put_u64(ntp);
+                buf.put_u32(rtp_now);
+                buf.put_u32(stream.packets);
+                buf.put_u32(stream.octets);
+                put_sdes_cname(&mut buf, ssrc, &self.cname);
+                buf.freeze()
+            })
+            .collect()
+    }
+
+    /// Log the receiver reports about our streams
+    pub fn handle_rtcp(&self, data: &[u8], from: SocketAddr) {
+        for report in parse_rtcp(data) {
+            let (RtcpReport::Receiver { blocks, .. } | RtcpReport::Sender { blocks, .. }) = report;
+            for block in blocks.iter().filter(|block| block.ssrc & !0xFF == self.ssrc_base) {
+                tracing::debug!(
+                    "RTCP from {}: track {} lost {:.1}% ({} total), jitter {:.1} ms",
+                    from,
+                    track_for_ssrc(block.ssrc),
+                    block.fraction_lost as f32 * 100.0 / 256.0,
+                    block.cumulative_lost,
+                    block.jitter as f32 * 1000.0 / RTP_CLOCK_RATE as f32,
+                );
+            }
+        }
+    }
+}
+
+/// Reception state of one incoming stream (RFC 3550 appendix A)
+#[derive(Debug)]
+struct IncomingStream {
+    base_sequence: u32,
+    /// Extended highest sequence number
+    max_sequence: u32,
+    received: u32,
+    expected_prior: u32,
+    received_prior: u32,
+    /// Extended timestamp of the last packet
+    timestamp: u64,
+    /// Relative transit time of the last packet (RTP units)
+    transit: Option<i64>,
+    jitter: f64,
+    last_sr: u32,
+    last_sr_at: Option<Instant>,
+    last_seen: Instant,
+}
+
+impl IncomingStream {
+    fn new(sequence: u16, timestamp: u32, now: Instant) -> Self {
+        Self {
+            base_sequence: sequence as u32,
+            max_sequence: sequence as u32,
+            received: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            timestamp: timestamp as u64,
+            transit: None,
+            jitter: 0.0,
+            last_sr: 0,
+            last_sr_at: None,
+            last_seen: now,
+        }
+    }
+
+    /// Extend a 16-bit sequence number next to the highest one seen
+    fn extend_sequence(&mut self, sequence: u16) -> u32 {
+        let delta = sequence.wrapping_sub(self.max_sequence as u16) as i16;
+        let extended = self.max_sequence.wrapping_add_signed(delta as i32);
+        if delta > 0 {
+            self.max_sequence = extended;
+        }
+        extended
+    }
+
+    /// Extend a 32-bit timestamp next to the last one
+    fn extend_timestamp(&mut self, timestamp: u32) -> u64 {
+        let delta = timestamp.wrapping_sub(self.timestamp as u32) as i32;
+        self.timestamp = self.timestamp.wrapping_add_signed(delta as i64);
+        self.timestamp
+    }
+
+    fn report_block(&mut self, ssrc: u32, now: Instant) -> ReportBlock {
+        let expected = self.max_sequence.wrapping_sub(self.base_sequence).wrapping_add(1);
+        let lost = expected as i64 - self.received as i64;
+        let expected_interval = expected.wrapping_sub(self.expected_prior);
+        let received_interval = self.received.wrapping_sub(self.received_prior);
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        self.expected_prior = expected;
+        self.received_prior = self.received;
+
+        let fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval as i64).min(255) as u8
+        };
+        let delay_since_last_sr = self
+            .last_sr_at
+            .map(|at| (now.duration_since(at).as_secs_f64() * 65536.0) as u32)
+            .unwrap_or(0);
+
+        ReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: lost.clamp(-0x80_0000, 0x7F_FFFF) as i32,
+            highest_sequence: self.max_sequence,
+            jitter: self.jitter as u32,
+            last_sr: self.last_sr,
+            delay_since_last_sr,
+        }
+    }
+}
+
+/// Receiving side: unwraps RTP sequence and timestamps into the native
+/// packet fields and writes receiver reports to every source
+#[derive(Debug)]
+pub struct RtpReceiver {
+    ssrc: u32,
+    cname: String,
+    streams: HashMap<(SocketAddr, u32), IncomingStream>,
+    epoch: Instant,
+    last_report: Option<Instant>,
+}
+
+impl Default for RtpReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtpReceiver {
+    pub fn new() -> Self {
+        let ssrc = rand::thread_rng().next_u32();
+        Self {
+            ssrc,
+            cname: format!("lan-audio-{:08x}", ssrc),
+            streams: HashMap::new(),
+            epoch: Instant::now(),
+            last_report: None,
+        }
+    }
+
+    /// Turn an RTP packet into a received packet of the track its SSRC names
+    pub fn receive(&mut self, packet: RtpPacket, from: SocketAddr, now: Instant) -> ReceivedPacket {
+        let stream = self
+            .streams
+            .entry((from, packet.ssrc))
+            .or_insert_with(|| IncomingStream::new(packet.sequence, packet.timestamp, now));
+        let sequence = stream.extend_sequence(packet.sequence);
+        let timestamp = stream.extend_timestamp(packet.timestamp);
+        stream.received = stream.received.wrapping_add(1);
+        stream.last_seen = now;
+
+        // Interarrival jitter (RFC 3550 A.8)
+        let arrival = (now.duration_since(self.epoch).as_micros() as u64 * RTP_CLOCK_RATE / 1_000_000) as i64;
+        let transit = arrival - timestamp as i64;
+        if let Some(previous) = stream.transit {
+            let d = (transit - previous).abs() as f64;
+            stream.jitter += (d - stream.jitter) / 16.0;
+        }
+        stream.transit = Some(transit);
+
+        ReceivedPacket {
+            track_id: track_for_ssrc(packet.ssrc),
+            sequence,
+            timestamp: timestamp * 1_000_000 / RTP_CLOCK_RATE,
+            // Opus TOC byte: stereo flag
+            is_stereo: packet.payload.first().is_some_and(|toc| toc & 0x04 != 0),
+            payload: packet.payload,
+            has_fec: false,
+            is_keyframe: packet.marker,
+            is_probe: false,
+            codec: Codec::Opus,
+            receive_time: now,
+            source: Some(from),
+        }
+    }
+
+    /// Remember the sender reports of our sources (for LSR/DLSR)
+    pub fn handle_rtcp(&mut self, data: &[u8], from: SocketAddr, now: Instant) {
+        for report in parse_rtcp(data) {
+            if let RtcpReport::Sender { ssrc, ntp, .. } = report {
+                if let Some(stream) = self.streams.get_mut(&(from, ssrc)) {
+                    stream.last_sr = ntp_middle(ntp);
+                    stream.last_sr_at = Some(now);
+                }
+            }
+        }
+    }
+
+    /// Receiver reports to every source when due: (address, packet)
+    pub fn due_reports(&mut self, now: Instant) -> Vec<(SocketAddr, Bytes)> {
+        if self.last_report.is_some_and(|t| now.duration_since(t) < RTCP_INTERVAL) {
+            return Vec::new();
+        }
+        self.last_report = Some(now);
+        self.streams.retain(|_, stream| now.duration_since(stream.last_seen) < STREAM_TIMEOUT);
+
+        let mut blocks: HashMap<SocketAddr, Vec<ReportBlock>> = HashMap::new();
+        for (&(address, ssrc), stream) in self.streams.iter_mut() {
+            blocks.entry(address).or_default().push(stream.report_block(ssrc, now));
+        }
+
+        blocks
+            .into_iter()
+            .map(|(address, mut blocks)| {
+                blocks.sort_unstable_by_key(|block| block.ssrc);
+                blocks.truncate(31);
+                let mut buf = BytesMut::with_capacity(8 + REPORT_BLOCK_SIZE * blocks.len() + 32);
+                put_rtcp_header(&mut buf, blocks.len() as u8, RTCP_RR, (1 + 6 * blocks.len()) as u16);
+                buf.put_u32(self.ssrc);
+                for block in &blocks {
+                    block.put(&mut buf);
+                }
+                put_sdes_cname(&mut buf, self.ssrc, &self.cname);
+                (address, buf.freeze())
+            })
+            .collect()
+    }
+}
+
+/// SDP description of the streams for GStreamer/VLC (`m=audio` with
+/// the Opus payload type, RFC 7587 section 7)
+pub fn sdp_description(address: SocketAddr, payload_type: u8, stereo: bool) -> String {
+    let (family, ip) = match address.ip() {
+        std::net::IpAddr::V4(ip) => ("IP4", ip.to_string()),
+        std::net::IpAddr::V6(ip) => ("IP6", ip.to_string()),
+    };
+    let mut sdp = format!(
+        "v=0\r\no=- 0 0 IN {family} {ip}\r\ns=LAN Audio Streamer\r\nc=IN {family} {ip}\r\nt=0 0\r\n\
+         m=audio {port} RTP/AVP {pt}\r\na=rtpmap:{pt} opus/48000/2\r\na=rtcp-mux\r\n",
+        port = address.port(),
+        pt = payload_type,
+    );
+    if stereo {
+        sdp.push_str(&format!("a=fmtp:{} stereo=1; sprop-stereo=1\r\n", payload_type));
+    }
+    sdp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtp_packet_roundtrip() {
+        let packet = RtpPacket {
+            marker: true,
+            payload_type: DEFAULT_PAYLOAD_TYPE,
+            sequence: 0xFFFF,
+            timestamp: 0xDEAD_BEEF,
+            ssrc: 0x1234_5603,
+            payload: Bytes::from_static(&[0xFC, 1, 2, 3]),
+        };
+        let data = packet.serialize();
+        assert!(is_rtp(&data) && !is_rtcp(&data));
+        assert_eq!(RtpPacket::deserialize(data), Some(packet.clone()));
+
+        // One CSRC, a one-word extension and two bytes of padding
+        let mut raw = BytesMut::new();
+        raw.put_u8(0x80 | 0x20 | 0x10 | 1);
+        raw.put_u8(DEFAULT_PAYLOAD_TYPE);
+        raw.put_u16(7);
+        raw.put_u32(960);
+        raw.put_u32(0x1234_5603);
+        raw.put_u32(0xCAFE);
+        raw.put_u32(0xBEDE_0001);
+        raw.put_u32(0);
+        raw.put_slice(&[0xFC, 9, 0, 2]);
+        let parsed = RtpPacket::deserialize(raw.freeze()).unwrap();
+        assert_eq!(&parsed.payload[..], &[0xFC, 9]);
+        assert_eq!(track_for_ssrc(parsed.ssrc), 3);
+    }
+
+    #[test]
+    fn test_receiver_unwraps_and_reports_loss() {
+        let mut sender = RtpSender::new(DEFAULT_PAYLOAD_TYPE);
+        let mut receiver = RtpReceiver::new();
+        let source: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        let now = Instant::now();
+
+        // Sequence numbers cross the 16-bit wrap; every third packet is lost
+        let mut received = Vec::new();
+        for sequence in 65_530u32..65_545 {
+            let data = sender.packetize(2, sequence, sequence as u64 * 10_000, sequence == 65_530, Bytes::from_static(&[0x04]));
+            if sequence % 3 != 2 {
+                let packet = RtpPacket::deserialize(data).unwrap();
+                received.push(receiver.receive(packet, source, now));
+            }
+        }
+        assert!(received.iter().all(|packet| packet.track_id == 2 && packet.is_stereo));
+        assert!(received[0].is_keyframe && !received[1].is_keyframe);
+        assert_eq!(received.last().unwrap().sequence, 65_544);
+        assert!(received.windows(2).all(|pair| pair[1].timestamp > pair[0].timestamp));
+
+        let reports = receiver.due_reports(now);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].0, source);
+        let parsed = parse_rtcp(&reports[0].1);
+        let RtcpReport::Receiver { ref blocks, .. } = parsed[0] else {
+            panic!("expected a receiver report");
+        };
+        assert_eq!(blocks[0].ssrc, sender.ssrc(2));
+        assert_eq!(blocks[0].cumulative_lost, 5);
+        assert_eq!(blocks[0].highest_sequence, 65_544);
+        assert_eq!(blocks[0].fraction_lost, (5 * 256 / 15) as u8);
+
+        // Not due again until the interval passes
+        assert!(receiver.due_reports(now).is_empty());
+    }
+
+    #[test]
+    fn test_silence_keeps_sequence() {
+        let mut sender = RtpSender::new(DEFAULT_PAYLOAD_TYPE);
+        sender.packetize(0, 0, 0, true, Bytes::from_static(&[1]));
+        sender.packetize(0, 1, 10_000, false, Bytes::from_static(&[2]));
+        sender.silence(0);
+
+        // The marker's sequence number is skipped, the talkspurt is marked
+        let packet = RtpPacket::deserialize(sender.packetize(0, 3, 900_000, false, Bytes::from_static(&[3]))).unwrap();
+        assert_eq!(packet.sequence, 2);
+        assert!(packet.marker);
+        let packet = RtpPacket::deserialize(sender.packetize(0, 4, 910_000, false, Bytes::from_static(&[4]))).unwrap();
+        assert_eq!(packet.sequence, 3);
+        assert!(!packet.marker);
+    }
+
+    #[test]
+    fn test_sender_report() {
+        let mut sender = RtpSender::new(DEFAULT_PAYLOAD_TYPE);
+        sender.packetize(0, 0, 0, true, Bytes::from_static(&[1, 2, 3]));
+        sender.packetize(0, 1, 10_000, false, Bytes::from_static(&[4, 5]));
+
+        let reports = sender.due_reports(Instant::now());
+        assert_eq!(reports.len(), 1);
+        assert!(is_rtcp(&reports[0]));
+        assert_eq!(reports[0].len() % 4, 0);
+        let parsed = parse_rtcp(&reports[0]);
+        assert_eq!(parsed.len(), 1);
+        let RtcpReport::Sender { ssrc, ntp, .. } = parsed[0] else {
+            panic!("expected a sender report");
+        };
+        assert_eq!(ssrc, sender.ssrc(0));
+        assert!(ntp >> 32 > NTP_UNIX_OFFSET);
+        assert_eq!(u32::from_be_bytes(reports[0][20..24].try_into().unwrap()), 2);
+        assert_eq!(u32::from_be_bytes(reports[0][24..28].try_into().unwrap()), 5);
+    }
+}
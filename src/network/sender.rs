@@ -2,18 +2,50 @@
 //!
 //! Handles sending encoded audio packets over UDP with proper
 //! sequencing and timing.
+//!
+//! Packets of redundant tracks are sent once more over each extra path
+//! to the target (another network the peer is reachable on) with the
+//! same sequence number; the receiver drops whichever copy comes second.
+//!
+//! With a PSK every packet is sealed, except packets of plaintext tracks
+//! sent to a receiver that allows them (see `network::subscription`).
+//! In the RTP packet format packets go out as RTP (see `network::rtp`).
+//!
+//! A multicast target gets one stream for all receivers in the group:
+//! subscriptions and plaintext tracks are off for it.
+//!
+//! When the receiver doesn't answer over UDP the sender thread moves to
+//! the TCP fallback transport (see `network::transport`); in QUIC mode it
+//! moves to QUIC once the receiver offers it (see `network::quic`).
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use crossbeam_channel::Receiver;
+use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
 use crate::error::NetworkError;
-use crate::network::udp::{create_socket, PacketSender};
-use crate::protocol::{AudioPacket, PacketFlags};
-use crate::config::NetworkConfig;
+use crate::network::crypto::PacketCipher;
+use crate::network::feedback::FeedbackInbox;
+use crate::network::file_transfer::FileTransfers;
+use crate::network::handshake::{HandshakeManager, HandshakePacket, PeerCapabilities};
+use crate::network::packet_log::{self, Direction};
+use crate::network::qos::{self, QosFlows};
+use crate::network::quic::QuicTransport;
+use crate::network::rtp::{self, RtpSender};
+use crate::network::subscription::{TrackCatalog, TrackOffer, TrackSubscriber};
+use crate::network::timesync::{handle_socket_packet, is_handshake_packet, TimeSync};
+use crate::network::transport::{self, ConnectivityCheck, PendingConnection, TcpTransport, Transport};
+use crate::network::udp::{canonical_addr, create_socket, target_for_socket, PacketSender};
+use crate::protocol::{AudioPacket, Codec, PacketFlags, TrackPriority, HEADER_SIZE, MAX_PAYLOAD_SIZE};
+use crate::config::{NetworkConfig, PacketFormat, TransportMode};
+
+/// Size of the arena the sender thread serializes datagrams into
+/// (reused once the datagrams sent from it are dropped)
+const DATAGRAM_ARENA_SIZE: usize = (HEADER_SIZE + MAX_PAYLOAD_SIZE) * 32;
 
 /// Encoded packet ready for sending
 pub struct EncodedPacket {
@@ -22,6 +54,10 @@ pub struct EncodedPacket {
     pub timestamp: u64,
     pub payload: Bytes,
     pub flags: PacketFlags,
+    /// Selects the DSCP class of the datagrams
+    pub priority: TrackPriority,
+    /// Also send over the redundant paths
+    pub redundant: bool,
 }
 
 /// Audio sender for multiple tracks
@@ -41,8 +77,115 @@ pub struct AudioSender {
     /// Input channel for packets
     packet_tx: crossbeam_channel::Sender<EncodedPacket>,
     
+    /// Control packets (handshake) to send to the target
+    control_tx: crossbeam_channel::Sender<Bytes>,
+    
     /// Target address
     target_addr: SocketAddr,
+    
+    /// Other addresses of the target for redundant packets
+    paths: Arc<RwLock<Vec<SocketAddr>>>,
+    
+    /// Handlers for control packets arriving on the sending socket
+    control: ControlHandlers,
+}
+
+/// Shared state fed by control packets arriving on the sending socket
+#[derive(Clone, Default)]
+struct ControlHandlers {
+    /// Clock synchronization (processes pongs)
+    time_sync: Option<Arc<TimeSync>>,
+    
+    /// Receiver feedback
+    feedback: Option<Arc<FeedbackInbox>>,
+    
+    /// Offered tracks and the receiver's subscription
+    offer: TrackOffer,
+    
+    /// Files sent to the receiver (acknowledgements arrive here)
+    files: Option<Arc<FileTransfers>>,
+    
+    /// Handshakes with peers (replies to a Hello sent from the shared
+    /// audio port can arrive on this socket)
+    handshake: Option<Arc<HandshakeManager>>,
+    
+    /// Our subscriptions to the peer's tracks (its track lists and
+    /// changes to them can arrive on the shared audio port too)
+    subscriber: Option<Arc<TrackSubscriber>>,
+}
+
+/// How the sender thread puts packets on the wire
+enum PacketFraming {
+    /// `AudioPacket`, sealed when a PSK is configured
+    Native(Option<PacketCipher>),
+    /// RTP with periodic RTCP sender reports
+    Rtp(RtpSender),
+}
+
+impl PacketFraming {
+    fn from_config(config: &NetworkConfig) -> Result<Self, NetworkError> {
+        rtp::check_config(config)?;
+        Ok(match config.packet_format {
+            PacketFormat::Native => Self::Native(config.cipher()),
+            PacketFormat::Rtp => Self::Rtp(RtpSender::new(config.rtp_payload_type)),
+        })
+    }
+}
+
+/// Channels and redundant paths feeding the sender thread, and the
+/// state of its transport
+struct SenderQueues {
+    packets: Receiver<EncodedPacket>,
+    control: Receiver<Bytes>,
+    paths: Arc<RwLock<Vec<SocketAddr>>>,
+    connectivity: ConnectivityCheck,
+    /// TCP or QUIC connection being made in the background
+    pending: Option<PendingConnection>,
+}
+
+impl ControlHandlers {
+    /// Handle a handshake packet; returns a reply for the sender of the packet
+    /// (`from_receiver`: it came from the receiver or one of its paths)
+    fn handle(&self, data: &[u8], from: SocketAddr, from_receiver: bool) -> Option<Bytes> {
+        let from_receiver =
+            from_receiver || self.handshake.as_ref().is_some_and(|handshake| handshake.is_trusted(&from));
+        if self.feedback.as_ref().is_some_and(|inbox| inbox.handle_packet(data, from, from_receiver)) {
+            return None;
+        }
+        if let Some(reply) = self.offer.handle_packet(data, from, from_receiver) {
+            return reply;
+        }
+        if let Some(reply) = self.handshake.as_ref().and_then(|handshake| handshake.handle_packet(data, from)) {
+            return reply;
+        }
+        if self.subscriber.as_ref().is_some_and(|subscriber| subscriber.handle_packet(data, from)) {
+            return None;
+        }
+        if let Some((files, handshake)) = self.files.as_ref().zip(self.handshake.as_ref()) {
+            if let Some(reply) = files.handle_packet(data, from, handshake) {
+                return reply;
+            }
+        }
+        handle_socket_packet(self.time_sync.as_deref(), data, from)
+    }
+    
+    /// QUIC port the receiver offers in its handshake or subscription
+    fn quic_port(&self, receiver: SocketAddr) -> Option<u16> {
+        self.handshake
+            .as_ref()
+            .and_then(|handshake| handshake.peer_capabilities(&receiver))
+            .and_then(|capabilities| capabilities.quic_port)
+            .or_else(|| self.offer.peer_capabilities().and_then(|capabilities| capabilities.quic_port))
+    }
+    
+    /// Same handlers for a socket sending to another receiver (which
+    /// subscribes on its own)
+    fn for_peer(&self) -> Self {
+        Self {
+            offer: self.offer.for_peer(),
+            ..self.clone()
+        }
+    }
 }
 
 impl AudioSender {
@@ -54,6 +197,7 @@ impl AudioSender {
         let _socket = create_socket(config)?;
         
         let (packet_tx, _packet_rx) = crossbeam_channel::bounded::<EncodedPacket>(1024);
+        let (control_tx, _control_rx) = crossbeam_channel::bounded::<Bytes>(16);
         
         let running = Arc::new(AtomicBool::new(false));
         let packets_sent = Arc::new(AtomicU64::new(0));
@@ -65,10 +209,54 @@ impl AudioSender {
             packets_sent,
             bytes_sent,
             packet_tx,
+            control_tx,
             target_addr,
+            paths: Arc::new(RwLock::new(Vec::new())),
+            control: ControlHandlers::default(),
         })
     }
     
+    /// Share clock synchronization state (must be called before `start`)
+    pub fn set_time_sync(&mut self, time_sync: Arc<TimeSync>) {
+        self.control.time_sync = Some(time_sync);
+    }
+    
+    /// Collect receiver feedback into a shared inbox (must be called before `start`)
+    pub fn set_feedback_inbox(&mut self, inbox: Arc<FeedbackInbox>) {
+        self.control.feedback = Some(inbox);
+    }
+    
+    /// Offer these tracks to the receiver for subscription (must be called before `start`)
+    pub fn set_track_catalog(&mut self, catalog: Arc<TrackCatalog>) {
+        self.control.offer.set_catalog(catalog);
+    }
+    
+    /// Send the files queued for the receiver (must be called before `start`)
+    pub fn set_file_transfers(&mut self, files: Arc<FileTransfers>) {
+        self.control.files = Some(files);
+    }
+    
+    /// Answer and track handshakes arriving on the socket (must be called before `start`)
+    pub fn set_handshake(&mut self, handshake: Arc<HandshakeManager>) {
+        self.control.handshake = Some(handshake);
+    }
+    
+    /// Take track lists and track changes of a peer we also receive from
+    /// (must be called before `start`)
+    pub fn set_subscriber(&mut self, subscriber: Arc<TrackSubscriber>) {
+        self.control.subscriber = Some(subscriber);
+    }
+    
+    /// Whether the receiver wants a track (all tracks until it subscribes)
+    pub fn is_subscribed(&self, track_id: u8) -> bool {
+        self.control.offer.is_subscribed(track_id)
+    }
+    
+    /// What the receiver can play (None until it reports it)
+    pub fn peer_capabilities(&self) -> Option<PeerCapabilities> {
+        self.control.offer.peer_capabilities()
+    }
+    
     /// Start the sender thread
     pub fn start(&mut self, config: NetworkConfig) -> Result<(), NetworkError> {
         if self.running.load(Ordering::SeqCst) {
@@ -76,21 +264,56 @@ impl AudioSender {
         }
         
         let socket = create_socket(&config)?;
-        let sender = PacketSender::new(socket, self.target_addr);
+        let target = match socket.local_addr() {
+            Ok(local) => target_for_socket(local, self.target_addr),
+            Err(_) => self.target_addr,
+        };
+        let sender = PacketSender::new(socket, target).with_dscp_marking(config.qos.dscp);
+        let framing = PacketFraming::from_config(&config)?;
+        self.control.offer.set_cipher(config.cipher().map(Arc::new));
+        let target_ip = self.target_addr.ip().to_canonical();
+        if target_ip.is_multicast() {
+            self.control.offer.set_shared();
+        }
+        let datagram_only = match target_ip {
+            std::net::IpAddr::V4(ip) => ip.is_multicast() || ip.is_broadcast(),
+            std::net::IpAddr::V6(ip) => ip.is_multicast(),
+        };
         
         let (packet_tx, packet_rx) = crossbeam_channel::bounded::<EncodedPacket>(1024);
         self.packet_tx = packet_tx;
+        let (control_tx, control_rx) = crossbeam_channel::bounded::<Bytes>(16);
+        self.control_tx = control_tx;
+        let queues = SenderQueues {
+            packets: packet_rx,
+            control: control_rx,
+            paths: self.paths.clone(),
+            connectivity: ConnectivityCheck::new(config.transport, datagram_only, std::time::Instant::now()),
+            pending: None,
+        };
         
         let running = self.running.clone();
         let packets_sent = self.packets_sent.clone();
         let bytes_sent = self.bytes_sent.clone();
+        let control = self.control.clone();
+        let flow_targets: Vec<SocketAddr> = match sender.socket().map(|socket| socket.local_addr()) {
+            Some(Ok(local)) => self.paths.read().iter().map(|path| target_for_socket(local, *path)).collect(),
+            _ => self.paths.read().clone(),
+        };
         
         running.store(true, Ordering::SeqCst);
         
         let handle = thread::Builder::new()
             .name("audio-sender".to_string())
             .spawn(move || {
-                Self::sender_loop(sender, packet_rx, running, packets_sent, bytes_sent);
+                let _mmcss = qos::register_thread(&config.qos);
+                let flows = QosFlows::new(&config.qos);
+                if let Some(socket) = sender.socket() {
+                    for destination in std::iter::once(target).chain(flow_targets) {
+                        flows.add(socket, destination);
+                    }
+                }
+                Self::sender_loop(sender, framing, control, queues, running, packets_sent, bytes_sent);
             })
             .map_err(|e| NetworkError::SendFailed(e.to_string()))?;
         
@@ -98,10 +321,38 @@ impl AudioSender {
         Ok(())
     }
     
+    /// Start connecting to the receiver in this transport mode (None while
+    /// the receiver hasn't offered QUIC, which keeps it on UDP)
+    fn connect_stream(
+        mode: TransportMode,
+        receiver: SocketAddr,
+        control: &ControlHandlers,
+    ) -> std::io::Result<Option<PendingConnection>> {
+        if mode == TransportMode::Quic {
+            let Some(port) = control.quic_port(receiver) else {
+                return Ok(None);
+            };
+            return PendingConnection::start(move || {
+                let quic = QuicTransport::connect(receiver, port)?;
+                tracing::info!("Streaming to {} over QUIC", receiver);
+                Ok(Box::new(quic) as Box<dyn Transport>)
+            })
+            .map(Some);
+        }
+        PendingConnection::start(move || {
+            let tcp = TcpTransport::connect(receiver)?;
+            tracing::warn!("No UDP reply from {}, streaming over TCP", receiver);
+            Ok(Box::new(tcp) as Box<dyn Transport>)
+        })
+        .map(Some)
+    }
+    
     /// Sender loop
     fn sender_loop(
-        sender: PacketSender,
-        packet_rx: Receiver<EncodedPacket>,
+        mut sender: PacketSender,
+        mut framing: PacketFraming,
+        control: ControlHandlers,
+        mut queues: SenderQueues,
         running: Arc<AtomicBool>,
         packets_sent: Arc<AtomicU64>,
         bytes_sent: Arc<AtomicU64>,
@@ -109,8 +360,87 @@ impl AudioSender {
         // Adaptive timeout: start fast, slow down during silence
         let mut consecutive_timeouts = 0u32;
         const MAX_CONSECUTIVE_TIMEOUTS: u32 = 100;
+        let mut non_opus_dropped = 0u64;
+        
+        let mut control_buffer = [0u8; 256];
+        let receiver = canonical_addr(sender.target());
+        let mut datagram_arena = BytesMut::with_capacity(DATAGRAM_ARENA_SIZE);
+        let mut datagrams: Vec<Bytes> = Vec::new();
         
         while running.load(Ordering::Relaxed) {
+            // Answer clock-sync pings and collect feedback from receivers
+            while let Ok((size, addr)) = sender.recv_from(&mut control_buffer) {
+                let data = &control_buffer[..size.min(control_buffer.len())];
+                if is_handshake_packet(data) {
+                    packet_log::record(Direction::Received, addr, data);
+                    if addr == receiver {
+                        queues.connectivity.confirm();
+                    }
+                    let from_receiver =
+                        addr == receiver || queues.paths.read().iter().any(|path| canonical_addr(*path) == addr);
+                    if let Some(reply) = control.handle(data, addr, from_receiver) {
+                        packet_log::record(Direction::Sent, addr, &reply);
+                        let _ = sender.send_to(&reply, addr);
+                    }
+                } else if let PacketFraming::Rtp(ref rtp) = framing {
+                    if rtp::is_rtcp(data) {
+                        rtp.handle_rtcp(data, addr);
+                    }
+                }
+            }
+            
+            if let PacketFraming::Rtp(ref mut rtp) = framing {
+                for report in rtp.due_reports(std::time::Instant::now()) {
+                    let _ = sender.send(&report);
+                }
+            }
+            
+            // A receiver that never answers over UDP is tried over TCP,
+            // one that offers QUIC gets it in QUIC mode
+            let now = std::time::Instant::now();
+            if queues.connectivity.probe_due(now) {
+                let ping = HandshakePacket::ping(0).serialize();
+                packet_log::record(Direction::Sent, receiver, &ping);
+                let _ = sender.send(&ping);
+            }
+            if queues.pending.is_none() && queues.connectivity.connect_due(now) {
+                match Self::connect_stream(queues.connectivity.mode(), receiver, &control) {
+                    Ok(pending) => queues.pending = pending,
+                    Err(e) => tracing::debug!("Connection to {} failed: {}", receiver, e),
+                }
+            }
+            // Packets keep going over UDP until the connection is up
+            if let Some(result) = queues.pending.as_ref().and_then(PendingConnection::poll) {
+                queues.pending = None;
+                match result {
+                    Ok(transport) => {
+                        sender.set_transport(transport);
+                        queues.connectivity.connected();
+                    }
+                    Err(e) => tracing::debug!("Connection to {} failed: {}", receiver, e),
+                }
+            }
+            let max_payload = sender
+                .max_datagram_size()
+                .map_or(MAX_PAYLOAD_SIZE, |size| size.saturating_sub(HEADER_SIZE).min(MAX_PAYLOAD_SIZE));
+            
+            // Control packets go out ahead of queued audio
+            while let Ok(data) = queues.control.try_recv() {
+                packet_log::record(Direction::Sent, receiver, &data);
+                let _ = sender.send(&data);
+            }
+            if let Some(ref files) = control.files {
+                for data in files.due_packets(receiver, now) {
+                    packet_log::record(Direction::Sent, receiver, &data);
+                    let _ = sender.send(&data);
+                }
+            }
+            // Added, reconfigured and removed tracks
+            for data in control.offer.due_notifications() {
+                packet_log::record(Direction::Sent, receiver, &data);
+                let _ = sender.send(&data);
+            }
+            
             // Adaptive timeout based on traffic pattern
             let timeout = if consecutive_timeouts < 10 {
                 std::time::Duration::from_micros(100) // Fast polling during active streaming
@@ -120,33 +450,98 @@ impl AudioSender {
                 std::time::Duration::from_millis(5) // Slow polling during silence
             };
             
-            match packet_rx.recv_timeout(timeout) {
+            match queues.packets.recv_timeout(timeout) {
                 Ok(encoded) => {
                     consecutive_timeouts = 0; // Reset on successful receive
                     
-                    // Create audio packet
-                    let packet = AudioPacket {
-                        track_id: encoded.track_id,
-                        flags: encoded.flags,
-                        sequence: encoded.sequence,
-                        timestamp: encoded.timestamp,
-                        payload: encoded.payload,
-                    };
+                    // Routers queue voice ahead of music and ambience (the
+                    // control packets that follow keep this class)
+                    sender.set_dscp(encoded.priority.dscp());
                     
-                    // Serialize and send
-                    let data = packet.serialize();
-                    match sender.send(&data) {
+                    // Serialize (fragmenting frames larger than a datagram) and send
+                    datagrams.clear();
+                    match framing {
+                        PacketFraming::Native(ref cipher) => {
+                            let mut packet = AudioPacket {
+                                track_id: encoded.track_id,
+                                flags: encoded.flags,
+                                sequence: encoded.sequence,
+                                timestamp: encoded.timestamp,
+                                payload: encoded.payload,
+                            };
+                            if let Some(ref cipher) = cipher {
+                                if !control.offer.sends_plaintext(packet.track_id) {
+                                    cipher.seal_packet(&mut packet);
+                                }
+                            }
+                            if packet.payload.len() <= max_payload {
+                                datagrams.push(packet.serialize_into(&mut datagram_arena));
+                            } else {
+                                match packet.fragment(max_payload) {
+                                    Ok(fragments) => datagrams.extend(
+                                        fragments.iter().map(|fragment| fragment.serialize_into(&mut datagram_arena)),
+                                    ),
+                                    Err(e) => {
+                                        tracing::warn!("Dropping frame of track {}: {}", packet.track_id, e);
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+                        // Silence in RTP is a pause in the stream
+                        PacketFraming::Rtp(ref mut rtp) if encoded.payload.is_empty() => {
+                            rtp.silence(encoded.track_id);
+                            continue;
+                        }
+                        // RTP carries Opus only (RFC 7587)
+                        PacketFraming::Rtp(_) if encoded.flags.codec() != Some(Codec::Opus) => {
+                            if non_opus_dropped.is_multiple_of(1000) {
+                                tracing::warn!(
+                                    "Track {} is not Opus, which the RTP packet format can't carry; not sending it",
+                                    encoded.track_id
+                                );
+                            }
+                            non_opus_dropped += 1;
+                            continue;
+                        }
+                        PacketFraming::Rtp(ref mut rtp) => datagrams.push(rtp.packetize(
+                            encoded.track_id,
+                            encoded.sequence,
+                            encoded.timestamp,
+                            encoded.flags.is_keyframe(),
+                            encoded.payload,
+                        )),
+                    }
+                    let result = datagrams
+                        .iter()
+                        .try_fold(0, |total, data| sender.send(data).map(|sent| total + sent));
+                    match result {
                         Ok(sent) => {
                             packets_sent.fetch_add(1, Ordering::Relaxed);
                             bytes_sent.fetch_add(sent as u64, Ordering::Relaxed);
                         }
                         Err(e) => {
+                            if queues.connectivity.is_streaming() && transport::is_connection_lost(&e) {
+                                tracing::warn!("Connection to {} lost: {}", receiver, e);
+                                queues.connectivity.connection_lost();
+                            }
                             // Only log periodically to avoid log spam
-                            if packets_sent.load(Ordering::Relaxed) % 1000 == 0 {
+                            if packets_sent.load(Ordering::Relaxed).is_multiple_of(1000) {
                                 tracing::warn!("Failed to send packet: {}", e);
                             }
                         }
                     }
+                    
+                    // Same packet over the other networks (best effort)
+                    if encoded.redundant {
+                        for path in queues.paths.read().iter() {
+                            for data in &datagrams {
+                                if let Ok(sent) = sender.send_to(data, *path) {
+                                    bytes_sent.fetch_add(sent as u64, Ordering::Relaxed);
+                                }
+                            }
+                        }
+                    }
                 }
                 Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
                     consecutive_timeouts = consecutive_timeouts.saturating_add(1);
@@ -175,6 +570,13 @@ impl AudioSender {
             .map_err(|_| NetworkError::SendFailed("Channel full".to_string()))
     }
     
+    /// Send a control (handshake) packet to the target
+    pub fn send_control(&self, data: Bytes) -> Result<(), NetworkError> {
+        self.control_tx
+            .try_send(data)
+            .map_err(|_| NetworkError::SendFailed("Control channel full".to_string()))
+    }
+    
     /// Get channel for sending packets
     pub fn sender(&self) -> crossbeam_channel::Sender<EncodedPacket> {
         self.packet_tx.clone()
@@ -185,6 +587,11 @@ impl AudioSender {
         self.running.load(Ordering::SeqCst)
     }
     
+    /// Packets waiting for the sender thread
+    pub fn queued_packets(&self) -> usize {
+        self.packet_tx.len()
+    }
+    
     /// Get packets sent count
     pub fn packets_sent(&self) -> u64 {
         self.packets_sent.load(Ordering::Relaxed)
@@ -199,6 +606,13 @@ impl AudioSender {
     pub fn set_target(&mut self, addr: SocketAddr) {
         self.target_addr = addr;
     }
+    
+    /// Set the other addresses of the target (takes effect immediately)
+    pub fn set_redundant_paths(&self, paths: Vec<SocketAddr>) {
+        if *self.paths.read() != paths {
+            *self.paths.write() = paths;
+        }
+    }
 }
 
 impl Drop for AudioSender {
@@ -208,66 +622,266 @@ impl Drop for AudioSender {
 }
 
 /// Multi-track sender that aggregates packets from multiple tracks
+///
+/// Tracks go to the target unless given a destination of their own
+/// (`set_track_destination`); each extra destination gets its own sender.
 pub struct MultiTrackSender {
     inner: AudioSender,
+    /// Configuration the senders were started with
+    config: Option<NetworkConfig>,
+    /// Senders to the destinations of tracks that don't go to the target
+    destinations: RwLock<HashMap<SocketAddr, AudioSender>>,
+    /// Tracks sent to another destination than the target
+    track_destinations: dashmap::DashMap<u8, SocketAddr>,
     /// Per-track sequence counters
     sequences: dashmap::DashMap<u8, u32>,
+    /// Tracks whose next packet carries the restart marker
+    pending_restarts: dashmap::DashSet<u8>,
+    /// Tracks whose next packet is a latency probe
+    pending_probes: dashmap::DashSet<u8>,
+    /// Link to the receiver interrupted: audio isn't sent until it's back
+    paused: AtomicBool,
 }
 
 impl MultiTrackSender {
     pub fn new(config: &NetworkConfig, target_addr: SocketAddr) -> Result<Self, NetworkError> {
         Ok(Self {
             inner: AudioSender::new(config, target_addr)?,
+            config: None,
+            destinations: RwLock::new(HashMap::new()),
+            track_destinations: dashmap::DashMap::new(),
             sequences: dashmap::DashMap::new(),
+            pending_restarts: dashmap::DashSet::new(),
+            pending_probes: dashmap::DashSet::new(),
+            paused: AtomicBool::new(false),
         })
     }
     
     /// Start sender
     pub fn start(&mut self, config: NetworkConfig) -> Result<(), NetworkError> {
+        self.config = Some(config.clone());
         self.inner.start(config)
     }
     
+    /// Share clock synchronization state (must be called before `start`)
+    pub fn set_time_sync(&mut self, time_sync: Arc<TimeSync>) {
+        self.inner.set_time_sync(time_sync);
+    }
+    
+    /// Collect receiver feedback into a shared inbox (must be called before `start`)
+    pub fn set_feedback_inbox(&mut self, inbox: Arc<FeedbackInbox>) {
+        self.inner.set_feedback_inbox(inbox);
+    }
+    
+    /// Offer these tracks to the receiver for subscription (must be called before `start`)
+    pub fn set_track_catalog(&mut self, catalog: Arc<TrackCatalog>) {
+        self.inner.set_track_catalog(catalog);
+    }
+    
+    /// Send the files queued for the receiver (must be called before `start`)
+    pub fn set_file_transfers(&mut self, files: Arc<FileTransfers>) {
+        self.inner.set_file_transfers(files);
+    }
+    
+    /// Answer and track handshakes arriving on the socket (must be called before `start`)
+    pub fn set_handshake(&mut self, handshake: Arc<HandshakeManager>) {
+        self.inner.set_handshake(handshake);
+    }
+    
+    /// Take track lists and track changes of a peer we also receive from
+    /// (must be called before `start`)
+    pub fn set_subscriber(&mut self, subscriber: Arc<TrackSubscriber>) {
+        self.inner.set_subscriber(subscriber);
+    }
+    
+    /// Whether the receiver wants a track (all tracks until it subscribes)
+    pub fn is_subscribed(&self, track_id: u8) -> bool {
+        self.with_sender(track_id, |sender| sender.is_subscribed(track_id))
+    }
+    
+    /// What the receiver can play (None until it reports it)
+    pub fn peer_capabilities(&self) -> Option<PeerCapabilities> {
+        self.inner.peer_capabilities()
+    }
+    
     /// Stop sender
     pub fn stop(&mut self) {
         self.inner.stop();
+        for sender in self.destinations.write().values_mut() {
+            sender.stop();
+        }
+    }
+    
+    /// Pause or resume sending audio while the link to the receiver is down
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+    
+    /// Whether sending is paused (`set_paused`)
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+    
+    /// Send a track to another receiver than the target (None sends it to
+    /// the target again); the sender to a new destination starts here
+    pub fn set_track_destination(&self, track_id: u8, destination: Option<SocketAddr>) -> Result<(), NetworkError> {
+        let destination = destination.filter(|addr| *addr != self.inner.target_addr);
+        if self.track_destinations.get(&track_id).map(|entry| *entry.value()) == destination {
+            return Ok(());
+        }
+        
+        match destination {
+            Some(addr) => {
+                let mut senders = self.destinations.write();
+                if let std::collections::hash_map::Entry::Vacant(entry) = senders.entry(addr) {
+                    let config = self
+                        .config
+                        .as_ref()
+                        .ok_or_else(|| NetworkError::SendFailed("Sender not started".to_string()))?;
+                    let mut sender = AudioSender::new(config, addr)?;
+                    sender.control = self.inner.control.for_peer();
+                    sender.start(config.clone())?;
+                    tracing::info!("Sending to another receiver: {}", addr);
+                    entry.insert(sender);
+                }
+                self.track_destinations.insert(track_id, addr);
+            }
+            None => {
+                self.track_destinations.remove(&track_id);
+            }
+        }
+        tracing::info!("Track {} destination: {}", track_id, destination.unwrap_or(self.inner.target_addr));
+        
+        // The receiver now getting the track starts a fresh stream
+        self.mark_restart(track_id);
+        self.drop_unused_destinations();
+        Ok(())
+    }
+    
+    /// Tracks sent to another destination than the target
+    pub fn redirected_tracks(&self) -> Vec<u8> {
+        self.track_destinations.iter().map(|entry| *entry.key()).collect()
+    }
+    
+    /// Stop the senders to destinations no track goes to anymore
+    fn drop_unused_destinations(&self) {
+        self.destinations
+            .write()
+            .retain(|addr, _| self.track_destinations.iter().any(|entry| entry.value() == addr));
+    }
+    
+    /// Run `f` with the sender carrying a track
+    fn with_sender<T>(&self, track_id: u8, f: impl FnOnce(&AudioSender) -> T) -> T {
+        let destination = self.track_destinations.get(&track_id).map(|entry| *entry.value());
+        let senders = self.destinations.read();
+        match destination.and_then(|addr| senders.get(&addr)) {
+            Some(sender) => f(sender),
+            None => f(&self.inner),
+        }
+    }
+    
+    /// Set the other network paths to the target for redundant tracks
+    pub fn set_redundant_paths(&self, paths: Vec<SocketAddr>) {
+        self.inner.set_redundant_paths(paths);
     }
     
     /// Send encoded audio for a track
+    /// (`flags` describe the payload: stereo, in-band FEC data for the
+    /// previous frame, lossless codec; restart and probe marks are added
+    /// here; `priority` selects the DSCP class; `redundant` also sends the
+    /// packet over the redundant paths)
     pub fn send_audio(
         &self,
         track_id: u8,
         payload: Bytes,
         timestamp: u64,
-        stereo: bool,
+        flags: PacketFlags,
+        priority: TrackPriority,
+        redundant: bool,
     ) -> Result<u32, NetworkError> {
-        // Get and increment sequence
-        let sequence = {
-            let mut entry = self.sequences.entry(track_id).or_insert(0);
-            let seq = *entry;
-            *entry = entry.wrapping_add(1);
-            seq
+        // Get and increment sequence (first packet of a track starts the stream)
+        let (sequence, first) = match self.sequences.entry(track_id) {
+            dashmap::mapref::entry::Entry::Occupied(mut entry) => {
+                let seq = *entry.get();
+                *entry.get_mut() = seq.wrapping_add(1);
+                (seq, false)
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(1);
+                (0, true)
+            }
         };
         
+        let restart = self.pending_restarts.remove(&track_id).is_some() || first;
+        let probe = self.pending_probes.remove(&track_id).is_some();
+        
         let packet = EncodedPacket {
             track_id,
             sequence,
             timestamp,
             payload,
-            flags: PacketFlags::new().set_stereo(stereo),
+            flags: flags.set_keyframe(restart).set_probe(probe),
+            priority,
+            redundant,
         };
         
-        self.inner.send(packet)?;
+        self.with_sender(track_id, |sender| sender.send(packet))?;
         Ok(sequence)
     }
     
+    /// Send a silence marker for a track (a packet without payload, see
+    /// `audio::silence`): the receiver plays out what it has and then
+    /// silence, without counting the frames that aren't sent as lost
+    pub fn send_silence(
+        &self,
+        track_id: u8,
+        timestamp: u64,
+        flags: PacketFlags,
+        priority: TrackPriority,
+    ) -> Result<u32, NetworkError> {
+        self.send_audio(track_id, Bytes::new(), timestamp, flags.set_fec(false), priority, false)
+    }
+    
+    /// Mark the next packet of a track as a stream restart
+    /// (call whenever the track's encoder is reset or reconfigured)
+    pub fn mark_restart(&self, track_id: u8) {
+        self.pending_restarts.insert(track_id);
+    }
+    
+    /// Mark the next packet of a track as a latency probe
+    /// (its frame starts with the probe chirp, see `audio::probe`)
+    pub fn mark_probe(&self, track_id: u8) {
+        self.pending_probes.insert(track_id);
+    }
+    
     /// Reset sequence counter for a track
     pub fn reset_sequence(&self, track_id: u8) {
         self.sequences.insert(track_id, 0);
+        self.mark_restart(track_id);
+    }
+    
+    /// Re-sync after the machine woke up from sleep: restart every track
+    /// from sequence 0 and tell the receiver to flush its buffers
+    pub fn resync(&self) -> Result<(), NetworkError> {
+        let track_ids: Vec<u8> = self.sequences.iter().map(|entry| *entry.key()).collect();
+        for track_id in track_ids {
+            self.reset_sequence(track_id);
+        }
+        let packet = HandshakePacket::resync(0).serialize();
+        for sender in self.destinations.read().values() {
+            sender.send_control(packet.clone())?;
+        }
+        self.inner.send_control(packet)
     }
     
     /// Remove track
     pub fn remove_track(&self, track_id: u8) {
         self.sequences.remove(&track_id);
+        self.pending_restarts.remove(&track_id);
+        self.pending_probes.remove(&track_id);
+        if self.track_destinations.remove(&track_id).is_some() {
+            self.drop_unused_destinations();
+        }
     }
     
     /// Get sender channel
@@ -275,11 +889,19 @@ impl MultiTrackSender {
         self.inner.sender()
     }
     
+    /// Packets waiting to be sent, to all destinations
+    pub fn queued_packets(&self) -> usize {
+        let senders = self.destinations.read();
+        self.inner.queued_packets() + senders.values().map(AudioSender::queued_packets).sum::<usize>()
+    }
+    
     /// Get statistics
     pub fn stats(&self) -> SenderStats {
+        let senders = self.destinations.read();
+        let all = || std::iter::once(&self.inner).chain(senders.values());
         SenderStats {
-            packets_sent: self.inner.packets_sent(),
-            bytes_sent: self.inner.bytes_sent(),
+            packets_sent: all().map(AudioSender::packets_sent).sum(),
+            bytes_sent: all().map(AudioSender::bytes_sent).sum(),
             active_tracks: self.sequences.len(),
         }
     }
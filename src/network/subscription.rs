@@ -0,0 +1,821 @@
+//! Подписка получателя на треки
+//!
+//! Без подписки отправитель шлёт каждому пиру все свои треки. Получатель
+//! может выбрать нужные: он периодически запрашивает у каждого источника
+//! аудио список треков (`SyncRequest` → `SyncResponse`) и отвечает пакетом
+//! `Subscribe` с выбранными треками из этого списка. Отправитель хранит
+//! подписку в [`TrackOffer`] своего сокета и не кодирует/не отправляет
+//! этому пиру остальные треки.
+//!
+//! Тот же обмен согласует треки без шифрования: получатель, которому
+//! разрешено принимать такие треки, ставит флаг в `SyncRequest`, а
+//! отправитель помечает в `SyncResponse` треки с `TrackConfig::plaintext`.
+//! Только такие треки отправитель не шифрует этому пиру, и только их
+//! получатель принимает в открытом виде.
+//!
+//! В `SyncRequest` получатель также сообщает свои возможности (стерео,
+//! FEC, число треков); веб-интерфейс отправителя блокирует настройки,
+//! которые получатель не выполнит.
+//!
+//! Когда треки отправителя меняются (добавлен, перенастроен, удалён),
+//! каждый его сокет сразу шлёт получателю `TrackAdded` / `TrackRemoved`,
+//! и получатель обновляет имена, битрейт и число каналов своих треков
+//! (`TrackSubscriber::take_changes`). Потерянное уведомление не страшно:
+//! следующий `SyncResponse` приводит к тому же списку.
+//!
+//! При групповой рассылке (multicast) один поток идёт всем получателям
+//! сразу: отправитель по-прежнему отвечает на `SyncRequest`, но
+//! игнорирует подписки и всегда шифрует треки.
+//!
+//! `SyncRequest` и `Subscribe` меняют то, что отправляет сокет, поэтому
+//! отправитель принимает их только от своего получателя (его адреса,
+//! резервного пути или доверенного пира рукопожатия). С PSK получатель
+//! подписывает их (`PacketCipher::sign_control`), и пакеты без верной
+//! подписи отбрасываются.
+//!
+//! Формат полезной нагрузки `Subscribe`:
+//!
+//! ```text
+//! []                              все треки (подписка по умолчанию)
+//! [COUNT:1] [TRACK_ID:1] * COUNT  только перечисленные треки
+//! ```
+
+use bytes::{BufMut, Bytes, BytesMut};
+use dashmap::DashMap;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::network::crypto::PacketCipher;
+use crate::network::handshake::{HandshakePacket, HandshakePacketType, PeerCapabilities, TrackInfo};
+
+/// Интервал запроса списка треков (и повтора подписки на случай потерь)
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Источник, от которого ничего не приходило дольше этого, забывается
+const SOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Какие треки получатель хочет от отправителя
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Subscription {
+    /// Все треки (в том числе появившиеся позже)
+    #[default]
+    All,
+    /// Только перечисленные треки
+    Tracks(Vec<u8>),
+}
+
+impl Subscription {
+    /// Входит ли трек в подписку
+    pub fn includes(&self, track_id: u8) -> bool {
+        match self {
+            Self::All => true,
+            Self::Tracks(tracks) => tracks.contains(&track_id),
+        }
+    }
+
+    /// Сериализовать в полезную нагрузку пакета
+    pub fn encode(&self) -> Bytes {
+        match self {
+            Self::All => Bytes::new(),
+            Self::Tracks(tracks) => {
+                let count = tracks.len().min(u8::MAX as usize);
+                let mut buf = BytesMut::with_capacity(1 + count);
+                buf.put_u8(count as u8);
+                buf.put_slice(&tracks[..count]);
+                buf.freeze()
+            }
+        }
+    }
+
+    /// Разобрать полезную нагрузку пакета
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let Some((&count, tracks)) = payload.split_first() else {
+            return Some(Self::All);
+        };
+        let tracks = tracks.get(..count as usize)?;
+        Some(Self::Tracks(tracks.to_vec()))
+    }
+}
+
+/// Изменение трека отправителя
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackChange {
+    /// Новый трек или трек с другими настройками
+    Updated(TrackInfo),
+    /// Трек удалён
+    Removed(u8),
+}
+
+impl TrackChange {
+    /// Изменения, переводящие список `old` в `new`
+    pub fn between(old: &[TrackInfo], new: &[TrackInfo]) -> Vec<Self> {
+        let updated = new
+            .iter()
+            .filter(|track| !old.contains(track))
+            .map(|track| Self::Updated(track.clone()));
+        let removed = old
+            .iter()
+            .filter(|track| !new.iter().any(|t| t.track_id == track.track_id))
+            .map(|track| Self::Removed(track.track_id));
+        updated.chain(removed).collect()
+    }
+
+    /// Уведомление получателю
+    fn packet(&self) -> HandshakePacket {
+        match self {
+            Self::Updated(track) => HandshakePacket::track_added(0, track),
+            Self::Removed(track_id) => HandshakePacket::track_removed(0, *track_id),
+        }
+    }
+}
+
+/// Список треков, который отправитель сообщает в `SyncResponse`
+/// (общий для всех отправителей процесса)
+#[derive(Debug, Default)]
+pub struct TrackCatalog {
+    tracks: RwLock<Vec<TrackInfo>>,
+    /// Растёт при каждом изменении списка
+    version: AtomicU64,
+}
+
+impl TrackCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Заменить список треков
+    pub fn set_tracks(&self, tracks: Vec<TrackInfo>) {
+        let mut current = self.tracks.write();
+        if *current != tracks {
+            *current = tracks;
+            self.version.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    /// Текущий список треков
+    pub fn tracks(&self) -> Vec<TrackInfo> {
+        self.tracks.read().clone()
+    }
+
+    /// Номер версии списка
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+/// Сторона отправителя: список треков и подписка пира одного сокета
+#[derive(Debug, Clone, Default)]
+pub struct TrackOffer {
+    /// Отвечать на `SyncRequest` (без списка запрос игнорируется)
+    catalog: Option<Arc<TrackCatalog>>,
+    /// Последняя подписка пира
+    subscription: Arc<RwLock<Subscription>>,
+    /// Пир принимает треки без шифрования (флаг его `SyncRequest`)
+    accepts_plaintext: Arc<AtomicBool>,
+    /// Возможности пира из его `SyncRequest`
+    peer_capabilities: Arc<RwLock<Option<PeerCapabilities>>>,
+    /// Поток общий для всех получателей группы
+    shared: bool,
+    /// Треки, о которых пир уже знает
+    notified: Arc<Mutex<Option<NotifiedTracks>>>,
+    /// Проверка подписи `SyncRequest` и `Subscribe` (с PSK)
+    cipher: Option<Arc<PacketCipher>>,
+}
+
+/// Версия списка треков, последней отправленная пиру
+#[derive(Debug)]
+struct NotifiedTracks {
+    version: u64,
+    tracks: Vec<TrackInfo>,
+}
+
+impl TrackOffer {
+    /// Сообщать пиру треки из общего списка
+    pub fn set_catalog(&mut self, catalog: Arc<TrackCatalog>) {
+        self.catalog = Some(catalog);
+    }
+
+    /// Принимать только подписанные этим ключом запросы
+    pub fn set_cipher(&mut self, cipher: Option<Arc<PacketCipher>>) {
+        self.cipher = cipher;
+    }
+
+    /// Один поток для многих получателей (групповая рассылка): подписки и
+    /// треки без шифрования отдельного получателя не учитываются
+    pub fn set_shared(&mut self) {
+        self.shared = true;
+    }
+
+    /// Те же треки для другого пира (со своей подпиской)
+    pub fn for_peer(&self) -> Self {
+        Self {
+            catalog: self.catalog.clone(),
+            cipher: self.cipher.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Обработать handshake-пакет; `Some` если это был `SyncRequest` или
+    /// `Subscribe` (внутри - ответ, который нужно отправить обратно).
+    /// `from_receiver` - пакет пришёл от получателя этого сокета.
+    pub fn handle_packet(&self, data: &[u8], from: SocketAddr, from_receiver: bool) -> Option<Option<Bytes>> {
+        let packet = HandshakePacket::deserialize(data)?;
+        if !matches!(packet.packet_type, HandshakePacketType::SyncRequest | HandshakePacketType::Subscribe) {
+            return None;
+        }
+        // С PSK подпись проверяется и снимается перед разбором
+        let packet = match self.cipher {
+            Some(ref cipher) => match cipher.verify_control(data).ok().and_then(HandshakePacket::deserialize) {
+                Some(packet) => packet,
+                None => {
+                    tracing::debug!("Запрос {:?} от {} без верной подписи отброшен", packet.packet_type, from);
+                    return Some(None);
+                }
+            },
+            None => packet,
+        };
+        // Общий поток отвечает любому получателю группы
+        if !from_receiver && !self.shared {
+            tracing::debug!("Запрос {:?} не от получателя ({}) отброшен", packet.packet_type, from);
+            return Some(None);
+        }
+        match packet.packet_type {
+            HandshakePacketType::SyncRequest => {
+                if from_receiver && !self.shared {
+                    self.accepts_plaintext.store(packet.accepts_plaintext(), Ordering::Relaxed);
+                }
+                if let Some(capabilities) = packet.sync_capabilities().filter(|_| from_receiver) {
+                    *self.peer_capabilities.write() = Some(capabilities);
+                }
+                Some(self.catalog.as_ref().map(|catalog| {
+                    HandshakePacket::sync_response(packet.session_id, &catalog.tracks()).serialize()
+                }))
+            }
+            HandshakePacketType::Subscribe => {
+                if let Some(subscription) = packet.parse_subscribe().filter(|_| from_receiver && !self.shared) {
+                    let mut current = self.subscription.write();
+                    if *current != subscription {
+                        tracing::info!("Пир {} подписался на треки: {:?}", from, subscription);
+                        *current = subscription;
+                    }
+                }
+                Some(None)
+            }
+            _ => None,
+        }
+    }
+
+    /// Уведомления `TrackAdded` / `TrackRemoved` об изменениях списка
+    /// треков с прошлого вызова (сериализованные пакеты)
+    pub fn due_notifications(&self) -> Vec<Bytes> {
+        let Some(ref catalog) = self.catalog else {
+            return Vec::new();
+        };
+        let version = catalog.version();
+        let mut notified = self.notified.lock();
+        if notified.as_ref().is_some_and(|known| known.version == version) {
+            return Vec::new();
+        }
+
+        let tracks = catalog.tracks();
+        let packets = match notified.as_ref() {
+            Some(known) => TrackChange::between(&known.tracks, &tracks)
+                .iter()
+                .map(|change| change.packet().serialize())
+                .collect(),
+            // Первый список пир получит в SyncResponse
+            None => Vec::new(),
+        };
+        *notified = Some(NotifiedTracks { version, tracks });
+        packets
+    }
+
+    /// Возможности пира (None, пока он их не сообщил)
+    pub fn peer_capabilities(&self) -> Option<PeerCapabilities> {
+        *self.peer_capabilities.read()
+    }
+
+    /// Нужен ли трек пиру
+    pub fn is_subscribed(&self, track_id: u8) -> bool {
+        self.subscription.read().includes(track_id)
+    }
+
+    /// Отправлять ли трек пиру без шифрования: трек помечен в списке и
+    /// пир разрешил такие треки
+    pub fn sends_plaintext(&self, track_id: u8) -> bool {
+        self.accepts_plaintext.load(Ordering::Relaxed)
+            && self.catalog.as_ref().is_some_and(|catalog| {
+                catalog.tracks.read().iter().any(|track| track.track_id == track_id && track.plaintext)
+            })
+    }
+}
+
+/// Состояние одного источника аудио на стороне получателя
+#[derive(Debug)]
+struct SourceState {
+    last_seen: Instant,
+    last_request: Option<Instant>,
+    /// Треки из последнего `SyncResponse`
+    tracks: Option<Vec<TrackInfo>>,
+    /// Подписку нужно (пере)отправить
+    subscribe_pending: bool,
+}
+
+/// Сторона получателя: запрашивает списки треков у источников и
+/// подписывается на выбранные
+#[derive(Debug, Default)]
+pub struct TrackSubscriber {
+    sources: DashMap<SocketAddr, SourceState>,
+    /// Принимать только эти треки (None - все)
+    wanted: RwLock<Option<Vec<u8>>>,
+    /// Треки, удалённые пользователем
+    excluded: RwLock<HashSet<u8>>,
+    /// Принимать треки без шифрования, которые источник так пометил
+    accept_plaintext: AtomicBool,
+    /// Возможности, сообщаемые источникам (None - `receiver_only`)
+    capabilities: RwLock<Option<PeerCapabilities>>,
+    /// Изменения треков источников (забираются `take_changes`)
+    changes: Mutex<Vec<(SocketAddr, TrackChange)>>,
+    /// Треки, удалённые самим отправителем (а не пользователем)
+    withdrawn: RwLock<HashSet<u8>>,
+    /// Подпись `SyncRequest` и `Subscribe` (с PSK)
+    cipher: RwLock<Option<PacketCipher>>,
+}
+
+impl TrackSubscriber {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Принимать только перечисленные треки (None - все)
+    pub fn set_wanted(&self, wanted: Option<Vec<u8>>) {
+        *self.wanted.write() = wanted;
+        self.resubscribe();
+    }
+
+    /// Отписаться от трека у всех источников
+    pub fn exclude(&self, track_id: u8) {
+        if self.excluded.write().insert(track_id) {
+            self.resubscribe();
+        }
+    }
+
+    /// Снова принимать трек
+    pub fn include(&self, track_id: u8) {
+        if self.excluded.write().remove(&track_id) {
+            self.resubscribe();
+        }
+    }
+
+    /// Разрешить источникам не шифровать треки с `plaintext`
+    pub fn set_accept_plaintext(&self, accept: bool) {
+        self.accept_plaintext.store(accept, Ordering::Relaxed);
+    }
+
+    /// Подписывать запросы источникам этим ключом
+    pub fn set_cipher(&self, cipher: Option<PacketCipher>) {
+        *self.cipher.write() = cipher;
+    }
+
+    /// Сообщать источникам эти возможности
+    pub fn set_capabilities(&self, capabilities: PeerCapabilities) {
+        *self.capabilities.write() = Some(capabilities);
+    }
+
+    /// Принять ли незашифрованный пакет трека от источника
+    pub fn is_plaintext_track(&self, source: SocketAddr, track_id: u8) -> bool {
+        self.accept_plaintext.load(Ordering::Relaxed)
+            && self.sources.get(&source).is_some_and(|source| {
+                source
+                    .tracks
+                    .as_ref()
+                    .is_some_and(|tracks| tracks.iter().any(|t| t.track_id == track_id && t.plaintext))
+            })
+    }
+
+    fn resubscribe(&self) {
+        for mut source in self.sources.iter_mut() {
+            source.subscribe_pending = true;
+        }
+    }
+
+    /// Отметить источник аудио (вызывается на каждый аудио-пакет)
+    pub fn note_source(&self, address: SocketAddr) {
+        let now = Instant::now();
+        self.sources
+            .entry(address)
+            .and_modify(|source| source.last_seen = now)
+            .or_insert_with(|| SourceState {
+                last_seen: now,
+                last_request: None,
+                tracks: None,
+                subscribe_pending: false,
+            });
+    }
+
+    /// Подписка для источника с данным списком треков
+    fn subscription_for(&self, tracks: &[TrackInfo]) -> Subscription {
+        let wanted = self.wanted.read();
+        let excluded = self.excluded.read();
+        if wanted.is_none() && excluded.is_empty() {
+            return Subscription::All;
+        }
+        Subscription::Tracks(
+            tracks
+                .iter()
+                .map(|track| track.track_id)
+                .filter(|id| wanted.as_ref().is_none_or(|wanted| wanted.contains(id)))
+                .filter(|id| !excluded.contains(id))
+                .collect(),
+        )
+    }
+
+    /// Пакеты, которые пора отправить: (адрес, сериализованный пакет)
+    pub fn due_packets(&self) -> Vec<(SocketAddr, Bytes)> {
+        let now = Instant::now();
+        self.sources.retain(|_, source| now.duration_since(source.last_seen) < SOURCE_TIMEOUT);
+
+        let accept_plaintext = self.accept_plaintext.load(Ordering::Relaxed);
+        let capabilities = self.capabilities.read().unwrap_or_else(PeerCapabilities::receiver_only);
+        let cipher = self.cipher.read();
+        let sign = |packet: HandshakePacket| match *cipher {
+            Some(ref cipher) => cipher.sign_control(&packet.serialize()),
+            None => packet.serialize(),
+        };
+        let mut packets = Vec::new();
+        for mut source in self.sources.iter_mut() {
+            let address = *source.key();
+            let due = source
+                .last_request
+                .is_none_or(|t| now.duration_since(t) >= SYNC_INTERVAL);
+            if due {
+                source.last_request = Some(now);
+                packets.push((address, sign(HandshakePacket::sync_request(0, accept_plaintext, capabilities))));
+            }
+
+            if source.subscribe_pending {
+                if let Some(ref tracks) = source.tracks {
+                    let subscription = self.subscription_for(tracks);
+                    packets.push((address, sign(HandshakePacket::subscribe(0, &subscription))));
+                }
+                source.subscribe_pending = false;
+            }
+        }
+        packets
+    }
+
+    /// Обработать handshake-пакет, пришедший на аудио-сокет; true если
+    /// это был `SyncResponse`, `TrackAdded` или `TrackRemoved`
+    pub fn handle_packet(&self, data: &[u8], from: SocketAddr) -> bool {
+        let Some(packet) = HandshakePacket::deserialize(data) else {
+            return false;
+        };
+        match packet.packet_type {
+            HandshakePacketType::SyncResponse => {
+                if let Some(tracks) = packet.parse_sync_response() {
+                    self.handle_sync_response(tracks, from);
+                }
+            }
+            HandshakePacketType::TrackAdded => {
+                if let Some(track) = packet.parse_track_added() {
+                    self.apply_change(from, TrackChange::Updated(track));
+                }
+            }
+            HandshakePacketType::TrackRemoved => {
+                if let Some(track_id) = packet.parse_track_removed() {
+                    self.apply_change(from, TrackChange::Removed(track_id));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_sync_response(&self, tracks: Vec<TrackInfo>, from: SocketAddr) {
+        let mut source = self.sources.entry(from).or_insert_with(|| SourceState {
+            last_seen: Instant::now(),
+            last_request: Some(Instant::now()),
+            tracks: None,
+            subscribe_pending: false,
+        });
+        if source.tracks.is_none() {
+            let names: Vec<String> = tracks
+                .iter()
+                .map(|track| format!("{} ({})", track.track_id, track.name))
+                .collect();
+            tracing::info!("Источник {} предлагает треки: {}", from, names.join(", "));
+        }
+        // Первый список тоже сообщается: треки, созданные по первым
+        // аудио-пакетам, получают имена отправителя
+        let changes = TrackChange::between(source.tracks.as_deref().unwrap_or_default(), &tracks);
+        source.last_seen = Instant::now();
+        source.tracks = Some(tracks);
+        // Повтор подписки на каждый ответ: новые треки и потерянные пакеты
+        source.subscribe_pending = true;
+        drop(source);
+        for change in changes {
+            self.record_change(from, change);
+        }
+    }
+
+    /// Применить уведомление источника к его списку треков
+    fn apply_change(&self, from: SocketAddr, change: TrackChange) {
+        if let Some(mut entry) = self.sources.get_mut(&from) {
+            let source = &mut *entry;
+            source.last_seen = Instant::now();
+            if let Some(ref mut tracks) = source.tracks {
+                match change {
+                    TrackChange::Updated(ref track) => match tracks.iter_mut().find(|t| t.track_id == track.track_id) {
+                        Some(known) => *known = track.clone(),
+                        None => {
+                            tracks.push(track.clone());
+                            source.subscribe_pending = true;
+                        }
+                    },
+                    TrackChange::Removed(track_id) => tracks.retain(|t| t.track_id != track_id),
+                }
+            }
+        }
+        // Без списка (SyncResponse ещё не пришёл) изменение всё равно
+        // сообщается: трек мог быть создан по аудио-пакетам
+        self.record_change(from, change);
+    }
+
+    fn record_change(&self, from: SocketAddr, change: TrackChange) {
+        match change {
+            TrackChange::Updated(ref track) => {
+                self.withdrawn.write().remove(&track.track_id);
+            }
+            TrackChange::Removed(track_id) => {
+                tracing::info!("Источник {} удалил трек {}", from, track_id);
+                self.withdrawn.write().insert(track_id);
+            }
+        }
+        self.changes.lock().push((from, change));
+    }
+
+    /// Изменения треков источников с прошлого вызова
+    pub fn take_changes(&self) -> Vec<(SocketAddr, TrackChange)> {
+        std::mem::take(&mut *self.changes.lock())
+    }
+
+    /// Удалил ли трек сам отправитель (удаление выходного трека по этой
+    /// причине - не отписка пользователя)
+    pub fn is_withdrawn(&self, track_id: u8) -> bool {
+        self.withdrawn.read().contains(&track_id)
+    }
+
+    /// Треки, которые предлагают источники
+    pub fn advertised(&self) -> Vec<(SocketAddr, Vec<TrackInfo>)> {
+        self.sources
+            .iter()
+            .filter_map(|source| source.tracks.clone().map(|tracks| (*source.key(), tracks)))
+            .collect()
+    }
+
+    /// Предложенные источниками треки, входящие в подписку (для них
+    /// заранее создаются выходные треки)
+    pub fn subscribed(&self) -> Vec<(SocketAddr, TrackInfo)> {
+        self.advertised()
+            .into_iter()
+            .flat_map(|(address, tracks)| {
+                let subscription = self.subscription_for(&tracks);
+                tracks
+                    .into_iter()
+                    .filter(move |track| subscription.includes(track.track_id))
+                    .map(move |track| (address, track))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Codec;
+
+    fn track(track_id: u8, name: &str) -> TrackInfo {
+        TrackInfo {
+            track_id,
+            name: name.to_string(),
+            bitrate: 128_000,
+            channels: 2,
+            fec_enabled: false,
+            plaintext: false,
+            codec: Codec::Opus,
+            frame_duration_us: 10_000,
+        }
+    }
+
+    #[test]
+    fn test_subscription_encoding() {
+        for subscription in [Subscription::All, Subscription::Tracks(vec![]), Subscription::Tracks(vec![0, 3, 7])] {
+            assert_eq!(Subscription::decode(&subscription.encode()), Some(subscription));
+        }
+        assert_eq!(Subscription::decode(&[3, 1]), None);
+        assert!(Subscription::All.includes(9));
+        assert!(!Subscription::Tracks(vec![1]).includes(2));
+    }
+
+    #[test]
+    fn test_subscription_exchange() {
+        let catalog = Arc::new(TrackCatalog::new());
+        catalog.set_tracks(vec![track(0, "Микрофон"), track(1, "Игра"), track(2, "Музыка")]);
+        let mut offer = TrackOffer::default();
+        offer.set_catalog(catalog);
+
+        let subscriber = TrackSubscriber::new();
+        let sender: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let receiver: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        subscriber.note_source(sender);
+        subscriber.exclude(1);
+
+        // Сначала только запрос списка треков
+        let packets = subscriber.due_packets();
+        assert_eq!(packets.len(), 1);
+        let response = offer.handle_packet(&packets[0].1, receiver, true).unwrap().unwrap();
+        assert!(subscriber.handle_packet(&response, sender));
+        assert_eq!(subscriber.advertised()[0].1.len(), 3);
+
+        // Затем подписка на выбранные из списка
+        let packets = subscriber.due_packets();
+        assert_eq!(packets.len(), 1);
+        assert!(offer.is_subscribed(1));
+        assert_eq!(offer.handle_packet(&packets[0].1, receiver, true), Some(None));
+        assert!(offer.is_subscribed(0) && offer.is_subscribed(2));
+        assert!(!offer.is_subscribed(1));
+
+        subscriber.set_wanted(Some(vec![1, 2]));
+        offer.handle_packet(&subscriber.due_packets()[0].1, receiver, true);
+        assert!(!offer.is_subscribed(0) && !offer.is_subscribed(1));
+        assert!(offer.is_subscribed(2));
+
+        subscriber.set_wanted(None);
+        subscriber.include(1);
+        offer.handle_packet(&subscriber.due_packets()[0].1, receiver, true);
+        assert!(offer.is_subscribed(0) && offer.is_subscribed(1));
+
+        // Выходные треки создаются заранее для треков из подписки
+        subscriber.exclude(2);
+        let subscribed: Vec<u8> = subscriber.subscribed().iter().map(|(_, track)| track.track_id).collect();
+        assert_eq!(subscribed, vec![0, 1]);
+        let output = subscriber.subscribed()[1].1.output_config("output:Speakers".to_string());
+        assert_eq!((output.track_id, output.name.as_str(), output.channels), (Some(1), "Игра", 2));
+    }
+
+    #[test]
+    fn test_track_changes_reach_receiver() {
+        let catalog = Arc::new(TrackCatalog::new());
+        catalog.set_tracks(vec![track(0, "Микрофон"), track(1, "Игра")]);
+        let mut offer = TrackOffer::default();
+        offer.set_catalog(catalog.clone());
+        assert!(offer.due_notifications().is_empty());
+
+        let subscriber = TrackSubscriber::new();
+        let sender: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let receiver: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        subscriber.note_source(sender);
+        let response = offer.handle_packet(&subscriber.due_packets()[0].1, receiver, true).unwrap().unwrap();
+        subscriber.handle_packet(&response, sender);
+        assert_eq!(subscriber.take_changes().len(), 2);
+
+        // Отправитель переименовал трек 0 и удалил трек 1
+        catalog.set_tracks(vec![TrackInfo { channels: 1, ..track(0, "Голос") }]);
+        let version = catalog.version();
+        catalog.set_tracks(vec![TrackInfo { channels: 1, ..track(0, "Голос") }]);
+        assert_eq!(catalog.version(), version);
+        let notifications = offer.due_notifications();
+        assert_eq!(notifications.len(), 2);
+        assert!(offer.due_notifications().is_empty());
+        for notification in &notifications {
+            assert!(subscriber.handle_packet(notification, sender));
+        }
+
+        let changes = subscriber.take_changes();
+        assert!(matches!(&changes[0].1, TrackChange::Updated(t) if t.name == "Голос" && t.channels == 1));
+        assert_eq!(changes[1], (sender, TrackChange::Removed(1)));
+        assert!(subscriber.is_withdrawn(1));
+        assert_eq!(subscriber.advertised()[0].1.len(), 1);
+
+        // Вернувшийся трек снова принимается
+        catalog.set_tracks(vec![TrackInfo { channels: 1, ..track(0, "Голос") }, track(1, "Игра")]);
+        subscriber.handle_packet(&offer.due_notifications()[0], sender);
+        assert!(!subscriber.is_withdrawn(1));
+        assert_eq!(subscriber.subscribed().len(), 2);
+    }
+
+    #[test]
+    fn test_plaintext_negotiation() {
+        let catalog = Arc::new(TrackCatalog::new());
+        let music = TrackInfo { plaintext: true, ..track(1, "Музыка") };
+        catalog.set_tracks(vec![track(0, "Микрофон"), music]);
+        let mut offer = TrackOffer::default();
+        offer.set_catalog(catalog);
+
+        let subscriber = TrackSubscriber::new();
+        let sender: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let receiver: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        subscriber.note_source(sender);
+
+        // Получатель не разрешил: всё шифруется
+        let response = offer.handle_packet(&subscriber.due_packets()[0].1, receiver, true).unwrap().unwrap();
+        subscriber.handle_packet(&response, sender);
+        assert!(!offer.sends_plaintext(1));
+        assert!(!subscriber.is_plaintext_track(sender, 1));
+
+        subscriber.set_accept_plaintext(true);
+        let request = HandshakePacket::sync_request(0, true, PeerCapabilities::receiver_only()).serialize();
+        offer.handle_packet(&request, receiver, true);
+        assert!(offer.sends_plaintext(1));
+        assert!(!offer.sends_plaintext(0));
+        assert!(subscriber.is_plaintext_track(sender, 1));
+        assert!(!subscriber.is_plaintext_track(sender, 0));
+        assert!(!subscriber.is_plaintext_track(receiver, 1));
+    }
+
+    #[test]
+    fn test_capabilities_reach_sender() {
+        let offer = TrackOffer::default();
+        let subscriber = TrackSubscriber::new();
+        let sender: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let receiver: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        subscriber.note_source(sender);
+        subscriber.set_capabilities(PeerCapabilities { max_tracks: 4, ..PeerCapabilities::receiver_only() });
+        assert!(offer.peer_capabilities().is_none());
+
+        offer.handle_packet(&subscriber.due_packets()[0].1, receiver, true);
+        assert_eq!(offer.peer_capabilities().unwrap().max_tracks, 4);
+    }
+
+    #[test]
+    fn test_shared_offer_ignores_receivers() {
+        let catalog = Arc::new(TrackCatalog::new());
+        catalog.set_tracks(vec![TrackInfo { plaintext: true, ..track(0, "Музыка") }]);
+        let mut offer = TrackOffer::default();
+        offer.set_catalog(catalog);
+        offer.set_shared();
+        let receiver: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+
+        // Список треков получатели группы по-прежнему получают
+        let request = HandshakePacket::sync_request(0, true, PeerCapabilities::receiver_only()).serialize();
+        assert!(matches!(offer.handle_packet(&request, receiver, false), Some(Some(_))));
+        assert!(!offer.sends_plaintext(0));
+
+        let subscribe = HandshakePacket::subscribe(0, &Subscription::Tracks(vec![1])).serialize();
+        assert_eq!(offer.handle_packet(&subscribe, receiver, true), Some(None));
+        assert!(offer.is_subscribed(0));
+    }
+
+    #[test]
+    fn test_requests_from_others_rejected() {
+        let catalog = Arc::new(TrackCatalog::new());
+        catalog.set_tracks(vec![TrackInfo { plaintext: true, ..track(0, "Музыка") }, track(1, "Игра")]);
+        let mut offer = TrackOffer::default();
+        offer.set_catalog(catalog);
+        let stranger: SocketAddr = "192.168.1.66:5000".parse().unwrap();
+
+        let request = HandshakePacket::sync_request(0, true, PeerCapabilities::receiver_only()).serialize();
+        assert_eq!(offer.handle_packet(&request, stranger, false), Some(None));
+        let subscribe = HandshakePacket::subscribe(0, &Subscription::Tracks(vec![1])).serialize();
+        assert_eq!(offer.handle_packet(&subscribe, stranger, false), Some(None));
+        assert!(offer.peer_capabilities().is_none());
+        assert!(!offer.sends_plaintext(0));
+        assert!(offer.is_subscribed(0));
+    }
+
+    #[test]
+    fn test_signed_requests_with_psk() {
+        let catalog = Arc::new(TrackCatalog::new());
+        catalog.set_tracks(vec![track(0, "Микрофон"), track(1, "Игра")]);
+        let key = [3u8; crate::network::crypto::KEY_SIZE];
+        let mut offer = TrackOffer::default();
+        offer.set_catalog(catalog);
+        offer.set_cipher(Some(Arc::new(PacketCipher::from_key(&key))));
+
+        let subscriber = TrackSubscriber::new();
+        subscriber.set_cipher(Some(PacketCipher::from_key(&key)));
+        let sender: SocketAddr = "192.168.1.10:40000".parse().unwrap();
+        let receiver: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        subscriber.note_source(sender);
+        subscriber.exclude(1);
+
+        let request = subscriber.due_packets().remove(0).1;
+        let response = offer.handle_packet(&request, receiver, true).unwrap().unwrap();
+        subscriber.handle_packet(&response, sender);
+        // Повтор перехваченного запроса не принимается
+        assert_eq!(offer.handle_packet(&request, receiver, true), Some(None));
+
+        let subscribe = subscriber.due_packets().remove(0).1;
+        offer.handle_packet(&subscribe, receiver, true);
+        assert!(!offer.is_subscribed(1));
+
+        // Без подписи запросы отбрасываются, даже с адреса получателя
+        let unsigned = HandshakePacket::subscribe(0, &Subscription::All).serialize();
+        assert_eq!(offer.handle_packet(&unsigned, receiver, true), Some(None));
+        assert!(!offer.is_subscribed(1));
+    }
+}
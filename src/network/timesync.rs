@@ -0,0 +1,414 @@
+//! Синхронизация часов между пирами
+//!
+//! Временные метки аудио-пакетов берутся из [`media_time_us`] отправителя,
+//! поэтому получатель не может сравнить их со своими часами напрямую. Здесь
+//! реализован NTP-подобный обмен поверх handshake `Ping`/`Pong`:
+//!
+//! ```text
+//! Получатель                      Отправитель
+//!   t0 ──── PING (t0) ───────────────> t1
+//!   t3 <─── PONG (t0, t1, t2) ──────── t2
+//!
+//! offset = ((t1 - t0) + (t2 - t3)) / 2     (удалённые - локальные часы)
+//! rtt    = (t3 - t0) - (t2 - t1)
+//! ```
+//!
+//! Из последних замеров используется замер с минимальным RTT: у него
+//! наименьшая асимметрия очередей, а значит и наиболее точное смещение.
+//!
+//! После сна машины медиа-часы перестают соответствовать часам пиров
+//! (на Linux монотонные часы во сне стоят). [`SuspendDetector`] замечает
+//! такой скачок, после чего замеры сбрасываются, а пирам отправляется
+//! `Resync`, чтобы они сбросили свои буферы.
+
+use bytes::Bytes;
+use dashmap::{DashMap, DashSet};
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::network::handshake::{HandshakePacket, HandshakePacketType};
+
+/// Интервал между пингами одного пира
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Количество замеров в окне фильтра
+const SAMPLE_WINDOW: usize = 8;
+
+/// Пир забывается, если от него нет пакетов дольше этого времени
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Пауза основного цикла, после которой считается, что машина спала
+pub const SUSPEND_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Общие для процесса медиа-часы (микросекунды, монотонные)
+///
+/// Все временные метки аудио-пакетов и ответы на пинги должны браться
+/// из этих часов, иначе смещение не будет соответствовать пакетам.
+pub fn media_time_us() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+/// Настенное время в миллисекундах от эпохи Unix (для журналов, истории
+/// и отметок, которые показываются пользователю; не для аудио-пакетов)
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Оценка часов удалённого пира
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockEstimate {
+    /// Смещение удалённых часов относительно локальных (мкс)
+    pub offset_us: i64,
+    /// Время кругового обхода (мкс)
+    pub rtt_us: u64,
+}
+
+impl ClockEstimate {
+    /// Оценка по четырём временным меткам NTP-обмена
+    pub fn from_timestamps(t0: u64, t1: u64, t2: u64, t3: u64) -> Self {
+        let (t0, t1, t2, t3) = (t0 as i64, t1 as i64, t2 as i64, t3 as i64);
+        Self {
+            offset_us: ((t1 - t0) + (t2 - t3)) / 2,
+            rtt_us: ((t3 - t0) - (t2 - t1)).max(0) as u64,
+        }
+    }
+
+    /// Перевести удалённую временную метку в локальные часы
+    pub fn to_local_us(&self, remote_us: u64) -> u64 {
+        (remote_us as i64 - self.offset_us).max(0) as u64
+    }
+}
+
+/// Состояние синхронизации с одним пиром
+#[derive(Debug)]
+struct PeerClock {
+    /// Адрес, с которого приходит аудио (туда же отправляются пинги)
+    address: SocketAddr,
+    last_seen: Instant,
+    last_ping: Option<Instant>,
+    samples: VecDeque<ClockEstimate>,
+}
+
+impl PeerClock {
+    fn best(&self) -> Option<ClockEstimate> {
+        self.samples.iter().min_by_key(|s| s.rtt_us).copied()
+    }
+}
+
+/// Реестр синхронизации часов, общий для приёмника и отправителей
+pub struct TimeSync {
+    /// Пиры по IP (порт источника и порт ответа могут отличаться)
+    peers: DashMap<IpAddr, PeerClock>,
+    /// Пиры, приславшие `Resync` и ещё не обработанные приложением
+    resyncs: DashSet<IpAddr>,
+    next_session_id: AtomicU32,
+}
+
+impl TimeSync {
+    pub fn new() -> Self {
+        Self {
+            peers: DashMap::new(),
+            resyncs: DashSet::new(),
+            next_session_id: AtomicU32::new(1),
+        }
+    }
+
+    /// Отметить источник аудио (вызывается на каждый принятый пакет)
+    pub fn note_source(&self, address: SocketAddr) {
+        let now = Instant::now();
+        self.peers
+            .entry(address.ip())
+            .and_modify(|peer| {
+                peer.address = address;
+                peer.last_seen = now;
+            })
+            .or_insert_with(|| PeerClock {
+                address,
+                last_seen: now,
+                last_ping: None,
+                samples: VecDeque::with_capacity(SAMPLE_WINDOW),
+            });
+    }
+
+    /// Пинги, которые пора отправить: (адрес, сериализованный пакет)
+    pub fn due_pings(&self) -> Vec<(SocketAddr, Bytes)> {
+        let now = Instant::now();
+        self.peers.retain(|_, peer| now.duration_since(peer.last_seen) < PEER_TIMEOUT);
+
+        let mut pings = Vec::new();
+        for mut peer in self.peers.iter_mut() {
+            let due = peer
+                .last_ping
+                .map(|t| now.duration_since(t) >= PING_INTERVAL)
+                .unwrap_or(true);
+            if due {
+                peer.last_ping = Some(now);
+                let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+                let ping = HandshakePacket::time_ping(session_id, media_time_us());
+                pings.push((peer.address, ping.serialize()));
+            }
+        }
+        pings
+    }
+
+    /// Обработать handshake-пакет, пришедший на аудио-сокет.
+    /// Возвращает ответ, который нужно отправить обратно (Pong на Ping, эхо на зонд).
+    pub fn handle_packet(&self, data: &[u8], from: SocketAddr) -> Option<Bytes> {
+        let received_us = media_time_us();
+        let packet = HandshakePacket::deserialize(data)?;
+
+        match packet.packet_type {
+            HandshakePacketType::Ping => Some(respond_to_ping(&packet, received_us).serialize()),
+            HandshakePacketType::ProbeRequest => packet.probe_echo(received_us).map(|echo| echo.serialize()),
+            HandshakePacketType::Pong => {
+                let (t0, t1, t2) = packet.parse_time_pong()?;
+                self.record(from.ip(), ClockEstimate::from_timestamps(t0, t1, t2, received_us));
+                None
+            }
+            HandshakePacketType::Resync => {
+                self.reset_peer(from.ip());
+                self.resyncs.insert(from.ip());
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Добавить замер для пира
+    fn record(&self, ip: IpAddr, estimate: ClockEstimate) {
+        if let Some(mut peer) = self.peers.get_mut(&ip) {
+            if peer.samples.len() >= SAMPLE_WINDOW {
+                peer.samples.pop_front();
+            }
+            peer.samples.push_back(estimate);
+        }
+    }
+
+    /// Сбросить замеры пира (его часы прыгнули)
+    fn reset_peer(&self, ip: IpAddr) {
+        if let Some(mut peer) = self.peers.get_mut(&ip) {
+            peer.samples.clear();
+            peer.last_ping = None;
+        }
+    }
+    
+    /// Сбросить замеры всех пиров (прыгнули локальные часы)
+    pub fn reset_all(&self) {
+        for mut peer in self.peers.iter_mut() {
+            peer.samples.clear();
+            peer.last_ping = None;
+        }
+    }
+    
+    /// Забрать адреса пиров, приславших `Resync`
+    pub fn take_resyncs(&self) -> Vec<IpAddr> {
+        let ips: Vec<IpAddr> = self.resyncs.iter().map(|ip| *ip).collect();
+        for ip in &ips {
+            self.resyncs.remove(ip);
+        }
+        ips
+    }
+    
+    /// Текущая оценка часов пира (замер с минимальным RTT)
+    pub fn estimate(&self, ip: IpAddr) -> Option<ClockEstimate> {
+        self.peers.get(&ip).and_then(|peer| peer.best())
+    }
+
+    /// Перевести временную метку пакета пира в локальные медиа-часы
+    pub fn to_local_us(&self, ip: IpAddr, remote_us: u64) -> Option<u64> {
+        self.estimate(ip).map(|e| e.to_local_us(remote_us))
+    }
+
+    /// Задержка от захвата на удалённой стороне до текущего момента (мкс)
+    pub fn age_us(&self, ip: IpAddr, remote_us: u64) -> Option<u64> {
+        self.to_local_us(ip, remote_us)
+            .map(|captured| media_time_us().saturating_sub(captured))
+    }
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Обнаружение сна/пробуждения машины по скачку часов.
+///
+/// `check` вызывается из основного цикла; если с предыдущего вызова по
+/// монотонным или системным часам прошло больше [`SUSPEND_THRESHOLD`],
+/// цикл стоял - машина спала (монотонные часы во сне идут не на всех ОС,
+/// поэтому проверяются оба источника).
+#[derive(Debug)]
+pub struct SuspendDetector {
+    last_instant: Instant,
+    last_wall: SystemTime,
+    threshold: Duration,
+}
+
+impl SuspendDetector {
+    pub fn new() -> Self {
+        Self::with_threshold(SUSPEND_THRESHOLD)
+    }
+    
+    pub fn with_threshold(threshold: Duration) -> Self {
+        Self {
+            last_instant: Instant::now(),
+            last_wall: SystemTime::now(),
+            threshold,
+        }
+    }
+    
+    /// Длительность паузы, если с прошлого вызова машина спала
+    pub fn check(&mut self) -> Option<Duration> {
+        self.check_at(Instant::now(), SystemTime::now())
+    }
+    
+    fn check_at(&mut self, now: Instant, wall: SystemTime) -> Option<Duration> {
+        let monotonic = now.duration_since(self.last_instant);
+        // Перевод системных часов назад скачком не считается
+        let wall_elapsed = wall.duration_since(self.last_wall).unwrap_or_default();
+        self.last_instant = now;
+        self.last_wall = wall;
+        
+        let gap = monotonic.max(wall_elapsed);
+        (gap >= self.threshold).then_some(gap)
+    }
+}
+
+impl Default for SuspendDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Ответить на пинг (без состояния - отвечать может любой сокет)
+pub fn respond_to_ping(ping: &HandshakePacket, received_us: u64) -> HandshakePacket {
+    match ping.parse_time_ping() {
+        Some(t0) => HandshakePacket::time_pong(ping.session_id, t0, received_us, media_time_us()),
+        None => HandshakePacket::pong(ping.session_id),
+    }
+}
+
+/// Ответить на Ping (и зонд теста связи) без реестра (сокеты, которые
+/// только отвечают на пинги)
+pub fn answer_ping(data: &[u8]) -> Option<Bytes> {
+    let received_us = media_time_us();
+    let packet = HandshakePacket::deserialize(data)?;
+    match packet.packet_type {
+        HandshakePacketType::Ping => Some(respond_to_ping(&packet, received_us).serialize()),
+        HandshakePacketType::ProbeRequest => packet.probe_echo(received_us).map(|echo| echo.serialize()),
+        _ => None,
+    }
+}
+
+/// Обработать handshake-пакет на аудио-сокете: с реестром или только
+/// отвечая на пинги. Возвращает ответ для отправителя пакета.
+pub fn handle_socket_packet(time_sync: Option<&TimeSync>, data: &[u8], from: SocketAddr) -> Option<Bytes> {
+    match time_sync {
+        Some(sync) => sync.handle_packet(data, from),
+        None => answer_ping(data),
+    }
+}
+
+/// Похож ли пакет на handshake-пакет (а не на аудио)
+pub fn is_handshake_packet(data: &[u8]) -> bool {
+    HandshakePacket::has_magic(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_from_timestamps() {
+        // Удалённые часы на 5000 мкс впереди, задержка 300 мкс в каждую сторону,
+        // обработка на удалённой стороне 50 мкс
+        let estimate = ClockEstimate::from_timestamps(1_000, 6_300, 6_350, 1_650);
+        assert_eq!(estimate.offset_us, 5_000);
+        assert_eq!(estimate.rtt_us, 600);
+        assert_eq!(estimate.to_local_us(16_000), 11_000);
+    }
+
+    #[test]
+    fn test_unix_time_is_wall_clock() {
+        // Миллисекунды от 1970 года, а не от запуска процесса, как медиа-часы
+        let before = unix_time_ms();
+        assert!(before > 1_577_836_800_000, "раньше 2020 года: {}", before);
+        assert!(unix_time_ms() >= before);
+    }
+
+    #[test]
+    fn test_ping_pong_roundtrip() {
+        let sync = TimeSync::new();
+        let addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        sync.note_source(addr);
+
+        let pings = sync.due_pings();
+        assert_eq!(pings.len(), 1);
+        assert!(sync.due_pings().is_empty());
+
+        // "Удалённая" сторона - тот же процесс, смещение ~0
+        let (to, ping) = &pings[0];
+        assert_eq!(*to, addr);
+        assert!(is_handshake_packet(ping));
+        let pong = TimeSync::new().handle_packet(ping, addr).unwrap();
+        assert!(sync.handle_packet(&pong, addr).is_none());
+
+        let estimate = sync.estimate(addr.ip()).unwrap();
+        assert!(estimate.offset_us.abs() < 50_000);
+        assert!(sync.age_us(addr.ip(), media_time_us()).is_some());
+    }
+
+    #[test]
+    fn test_min_rtt_filter() {
+        let sync = TimeSync::new();
+        let addr: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        sync.note_source(addr);
+
+        // Асимметричная очередь искажает смещение; замер с меньшим RTT точнее
+        sync.record(addr.ip(), ClockEstimate { offset_us: 9_000, rtt_us: 20_000 });
+        sync.record(addr.ip(), ClockEstimate { offset_us: 5_100, rtt_us: 400 });
+        sync.record(addr.ip(), ClockEstimate { offset_us: 7_000, rtt_us: 8_000 });
+        assert_eq!(sync.estimate(addr.ip()).unwrap().offset_us, 5_100);
+    }
+    
+    #[test]
+    fn test_resync_resets_peer_clock() {
+        let sync = TimeSync::new();
+        let addr: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        sync.note_source(addr);
+        sync.record(addr.ip(), ClockEstimate { offset_us: 5_000, rtt_us: 400 });
+        
+        let resync = HandshakePacket::resync(1).serialize();
+        assert!(sync.handle_packet(&resync, addr).is_none());
+        assert!(sync.estimate(addr.ip()).is_none());
+        assert_eq!(sync.take_resyncs(), vec![addr.ip()]);
+        assert!(sync.take_resyncs().is_empty());
+        
+        // После сброса пинг уходит сразу
+        assert_eq!(sync.due_pings().len(), 1);
+    }
+    
+    #[test]
+    fn test_suspend_detector() {
+        let mut detector = SuspendDetector::new();
+        let (now, wall) = (detector.last_instant, detector.last_wall);
+        
+        assert!(detector.check_at(now + Duration::from_millis(5), wall + Duration::from_millis(5)).is_none());
+        
+        // Монотонные часы во сне стояли, системные ушли вперёд
+        let gap = detector
+            .check_at(now + Duration::from_millis(10), wall + Duration::from_secs(600))
+            .unwrap();
+        assert!(gap >= Duration::from_secs(599));
+        
+        // Системные часы переведены назад - не сон
+        assert!(detector.check_at(now + Duration::from_millis(15), wall).is_none());
+    }
+}
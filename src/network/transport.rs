@@ -0,0 +1,655 @@
+//! Stream transport fallback for networks that drop UDP
+//!
+//! Audio normally travels in UDP datagrams. Sender and receiver code move
+//! datagrams through the [`Transport`] trait, so the same packets can also
+//! travel over TCP, each one prefixed with its length:
+//!
+//! ```text
+//! [LEN:2 BE] [PACKET:LEN]   (the RFC 4571 framing, also used for RTP)
+//! ```
+//!
+//! Receivers with `TransportMode::Auto` or `Tcp` listen for TCP on the
+//! audio port next to the UDP socket. A sender in `Auto` mode pings the
+//! receiver over UDP (handshake `Ping`); if nothing comes back within
+//! [`FALLBACK_TIMEOUT`] it connects over TCP and streams there, retrying
+//! the connection while it fails. `Tcp` mode connects right away, and
+//! `Quic` mode once the receiver offers QUIC (see `network::quic`).
+//! Connections are made on their own thread ([`PendingConnection`]), so
+//! the sender keeps streaming over UDP while one is attempted.
+//! Redundant paths need datagrams and stay unused over TCP and QUIC.
+
+use parking_lot::Mutex;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket as StdUdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::config::{NetworkConfig, TransportMode};
+use crate::network::packet_log::Direction;
+use crate::network::pcap;
+use crate::network::quic::QuicListener;
+use crate::network::udp::canonical_addr;
+
+/// Without a UDP reply for this long a sender falls back to TCP
+pub const FALLBACK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Interval of UDP connectivity pings and of TCP connection attempts
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connectivity ping without a reply after this long counts as lost
+pub const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Time allowed for a TCP connection to be established
+pub const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Queued outgoing bytes per connection; further packets are dropped
+/// rather than delayed behind a stalled connection
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+
+/// Length prefix of a frame
+const FRAME_HEADER: usize = 2;
+
+/// Moves audio and control datagrams to and from peers
+pub trait Transport: Send + Sync {
+    /// Send one packet to `addr`
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Receive the next packet without blocking (`WouldBlock` if none)
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Local address of the transport
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Underlying UDP socket, for datagram transports
+    fn udp_socket(&self) -> Option<&StdUdpSocket> {
+        None
+    }
+
+    /// Largest packet the transport carries now, if smaller than a UDP datagram
+    fn max_datagram_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl Transport for StdUdpSocket {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        StdUdpSocket::send_to(self, data, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        StdUdpSocket::recv_from(self, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        StdUdpSocket::local_addr(self)
+    }
+
+    fn udp_socket(&self) -> Option<&StdUdpSocket> {
+        Some(self)
+    }
+}
+
+/// Whether an I/O error means the stream connection is gone
+pub fn is_connection_lost(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// One framed TCP connection
+struct FramedStream {
+    stream: TcpStream,
+    peer: SocketAddr,
+    /// Received bytes not yet returned as frames
+    inbound: Vec<u8>,
+    /// Frames not yet accepted by the kernel
+    outbound: Vec<u8>,
+}
+
+impl FramedStream {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        let peer = canonical_addr(stream.peer_addr()?);
+        Ok(Self {
+            stream,
+            peer,
+            inbound: Vec::new(),
+            outbound: Vec::new(),
+        })
+    }
+
+    /// Write queued bytes the kernel accepts without blocking
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outbound.is_empty() {
+            match self.stream.write(&self.outbound) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outbound.drain(..written);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = u16::try_from(data.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet too large for a frame"))?;
+        self.flush()?;
+        if self.outbound.len() + FRAME_HEADER + data.len() > MAX_PENDING_BYTES {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.outbound.extend_from_slice(&len.to_be_bytes());
+        self.outbound.extend_from_slice(data);
+        self.flush()?;
+        Ok(data.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush()?;
+        loop {
+            if let Some(size) = self.take_frame(buf) {
+                return Ok(size);
+            }
+            let mut chunk = [0u8; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.inbound.extend_from_slice(&chunk[..read]),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Next complete frame, truncated to `buf`
+    fn take_frame(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let header = self.inbound.get(..FRAME_HEADER)?;
+        let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+        let frame = self.inbound.get(FRAME_HEADER..FRAME_HEADER + len)?;
+        let size = len.min(buf.len());
+        buf[..size].copy_from_slice(&frame[..size]);
+        self.inbound.drain(..FRAME_HEADER + len);
+        Some(size)
+    }
+}
+
+/// Sender side: packets over a TCP connection to the receiver
+pub struct TcpTransport {
+    connection: Mutex<FramedStream>,
+    local: SocketAddr,
+}
+
+impl TcpTransport {
+    /// Connect to a receiver's TCP listener
+    pub fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        let local = stream.local_addr()?;
+        Ok(Self {
+            connection: Mutex::new(FramedStream::new(stream)?),
+            local,
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let mut connection = self.connection.lock();
+        if canonical_addr(addr) != connection.peer {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "no stream to this address"));
+        }
+        connection.send(data)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut connection = self.connection.lock();
+        let size = connection.recv(buf)?;
+        Ok((size, connection.peer))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local)
+    }
+}
+
+/// Connection attempt running on its own thread, so an unreachable
+/// receiver doesn't stall the sender loop for [`CONNECT_TIMEOUT`]
+pub struct PendingConnection {
+    result: crossbeam_channel::Receiver<io::Result<Box<dyn Transport>>>,
+}
+
+impl PendingConnection {
+    /// Run `connect` on a new thread
+    pub fn start<F>(connect: F) -> io::Result<Self>
+    where
+        F: FnOnce() -> io::Result<Box<dyn Transport>> + Send + 'static,
+    {
+        let (result_tx, result) = crossbeam_channel::bounded(1);
+        std::thread::Builder::new()
+            .name("transport-connect".to_string())
+            .spawn(move || {
+                let _ = result_tx.send(connect());
+            })?;
+        Ok(Self { result })
+    }
+
+    /// Result of the attempt once it is over, without blocking
+    pub fn poll(&self) -> Option<io::Result<Box<dyn Transport>>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(crossbeam_channel::TryRecvError::Empty) => None,
+            Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                Some(Err(io::Error::other("connection attempt aborted")))
+            }
+        }
+    }
+}
+
+/// Non-blocking TCP listener on `addr` (dual-stack like the UDP socket)
+fn listen(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(16)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Receiver side: the UDP socket plus TCP connections from senders that
+/// fell back and QUIC connections; replies go back the way their peer's
+/// packets came
+pub struct ReceiverTransport {
+    udp: StdUdpSocket,
+    listener: Option<TcpListener>,
+    connections: Mutex<Vec<FramedStream>>,
+    quic: Option<QuicListener>,
+}
+
+impl ReceiverTransport {
+    /// Wrap the UDP socket and listen for TCP on the same address, or for
+    /// QUIC on the QUIC port, if the transport mode allows it
+    pub fn new(udp: StdUdpSocket, config: &NetworkConfig) -> Self {
+        let listener = match config.transport {
+            TransportMode::Udp | TransportMode::Quic => None,
+            TransportMode::Auto | TransportMode::Tcp => udp
+                .local_addr()
+                .and_then(listen)
+                .inspect_err(|e| tracing::warn!("TCP fallback unavailable: {}", e))
+                .ok(),
+        };
+        let quic = match config.transport {
+            TransportMode::Quic => udp
+                .local_addr()
+                .and_then(|addr| QuicListener::bind(SocketAddr::new(addr.ip(), config.quic_port)))
+                .inspect_err(|e| tracing::warn!("QUIC unavailable on port {}: {}", config.quic_port, e))
+                .ok(),
+            _ => None,
+        };
+        Self {
+            udp,
+            listener,
+            connections: Mutex::new(Vec::new()),
+            quic,
+        }
+    }
+
+    /// The UDP socket
+    pub fn udp(&self) -> &StdUdpSocket {
+        &self.udp
+    }
+
+    /// Addresses of senders streaming over TCP
+    pub fn tcp_peers(&self) -> Vec<SocketAddr> {
+        self.connections.lock().iter().map(|connection| connection.peer).collect()
+    }
+
+    /// Addresses of senders streaming over QUIC
+    pub fn quic_peers(&self) -> Vec<SocketAddr> {
+        self.quic.as_ref().map(QuicListener::peers).unwrap_or_default()
+    }
+
+    fn accept(&self, connections: &mut Vec<FramedStream>) {
+        let Some(ref listener) = self.listener else {
+            return;
+        };
+        while let Ok((stream, addr)) = listener.accept() {
+            match FramedStream::new(stream) {
+                Ok(connection) => {
+                    tracing::info!("Sender {} connected over TCP", connection.peer);
+                    connections.push(connection);
+                }
+                Err(e) => tracing::warn!("Failed to set up TCP connection from {}: {}", addr, e),
+            }
+        }
+    }
+}
+
+impl Transport for ReceiverTransport {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let peer = canonical_addr(addr);
+        pcap::record_on(Direction::Sent, self, peer, data);
+        let mut connections = self.connections.lock();
+        if let Some(index) = connections.iter().position(|connection| connection.peer == peer) {
+            let result = connections[index].send(data);
+            if result.as_ref().is_err_and(is_connection_lost) {
+                connections.swap_remove(index);
+            }
+            return result;
+        }
+        drop(connections);
+        if let Some(ref quic) = self.quic {
+            if quic.is_connected(peer) {
+                return quic.send_to(data, peer);
+            }
+        }
+        self.udp.send_to(data, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, addr) = self.receive(buf)?;
+        pcap::record_on(Direction::Received, self, canonical_addr(addr), &buf[..size]);
+        Ok((size, addr))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+
+    fn udp_socket(&self) -> Option<&StdUdpSocket> {
+        Some(&self.udp)
+    }
+}
+
+impl ReceiverTransport {
+    /// Next datagram from the UDP socket, a QUIC or a TCP connection
+    fn receive(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.udp.recv_from(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            result => return result,
+        }
+        if let Some(ref quic) = self.quic {
+            if let Ok(received) = quic.recv_from(buf) {
+                return Ok(received);
+            }
+        }
+
+        let mut connections = self.connections.lock();
+        self.accept(&mut connections);
+        let mut index = 0;
+        while index < connections.len() {
+            match connections[index].recv(buf) {
+                Ok(size) => return Ok((size, connections[index].peer)),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => index += 1,
+                Err(e) => {
+                    let connection = connections.swap_remove(index);
+                    tracing::info!("TCP connection from {} closed: {}", connection.peer, e);
+                }
+            }
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+/// Decides when a sender gives up on UDP
+#[derive(Debug)]
+pub struct ConnectivityCheck {
+    mode: TransportMode,
+    started: Instant,
+    last_probe: Option<Instant>,
+    last_attempt: Option<Instant>,
+    /// The receiver answered over UDP
+    confirmed: bool,
+    /// Streaming over TCP or QUIC
+    streaming: bool,
+}
+
+impl ConnectivityCheck {
+    /// `datagram_only` targets (multicast, broadcast) are never checked
+    pub fn new(mode: TransportMode, datagram_only: bool, now: Instant) -> Self {
+        Self {
+            mode: if datagram_only { TransportMode::Udp } else { mode },
+            started: now,
+            last_probe: None,
+            last_attempt: None,
+            confirmed: false,
+            streaming: false,
+        }
+    }
+
+    /// Whether to ping the receiver over UDP now
+    pub fn probe_due(&mut self, now: Instant) -> bool {
+        let due = self.mode == TransportMode::Auto
+            && !self.confirmed
+            && !self.streaming
+            && self.last_probe.is_none_or(|last| now.duration_since(last) >= PROBE_INTERVAL);
+        if due {
+            self.last_probe = Some(now);
+        }
+        due
+    }
+
+    /// A packet from the receiver arrived over UDP
+    pub fn confirm(&mut self) {
+        if !self.streaming {
+            self.confirmed = true;
+        }
+    }
+
+    /// Transport mode the check runs in (`Udp` for datagram-only targets)
+    pub fn mode(&self) -> TransportMode {
+        self.mode
+    }
+
+    /// Whether to try connecting over TCP (or QUIC) now
+    pub fn connect_due(&mut self, now: Instant) -> bool {
+        let waited = match self.mode {
+            TransportMode::Udp => return false,
+            // Only while the latest ping has gone unanswered, so a receiver
+            // that just came up answers over UDP first
+            TransportMode::Auto => {
+                !self.confirmed
+                    && now.duration_since(self.started) >= FALLBACK_TIMEOUT
+                    && self.last_probe.is_some_and(|probe| now.duration_since(probe) >= REPLY_TIMEOUT)
+            }
+            // QUIC waits for the receiver to offer it, over UDP meanwhile
+            TransportMode::Tcp | TransportMode::Quic => true,
+        };
+        let due = waited
+            && !self.streaming
+            && self.last_attempt.is_none_or(|last| now.duration_since(last) >= PROBE_INTERVAL);
+        if due {
+            self.last_attempt = Some(now);
+        }
+        due
+    }
+
+    /// The TCP or QUIC connection is up
+    pub fn connected(&mut self) {
+        self.streaming = true;
+    }
+
+    /// The connection broke; reconnect on the next attempt
+    pub fn connection_lost(&mut self) {
+        self.streaming = false;
+    }
+
+    /// Whether packets currently go over TCP or QUIC
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::quic::QuicTransport;
+
+    fn wait_recv(transport: &dyn Transport, buf: &mut [u8]) -> (usize, SocketAddr) {
+        for _ in 0..200 {
+            if let Ok(received) = transport.recv_from(buf) {
+                return received;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no packet received");
+    }
+
+    #[test]
+    fn test_tcp_roundtrip() {
+        let udp = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_nonblocking(true).unwrap();
+        let receiver = ReceiverTransport::new(udp, &NetworkConfig::default());
+        let addr = receiver.local_addr().unwrap();
+
+        let sender = TcpTransport::connect(addr).unwrap();
+        sender.send_to(b"first", addr).unwrap();
+        sender.send_to(b"second", addr).unwrap();
+        assert!(sender.send_to(b"elsewhere", "127.0.0.1:9".parse().unwrap()).is_err());
+
+        let mut buf = [0u8; 64];
+        let (size, from) = wait_recv(&receiver, &mut buf);
+        assert_eq!(&buf[..size], b"first");
+        assert_eq!(from, sender.local_addr().unwrap());
+        let (size, _) = wait_recv(&receiver, &mut buf);
+        assert_eq!(&buf[..size], b"second");
+        assert_eq!(receiver.tcp_peers(), vec![from]);
+
+        // Replies to a TCP peer take its connection
+        receiver.send_to(b"reply", from).unwrap();
+        let (size, reply_from) = wait_recv(&sender, &mut buf);
+        assert_eq!(&buf[..size], b"reply");
+        assert_eq!(reply_from, addr);
+
+        // UDP still works next to it
+        let client = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"datagram", addr).unwrap();
+        let (size, from) = wait_recv(&receiver, &mut buf);
+        assert_eq!(&buf[..size], b"datagram");
+        assert_eq!(from, client.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_udp_only_receiver_refuses_tcp() {
+        let udp = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = NetworkConfig {
+            transport: TransportMode::Udp,
+            ..Default::default()
+        };
+        let receiver = ReceiverTransport::new(udp, &config);
+        assert!(TcpTransport::connect(receiver.local_addr().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_quic_receiver() {
+        let quic_port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let udp = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_nonblocking(true).unwrap();
+        let config = NetworkConfig {
+            transport: TransportMode::Quic,
+            quic_port,
+            ..Default::default()
+        };
+        let receiver = ReceiverTransport::new(udp, &config);
+        let addr = receiver.local_addr().unwrap();
+        assert!(TcpTransport::connect(addr).is_err());
+
+        let sender = QuicTransport::connect(addr, quic_port).unwrap();
+        sender.send_to(b"packet", addr).unwrap();
+        let mut buf = [0u8; 64];
+        let (size, from) = wait_recv(&receiver, &mut buf);
+        assert_eq!(&buf[..size], b"packet");
+        assert_eq!(receiver.quic_peers(), vec![from]);
+
+        // Replies to a QUIC peer take its connection
+        receiver.send_to(b"reply", from).unwrap();
+        let (size, reply_from) = wait_recv(&sender, &mut buf);
+        assert_eq!(&buf[..size], b"reply");
+        assert_eq!(reply_from, addr);
+    }
+
+    #[test]
+    fn test_pending_connection_to_unreachable_port() {
+        // Nothing listens for QUIC there: the handshake times out
+        let silent = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap();
+        let started = Instant::now();
+        let pending =
+            PendingConnection::start(move || Ok(Box::new(QuicTransport::connect(addr, addr.port())?) as Box<dyn Transport>))
+                .unwrap();
+        assert!(started.elapsed() < CONNECT_TIMEOUT);
+        assert!(pending.poll().is_none());
+
+        let deadline = started + CONNECT_TIMEOUT * 10;
+        let result = loop {
+            if let Some(result) = pending.poll() {
+                break result;
+            }
+            assert!(Instant::now() < deadline, "connection attempt never ended");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert!(result.is_err());
+
+        // A closed TCP port is refused
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let pending =
+            PendingConnection::start(move || Ok(Box::new(TcpTransport::connect(closed)?) as Box<dyn Transport>)).unwrap();
+        let result = loop {
+            if let Some(result) = pending.poll() {
+                break result;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_connectivity_check() {
+        let start = Instant::now();
+        let mut check = ConnectivityCheck::new(TransportMode::Auto, false, start);
+        assert!(check.probe_due(start));
+        assert!(!check.probe_due(start));
+        assert!(!check.connect_due(start + PROBE_INTERVAL));
+
+        // No reply: fall back, retrying while the connection fails
+        let late = start + FALLBACK_TIMEOUT;
+        assert!(check.connect_due(late));
+        assert!(!check.connect_due(late));
+        assert!(check.connect_due(late + PROBE_INTERVAL));
+        // Not while a fresh ping may still be answered
+        assert!(check.probe_due(late + PROBE_INTERVAL * 2));
+        assert!(!check.connect_due(late + PROBE_INTERVAL * 2));
+        check.connected();
+        assert!(check.is_streaming());
+        assert!(!check.probe_due(late + PROBE_INTERVAL * 3));
+        check.connection_lost();
+        assert!(check.connect_due(late + PROBE_INTERVAL * 3));
+
+        // A UDP reply keeps the sender on UDP
+        let mut check = ConnectivityCheck::new(TransportMode::Auto, false, start);
+        check.confirm();
+        assert!(!check.probe_due(start));
+        assert!(!check.connect_due(late));
+
+        // QUIC mode doesn't ping and connects as soon as the receiver offers QUIC
+        let mut check = ConnectivityCheck::new(TransportMode::Quic, false, start);
+        assert!(!check.probe_due(start));
+        assert!(check.connect_due(start));
+        assert!(!check.connect_due(start));
+        assert!(check.connect_due(start + PROBE_INTERVAL));
+        assert_eq!(check.mode(), TransportMode::Quic);
+
+        // Multicast and broadcast are never checked
+        let mut check = ConnectivityCheck::new(TransportMode::Tcp, true, start);
+        assert!(!check.probe_due(start));
+        assert!(!check.connect_due(late));
+        let check = ConnectivityCheck::new(TransportMode::Quic, true, start);
+        assert_eq!(check.mode(), TransportMode::Udp);
+    }
+}
@@ -2,34 +2,55 @@
 //!
 //! Optimized for low-latency audio streaming with configurable
 //! buffer sizes and non-blocking I/O.
+//!
+//! With `NetworkConfig::multicast_group` set every audio socket joins the
+//! group and multicast packets it sends carry `multicast_ttl`.
+//!
+//! With `QosConfig::dscp` (Linux) sockets mark their datagrams with a DSCP
+//! class: Expedited Forwarding for control packets, and the class of the
+//! track's priority for audio (`TrackPriority::dscp`), so routers and Wi-Fi
+//! access points queue voice ahead of music and ambience.
 
-use socket2::{Domain, Protocol, Socket, Type};
-use std::net::{SocketAddr, UdpSocket as StdUdpSocket};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket};
 use std::io;
 use tokio::net::UdpSocket as TokioUdpSocket;
 
 use crate::config::NetworkConfig;
 use crate::error::NetworkError;
+use crate::network::packet_log::Direction;
+use crate::network::pcap;
+use crate::network::transport::Transport;
+use crate::protocol::TrackPriority;
 
 /// Re-export for convenience
 pub type UdpSocket = TokioUdpSocket;
 
-/// Create a configured UDP socket for audio streaming
+/// Create a configured UDP socket for audio streaming.
+///
+/// An IPv6 bind address gives a dual-stack socket: IPv4 peers are
+/// reached through v4-mapped addresses (`::ffff:a.b.c.d`).
 pub fn create_socket(config: &NetworkConfig) -> Result<StdUdpSocket, NetworkError> {
-    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+    let addr = config.bind_socket_addr()
+        .ok_or_else(|| NetworkError::BindFailed(format!("Invalid bind address: {}", config.bind_address)))?;
+    
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))
         .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
     
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)
+            .map_err(|e| NetworkError::BindFailed(format!("Failed to enable dual-stack: {}", e)))?;
+    }
+    
     // Set socket options for low latency
-    configure_socket(&socket, config)?;
+    configure_socket(&socket, config, addr.is_ipv4())?;
     
     // Bind to address
-    let addr: SocketAddr = format!("{}:{}", config.bind_address, config.udp_port)
-        .parse()
-        .map_err(|e: std::net::AddrParseError| NetworkError::BindFailed(e.to_string()))?;
-    
     socket.bind(&addr.into())
         .map_err(|e| NetworkError::BindFailed(e.to_string()))?;
     
+    join_multicast(&socket, config, addr)?;
+    
     // Convert to std socket
     let std_socket: StdUdpSocket = socket.into();
     std_socket.set_nonblocking(true)
@@ -45,8 +66,22 @@ pub async fn create_async_socket(config: &NetworkConfig) -> Result<TokioUdpSocke
         .map_err(|e| NetworkError::BindFailed(e.to_string()))
 }
 
+/// Destination address usable from a socket bound to `local`
+/// (IPv4 targets are v4-mapped on IPv6 sockets)
+pub fn target_for_socket(local: SocketAddr, target: SocketAddr) -> SocketAddr {
+    match (local, target.ip()) {
+        (SocketAddr::V6(_), IpAddr::V4(v4)) => SocketAddr::new(IpAddr::V6(v4.to_ipv6_mapped()), target.port()),
+        _ => target,
+    }
+}
+
+/// Canonical form of a peer address (v4-mapped IPv6 back to IPv4)
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
 /// Configure socket options for low-latency audio
-fn configure_socket(socket: &Socket, config: &NetworkConfig) -> Result<(), NetworkError> {
+fn configure_socket(socket: &Socket, config: &NetworkConfig, ipv4: bool) -> Result<(), NetworkError> {
     // Allow address reuse
     if config.reuse_addr {
         socket.set_reuse_address(true)
@@ -61,36 +96,87 @@ fn configure_socket(socket: &Socket, config: &NetworkConfig) -> Result<(), Netwo
     socket.set_recv_buffer_size(config.recv_buffer_size)
         .map_err(|e| NetworkError::BindFailed(format!("Failed to set recv buffer: {}", e)))?;
     
-    // Enable broadcast (useful for local network discovery and fallback)
-    socket.set_broadcast(true)
-        .map_err(|e| NetworkError::BindFailed(format!("Failed to set broadcast: {}", e)))?;
+    // Enable broadcast (useful for local network discovery and fallback; IPv4 only)
+    if ipv4 {
+        socket.set_broadcast(true)
+            .map_err(|e| NetworkError::BindFailed(format!("Failed to set broadcast: {}", e)))?;
+    }
     
-    // Platform-specific optimizations
-    #[cfg(target_os = "linux")]
-    {
-        configure_linux_socket(socket)?;
+    // Control packets go out as voice; audio is re-marked per track
+    if config.qos.dscp {
+        if let Err(e) = set_dscp(socket, TrackPriority::High.dscp(), ipv4) {
+            tracing::warn!("Failed to set DSCP marking: {}", e);
+        }
     }
     
     Ok(())
 }
 
-#[cfg(target_os = "linux")]
-fn configure_linux_socket(_socket: &Socket) -> Result<(), NetworkError> {
-    // Note: On Linux, setting IP_TOS and SO_BUSY_POLL would require libc
-    // These optimizations are available but require adding libc dependency
-    // For now, the socket2 configuration handles the most important settings
+/// Join the configured multicast group and set the multicast TTL
+fn join_multicast(socket: &Socket, config: &NetworkConfig, local: SocketAddr) -> Result<(), NetworkError> {
+    let Some(ref configured) = config.multicast_group else {
+        return Ok(());
+    };
+    let group = config.multicast_group_addr()
+        .ok_or_else(|| NetworkError::BindFailed(format!("Invalid multicast group: {}", configured)))?;
+    let failed = |e: io::Error| NetworkError::BindFailed(format!("Failed to join multicast group {}: {}", group, e));
     
-    // If libc is added to Cargo.toml, the following could be enabled:
-    // - IP_TOS for DSCP marking (QoS)
-    // - SO_BUSY_POLL for reduced latency polling
+    match (group, local.ip()) {
+        (IpAddr::V4(group), local_ip) => {
+            // A specific IPv4 bind address selects the interface to join on
+            let interface = match local_ip {
+                IpAddr::V4(ip) => ip,
+                IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+            };
+            socket.set_multicast_ttl_v4(config.multicast_ttl).map_err(failed)?;
+            socket.join_multicast_v4(&group, &interface).map_err(failed)?;
+        }
+        (IpAddr::V6(group), IpAddr::V6(_)) => {
+            socket.set_multicast_hops_v6(config.multicast_ttl).map_err(failed)?;
+            socket.join_multicast_v6(&group, 0).map_err(failed)?;
+        }
+        (IpAddr::V6(group), IpAddr::V4(_)) => {
+            return Err(NetworkError::BindFailed(format!(
+                "IPv6 multicast group {} needs an IPv6 bind address", group
+            )));
+        }
+    }
     
+    tracing::info!("Joined multicast group {} (TTL {})", group, config.multicast_ttl);
     Ok(())
 }
 
+/// Mark datagrams sent from `socket` with a DSCP class
+/// (Linux only; on Windows qWave tags the flows, see `network::qos`)
+pub fn set_dscp(socket: &Socket, dscp: u8, ipv4: bool) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        // DSCP is the upper six bits of the TOS / traffic class byte
+        let tos = u32::from(dscp) << 2;
+        if ipv4 {
+            socket.set_tos(tos)
+        } else {
+            socket.set_tclass_v6(tos)?;
+            // IPv4 peers of a dual-stack socket get IP_TOS
+            let _ = socket.set_tos(tos);
+            Ok(())
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (socket, dscp, ipv4);
+        Ok(())
+    }
+}
+
 /// High-performance packet sender
 pub struct PacketSender {
-    socket: StdUdpSocket,
+    transport: Box<dyn Transport>,
     target: SocketAddr,
+    /// Mark datagrams per track priority
+    dscp_marking: bool,
+    /// DSCP class the socket marks datagrams with now
+    dscp: Option<u8>,
     packets_sent: std::sync::atomic::AtomicU64,
     bytes_sent: std::sync::atomic::AtomicU64,
 }
@@ -98,16 +184,46 @@ pub struct PacketSender {
 impl PacketSender {
     pub fn new(socket: StdUdpSocket, target: SocketAddr) -> Self {
         Self {
-            socket,
+            transport: Box::new(socket),
             target,
+            dscp_marking: false,
+            dscp: None,
             packets_sent: std::sync::atomic::AtomicU64::new(0),
             bytes_sent: std::sync::atomic::AtomicU64::new(0),
         }
     }
     
+    /// Enable `set_dscp` (`QosConfig::dscp`)
+    pub fn with_dscp_marking(mut self, enabled: bool) -> Self {
+        self.dscp_marking = enabled;
+        self
+    }
+    
+    /// Mark the following datagrams with a DSCP class
+    /// (the socket option only changes with the class; no-op over TCP)
+    pub fn set_dscp(&mut self, dscp: u8) {
+        if !self.dscp_marking || self.dscp == Some(dscp) {
+            return;
+        }
+        let Some(socket) = self.transport.udp_socket() else {
+            return;
+        };
+        let ipv4 = socket.local_addr().is_ok_and(|local| local.is_ipv4());
+        if let Err(e) = set_dscp(&SockRef::from(socket), dscp, ipv4) {
+            tracing::debug!("Failed to set DSCP {}: {}", dscp, e);
+        }
+        self.dscp = Some(dscp);
+    }
+    
+    /// DSCP class of the latest `set_dscp`
+    pub fn dscp(&self) -> Option<u8> {
+        self.dscp
+    }
+    
     /// Send packet to target
     pub fn send(&self, data: &[u8]) -> io::Result<usize> {
-        let sent = self.socket.send_to(data, self.target)?;
+        pcap::record_on(Direction::Sent, self.transport.as_ref(), self.target, data);
+        let sent = self.transport.send_to(data, self.target)?;
         self.packets_sent.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         self.bytes_sent.fetch_add(sent as u64, std::sync::atomic::Ordering::Relaxed);
         Ok(sent)
@@ -123,10 +239,48 @@ impl PacketSender {
         self.bytes_sent.load(std::sync::atomic::Ordering::Relaxed)
     }
     
+    /// Sending socket (None over a stream transport)
+    pub fn socket(&self) -> Option<&StdUdpSocket> {
+        self.transport.udp_socket()
+    }
+    
+    /// Largest packet the transport carries now, if smaller than a UDP datagram
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.transport.max_datagram_size()
+    }
+    
+    /// Send over another transport from now on (e.g. the TCP fallback)
+    pub fn set_transport(&mut self, transport: Box<dyn Transport>) {
+        self.transport = transport;
+    }
+    
+    /// Target address
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+    
     /// Update target address
     pub fn set_target(&mut self, target: SocketAddr) {
         self.target = target;
     }
+    
+    /// Send a control datagram to an arbitrary address (not counted as audio)
+    pub fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let addr = match self.transport.local_addr() {
+            Ok(local) => target_for_socket(local, addr),
+            Err(_) => addr,
+        };
+        pcap::record_on(Direction::Sent, self.transport.as_ref(), addr, data);
+        self.transport.send_to(data, addr)
+    }
+    
+    /// Receive a datagram arriving on the sending socket (non-blocking)
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, addr) = self.transport.recv_from(buf)?;
+        let addr = canonical_addr(addr);
+        pcap::record_on(Direction::Received, self.transport.as_ref(), addr, &buf[..size]);
+        Ok((size, addr))
+    }
 }
 
 /// High-performance packet receiver
@@ -206,4 +360,128 @@ mod tests {
         let socket = create_socket(&config);
         assert!(socket.is_ok());
     }
+    
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dscp_marking() {
+        let config = NetworkConfig {
+            bind_address: "127.0.0.1".to_string(),
+            udp_port: 0,
+            ..Default::default()
+        };
+        let socket = create_socket(&config).unwrap();
+        let target = socket.local_addr().unwrap();
+        // Expedited Forwarding until audio goes out
+        assert_eq!(SockRef::from(&socket).tos().unwrap(), 0xB8);
+        
+        let mut sender = PacketSender::new(socket, target).with_dscp_marking(true);
+        sender.set_dscp(TrackPriority::Normal.dscp());
+        assert_eq!(sender.dscp(), Some(34));
+        assert_eq!(SockRef::from(sender.socket().unwrap()).tos().unwrap(), 34 << 2);
+        
+        let mut sender = sender.with_dscp_marking(false);
+        sender.set_dscp(TrackPriority::Low.dscp());
+        assert_eq!(sender.dscp(), Some(34));
+    }
+    
+    #[test]
+    fn test_address_parsing() {
+        let config = NetworkConfig {
+            bind_address: "::".to_string(),
+            remote_address: Some("[fd00::2]".to_string()),
+            udp_port: 5000,
+            ..Default::default()
+        };
+        assert_eq!(config.bind_socket_addr(), Some("[::]:5000".parse().unwrap()));
+        assert_eq!(config.remote_socket_addr(), Some("[fd00::2]:5000".parse().unwrap()));
+        
+        let config = NetworkConfig {
+            remote_address: Some("192.168.1.10:6000".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.remote_socket_addr(), Some("192.168.1.10:6000".parse().unwrap()));
+        
+        let config = NetworkConfig {
+            bind_address: "not an ip".to_string(),
+            ..Default::default()
+        };
+        assert!(create_socket(&config).is_err());
+    }
+    
+    #[test]
+    fn test_dual_stack_mapping() {
+        let v6_local: SocketAddr = "[::]:5000".parse().unwrap();
+        let v4_local: SocketAddr = "0.0.0.0:5000".parse().unwrap();
+        let v4_target: SocketAddr = "192.168.1.10:5000".parse().unwrap();
+        
+        let mapped = target_for_socket(v6_local, v4_target);
+        assert_eq!(mapped, "[::ffff:192.168.1.10]:5000".parse().unwrap());
+        assert_eq!(target_for_socket(v4_local, v4_target), v4_target);
+        assert_eq!(canonical_addr(mapped), v4_target);
+    }
+    
+    #[test]
+    fn test_dual_stack_loopback() {
+        let config = NetworkConfig {
+            bind_address: "::".to_string(),
+            udp_port: 0,
+            ..Default::default()
+        };
+        // IPv6 may be disabled on the host
+        let Ok(socket) = create_socket(&config) else {
+            return;
+        };
+        let port = socket.local_addr().unwrap().port();
+        
+        let client = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"ping", ("127.0.0.1", port)).unwrap();
+        
+        let mut buf = [0u8; 16];
+        for _ in 0..100 {
+            if let Ok((size, addr)) = socket.recv_from(&mut buf) {
+                assert_eq!(&buf[..size], b"ping");
+                assert_eq!(canonical_addr(addr), client.local_addr().unwrap());
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("no datagram received on dual-stack socket");
+    }
+    
+    #[test]
+    fn test_multicast_group() {
+        let config = NetworkConfig {
+            multicast_group: Some("239.255.77.1".to_string()),
+            udp_port: 0,
+            ..Default::default()
+        };
+        assert_eq!(config.multicast_socket_addr(), Some("239.255.77.1:0".parse().unwrap()));
+        
+        let unicast = NetworkConfig {
+            multicast_group: Some("192.168.1.10".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(unicast.multicast_group_addr(), None);
+        assert!(create_socket(&unicast).is_err());
+        
+        // Hosts without a multicast route can't join
+        let Ok(socket) = create_socket(&config) else {
+            return;
+        };
+        let port = socket.local_addr().unwrap().port();
+        let client = StdUdpSocket::bind("0.0.0.0:0").unwrap();
+        if client.send_to(b"group", ("239.255.77.1", port)).is_err() {
+            return;
+        }
+        
+        let mut buf = [0u8; 16];
+        for _ in 0..100 {
+            if let Ok((size, _)) = socket.recv_from(&mut buf) {
+                assert_eq!(&buf[..size], b"group");
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("no datagram received from the multicast group");
+    }
 }
@@ -0,0 +1,238 @@
+//! Per-track pipeline profiling (`profiling` feature)
+//!
+//! [`stage`] returns a guard that adds the time until it drops, and the
+//! heap allocations its thread made meanwhile, to the track's counters
+//! for that pipeline stage. Allocations are counted by a wrapper around
+//! the system allocator that the feature installs as the global
+//! allocator. Without the feature the guard is empty and every hook
+//! compiles away.
+//!
+//! The summary is served at `GET /api/profile` (`DELETE` resets it).
+
+use serde::{Deserialize, Serialize};
+
+/// Pipeline stage of a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Captured frame processing (level, gain, probe)
+    Capture,
+    /// Opus encoding
+    Encode,
+    /// Handing the packet to the network sender
+    Send,
+    /// Opus decoding of a received packet
+    Decode,
+    /// Jitter buffer, concealment and output
+    Playout,
+}
+
+/// Counters of one stage of one track
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageSummary {
+    pub track_id: u8,
+    pub stage: Stage,
+    pub calls: u64,
+    pub total_us: u64,
+    pub mean_us: f32,
+    pub max_us: u64,
+    /// Heap allocations made on the stage's thread inside the stage
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+}
+
+/// Whether the profiler was compiled in
+pub fn is_enabled() -> bool {
+    cfg!(feature = "profiling")
+}
+
+/// Measures a stage until dropped
+#[must_use = "the stage is measured until the guard drops"]
+pub struct StageGuard {
+    #[cfg(feature = "profiling")]
+    active: imp::ActiveStage,
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "profiling")]
+        self.active.finish();
+    }
+}
+
+/// Start measuring `stage` of a track
+#[inline]
+pub fn stage(track_id: u8, stage: Stage) -> StageGuard {
+    #[cfg(not(feature = "profiling"))]
+    let _ = (track_id, stage);
+    StageGuard {
+        #[cfg(feature = "profiling")]
+        active: imp::ActiveStage::start(track_id, stage),
+    }
+}
+
+/// Counters of every measured stage, by track and stage
+/// (empty without the feature)
+pub fn summary() -> Vec<StageSummary> {
+    #[cfg(feature = "profiling")]
+    return imp::summary();
+    #[cfg(not(feature = "profiling"))]
+    Vec::new()
+}
+
+/// Clear all counters
+pub fn reset() {
+    #[cfg(feature = "profiling")]
+    imp::reset();
+}
+
+#[cfg(feature = "profiling")]
+mod imp {
+    use dashmap::DashMap;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    use super::{Stage, StageSummary};
+
+    thread_local! {
+        /// Allocations and allocated bytes of this thread
+        static THREAD_ALLOCS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+    }
+
+    /// System allocator that counts allocations per thread
+    pub struct CountingAllocator;
+
+    fn count(size: usize) {
+        // Fails only while the thread is being torn down
+        let _ = THREAD_ALLOCS.try_with(|allocs| {
+            let (count, bytes) = allocs.get();
+            allocs.set((count + 1, bytes + size as u64));
+        });
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    #[derive(Default)]
+    struct Counters {
+        calls: AtomicU64,
+        total_ns: AtomicU64,
+        max_ns: AtomicU64,
+        allocations: AtomicU64,
+        allocated_bytes: AtomicU64,
+    }
+
+    fn counters() -> &'static DashMap<(u8, Stage), Counters> {
+        static COUNTERS: OnceLock<DashMap<(u8, Stage), Counters>> = OnceLock::new();
+        COUNTERS.get_or_init(DashMap::new)
+    }
+
+    fn thread_allocs() -> (u64, u64) {
+        THREAD_ALLOCS.try_with(Cell::get).unwrap_or_default()
+    }
+
+    pub struct ActiveStage {
+        track_id: u8,
+        stage: Stage,
+        started: Instant,
+        allocs: (u64, u64),
+    }
+
+    impl ActiveStage {
+        pub fn start(track_id: u8, stage: Stage) -> Self {
+            Self {
+                track_id,
+                stage,
+                started: Instant::now(),
+                allocs: thread_allocs(),
+            }
+        }
+
+        pub fn finish(&self) {
+            let elapsed_ns = self.started.elapsed().as_nanos() as u64;
+            let (count, bytes) = thread_allocs();
+            let entry = counters().entry((self.track_id, self.stage)).or_default();
+            entry.calls.fetch_add(1, Ordering::Relaxed);
+            entry.total_ns.fetch_add(elapsed_ns, Ordering::Relaxed);
+            entry.max_ns.fetch_max(elapsed_ns, Ordering::Relaxed);
+            entry.allocations.fetch_add(count - self.allocs.0, Ordering::Relaxed);
+            entry.allocated_bytes.fetch_add(bytes - self.allocs.1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn summary() -> Vec<StageSummary> {
+        let mut summary: Vec<StageSummary> = counters()
+            .iter()
+            .map(|entry| {
+                let (track_id, stage) = *entry.key();
+                let calls = entry.calls.load(Ordering::Relaxed);
+                let total_ns = entry.total_ns.load(Ordering::Relaxed);
+                StageSummary {
+                    track_id,
+                    stage,
+                    calls,
+                    total_us: total_ns / 1000,
+                    mean_us: if calls > 0 { total_ns as f32 / calls as f32 / 1000.0 } else { 0.0 },
+                    max_us: entry.max_ns.load(Ordering::Relaxed) / 1000,
+                    allocations: entry.allocations.load(Ordering::Relaxed),
+                    allocated_bytes: entry.allocated_bytes.load(Ordering::Relaxed),
+                }
+            })
+            .collect();
+        summary.sort_unstable_by_key(|s| (s.track_id, s.stage));
+        summary
+    }
+
+    pub fn reset() {
+        counters().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_counters() {
+        // Track ID no other test uses
+        {
+            let _stage = stage(250, Stage::Encode);
+            std::hint::black_box(vec![0u8; 4096]);
+        }
+        drop(stage(250, Stage::Encode));
+
+        let encode = summary().into_iter().find(|s| s.track_id == 250 && s.stage == Stage::Encode);
+        if is_enabled() {
+            let encode = encode.unwrap();
+            assert_eq!(encode.calls, 2);
+            assert!(encode.allocations >= 1);
+            assert!(encode.allocated_bytes >= 4096);
+        } else {
+            assert!(encode.is_none());
+        }
+    }
+}
@@ -9,19 +9,48 @@
 //! │ Magic(2) │TrackID(1)│ Flags(1) │  Seq(4)  │      Timestamp(8)          │
 //! │  0xAF01  │   0-255  │ See below│ u32 LE   │      u64 LE (µs)           │
 //! ├──────────┴──────────┴──────────┴──────────┴────────────────────────────┤
-//! │                        Opus Payload (variable)                         │
+//! │                      Encoded Payload (variable)                        │
 //! │                        Max: 1456 bytes                                 │
 //! └────────────────────────────────────────────────────────────────────────┘
 //!
 //! Flags byte:
 //! ┌─────┬─────┬─────┬─────┬─────┬─────┬─────┬─────┐
 //! │  7  │  6  │  5  │  4  │  3  │  2  │  1  │  0  │
-//! │ RSV │ RSV │ RSV │ RSV │ RSV │ FEC │STEREO│KEYF│
+//! │   CODEC   │ FRG │ PRB │ ENC │ FEC │STEREO│KEYF│
 //! └─────┴─────┴─────┴─────┴─────┴─────┴─────┴─────┘
+//!
+//! Fragment (FRG set) – extended header before the payload chunk:
+//! ┌──────────┬──────────┬─────────────────────────────────────────────────┐
+//! │ Index(1) │ Count(1) │ Payload chunk (max 1454 bytes)                  │
+//! └──────────┴──────────┴─────────────────────────────────────────────────┘
 //! ```
+//!
+//! ENC marks a payload sealed with the pre-shared key (see
+//! `network::crypto`).
+//!
+//! PRB marks a latency probe: the frame starts with the probe chirp and
+//! its timestamp is the time it was sent (see `audio::probe`).
+//!
+//! KEYF marks a stream restart: the sender (re)created its encoder or
+//! reset the sequence, so receivers reset decoder and jitter buffer state
+//! starting exactly at this packet.
+//!
+//! CODEC is the id of the codec the payload is encoded with
+//! ([`Codec::id`]): 0 for Opus (what older versions send), 1 for FLAC
+//! frames (24-bit, no stream header, see `codec::flac`); 2 and 3 are free
+//! for future codecs, receivers drop packets with an id they don't know.
+//!
+//! FRG marks one fragment of a frame too large for a single datagram
+//! (high-bitrate stereo, PCM). Every fragment repeats the header of the
+//! frame, so the receiver reassembles them by track, sequence and
+//! timestamp before decrypting (see [`AudioPacket::fragment`]).
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::config::{OpusBandwidth, OpusBitrateMode, OpusSignal};
+
+use crate::logs::LogRecord;
 
 /// Magic number for packet identification
 pub const PACKET_MAGIC: u16 = 0xAF01;
@@ -32,14 +61,30 @@ pub const MAX_PAYLOAD_SIZE: usize = 1456;
 /// Header size in bytes
 pub const HEADER_SIZE: usize = 16;
 
+/// Extended header of a fragment (index, count)
+pub const FRAGMENT_HEADER_SIZE: usize = 2;
+
+/// Most fragments a frame can be split into
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+
 /// Packet flags
 #[derive(Debug, Clone, Copy, Default)]
 pub struct PacketFlags(u8);
 
 impl PacketFlags {
+    /// Stream restart marker (encoder reset / config change)
     pub const KEYFRAME: u8 = 0x01;
     pub const STEREO: u8 = 0x02;
     pub const FEC: u8 = 0x04;
+    /// Payload encrypted with the pre-shared key
+    pub const ENCRYPTED: u8 = 0x08;
+    /// Frame carries the latency probe marker
+    pub const PROBE: u8 = 0x10;
+    /// One fragment of a frame larger than a datagram
+    pub const FRAGMENT: u8 = 0x20;
+    /// Codec id of the payload (two bits)
+    pub const CODEC_MASK: u8 = 0xC0;
+    const CODEC_SHIFT: u8 = 6;
     
     pub fn new() -> Self {
         Self(0)
@@ -72,6 +117,38 @@ impl PacketFlags {
         self
     }
     
+    pub fn set_encrypted(mut self, value: bool) -> Self {
+        if value {
+            self.0 |= Self::ENCRYPTED;
+        } else {
+            self.0 &= !Self::ENCRYPTED;
+        }
+        self
+    }
+    
+    pub fn set_probe(mut self, value: bool) -> Self {
+        if value {
+            self.0 |= Self::PROBE;
+        } else {
+            self.0 &= !Self::PROBE;
+        }
+        self
+    }
+    
+    pub fn set_fragment(mut self, value: bool) -> Self {
+        if value {
+            self.0 |= Self::FRAGMENT;
+        } else {
+            self.0 &= !Self::FRAGMENT;
+        }
+        self
+    }
+    
+    pub fn set_codec(mut self, codec: Codec) -> Self {
+        self.0 = (self.0 & !Self::CODEC_MASK) | (codec.id() << Self::CODEC_SHIFT);
+        self
+    }
+    
     pub fn is_keyframe(&self) -> bool {
         self.0 & Self::KEYFRAME != 0
     }
@@ -84,6 +161,23 @@ impl PacketFlags {
         self.0 & Self::FEC != 0
     }
     
+    pub fn is_encrypted(&self) -> bool {
+        self.0 & Self::ENCRYPTED != 0
+    }
+    
+    pub fn is_probe(&self) -> bool {
+        self.0 & Self::PROBE != 0
+    }
+    
+    pub fn is_fragment(&self) -> bool {
+        self.0 & Self::FRAGMENT != 0
+    }
+    
+    /// Codec of the payload (None for an id this version doesn't know)
+    pub fn codec(&self) -> Option<Codec> {
+        Codec::from_id((self.0 & Self::CODEC_MASK) >> Self::CODEC_SHIFT)
+    }
+    
     pub fn as_byte(&self) -> u8 {
         self.0
     }
@@ -126,7 +220,14 @@ impl AudioPacket {
     
     /// Serialize packet to bytes for network transmission
     pub fn serialize(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(HEADER_SIZE + self.payload.len());
+        self.serialize_into(&mut BytesMut::with_capacity(HEADER_SIZE + self.payload.len()))
+    }
+    
+    /// Serialize packet into an arena, splitting it off (the arena's
+    /// allocation is reused once the returned datagram is dropped)
+    pub fn serialize_into(&self, buf: &mut BytesMut) -> Bytes {
+        buf.clear();
+        buf.reserve(HEADER_SIZE + self.payload.len());
         
         // Magic number
         buf.put_u16_le(PACKET_MAGIC);
@@ -141,7 +242,7 @@ impl AudioPacket {
         // Payload
         buf.put_slice(&self.payload);
         
-        buf.freeze()
+        buf.split().freeze()
     }
     
     /// Deserialize packet from bytes
@@ -175,6 +276,50 @@ impl AudioPacket {
     pub fn total_size(&self) -> usize {
         HEADER_SIZE + self.payload.len()
     }
+    
+    /// Split into packets whose payload fits `max_payload` bytes
+    ///
+    /// A packet that fits is returned as is. Otherwise every fragment has
+    /// the FRAGMENT flag and starts its payload with the fragment index and
+    /// count; the receiver puts them back together with
+    /// `network::receiver::FragmentAssembler`.
+    pub fn fragment(&self, max_payload: usize) -> Result<Vec<AudioPacket>, crate::error::NetworkError> {
+        if self.payload.len() <= max_payload {
+            return Ok(vec![self.clone()]);
+        }
+        
+        let chunk_size = max_payload.saturating_sub(FRAGMENT_HEADER_SIZE).max(1);
+        let count = self.payload.len().div_ceil(chunk_size);
+        if count > MAX_FRAGMENTS {
+            return Err(crate::error::NetworkError::PacketTooLarge(self.total_size()));
+        }
+        
+        Ok(self
+            .payload
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut payload = BytesMut::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+                payload.put_u8(index as u8);
+                payload.put_u8(count as u8);
+                payload.put_slice(chunk);
+                AudioPacket {
+                    flags: self.flags.set_fragment(true),
+                    payload: payload.freeze(),
+                    ..*self
+                }
+            })
+            .collect())
+    }
+    
+    /// Index, count and payload chunk of a fragment (None if malformed)
+    pub fn fragment_info(&self) -> Option<(u8, u8, Bytes)> {
+        if !self.flags.is_fragment() || self.payload.len() < FRAGMENT_HEADER_SIZE {
+            return None;
+        }
+        let (index, count) = (self.payload[0], self.payload[1]);
+        (index < count).then(|| (index, count, self.payload.slice(FRAGMENT_HEADER_SIZE..)))
+    }
 }
 
 /// Response for device list with receiver/sender flag
@@ -184,6 +329,208 @@ pub struct DevicesResponse {
     pub is_receiver: bool,
 }
 
+/// Destinations of an input track in the routing matrix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackRoute {
+    pub track_id: u8,
+    /// Peer keys ("ip:audio_port") the track is sent to
+    pub destinations: Vec<String>,
+}
+
+/// Mixer settings for audio received from one peer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerMix {
+    /// Peer IP address
+    pub peer: String,
+    /// Linear gain (1.0 = unity)
+    pub gain: f32,
+    pub muted: bool,
+}
+
+/// Master processing of one output device, applied to the mix of all
+/// tracks playing there (EQ, then delay, compressor and limiter)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputDsp {
+    /// Filters applied in order
+    pub eq: Vec<EqBand>,
+    /// Delay of the whole output in ms (aligns a speaker with the others)
+    pub delay_ms: f32,
+    /// Compressor evening out the mix before the limiter
+    pub compressor: Option<CompressorSettings>,
+    /// Peak limiter at the end of the chain
+    pub limiter: Option<LimiterSettings>,
+}
+
+impl OutputDsp {
+    /// Whether the settings leave the audio untouched
+    pub fn is_bypass(&self) -> bool {
+        self.eq.is_empty() && self.delay_ms == 0.0 && self.compressor.is_none() && self.limiter.is_none()
+    }
+}
+
+/// Filter type of an EQ band
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EqBandKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+/// One EQ filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: EqBandKind,
+    /// Centre or corner frequency in Hz
+    pub frequency_hz: f32,
+    /// Boost or cut in dB (peaking and shelf bands)
+    #[serde(default)]
+    pub gain_db: f32,
+    /// Bandwidth (resonance for low/high pass)
+    #[serde(default = "EqBand::default_q")]
+    pub q: f32,
+}
+
+impl EqBand {
+    fn default_q() -> f32 {
+        std::f32::consts::FRAC_1_SQRT_2
+    }
+}
+
+/// Output peak limiter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LimiterSettings {
+    /// Highest output peak in dBFS
+    pub ceiling_db: f32,
+    /// Time to recover from gain reduction
+    #[serde(default = "LimiterSettings::default_release_ms")]
+    pub release_ms: f32,
+}
+
+impl LimiterSettings {
+    fn default_release_ms() -> f32 {
+        100.0
+    }
+}
+
+/// Output compressor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompressorSettings {
+    /// Peak level above which the gain is reduced (dBFS)
+    pub threshold_db: f32,
+    /// Input dB above the threshold per output dB
+    pub ratio: f32,
+    /// Time to reach the gain reduction of a louder peak
+    #[serde(default = "CompressorSettings::default_attack_ms")]
+    pub attack_ms: f32,
+    /// Time to recover from gain reduction
+    #[serde(default = "CompressorSettings::default_release_ms")]
+    pub release_ms: f32,
+    /// Gain after compression in dB
+    #[serde(default)]
+    pub makeup_db: f32,
+}
+
+impl CompressorSettings {
+    fn default_attack_ms() -> f32 {
+        10.0
+    }
+
+    fn default_release_ms() -> f32 {
+        200.0
+    }
+}
+
+/// File format of a recording
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// 24-bit PCM
+    #[default]
+    Wav,
+    /// 24-bit lossless, about half the size
+    Flac,
+}
+
+/// What a recording writes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingMode {
+    /// One file per received track
+    #[default]
+    PerTrack,
+    /// One stereo file with all tracks summed
+    Mixed,
+    /// Per-track files and the mix
+    Both,
+}
+
+/// Recording to start
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingRequest {
+    pub format: RecordingFormat,
+    pub mode: RecordingMode,
+    /// Tracks to record (None = all received tracks)
+    pub tracks: Option<Vec<u8>>,
+    /// Start a new file once one reaches this size
+    pub max_file_mb: Option<u64>,
+    /// Start a new file once one is this long
+    pub max_file_secs: Option<u64>,
+}
+
+/// One file written by a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFile {
+    pub path: String,
+    /// Track recorded (None = the mix)
+    pub track_id: Option<u8>,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    /// Closed (by stop or rotation)
+    pub finished: bool,
+}
+
+/// State of the recorder
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecordingStatus {
+    pub active: bool,
+    /// Settings of the current or last recording
+    pub request: Option<RecordingRequest>,
+    /// Files of the current or last recording, oldest first
+    pub files: Vec<RecordedFile>,
+    /// Audio frames lost because the disk fell behind
+    pub dropped_frames: u64,
+    /// Write error that ended the recording
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Transport command of a track playing a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlayerCommand {
+    Play,
+    Pause,
+    /// Jump to a position from the start of the file
+    Seek { position_ms: u64 },
+    /// Start over at the end of the file instead of stopping
+    SetLoop { enabled: bool },
+}
+
+/// Playback state of a track playing a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilePlayerStatus {
+    pub path: String,
+    pub playing: bool,
+    pub looping: bool,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+}
+
 /// Control message types for WebSocket communication
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
@@ -191,6 +538,9 @@ pub enum ControlMessage {
     /// Create a new track
     CreateTrack(TrackConfig),
     
+    /// ID of a track created by `CreateTrack`
+    TrackCreated { track_id: u8 },
+    
     /// Remove an existing track
     RemoveTrack { track_id: u8 },
     
@@ -203,18 +553,90 @@ pub enum ControlMessage {
     /// Solo a track
     SetSolo { track_id: u8, solo: bool },
     
+    /// Press/release the talkback button
+    SetTalkback { active: bool },
+    
+    /// Restrict an input track to a set of peers (None = all peers)
+    SetRoute { track_id: u8, destinations: Option<Vec<String>> },
+    
+    /// Get the routing matrix
+    GetRouting,
+    
+    /// Routing matrix response
+    Routing(Vec<TrackRoute>),
+    
+    /// Change volume/mute of audio received from a peer (key or IP)
+    SetPeerMix { peer: String, gain: Option<f32>, muted: Option<bool> },
+    
+    /// Get per-peer mixer settings
+    GetPeerMixer,
+    
+    /// Per-peer mixer response
+    PeerMixer(Vec<PeerMix>),
+    
+    /// Get known peers
+    ListPeers,
+    
+    /// Known peers response
+    Peers(Vec<PeerStatus>),
+    
+    /// Start sending to a peer (key, or the audio address of a new peer)
+    ConnectPeer { peer: String },
+    
+    /// Stop sending to a peer (it stays in the list)
+    DisconnectPeer { peer: String },
+    
+    /// Change the display name of a peer
+    RenamePeer { peer: String, name: String },
+    
+    /// Get what the receivers of our tracks support
+    GetCapabilities,
+    
+    /// Receiver capabilities response
+    Capabilities(RemoteCapabilities),
+    
     /// Get track status
     GetStatus,
     
     /// Status response
     Status(Vec<TrackStatus>),
     
+    /// Level meters of all tracks, pushed more often than the status
+    Levels(Vec<TrackLevel>),
+    
     /// List available audio devices
     ListDevices,
     
     /// Device list response
     Devices(DevicesResponse),
     
+    /// Start recording received audio
+    StartRecording(RecordingRequest),
+    
+    /// Stop recording and close the files
+    StopRecording,
+    
+    /// Get the recorder state
+    GetRecording,
+    
+    /// Recorder state response
+    Recording(RecordingStatus),
+    
+    /// Get the log records after a `seq` (None = all kept)
+    GetLogs {
+        #[serde(default)]
+        since: Option<u64>,
+    },
+    
+    /// Log records, oldest first (also pushed as they come)
+    Logs(Vec<LogRecord>),
+    
+    /// Play, pause, seek or loop a track playing a file
+    FilePlayer { track_id: u8, command: PlayerCommand },
+    
+    /// File playback state response
+    Player { track_id: u8, status: FilePlayerStatus },
+    
     /// Error response
     Error { message: String },
     
@@ -225,6 +647,28 @@ pub enum ControlMessage {
     Pong,
 }
 
+impl ControlMessage {
+    /// Whether this is a response or push rather than a request
+    pub fn is_reply(&self) -> bool {
+        matches!(
+            self,
+            Self::TrackCreated { .. }
+                | Self::Routing(_)
+                | Self::PeerMixer(_)
+                | Self::Peers(_)
+                | Self::Capabilities(_)
+                | Self::Status(_)
+                | Self::Levels(_)
+                | Self::Devices(_)
+                | Self::Recording(_)
+                | Self::Logs(_)
+                | Self::Player { .. }
+                | Self::Error { .. }
+                | Self::Pong
+        )
+    }
+}
+
 /// Track configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrackConfig {
@@ -237,20 +681,172 @@ pub struct TrackConfig {
     /// Audio device identifier
     pub device_id: String,
     
+    /// Name of the device (or its beginning): selects the device when
+    /// `device_id` is empty or no longer exists, e.g. after a driver
+    /// reinstall (an output track gives `device_id = "output:"`); filled
+    /// in from `device_id` when the track is created
+    #[serde(default)]
+    pub device_name: Option<String>,
+    
+    /// Stable hash of the normalized device name, the last fallback when
+    /// the name has changed too much to match (see `tracks::device_match`)
+    #[serde(default)]
+    pub device_key: Option<String>,
+    
     /// Target bitrate in bits per second
     pub bitrate: u32,
     
     /// Frame size in milliseconds (2.5, 5, 10, 20)
     pub frame_size_ms: f32,
     
-    /// Number of channels (1 or 2)
+    /// Number of channels (1 or 2; 3 to 8 for a surround track sent with
+    /// Opus multistream to receivers playing that many)
     pub channels: u16,
     
     /// Track type (affects Opus tuning)
     pub track_type: TrackType,
     
+    /// Codec the track is sent with
+    #[serde(default)]
+    pub codec: Codec,
+    
+    /// Opus encoder complexity 0-10: higher sounds better for more CPU
+    /// (None = the preset of the track)
+    #[serde(default)]
+    pub complexity: Option<u8>,
+    
+    /// Opus constant, variable or constrained variable bitrate (None = preset)
+    #[serde(default)]
+    pub bitrate_mode: Option<OpusBitrateMode>,
+    
+    /// Opus signal type hint (None = preset)
+    #[serde(default)]
+    pub signal: Option<OpusSignal>,
+    
+    /// Widest audio band Opus codes (None = preset)
+    #[serde(default)]
+    pub max_bandwidth: Option<OpusBandwidth>,
+    
     /// Enable FEC (Forward Error Correction)
     pub fec_enabled: bool,
+    
+    /// Talkback track: muted except while the talk button is held,
+    /// ducks the other outgoing tracks while transmitting
+    #[serde(default)]
+    pub talkback: bool,
+    
+    /// Source channel for each channel of the track: an input track takes
+    /// its encoded channels from these capture channels, an output track
+    /// feeds these decoded channels to the device (empty = automatic
+    /// up/down-mix, see `audio::convert`)
+    #[serde(default)]
+    pub channel_map: Vec<usize>,
+    
+    /// Opus deep redundancy (voice tracks only): recovers bursts of lost
+    /// packets, used where libopus supports it
+    #[serde(default)]
+    pub dred: bool,
+    
+    /// Also send the track over every other network path of a peer
+    /// (e.g. Wi-Fi next to Ethernet); the receiver keeps whichever copy
+    /// of a packet arrives first
+    #[serde(default)]
+    pub redundant: bool,
+    
+    /// Send without encryption even when a PSK is set, to receivers that
+    /// allow plaintext tracks (saves decryption CPU on low-power devices;
+    /// for non-sensitive audio such as a music bed)
+    #[serde(default)]
+    pub plaintext: bool,
+    
+    /// Send the track to this receiver ("IP" or "IP:port") instead of the
+    /// sender's target, e.g. the microphone to one PC and desktop audio
+    /// to another (None = the target)
+    #[serde(default)]
+    pub destination: Option<String>,
+    
+    /// Which tracks keep sending when the link is congested: low priority
+    /// tracks are cut and paused first, high priority tracks only lower
+    /// their bitrate; also selects the DSCP class of the datagrams
+    #[serde(default)]
+    pub priority: TrackPriority,
+    
+    /// Silence suppression: no frames are sent while the input stays
+    /// below `silence_threshold_db`, the receiver plays silence meanwhile
+    /// (see `audio::silence`; works with every codec)
+    #[serde(default)]
+    pub dtx: bool,
+    
+    /// Peak level below which captured audio counts as silence (dBFS)
+    #[serde(default = "TrackConfig::default_silence_threshold_db")]
+    pub silence_threshold_db: f32,
+    
+    /// Time below the threshold before sending stops
+    #[serde(default = "TrackConfig::default_silence_hold_ms")]
+    pub silence_hold_ms: u32,
+    
+    /// Corner frequency of the capture high-pass filter in Hz, 0 = off
+    /// (voice tracks only, see `audio::voice`)
+    #[serde(default)]
+    pub high_pass_hz: f32,
+    
+    /// Suppress steady background noise on capture (voice tracks only)
+    #[serde(default)]
+    pub noise_suppression: bool,
+    
+    /// Automatic gain control on capture (see `audio::agc`)
+    #[serde(default)]
+    pub agc: bool,
+    
+    /// Loudness the AGC aims for (RMS dBFS)
+    #[serde(default = "TrackConfig::default_agc_target_db")]
+    pub agc_target_db: f32,
+    
+    /// Time for the AGC gain to come down on loud input
+    #[serde(default = "TrackConfig::default_agc_attack_ms")]
+    pub agc_attack_ms: f32,
+    
+    /// Time for the AGC gain to rise on quiet input
+    #[serde(default = "TrackConfig::default_agc_release_ms")]
+    pub agc_release_ms: f32,
+    
+    /// Device buffer in frames the track's capture or playback stream
+    /// asks for (None = host default; a shared output stream takes the
+    /// buffer of the track that opened it). The achieved period and
+    /// latency are reported in `TrackStatus`
+    #[serde(default)]
+    pub buffer_frames: Option<u32>,
+    
+    /// Further devices a received track also plays on besides `device_id`,
+    /// at the track's gain and channel map (e.g. headphones and a virtual
+    /// cable for OBS at the same time)
+    #[serde(default)]
+    pub output_devices: Vec<String>,
+    
+    /// Linear gain of the track (1.0 = unity, up to `MAX_TRACK_VOLUME`):
+    /// scales what a sent track sends and a received track plays
+    #[serde(default = "TrackConfig::default_volume")]
+    pub volume: f32,
+    
+    /// How the jitter buffer of a received track sets its delay: `Auto`
+    /// follows the measured jitter between `jitter_min_ms` and
+    /// `jitter_max_ms`, `Manual` holds `jitter_target_ms`
+    #[serde(default)]
+    pub jitter_mode: JitterMode,
+    
+    /// Lowest delay of the jitter buffer in ms (None = 2 frames)
+    #[serde(default)]
+    pub jitter_min_ms: Option<u32>,
+    
+    /// Highest delay of the jitter buffer in ms (None = 16 frames, which
+    /// also caps the settings)
+    #[serde(default)]
+    pub jitter_max_ms: Option<u32>,
+    
+    /// Delay the jitter buffer holds in manual mode, in ms (None = the
+    /// lowest delay)
+    #[serde(default)]
+    pub jitter_target_ms: Option<u32>,
 }
 
 impl Default for TrackConfig {
@@ -259,13 +855,101 @@ impl Default for TrackConfig {
             track_id: None,
             name: String::from("New Track"),
             device_id: String::new(),
+            device_name: None,
+            device_key: None,
             bitrate: 128_000,
             frame_size_ms: 10.0,
             channels: 2,
             track_type: TrackType::Music,
+            codec: Codec::Opus,
+            complexity: None,
+            bitrate_mode: None,
+            signal: None,
+            max_bandwidth: None,
             fec_enabled: false,
+            talkback: false,
+            channel_map: Vec::new(),
+            dred: false,
+            redundant: false,
+            plaintext: false,
+            destination: None,
+            priority: TrackPriority::Normal,
+            dtx: false,
+            silence_threshold_db: Self::default_silence_threshold_db(),
+            silence_hold_ms: Self::default_silence_hold_ms(),
+            high_pass_hz: 0.0,
+            noise_suppression: false,
+            agc: false,
+            agc_target_db: Self::default_agc_target_db(),
+            agc_attack_ms: Self::default_agc_attack_ms(),
+            agc_release_ms: Self::default_agc_release_ms(),
+            buffer_frames: None,
+            output_devices: Vec::new(),
+            volume: Self::default_volume(),
+            jitter_mode: JitterMode::Auto,
+            jitter_min_ms: None,
+            jitter_max_ms: None,
+            jitter_target_ms: None,
+        }
+    }
+}
+
+impl TrackConfig {
+    fn default_silence_threshold_db() -> f32 {
+        -60.0
+    }
+    
+    fn default_silence_hold_ms() -> u32 {
+        300
+    }
+    
+    fn default_agc_target_db() -> f32 {
+        -20.0
+    }
+    
+    fn default_agc_attack_ms() -> f32 {
+        50.0
+    }
+    
+    fn default_agc_release_ms() -> f32 {
+        1000.0
+    }
+    
+    fn default_volume() -> f32 {
+        1.0
+    }
+    
+    /// Low-latency voice track for operator talkback on the given input device
+    pub fn talkback(device_id: impl Into<String>) -> Self {
+        Self {
+            name: String::from("Talkback"),
+            device_id: device_id.into(),
+            bitrate: 32_000,
+            frame_size_ms: 10.0,
+            track_type: TrackType::Voice,
+            fec_enabled: true,
+            talkback: true,
+            priority: TrackPriority::High,
+            ..Default::default()
+        }
+    }
+    
+    /// Priority the track is sent with (talkback always goes as voice)
+    pub fn send_priority(&self) -> TrackPriority {
+        if self.talkback {
+            TrackPriority::High
+        } else {
+            self.priority
         }
     }
+    
+    /// Address the track is sent to instead of the sender's target
+    /// (None if unset or not a valid address)
+    pub fn destination_addr(&self) -> Option<std::net::SocketAddr> {
+        self.destination
+            .as_deref()
+            .and_then(|address| crate::config::parse_socket_addr(address, crate::constants::DEFAULT_UDP_PORT))
+    }
 }
 
 /// Partial track configuration for updates
@@ -276,22 +960,127 @@ pub struct TrackConfigUpdate {
     pub bitrate: Option<u32>,
     pub frame_size_ms: Option<f32>,
     pub fec_enabled: Option<bool>,
+    pub channel_map: Option<Vec<usize>>,
+    pub dred: Option<bool>,
+    pub redundant: Option<bool>,
+    pub plaintext: Option<bool>,
+    /// Empty string sends the track to the sender's target again
+    pub destination: Option<String>,
+    pub codec: Option<Codec>,
+    /// Opus settings: `null` returns the setting to the track's preset
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub complexity: Option<Option<u8>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub bitrate_mode: Option<Option<OpusBitrateMode>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub signal: Option<Option<OpusSignal>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth: Option<Option<OpusBandwidth>>,
+    pub priority: Option<TrackPriority>,
+    pub dtx: Option<bool>,
+    pub silence_threshold_db: Option<f32>,
+    pub silence_hold_ms: Option<u32>,
+    pub high_pass_hz: Option<f32>,
+    pub noise_suppression: Option<bool>,
+    pub agc: Option<bool>,
+    pub agc_target_db: Option<f32>,
+    pub agc_attack_ms: Option<f32>,
+    pub agc_release_ms: Option<f32>,
+    /// 0 returns the track to the host's default buffer
+    pub buffer_frames: Option<u32>,
+    /// Replaces the list, empty plays on `device_id` only
+    pub output_devices: Option<Vec<String>>,
+    pub volume: Option<f32>,
+    pub jitter_mode: Option<JitterMode>,
+    /// 0 returns the delay to its default
+    pub jitter_min_ms: Option<u32>,
+    pub jitter_max_ms: Option<u32>,
+    pub jitter_target_ms: Option<u32>,
+}
+
+/// A field that is present, even as `null`, is Some (an absent one stays
+/// None through `#[serde(default)]`)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 /// Track type for Opus optimization
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TrackType {
     /// Voice/speech - optimized for intelligibility
     Voice,
     /// Music - optimized for audio quality
+    #[default]
     Music,
     /// Low latency - minimal algorithmic delay
     LowLatency,
 }
 
-impl Default for TrackType {
-    fn default() -> Self {
-        Self::Music
+/// How the jitter buffer of a received track sets its delay
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum JitterMode {
+    /// Follows the measured jitter
+    #[default]
+    Auto,
+    /// Fixed delay, whatever the jitter
+    Manual,
+}
+
+/// Importance of a track on a congested link and to the routers on the way
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrackPriority {
+    /// Cut and paused first (e.g. ambience)
+    Low,
+    /// E.g. music
+    #[default]
+    Normal,
+    /// Cut last and never paused (e.g. voice, talkback)
+    High,
+}
+
+impl TrackPriority {
+    /// DSCP class of the track's datagrams: Expedited Forwarding for
+    /// voice, AF41 (video class) for music, best effort for ambience
+    pub fn dscp(self) -> u8 {
+        match self {
+            TrackPriority::Low => 0,
+            TrackPriority::Normal => 34,
+            TrackPriority::High => 46,
+        }
+    }
+}
+
+/// Codec a track is encoded with
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Codec {
+    /// Opus - lossy, 6-510 kbps
+    #[default]
+    Opus,
+    /// FLAC - lossless 24-bit for archival-quality streaming, about
+    /// 1.5-2.5 Mbps per stereo track; no FEC, DRED or adaptive bitrate
+    /// (native packet format only)
+    Flac,
+}
+
+impl Codec {
+    /// Id of the codec on the wire (packet flags, track catalog)
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Opus => 0,
+            Self::Flac => 1,
+        }
+    }
+    
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Opus),
+            1 => Some(Self::Flac),
+            _ => None,
+        }
     }
 }
 
@@ -301,16 +1090,117 @@ pub struct TrackStatus {
     pub track_id: u8,
     pub name: String,
     pub device_id: String,
+    /// Как найдено устройство сохранённого трека (None — трек создан не
+    /// из файла конфигурации)
+    #[serde(default)]
+    pub device_match: Option<DeviceMatch>,
     pub active: bool,
     pub muted: bool,
     pub solo: bool,
+    /// Трек внутренней связи (передаёт только при удержании кнопки)
+    pub talkback: bool,
+    /// Уровень трека приглушён на время передачи talkback
+    pub ducked: bool,
     pub bitrate: u32,
+    /// Кодек трека
+    #[serde(default)]
+    pub codec: Codec,
+    /// Тип трека (обработка голоса доступна только голосовым)
+    #[serde(default)]
+    pub track_type: TrackType,
+    /// Сложность кодера Opus (None — по предустановке трека)
+    #[serde(default)]
+    pub complexity: Option<u8>,
+    /// Режим битрейта Opus (None — по предустановке)
+    #[serde(default)]
+    pub bitrate_mode: Option<OpusBitrateMode>,
+    /// Подсказка о типе сигнала для Opus (None — по предустановке)
+    #[serde(default)]
+    pub signal: Option<OpusSignal>,
+    /// Наибольшая полоса Opus (None — по предустановке)
+    #[serde(default)]
+    pub max_bandwidth: Option<OpusBandwidth>,
     pub frame_size_ms: f32,
     pub packets_sent: u64,
     pub packets_received: u64,
     pub packets_lost: u64,
     pub current_latency_ms: f32,
     pub jitter_ms: f32,
+    /// Расхождение часов устройства вывода с часами хоста (ppm)
+    pub clock_skew_ppm: Option<f32>,
+    /// Буфер устройства, запрошенный треком, в кадрах (None — по умолчанию хоста)
+    #[serde(default)]
+    pub buffer_frames: Option<u32>,
+    /// Период, с которым устройство отдаёт или забирает звук (мс; None —
+    /// поток не открыт)
+    #[serde(default)]
+    pub device_period_ms: Option<f32>,
+    /// Задержка устройства по данным хоста (мс; None — хост её не сообщает)
+    #[serde(default)]
+    pub device_latency_ms: Option<f32>,
+    /// Дополнительные устройства, на которые выводится принятый трек
+    #[serde(default)]
+    pub output_devices: Vec<String>,
+    /// Громкость трека (линейное усиление, 1.0 — без изменений)
+    #[serde(default = "TrackConfig::default_volume")]
+    pub volume: f32,
+    /// Как джиттер-буфер принятого трека выбирает задержку
+    #[serde(default)]
+    pub jitter_mode: JitterMode,
+    /// Наименьшая задержка джиттер-буфера (мс; None — 2 кадра)
+    #[serde(default)]
+    pub jitter_min_ms: Option<u32>,
+    /// Наибольшая задержка джиттер-буфера (мс; None — 16 кадров)
+    #[serde(default)]
+    pub jitter_max_ms: Option<u32>,
+    /// Задержка в ручном режиме (мс; None — наименьшая)
+    #[serde(default)]
+    pub jitter_target_ms: Option<u32>,
+    /// Задержка от захвата на отправителе до воспроизведения (мс),
+    /// None пока часы с отправителем не синхронизированы
+    pub e2e_latency_ms: Option<f32>,
+    /// Измеренная пробой задержка от отправки до потока вывода (мс),
+    /// None вне режима измерения
+    #[serde(default)]
+    pub probe_latency_ms: Option<f32>,
+    /// Задержка пробы до входа аналоговой петли (мс)
+    #[serde(default)]
+    pub loopback_latency_ms: Option<f32>,
+    /// Текущий битрейт кодека после адаптации по потерям у получателя,
+    /// None пока обратная связь не меняла битрейт
+    pub adaptive_bitrate: Option<u32>,
+    /// Приоритет трека при перегрузке канала
+    #[serde(default)]
+    pub priority: TrackPriority,
+    /// Отправка приостановлена контролем перегрузки (канал не вмещает трек)
+    #[serde(default)]
+    pub congestion_paused: bool,
+    /// Подавление тишины включено
+    #[serde(default)]
+    pub dtx: bool,
+    /// Срез фильтра высоких частот на захвате, Гц (0 — выключен)
+    #[serde(default)]
+    pub high_pass_hz: f32,
+    /// Шумоподавление на захвате
+    #[serde(default)]
+    pub noise_suppression: bool,
+    /// Автоматическая регулировка усиления включена
+    #[serde(default)]
+    pub agc: bool,
+    /// Текущее усиление АРУ в dB (None — трек не захватывается с АРУ)
+    #[serde(default)]
+    pub agc_gain_db: Option<f32>,
+    /// Подавление усиления компрессором и лимитером вывода трека в dB
+    /// (None — на выводе нет ни того, ни другого)
+    #[serde(default)]
+    pub output_gain_reduction_db: Option<f32>,
+    /// Коррекция дрейфа часов отправителя относительно устройства вывода в
+    /// ppm: положительная — часы отправителя спешат (None — ещё не оценена)
+    #[serde(default)]
+    pub playout_drift_ppm: Option<f32>,
+    /// Идёт тишина: кадры трека не отправляются
+    #[serde(default)]
+    pub silent: bool,
     /// Текущий сглаженный уровень в dB
     pub level_db: f32,
     /// Пиковый уровень в dB (с удержанием)
@@ -319,6 +1209,107 @@ pub struct TrackStatus {
     pub level_normalized: f32,
     /// Нормализованный пик (0.0 - 1.0) для UI
     pub peak_normalized: f32,
+    /// Кратковременная громкость по EBU R128 за 400 мс в LUFS
+    /// (None — ещё не измерена или тишина)
+    #[serde(default)]
+    pub loudness_momentary_lufs: Option<f32>,
+    /// Громкость за 3 с в LUFS
+    #[serde(default)]
+    pub loudness_short_term_lufs: Option<f32>,
+    /// Интегральная громкость с запуска трека в LUFS, с абсолютным
+    /// (-70 LUFS) и относительным (-10 LU) гейтом
+    #[serde(default)]
+    pub loudness_integrated_lufs: Option<f32>,
+    /// Почему принятое аудио трека не прозвучало
+    #[serde(default)]
+    pub drops: PlayoutDrops,
+    /// Воспроизведение файла, если источник трека — файл
+    #[serde(default)]
+    pub file_player: Option<FilePlayerStatus>,
+    /// Получатель трека вместо основного (`TrackConfig::destination`)
+    #[serde(default)]
+    pub destination: Option<String>,
+}
+
+/// Уровни трека для частого обновления индикаторов
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackLevel {
+    pub track_id: u8,
+    pub level_db: f32,
+    pub peak_db: f32,
+    pub level_normalized: f32,
+    pub peak_normalized: f32,
+}
+
+impl From<&TrackStatus> for TrackLevel {
+    fn from(status: &TrackStatus) -> Self {
+        Self {
+            track_id: status.track_id,
+            level_db: status.level_db,
+            peak_db: status.peak_db,
+            level_normalized: status.level_normalized,
+            peak_normalized: status.peak_normalized,
+        }
+    }
+}
+
+/// Причина, по которой принятое аудио не прозвучало
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// Пакет удалённого трека
+    Deleted,
+    /// У трека нет устройства вывода
+    NoDevice,
+    /// Декодер отверг пакет
+    DecodeError,
+    /// Пакет пришёл после своего момента воспроизведения
+    Late,
+    /// Вывод опустошил буфер трека и заново набирает предзаполнение
+    Underflow,
+    /// Кадр прозвучал беззвучно: пир-источник заглушён
+    Muted,
+}
+
+/// Счётчики непрозвучавшего аудио трека за сессию (пакеты, кадры по
+/// 10 мс или, для `underflow`, опустошения буфера)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayoutDrops {
+    pub deleted: u64,
+    pub no_device: u64,
+    pub decode_error: u64,
+    pub late: u64,
+    pub underflow: u64,
+    pub muted: u64,
+}
+
+impl PlayoutDrops {
+    /// Учесть `count` случаев по причине
+    pub fn add(&mut self, reason: DropReason, count: u64) {
+        let counter = match reason {
+            DropReason::Deleted => &mut self.deleted,
+            DropReason::NoDevice => &mut self.no_device,
+            DropReason::DecodeError => &mut self.decode_error,
+            DropReason::Late => &mut self.late,
+            DropReason::Underflow => &mut self.underflow,
+            DropReason::Muted => &mut self.muted,
+        };
+        *counter += count;
+    }
+    
+    /// Всего случаев по всем причинам
+    pub fn total(&self) -> u64 {
+        self.deleted + self.no_device + self.decode_error + self.late + self.underflow + self.muted
+    }
+}
+
+/// Архив причин одного трека (остаётся и после удаления трека)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackDrops {
+    pub track_id: u8,
+    /// Трек ещё существует
+    pub present: bool,
+    pub drops: PlayoutDrops,
 }
 
 /// Audio device information
@@ -331,6 +1322,241 @@ pub struct AudioDeviceInfo {
     pub is_default: bool,
     pub sample_rates: Vec<u32>,
     pub channels: Vec<u16>,
+    /// Вход, записывающий звук, который играет выход (WASAPI loopback)
+    #[serde(default)]
+    pub is_loopback: bool,
+    /// Режим, в котором открыт захват с устройства (WASAPI; None, если
+    /// захват не идёт)
+    #[serde(default)]
+    pub capture_mode: Option<StreamMode>,
+    /// Режим, в котором открыто воспроизведение на устройство
+    #[serde(default)]
+    pub playback_mode: Option<StreamMode>,
+}
+
+/// Как найдено устройство трека из файла конфигурации
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceMatch {
+    /// Устройство с сохранённым ID есть
+    Id,
+    /// Совпало имя устройства
+    Name,
+    /// Имя начинается с сохранённого (или наоборот)
+    Prefix,
+    /// Ближайшее похожее имя
+    Fuzzy,
+    /// Совпал хеш нормализованного имени
+    Key,
+    /// Подходящего устройства нет, остался сохранённый ID
+    NotFound,
+}
+
+/// Режим потока WASAPI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMode {
+    /// Общий режим с буфером микшера Windows по умолчанию
+    Shared,
+    /// Общий режим с минимальным периодом
+    LowLatency,
+    /// Монопольный доступ к устройству в обход микшера
+    Exclusive,
+}
+
+/// Информация о статусе пира
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    /// Ключ пира ("ip:audio_port")
+    pub id: String,
+    pub name: String,
+    pub address: String,
+    /// Резервные адреса того же пира (другие сети)
+    #[serde(default)]
+    pub paths: Vec<String>,
+    pub active: bool,
+    /// Состояние соединения (рукопожатие и keepalive)
+    #[serde(default)]
+    pub connection: PeerConnection,
+    /// Сколько миллисекунд назад пир был виден
+    pub last_seen_ms: u64,
+    pub bandwidth: PeerBandwidth,
+}
+
+/// Состояние соединения с пиром
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerConnection {
+    /// Рукопожатие ещё не завершено
+    #[default]
+    Connecting,
+    /// Рукопожатие завершено, пир отвечает на пинги
+    Connected,
+    /// Пир на время перестал отвечать: отправка приостановлена до ответа
+    Interrupted,
+    /// Пир прислал Goodbye
+    Left,
+    /// Пир перестал отвечать на пинги (процесс упал, сеть пропала)
+    Lost,
+}
+
+/// Использование канала одним пиром
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerBandwidth {
+    /// Текущая исходящая скорость (kbps)
+    pub up_kbps: f32,
+    /// Текущая входящая скорость (kbps)
+    pub down_kbps: f32,
+    /// Всего отправлено за сессию (байт, включая заголовки)
+    pub bytes_sent: u64,
+    /// Всего получено за сессию (байт, включая заголовки)
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// Длительность сессии в секундах
+    pub session_secs: u64,
+}
+
+/// Что выполнят получатели треков этого узла: элементы UI, которые
+/// получатели не поддерживают, блокируются с пояснением
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteCapabilities {
+    /// Сколько получателей сообщили свои возможности (0 - ограничений нет)
+    pub peers: usize,
+    /// Все получатели воспроизводят стерео
+    pub stereo: bool,
+    /// Все получатели восстанавливают потери по FEC
+    pub fec: bool,
+    /// Все получатели декодируют DRED
+    pub dred: bool,
+    /// Все получатели декодируют lossless-треки (FLAC)
+    pub flac: bool,
+    /// Сколько треков примет каждый получатель
+    pub max_tracks: u8,
+    /// Сколько каналов воспроизводит каждый получатель (surround - больше 2)
+    pub max_channels: u8,
+}
+
+impl Default for RemoteCapabilities {
+    fn default() -> Self {
+        Self {
+            peers: 0,
+            stereo: true,
+            fec: true,
+            dred: true,
+            flac: true,
+            max_tracks: crate::constants::MAX_TRACKS as u8,
+            max_channels: crate::codec::multistream::MAX_SURROUND_CHANNELS as u8,
+        }
+    }
+}
+
+/// Сопряжение пиров для веб-интерфейса (см. `network::pairing`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairingStatus {
+    /// Hello принимается только от пиров из allowlist
+    pub required: bool,
+    /// Ключ этого узла
+    pub key: String,
+    /// Действующий одноразовый PIN
+    pub pin: Option<String>,
+    /// Сколько секунд PIN ещё действует
+    pub pin_expires_in_secs: u64,
+    /// Ключи сопряжённых пиров (секреты пар не показываются)
+    pub allowed_peers: Vec<String>,
+}
+
+/// Одна ступень теста связи: зонды заданного размера с заданной частотой
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkTestStage {
+    /// Зондов в секунду
+    pub rate_pps: u32,
+    /// Размер пакета зонда (байт)
+    pub size_bytes: usize,
+    pub sent: u32,
+    /// Вернувшихся эхо
+    pub received: u32,
+    /// Потери туда и обратно (%)
+    pub loss_percent: f32,
+    pub rtt_min_ms: f32,
+    pub rtt_avg_ms: f32,
+    pub rtt_max_ms: f32,
+    /// Джиттер по RFC 3550 (мс) в сторону пира и обратно
+    pub jitter_up_ms: f32,
+    pub jitter_down_ms: f32,
+    /// 95-й перцентиль задержки сверх минимальной (мс), худшее направление
+    pub delay_spread_ms: f32,
+}
+
+/// Настройки, рекомендованные по итогам теста связи
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkRecommendation {
+    pub frame_size_ms: f32,
+    /// Битрейт стерео-трека (bps)
+    pub bitrate: u32,
+    pub jitter_buffer_ms: u32,
+    /// Включить FEC (есть потери)
+    pub fec: bool,
+}
+
+/// Результат теста связи с пиром (см. `network::link_test`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkTestReport {
+    /// Адрес, на который шли зонды
+    pub address: String,
+    pub stages: Vec<LinkTestStage>,
+    pub recommendation: LinkRecommendation,
+}
+
+/// Kind of MIDI message a controller sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiKind {
+    /// Control change (faders, knobs, most buttons)
+    Cc,
+    /// Note on/off (pads, buttons in note mode)
+    Note,
+}
+
+/// Track control a MIDI control is mapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiTarget {
+    /// 0..127 sets the volume from 0 to unity
+    Volume,
+    /// A press toggles mute
+    Mute,
+    /// A press toggles solo
+    Solo,
+}
+
+/// A MIDI control mapped to a track control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MidiMapping {
+    /// MIDI channel 1-16
+    pub channel: u8,
+    pub kind: MidiKind,
+    /// Controller or note number
+    pub number: u8,
+    pub track_id: u8,
+    pub control: MidiTarget,
+}
+
+/// Track control the next MIDI control moved is mapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MidiLearn {
+    pub track_id: u8,
+    pub control: MidiTarget,
+}
+
+/// MIDI input state for the web UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MidiStatus {
+    /// Input ports connected
+    pub ports: Vec<String>,
+    pub mappings: Vec<MidiMapping>,
+    /// Learn mode waiting for a control
+    pub learning: Option<MidiLearn>,
 }
 
 #[cfg(test)]
@@ -358,6 +1584,20 @@ mod tests {
         assert_eq!(deserialized.payload.as_ref(), &[1, 2, 3, 4, 5]);
     }
     
+    #[test]
+    fn test_serialize_into_arena() {
+        let mut arena = BytesMut::with_capacity(1024);
+        let first = AudioPacket::new(1, 7, 100, Bytes::from_static(&[1, 2, 3]));
+        let second = AudioPacket::new(2, 8, 200, Bytes::from_static(&[4, 5]));
+        
+        // Datagrams split off the arena are independent of each other
+        let a = first.serialize_into(&mut arena);
+        let b = second.serialize_into(&mut arena);
+        assert_eq!(a, first.serialize());
+        assert_eq!(b, second.serialize());
+        assert!(arena.is_empty());
+    }
+    
     #[test]
     fn test_flags() {
         let flags = PacketFlags::new()
@@ -369,5 +1609,71 @@ mod tests {
         assert!(flags.is_stereo());
         assert!(flags.has_fec());
         assert_eq!(flags.as_byte(), 0x07);
+        
+        let probe = flags.set_probe(true);
+        assert!(probe.is_probe() && !flags.is_probe());
+        assert_eq!(probe.set_probe(false).as_byte(), 0x07);
+        assert_eq!(flags.set_fragment(true).as_byte(), 0x27);
+        
+        // The codec id keeps the other flags
+        assert_eq!(flags.codec(), Some(Codec::Opus));
+        let flac = flags.set_codec(Codec::Flac);
+        assert_eq!(flac.as_byte(), 0x47);
+        assert_eq!(flac.codec(), Some(Codec::Flac));
+        assert_eq!(flac.set_codec(Codec::Opus).as_byte(), 0x07);
+        assert_eq!(PacketFlags::from_byte(0x80).codec(), None);
+    }
+    
+    #[test]
+    fn test_fragmentation() {
+        let payload: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        let packet = AudioPacket {
+            track_id: 2,
+            flags: PacketFlags::new().set_stereo(true).set_encrypted(true),
+            sequence: 77,
+            timestamp: 1_000_000,
+            payload: Bytes::from(payload.clone()),
+        };
+        
+        // Small frames are sent unchanged
+        let small = AudioPacket::new(2, 1, 0, Bytes::from_static(&[1, 2, 3]));
+        let unchanged = small.fragment(MAX_PAYLOAD_SIZE).unwrap();
+        assert_eq!(unchanged.len(), 1);
+        assert!(!unchanged[0].flags.is_fragment());
+        
+        let fragments = packet.fragment(MAX_PAYLOAD_SIZE).unwrap();
+        assert_eq!(fragments.len(), 3);
+        let mut reassembled = Vec::new();
+        for (expected_index, fragment) in fragments.iter().enumerate() {
+            let datagram = fragment.serialize();
+            assert!(datagram.len() <= HEADER_SIZE + MAX_PAYLOAD_SIZE);
+            
+            let parsed = AudioPacket::deserialize(datagram).unwrap();
+            assert!(parsed.flags.is_fragment() && parsed.flags.is_stereo() && parsed.flags.is_encrypted());
+            assert_eq!((parsed.sequence, parsed.timestamp), (77, 1_000_000));
+            let (index, count, chunk) = parsed.fragment_info().unwrap();
+            assert_eq!((index as usize, count), (expected_index, 3));
+            reassembled.extend_from_slice(&chunk);
+        }
+        assert_eq!(reassembled, payload);
+        
+        // The index and count are one byte each
+        let huge = AudioPacket::new(0, 0, 0, Bytes::from(vec![0u8; 400_000]));
+        assert!(huge.fragment(MAX_PAYLOAD_SIZE).is_err());
+        assert!(small.fragment_info().is_none());
+    }
+    
+    #[test]
+    fn test_playout_drops() {
+        let mut drops = PlayoutDrops::default();
+        drops.add(DropReason::Late, 2);
+        drops.add(DropReason::NoDevice, 1);
+        drops.add(DropReason::Late, 1);
+        assert_eq!(drops.late, 3);
+        assert_eq!(drops.total(), 4);
+        
+        let json = serde_json::to_value(&drops).unwrap();
+        assert_eq!(json["no_device"], 1);
+        assert_eq!(serde_json::to_value(DropReason::DecodeError).unwrap(), "decode_error");
     }
 }
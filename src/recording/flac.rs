@@ -0,0 +1,563 @@
+//! Minimal FLAC encoder
+//!
+//! Writes a fixed-blocksize stream of 24-bit samples. Each channel is
+//! coded on its own with the best fixed predictor (orders 0-4) and
+//! partitioned Rice residuals; there is no LPC search, so files come out
+//! somewhat larger than `flac -8` would make them, but encoding stays
+//! cheap on the recorder thread. STREAMINFO is written with an unknown
+//! length first and rewritten with the sample count by [`FlacWriter::finish`].
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Samples per channel in a frame
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Bits per stored sample
+pub const BITS_PER_SAMPLE: u32 = 24;
+
+/// Deepest residual partitioning tried
+const MAX_PARTITION_ORDER: u32 = 8;
+
+/// Highest fixed predictor order
+const MAX_FIXED_ORDER: usize = 4;
+
+/// FLAC stream writer over a seekable output
+pub struct FlacWriter<W: Write + Seek> {
+    out: W,
+    sample_rate: u32,
+    channels: u16,
+    /// Interleaved samples not yet coded into a frame
+    pending: Vec<i32>,
+    frame_number: u64,
+    /// Samples per channel written
+    total_samples: u64,
+    min_frame_bytes: u32,
+    max_frame_bytes: u32,
+    bytes_written: u64,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    /// Start a stream (1-8 channels)
+    pub fn new(mut out: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        if !(1..=8).contains(&channels) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "FLAC supports 1 to 8 channels"));
+        }
+        let mut writer = Self {
+            out: {
+                out.write_all(b"fLaC")?;
+                out
+            },
+            sample_rate,
+            channels,
+            pending: Vec::with_capacity(BLOCK_SIZE * channels as usize),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_bytes: 0,
+            max_frame_bytes: 0,
+            bytes_written: 4,
+        };
+        let streaminfo = writer.streaminfo();
+        writer.out.write_all(&streaminfo)?;
+        writer.bytes_written += streaminfo.len() as u64;
+        Ok(writer)
+    }
+
+    /// Append interleaved 24-bit samples
+    pub fn write(&mut self, samples: &[i32]) -> io::Result<()> {
+        self.pending.extend_from_slice(samples);
+        let block = BLOCK_SIZE * self.channels as usize;
+        let mut start = 0;
+        while self.pending.len() - start >= block {
+            let frame = encode_frame(
+                &self.pending[start..start + block],
+                self.channels as usize,
+                self.frame_number,
+                self.sample_rate,
+            );
+            self.write_frame(&frame, BLOCK_SIZE)?;
+            start += block;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    /// Bytes in the file so far (samples still buffered are not counted)
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Code the last partial frame and fill in STREAMINFO
+    pub fn finish(mut self) -> io::Result<W> {
+        let channels = self.channels as usize;
+        let frames = self.pending.len() / channels;
+        if frames > 0 {
+            let pending = std::mem::take(&mut self.pending);
+            let frame = encode_frame(&pending[..frames * channels], channels, self.frame_number, self.sample_rate);
+            self.write_frame(&frame, frames)?;
+        }
+
+        let streaminfo = self.streaminfo();
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&streaminfo)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_frame(&mut self, frame: &[u8], samples: usize) -> io::Result<()> {
+        self.out.write_all(frame)?;
+        let size = frame.len() as u32;
+        self.min_frame_bytes = if self.frame_number == 0 { size } else { self.min_frame_bytes.min(size) };
+        self.max_frame_bytes = self.max_frame_bytes.max(size);
+        self.frame_number += 1;
+        self.total_samples += samples as u64;
+        self.bytes_written += frame.len() as u64;
+        Ok(())
+    }
+
+    /// The only (and last) metadata block
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut block = Vec::with_capacity(38);
+        block.extend_from_slice(&[0x80, 0, 0, 34]);
+        block.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+        block.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+        block.extend_from_slice(&self.min_frame_bytes.to_be_bytes()[1..]);
+        block.extend_from_slice(&self.max_frame_bytes.to_be_bytes()[1..]);
+        let packed = ((self.sample_rate as u64) << 44)
+            | ((self.channels as u64 - 1) << 41)
+            | ((BITS_PER_SAMPLE as u64 - 1) << 36)
+            | (self.total_samples & 0xF_FFFF_FFFF);
+        block.extend_from_slice(&packed.to_be_bytes());
+        // MD5 of the audio is left unset (all zero = unknown)
+        block.extend_from_slice(&[0; 16]);
+        block
+    }
+}
+
+/// Big-endian bit packer
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Low `count` bits of `value` (count <= 32)
+    fn put(&mut self, value: u64, count: u32) {
+        if count == 0 {
+            return;
+        }
+        self.acc = (self.acc << count) | (value & ((1u64 << count) - 1));
+        self.bits += count;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1u64 << self.bits) - 1;
+    }
+
+    fn put_signed(&mut self, value: i64, count: u32) {
+        self.put(value as u64, count);
+    }
+
+    /// Unary `quotient` zeros and a stop bit, then the low `k` bits
+    fn put_rice(&mut self, folded: u64, k: u32) {
+        let mut quotient = folded >> k;
+        while quotient >= 32 {
+            self.put(0, 32);
+            quotient -= 32;
+        }
+        self.put(1, quotient as u32 + 1);
+        self.put(folded, k);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.put(0, 8 - self.bits);
+        }
+    }
+}
+
+/// Code interleaved samples into one frame (also the payload of lossless
+/// network tracks, see `codec::flac`)
+pub(crate) fn encode_frame(samples: &[i32], channels: usize, frame_number: u64, sample_rate: u32) -> Vec<u8> {
+    let block_size = samples.len() / channels;
+    let mut bits = BitWriter::new();
+
+    // Sync code, fixed blocking, block size as 16-bit (n - 1) after the header
+    bits.put(0b11_1111_1111_1110, 14);
+    bits.put(0, 1);
+    bits.put(0, 1);
+    bits.put(0b0111, 4);
+    bits.put(sample_rate_code(sample_rate), 4);
+    bits.put(channels as u64 - 1, 4);
+    bits.put(0b110, 3);
+    bits.put(0, 1);
+    for byte in utf8_number(frame_number) {
+        bits.put(byte as u64, 8);
+    }
+    bits.put(block_size as u64 - 1, 16);
+    let crc = crc8(&bits.bytes);
+    bits.put(crc as u64, 8);
+
+    let mut channel = Vec::with_capacity(block_size);
+    for index in 0..channels {
+        channel.clear();
+        channel.extend(samples.iter().skip(index).step_by(channels).map(|&sample| sample as i64));
+        encode_subframe(&mut bits, &channel);
+    }
+
+    bits.align();
+    let crc = crc16(&bits.bytes);
+    bits.put(crc as u64, 16);
+    bits.bytes
+}
+
+fn sample_rate_code(sample_rate: u32) -> u64 {
+    match sample_rate {
+        88_200 => 0b0001,
+        176_400 => 0b0010,
+        192_000 => 0b0011,
+        8_000 => 0b0100,
+        16_000 => 0b0101,
+        22_050 => 0b0110,
+        24_000 => 0b0111,
+        32_000 => 0b1000,
+        44_100 => 0b1001,
+        48_000 => 0b1010,
+        96_000 => 0b1011,
+        // Taken from STREAMINFO
+        _ => 0b0000,
+    }
+}
+
+/// Frame number in FLAC's extended UTF-8 coding
+fn utf8_number(value: u64) -> Vec<u8> {
+    if value < 0x80 {
+        return vec![value as u8];
+    }
+    let length = match value {
+        0..=0x7FF => 2,
+        0x800..=0xFFFF => 3,
+        0x1_0000..=0x1F_FFFF => 4,
+        0x20_0000..=0x3FF_FFFF => 5,
+        0x400_0000..=0x7FFF_FFFF => 6,
+        _ => 7,
+    };
+    let mut bytes = Vec::with_capacity(length);
+    let lead = (0xFF00u16 >> length) as u8;
+    bytes.push(lead | (value >> (6 * (length - 1))) as u8);
+    for index in (0..length - 1).rev() {
+        bytes.push(0x80 | ((value >> (6 * index)) & 0x3F) as u8);
+    }
+    bytes
+}
+
+fn encode_subframe(bits: &mut BitWriter, samples: &[i64]) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        bits.put(0b0000_0000, 8);
+        bits.put_signed(samples[0], BITS_PER_SAMPLE);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * BITS_PER_SAMPLE as u64;
+    let best = (0..=MAX_FIXED_ORDER.min(samples.len() - 1))
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let plan = plan_residual(&residual, samples.len(), order);
+            let cost = order as u64 * BITS_PER_SAMPLE as u64 + plan.bits;
+            (cost, order, residual, plan)
+        })
+        .min_by_key(|(cost, ..)| *cost);
+
+    match best {
+        Some((cost, order, residual, plan)) if cost < verbatim_bits => {
+            bits.put(0b0001_0000 | (order as u64) << 1, 8);
+            for &sample in &samples[..order] {
+                bits.put_signed(sample, BITS_PER_SAMPLE);
+            }
+            write_residual(bits, &residual, samples.len(), order, &plan);
+        }
+        _ => {
+            bits.put(0b0000_0010, 8);
+            for &sample in samples {
+                bits.put_signed(sample, BITS_PER_SAMPLE);
+            }
+        }
+    }
+}
+
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    let s = samples;
+    (order..s.len())
+        .map(|i| match order {
+            0 => s[i],
+            1 => s[i] - s[i - 1],
+            2 => s[i] - 2 * s[i - 1] + s[i - 2],
+            3 => s[i] - 3 * s[i - 1] + 3 * s[i - 2] - s[i - 3],
+            _ => s[i] - 4 * s[i - 1] + 6 * s[i - 2] - 4 * s[i - 3] + s[i - 4],
+        })
+        .collect()
+}
+
+/// Partition order and Rice parameters chosen for a residual
+struct ResidualPlan {
+    partition_order: u32,
+    parameters: Vec<u32>,
+    /// 5-bit parameters (RICE2) needed
+    wide: bool,
+    bits: u64,
+}
+
+fn fold(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+fn plan_residual(residual: &[i64], block_size: usize, order: usize) -> ResidualPlan {
+    // Deepest order whose partitions each hold more than the warm-up
+    let mut max_order = 0;
+    while max_order < MAX_PARTITION_ORDER
+        && block_size.is_multiple_of(1 << (max_order + 1))
+        && block_size >> (max_order + 1) > order
+    {
+        max_order += 1;
+    }
+
+    // Folded sums of the finest partitions, merged pairwise for coarser ones
+    let partition_size = block_size >> max_order;
+    let mut sums: Vec<(u64, u64)> = (0..1usize << max_order)
+        .map(|partition| {
+            let start = (partition * partition_size).saturating_sub(order);
+            let end = (partition + 1) * partition_size - order;
+            let sum = residual[start..end].iter().map(|&r| fold(r)).sum();
+            (sum, (end - start) as u64)
+        })
+        .collect();
+
+    let mut best: Option<ResidualPlan> = None;
+    for partition_order in (0..=max_order).rev() {
+        let parameters: Vec<u32> = sums.iter().map(|&(sum, count)| rice_parameter(sum, count)).collect();
+        let wide = parameters.iter().any(|&k| k > 14);
+        let parameter_bits = if wide { 5 } else { 4 };
+        let bits = 6
+            + sums
+                .iter()
+                .zip(&parameters)
+                .map(|(&(sum, count), &k)| parameter_bits + count * (k as u64 + 1) + (sum >> k))
+                .sum::<u64>();
+        if best.as_ref().is_none_or(|best| bits < best.bits) {
+            best = Some(ResidualPlan {
+                partition_order,
+                parameters,
+                wide,
+                bits,
+            });
+        }
+        sums = sums.chunks(2).map(|pair| pair.iter().fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1))).collect();
+    }
+    best.expect("at least partition order 0")
+}
+
+/// Rice parameter near log2 of the mean folded residual
+fn rice_parameter(sum: u64, count: u64) -> u32 {
+    let mean = sum / count.max(1);
+    if mean == 0 {
+        0
+    } else {
+        (63 - mean.leading_zeros()).min(30)
+    }
+}
+
+fn write_residual(bits: &mut BitWriter, residual: &[i64], block_size: usize, order: usize, plan: &ResidualPlan) {
+    let (method, parameter_bits) = if plan.wide { (1, 5) } else { (0, 4) };
+    bits.put(method, 2);
+    bits.put(plan.partition_order as u64, 4);
+
+    let partition_size = block_size >> plan.partition_order;
+    for (partition, &k) in plan.parameters.iter().enumerate() {
+        let start = (partition * partition_size).saturating_sub(order);
+        let end = (partition + 1) * partition_size - order;
+        bits.put(k as u64, parameter_bits);
+        for &r in &residual[start..end] {
+            bits.put_rice(fold(r), k);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Bit reader for the decoder below
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn get(&mut self, count: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..count {
+                let bit = (self.data[self.position / 8] >> (7 - self.position % 8)) & 1;
+                value = (value << 1) | bit as u64;
+                self.position += 1;
+            }
+            value
+        }
+
+        fn get_signed(&mut self, count: u32) -> i64 {
+            let value = self.get(count) as i64;
+            (value << (64 - count)) >> (64 - count)
+        }
+
+        fn get_rice(&mut self, k: u32) -> i64 {
+            let mut quotient = 0;
+            while self.get(1) == 0 {
+                quotient += 1;
+            }
+            let folded = (quotient << k) | self.get(k);
+            ((folded >> 1) as i64) ^ -((folded & 1) as i64)
+        }
+
+        fn byte_position(&mut self) -> usize {
+            self.position = self.position.div_ceil(8) * 8;
+            self.position / 8
+        }
+    }
+
+    /// Decode the subset of FLAC this encoder writes: (rate, channels,
+    /// STREAMINFO sample count, interleaved samples)
+    fn decode(data: &[u8]) -> (u32, usize, u64, Vec<i32>) {
+        assert_eq!(&data[..4], b"fLaC");
+        assert_eq!(data[4], 0x80);
+        let packed = u64::from_be_bytes(data[18..26].try_into().unwrap());
+        let sample_rate = (packed >> 44) as u32;
+        let channels = ((packed >> 41) & 7) as usize + 1;
+        assert_eq!((packed >> 36) & 31, BITS_PER_SAMPLE as u64 - 1);
+        let total = packed & 0xF_FFFF_FFFF;
+
+        let mut reader = BitReader { data, position: 42 * 8 };
+        let mut output = Vec::new();
+        while reader.position / 8 < data.len() {
+            let frame_start = reader.position / 8;
+            assert_eq!(reader.get(14), 0b11_1111_1111_1110);
+            reader.get(2);
+            assert_eq!(reader.get(4), 0b0111);
+            reader.get(4);
+            assert_eq!(reader.get(4) as usize, channels - 1);
+            assert_eq!(reader.get(4), 0b1100);
+            let lead = reader.get(8);
+            for _ in 1..(lead as u8).leading_ones().max(1) {
+                reader.get(8);
+            }
+            let block_size = reader.get(16) as usize + 1;
+            let header_end = reader.position / 8;
+            assert_eq!(reader.get(8) as u8, crc8(&data[frame_start..header_end]));
+
+            let mut decoded = vec![Vec::with_capacity(block_size); channels];
+            for channel in decoded.iter_mut() {
+                reader.get(1);
+                let kind = reader.get(6);
+                reader.get(1);
+                match kind {
+                    0 => channel.resize(block_size, reader.get_signed(BITS_PER_SAMPLE)),
+                    1 => (0..block_size).for_each(|_| channel.push(reader.get_signed(BITS_PER_SAMPLE))),
+                    8..=12 => {
+                        let order = (kind - 8) as usize;
+                        (0..order).for_each(|_| channel.push(reader.get_signed(BITS_PER_SAMPLE)));
+                        let parameter_bits = if reader.get(2) == 1 { 5 } else { 4 };
+                        let partition_order = reader.get(4);
+                        let partition_size = block_size >> partition_order;
+                        for partition in 0..1usize << partition_order {
+                            let k = reader.get(parameter_bits) as u32;
+                            let count = if partition == 0 { partition_size - order } else { partition_size };
+                            for _ in 0..count {
+                                let r = reader.get_rice(k);
+                                let s = &channel[channel.len() - order..];
+                                let prediction = match order {
+                                    0 => 0,
+                                    1 => s[0],
+                                    2 => 2 * s[1] - s[0],
+                                    3 => 3 * s[2] - 3 * s[1] + s[0],
+                                    _ => 4 * s[3] - 6 * s[2] + 4 * s[1] - s[0],
+                                };
+                                channel.push(prediction + r);
+                            }
+                        }
+                    }
+                    other => panic!("unexpected subframe type {}", other),
+                }
+            }
+            let frame_end = reader.byte_position();
+            assert_eq!(reader.get(16) as u16, crc16(&data[frame_start..frame_end]));
+            for i in 0..block_size {
+                output.extend(decoded.iter().map(|channel| channel[i] as i32));
+            }
+        }
+        (sample_rate, channels, total, output)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Sine, silence (constant subframes) and full-scale noise (verbatim)
+        let frames = BLOCK_SIZE * 3 + 1000;
+        let mut noise = 0x1234_5678u32;
+        let samples: Vec<i32> = (0..frames)
+            .flat_map(|i| {
+                noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let left = ((i as f64 * 0.01).sin() * 4_000_000.0) as i32;
+                let right = if i < BLOCK_SIZE { 0 } else { (noise as i32) >> 8 };
+                [left, right]
+            })
+            .collect();
+
+        let mut writer = FlacWriter::new(Cursor::new(Vec::new()), 48_000, 2).unwrap();
+        // Uneven writes cross frame boundaries
+        for chunk in samples.chunks(960) {
+            writer.write(chunk).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let (sample_rate, channels, total, decoded) = decode(&data);
+        assert_eq!((sample_rate, channels, total), (48_000, 2, frames as u64));
+        assert_eq!(decoded, samples);
+        // Predictable audio compresses
+        assert!(data.len() < samples.len() * 3);
+    }
+
+    #[test]
+    fn test_utf8_frame_numbers() {
+        assert_eq!(utf8_number(0x7F), vec![0x7F]);
+        assert_eq!(utf8_number(0x80), vec![0xC2, 0x80]);
+        assert_eq!(utf8_number(0xFFFF), vec![0xEF, 0xBF, 0xBF]);
+    }
+}
@@ -0,0 +1,10 @@
+//! Recording of received audio
+//!
+//! Writes received tracks, one file each and/or their stereo mix, to
+//! timestamped WAV or FLAC files with rotation by size or duration.
+
+pub mod flac;
+pub mod recorder;
+pub mod wav;
+
+pub use recorder::Recorder;
@@ -0,0 +1,571 @@
+//! Recorder of received tracks
+//!
+//! The receive loop hands every decoded frame to [`Recorder::push`] where
+//! it goes to playback, so a recording holds what arrived (concealed gaps
+//! included) before the mixer's peer gain, mute and solo. Files are
+//! written on a thread of their own: `push` only copies the frame into a
+//! bounded queue and drops it if the disk falls behind.
+//!
+//! Files are named `<date>_<time>_track<N>.wav` (`_mix` for the sum of all
+//! tracks) in the recordings directory. Once a file reaches `max_file_mb`
+//! or `max_file_secs` it is closed and the next frame starts a new
+//! timestamped one.
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{RecordedFile, RecordingFormat, RecordingMode, RecordingRequest, RecordingStatus};
+use crate::recording::flac::FlacWriter;
+use crate::recording::wav::{self, WavWriter};
+
+/// Frames queued for the writer thread (about 10 s of 10 ms frames)
+const QUEUE_FRAMES: usize = 1024;
+
+/// A track silent this long no longer holds the mix back
+const MIX_STALE: Duration = Duration::from_millis(500);
+
+/// Channels of the mixed file
+const MIX_CHANNELS: usize = 2;
+
+/// Decoded audio of one track
+struct Chunk {
+    track_id: u8,
+    channels: u16,
+    samples: Vec<f32>,
+}
+
+/// Records received tracks to WAV or FLAC files
+pub struct Recorder {
+    dir: PathBuf,
+    sample_rate: u32,
+    active: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    status: Arc<Mutex<RecordingStatus>>,
+    queue: RwLock<Option<Sender<Chunk>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Recorder {
+    /// Recorder writing to `dir` (created on the first start) audio
+    /// decoded at `sample_rate`
+    pub fn new(dir: PathBuf, sample_rate: u32) -> Self {
+        Self {
+            dir,
+            sample_rate,
+            active: Arc::new(AtomicBool::new(false)),
+            dropped: Arc::new(AtomicU64::new(0)),
+            status: Arc::new(Mutex::new(RecordingStatus::default())),
+            queue: RwLock::new(None),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Directory recordings are written to
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Start a recording
+    pub fn start(&self, request: RecordingRequest) -> Result<RecordingStatus, String> {
+        let mut thread = self.thread.lock();
+        if self.active.load(Ordering::Relaxed) {
+            return Err("Already recording".to_string());
+        }
+        if request.max_file_mb == Some(0) || request.max_file_secs == Some(0) {
+            return Err("File size and duration limits must be above zero".to_string());
+        }
+        if request.tracks.as_ref().is_some_and(|tracks| tracks.is_empty()) {
+            return Err("No tracks to record".to_string());
+        }
+
+        // A recording ended by a write error still has its thread
+        self.queue.write().take();
+        if let Some(old) = thread.take() {
+            let _ = old.join();
+        }
+
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+
+        *self.status.lock() = RecordingStatus {
+            request: Some(request.clone()),
+            ..RecordingStatus::default()
+        };
+        self.dropped.store(0, Ordering::Relaxed);
+
+        let (tx, rx) = crossbeam_channel::bounded(QUEUE_FRAMES);
+        let writer = SessionWriter::new(self.dir.clone(), self.sample_rate, request, self.status.clone());
+        let active = self.active.clone();
+        let handle = std::thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || writer.run(rx, active))
+            .map_err(|e| format!("Failed to start recorder thread: {}", e))?;
+
+        self.active.store(true, Ordering::Relaxed);
+        *self.queue.write() = Some(tx);
+        *thread = Some(handle);
+        tracing::info!("Recording to {}", self.dir.display());
+        Ok(self.status())
+    }
+
+    /// Stop recording and close the files
+    pub fn stop(&self) -> RecordingStatus {
+        let mut thread = self.thread.lock();
+        self.active.store(false, Ordering::Relaxed);
+        // The writer drains the queue and finishes once the sender is gone
+        self.queue.write().take();
+        if let Some(handle) = thread.take() {
+            if handle.join().is_err() {
+                self.status.lock().error = Some("Recorder thread panicked".to_string());
+            }
+            tracing::info!("Recording stopped");
+        }
+        self.status()
+    }
+
+    /// Recorder state and files
+    pub fn status(&self) -> RecordingStatus {
+        let mut status = self.status.lock().clone();
+        status.active = self.active.load(Ordering::Relaxed);
+        status.dropped_frames = self.dropped.load(Ordering::Relaxed);
+        status
+    }
+
+    /// Whether a recording is running
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Hand over decoded interleaved audio of a track
+    pub fn push(&self, track_id: u8, samples: &[f32], channels: u16) {
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(ref queue) = *self.queue.read() else {
+            return;
+        };
+        let chunk = Chunk {
+            track_id,
+            channels,
+            samples: samples.to_vec(),
+        };
+        if let Err(TrySendError::Full(_)) = queue.try_send(chunk) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// An open output file
+enum FileWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Flac(FlacWriter<BufWriter<File>>),
+}
+
+impl FileWriter {
+    fn write(&mut self, samples: &[i32]) -> io::Result<()> {
+        match self {
+            FileWriter::Wav(writer) => writer.write(samples),
+            FileWriter::Flac(writer) => writer.write(samples),
+        }
+    }
+
+    fn bytes_written(&self) -> u64 {
+        match self {
+            FileWriter::Wav(writer) => writer.bytes_written(),
+            FileWriter::Flac(writer) => writer.bytes_written(),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            FileWriter::Wav(writer) => writer.finish().map(drop),
+            FileWriter::Flac(writer) => writer.finish().map(drop),
+        }
+    }
+}
+
+/// File of one track (or the mix) with its entry in the status
+struct OpenFile {
+    writer: FileWriter,
+    channels: u16,
+    frames: u64,
+    status_index: usize,
+}
+
+/// Writer thread state of one recording
+struct SessionWriter {
+    dir: PathBuf,
+    sample_rate: u32,
+    request: RecordingRequest,
+    status: Arc<Mutex<RecordingStatus>>,
+    tracks: HashMap<u8, OpenFile>,
+    mix: Option<MixBuffer>,
+    mix_file: Option<OpenFile>,
+    /// Conversion scratch
+    pcm: Vec<i32>,
+}
+
+impl SessionWriter {
+    fn new(dir: PathBuf, sample_rate: u32, request: RecordingRequest, status: Arc<Mutex<RecordingStatus>>) -> Self {
+        let mix = matches!(request.mode, RecordingMode::Mixed | RecordingMode::Both).then(MixBuffer::new);
+        Self {
+            dir,
+            sample_rate,
+            request,
+            status,
+            tracks: HashMap::new(),
+            mix,
+            mix_file: None,
+            pcm: Vec::new(),
+        }
+    }
+
+    fn run(mut self, rx: Receiver<Chunk>, active: Arc<AtomicBool>) {
+        let result = loop {
+            let step = match rx.recv_timeout(MIX_STALE / 2) {
+                Ok(chunk) => self.handle(chunk, Instant::now()),
+                // Let the mix move on past tracks that went silent
+                Err(RecvTimeoutError::Timeout) => self.write_mix(false, Instant::now()),
+                Err(RecvTimeoutError::Disconnected) => break Ok(()),
+            };
+            if let Err(e) = step {
+                break Err(e);
+            }
+        };
+        let result = result.and_then(|_| self.write_mix(true, Instant::now()));
+
+        let mut finished = self.finish_all();
+        if let Err(e) = result {
+            finished = Err(e);
+        }
+        if let Err(e) = finished {
+            tracing::error!("Recording failed: {}", e);
+            active.store(false, Ordering::Relaxed);
+            self.status.lock().error = Some(e.to_string());
+        }
+    }
+
+    fn handle(&mut self, chunk: Chunk, now: Instant) -> io::Result<()> {
+        if let Some(ref tracks) = self.request.tracks {
+            if !tracks.contains(&chunk.track_id) {
+                return Ok(());
+            }
+        }
+        if chunk.channels == 0 {
+            return Ok(());
+        }
+
+        if self.request.mode != RecordingMode::Mixed {
+            // A track that changed its channel count continues in a new file
+            if self.tracks.get(&chunk.track_id).is_some_and(|file| file.channels != chunk.channels) {
+                let file = self.tracks.remove(&chunk.track_id).expect("checked above");
+                self.close(file)?;
+            }
+            self.write(Some(chunk.track_id), &chunk.samples, chunk.channels)?;
+        }
+
+        if let Some(ref mut mix) = self.mix {
+            mix.add(chunk.track_id, &chunk.samples, chunk.channels as usize, now);
+            self.write_mix(false, now)?;
+        }
+        Ok(())
+    }
+
+    /// Write the part of the mix every live track has reached (all if `flush`)
+    fn write_mix(&mut self, flush: bool, now: Instant) -> io::Result<()> {
+        let Some(ref mut mix) = self.mix else {
+            return Ok(());
+        };
+        let ready = if flush { mix.take_all() } else { mix.take_ready(now) };
+        if ready.is_empty() {
+            return Ok(());
+        }
+        self.write(None, &ready, MIX_CHANNELS as u16)
+    }
+
+    /// Append audio to the file of a track (None = the mix), closing it at
+    /// the duration limit exactly and at the size limit after the write
+    fn write(&mut self, track_id: Option<u8>, samples: &[f32], channels: u16) -> io::Result<()> {
+        let max_frames = self.request.max_file_secs.map_or(u64::MAX, |secs| secs.saturating_mul(self.sample_rate as u64));
+        let mut pcm = std::mem::take(&mut self.pcm);
+        to_pcm24(samples, &mut pcm);
+
+        let mut rest = &pcm[..];
+        let mut result = Ok(());
+        while !rest.is_empty() {
+            let slot = match track_id {
+                Some(track_id) => self.tracks.remove(&track_id),
+                None => self.mix_file.take(),
+            };
+            let mut file = match slot {
+                Some(file) => file,
+                None => {
+                    let label = track_id.map_or_else(|| "mix".to_string(), |track_id| format!("track{}", track_id));
+                    match self.open(&label, track_id, channels) {
+                        Ok(file) => file,
+                        Err(e) => {
+                            result = Err(e);
+                            break;
+                        }
+                    }
+                }
+            };
+
+            let frames = ((rest.len() / channels as usize) as u64).min(max_frames - file.frames);
+            let (now, later) = rest.split_at(frames as usize * channels as usize);
+            rest = later;
+            if let Err(e) = file.writer.write(now) {
+                result = Err(e);
+                break;
+            }
+            file.frames += frames;
+            self.update_status(&file);
+
+            let mut max_bytes = self.request.max_file_mb.map_or(u64::MAX, |mb| mb.saturating_mul(1024 * 1024));
+            if matches!(file.writer, FileWriter::Wav(_)) {
+                max_bytes = max_bytes.min(wav::MAX_DATA_BYTES);
+            }
+            if file.writer.bytes_written() >= max_bytes || file.frames >= max_frames {
+                if let Err(e) = self.close(file) {
+                    result = Err(e);
+                    break;
+                }
+            } else {
+                match track_id {
+                    Some(track_id) => {
+                        self.tracks.insert(track_id, file);
+                    }
+                    None => self.mix_file = Some(file),
+                }
+            }
+        }
+        self.pcm = pcm;
+        result
+    }
+
+    fn open(&mut self, label: &str, track_id: Option<u8>, channels: u16) -> io::Result<OpenFile> {
+        let extension = match self.request.format {
+            RecordingFormat::Wav => "wav",
+            RecordingFormat::Flac => "flac",
+        };
+        let stamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let path = unique_path(&self.dir, &format!("{}_{}", stamp, label), extension);
+        let out = BufWriter::new(File::create(&path)?);
+        let writer = match self.request.format {
+            RecordingFormat::Wav => FileWriter::Wav(WavWriter::new(out, self.sample_rate, channels)?),
+            RecordingFormat::Flac => FileWriter::Flac(FlacWriter::new(out, self.sample_rate, channels)?),
+        };
+        tracing::info!("Recording {} to {}", label, path.display());
+
+        let mut status = self.status.lock();
+        status.files.push(RecordedFile {
+            path: path.display().to_string(),
+            track_id,
+            bytes: writer.bytes_written(),
+            duration_ms: 0,
+            finished: false,
+        });
+        Ok(OpenFile {
+            writer,
+            channels,
+            frames: 0,
+            status_index: status.files.len() - 1,
+        })
+    }
+
+    fn close(&self, file: OpenFile) -> io::Result<()> {
+        let index = file.status_index;
+        let frames = file.frames;
+        file.writer.finish()?;
+        let mut status = self.status.lock();
+        let entry = &mut status.files[index];
+        if let Ok(metadata) = std::fs::metadata(&entry.path) {
+            entry.bytes = metadata.len();
+        }
+        entry.duration_ms = frames * 1000 / self.sample_rate as u64;
+        entry.finished = true;
+        Ok(())
+    }
+
+    fn update_status(&self, file: &OpenFile) {
+        let mut status = self.status.lock();
+        let entry = &mut status.files[file.status_index];
+        entry.bytes = file.writer.bytes_written();
+        entry.duration_ms = file.frames * 1000 / self.sample_rate as u64;
+    }
+
+    fn finish_all(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        let files: Vec<OpenFile> = self.tracks.drain().map(|(_, file)| file).chain(self.mix_file.take()).collect();
+        for file in files {
+            if let Err(e) = self.close(file) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+/// `dir/name.extension`, or `name-2.extension`... if that exists
+fn unique_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    let candidate = dir.join(format!("{}.{}", name, extension));
+    if !candidate.exists() {
+        return candidate;
+    }
+    (2..)
+        .map(|n| dir.join(format!("{}-{}.{}", name, n, extension)))
+        .find(|path| !path.exists())
+        .expect("some name is free")
+}
+
+/// Float samples to 24-bit integers
+fn to_pcm24(samples: &[f32], out: &mut Vec<i32>) {
+    out.clear();
+    out.extend(samples.iter().map(|&sample| (sample.clamp(-1.0, 1.0) * 8_388_607.0).round() as i32));
+}
+
+/// Position of one track in the mix
+struct MixCursor {
+    /// Frames written from the start of the buffer
+    frames: usize,
+    last_seen: Instant,
+}
+
+/// Stereo sum of tracks that deliver frames at their own pace
+///
+/// Each track adds its audio at its own cursor; the mix is complete up to
+/// the slowest track seen in the last [`MIX_STALE`]. A track that stops
+/// sending is left behind and rejoins at the head of the buffer.
+struct MixBuffer {
+    /// Interleaved stereo
+    samples: Vec<f32>,
+    cursors: HashMap<u8, MixCursor>,
+}
+
+impl MixBuffer {
+    fn new() -> Self {
+        Self {
+            samples: Vec::new(),
+            cursors: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, track_id: u8, samples: &[f32], channels: usize, now: Instant) {
+        let cursor = self.cursors.entry(track_id).or_insert(MixCursor {
+            frames: 0,
+            last_seen: now,
+        });
+        cursor.last_seen = now;
+
+        let frames = samples.len() / channels;
+        let end = (cursor.frames + frames) * MIX_CHANNELS;
+        if self.samples.len() < end {
+            self.samples.resize(end, 0.0);
+        }
+        let mix = &mut self.samples[cursor.frames * MIX_CHANNELS..end];
+        for (out, frame) in mix.chunks_exact_mut(MIX_CHANNELS).zip(samples.chunks_exact(channels)) {
+            // Mono goes to both sides, extra channels are left out
+            out[0] += frame[0];
+            out[1] += frame[if channels > 1 { 1 } else { 0 }];
+        }
+        cursor.frames += frames;
+    }
+
+    fn take_ready(&mut self, now: Instant) -> Vec<f32> {
+        let ready = self
+            .cursors
+            .values()
+            .filter(|cursor| now.saturating_duration_since(cursor.last_seen) < MIX_STALE)
+            .map(|cursor| cursor.frames)
+            .min()
+            .unwrap_or(self.samples.len() / MIX_CHANNELS);
+        self.take(ready)
+    }
+
+    fn take_all(&mut self) -> Vec<f32> {
+        self.take(self.samples.len() / MIX_CHANNELS)
+    }
+
+    fn take(&mut self, frames: usize) -> Vec<f32> {
+        for cursor in self.cursors.values_mut() {
+            cursor.frames = cursor.frames.saturating_sub(frames);
+        }
+        self.samples.drain(..frames * MIX_CHANNELS).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix_waits_for_live_tracks() {
+        let start = Instant::now();
+        let mut mix = MixBuffer::new();
+
+        mix.add(0, &[0.1, 0.2, 0.1, 0.2], 2, start);
+        mix.add(1, &[0.5], 1, start);
+        // Track 1 has only reached the first frame
+        assert_eq!(mix.take_ready(start), vec![0.6, 0.7]);
+        assert!(mix.take_ready(start).is_empty());
+
+        mix.add(1, &[0.5], 1, start);
+        assert_eq!(mix.take_ready(start), vec![0.6, 0.7]);
+
+        // Track 1 went silent: track 0 carries on alone
+        let later = start + MIX_STALE;
+        mix.add(0, &[0.25, 0.25], 2, later);
+        assert_eq!(mix.take_ready(later), vec![0.25, 0.25]);
+    }
+
+    #[test]
+    fn test_records_rotated_files() {
+        let dir = std::env::temp_dir().join(format!("lan-audio-recordings-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let recorder = Recorder::new(dir.clone(), 48_000);
+
+        // Not recording: frames are ignored
+        recorder.push(0, &[0.0; 960], 2);
+
+        recorder
+            .start(RecordingRequest {
+                format: RecordingFormat::Flac,
+                mode: RecordingMode::Both,
+                max_file_secs: Some(1),
+                ..RecordingRequest::default()
+            })
+            .unwrap();
+        assert!(recorder.start(RecordingRequest::default()).is_err());
+
+        // 1.5 s of a stereo and a mono track in 10 ms frames
+        for _ in 0..150 {
+            recorder.push(0, &[0.25; 960], 2);
+            recorder.push(1, &[-0.5; 480], 1);
+        }
+        let status = recorder.stop();
+
+        assert!(!status.active);
+        assert_eq!(status.error, None);
+        assert_eq!(status.dropped_frames, 0);
+        // Files rotate at exactly 1 s; the mix may start a frame early,
+        // before track 1 shows up
+        for track_id in [Some(0), Some(1), None] {
+            let files: Vec<_> = status.files.iter().filter(|file| file.track_id == track_id).collect();
+            let durations: Vec<_> = files.iter().map(|file| file.duration_ms).collect();
+            match track_id {
+                Some(_) => assert_eq!(durations, vec![1000, 500]),
+                None => assert!(durations.len() == 2 && durations[0] == 1000 && (500..=510).contains(&durations[1])),
+            }
+            assert!(files.iter().all(|file| file.finished && std::fs::metadata(&file.path).unwrap().len() == file.bytes));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
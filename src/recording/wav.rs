@@ -0,0 +1,102 @@
+//! 24-bit PCM WAV writer
+//!
+//! The RIFF and data chunk sizes are written as zero first and filled in
+//! by [`WavWriter::finish`], so a file cut short by a crash still holds
+//! its audio (most editors read it with a size warning).
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Bits per stored sample
+pub const BITS_PER_SAMPLE: u16 = 24;
+
+/// Size of the header before the samples
+const HEADER_BYTES: u64 = 44;
+
+/// Largest data chunk a RIFF size field can describe
+pub const MAX_DATA_BYTES: u64 = u32::MAX as u64 - HEADER_BYTES;
+
+/// WAV file writer over a seekable output
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_bytes: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Write the header of a stream
+    pub fn new(mut out: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+        let mut header = Vec::with_capacity(HEADER_BYTES as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        out.write_all(&header)?;
+
+        Ok(Self {
+            out,
+            data_bytes: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Append interleaved 24-bit samples
+    pub fn write(&mut self, samples: &[i32]) -> io::Result<()> {
+        self.buffer.clear();
+        for sample in samples {
+            self.buffer.extend_from_slice(&sample.to_le_bytes()[..3]);
+        }
+        self.out.write_all(&self.buffer)?;
+        self.data_bytes += self.buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Bytes in the file so far
+    pub fn bytes_written(&self) -> u64 {
+        HEADER_BYTES + self.data_bytes
+    }
+
+    /// Fill in the chunk sizes
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_bytes = self.data_bytes.min(MAX_DATA_BYTES) as u32;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(data_bytes + HEADER_BYTES as u32 - 8).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data_bytes.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_and_samples() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 48_000, 2).unwrap();
+        writer.write(&[0x12_3456, -1, 8_388_607, -8_388_608]).unwrap();
+        assert_eq!(writer.bytes_written(), 44 + 12);
+        let data = writer.finish().unwrap().into_inner();
+
+        assert_eq!(data.len(), 56);
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 48);
+        assert_eq!(u16::from_le_bytes(data[22..24].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 48_000);
+        assert_eq!(u32::from_le_bytes(data[28..32].try_into().unwrap()), 48_000 * 6);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 12);
+        assert_eq!(&data[44..], &[0x56, 0x34, 0x12, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x00, 0x00, 0x80]);
+    }
+}
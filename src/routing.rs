@@ -0,0 +1,173 @@
+//! Routing matrix between input tracks and peers
+//!
+//! By default every input track is sent to every connected peer. A track
+//! can be restricted to a subset of destinations, identified by peer key
+//! ("ip:audio_port", see [`PeerRegistry::key_for`]). An empty destination
+//! list keeps the track local (sent to nobody).
+//!
+//! [`PeerRegistry::key_for`]: crate::network::PeerRegistry::key_for
+
+use dashmap::DashMap;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::RoutingConfig;
+use crate::protocol::TrackRoute;
+
+/// Per-track destination sets shared by the send loop and the control API
+pub struct RoutingMatrix {
+    /// Restricted tracks; a track without an entry goes to all peers
+    routes: DashMap<u8, BTreeSet<String>>,
+
+    /// Routes changed since the last `take_changed` (needs persisting)
+    changed: AtomicBool,
+}
+
+impl RoutingMatrix {
+    /// Create a matrix routing every track to every peer
+    pub fn new() -> Self {
+        Self {
+            routes: DashMap::new(),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Create a matrix from persisted configuration
+    pub fn from_config(config: &RoutingConfig) -> Self {
+        let matrix = Self::new();
+        for route in &config.routes {
+            matrix
+                .routes
+                .insert(route.track_id, route.destinations.iter().cloned().collect());
+        }
+        matrix
+    }
+
+    /// Snapshot for persisting in `AppConfig`
+    pub fn to_config(&self) -> RoutingConfig {
+        RoutingConfig {
+            routes: self.routes(),
+        }
+    }
+
+    /// Restrict a track to `destinations` (None routes it to all peers again)
+    pub fn set_route(&self, track_id: u8, destinations: Option<Vec<String>>) {
+        match destinations {
+            Some(destinations) => {
+                self.routes.insert(track_id, destinations.into_iter().collect());
+            }
+            None => {
+                self.routes.remove(&track_id);
+            }
+        }
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Forget the route of a removed track
+    pub fn remove_track(&self, track_id: u8) {
+        if self.routes.remove(&track_id).is_some() {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Destinations of a track (None = all peers)
+    pub fn route(&self, track_id: u8) -> Option<Vec<String>> {
+        self.routes
+            .get(&track_id)
+            .map(|destinations| destinations.iter().cloned().collect())
+    }
+
+    /// Check whether a track should be sent to a peer
+    pub fn is_routed(&self, track_id: u8, peer_key: &str) -> bool {
+        self.routes
+            .get(&track_id)
+            .map(|destinations| destinations.contains(peer_key))
+            .unwrap_or(true)
+    }
+
+    /// All restricted tracks, ordered by track ID
+    pub fn routes(&self) -> Vec<TrackRoute> {
+        let mut routes: Vec<TrackRoute> = self
+            .routes
+            .iter()
+            .map(|entry| TrackRoute {
+                track_id: *entry.key(),
+                destinations: entry.value().iter().cloned().collect(),
+            })
+            .collect();
+        routes.sort_by_key(|route| route.track_id);
+        routes
+    }
+
+    /// Check and clear the changed flag
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Default for RoutingMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER_A: &str = "192.168.1.10:5000";
+    const PEER_B: &str = "192.168.1.11:5000";
+
+    #[test]
+    fn test_default_routes_to_all_peers() {
+        let matrix = RoutingMatrix::new();
+        assert!(matrix.is_routed(0, PEER_A));
+        assert!(matrix.is_routed(0, PEER_B));
+        assert_eq!(matrix.route(0), None);
+        assert!(!matrix.take_changed());
+    }
+
+    #[test]
+    fn test_restricted_route() {
+        let matrix = RoutingMatrix::new();
+        matrix.set_route(1, Some(vec![PEER_B.to_string()]));
+        assert!(matrix.take_changed());
+        assert!(!matrix.take_changed());
+
+        assert!(!matrix.is_routed(1, PEER_A));
+        assert!(matrix.is_routed(1, PEER_B));
+        // Other tracks are unaffected
+        assert!(matrix.is_routed(0, PEER_A));
+
+        // Empty list keeps the track local
+        matrix.set_route(2, Some(Vec::new()));
+        assert!(!matrix.is_routed(2, PEER_A));
+
+        matrix.set_route(1, None);
+        assert!(matrix.is_routed(1, PEER_A));
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let matrix = RoutingMatrix::new();
+        matrix.set_route(3, Some(vec![PEER_B.to_string(), PEER_A.to_string()]));
+        matrix.set_route(1, Some(Vec::new()));
+
+        let config = matrix.to_config();
+        assert_eq!(config.routes.len(), 2);
+        assert_eq!(config.routes[0].track_id, 1);
+
+        // Persisted as part of the application config file
+        let app_config = crate::config::AppConfig {
+            routing: config,
+            ..Default::default()
+        };
+        let toml = toml::to_string_pretty(&app_config).unwrap();
+        let loaded: crate::config::AppConfig = toml::from_str(&toml).unwrap();
+
+        let restored = RoutingMatrix::from_config(&loaded.routing);
+        assert_eq!(restored.routes(), matrix.routes());
+        assert!(!restored.is_routed(1, PEER_A));
+        assert!(restored.is_routed(3, PEER_A));
+    }
+}
@@ -0,0 +1,343 @@
+//! `--self-test`: the whole chain on one machine
+//!
+//! Streams a test tone through every stage a link between two PCs uses,
+//! inside one process: a `generator:` capture, the Opus encoder, a UDP
+//! sender and receiver over localhost, the decoder and jitter buffer, and
+//! an output mix. The tone carries a latency probe (see `audio::probe`)
+//! every [`PROBE_INTERVAL`]. At the end the measured latency, the packets
+//! lost and the glitches (output underruns, concealed and late frames) are
+//! printed, and the test fails if no audio got through or more than
+//! [`MAX_LOSS_PERCENT`] was lost, so it can gate CI and its output can go
+//! into a bug report.
+//!
+//! The mix is played to the null output unless `--self-test-output` picks a
+//! device, so no sound card is needed.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::audio::buffer::{create_shared_buffer, AudioFrame, JitterBuffer};
+use crate::audio::capture::AudioCapture;
+use crate::audio::mixer::OutputMixer;
+use crate::audio::null_output::NULL_DEVICE_ID;
+use crate::audio::probe::ProbeInjector;
+use crate::codec::{new_decoder, new_encoder, plc::next_frame_concealed};
+use crate::config::{NetworkConfig, OpusConfig};
+use crate::constants::*;
+use crate::error::{Error, Result};
+use crate::network::receiver::AudioReceiver;
+use crate::network::sender::MultiTrackSender;
+use crate::network::timesync::media_time_us;
+use crate::protocol::{Codec, PacketFlags, TrackPriority};
+
+/// Signal streamed through the chain
+pub const SIGNAL: &str = "generator:sine:1000";
+
+/// Default length of the test
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+/// Interval between latency probes
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Loss over localhost above which the test fails
+pub const MAX_LOSS_PERCENT: f32 = 1.0;
+
+/// Track the tone is sent as
+const TRACK_ID: u8 = 0;
+
+/// Options of `--self-test`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestArgs {
+    /// Device the received tone is played to (None = the null output)
+    pub output: Option<String>,
+    pub duration: Duration,
+}
+
+impl Default for SelfTestArgs {
+    fn default() -> Self {
+        Self {
+            output: None,
+            duration: DEFAULT_DURATION,
+        }
+    }
+}
+
+impl SelfTestArgs {
+    fn output_device(&self) -> &str {
+        self.output.as_deref().unwrap_or(NULL_DEVICE_ID)
+    }
+}
+
+/// What the test measured
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SelfTestReport {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// Gaps in the received sequence numbers
+    pub packets_lost: u64,
+    /// Latency of every probe from the encoder to the output stream (ms)
+    pub latencies_ms: Vec<f32>,
+    /// Latency the output device adds on top (None for the null output)
+    pub device_latency_ms: Option<f32>,
+    /// Times the output ran dry
+    pub underruns: u64,
+    /// Frames played from packet loss concealment
+    pub concealed: u64,
+    /// Frames that arrived too late to be played
+    pub late: u64,
+    /// Packets the decoder rejected
+    pub decode_errors: u64,
+}
+
+impl SelfTestReport {
+    pub fn loss_percent(&self) -> f32 {
+        let expected = self.packets_received + self.packets_lost;
+        if expected == 0 {
+            0.0
+        } else {
+            self.packets_lost as f32 * 100.0 / expected as f32
+        }
+    }
+
+    pub fn latency_mean_ms(&self) -> Option<f32> {
+        (!self.latencies_ms.is_empty())
+            .then(|| self.latencies_ms.iter().sum::<f32>() / self.latencies_ms.len() as f32)
+    }
+
+    pub fn latency_max_ms(&self) -> Option<f32> {
+        self.latencies_ms.iter().copied().reduce(f32::max)
+    }
+
+    /// Audible disturbances: underruns, concealed, late and undecodable frames
+    pub fn glitches(&self) -> u64 {
+        self.underruns + self.concealed + self.late + self.decode_errors
+    }
+
+    /// Why the test failed (None = passed)
+    pub fn failure(&self) -> Option<String> {
+        if self.packets_received == 0 {
+            Some("no audio arrived over localhost".to_string())
+        } else if self.latencies_ms.is_empty() {
+            Some("no latency probe reached the output".to_string())
+        } else if self.loss_percent() > MAX_LOSS_PERCENT {
+            Some(format!("{:.1}% of the packets were lost", self.loss_percent()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.latency_mean_ms(), self.latency_max_ms()) {
+            (Some(mean), Some(max)) => writeln!(
+                f,
+                "Latency:  {:.1} ms mean, {:.1} ms max ({} probes)",
+                mean,
+                max,
+                self.latencies_ms.len()
+            )?,
+            _ => writeln!(f, "Latency:  not measured")?,
+        }
+        if let Some(device_ms) = self.device_latency_ms {
+            writeln!(f, "          + {:.1} ms output device", device_ms)?;
+        }
+        writeln!(
+            f,
+            "Packets:  {} sent, {} received, {} lost ({:.2}%)",
+            self.packets_sent,
+            self.packets_received,
+            self.packets_lost,
+            self.loss_percent()
+        )?;
+        write!(
+            f,
+            "Glitches: {} ({} underruns, {} concealed, {} late, {} decode errors)",
+            self.glitches(),
+            self.underruns,
+            self.concealed,
+            self.late,
+            self.decode_errors
+        )
+    }
+}
+
+/// Run the test, print its report and fail if the chain is broken
+pub fn run(args: &SelfTestArgs) -> Result<()> {
+    println!(
+        "Self-test: {} s of {} over localhost to {}...",
+        args.duration.as_secs(),
+        SIGNAL,
+        args.output_device()
+    );
+    let report = measure(args)?;
+    println!("{}", report);
+    match report.failure() {
+        Some(reason) => Err(Error::SelfTest(reason)),
+        None => {
+            println!("Self-test passed");
+            Ok(())
+        }
+    }
+}
+
+/// Stream the tone for `args.duration` and measure it
+pub fn measure(args: &SelfTestArgs) -> Result<SelfTestReport> {
+    // Receiver on a free localhost port, sender on any port
+    let port = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?.port();
+    let mut network = NetworkConfig {
+        bind_address: Ipv4Addr::LOCALHOST.to_string(),
+        udp_port: port,
+        ..NetworkConfig::default()
+    };
+    network.qos.disable();
+
+    let (packet_tx, packet_rx) = crossbeam_channel::bounded(1024);
+    let mut receiver = AudioReceiver::new();
+    receiver.set_global_channel(packet_tx);
+    receiver.start(network.clone())?;
+
+    let mut sender = MultiTrackSender::new(&network, SocketAddr::from((Ipv4Addr::LOCALHOST, port)))?;
+    sender.start(NetworkConfig { udp_port: 0, ..network })?;
+
+    // Receive side: decoder, jitter buffer and the output mix
+    let frame_size = (DEFAULT_SAMPLE_RATE as f32 * DEFAULT_FRAME_SIZE_MS / 1000.0) as usize;
+    let mut decoder = new_decoder(Codec::Opus, DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS, frame_size)?;
+    let mut jitter_buffer = JitterBuffer::new(32, 2);
+    let mixer = OutputMixer::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS);
+    let playback = mixer.attach(TRACK_ID, args.output_device(), None)?;
+
+    // Send side: the tone with probes, encoded like a music track
+    let capture_buffer = create_shared_buffer(RING_BUFFER_CAPACITY);
+    let mut capture = AudioCapture::new(TRACK_ID, SIGNAL, Some(DEFAULT_SAMPLE_RATE), None, None, capture_buffer.clone())?;
+    capture.set_output_channels(DEFAULT_CHANNELS);
+    let mut encoder = new_encoder(Codec::Opus, OpusConfig::music())?;
+    let samples_per_frame = encoder.samples_per_frame();
+    let flags = PacketFlags::new()
+        .set_stereo(DEFAULT_CHANNELS == 2)
+        .set_fec(encoder.fec_enabled())
+        .set_codec(encoder.codec());
+    let mut probe = ProbeInjector::with_interval(DEFAULT_SAMPLE_RATE, PROBE_INTERVAL);
+    let mut pending: Vec<f32> = Vec::with_capacity(samples_per_frame * 2);
+    capture.start()?;
+
+    let mut report = SelfTestReport::default();
+    let mut sequences: Option<(u32, u32)> = None;
+    let mut last_probe = None;
+    let deadline = Instant::now() + args.duration;
+
+    while Instant::now() < deadline {
+        while let Some(frame) = capture_buffer.try_pop() {
+            pending.extend_from_slice(&frame.samples);
+            while pending.len() >= samples_per_frame {
+                let mut samples: Vec<f32> = pending.drain(..samples_per_frame).collect();
+                if probe.inject(&mut samples, DEFAULT_CHANNELS as usize) {
+                    sender.mark_probe(TRACK_ID);
+                }
+                let encoded = encoder.encode(&samples)?;
+                sender.send_audio(TRACK_ID, encoded, media_time_us(), flags, TrackPriority::Normal, false)?;
+                report.packets_sent += 1;
+            }
+        }
+
+        let mut next = packet_rx.recv_timeout(Duration::from_millis(1)).ok();
+        while let Some(packet) = next {
+            report.packets_received += 1;
+            sequences = Some(match sequences {
+                Some((first, last)) => (first.min(packet.sequence), last.max(packet.sequence)),
+                None => (packet.sequence, packet.sequence),
+            });
+
+            match decoder.decode(&packet.payload) {
+                Ok(samples) => {
+                    let mut frame = AudioFrame::new(samples, decoder.channels(), packet.timestamp, packet.sequence);
+                    // Both ends share the media clock: the timestamp is the send time
+                    if packet.is_probe {
+                        frame.probe_us = Some(packet.timestamp);
+                    }
+                    if !jitter_buffer.insert(frame) {
+                        report.late += 1;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Self-test packet {} not decoded: {}", packet.sequence, e);
+                    report.decode_errors += 1;
+                }
+            }
+            while let Some(frame) = next_frame_concealed(decoder.as_mut(), &mut jitter_buffer) {
+                playback.push_frame(frame);
+            }
+            next = packet_rx.try_recv().ok();
+        }
+
+        let meter = playback.probe_meter();
+        if let Some(played) = meter.last_played_us().filter(|played| last_probe != Some(*played)) {
+            last_probe = Some(played);
+            if let Some(latency_us) = meter.output_latency_us() {
+                report.latencies_ms.push(latency_us as f32 / 1000.0);
+            }
+        }
+    }
+
+    capture.stop();
+    sender.stop();
+    receiver.stop();
+
+    if let Some((first, last)) = sequences {
+        let expected = (last - first) as u64 + 1;
+        report.packets_lost = expected.saturating_sub(report.packets_received);
+    }
+    report.underruns = playback.take_underruns();
+    report.concealed = jitter_buffer.stats().concealed as u64;
+    report.device_latency_ms = playback.device_latency_ms();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = SelfTestReport {
+            packets_sent: 1000,
+            packets_received: 990,
+            packets_lost: 10,
+            latencies_ms: vec![30.0, 40.0, 35.0],
+            underruns: 2,
+            concealed: 10,
+            ..Default::default()
+        };
+        assert_eq!(report.loss_percent(), 1.0);
+        assert_eq!((report.latency_mean_ms(), report.latency_max_ms()), (Some(35.0), Some(40.0)));
+        assert_eq!(report.glitches(), 12);
+        assert_eq!(report.failure(), None);
+        let text = report.to_string();
+        assert!(text.contains("35.0 ms mean, 40.0 ms max (3 probes)"), "{}", text);
+        assert!(text.contains("12 (2 underruns, 10 concealed"), "{}", text);
+
+        report.packets_lost = 20;
+        assert!(report.failure().unwrap().contains("2.0%"));
+        report.latencies_ms.clear();
+        assert_eq!(report.failure().as_deref(), Some("no latency probe reached the output"));
+        assert!(report.to_string().starts_with("Latency:  not measured"));
+        assert_eq!(SelfTestReport::default().failure().as_deref(), Some("no audio arrived over localhost"));
+    }
+
+    #[test]
+    fn test_loopback_to_null_output() {
+        let args = SelfTestArgs {
+            output: None,
+            duration: Duration::from_secs(2),
+        };
+        let report = measure(&args).unwrap();
+
+        // 10 ms frames for 2 s, all of them through localhost
+        assert!(report.packets_sent >= 150, "{:?}", report);
+        assert!(report.packets_received >= 150, "{:?}", report);
+        assert_eq!(report.failure(), None, "{:?}", report);
+        assert_eq!(report.device_latency_ms, None);
+        assert!(report.latencies_ms.iter().all(|&ms| ms > 0.0 && ms < 500.0), "{:?}", report);
+    }
+}
@@ -0,0 +1,559 @@
+//! Running the receiver at boot
+//!
+//! `receiver --install-service` registers the receiver with the platform's
+//! service manager, with the rest of its command line: a Windows service
+//! started at boot, or a systemd user unit on Linux (started at login, or at
+//! boot once `loginctl enable-linger` is set). `--uninstall-service` removes
+//! it again. The service runs `receiver --service ...`, which logs to the
+//! Windows event log or the systemd journal and stops gracefully when the
+//! service manager (or Ctrl+C) asks it to. A `--psk` on the command line
+//! stays out of the systemd unit: it goes to an environment file only the
+//! user can read, which the unit loads.
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::error::{Error, Result};
+
+/// Name of the systemd unit (`lan-audio-receiver.service`)
+pub const SERVICE_NAME: &str = "lan-audio-receiver";
+
+/// Name of the Windows service
+pub const WINDOWS_SERVICE_NAME: &str = "LanAudioReceiver";
+
+const DISPLAY_NAME: &str = "LAN Audio Receiver";
+#[cfg(windows)]
+const DESCRIPTION: &str = "Receives audio tracks from LAN Audio senders and plays them";
+
+/// Options that install or remove the service, left out of its command line
+const SERVICE_FLAGS: [&str; 3] = ["--install-service", "--uninstall-service", "--service"];
+
+/// Register or remove the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceAction {
+    Install,
+    Uninstall,
+}
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether the process was asked to stop
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::Relaxed)
+}
+
+/// Ask the process to stop
+pub fn request_shutdown() {
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+/// Request shutdown on Ctrl+C, and on SIGTERM (how systemd stops a unit)
+pub fn spawn_signal_listener() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let (Ok(mut interrupt), Ok(mut terminate)) = (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) else {
+                tracing::warn!("Failed to listen for termination signals");
+                return;
+            };
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        #[cfg(not(unix))]
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        tracing::info!("Stopping...");
+        request_shutdown();
+    })
+}
+
+/// Install or uninstall the service of the receiver; `config` is the
+/// configuration file the service is to use
+pub fn run(action: ServiceAction, config: Option<&Path>) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    match action {
+        ServiceAction::Install => {
+            let config = config.map(std::path::absolute).transpose()?;
+            let args = service_args(std::env::args_os().skip(1), config.as_deref());
+            platform::install(&exe, &args)
+        }
+        ServiceAction::Uninstall => platform::uninstall(),
+    }
+}
+
+/// Command line of the service: this one without the service options and
+/// `--config`, which is given as an absolute path (the service doesn't
+/// run in this directory, nor always as this user on Windows)
+pub fn service_args(args: impl IntoIterator<Item = OsString>, config: Option<&Path>) -> Vec<OsString> {
+    let mut service = vec![OsString::from("--service")];
+    if let Some(config) = config {
+        service.extend([OsString::from("--config"), config.as_os_str().to_owned()]);
+    }
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text == "--config" || text == "-c" {
+            args.next();
+        } else if !(text.starts_with("--config=") || (text.starts_with("-c") && !text.starts_with("--"))
+            || SERVICE_FLAGS.contains(&text.as_ref()))
+        {
+            service.push(arg);
+        }
+    }
+    service
+}
+
+/// Logging of the receiver: to the systemd journal or the Windows event log
+/// when running as a service, else to the terminal
+pub fn init_logging(as_service: bool) {
+    let filter = tracing_subscriber::EnvFilter::new(std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()));
+    let platform = as_service.then(platform::log_layer).flatten();
+    let terminal = platform.is_none().then(tracing_subscriber::fmt::layer);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(platform)
+        .with(terminal)
+        .with(crate::logs::layer())
+        .init();
+}
+
+/// Hand the process over to the Windows service manager (`--service`);
+/// elsewhere the service manager needs nothing from the process
+pub fn start_dispatcher() -> Result<()> {
+    platform::start_dispatcher()
+}
+
+/// Tell the service manager the receiver has stopped
+pub fn stopped() {
+    platform::stopped();
+}
+
+/// Path of the systemd user unit
+#[cfg(target_os = "linux")]
+pub fn unit_path() -> Option<std::path::PathBuf> {
+    directories::BaseDirs::new()
+        .map(|dirs| dirs.config_dir().join("systemd").join("user").join(format!("{}.service", SERVICE_NAME)))
+}
+
+/// Path of the environment file holding the PSK of the unit
+#[cfg(target_os = "linux")]
+pub fn environment_path() -> Option<std::path::PathBuf> {
+    unit_path().map(|path| path.with_extension("env"))
+}
+
+/// Split `--psk KEY` (or `-k KEY`, `--psk=KEY`, `-kKEY`) off a command
+/// line: the rest of the arguments and the key
+pub fn take_psk(args: &[OsString]) -> (Vec<OsString>, Option<OsString>) {
+    let mut rest = Vec::with_capacity(args.len());
+    let mut psk = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text == "--psk" || text == "-k" {
+            psk = args.next().cloned();
+        } else if let Some(key) = text.strip_prefix("--psk=") {
+            psk = Some(OsString::from(key));
+        } else if let Some(key) = text.strip_prefix("-k").filter(|key| !key.is_empty()) {
+            psk = Some(OsString::from(key));
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    (rest, psk)
+}
+
+/// systemd user unit running `exe` with `args`, loading the variables of
+/// `environment` (a file) if given
+#[cfg(target_os = "linux")]
+pub fn unit_file(exe: &Path, args: &[OsString], environment: Option<&Path>) -> String {
+    let command: Vec<String> = std::iter::once(exe.as_os_str())
+        .chain(args.iter().map(OsString::as_os_str))
+        .map(|arg| unit_quote(&arg.to_string_lossy()))
+        .collect();
+    let environment = environment
+        .map(|path| format!("EnvironmentFile={}\n", path.to_string_lossy().replace('%', "%%")))
+        .unwrap_or_default();
+    format!(
+        "[Unit]\n\
+         Description={DISPLAY_NAME}\n\
+         After=pipewire.service pulseaudio.service\n\
+         \n\
+         [Service]\n\
+         {environment}\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         TimeoutStopSec=15\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        command.join(" ")
+    )
+}
+
+/// Environment file giving the receiver its PSK
+#[cfg(target_os = "linux")]
+pub fn environment_file(psk: &str) -> String {
+    let escaped = psk.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("{}=\"{}\"\n", crate::constants::PSK_ENV_VAR, escaped)
+}
+
+/// Quote an argument of `ExecStart=`
+#[cfg(target_os = "linux")]
+fn unit_quote(arg: &str) -> String {
+    let escaped = arg.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%").replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != arg {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::process::Command;
+
+    pub fn install(exe: &Path, args: &[OsString]) -> Result<()> {
+        let path = unit_path().ok_or_else(|| Error::Service("no home directory for the user unit".to_string()))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let (args, psk) = take_psk(args);
+        let environment = match psk {
+            Some(psk) => {
+                let environment = environment_path().unwrap_or_else(|| path.with_extension("env"));
+                write_private(&environment, &environment_file(&psk.to_string_lossy()))?;
+                println!("Wrote the PSK to {}", environment.display());
+                Some(environment)
+            }
+            None => None,
+        };
+        std::fs::write(&path, unit_file(exe, &args, environment.as_deref()))?;
+        println!("Wrote {}", path.display());
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", &format!("{}.service", SERVICE_NAME)])?;
+        println!("Started {}; it starts at login from now on", SERVICE_NAME);
+        println!("To start it at boot without logging in: loginctl enable-linger");
+        println!("Logs: journalctl --user -u {}", SERVICE_NAME);
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<()> {
+        let path = unit_path().ok_or_else(|| Error::Service("no home directory for the user unit".to_string()))?;
+        if !path.exists() {
+            return Err(Error::Service(format!("{} is not installed", path.display())));
+        }
+        if let Err(e) = systemctl(&["disable", "--now", &format!("{}.service", SERVICE_NAME)]) {
+            tracing::warn!("{}", e);
+        }
+        std::fs::remove_file(&path)?;
+        if let Some(environment) = environment_path().filter(|environment| environment.exists()) {
+            std::fs::remove_file(&environment)?;
+        }
+        systemctl(&["daemon-reload"])?;
+        println!("Removed {}", path.display());
+        Ok(())
+    }
+
+    /// Write a file only its owner can read (0600, also when it exists)
+    fn write_private(path: &Path, contents: &str) -> Result<()> {
+        use std::io::Write;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    fn systemctl(args: &[&str]) -> Result<()> {
+        let status = Command::new("systemctl")
+            .arg("--user")
+            .args(args)
+            .status()
+            .map_err(|e| Error::Service(format!("systemctl: {}", e)))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::Service(format!("systemctl --user {} failed ({})", args.join(" "), status)))
+        }
+    }
+
+    pub fn log_layer() -> Option<tracing_journald::Layer> {
+        match tracing_journald::layer() {
+            Ok(layer) => Some(layer.with_syslog_identifier(SERVICE_NAME.to_string())),
+            Err(e) => {
+                eprintln!("The systemd journal is not available ({}), logging to stderr", e);
+                None
+            }
+        }
+    }
+
+    pub fn start_dispatcher() -> Result<()> {
+        Ok(())
+    }
+
+    pub fn stopped() {}
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+    use parking_lot::{Condvar, Mutex};
+    use std::ffi::OsStr;
+    use std::time::Duration;
+    use tracing::{field::Field, Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::EventLog::{
+        RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+    };
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceDependency, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    fn service_error(e: windows_service::Error) -> Error {
+        Error::Service(e.to_string())
+    }
+
+    pub fn install(exe: &Path, args: &[OsString]) -> Result<()> {
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .map_err(service_error)?;
+        let info = ServiceInfo {
+            name: OsString::from(WINDOWS_SERVICE_NAME),
+            display_name: OsString::from(DISPLAY_NAME),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: exe.to_path_buf(),
+            launch_arguments: args.to_vec(),
+            // Windows Audio
+            dependencies: vec![ServiceDependency::Service(OsString::from("Audiosrv"))],
+            account_name: None,
+            account_password: None,
+        };
+        let service = manager
+            .create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
+            .map_err(service_error)?;
+        service.set_description(DESCRIPTION).map_err(service_error)?;
+        service.start(&[] as &[&OsStr]).map_err(service_error)?;
+        println!("Installed and started the {} service; it starts at boot", WINDOWS_SERVICE_NAME);
+        println!("Logs: Event Viewer, Windows Logs > Application, source {}", WINDOWS_SERVICE_NAME);
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<()> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).map_err(service_error)?;
+        let service = manager
+            .open_service(WINDOWS_SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)
+            .map_err(service_error)?;
+        if service.query_status().map_err(service_error)?.current_state != ServiceState::Stopped {
+            service.stop().map_err(service_error)?;
+        }
+        service.delete().map_err(service_error)?;
+        println!("Removed the {} service", WINDOWS_SERVICE_NAME);
+        Ok(())
+    }
+
+    /// Log lines as events of the Application log
+    pub struct EventLogLayer {
+        source: HANDLE,
+    }
+
+    struct MessageVisitor<'a>(&'a mut String);
+
+    impl tracing::field::Visit for MessageVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            use std::fmt::Write;
+            if field.name() == "message" {
+                let _ = write!(self.0, "{:?}", value);
+            } else {
+                let _ = write!(self.0, " {}={:?}", field.name(), value);
+            }
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for EventLogLayer {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let kind = match *event.metadata().level() {
+                tracing::Level::ERROR => EVENTLOG_ERROR_TYPE,
+                tracing::Level::WARN => EVENTLOG_WARNING_TYPE,
+                _ => EVENTLOG_INFORMATION_TYPE,
+            };
+            let mut message = String::new();
+            event.record(&mut MessageVisitor(&mut message));
+            let message = HSTRING::from(message);
+            let strings = [PCWSTR(message.as_ptr())];
+            unsafe {
+                let _ = ReportEventW(self.source, kind, 0, 0, None, 0, Some(&strings), None);
+            }
+        }
+    }
+
+    pub fn log_layer() -> Option<EventLogLayer> {
+        match unsafe { RegisterEventSourceW(PCWSTR::null(), &HSTRING::from(WINDOWS_SERVICE_NAME)) } {
+            Ok(source) => Some(EventLogLayer { source }),
+            Err(e) => {
+                eprintln!("The event log is not available ({}), logging to stderr", e);
+                None
+            }
+        }
+    }
+
+    /// Set once the receiver has stopped, for the service's status
+    static STOPPED: Mutex<bool> = Mutex::new(false);
+    static STOPPED_CHANGED: Condvar = Condvar::new();
+    /// Thread of the service dispatcher, done once it reported the stop
+    static DISPATCHER: Mutex<Option<std::thread::JoinHandle<()>>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    pub fn start_dispatcher() -> Result<()> {
+        let thread = std::thread::Builder::new().name("service-dispatcher".to_string()).spawn(|| {
+            if let Err(e) = service_dispatcher::start(WINDOWS_SERVICE_NAME, ffi_service_main) {
+                tracing::error!("Not started by the service manager: {}", e);
+                request_shutdown();
+            }
+        })?;
+        *DISPATCHER.lock() = Some(thread);
+        Ok(())
+    }
+
+    fn service_main(_args: Vec<OsString>) {
+        let handler = |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                tracing::info!("Stop requested by the service manager");
+                request_shutdown();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let status_handle = match service_control_handler::register(WINDOWS_SERVICE_NAME, handler) {
+            Ok(handle) => handle,
+            Err(e) => {
+                tracing::error!("Failed to register the service control handler: {}", e);
+                request_shutdown();
+                return;
+            }
+        };
+        let status = |state, controls_accepted| ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::from_secs(15),
+            process_id: None,
+        };
+        let _ = status_handle.set_service_status(status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        ));
+
+        let mut stopped = STOPPED.lock();
+        while !*stopped {
+            STOPPED_CHANGED.wait(&mut stopped);
+        }
+        drop(stopped);
+        let _ = status_handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty()));
+    }
+
+    pub fn stopped() {
+        *STOPPED.lock() = true;
+        STOPPED_CHANGED.notify_all();
+        if let Some(thread) = DISPATCHER.lock().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use super::*;
+
+    pub fn install(_exe: &Path, _args: &[OsString]) -> Result<()> {
+        Err(Error::Service("services can be installed on Windows and Linux (systemd)".to_string()))
+    }
+
+    pub fn uninstall() -> Result<()> {
+        install(Path::new(""), &[])
+    }
+
+    pub fn log_layer() -> Option<tracing_subscriber::layer::Identity> {
+        None
+    }
+
+    pub fn start_dispatcher() -> Result<()> {
+        Ok(())
+    }
+
+    pub fn stopped() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_service_args() {
+        let config = Path::new("/home/pi/.config/lan-audio/config.toml");
+        assert_eq!(
+            service_args(args(&["--install-service", "-c", "config.toml", "--port", "5100"]), Some(config)),
+            args(&["--service", "--config", "/home/pi/.config/lan-audio/config.toml", "--port", "5100"])
+        );
+        assert_eq!(
+            service_args(args(&["recv", "--config=a.toml", "-cb.toml", "--install-service", "--quiet"]), None),
+            args(&["--service", "recv", "--quiet"])
+        );
+    }
+
+    #[test]
+    fn test_take_psk() {
+        for psk in [&["--psk", "50%$ecret"][..], &["-k", "50%$ecret"], &["--psk=50%$ecret"], &["-k50%$ecret"]] {
+            let command: Vec<&str> = ["--service"].iter().chain(psk).chain(&["--quiet"]).copied().collect();
+            assert_eq!(take_psk(&args(&command)), (args(&["--service", "--quiet"]), Some(OsString::from("50%$ecret"))));
+        }
+        assert_eq!(take_psk(&args(&["--service", "--quiet"])), (args(&["--service", "--quiet"]), None));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_unit_file() {
+        let (service, psk) = take_psk(&args(&["--service", "--psk", "50%$ecret", "--name", "50% \"mix\""]));
+        let environment = Path::new("/home/pi/.config/systemd/user/lan-audio-receiver.env");
+        let unit = unit_file(Path::new("/opt/lan audio/receiver"), &service, Some(environment));
+        assert!(unit.contains(r#"ExecStart="/opt/lan audio/receiver" --service --name "50%% \"mix\"""#));
+        assert!(unit.contains("EnvironmentFile=/home/pi/.config/systemd/user/lan-audio-receiver.env\n"));
+        assert!(unit.contains("WantedBy=default.target"));
+
+        // The PSK is only in the environment file
+        assert!(!unit.contains("ecret") && !unit.contains("--psk"));
+        assert_eq!(environment_file(&psk.unwrap().to_string_lossy()), "LAN_AUDIO_PSK=\"50%$ecret\"\n");
+        assert_eq!(environment_file(r#"a"b\c"#), "LAN_AUDIO_PSK=\"a\\\"b\\\\c\"\n");
+        assert!(!unit_file(Path::new("/usr/bin/receiver"), &args(&["--service"]), None).contains("EnvironmentFile"));
+    }
+}
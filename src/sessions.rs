@@ -0,0 +1,511 @@
+//! History of streaming sessions
+//!
+//! A session with a peer runs from the handshake (the peer becomes
+//! `Connected`) until it says goodbye, stops answering or is removed. Fed
+//! from the per-second statistics sampling (see `stats`), the store sums up
+//! every session: duration, loss, jitter, bitrate, interruptions and
+//! glitches. Finished sessions are appended as JSON lines to a file in the
+//! data directory (`AppConfig::sessions_path`), so they survive restarts
+//! and `GET /api/sessions` can show whether yesterday's complaints line up
+//! with network problems.
+//!
+//! Tracks carry no source peer: loss, jitter and glitches of a session
+//! cover every track sent or received while it ran, which is exact for the
+//! usual two-PC setup.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::network::timesync::unix_time_ms;
+use crate::protocol::{PeerConnection, PeerStatus, TrackStatus};
+
+/// Finished sessions kept in memory and in the file
+pub const MAX_SESSIONS: usize = 1000;
+
+/// Why a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionEnd {
+    /// The peer sent Goodbye
+    Left,
+    /// The peer stopped answering pings
+    Lost,
+    /// The peer was disconnected or removed
+    Removed,
+    /// This peer shut down
+    Shutdown,
+}
+
+/// Audio glitches during a session
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionGlitches {
+    /// Packets lost in the network
+    pub lost: u64,
+    /// Frames that arrived too late to be played
+    pub late: u64,
+    /// Times an output ran dry
+    pub underflow: u64,
+    /// Packets the decoder rejected
+    pub decode_error: u64,
+}
+
+impl SessionGlitches {
+    /// Glitches a listener hears besides concealed losses
+    pub fn audible(&self) -> u64 {
+        self.late + self.underflow + self.decode_error
+    }
+}
+
+/// Summary of one session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// Peer key ("ip:audio_port")
+    pub peer_id: String,
+    pub peer_name: String,
+    /// Start and end (ms since the Unix epoch)
+    pub started_ms: u64,
+    pub ended_ms: u64,
+    pub duration_secs: u64,
+    /// None while the session runs
+    pub end: Option<SessionEnd>,
+    /// Share of the packets lost over the session (%)
+    pub avg_loss_percent: f32,
+    /// Worst second
+    pub max_loss_percent: f32,
+    pub avg_jitter_ms: f32,
+    pub max_jitter_ms: f32,
+    pub avg_up_kbps: f32,
+    pub avg_down_kbps: f32,
+    /// Times the peer stopped answering for a while
+    pub interruptions: u32,
+    pub packets: u64,
+    pub glitches: SessionGlitches,
+}
+
+/// Response of `GET /api/sessions`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionsReport {
+    /// Sessions running now
+    pub active: Vec<SessionRecord>,
+    /// Finished sessions, newest first
+    pub finished: Vec<SessionRecord>,
+}
+
+/// Totals of a running session
+struct OpenSession {
+    record: SessionRecord,
+    /// Seconds sampled, and those with track packets
+    seconds: u64,
+    track_seconds: u64,
+    jitter_sum: f32,
+    up_sum: f32,
+    down_sum: f32,
+    interrupted: bool,
+}
+
+impl OpenSession {
+    fn new(peer: &PeerStatus, time_ms: u64) -> Self {
+        Self {
+            record: SessionRecord {
+                peer_id: peer.id.clone(),
+                peer_name: peer.name.clone(),
+                started_ms: time_ms,
+                ended_ms: time_ms,
+                duration_secs: 0,
+                end: None,
+                avg_loss_percent: 0.0,
+                max_loss_percent: 0.0,
+                avg_jitter_ms: 0.0,
+                max_jitter_ms: 0.0,
+                avg_up_kbps: 0.0,
+                avg_down_kbps: 0.0,
+                interruptions: 0,
+                packets: 0,
+                glitches: SessionGlitches::default(),
+            },
+            seconds: 0,
+            track_seconds: 0,
+            jitter_sum: 0.0,
+            up_sum: 0.0,
+            down_sum: 0.0,
+            interrupted: false,
+        }
+    }
+
+    fn add(&mut self, time_ms: u64, peer: &PeerStatus, second: &TrackSecond) {
+        let record = &mut self.record;
+        record.peer_name = peer.name.clone();
+        record.ended_ms = time_ms;
+        record.duration_secs = time_ms.saturating_sub(record.started_ms) / 1000;
+
+        let interrupted = peer.connection == PeerConnection::Interrupted;
+        if interrupted && !self.interrupted {
+            record.interruptions += 1;
+        }
+        self.interrupted = interrupted;
+
+        self.seconds += 1;
+        self.up_sum += peer.bandwidth.up_kbps;
+        self.down_sum += peer.bandwidth.down_kbps;
+        record.avg_up_kbps = self.up_sum / self.seconds as f32;
+        record.avg_down_kbps = self.down_sum / self.seconds as f32;
+
+        record.packets += second.packets;
+        record.glitches.lost += second.glitches.lost;
+        record.glitches.late += second.glitches.late;
+        record.glitches.underflow += second.glitches.underflow;
+        record.glitches.decode_error += second.glitches.decode_error;
+        let expected = record.packets + record.glitches.lost;
+        if expected > 0 {
+            record.avg_loss_percent = record.glitches.lost as f32 * 100.0 / expected as f32;
+        }
+        if second.packets + second.glitches.lost > 0 {
+            let loss_percent = second.glitches.lost as f32 * 100.0 / (second.packets + second.glitches.lost) as f32;
+            record.max_loss_percent = record.max_loss_percent.max(loss_percent);
+            self.track_seconds += 1;
+            self.jitter_sum += second.jitter_ms;
+            record.avg_jitter_ms = self.jitter_sum / self.track_seconds as f32;
+            record.max_jitter_ms = record.max_jitter_ms.max(second.jitter_ms);
+        }
+    }
+
+    fn finish(mut self, time_ms: u64, end: SessionEnd) -> SessionRecord {
+        self.record.ended_ms = time_ms.max(self.record.ended_ms);
+        self.record.duration_secs = self.record.ended_ms.saturating_sub(self.record.started_ms) / 1000;
+        self.record.end = Some(end);
+        self.record
+    }
+}
+
+/// What all tracks did in one second
+#[derive(Default)]
+struct TrackSecond {
+    packets: u64,
+    glitches: SessionGlitches,
+    /// Mean jitter of the tracks with packets
+    jitter_ms: f32,
+}
+
+/// Counters of a track at the previous sample
+struct TrackCounters {
+    packets: u64,
+    glitches: SessionGlitches,
+}
+
+impl TrackCounters {
+    fn of(track: &TrackStatus) -> Self {
+        Self {
+            packets: track.packets_sent.max(track.packets_received),
+            glitches: SessionGlitches {
+                lost: track.packets_lost,
+                late: track.drops.late,
+                underflow: track.drops.underflow,
+                decode_error: track.drops.decode_error,
+            },
+        }
+    }
+}
+
+/// Running and finished sessions, and the file they are saved to
+pub struct SessionStore {
+    /// None keeps the history in memory only
+    path: Option<PathBuf>,
+    open: Mutex<BTreeMap<String, OpenSession>>,
+    tracks: Mutex<BTreeMap<u8, TrackCounters>>,
+    finished: Mutex<VecDeque<SessionRecord>>,
+}
+
+impl SessionStore {
+    /// A history that is not saved
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            open: Mutex::new(BTreeMap::new()),
+            tracks: Mutex::new(BTreeMap::new()),
+            finished: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Load the history saved in `path` and append new sessions to it
+    ///
+    /// Unreadable lines are skipped; a file grown past [`MAX_SESSIONS`] is
+    /// cut down to the newest ones.
+    pub fn open(path: &Path) -> Self {
+        let mut finished = VecDeque::new();
+        let mut lines = 0;
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                    lines += 1;
+                    match serde_json::from_str::<SessionRecord>(line) {
+                        Ok(record) => finished.push_back(record),
+                        Err(e) => tracing::warn!("Skipping a session in {}: {}", path.display(), e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Session history {} not read: {}", path.display(), e),
+        }
+        while finished.len() > MAX_SESSIONS {
+            finished.pop_front();
+        }
+        if lines > MAX_SESSIONS * 2 {
+            let content: String = finished
+                .iter()
+                .filter_map(|record| serde_json::to_string(record).ok())
+                .map(|line| line + "\n")
+                .collect();
+            if let Err(e) = std::fs::write(path, content) {
+                tracing::warn!("Session history {} not compacted: {}", path.display(), e);
+            }
+        }
+
+        Self {
+            path: Some(path.to_path_buf()),
+            finished: Mutex::new(finished),
+            ..Self::new()
+        }
+    }
+
+    /// File the history is saved to
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Add a second of statistics: sessions start with connected peers and
+    /// end with peers that left, were lost or are gone
+    pub fn record(&self, time_ms: u64, tracks: &[TrackStatus], peers: &[PeerStatus]) {
+        let second = self.track_second(tracks);
+
+        let mut open = self.open.lock();
+        let mut ended = Vec::new();
+        for peer in peers {
+            let end = match peer.connection {
+                PeerConnection::Left => Some(SessionEnd::Left),
+                PeerConnection::Lost => Some(SessionEnd::Lost),
+                _ if !peer.active => Some(SessionEnd::Removed),
+                _ => None,
+            };
+            match (open.remove(&peer.id), end) {
+                (Some(session), Some(end)) => ended.push(session.finish(time_ms, end)),
+                (Some(mut session), None) => {
+                    session.add(time_ms, peer, &second);
+                    open.insert(peer.id.clone(), session);
+                }
+                (None, None) if peer.connection == PeerConnection::Connected => {
+                    let mut session = OpenSession::new(peer, time_ms);
+                    session.add(time_ms, peer, &second);
+                    open.insert(peer.id.clone(), session);
+                }
+                (None, _) => {}
+            }
+        }
+        let gone: Vec<String> = open
+            .keys()
+            .filter(|id| !peers.iter().any(|peer| &peer.id == *id))
+            .cloned()
+            .collect();
+        for id in gone {
+            if let Some(session) = open.remove(&id) {
+                ended.push(session.finish(time_ms, SessionEnd::Removed));
+            }
+        }
+        drop(open);
+
+        for record in ended {
+            self.save(record);
+        }
+    }
+
+    /// End every running session (on shutdown)
+    pub fn finish_all(&self) {
+        let time_ms = unix_time_ms();
+        let open = std::mem::take(&mut *self.open.lock());
+        for session in open.into_values() {
+            self.save(session.finish(time_ms, SessionEnd::Shutdown));
+        }
+    }
+
+    /// Running sessions and finished ones that ended after `since_ms`,
+    /// optionally of one peer
+    pub fn report(&self, since_ms: Option<u64>, peer: Option<&str>) -> SessionsReport {
+        let of_peer = |record: &SessionRecord| peer.is_none_or(|peer| record.peer_id == peer || record.peer_name == peer);
+        SessionsReport {
+            active: self
+                .open
+                .lock()
+                .values()
+                .map(|session| session.record.clone())
+                .filter(|record| of_peer(record))
+                .collect(),
+            finished: self
+                .finished
+                .lock()
+                .iter()
+                .rev()
+                .filter(|record| record.ended_ms > since_ms.unwrap_or(0) && of_peer(record))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Sum up the tracks since the previous sample
+    fn track_second(&self, tracks: &[TrackStatus]) -> TrackSecond {
+        let mut counters = self.tracks.lock();
+        let mut second = TrackSecond::default();
+        let mut jitter_tracks = 0;
+        for track in tracks {
+            let now = TrackCounters::of(track);
+            // Counters in place before the first sample aren't counted
+            let Some(last) = counters.insert(track.track_id, TrackCounters::of(track)) else {
+                continue;
+            };
+            // Counters restart with a recreated track
+            let packets = now.packets.saturating_sub(last.packets);
+            let lost = now.glitches.lost.saturating_sub(last.glitches.lost);
+            second.packets += packets;
+            second.glitches.lost += lost;
+            second.glitches.late += now.glitches.late.saturating_sub(last.glitches.late);
+            second.glitches.underflow += now.glitches.underflow.saturating_sub(last.glitches.underflow);
+            second.glitches.decode_error += now.glitches.decode_error.saturating_sub(last.glitches.decode_error);
+            if packets + lost > 0 {
+                second.jitter_ms += track.jitter_ms;
+                jitter_tracks += 1;
+            }
+        }
+        counters.retain(|track_id, _| tracks.iter().any(|track| track.track_id == *track_id));
+        if jitter_tracks > 0 {
+            second.jitter_ms /= jitter_tracks as f32;
+        }
+        second
+    }
+
+    /// Keep a finished session and append it to the file
+    fn save(&self, record: SessionRecord) {
+        tracing::info!(
+            "Session with {} ended ({:?}) after {} s: {:.1}% loss, {:.1} ms jitter, {} glitches",
+            record.peer_name,
+            record.end,
+            record.duration_secs,
+            record.avg_loss_percent,
+            record.avg_jitter_ms,
+            record.glitches.audible()
+        );
+        if let Some(path) = &self.path {
+            if let Err(e) = append(path, &record) {
+                tracing::warn!("Session not saved to {}: {}", path.display(), e);
+            }
+        }
+        let mut finished = self.finished.lock();
+        finished.push_back(record);
+        if finished.len() > MAX_SESSIONS {
+            finished.pop_front();
+        }
+    }
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn append(path: &Path, record: &SessionRecord) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PeerBandwidth, TrackConfig};
+    use crate::tracks::Track;
+
+    fn peer(connection: PeerConnection, down_kbps: f32) -> PeerStatus {
+        PeerStatus {
+            id: "192.168.1.20:5000".to_string(),
+            name: "studio".to_string(),
+            address: "192.168.1.20:5000".to_string(),
+            paths: Vec::new(),
+            active: true,
+            connection,
+            last_seen_ms: 0,
+            bandwidth: PeerBandwidth {
+                down_kbps,
+                ..PeerBandwidth::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_session_summary() {
+        let store = SessionStore::new();
+        let track = Track::new(1, TrackConfig::default());
+
+        store.record(1_000, &[track.status()], &[peer(PeerConnection::Connecting, 0.0)]);
+        assert!(store.report(None, None).active.is_empty());
+        store.record(2_000, &[track.status()], &[peer(PeerConnection::Connected, 0.0)]);
+        for _ in 0..90 {
+            track.increment_packets();
+        }
+        for _ in 0..10 {
+            track.increment_lost();
+        }
+        store.record(3_000, &[track.status()], &[peer(PeerConnection::Connected, 200.0)]);
+        for _ in 0..100 {
+            track.increment_packets();
+        }
+        store.record(4_000, &[track.status()], &[peer(PeerConnection::Interrupted, 100.0)]);
+        store.record(5_000, &[track.status()], &[peer(PeerConnection::Connected, 0.0)]);
+
+        let active = store.report(None, None).active;
+        assert_eq!(active.len(), 1);
+        assert_eq!((active[0].duration_secs, active[0].interruptions), (3, 1));
+
+        store.record(6_000, &[track.status()], &[peer(PeerConnection::Lost, 0.0)]);
+        let report = store.report(None, None);
+        assert!(report.active.is_empty());
+        let session = &report.finished[0];
+        assert_eq!(session.end, Some(SessionEnd::Lost));
+        assert_eq!((session.started_ms, session.duration_secs), (2_000, 4));
+        assert_eq!((session.packets, session.glitches.lost), (190, 10));
+        assert_eq!(session.avg_loss_percent, 5.0);
+        assert_eq!(session.max_loss_percent, 10.0);
+        assert_eq!(session.avg_down_kbps, 75.0);
+
+        // Filters
+        assert!(store.report(Some(6_000), None).finished.is_empty());
+        assert_eq!(store.report(None, Some("studio")).finished.len(), 1);
+        assert!(store.report(None, Some("office")).finished.is_empty());
+    }
+
+    #[test]
+    fn test_sessions_persist() {
+        let path = std::env::temp_dir().join(format!("lan-audio-sessions-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = SessionStore::open(&path);
+        store.record(1_000, &[], &[peer(PeerConnection::Connected, 0.0)]);
+        store.record(2_000, &[], &[peer(PeerConnection::Connected, 0.0)]);
+        // The peer disappears from the registry
+        store.record(3_000, &[], &[]);
+        store.record(4_000, &[], &[peer(PeerConnection::Connected, 0.0)]);
+        store.finish_all();
+        drop(store);
+
+        let reopened = SessionStore::open(&path);
+        let finished = reopened.report(None, None).finished;
+        assert_eq!(finished.len(), 2);
+        assert_eq!(finished[0].end, Some(SessionEnd::Shutdown));
+        assert_eq!((finished[1].end, finished[1].duration_secs), (Some(SessionEnd::Removed), 2));
+        let _ = std::fs::remove_file(&path);
+    }
+}
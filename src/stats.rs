@@ -0,0 +1,307 @@
+//! History of track and peer statistics
+//!
+//! Once a second the web server samples every track (loss, jitter, codec
+//! bitrate, buffer level) and every peer (bandwidth) into an in-memory time
+//! series covering the last [`HISTORY_SECS`]. `GET /api/stats` serves it,
+//! so the web UI can chart the last minutes instead of showing only the
+//! current numbers.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::network::peers::PeerRegistry;
+use crate::network::timesync::unix_time_ms;
+use crate::protocol::{PeerStatus, TrackStatus};
+use crate::sessions::SessionStore;
+use crate::tracks::TrackManager;
+
+/// Time between samples
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// History kept per track and peer
+pub const HISTORY_SECS: u64 = 15 * 60;
+
+/// One second of a track
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackSample {
+    /// Wall-clock time (ms since the Unix epoch)
+    pub time_ms: u64,
+    /// Packets sent or received since the previous sample
+    pub packets: u64,
+    /// Packets lost since the previous sample
+    pub lost: u64,
+    /// Share of the packets lost since the previous sample (%)
+    pub loss_percent: f32,
+    pub jitter_ms: f32,
+    /// Codec bitrate after adaptation (kbps)
+    pub bitrate_kbps: f32,
+    /// Receive buffer level; encode time on the sending side (ms)
+    pub buffer_ms: f32,
+    pub level_db: f32,
+}
+
+/// One second of a peer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerSample {
+    pub time_ms: u64,
+    pub active: bool,
+    pub up_kbps: f32,
+    pub down_kbps: f32,
+}
+
+/// History of one track, oldest sample first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackSeries {
+    pub track_id: u8,
+    pub name: String,
+    pub samples: Vec<TrackSample>,
+}
+
+/// History of one peer, oldest sample first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerSeries {
+    /// Peer key ("ip:audio_port")
+    pub id: String,
+    pub name: String,
+    pub samples: Vec<PeerSample>,
+}
+
+/// Response of `GET /api/stats`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub interval_ms: u64,
+    pub history_secs: u64,
+    pub tracks: Vec<TrackSeries>,
+    pub peers: Vec<PeerSeries>,
+}
+
+struct TrackHistory {
+    name: String,
+    /// Packet counters at the previous sample
+    last_packets: u64,
+    last_lost: u64,
+    samples: VecDeque<TrackSample>,
+}
+
+struct PeerHistory {
+    name: String,
+    samples: VecDeque<PeerSample>,
+}
+
+/// Time series store of the statistics
+pub struct StatsHistory {
+    tracks: Mutex<BTreeMap<u8, TrackHistory>>,
+    peers: Mutex<BTreeMap<String, PeerHistory>>,
+}
+
+impl StatsHistory {
+    pub fn new() -> Self {
+        Self {
+            tracks: Mutex::new(BTreeMap::new()),
+            peers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add a sample of every track and peer
+    ///
+    /// Tracks and peers that are gone keep their history until it ages out.
+    pub fn record(&self, time_ms: u64, tracks: &[TrackStatus], peers: &[PeerStatus]) {
+        let oldest = time_ms.saturating_sub(HISTORY_SECS * 1000);
+
+        let mut histories = self.tracks.lock();
+        for track in tracks {
+            let packets_total = track.packets_sent.max(track.packets_received);
+            let history = histories.entry(track.track_id).or_insert_with(|| TrackHistory {
+                name: track.name.clone(),
+                last_packets: packets_total,
+                last_lost: track.packets_lost,
+                samples: VecDeque::new(),
+            });
+
+            // Counters restart with a recreated track
+            let packets = packets_total.saturating_sub(history.last_packets);
+            let lost = track.packets_lost.saturating_sub(history.last_lost);
+            history.name = track.name.clone();
+            history.last_packets = packets_total;
+            history.last_lost = track.packets_lost;
+
+            let expected = packets + lost;
+            history.samples.push_back(TrackSample {
+                time_ms,
+                packets,
+                lost,
+                loss_percent: if expected > 0 { lost as f32 * 100.0 / expected as f32 } else { 0.0 },
+                jitter_ms: track.jitter_ms,
+                bitrate_kbps: track.adaptive_bitrate.unwrap_or(track.bitrate) as f32 / 1000.0,
+                buffer_ms: track.current_latency_ms,
+                level_db: track.level_db,
+            });
+        }
+        for history in histories.values_mut() {
+            prune(&mut history.samples, oldest, |sample| sample.time_ms);
+        }
+        histories.retain(|_, history| !history.samples.is_empty());
+        drop(histories);
+
+        let mut histories = self.peers.lock();
+        for peer in peers {
+            let history = histories.entry(peer.id.clone()).or_insert_with(|| PeerHistory {
+                name: peer.name.clone(),
+                samples: VecDeque::new(),
+            });
+            history.name = peer.name.clone();
+            history.samples.push_back(PeerSample {
+                time_ms,
+                active: peer.active,
+                up_kbps: peer.bandwidth.up_kbps,
+                down_kbps: peer.bandwidth.down_kbps,
+            });
+        }
+        for history in histories.values_mut() {
+            prune(&mut history.samples, oldest, |sample| sample.time_ms);
+        }
+        histories.retain(|_, history| !history.samples.is_empty());
+    }
+
+    /// Samples later than `since_ms`, at most `window_secs` back from the
+    /// newest sample (the whole history if None)
+    pub fn report(&self, since_ms: Option<u64>, window_secs: Option<u64>) -> StatsReport {
+        let tracks = self.tracks.lock();
+        let peers = self.peers.lock();
+
+        let newest = tracks
+            .values()
+            .filter_map(|history| history.samples.back().map(|sample| sample.time_ms))
+            .chain(peers.values().filter_map(|history| history.samples.back().map(|sample| sample.time_ms)))
+            .max()
+            .unwrap_or(0);
+        let window_start = window_secs.map_or(0, |secs| newest.saturating_sub(secs * 1000));
+        let after = since_ms.unwrap_or(0).max(window_start.saturating_sub(1));
+
+        StatsReport {
+            interval_ms: SAMPLE_INTERVAL.as_millis() as u64,
+            history_secs: HISTORY_SECS,
+            tracks: tracks
+                .iter()
+                .map(|(&track_id, history)| TrackSeries {
+                    track_id,
+                    name: history.name.clone(),
+                    samples: history.samples.iter().filter(|s| s.time_ms > after).cloned().collect(),
+                })
+                .collect(),
+            peers: peers
+                .iter()
+                .map(|(id, history)| PeerSeries {
+                    id: id.clone(),
+                    name: history.name.clone(),
+                    samples: history.samples.iter().filter(|s| s.time_ms > after).cloned().collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Default for StatsHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn prune<T>(samples: &mut VecDeque<T>, oldest: u64, time_ms: impl Fn(&T) -> u64) {
+    while samples.front().is_some_and(|sample| time_ms(sample) < oldest) {
+        samples.pop_front();
+    }
+}
+
+/// Sample the tracks and peers every [`SAMPLE_INTERVAL`] in the background,
+/// into the history and the session summaries
+pub fn spawn_sampler(
+    history: Arc<StatsHistory>,
+    sessions: Arc<SessionStore>,
+    track_manager: Arc<TrackManager>,
+    peers: Arc<PeerRegistry>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            let time_ms = unix_time_ms();
+            let (tracks, peers) = (track_manager.get_all_statuses(), peers.statuses());
+            history.record(time_ms, &tracks, &peers);
+            sessions.record(time_ms, &tracks, &peers);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PeerBandwidth, PeerConnection, TrackConfig};
+    use crate::tracks::Track;
+
+    fn peer(up_kbps: f32) -> PeerStatus {
+        PeerStatus {
+            id: "192.168.1.20:5000".to_string(),
+            name: "studio".to_string(),
+            address: "192.168.1.20:5000".to_string(),
+            paths: Vec::new(),
+            active: true,
+            connection: PeerConnection::Connected,
+            last_seen_ms: 0,
+            bandwidth: PeerBandwidth {
+                up_kbps,
+                ..PeerBandwidth::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_samples_per_interval() {
+        let history = StatsHistory::new();
+        let track = Track::new(3, TrackConfig::default());
+
+        // Counters in place before the first sample aren't counted
+        track.increment_packets();
+        history.record(1_000, &[track.status()], &[peer(100.0)]);
+        for _ in 0..90 {
+            track.increment_packets();
+        }
+        for _ in 0..10 {
+            track.increment_lost();
+        }
+        history.record(2_000, &[track.status()], &[peer(200.0)]);
+
+        let report = history.report(None, None);
+        let samples = &report.tracks[0].samples;
+        assert_eq!(report.tracks[0].track_id, 3);
+        assert_eq!((samples[0].packets, samples[0].lost), (0, 0));
+        assert_eq!((samples[1].packets, samples[1].lost), (90, 10));
+        assert_eq!(samples[1].loss_percent, 10.0);
+        assert_eq!(samples[1].bitrate_kbps, 128.0);
+        assert_eq!(report.peers[0].samples[1].up_kbps, 200.0);
+
+        // Incremental and windowed reads
+        assert_eq!(history.report(Some(1_000), None).tracks[0].samples.len(), 1);
+        assert_eq!(history.report(None, Some(0)).peers[0].samples.len(), 1);
+        assert_eq!(history.report(None, Some(1)).peers[0].samples.len(), 2);
+    }
+
+    #[test]
+    fn test_history_ages_out() {
+        let history = StatsHistory::new();
+        let track = Track::new(0, TrackConfig::default());
+        history.record(1_000, &[track.status()], &[peer(1.0)]);
+
+        // The track and peer are gone but keep their history for a while
+        history.record(2_000, &[], &[]);
+        assert_eq!(history.report(None, None).tracks.len(), 1);
+
+        history.record(1_000 + HISTORY_SECS * 1000 + 1, &[], &[]);
+        let report = history.report(None, None);
+        assert!(report.tracks.is_empty() && report.peers.is_empty());
+    }
+}
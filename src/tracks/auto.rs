@@ -0,0 +1,209 @@
+//! Automatic track creation from device rules
+//!
+//! The sender evaluates [`AutoTrackRule`]s against the input devices at
+//! startup, so a multi-microphone setup comes up the same way every time
+//! instead of depending on which device the system marks as default.
+
+use crate::audio::generator::{generator_device, GENERATOR_PREFIX};
+use crate::config::AutoTrackRule;
+use crate::protocol::{AudioDeviceInfo, TrackConfig};
+
+/// Track configurations for the input devices matched by `rules`.
+/// Every device gets at most one track, from the first rule it matches.
+/// A rule naming a test signal (`generator:sine:440`) adds a track of it.
+pub fn plan_tracks(rules: &[AutoTrackRule], devices: &[AudioDeviceInfo]) -> Vec<TrackConfig> {
+    let mut tracks = Vec::new();
+    let mut used = Vec::new();
+
+    for rule in rules {
+        if rule.device.starts_with(GENERATOR_PREFIX) {
+            match generator_device(&rule.device) {
+                Some(device) => tracks.push(track_config(rule, &device)),
+                None => tracing::warn!("Unknown test signal in auto track rule: {}", rule.device),
+            }
+            continue;
+        }
+        for device in devices.iter().filter(|device| device.is_input) {
+            if used.contains(&&device.id) || !rule_matches(rule, device) {
+                continue;
+            }
+            used.push(&device.id);
+            tracks.push(track_config(rule, device));
+        }
+    }
+    tracks
+}
+
+fn rule_matches(rule: &AutoTrackRule, device: &AudioDeviceInfo) -> bool {
+    if rule.device.eq_ignore_ascii_case(AutoTrackRule::DEFAULT_DEVICE) {
+        return device.is_default;
+    }
+    // Loopback inputs mirror every output, so `*` alone doesn't capture them
+    if device.is_loopback && !rule.device.to_lowercase().contains("loopback") {
+        return false;
+    }
+    // Test signals come from rules naming them, not from patterns
+    if device.id.starts_with(GENERATOR_PREFIX) {
+        return false;
+    }
+    matches_pattern(&rule.device, &device.name)
+}
+
+fn track_config(rule: &AutoTrackRule, device: &AudioDeviceInfo) -> TrackConfig {
+    let defaults = TrackConfig::default();
+    TrackConfig {
+        name: rule.name.replace("{device}", &device.name),
+        device_id: device.id.clone(),
+        bitrate: rule.bitrate.unwrap_or(defaults.bitrate),
+        frame_size_ms: rule.frame_size_ms.unwrap_or(defaults.frame_size_ms),
+        channels: rule.channels.unwrap_or(defaults.channels),
+        track_type: rule.track_type,
+        fec_enabled: rule.fec_enabled,
+        ..defaults
+    }
+}
+
+/// Case-insensitive glob match: `*` matches any text, `?` one character
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    // Let the last `*` swallow one more character
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::TrackType;
+
+    fn input(name: &str, is_default: bool) -> AudioDeviceInfo {
+        AudioDeviceInfo {
+            id: format!("input:{}", name),
+            name: name.to_string(),
+            is_input: true,
+            is_output: false,
+            is_default,
+            sample_rates: vec![48000],
+            channels: vec![2],
+            is_loopback: false,
+            capture_mode: None,
+            playback_mode: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("*usb*", "Microphone (USB Audio Device)"));
+        assert!(matches_pattern("Mic ?", "mic 2"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("a*b*c", "aXbYbZc"));
+        assert!(!matches_pattern("USB", "USB Audio"));
+        assert!(!matches_pattern("a*b", "aXbY"));
+    }
+
+    #[test]
+    fn test_plan_tracks() {
+        let mut devices = vec![
+            input("Realtek Line In", true),
+            input("Shure MV7 (USB)", false),
+            input("Rode NT-USB", false),
+        ];
+        // Outputs are never captured
+        devices.push(AudioDeviceInfo {
+            is_input: false,
+            is_output: true,
+            ..input("USB Headphones", false)
+        });
+
+        let voice = AutoTrackRule {
+            device: "*USB*".to_string(),
+            name: "Mic - {device}".to_string(),
+            track_type: TrackType::Voice,
+            bitrate: Some(64_000),
+            ..AutoTrackRule::default_input()
+        };
+        let tracks = plan_tracks(&[voice, AutoTrackRule::default_input()], &devices);
+
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].name, "Mic - Shure MV7 (USB)");
+        assert_eq!(tracks[0].track_type, TrackType::Voice);
+        assert_eq!(tracks[0].bitrate, 64_000);
+        assert_eq!(tracks[1].device_id, "input:Rode NT-USB");
+        assert_eq!(tracks[2].name, "Default Input - Realtek Line In");
+        assert_eq!(tracks[2].bitrate, TrackConfig::default().bitrate);
+
+        // A device matched by several rules gets one track
+        let all = AutoTrackRule {
+            device: "*".to_string(),
+            ..AutoTrackRule::default_input()
+        };
+        assert_eq!(plan_tracks(&[AutoTrackRule::default_input(), all], &devices).len(), 3);
+    }
+
+    #[test]
+    fn test_loopback_needs_explicit_rule() {
+        let devices = vec![
+            input("Mic", true),
+            AudioDeviceInfo {
+                id: "loopback:Speakers".to_string(),
+                is_loopback: true,
+                ..input("Speakers (loopback)", false)
+            },
+        ];
+        let rule = |device: &str| AutoTrackRule {
+            device: device.to_string(),
+            ..AutoTrackRule::default_input()
+        };
+
+        let tracks = plan_tracks(&[rule("*")], &devices);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].device_id, "input:Mic");
+
+        let tracks = plan_tracks(&[rule("Speakers*(loopback)")], &devices);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].device_id, "loopback:Speakers");
+    }
+
+    #[test]
+    fn test_generator_rules() {
+        let mut devices = vec![input("Mic", true)];
+        devices.extend(crate::audio::generator::device_info());
+        let rule = |device: &str| AutoTrackRule {
+            device: device.to_string(),
+            name: "Tone - {device}".to_string(),
+            ..AutoTrackRule::default_input()
+        };
+
+        // Listed test signals aren't picked up by patterns
+        assert_eq!(plan_tracks(&[rule("*")], &devices).len(), 1);
+
+        let tracks = plan_tracks(&[rule("generator:sine:440"), rule("generator:bogus")], &devices);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].device_id, "generator:sine:440");
+        assert_eq!(tracks[0].name, "Tone - Test signal: sine 440 Hz");
+    }
+}
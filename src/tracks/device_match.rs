@@ -0,0 +1,258 @@
+//! Finding a track's device by name
+//!
+//! Device IDs carry the name the system gives a device, and that name
+//! changes between machines and driver reinstalls ("Microphone (Realtek
+//! Audio)" becomes "Microphone (2- Realtek Audio)"). A saved track whose
+//! `device_id` no longer exists is matched to a present device of the
+//! same direction through `TrackConfig::device_name`: an exact name
+//! first, then a unique device whose name starts with it (or that it
+//! starts with), then the closest name by edit distance, and last the
+//! device whose [`device_key`] (a stable hash of the name with instance
+//! numbers and punctuation removed) equals `TrackConfig::device_key`.
+
+use crate::audio::device::LOOPBACK_PREFIX;
+use crate::protocol::{AudioDeviceInfo, DeviceMatch, TrackConfig};
+
+/// Least similarity of two normalized names for a fuzzy match
+pub const FUZZY_THRESHOLD: f32 = 0.75;
+
+/// ID prefixes of hardware devices, which are matched by name
+const PREFIXES: [&str; 3] = ["input:", "output:", LOOPBACK_PREFIX];
+
+/// Device name inside a hardware device ID (None for files, test
+/// signals and virtual outputs)
+pub fn name_from_id(device_id: &str) -> Option<&str> {
+    PREFIXES
+        .iter()
+        .find_map(|prefix| device_id.strip_prefix(prefix))
+        .filter(|name| !name.is_empty())
+}
+
+/// Lowercase words of a name without instance numbers ("2- ") and
+/// punctuation
+pub fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !word.chars().all(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Stable hash (FNV-1a) of the normalized name, as 16 hex digits
+pub fn device_key(name: &str) -> String {
+    let hash = normalize_name(name)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// Fill in `device_name` and `device_key` from the device ID, so a saved
+/// config can find the device again after its ID changes
+pub fn remember_device(config: &mut TrackConfig) {
+    if let Some(name) = name_from_id(&config.device_id) {
+        config.device_name = Some(name.to_string());
+        config.device_key = Some(device_key(name));
+    }
+}
+
+/// How a device was found, for logs
+pub fn describe(device_match: DeviceMatch) -> &'static str {
+    match device_match {
+        DeviceMatch::Id => "ID",
+        DeviceMatch::Name => "name",
+        DeviceMatch::Prefix => "name prefix",
+        DeviceMatch::Fuzzy => "similar name",
+        DeviceMatch::Key => "name key",
+        DeviceMatch::NotFound => "nothing",
+    }
+}
+
+/// Similarity of two names from 0 to 1 (edit distance of the normalized
+/// names relative to the longer one)
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = normalize_name(a).chars().collect();
+    let b: Vec<char> = normalize_name(b).chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 0.0;
+    }
+
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    1.0 - row[b.len()] as f32 / longest as f32
+}
+
+/// Device of the track: its ID if that device is present, otherwise the
+/// one its name or key matches (None if nothing matches or the track has
+/// no hardware device)
+pub fn resolve(config: &TrackConfig, devices: &[AudioDeviceInfo]) -> Option<(String, DeviceMatch)> {
+    if devices.iter().any(|device| device.id == config.device_id) {
+        return Some((config.device_id.clone(), DeviceMatch::Id));
+    }
+
+    // Devices of the direction the track was set up with
+    let prefix = PREFIXES
+        .iter()
+        .find(|prefix| config.device_id.starts_with(*prefix))
+        .copied()
+        .unwrap_or("input:");
+    let candidates: Vec<&AudioDeviceInfo> = devices
+        .iter()
+        .filter(|device| match prefix {
+            "output:" => device.is_output && !device.is_loopback,
+            LOOPBACK_PREFIX => device.is_loopback,
+            _ => device.is_input && !device.is_loopback,
+        })
+        .filter(|device| name_from_id(&device.id).is_some())
+        .collect();
+    let id_of = |device: &AudioDeviceInfo| match prefix {
+        // A device that is input and output is listed once, under its input ID
+        "output:" => format!("output:{}", device_name(device)),
+        _ => device.id.clone(),
+    };
+
+    let name = config
+        .device_name
+        .clone()
+        .or_else(|| name_from_id(&config.device_id).map(str::to_string))
+        .filter(|name| !name.is_empty());
+    if let Some(name) = name {
+        let lower = name.to_lowercase();
+        if let Some(device) = candidates.iter().find(|device| device_name(device).to_lowercase() == lower) {
+            return Some((id_of(device), DeviceMatch::Name));
+        }
+
+        let prefixed: Vec<_> = candidates
+            .iter()
+            .filter(|device| {
+                let candidate = device_name(device).to_lowercase();
+                candidate.starts_with(&lower) || lower.starts_with(&candidate)
+            })
+            .collect();
+        if let [device] = prefixed[..] {
+            return Some((id_of(device), DeviceMatch::Prefix));
+        }
+
+        let closest = candidates
+            .iter()
+            .map(|device| (device, similarity(&name, device_name(device))))
+            .filter(|&(_, score)| score >= FUZZY_THRESHOLD)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((device, _)) = closest {
+            return Some((id_of(device), DeviceMatch::Fuzzy));
+        }
+    }
+
+    let key = config.device_key.as_deref()?;
+    candidates
+        .iter()
+        .find(|device| device_key(device_name(device)) == key)
+        .map(|device| (id_of(device), DeviceMatch::Key))
+}
+
+/// Name of a listed device without the " (loopback)" suffix of loopback
+/// inputs
+fn device_name(device: &AudioDeviceInfo) -> &str {
+    name_from_id(&device.id).unwrap_or(&device.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, is_input: bool, is_output: bool) -> AudioDeviceInfo {
+        AudioDeviceInfo {
+            id: id.to_string(),
+            name: name_from_id(id).unwrap_or(id).to_string(),
+            is_input,
+            is_output,
+            is_default: false,
+            sample_rates: vec![48_000],
+            channels: vec![2],
+            is_loopback: id.starts_with(LOOPBACK_PREFIX),
+            capture_mode: None,
+            playback_mode: None,
+        }
+    }
+
+    fn track(device_id: &str, device_name: Option<&str>) -> TrackConfig {
+        TrackConfig {
+            device_id: device_id.to_string(),
+            device_name: device_name.map(str::to_string),
+            ..TrackConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_names_and_keys() {
+        assert_eq!(normalize_name("Microphone (2- Realtek(R) Audio)"), "microphone realtek r audio");
+        assert_eq!(device_key("Microphone (Realtek(R) Audio)"), device_key("MICROPHONE (3- Realtek(R) Audio)"));
+        assert_ne!(device_key("Microphone (Realtek)"), device_key("Line In (Realtek)"));
+        assert_eq!(device_key("Mic").len(), 16);
+        assert_eq!(name_from_id("loopback:Speakers"), Some("Speakers"));
+        assert_eq!(name_from_id("generator:sine:440"), None);
+        assert!(similarity("Rode NT-USB", "RODE NT-USB Mini") > 0.6);
+        assert!(similarity("Rode NT-USB", "Speakers") < 0.3);
+
+        let mut config = track("input:Mic (USB)", None);
+        remember_device(&mut config);
+        assert_eq!(config.device_name.as_deref(), Some("Mic (USB)"));
+        assert_eq!(config.device_key, Some(device_key("Mic (USB)")));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let devices = vec![
+            device("input:Microphone (2- Realtek Audio)", true, false),
+            device("input:Rode NT-USB Mini", true, false),
+            device("input:Headset (Jabra)", true, true),
+            device("output:Speakers (Realtek Audio)", false, true),
+            device("loopback:Speakers (Realtek Audio)", true, false),
+            device("generator:sine:440", true, false),
+        ];
+        let resolve = |config: &TrackConfig| resolve(config, &devices);
+
+        // A present ID is used as it is
+        let config = track("input:Rode NT-USB Mini", None);
+        assert_eq!(resolve(&config), Some((config.device_id.clone(), DeviceMatch::Id)));
+
+        // The saved name, whole or its beginning
+        let config = track("", Some("rode nt-usb mini"));
+        assert_eq!(resolve(&config).unwrap(), ("input:Rode NT-USB Mini".to_string(), DeviceMatch::Name));
+        let config = track("", Some("Rode"));
+        assert_eq!(resolve(&config).unwrap().1, DeviceMatch::Prefix);
+
+        // A reinstalled driver numbers the device
+        let config = track("input:Microphone (Realtek Audio)", None);
+        assert_eq!(
+            resolve(&config).unwrap(),
+            ("input:Microphone (2- Realtek Audio)".to_string(), DeviceMatch::Fuzzy)
+        );
+
+        // Outputs match outputs, including a device listed as input/output
+        let config = track("output:Headset", None);
+        assert_eq!(resolve(&config).unwrap(), ("output:Headset (Jabra)".to_string(), DeviceMatch::Prefix));
+        let config = track("output:Speakers (3- Realtek Audio)", None);
+        assert_eq!(resolve(&config).unwrap().0, "output:Speakers (Realtek Audio)");
+        let config = track("loopback:Speakers (2- Realtek Audio)", None);
+        assert_eq!(resolve(&config).unwrap().0, "loopback:Speakers (Realtek Audio)");
+
+        // The key finds a device whose name has changed beyond matching
+        let mut config = track("input:Desk mic", Some("Desk mic"));
+        config.device_key = Some(device_key("Rode NT-USB Mini"));
+        assert_eq!(resolve(&config).unwrap(), ("input:Rode NT-USB Mini".to_string(), DeviceMatch::Key));
+
+        assert_eq!(resolve(&track("input:Webcam", None)), None);
+        assert_eq!(resolve(&track("generator:sine:440", None)).unwrap().1, DeviceMatch::Id);
+    }
+}
@@ -1,13 +1,33 @@
 //! Track manager for handling multiple audio tracks
 
 use dashmap::DashMap;
+use parking_lot::RwLock;
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
 
+use crate::audio::convert::validate_channel_map;
+use crate::audio::dsp;
+use crate::audio::file_source::FilePlayer;
+use crate::audio::level_meter::LevelMeterParams;
+use crate::audio::{agc, device, simd, voice};
+use crate::codec::encoder::FRAME_DURATIONS_MS;
+use crate::codec::multistream::MAX_SURROUND_CHANNELS;
+use crate::config::SoloMode;
 use crate::error::TrackError;
-use crate::protocol::{TrackConfig, TrackConfigUpdate, TrackStatus};
+use crate::protocol::{
+    AudioDeviceInfo, DeviceMatch, DropReason, FilePlayerStatus, OutputDsp, PeerMix, PlayerCommand, PlayoutDrops,
+    RemoteCapabilities, TrackConfig, TrackConfigUpdate, TrackDrops, TrackStatus, TrackType,
+};
+use crate::tracks::device_match;
+use crate::tracks::timeline::{ActivityKind, Timeline};
 use crate::tracks::track::Track;
-use crate::constants::MAX_TRACKS;
+use crate::constants::{DEFAULT_GAP_THRESHOLD_MS, MAX_JITTER_DELAY_MS, MAX_PEER_GAIN, MAX_TRACKS, MAX_TRACK_VOLUME, TALKBACK_DUCK_GAIN};
+
+/// Peak at which audio counts as clipping (full scale)
+const CLIP_LEVEL: f32 = 1.0;
 
 /// Events emitted by the track manager
 #[derive(Debug, Clone)]
@@ -17,8 +37,12 @@ pub enum TrackEvent {
     Started(u8),
     Stopped(u8),
     ConfigUpdated(u8),
-    /// Device changed event: (track_id, old_device_id, new_device_id)
+    /// Device changed event: (track_id, old_device_id, new_device_id).
+    /// Also sent with the same ID twice when the device is to be reopened
+    /// with a new buffer size
     DeviceChanged(u8, String, String),
+    /// Master processing of an output device changed
+    OutputDspChanged(String),
     Error(u8, String),
 }
 
@@ -41,6 +65,33 @@ pub struct TrackManager {
     
     /// Solo mode active (any track soloed)
     solo_active: std::sync::atomic::AtomicBool,
+    
+    /// What soloing a track does
+    solo_mode: SoloMode,
+    
+    /// Mixer settings for audio received from each peer (unity if absent)
+    peer_mix: DashMap<IpAddr, PeerMix>,
+    
+    /// Master processing per output device ID (bypass if absent)
+    output_dsp: DashMap<String, OutputDsp>,
+    
+    /// Level meter parameters for new tracks
+    meter_params: LevelMeterParams,
+    
+    /// What the receivers of our tracks support (for the UI)
+    remote_capabilities: RwLock<RemoteCapabilities>,
+    
+    /// Why received audio didn't play, per track ID (kept after removal)
+    playout_drops: DashMap<u8, PlayoutDrops>,
+    
+    /// Track and peer activity for post-stream review
+    timeline: Timeline,
+    
+    /// Gap in the packets of a received track logged as an incident
+    gap_threshold: Duration,
+    
+    /// Players of the tracks streaming a file, registered by their captures
+    file_players: DashMap<u8, Arc<FilePlayer>>,
 }
 
 impl TrackManager {
@@ -55,24 +106,171 @@ impl TrackManager {
             _event_rx: event_rx,
             max_tracks: MAX_TRACKS,
             solo_active: std::sync::atomic::AtomicBool::new(false),
+            solo_mode: SoloMode::default(),
+            peer_mix: DashMap::new(),
+            output_dsp: DashMap::new(),
+            meter_params: LevelMeterParams::default(),
+            remote_capabilities: RwLock::new(RemoteCapabilities::default()),
+            playout_drops: DashMap::new(),
+            timeline: Timeline::new(),
+            gap_threshold: Duration::from_millis(DEFAULT_GAP_THRESHOLD_MS as u64),
+            file_players: DashMap::new(),
         }
     }
     
+    /// Use these level meter parameters for new tracks
+    pub fn with_meter_params(mut self, meter_params: LevelMeterParams) -> Self {
+        self.meter_params = meter_params;
+        self
+    }
+    
+    /// Use this solo mode instead of solo-in-place
+    pub fn with_solo_mode(mut self, solo_mode: SoloMode) -> Self {
+        self.solo_mode = solo_mode;
+        self
+    }
+    
+    /// Log gaps of more than this many milliseconds in received tracks
+    pub fn with_gap_threshold_ms(mut self, gap_threshold_ms: u32) -> Self {
+        self.gap_threshold = Duration::from_millis(gap_threshold_ms as u64);
+        self
+    }
+    
+    /// What soloing a track does
+    pub fn solo_mode(&self) -> SoloMode {
+        self.solo_mode
+    }
+    
+    /// Allow at most this many tracks
+    pub fn with_max_tracks(mut self, max_tracks: usize) -> Self {
+        self.max_tracks = max_tracks.min(MAX_TRACKS);
+        self
+    }
+    
+    /// Maximum number of tracks
+    pub fn max_tracks(&self) -> usize {
+        self.max_tracks
+    }
+    
+    /// Record what the receivers of our tracks support
+    pub fn set_remote_capabilities(&self, capabilities: RemoteCapabilities) {
+        *self.remote_capabilities.write() = capabilities;
+    }
+    
+    /// What the receivers of our tracks support
+    pub fn remote_capabilities(&self) -> RemoteCapabilities {
+        self.remote_capabilities.read().clone()
+    }
+    
+    /// Count received audio of a track that didn't play
+    pub fn record_drops(&self, track_id: u8, reason: DropReason, count: u64) {
+        if count > 0 {
+            self.playout_drops.entry(track_id).or_default().add(reason, count);
+            if reason == DropReason::Underflow {
+                let detail = format!("{}: output ran dry {} time(s)", self.track_name(track_id), count);
+                self.timeline.record_incident(ActivityKind::Underrun, track_id, detail);
+            }
+        }
+    }
+    
+    /// Log clipping if captured or decoded samples of a track reach full scale
+    pub fn check_clipping(&self, track_id: u8, samples: &[f32]) {
+        let peak = simd::peak_abs(samples);
+        if peak >= CLIP_LEVEL {
+            let detail = format!("{}: peak {:+.1} dBFS", self.track_name(track_id), 20.0 * peak.log10());
+            self.timeline.record_incident(ActivityKind::Clipping, track_id, detail);
+        }
+    }
+    
+    /// Log a stream restart that reset the jitter buffer of a received track
+    pub fn record_jitter_buffer_reset(&self, track_id: u8, sequence: u32) {
+        let detail = format!("{}: stream restarted at packet {}", self.track_name(track_id), sequence);
+        self.timeline.record_incident(ActivityKind::JitterBufferReset, track_id, detail);
+    }
+    
+    /// Log the time between two packets of a received track if it is
+    /// longer than the gap threshold
+    pub fn record_arrival_gap(&self, track_id: u8, gap: Duration) {
+        if gap > self.gap_threshold {
+            let detail = format!("{}: no packets for {} ms", self.track_name(track_id), gap.as_millis());
+            self.timeline.record_incident(ActivityKind::Gap, track_id, detail);
+        }
+    }
+    
+    fn track_name(&self, track_id: u8) -> String {
+        self.tracks
+            .get(&track_id)
+            .map_or_else(|| format!("Track {}", track_id), |track| track.config.name.clone())
+    }
+    
+    /// Drop counters of every track audio was received for, by track ID
+    pub fn playout_drops(&self) -> Vec<TrackDrops> {
+        let mut drops: Vec<TrackDrops> = self
+            .playout_drops
+            .iter()
+            .map(|entry| TrackDrops {
+                track_id: *entry.key(),
+                present: self.tracks.contains_key(entry.key()),
+                drops: entry.value().clone(),
+            })
+            .collect();
+        drops.sort_by_key(|track| track.track_id);
+        drops
+    }
+    
     /// Subscribe to track events
     pub fn subscribe(&self) -> broadcast::Receiver<TrackEvent> {
         self.event_tx.subscribe()
     }
     
+    /// Timestamped track and peer activity
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+    
     /// Create a new track
     pub fn create_track(&self, mut config: TrackConfig) -> Result<u8, TrackError> {
         if self.tracks.len() >= self.max_tracks {
             return Err(TrackError::MaxTracksReached(self.max_tracks));
         }
         
-        // Assign ID if not provided
+        // Only one talkback track: the talk button has a single target
+        if config.talkback {
+            if let Some(existing) = self.talkback_track_id() {
+                return Err(TrackError::InvalidConfig(format!(
+                    "talkback track already exists: {}",
+                    existing
+                )));
+            }
+        }
+        
+        validate_channels(config.channels)?;
+        validate_frame_size(config.frame_size_ms)?;
+        validate_channel_map(&config.channel_map)
+            .map_err(|e| TrackError::InvalidConfig(e.to_string()))?;
+        if let Some(ref destination) = config.destination {
+            validate_destination(destination)?;
+        }
+        if config.dred {
+            validate_dred(config.track_type)?;
+        }
+        validate_voice_processing(config.track_type, config.high_pass_hz, config.noise_suppression)?;
+        validate_agc(config.agc_target_db, config.agc_attack_ms, config.agc_release_ms)?;
+        if let Some(frames) = config.buffer_frames {
+            validate_buffer_frames(frames)?;
+        }
+        validate_output_devices(&config.output_devices)?;
+        validate_volume(config.volume)?;
+        validate_complexity(config.complexity)?;
+        validate_jitter_delay(config.jitter_min_ms, config.jitter_max_ms, config.jitter_target_ms)?;
+        
+        // Assign ID if not provided; automatic IDs continue after given ones
         let id = config.track_id.unwrap_or_else(|| {
             self.next_id.fetch_add(1, Ordering::SeqCst)
         });
+        if config.track_id.is_some() {
+            self.next_id.fetch_max(id.saturating_add(1), Ordering::SeqCst);
+        }
         
         // Check if ID already exists
         if self.tracks.contains_key(&id) {
@@ -80,14 +278,68 @@ impl TrackManager {
         }
         
         config.track_id = Some(id);
-        let track = Track::new(id, config);
+        if config.device_name.is_none() {
+            device_match::remember_device(&mut config);
+        }
+        let track = Track::with_meter_params(id, config, self.meter_params);
+        
+        // Talkback stays muted until the button is pressed
+        if track.config.talkback {
+            track.set_muted(true);
+        }
         
+        self.timeline.record(ActivityKind::TrackCreated, Some(id), track.config.name.clone());
         self.tracks.insert(id, track);
         let _ = self.event_tx.send(TrackEvent::Created(id));
         
         Ok(id)
     }
     
+    /// Create a track saved in the configuration file on the device its
+    /// ID, name or name key finds among `devices`
+    pub fn create_saved_track(&self, mut config: TrackConfig, devices: &[AudioDeviceInfo]) -> Result<u8, TrackError> {
+        let saved_id = config.device_id.clone();
+        let device_match = match device_match::resolve(&config, devices) {
+            Some((device_id, device_match)) => {
+                config.device_id = device_id;
+                device_match
+            }
+            // Virtual outputs, files and an empty ID (the default device)
+            None if device_match::name_from_id(&saved_id).is_none() && config.device_name.is_none() => DeviceMatch::Id,
+            None => DeviceMatch::NotFound,
+        };
+        
+        let name = config.name.clone();
+        let device_id = config.device_id.clone();
+        let id = self.create_track(config)?;
+        if let Some(mut track) = self.tracks.get_mut(&id) {
+            track.set_device_match(device_match);
+        }
+        
+        match device_match {
+            DeviceMatch::Id => {}
+            DeviceMatch::NotFound => tracing::warn!(
+                "No device matches {} of track {} ({})",
+                saved_id, id, name
+            ),
+            _ => {
+                tracing::info!(
+                    "Track {} ({}): {} not found, using {} (matched by {})",
+                    id, name, saved_id, device_id, device_match::describe(device_match)
+                );
+                self.timeline.record(
+                    ActivityKind::DeviceChanged,
+                    Some(id),
+                    format!(
+                        "{}: {} -> {} (matched by {})",
+                        name, saved_id, device_id, device_match::describe(device_match)
+                    ),
+                );
+            }
+        }
+        Ok(id)
+    }
+    
     /// Remove a track
     pub fn remove_track(&self, track_id: u8) -> Result<Track, TrackError> {
         let (_, mut track) = self.tracks
@@ -96,7 +348,9 @@ impl TrackManager {
         
         // Stop track if running
         track.stop();
+        self.file_players.remove(&track_id);
         
+        self.timeline.record(ActivityKind::TrackRemoved, Some(track_id), track.config.name.clone());
         let _ = self.event_tx.send(TrackEvent::Removed(track_id));
         
         // Update solo state
@@ -122,6 +376,7 @@ impl TrackManager {
             .ok_or(TrackError::NotFound(track_id))?;
         
         track.start()?;
+        self.timeline.record(ActivityKind::TrackStarted, Some(track_id), track.config.name.clone());
         let _ = self.event_tx.send(TrackEvent::Started(track_id));
         
         Ok(())
@@ -134,6 +389,7 @@ impl TrackManager {
             .ok_or(TrackError::NotFound(track_id))?;
         
         track.stop();
+        self.timeline.record(ActivityKind::TrackStopped, Some(track_id), track.config.name.clone());
         let _ = self.event_tx.send(TrackEvent::Stopped(track_id));
         
         Ok(())
@@ -154,6 +410,7 @@ impl TrackManager {
     pub fn stop_all(&self) {
         for mut entry in self.tracks.iter_mut() {
             entry.stop();
+            self.timeline.record(ActivityKind::TrackStopped, Some(*entry.key()), entry.config.name.clone());
             let _ = self.event_tx.send(TrackEvent::Stopped(*entry.key()));
         }
     }
@@ -164,15 +421,75 @@ impl TrackManager {
             .get_mut(&track_id)
             .ok_or(TrackError::NotFound(track_id))?;
         
+        if let Some(frame_size_ms) = update.frame_size_ms {
+            validate_frame_size(frame_size_ms)?;
+        }
+        if let Some(ref channel_map) = update.channel_map {
+            validate_channel_map(channel_map)
+                .map_err(|e| TrackError::InvalidConfig(e.to_string()))?;
+        }
+        if let Some(destination) = update.destination.as_deref().filter(|d| !d.is_empty()) {
+            validate_destination(destination)?;
+        }
+        if update.dred == Some(true) {
+            validate_dred(track.config.track_type)?;
+        }
+        validate_voice_processing(
+            track.config.track_type,
+            update.high_pass_hz.unwrap_or(track.config.high_pass_hz),
+            update.noise_suppression.unwrap_or(track.config.noise_suppression),
+        )?;
+        validate_agc(
+            update.agc_target_db.unwrap_or(track.config.agc_target_db),
+            update.agc_attack_ms.unwrap_or(track.config.agc_attack_ms),
+            update.agc_release_ms.unwrap_or(track.config.agc_release_ms),
+        )?;
+        if let Some(frames) = update.buffer_frames.filter(|&frames| frames > 0) {
+            validate_buffer_frames(frames)?;
+        }
+        if let Some(ref output_devices) = update.output_devices {
+            validate_output_devices(output_devices)?;
+        }
+        if let Some(volume) = update.volume {
+            validate_volume(volume)?;
+        }
+        if let Some(complexity) = update.complexity {
+            validate_complexity(complexity)?;
+        }
+        let jitter_ms = |update: Option<u32>, current: Option<u32>| match update {
+            Some(ms) => Some(ms).filter(|&ms| ms > 0),
+            None => current,
+        };
+        validate_jitter_delay(
+            jitter_ms(update.jitter_min_ms, track.config.jitter_min_ms),
+            jitter_ms(update.jitter_max_ms, track.config.jitter_max_ms),
+            jitter_ms(update.jitter_target_ms, track.config.jitter_target_ms),
+        )?;
+        
         // Check if device_id is changing
         let old_device_id = track.device_id.clone();
         let new_device_id = update.device_id.clone();
+        let old_buffer_frames = track.config.buffer_frames;
         
         track.update_config(&update)?;
         
+        // A new buffer size reopens the same device
+        if new_device_id.as_ref().is_none_or(|new_id| *new_id == old_device_id)
+            && track.config.buffer_frames != old_buffer_frames
+        {
+            let _ = self.event_tx.send(TrackEvent::DeviceChanged(track_id, old_device_id.clone(), old_device_id.clone()));
+        }
+        
         // Emit DeviceChanged event if device changed
         if let Some(ref new_id) = new_device_id {
             if &old_device_id != new_id {
+                // The new capture registers its own player
+                self.file_players.remove(&track_id);
+                self.timeline.record(
+                    ActivityKind::DeviceChanged,
+                    Some(track_id),
+                    format!("{}: {} -> {}", track.config.name, old_device_id, new_id),
+                );
                 let _ = self.event_tx.send(TrackEvent::DeviceChanged(
                     track_id,
                     old_device_id,
@@ -186,12 +503,50 @@ impl TrackManager {
         Ok(())
     }
     
+    /// Take the name, bitrate, channel count and frame size a sender
+    /// announced for a received track. Returns false if the track already
+    /// had them
+    pub fn apply_sender_config(
+        &self,
+        track_id: u8,
+        name: &str,
+        bitrate: u32,
+        channels: u16,
+        frame_size_ms: f32,
+    ) -> Result<bool, TrackError> {
+        let mut track = self.tracks
+            .get_mut(&track_id)
+            .ok_or(TrackError::NotFound(track_id))?;
+        
+        let config = &track.config;
+        if config.name == name
+            && config.bitrate == bitrate
+            && config.channels == channels
+            && config.frame_size_ms == frame_size_ms
+        {
+            return Ok(false);
+        }
+        track.name = name.to_string();
+        track.config.name = name.to_string();
+        track.config.bitrate = bitrate;
+        track.config.channels = channels;
+        track.config.frame_size_ms = frame_size_ms;
+        drop(track);
+        
+        let _ = self.event_tx.send(TrackEvent::ConfigUpdated(track_id));
+        Ok(true)
+    }
+    
     /// Set track mute state
     pub fn set_muted(&self, track_id: u8, muted: bool) -> Result<(), TrackError> {
         let track = self.tracks
             .get(&track_id)
             .ok_or(TrackError::NotFound(track_id))?;
         
+        if track.is_muted() != muted {
+            let kind = if muted { ActivityKind::Muted } else { ActivityKind::Unmuted };
+            self.timeline.record(kind, Some(track_id), track.config.name.clone());
+        }
         track.set_muted(muted);
         Ok(())
     }
@@ -208,6 +563,109 @@ impl TrackManager {
         Ok(())
     }
     
+    /// ID of the talkback track, if one exists
+    pub fn talkback_track_id(&self) -> Option<u8> {
+        self.tracks
+            .iter()
+            .find(|entry| entry.config.talkback)
+            .map(|entry| *entry.key())
+    }
+    
+    /// Press (true) or release (false) the talkback button
+    pub fn set_talkback(&self, active: bool) -> Result<(), TrackError> {
+        let track_id = self.talkback_track_id().ok_or(TrackError::NoTalkback)?;
+        self.set_muted(track_id, !active)
+    }
+    
+    /// Check if talkback is currently transmitting
+    pub fn is_talkback_active(&self) -> bool {
+        self.tracks
+            .iter()
+            .any(|entry| entry.config.talkback && !entry.is_muted())
+    }
+    
+    /// Gain for an outgoing track: None if the track must not transmit
+    /// (released talkback), ducked gain for other tracks while talkback is
+    /// held, times the track's volume
+    pub fn send_gain(&self, track_id: u8) -> Option<f32> {
+        let (talkback, volume) = self.tracks.get(&track_id).map(|t| (t.config.talkback, t.config.volume))?;
+        if talkback {
+            return self.is_talkback_active().then_some(volume);
+        }
+        
+        if self.is_talkback_active() {
+            Some(TALKBACK_DUCK_GAIN * volume)
+        } else {
+            Some(volume)
+        }
+    }
+    
+    /// Change volume and/or mute of audio received from a peer.
+    /// Returns the resulting settings.
+    pub fn set_peer_mix(
+        &self,
+        peer: IpAddr,
+        gain: Option<f32>,
+        muted: Option<bool>,
+    ) -> Result<PeerMix, TrackError> {
+        if let Some(gain) = gain {
+            if !(0.0..=MAX_PEER_GAIN).contains(&gain) {
+                return Err(TrackError::InvalidConfig(format!(
+                    "Peer gain must be between 0 and {}",
+                    MAX_PEER_GAIN
+                )));
+            }
+        }
+        
+        let mut entry = self.peer_mix.entry(peer).or_insert_with(|| PeerMix {
+            peer: peer.to_string(),
+            gain: 1.0,
+            muted: false,
+        });
+        if let Some(gain) = gain {
+            entry.gain = gain;
+        }
+        if let Some(muted) = muted {
+            entry.muted = muted;
+        }
+        Ok(entry.clone())
+    }
+    
+    /// Gain for audio received from a peer (0 when muted)
+    pub fn peer_gain(&self, peer: IpAddr) -> f32 {
+        self.peer_mix
+            .get(&peer)
+            .map(|mix| if mix.muted { 0.0 } else { mix.gain })
+            .unwrap_or(1.0)
+    }
+    
+    /// Mixer settings of all peers that have been adjusted
+    pub fn peer_mixer(&self) -> Vec<PeerMix> {
+        let mut mixer: Vec<PeerMix> = self.peer_mix.iter().map(|entry| entry.value().clone()).collect();
+        mixer.sort_by(|a, b| a.peer.cmp(&b.peer));
+        mixer
+    }
+    
+    /// Set the master processing of an output device (None = bypass)
+    pub fn set_output_dsp(&self, device_id: &str, settings: Option<OutputDsp>) -> Result<(), TrackError> {
+        match settings.filter(|settings| !settings.is_bypass()) {
+            Some(settings) => {
+                dsp::validate(&settings).map_err(TrackError::InvalidConfig)?;
+                self.output_dsp.insert(device_id.to_string(), settings);
+            }
+            None => {
+                self.output_dsp.remove(device_id);
+            }
+        }
+        let _ = self.event_tx.send(TrackEvent::OutputDspChanged(device_id.to_string()));
+        Ok(())
+    }
+    
+    /// Master processing of an output device
+    pub fn output_dsp(&self, device_id: &str) -> Option<OutputDsp> {
+        self.output_dsp.get(device_id).map(|settings| settings.clone())
+    }
+    
     /// Update global solo state
     fn update_solo_state(&self) {
         let any_solo = self.tracks
@@ -224,7 +682,7 @@ impl TrackManager {
                 return false;
             }
             
-            if self.solo_active.load(Ordering::Relaxed) {
+            if self.solo_in_place_active() {
                 return track.is_solo();
             }
             
@@ -234,11 +692,75 @@ impl TrackManager {
         }
     }
     
+    /// Whether soloed tracks currently silence the others
+    fn solo_in_place_active(&self) -> bool {
+        self.solo_mode == SoloMode::InPlace && self.solo_active.load(Ordering::Relaxed)
+    }
+    
+    /// Gain of a received track on its own output: solo-in-place silences
+    /// the tracks that aren't soloed, PFL leaves every output alone
+    pub fn solo_gain(&self, track_id: u8) -> f32 {
+        let silenced = self.solo_in_place_active()
+            && !self.tracks.get(&track_id).is_some_and(|track| track.is_solo());
+        if silenced { 0.0 } else { 1.0 }
+    }
+    
+    /// Gain of a received track on its output: its volume, and silence
+    /// when solo-in-place mutes it
+    pub fn output_gain(&self, track_id: u8) -> f32 {
+        let volume = self.tracks.get(&track_id).map_or(1.0, |track| track.config.volume);
+        volume * self.solo_gain(track_id)
+    }
+    
+    /// Whether a received track is copied to the monitor output (PFL)
+    pub fn is_pfl(&self, track_id: u8) -> bool {
+        self.solo_mode == SoloMode::Pfl && self.tracks.get(&track_id).is_some_and(|track| track.is_solo())
+    }
+    
+    /// Register the player of a track's capture (None = not a file source)
+    pub fn set_file_player(&self, track_id: u8, player: Option<Arc<FilePlayer>>) {
+        match player {
+            Some(player) => {
+                self.file_players.insert(track_id, player);
+            }
+            None => {
+                self.file_players.remove(&track_id);
+            }
+        }
+    }
+    
+    /// Player of a track streaming a file
+    pub fn file_player(&self, track_id: u8) -> Option<Arc<FilePlayer>> {
+        self.file_players.get(&track_id).map(|player| player.clone())
+    }
+    
+    /// Apply a transport command to a track streaming a file
+    pub fn control_player(&self, track_id: u8, command: PlayerCommand) -> Result<FilePlayerStatus, TrackError> {
+        if !self.tracks.contains_key(&track_id) {
+            return Err(TrackError::NotFound(track_id));
+        }
+        let player = self.file_player(track_id).ok_or_else(|| {
+            TrackError::InvalidConfig(format!("track {} does not play a file", track_id))
+        })?;
+        player.apply(command);
+        Ok(player.status())
+    }
+    
     /// Get all track statuses
     pub fn get_all_statuses(&self) -> Vec<TrackStatus> {
+        let talkback_active = self.is_talkback_active();
+        
         self.tracks
             .iter()
-            .map(|entry| entry.status())
+            .map(|entry| {
+                let mut status = entry.status();
+                status.ducked = talkback_active && !status.talkback;
+                if let Some(drops) = self.playout_drops.get(entry.key()) {
+                    status.drops = drops.clone();
+                }
+                status.file_player = self.file_players.get(entry.key()).map(|player| player.status());
+                status
+            })
             .collect()
     }
     
@@ -279,10 +801,160 @@ impl Default for TrackManager {
     }
 }
 
+/// Deep redundancy is tuned for speech, so only voice tracks may enable it
+fn validate_dred(track_type: TrackType) -> Result<(), TrackError> {
+    if track_type != TrackType::Voice {
+        return Err(TrackError::InvalidConfig(
+            "DRED is only available for voice tracks".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// High-pass filter and noise suppression are voice processing: voice
+/// tracks only, and the filter stays below the voice
+fn validate_voice_processing(track_type: TrackType, high_pass_hz: f32, noise_suppression: bool) -> Result<(), TrackError> {
+    if high_pass_hz != 0.0 && !voice::HIGH_PASS_RANGE.contains(&high_pass_hz) {
+        return Err(TrackError::InvalidConfig(format!(
+            "high-pass frequency must be 0 (off) or between {} and {} Hz",
+            voice::HIGH_PASS_RANGE.start(),
+            voice::HIGH_PASS_RANGE.end()
+        )));
+    }
+    if (high_pass_hz > 0.0 || noise_suppression) && track_type != TrackType::Voice {
+        return Err(TrackError::InvalidConfig(
+            "voice processing is only available for voice tracks".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// AGC target loudness and time constants within what the AGC can follow
+fn validate_agc(target_db: f32, attack_ms: f32, release_ms: f32) -> Result<(), TrackError> {
+    if !agc::TARGET_RANGE.contains(&target_db) {
+        return Err(TrackError::InvalidConfig(format!(
+            "AGC target must be between {} and {} dBFS",
+            agc::TARGET_RANGE.start(),
+            agc::TARGET_RANGE.end()
+        )));
+    }
+    if !(1.0..=10_000.0).contains(&attack_ms) || !(1.0..=10_000.0).contains(&release_ms) {
+        return Err(TrackError::InvalidConfig(
+            "AGC attack and release must be between 1 and 10000 ms".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Mono, stereo or a surround layout up to 7.1
+fn validate_channels(channels: u16) -> Result<(), TrackError> {
+    if !(1..=MAX_SURROUND_CHANNELS).contains(&channels) {
+        return Err(TrackError::InvalidConfig(format!(
+            "Track channels must be between 1 and {}",
+            MAX_SURROUND_CHANNELS
+        )));
+    }
+    Ok(())
+}
+
+/// A device buffer the hosts can be asked for
+fn validate_buffer_frames(frames: u32) -> Result<(), TrackError> {
+    if !device::BUFFER_FRAMES_RANGE.contains(&frames) {
+        return Err(TrackError::InvalidConfig(format!(
+            "Device buffer must be between {} and {} frames",
+            device::BUFFER_FRAMES_RANGE.start(),
+            device::BUFFER_FRAMES_RANGE.end()
+        )));
+    }
+    Ok(())
+}
+
+/// Further outputs of a track are device IDs, each listed once
+fn validate_output_devices(device_ids: &[String]) -> Result<(), TrackError> {
+    for (i, device_id) in device_ids.iter().enumerate() {
+        if device_id.is_empty() {
+            return Err(TrackError::InvalidConfig("output device ID must not be empty".to_string()));
+        }
+        if device_ids[..i].contains(device_id) {
+            return Err(TrackError::InvalidConfig(format!("output device {} listed twice", device_id)));
+        }
+    }
+    Ok(())
+}
+
+/// Track volume between silence and +12 dB
+fn validate_volume(volume: f32) -> Result<(), TrackError> {
+    if !(0.0..=MAX_TRACK_VOLUME).contains(&volume) {
+        return Err(TrackError::InvalidConfig(format!(
+            "Track volume must be between 0 and {}",
+            MAX_TRACK_VOLUME
+        )));
+    }
+    Ok(())
+}
+
+/// Jitter-buffer delays up to the limit, the lowest not above the highest
+fn validate_jitter_delay(min_ms: Option<u32>, max_ms: Option<u32>, target_ms: Option<u32>) -> Result<(), TrackError> {
+    if [min_ms, max_ms, target_ms].into_iter().flatten().any(|ms| ms > MAX_JITTER_DELAY_MS) {
+        return Err(TrackError::InvalidConfig(format!(
+            "Jitter buffer delay must be at most {} ms",
+            MAX_JITTER_DELAY_MS
+        )));
+    }
+    if let (Some(min_ms), Some(max_ms)) = (min_ms, max_ms) {
+        if min_ms > max_ms {
+            return Err(TrackError::InvalidConfig(format!(
+                "Lowest jitter buffer delay ({} ms) is above the highest ({} ms)",
+                min_ms, max_ms
+            )));
+        }
+    }
+    if let Some(target_ms) = target_ms {
+        if min_ms.is_some_and(|min_ms| target_ms < min_ms) || max_ms.is_some_and(|max_ms| target_ms > max_ms) {
+            return Err(TrackError::InvalidConfig(format!(
+                "Jitter buffer delay ({} ms) is outside the lowest and highest delay",
+                target_ms
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Frames last one of the durations Opus codes
+fn validate_frame_size(frame_size_ms: f32) -> Result<(), TrackError> {
+    if !FRAME_DURATIONS_MS.contains(&frame_size_ms) {
+        return Err(TrackError::InvalidConfig(format!(
+            "Frame size must be one of {:?} ms",
+            FRAME_DURATIONS_MS
+        )));
+    }
+    Ok(())
+}
+
+/// Opus complexity goes from 0 to 10
+fn validate_complexity(complexity: Option<u8>) -> Result<(), TrackError> {
+    if complexity.is_some_and(|complexity| complexity > 10) {
+        return Err(TrackError::InvalidConfig("Opus complexity must be between 0 and 10".to_string()));
+    }
+    Ok(())
+}
+
+/// A track destination must be "IP" or "IP:port"
+fn validate_destination(destination: &str) -> Result<(), TrackError> {
+    if crate::config::parse_socket_addr(destination, crate::constants::DEFAULT_UDP_PORT).is_none() {
+        return Err(TrackError::InvalidConfig(format!(
+            "invalid destination address: {}",
+            destination
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::TrackType;
+    use crate::config::{OpusBandwidth, OpusBitrateMode, OpusConfig, OpusSignal};
+    use crate::protocol::{Codec, JitterMode, TrackPriority, TrackType};
     
     #[test]
     fn test_create_track() {
@@ -292,16 +964,50 @@ mod tests {
             track_id: None,
             name: "Test Track".to_string(),
             device_id: "test".to_string(),
+            device_name: None,
+            device_key: None,
             bitrate: 128000,
             frame_size_ms: 10.0,
             channels: 2,
             track_type: TrackType::Music,
+            codec: Codec::Opus,
+            complexity: None,
+            bitrate_mode: None,
+            signal: None,
+            max_bandwidth: None,
             fec_enabled: false,
+            talkback: false,
+            channel_map: Vec::new(),
+            dred: false,
+            redundant: false,
+            plaintext: false,
+            destination: None,
+            priority: TrackPriority::Normal,
+            dtx: false,
+            silence_threshold_db: -60.0,
+            silence_hold_ms: 300,
+            high_pass_hz: 0.0,
+            noise_suppression: false,
+            agc: true,
+            agc_target_db: -18.0,
+            agc_attack_ms: 20.0,
+            agc_release_ms: 800.0,
+            buffer_frames: Some(256),
+            output_devices: vec!["virtual:lan-audio".to_string()],
+            volume: 0.5,
+            jitter_mode: JitterMode::Auto,
+            jitter_min_ms: None,
+            jitter_max_ms: Some(80),
+            jitter_target_ms: None,
         };
         
         let id = manager.create_track(config).unwrap();
         assert_eq!(id, 0);
         assert_eq!(manager.track_count(), 1);
+        
+        // Tracks restored with their IDs don't collide with new ones
+        manager.create_track(TrackConfig { track_id: Some(4), ..TrackConfig::default() }).unwrap();
+        assert_eq!(manager.create_track(TrackConfig::default()).unwrap(), 5);
     }
     
     #[test]
@@ -315,6 +1021,48 @@ mod tests {
         assert_eq!(manager.track_count(), 0);
     }
     
+    #[test]
+    fn test_apply_sender_config() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig::default()).unwrap();
+        let mut events = manager.subscribe();
+        
+        assert!(manager.apply_sender_config(id, "Vocals", 96_000, 1, 5.0).unwrap());
+        assert!(!manager.apply_sender_config(id, "Vocals", 96_000, 1, 5.0).unwrap());
+        assert!(manager.apply_sender_config(9, "Vocals", 96_000, 1, 5.0).is_err());
+        
+        let status = manager.get_track(id).unwrap().status();
+        assert_eq!((status.name.as_str(), status.bitrate, status.frame_size_ms), ("Vocals", 96_000, 5.0));
+        assert_eq!(manager.get_track(id).unwrap().config.channels, 1);
+        assert!(matches!(events.try_recv(), Ok(TrackEvent::ConfigUpdated(track_id)) if track_id == id));
+        assert!(events.try_recv().is_err());
+    }
+    
+    #[test]
+    fn test_track_destination() {
+        let manager = TrackManager::new();
+        let config = TrackConfig { destination: Some("192.168.1.30".to_string()), ..TrackConfig::default() };
+        let id = manager.create_track(config).unwrap();
+        assert_eq!(
+            manager.get_track(id).unwrap().config.destination_addr(),
+            Some("192.168.1.30:5000".parse().unwrap())
+        );
+        
+        let invalid = TrackConfig { destination: Some("pc-c".to_string()), ..TrackConfig::default() };
+        assert!(matches!(manager.create_track(invalid), Err(TrackError::InvalidConfig(_))));
+        let update = |destination: &str| TrackConfigUpdate {
+            destination: Some(destination.to_string()),
+            ..TrackConfigUpdate::default()
+        };
+        assert!(manager.update_track(id, update("192.168.1.31:abc")).is_err());
+        manager.update_track(id, update("192.168.1.31:6000")).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().config.destination.as_deref(), Some("192.168.1.31:6000"));
+        
+        // An empty destination goes back to the sender's target
+        manager.update_track(id, update("")).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().config.destination, None);
+    }
+    
     #[test]
     fn test_mute_solo() {
         let manager = TrackManager::new();
@@ -335,5 +1083,509 @@ mod tests {
         manager.set_solo(id1, true).unwrap();
         assert!(manager.should_output(id1));
         assert!(!manager.should_output(id2));
+        assert_eq!(manager.solo_gain(id1), 1.0);
+        assert_eq!(manager.solo_gain(id2), 0.0);
+        assert!(!manager.is_pfl(id1));
+    }
+    
+    #[test]
+    fn test_timeline_records_activity() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig {
+            name: "Mic".to_string(),
+            device_id: "input:USB".to_string(),
+            ..TrackConfig::default()
+        }).unwrap();
+        
+        // Only actual toggles are recorded
+        manager.set_muted(id, true).unwrap();
+        manager.set_muted(id, true).unwrap();
+        manager.set_muted(id, false).unwrap();
+        manager.update_track(id, TrackConfigUpdate {
+            device_id: Some("input:Headset".to_string()),
+            ..TrackConfigUpdate::default()
+        }).unwrap();
+        
+        let events = manager.timeline().since(None);
+        let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![
+            ActivityKind::TrackCreated,
+            ActivityKind::Muted,
+            ActivityKind::Unmuted,
+            ActivityKind::DeviceChanged,
+        ]);
+        assert!(events.iter().all(|event| event.track_id == Some(id)));
+        assert_eq!(events[3].detail, "Mic: input:USB -> input:Headset");
+    }
+    
+    #[test]
+    fn test_audio_incidents() {
+        let manager = TrackManager::new().with_gap_threshold_ms(100);
+        let id = manager.create_track(TrackConfig {
+            name: "Mic".to_string(),
+            ..TrackConfig::default()
+        }).unwrap();
+        manager.timeline().since(None);
+        
+        manager.check_clipping(id, &[0.5, -0.9]);
+        manager.check_clipping(id, &[0.5, -1.0]);
+        manager.record_drops(id, DropReason::Late, 1);
+        manager.record_drops(id, DropReason::Underflow, 2);
+        manager.record_arrival_gap(id, Duration::from_millis(80));
+        manager.record_arrival_gap(id, Duration::from_millis(350));
+        manager.record_jitter_buffer_reset(id, 42);
+        
+        let incidents: Vec<_> = manager
+            .timeline()
+            .since(None)
+            .into_iter()
+            .filter(|event| event.kind.is_incident())
+            .collect();
+        let kinds: Vec<_> = incidents.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![
+            ActivityKind::Clipping,
+            ActivityKind::Underrun,
+            ActivityKind::Gap,
+            ActivityKind::JitterBufferReset,
+        ]);
+        assert_eq!(incidents[0].detail, "Mic: peak +0.0 dBFS");
+        assert_eq!(incidents[2].detail, "Mic: no packets for 350 ms");
+    }
+    
+    #[test]
+    fn test_file_player_commands() {
+        use crate::audio::decode::DecodedAudio;
+        
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig {
+            device_id: "file:/tmp/jingle.wav".to_string(),
+            ..TrackConfig::default()
+        }).unwrap();
+        assert!(matches!(
+            manager.control_player(id, PlayerCommand::Pause),
+            Err(TrackError::InvalidConfig(_))
+        ));
+        
+        let audio = DecodedAudio { sample_rate: 1_000, channels: 1, samples: vec![0.0; 2_000] };
+        manager.set_file_player(id, Some(Arc::new(FilePlayer::new("/tmp/jingle.wav", audio))));
+        let status = manager.control_player(id, PlayerCommand::Seek { position_ms: 1_500 }).unwrap();
+        assert_eq!((status.position_ms, status.duration_ms), (1_500, 2_000));
+        assert!(manager.get_all_statuses()[0].file_player.as_ref().unwrap().playing);
+        
+        // A device switch drops the player until the new capture registers
+        manager.update_track(id, TrackConfigUpdate {
+            device_id: Some("input:USB".to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert!(manager.file_player(id).is_none());
+        assert!(matches!(manager.control_player(9, PlayerCommand::Play), Err(TrackError::NotFound(9))));
+    }
+    
+    #[test]
+    fn test_pfl_leaves_outputs_alone() {
+        let manager = TrackManager::new().with_solo_mode(SoloMode::Pfl);
+        let id1 = manager.create_track(TrackConfig::default()).unwrap();
+        let id2 = manager.create_track(TrackConfig::default()).unwrap();
+        
+        manager.set_solo(id1, true).unwrap();
+        assert!(manager.is_pfl(id1));
+        assert!(!manager.is_pfl(id2));
+        assert!(manager.should_output(id2));
+        assert_eq!(manager.solo_gain(id1), 1.0);
+        assert_eq!(manager.solo_gain(id2), 1.0);
+        
+        manager.set_solo(id1, false).unwrap();
+        assert!(!manager.is_pfl(id1));
+    }
+    
+    #[test]
+    fn test_peer_mix() {
+        let manager = TrackManager::new();
+        let peer_a: IpAddr = "192.168.1.10".parse().unwrap();
+        let peer_b: IpAddr = "192.168.1.11".parse().unwrap();
+        
+        assert_eq!(manager.peer_gain(peer_a), 1.0);
+        
+        manager.set_peer_mix(peer_a, Some(0.5), None).unwrap();
+        assert_eq!(manager.peer_gain(peer_a), 0.5);
+        assert_eq!(manager.peer_gain(peer_b), 1.0);
+        
+        // Mute keeps the gain for unmute
+        let mix = manager.set_peer_mix(peer_a, None, Some(true)).unwrap();
+        assert_eq!(mix.gain, 0.5);
+        assert_eq!(manager.peer_gain(peer_a), 0.0);
+        manager.set_peer_mix(peer_a, None, Some(false)).unwrap();
+        assert_eq!(manager.peer_gain(peer_a), 0.5);
+        
+        assert!(manager.set_peer_mix(peer_b, Some(MAX_PEER_GAIN * 2.0), None).is_err());
+        assert!(manager.set_peer_mix(peer_b, Some(f32::NAN), None).is_err());
+        assert_eq!(manager.peer_mixer().len(), 1);
+    }
+    
+    #[test]
+    fn test_output_dsp() {
+        let manager = TrackManager::new();
+        let mut events = manager.subscribe();
+        let delayed = OutputDsp {
+            delay_ms: 12.0,
+            ..OutputDsp::default()
+        };
+        
+        manager.set_output_dsp("output:Speakers", Some(delayed.clone())).unwrap();
+        assert_eq!(manager.output_dsp("output:Speakers"), Some(delayed));
+        assert!(matches!(events.try_recv(), Ok(TrackEvent::OutputDspChanged(id)) if id == "output:Speakers"));
+        
+        // Invalid settings keep the old ones
+        let invalid = OutputDsp {
+            delay_ms: -1.0,
+            ..OutputDsp::default()
+        };
+        assert!(manager.set_output_dsp("output:Speakers", Some(invalid)).is_err());
+        assert!(events.try_recv().is_err());
+        
+        // Settings that change nothing are the same as none
+        manager.set_output_dsp("output:Speakers", Some(OutputDsp::default())).unwrap();
+        assert_eq!(manager.output_dsp("output:Speakers"), None);
+    }
+    
+    #[test]
+    fn test_buffer_frames_reopen_device() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig {
+            device_id: "input:Mic".to_string(),
+            ..TrackConfig::default()
+        }).unwrap();
+        let mut events = manager.subscribe();
+        
+        let update = |frames| TrackConfigUpdate {
+            buffer_frames: Some(frames),
+            ..Default::default()
+        };
+        manager.update_track(id, update(128)).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().status().buffer_frames, Some(128));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(TrackEvent::DeviceChanged(track_id, old, new)) if track_id == id && old == "input:Mic" && new == old
+        ));
+        assert!(matches!(events.try_recv(), Ok(TrackEvent::ConfigUpdated(_))));
+        
+        // The same size does not reopen the device, 0 returns to the default
+        manager.update_track(id, update(128)).unwrap();
+        assert!(matches!(events.try_recv(), Ok(TrackEvent::ConfigUpdated(_))));
+        manager.update_track(id, update(0)).unwrap();
+        assert!(matches!(events.try_recv(), Ok(TrackEvent::DeviceChanged(..))));
+        assert_eq!(manager.get_track(id).unwrap().config.buffer_frames, None);
+        
+        assert!(manager.update_track(id, update(4)).is_err());
+        assert!(manager.create_track(TrackConfig { buffer_frames: Some(100_000), ..TrackConfig::default() }).is_err());
+    }
+    
+    #[test]
+    fn test_track_volume() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig::default()).unwrap();
+        assert_eq!(manager.send_gain(id), Some(1.0));
+        
+        // Volume scales sending and playing the track
+        let volume = |volume| TrackConfigUpdate { volume: Some(volume), ..Default::default() };
+        manager.update_track(id, volume(0.5)).unwrap();
+        assert_eq!(manager.send_gain(id), Some(0.5));
+        assert_eq!(manager.output_gain(id), 0.5);
+        assert!(manager.update_track(id, volume(-1.0)).is_err());
+        assert!(manager.update_track(id, volume(MAX_TRACK_VOLUME * 2.0)).is_err());
+        assert!(manager.create_track(TrackConfig { volume: 10.0, ..TrackConfig::default() }).is_err());
+    }
+    
+    #[test]
+    fn test_opus_settings() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig { complexity: Some(4), ..TrackConfig::default() }).unwrap();
+        assert!(manager.create_track(TrackConfig { complexity: Some(11), ..TrackConfig::default() }).is_err());
+        
+        let update: TrackConfigUpdate = serde_json::from_str(r#"{"bitrate_mode": "Cbr", "max_bandwidth": "Wideband"}"#).unwrap();
+        manager.update_track(id, update).unwrap();
+        let config = manager.get_track(id).unwrap().config.clone();
+        assert_eq!(config.complexity, Some(4));
+        assert_eq!(config.bitrate_mode, Some(OpusBitrateMode::Cbr));
+        assert_eq!(config.max_bandwidth, Some(OpusBandwidth::Wideband));
+        
+        // null returns a setting to the preset
+        let update: TrackConfigUpdate = serde_json::from_str(r#"{"complexity": null, "signal": "Voice"}"#).unwrap();
+        manager.update_track(id, update).unwrap();
+        let config = manager.get_track(id).unwrap().config.clone();
+        assert_eq!(config.complexity, None);
+        assert_eq!(config.signal, Some(OpusSignal::Voice));
+        assert_eq!(config.bitrate_mode, Some(OpusBitrateMode::Cbr));
+        
+        let opus = OpusConfig::music().with_track_settings(&config);
+        assert_eq!(opus.complexity, OpusConfig::music().complexity);
+        assert_eq!(opus.bitrate_mode(), OpusBitrateMode::Cbr);
+        assert_eq!(opus.signal, OpusSignal::Voice);
+        
+        let invalid = TrackConfigUpdate { complexity: Some(Some(12)), ..Default::default() };
+        assert!(manager.update_track(id, invalid).is_err());
+    }
+    
+    #[test]
+    fn test_frame_size_validation() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig { frame_size_ms: 2.5, ..TrackConfig::default() }).unwrap();
+        assert!(manager.create_track(TrackConfig { frame_size_ms: 7.0, ..TrackConfig::default() }).is_err());
+        
+        let frame_size = |frame_size_ms| TrackConfigUpdate { frame_size_ms: Some(frame_size_ms), ..Default::default() };
+        manager.update_track(id, frame_size(20.0)).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().config.frame_size_ms, 20.0);
+        assert!(manager.update_track(id, frame_size(0.0)).is_err());
+    }
+    
+    #[test]
+    fn test_jitter_delay() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig { jitter_max_ms: Some(60), ..TrackConfig::default() }).unwrap();
+        assert!(manager.create_track(TrackConfig { jitter_min_ms: Some(2000), ..TrackConfig::default() }).is_err());
+        
+        // Manual mode at a fixed +40 ms
+        let update = TrackConfigUpdate {
+            jitter_mode: Some(JitterMode::Manual),
+            jitter_target_ms: Some(40),
+            ..Default::default()
+        };
+        manager.update_track(id, update).unwrap();
+        let status = manager.get_track(id).unwrap().status();
+        assert_eq!((status.jitter_mode, status.jitter_target_ms), (JitterMode::Manual, Some(40)));
+        
+        // The fixed delay stays within the lowest and highest delay
+        let target = |ms| TrackConfigUpdate { jitter_target_ms: Some(ms), ..Default::default() };
+        assert!(manager.update_track(id, target(70)).is_err());
+        assert_eq!(manager.get_track(id).unwrap().config.jitter_target_ms, Some(40));
+        
+        // The lowest delay can't go above the highest or the fixed delay, 0 returns to the default
+        let min = |ms| TrackConfigUpdate { jitter_min_ms: Some(ms), ..Default::default() };
+        assert!(manager.update_track(id, min(80)).is_err());
+        assert!(manager.update_track(id, min(50)).is_err());
+        manager.update_track(id, min(30)).unwrap();
+        manager.update_track(id, min(0)).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().config.jitter_min_ms, None);
+    }
+    
+    #[test]
+    fn test_output_devices() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig::default()).unwrap();
+        
+        // Further outputs are replaced as a list
+        let outputs = |ids: &[&str]| TrackConfigUpdate {
+            output_devices: Some(ids.iter().map(|id| id.to_string()).collect()),
+            ..Default::default()
+        };
+        manager.update_track(id, outputs(&["output:Headphones", "virtual:lan-audio"])).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().status().output_devices.len(), 2);
+        assert!(manager.update_track(id, outputs(&["output:Headphones", "output:Headphones"])).is_err());
+        assert!(manager.update_track(id, outputs(&[""])).is_err());
+        manager.update_track(id, outputs(&[])).unwrap();
+        assert!(manager.get_track(id).unwrap().config.output_devices.is_empty());
+    }
+    
+    #[test]
+    fn test_channel_map_validation() {
+        let manager = TrackManager::new();
+        
+        let config = TrackConfig {
+            channel_map: vec![0, 0],
+            ..Default::default()
+        };
+        let id = manager.create_track(config).unwrap();
+        
+        let invalid = TrackConfig {
+            channel_map: vec![0, 12],
+            ..Default::default()
+        };
+        assert!(manager.create_track(invalid).is_err());
+        
+        let update = TrackConfigUpdate {
+            channel_map: Some(vec![1, 0]),
+            ..Default::default()
+        };
+        manager.update_track(id, update).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().config.channel_map, vec![1, 0]);
+        
+        let update = TrackConfigUpdate {
+            channel_map: Some(vec![0; 16]),
+            ..Default::default()
+        };
+        assert!(manager.update_track(id, update).is_err());
+        
+        // 5.1 and 7.1 tracks, nothing wider
+        assert!(manager.create_track(TrackConfig { channels: 6, ..Default::default() }).is_ok());
+        assert!(manager.create_track(TrackConfig { channels: 8, ..Default::default() }).is_ok());
+        assert!(manager.create_track(TrackConfig { channels: 9, ..Default::default() }).is_err());
+        assert!(manager.create_track(TrackConfig { channels: 0, ..Default::default() }).is_err());
+    }
+    
+    #[test]
+    fn test_dred_voice_only() {
+        let manager = TrackManager::new();
+        
+        let music = TrackConfig {
+            dred: true,
+            ..Default::default()
+        };
+        assert!(manager.create_track(music).is_err());
+        
+        let voice = TrackConfig {
+            dred: true,
+            ..TrackConfig::talkback("mic")
+        };
+        let id = manager.create_track(voice).unwrap();
+        
+        let music_id = manager.create_track(TrackConfig::default()).unwrap();
+        let update = TrackConfigUpdate {
+            dred: Some(true),
+            ..Default::default()
+        };
+        assert!(manager.update_track(music_id, update.clone()).is_err());
+        manager.update_track(id, update).unwrap();
+    }
+    
+    #[test]
+    fn test_voice_processing_validation() {
+        let manager = TrackManager::new();
+        
+        let music = TrackConfig {
+            noise_suppression: true,
+            ..Default::default()
+        };
+        assert!(manager.create_track(music).is_err());
+        let id = manager.create_track(TrackConfig::talkback("mic")).unwrap();
+        
+        let high_pass = |high_pass_hz| TrackConfigUpdate {
+            high_pass_hz: Some(high_pass_hz),
+            ..Default::default()
+        };
+        manager.update_track(id, high_pass(80.0)).unwrap();
+        assert!(manager.update_track(id, high_pass(5.0)).is_err());
+        assert!(manager.update_track(id, high_pass(f32::NAN)).is_err());
+        assert_eq!(manager.get_track(id).unwrap().config.high_pass_hz, 80.0);
+        manager.update_track(id, high_pass(0.0)).unwrap();
+    }
+    
+    #[test]
+    fn test_agc_validation() {
+        let manager = TrackManager::new();
+        
+        let loud = TrackConfig {
+            agc: true,
+            agc_target_db: 0.0,
+            ..Default::default()
+        };
+        assert!(manager.create_track(loud).is_err());
+        let id = manager.create_track(TrackConfig::default()).unwrap();
+        
+        let update = TrackConfigUpdate {
+            agc: Some(true),
+            agc_release_ms: Some(0.0),
+            ..Default::default()
+        };
+        assert!(manager.update_track(id, update).is_err());
+        assert!(!manager.get_track(id).unwrap().config.agc);
+        
+        let update = TrackConfigUpdate {
+            agc: Some(true),
+            agc_target_db: Some(-24.0),
+            ..Default::default()
+        };
+        manager.update_track(id, update).unwrap();
+        let status = manager.get_track(id).unwrap().status();
+        assert!(status.agc_gain_db.is_none());
+    }
+    
+    #[test]
+    fn test_talkback_gates_and_ducks() {
+        let manager = TrackManager::new();
+        
+        let music = manager.create_track(TrackConfig::default()).unwrap();
+        assert!(manager.set_talkback(true).is_err());
+        
+        let talkback = manager.create_track(TrackConfig::talkback("mic")).unwrap();
+        assert!(manager.create_track(TrackConfig::talkback("mic2")).is_err());
+        assert_eq!(manager.talkback_track_id(), Some(talkback));
+        
+        // Released: talkback silent, other tracks at full level
+        assert_eq!(manager.send_gain(talkback), None);
+        assert_eq!(manager.send_gain(music), Some(1.0));
+        
+        manager.set_talkback(true).unwrap();
+        assert_eq!(manager.send_gain(talkback), Some(1.0));
+        assert_eq!(manager.send_gain(music), Some(TALKBACK_DUCK_GAIN));
+        let statuses = manager.get_all_statuses();
+        assert!(statuses.iter().any(|s| s.track_id == music && s.ducked));
+        assert!(statuses.iter().any(|s| s.track_id == talkback && s.talkback && !s.ducked));
+        
+        manager.set_talkback(false).unwrap();
+        assert_eq!(manager.send_gain(talkback), None);
+        assert_eq!(manager.send_gain(music), Some(1.0));
+    }
+    
+    #[test]
+    fn test_playout_drops_outlive_track() {
+        let manager = TrackManager::new();
+        let id = manager.create_track(TrackConfig::default()).unwrap();
+        
+        manager.record_drops(id, DropReason::NoDevice, 3);
+        manager.record_drops(id, DropReason::Underflow, 0);
+        let status = manager.get_all_statuses().remove(0);
+        assert_eq!(status.drops.no_device, 3);
+        
+        manager.remove_track(id).unwrap();
+        manager.record_drops(id, DropReason::Deleted, 1);
+        let archive = manager.playout_drops();
+        assert_eq!(archive.len(), 1);
+        assert!(!archive[0].present);
+        assert_eq!(archive[0].drops.total(), 4);
+    }
+    
+    #[test]
+    fn test_saved_track_finds_renamed_device() {
+        let manager = TrackManager::new();
+        let devices = vec![AudioDeviceInfo {
+            id: "input:Microphone (2- USB Audio)".to_string(),
+            name: "Microphone (2- USB Audio)".to_string(),
+            is_input: true,
+            is_output: false,
+            is_default: true,
+            sample_rates: vec![48000],
+            channels: vec![1],
+            is_loopback: false,
+            capture_mode: None,
+            playback_mode: None,
+        }];
+        
+        let saved = TrackConfig {
+            device_id: "input:Microphone (USB Audio)".to_string(),
+            ..TrackConfig::default()
+        };
+        let id = manager.create_saved_track(saved, &devices).unwrap();
+        let status = manager.get_all_statuses().remove(0);
+        assert_eq!(status.device_id, "input:Microphone (2- USB Audio)");
+        assert_eq!(status.device_match, Some(DeviceMatch::Fuzzy));
+        // The new name is what the track saves from now on
+        assert_eq!(
+            manager.get_track(id).unwrap().config.device_name.as_deref(),
+            Some("Microphone (2- USB Audio)")
+        );
+        
+        let missing = TrackConfig {
+            device_id: "input:Webcam".to_string(),
+            ..TrackConfig::default()
+        };
+        let id = manager.create_saved_track(missing, &devices).unwrap();
+        let track = manager.get_track(id).unwrap();
+        assert_eq!(track.device_id, "input:Webcam");
+        assert_eq!(track.device_match(), Some(DeviceMatch::NotFound));
+        drop(track);
+        
+        // A virtual output or the default device has nothing to match
+        let id = manager.create_saved_track(TrackConfig::default(), &devices).unwrap();
+        assert_eq!(manager.get_track(id).unwrap().device_match(), Some(DeviceMatch::Id));
     }
 }
@@ -1,7 +1,11 @@
 //! Track management module
 
+pub mod auto;
+pub mod device_match;
 pub mod manager;
+pub mod timeline;
 pub mod track;
 
 pub use manager::{TrackManager, TrackEvent};
+pub use timeline::{ActivityEvent, ActivityKind, Timeline};
 pub use track::{Track, TrackState};
@@ -0,0 +1,215 @@
+//! Activity timeline of tracks and peers
+//!
+//! Track starts and stops, device switches, mute toggles and peers
+//! joining, connecting, leaving, dropping out or going silent are stamped with the
+//! wall-clock time and kept in memory, so a stream can be reviewed
+//! afterwards: "audio vanished at 21:34" lines up with "device changed at
+//! 21:34". Audio incidents of a track (clipping, buffer underruns,
+//! jitter-buffer resets, gaps in the packet stream) go into the same
+//! timeline, at most one per track and kind every [`INCIDENT_INTERVAL_MS`]
+//! with the repeats counted, so "it glitched at 21:14" can be looked up.
+//! The newest [`CAPACITY`] events are served at
+//! `GET /api/events?since=<ms>&track=<id>&incidents=true`.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::network::timesync::unix_time_ms;
+
+/// Events kept in the timeline
+pub const CAPACITY: usize = 1024;
+
+/// Shortest time between two incidents of one kind on a track
+pub const INCIDENT_INTERVAL_MS: u64 = 1000;
+
+/// What happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+    TrackCreated,
+    TrackRemoved,
+    TrackStarted,
+    TrackStopped,
+    DeviceChanged,
+    Muted,
+    Unmuted,
+    PeerJoined,
+    /// Handshake with a peer completed
+    PeerConnected,
+    /// A peer said goodbye
+    PeerLeft,
+    /// A peer stopped answering keepalive pings
+    PeerLost,
+    /// A peer missed a few keepalive pings (sending to it is paused)
+    PeerInterrupted,
+    /// An interrupted peer answers again
+    PeerResumed,
+    /// Audio of a track reached full scale
+    Clipping,
+    /// The output ran out of a track's audio
+    Underrun,
+    /// The sender restarted the stream and the jitter buffer started over
+    JitterBufferReset,
+    /// No packets of a track arrived for longer than the gap threshold
+    Gap,
+}
+
+impl ActivityKind {
+    /// Whether this is an audio incident of a track
+    pub fn is_incident(self) -> bool {
+        matches!(self, Self::Clipping | Self::Underrun | Self::JitterBufferReset | Self::Gap)
+    }
+}
+
+/// One timeline entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivityEvent {
+    /// Wall-clock time (ms since the Unix epoch)
+    pub time_ms: u64,
+    pub kind: ActivityKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track_id: Option<u8>,
+    /// Track name, device switch ("old -> new") or peer name and address
+    pub detail: String,
+}
+
+/// Ring of the newest [`CAPACITY`] events
+pub struct Timeline {
+    events: Mutex<VecDeque<ActivityEvent>>,
+    /// Time of the last recorded incident and repeats left out since, per
+    /// track and kind
+    incidents: Mutex<HashMap<(u8, ActivityKind), (u64, u64)>>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new(VecDeque::new()),
+            incidents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record an event now
+    pub fn record(&self, kind: ActivityKind, track_id: Option<u8>, detail: impl Into<String>) {
+        self.push(ActivityEvent {
+            time_ms: unix_time_ms(),
+            kind,
+            track_id,
+            detail: detail.into(),
+        });
+    }
+
+    /// Record an audio incident of a track now, unless one of the same
+    /// kind was recorded less than [`INCIDENT_INTERVAL_MS`] ago
+    pub fn record_incident(&self, kind: ActivityKind, track_id: u8, detail: impl Into<String>) {
+        self.push_incident(ActivityEvent {
+            time_ms: unix_time_ms(),
+            kind,
+            track_id: Some(track_id),
+            detail: detail.into(),
+        });
+    }
+
+    fn push_incident(&self, mut event: ActivityEvent) {
+        let key = (event.track_id.unwrap_or_default(), event.kind);
+        {
+            let mut incidents = self.incidents.lock();
+            let (last_ms, repeats) = incidents.entry(key).or_insert((0, 0));
+            if *last_ms > 0 && event.time_ms.saturating_sub(*last_ms) < INCIDENT_INTERVAL_MS {
+                *repeats += 1;
+                return;
+            }
+            if *repeats > 0 {
+                event.detail = format!("{} (+{} since the last one)", event.detail, repeats);
+            }
+            *last_ms = event.time_ms;
+            *repeats = 0;
+        }
+        self.push(event);
+    }
+
+    fn push(&self, event: ActivityEvent) {
+        let mut events = self.events.lock();
+        if events.len() == CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Events later than `since_ms` (all if None), oldest first
+    pub fn since(&self, since_ms: Option<u64>) -> Vec<ActivityEvent> {
+        let since_ms = since_ms.unwrap_or(0);
+        self.events
+            .lock()
+            .iter()
+            .filter(|event| event.time_ms > since_ms)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time_ms: u64, kind: ActivityKind) -> ActivityEvent {
+        ActivityEvent {
+            time_ms,
+            kind,
+            track_id: Some(0),
+            detail: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_since_and_capacity() {
+        let timeline = Timeline::new();
+        timeline.push(event(1_000, ActivityKind::TrackStarted));
+        timeline.push(event(2_000, ActivityKind::DeviceChanged));
+        timeline.push(event(3_000, ActivityKind::TrackStopped));
+
+        assert_eq!(timeline.since(None).len(), 3);
+        let later: Vec<_> = timeline.since(Some(2_000)).iter().map(|event| event.kind).collect();
+        assert_eq!(later, vec![ActivityKind::TrackStopped]);
+        assert!(timeline.since(Some(3_000)).is_empty());
+
+        for i in 0..CAPACITY as u64 {
+            timeline.push(event(10_000 + i, ActivityKind::Muted));
+        }
+        let events = timeline.since(None);
+        assert_eq!(events.len(), CAPACITY);
+        assert!(events.iter().all(|event| event.kind == ActivityKind::Muted));
+    }
+
+    #[test]
+    fn test_incidents_are_rate_limited() {
+        let timeline = Timeline::new();
+        for time_ms in [1_000, 1_200, 1_900, 2_100] {
+            timeline.push_incident(event(time_ms, ActivityKind::Clipping));
+        }
+        // Another kind or track is recorded on its own
+        timeline.push_incident(event(1_300, ActivityKind::Underrun));
+        timeline.push_incident(ActivityEvent {
+            track_id: Some(1),
+            ..event(1_400, ActivityKind::Clipping)
+        });
+
+        let events = timeline.since(None);
+        assert_eq!(events.len(), 4);
+        let clipping: Vec<_> = events
+            .iter()
+            .filter(|event| event.kind == ActivityKind::Clipping && event.track_id == Some(0))
+            .collect();
+        assert_eq!(clipping.iter().map(|event| event.time_ms).collect::<Vec<_>>(), vec![1_000, 2_100]);
+        assert!(clipping[1].detail.ends_with("(+2 since the last one)"));
+        assert!(events.iter().all(|event| event.kind.is_incident()));
+        assert!(!ActivityKind::TrackStarted.is_incident());
+    }
+}
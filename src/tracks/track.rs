@@ -7,12 +7,16 @@ use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 
+use parking_lot::Mutex;
+
 use crate::audio::buffer::{create_shared_buffer, SharedRingBuffer};
-use crate::audio::level_meter::SmoothLevelMeter;
+use crate::audio::level_meter::{LevelMeterParams, SmoothLevelMeter};
+use crate::audio::loudness::LoudnessMeter;
 use crate::config::OpusConfig;
 use crate::error::TrackError;
-use crate::protocol::{TrackConfig, TrackStatus, TrackType};
-use crate::constants::RING_BUFFER_CAPACITY;
+use crate::tracks::device_match;
+use crate::protocol::{DeviceMatch, PlayoutDrops, TrackConfig, TrackStatus, TrackType};
+use crate::constants::{DEFAULT_SAMPLE_RATE, RING_BUFFER_CAPACITY};
 
 /// Состояние трека
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -70,6 +74,43 @@ pub struct Track {
     /// Текущая оценка джиттера в микросекундах (AtomicU32 для потокобезопасности)
     jitter_us: Arc<AtomicU32>,
     
+    /// Расхождение часов устройства в ppm (биты f32, NaN - не измерено)
+    clock_skew_ppm: Arc<AtomicU32>,
+    
+    /// Задержка от захвата до воспроизведения в мс (биты f32, NaN - часы не синхронизированы)
+    e2e_latency_ms: Arc<AtomicU32>,
+    
+    /// Задержка пробы до потока вывода и до входа петли в мс (биты f32, NaN - не измерялась)
+    probe_latency_ms: Arc<AtomicU32>,
+    loopback_latency_ms: Arc<AtomicU32>,
+    
+    /// Битрейт после адаптации по обратной связи (0 - адаптация не применялась)
+    adaptive_bitrate: Arc<AtomicU32>,
+    
+    /// Отправка приостановлена контролем перегрузки
+    congestion_paused: Arc<AtomicBool>,
+    
+    /// Кадры не отправляются: на входе тишина (подавление тишины)
+    silent: Arc<AtomicBool>,
+    
+    /// Усиление АРУ в dB (биты f32, NaN - АРУ выключена)
+    agc_gain_db: Arc<AtomicU32>,
+    
+    /// Подавление усиления обработкой вывода в dB (биты f32, NaN - нет компрессора и лимитера)
+    output_gain_reduction_db: Arc<AtomicU32>,
+    
+    /// Коррекция дрейфа часов отправителя при выводе в ppm (биты f32, NaN - не оценена)
+    playout_drift_ppm: Arc<AtomicU32>,
+    
+    /// Период потока устройства в мс (биты f32, NaN - поток не открыт)
+    device_period_ms: Arc<AtomicU32>,
+    
+    /// Задержка устройства по данным хоста в мс (биты f32, NaN - не сообщается)
+    device_latency_ms: Arc<AtomicU32>,
+    
+    /// Как найдено устройство трека из файла конфигурации
+    device_match: Option<DeviceMatch>,
+    
     /// Время запуска
     start_time: Option<Instant>,
     
@@ -79,6 +120,9 @@ pub struct Track {
     /// Сглаженный измеритель уровня (заменяет peak_level_millibels)
     /// Использует lock-free атомарные операции для плавной визуализации
     level_meter: Arc<SmoothLevelMeter>,
+    
+    /// Измеритель громкости по EBU R128 (LUFS)
+    loudness: Arc<Mutex<LoudnessMeter>>,
 }
 
 // Track теперь Send + Sync безопасен (нет сырых указателей)
@@ -88,6 +132,11 @@ unsafe impl Sync for Track {}
 impl Track {
     /// Создать новый трек
     pub fn new(id: u8, config: TrackConfig) -> Self {
+        Self::with_meter_params(id, config, LevelMeterParams::default())
+    }
+    
+    /// Создать трек с заданными параметрами измерителя уровня
+    pub fn with_meter_params(id: u8, config: TrackConfig, meter_params: LevelMeterParams) -> Self {
         Self {
             id,
             name: config.name.clone(),
@@ -101,10 +150,24 @@ impl Track {
             packets_lost: Arc::new(AtomicU64::new(0)),
             latency_us: Arc::new(AtomicU32::new(0)),
             jitter_us: Arc::new(AtomicU32::new(0)),
+            clock_skew_ppm: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            e2e_latency_ms: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            probe_latency_ms: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            loopback_latency_ms: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            adaptive_bitrate: Arc::new(AtomicU32::new(0)),
+            congestion_paused: Arc::new(AtomicBool::new(false)),
+            silent: Arc::new(AtomicBool::new(false)),
+            agc_gain_db: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            output_gain_reduction_db: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            playout_drift_ppm: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            device_period_ms: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            device_latency_ms: Arc::new(AtomicU32::new(f32::NAN.to_bits())),
+            device_match: None,
             start_time: None,
             last_error: None,
             // Используем новый сглаженный измеритель уровня
-            level_meter: Arc::new(SmoothLevelMeter::new()),
+            level_meter: Arc::new(SmoothLevelMeter::with_params(meter_params)),
+            loudness: Arc::new(Mutex::new(LoudnessMeter::new(DEFAULT_SAMPLE_RATE))),
         }
     }
     
@@ -125,9 +188,11 @@ impl Track {
             bitrate: self.config.bitrate,
             frame_size,
             channels: self.config.channels,
-            fec: self.config.fec_enabled,
             ..base_config
         }
+        .with_fec(self.config.fec_enabled)
+        .with_dred(self.config.dred)
+        .with_track_settings(&self.config)
     }
     
     /// Start the track
@@ -140,6 +205,8 @@ impl Track {
         self.start_time = Some(Instant::now());
         self.packets_count.store(0, Ordering::Relaxed);
         self.packets_lost.store(0, Ordering::Relaxed);
+        // Интегральная громкость считается с запуска трека
+        self.loudness.lock().reset();
         self.state = TrackState::Running;
         
         Ok(())
@@ -223,6 +290,14 @@ impl Track {
         self.level_meter.update_from_samples(samples);
     }
     
+    /// Измерить громкость семплов с `channels` каналами (маркеры тишины
+    /// без семплов не меняют показаний)
+    pub fn update_loudness(&self, samples: &[f32], channels: usize) {
+        if !samples.is_empty() {
+            self.loudness.lock().process(samples, channels);
+        }
+    }
+    
     /// Получить текущий уровень в dB (сглаженный)
     /// 
     /// Возвращает плавно интерполированное значение, подходящее для UI.
@@ -273,6 +348,146 @@ impl Track {
         self.jitter_us.load(Ordering::Relaxed) as f32 / 1000.0
     }
     
+    /// Update device clock skew (ppm), None if not measured yet
+    pub fn update_clock_skew(&self, skew_ppm: Option<f64>) {
+        let value = skew_ppm.map(|v| v as f32).unwrap_or(f32::NAN);
+        self.clock_skew_ppm.store(value.to_bits(), Ordering::Relaxed);
+    }
+    
+    /// Get device clock skew in ppm
+    pub fn clock_skew_ppm(&self) -> Option<f32> {
+        let value = f32::from_bits(self.clock_skew_ppm.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Update capture-to-playback latency (microseconds), None if clocks are not synchronized
+    pub fn update_e2e_latency(&self, latency_us: Option<u64>) {
+        let value = latency_us.map(|us| us as f32 / 1000.0).unwrap_or(f32::NAN);
+        self.e2e_latency_ms.store(value.to_bits(), Ordering::Relaxed);
+    }
+    
+    /// Update latencies measured by the latency probe (microseconds):
+    /// to the output stream and to the analog loopback input
+    pub fn update_probe_latency(&self, output_us: Option<u64>, loopback_us: Option<u64>) {
+        for (value, latency_us) in [(&self.probe_latency_ms, output_us), (&self.loopback_latency_ms, loopback_us)] {
+            let ms = latency_us.map(|us| us as f32 / 1000.0).unwrap_or(f32::NAN);
+            value.store(ms.to_bits(), Ordering::Relaxed);
+        }
+    }
+    
+    /// Update bitrate chosen by the adaptive controller
+    pub fn update_adaptive_bitrate(&self, bitrate: u32) {
+        self.adaptive_bitrate.store(bitrate, Ordering::Relaxed);
+    }
+    
+    /// Get current adaptive bitrate, None until feedback adjusted the encoder
+    pub fn adaptive_bitrate(&self) -> Option<u32> {
+        match self.adaptive_bitrate.load(Ordering::Relaxed) {
+            0 => None,
+            bitrate => Some(bitrate),
+        }
+    }
+    
+    /// Mark the track paused (or resumed) by congestion control
+    pub fn set_congestion_paused(&self, paused: bool) {
+        self.congestion_paused.store(paused, Ordering::Relaxed);
+    }
+    
+    /// Whether congestion control has paused sending the track
+    pub fn is_congestion_paused(&self) -> bool {
+        self.congestion_paused.load(Ordering::Relaxed)
+    }
+    
+    /// Mark the track silent (frames suppressed) or sending again
+    pub fn set_silent(&self, silent: bool) {
+        self.silent.store(silent, Ordering::Relaxed);
+    }
+    
+    /// Whether silence suppression currently holds back the track's frames
+    pub fn is_silent(&self) -> bool {
+        self.silent.load(Ordering::Relaxed)
+    }
+    
+    /// Update gain applied by the AGC (dB), None if the AGC is off
+    pub fn update_agc_gain(&self, gain_db: Option<f32>) {
+        self.agc_gain_db.store(gain_db.unwrap_or(f32::NAN).to_bits(), Ordering::Relaxed);
+    }
+    
+    /// Get gain applied by the AGC in dB
+    pub fn agc_gain_db(&self) -> Option<f32> {
+        let value = f32::from_bits(self.agc_gain_db.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Update gain reduction of the track's output (dB), None without a
+    /// compressor or limiter there
+    pub fn update_output_gain_reduction(&self, reduction_db: Option<f32>) {
+        self.output_gain_reduction_db.store(reduction_db.unwrap_or(f32::NAN).to_bits(), Ordering::Relaxed);
+    }
+    
+    /// Get gain reduction of the track's output in dB
+    pub fn output_gain_reduction_db(&self) -> Option<f32> {
+        let value = f32::from_bits(self.output_gain_reduction_db.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Update the clock drift correction of the track's playout (ppm)
+    pub fn update_playout_drift(&self, drift_ppm: Option<f32>) {
+        self.playout_drift_ppm.store(drift_ppm.unwrap_or(f32::NAN).to_bits(), Ordering::Relaxed);
+    }
+    
+    /// Get the clock drift correction of the track's playout in ppm
+    pub fn playout_drift_ppm(&self) -> Option<f32> {
+        let value = f32::from_bits(self.playout_drift_ppm.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Update the period and latency (ms) the track's device stream achieved
+    pub fn update_device_timing(&self, period_ms: Option<f32>, latency_ms: Option<f32>) {
+        self.device_period_ms.store(period_ms.unwrap_or(f32::NAN).to_bits(), Ordering::Relaxed);
+        self.device_latency_ms.store(latency_ms.unwrap_or(f32::NAN).to_bits(), Ordering::Relaxed);
+    }
+    
+    /// Get the period of the track's device stream in ms
+    pub fn device_period_ms(&self) -> Option<f32> {
+        let value = f32::from_bits(self.device_period_ms.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Get the latency the host reports for the track's device in ms
+    pub fn device_latency_ms(&self) -> Option<f32> {
+        let value = f32::from_bits(self.device_latency_ms.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Record how the device of a saved track was found
+    pub fn set_device_match(&mut self, device_match: DeviceMatch) {
+        self.device_match = Some(device_match);
+    }
+    
+    /// How the device of a saved track was found
+    pub fn device_match(&self) -> Option<DeviceMatch> {
+        self.device_match
+    }
+    
+    /// Get capture-to-playback latency in milliseconds
+    pub fn e2e_latency_ms(&self) -> Option<f32> {
+        let value = f32::from_bits(self.e2e_latency_ms.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Get probe latency to the output stream in milliseconds
+    pub fn probe_latency_ms(&self) -> Option<f32> {
+        let value = f32::from_bits(self.probe_latency_ms.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
+    /// Get probe latency to the analog loopback input in milliseconds
+    pub fn loopback_latency_ms(&self) -> Option<f32> {
+        let value = f32::from_bits(self.loopback_latency_ms.load(Ordering::Relaxed));
+        (!value.is_nan()).then_some(value)
+    }
+    
     /// Set error state
     pub fn set_error(&mut self, error: String) {
         self.state = TrackState::Error;
@@ -294,6 +509,9 @@ impl Track {
         if let Some(ref device_id) = update.device_id {
             self.device_id = device_id.clone();
             self.config.device_id = device_id.clone();
+            // Выбранное устройство запоминается по имени
+            device_match::remember_device(&mut self.config);
+            self.device_match = None;
         }
         
         if let Some(bitrate) = update.bitrate {
@@ -311,6 +529,124 @@ impl Track {
             // Примечание: Если кодер существует в другом месте, вызывающий код должен его обновить
         }
         
+        if let Some(codec) = update.codec {
+            self.config.codec = codec;
+            // Примечание: Работающий кодер пересоздаётся по событию ConfigUpdated
+        }
+        
+        if let Some(complexity) = update.complexity {
+            self.config.complexity = complexity;
+            // Примечание: Работающий кодер перенастраивается по событию ConfigUpdated
+        }
+        
+        if let Some(bitrate_mode) = update.bitrate_mode {
+            self.config.bitrate_mode = bitrate_mode;
+        }
+        
+        if let Some(signal) = update.signal {
+            self.config.signal = signal;
+        }
+        
+        if let Some(max_bandwidth) = update.max_bandwidth {
+            self.config.max_bandwidth = max_bandwidth;
+        }
+        
+        if let Some(dred) = update.dred {
+            self.config.dred = dred;
+            // Примечание: Работающий кодер переключается по событию ConfigUpdated
+        }
+        
+        if let Some(redundant) = update.redundant {
+            self.config.redundant = redundant;
+        }
+        
+        if let Some(plaintext) = update.plaintext {
+            self.config.plaintext = plaintext;
+        }
+        
+        if let Some(priority) = update.priority {
+            self.config.priority = priority;
+        }
+        
+        if let Some(dtx) = update.dtx {
+            self.config.dtx = dtx;
+            // Примечание: Работающий трек применяет подавление тишины по событию ConfigUpdated
+        }
+        
+        if let Some(threshold_db) = update.silence_threshold_db {
+            self.config.silence_threshold_db = threshold_db;
+        }
+        
+        if let Some(hold_ms) = update.silence_hold_ms {
+            self.config.silence_hold_ms = hold_ms;
+        }
+        
+        if let Some(high_pass_hz) = update.high_pass_hz {
+            self.config.high_pass_hz = high_pass_hz;
+            // Примечание: Работающий захват применяет обработку голоса по событию ConfigUpdated
+        }
+        
+        if let Some(noise_suppression) = update.noise_suppression {
+            self.config.noise_suppression = noise_suppression;
+        }
+        
+        if let Some(agc) = update.agc {
+            self.config.agc = agc;
+            // Примечание: Работающий захват применяет АРУ по событию ConfigUpdated
+        }
+        
+        if let Some(target_db) = update.agc_target_db {
+            self.config.agc_target_db = target_db;
+        }
+        
+        if let Some(attack_ms) = update.agc_attack_ms {
+            self.config.agc_attack_ms = attack_ms;
+        }
+        
+        if let Some(release_ms) = update.agc_release_ms {
+            self.config.agc_release_ms = release_ms;
+        }
+        
+        if let Some(ref destination) = update.destination {
+            self.config.destination = Some(destination.clone()).filter(|d| !d.is_empty());
+        }
+        
+        if let Some(ref channel_map) = update.channel_map {
+            self.config.channel_map = channel_map.clone();
+            // Примечание: Захват и вывод трека применяют карту каналов по событию ConfigUpdated
+        }
+        
+        if let Some(buffer_frames) = update.buffer_frames {
+            self.config.buffer_frames = Some(buffer_frames).filter(|&frames| frames > 0);
+            // Примечание: Устройство открывается заново по событию DeviceChanged
+        }
+        
+        if let Some(ref output_devices) = update.output_devices {
+            self.config.output_devices = output_devices.clone();
+            // Примечание: Вывод трека открывает и закрывает копии по событию ConfigUpdated
+        }
+        
+        if let Some(volume) = update.volume {
+            self.config.volume = volume;
+        }
+        
+        if let Some(jitter_mode) = update.jitter_mode {
+            self.config.jitter_mode = jitter_mode;
+            // Примечание: Джиттер-буфер трека меняет задержку по событию ConfigUpdated
+        }
+        
+        if let Some(min_ms) = update.jitter_min_ms {
+            self.config.jitter_min_ms = Some(min_ms).filter(|&ms| ms > 0);
+        }
+        
+        if let Some(max_ms) = update.jitter_max_ms {
+            self.config.jitter_max_ms = Some(max_ms).filter(|&ms| ms > 0);
+        }
+        
+        if let Some(target_ms) = update.jitter_target_ms {
+            self.config.jitter_target_ms = Some(target_ms).filter(|&ms| ms > 0);
+        }
+        
         Ok(())
     }
     
@@ -320,26 +656,68 @@ impl Track {
     pub fn status(&self) -> TrackStatus {
         // Обновляем измеритель для плавной анимации
         self.level_meter.tick_for_ui();
+        let loudness = self.loudness.lock();
         
         TrackStatus {
             track_id: self.id,
             name: self.name.clone(),
             device_id: self.device_id.clone(),
+            device_match: self.device_match,
             active: self.is_running(),
             muted: self.is_muted(),
             solo: self.is_solo(),
+            talkback: self.config.talkback,
+            // Заполняется менеджером треков
+            ducked: false,
             bitrate: self.config.bitrate,
+            codec: self.config.codec,
+            track_type: self.config.track_type,
+            complexity: self.config.complexity,
+            bitrate_mode: self.config.bitrate_mode,
+            signal: self.config.signal,
+            max_bandwidth: self.config.max_bandwidth,
             frame_size_ms: self.config.frame_size_ms,
             packets_sent: self.packets_count(),
             packets_received: self.packets_count(),
             packets_lost: self.packets_lost(),
             current_latency_ms: self.latency_ms(),
             jitter_ms: self.jitter_ms(),
+            clock_skew_ppm: self.clock_skew_ppm(),
+            buffer_frames: self.config.buffer_frames,
+            device_period_ms: self.device_period_ms(),
+            device_latency_ms: self.device_latency_ms(),
+            output_devices: self.config.output_devices.clone(),
+            volume: self.config.volume,
+            jitter_mode: self.config.jitter_mode,
+            jitter_min_ms: self.config.jitter_min_ms,
+            jitter_max_ms: self.config.jitter_max_ms,
+            jitter_target_ms: self.config.jitter_target_ms,
+            e2e_latency_ms: self.e2e_latency_ms(),
+            probe_latency_ms: self.probe_latency_ms(),
+            loopback_latency_ms: self.loopback_latency_ms(),
+            adaptive_bitrate: self.adaptive_bitrate(),
+            priority: self.config.priority,
+            congestion_paused: self.is_congestion_paused(),
+            dtx: self.config.dtx,
+            high_pass_hz: self.config.high_pass_hz,
+            noise_suppression: self.config.noise_suppression,
+            agc: self.config.agc,
+            agc_gain_db: self.agc_gain_db(),
+            output_gain_reduction_db: self.output_gain_reduction_db(),
+            playout_drift_ppm: self.playout_drift_ppm(),
+            silent: self.is_silent(),
             // Сглаженные значения для плавной визуализации
             level_db: self.level_meter.level_db(),
             peak_db: self.level_meter.peak_db(),
             level_normalized: self.level_meter.level_normalized(),
             peak_normalized: self.level_meter.peak_normalized(),
+            loudness_momentary_lufs: loudness.momentary_lufs().map(|lufs| lufs as f32),
+            loudness_short_term_lufs: loudness.short_term_lufs().map(|lufs| lufs as f32),
+            loudness_integrated_lufs: loudness.integrated_lufs().map(|lufs| lufs as f32),
+            drops: PlayoutDrops::default(),
+            // Заполняется менеджером треков
+            file_player: None,
+            destination: self.config.destination.clone(),
         }
     }
 }
@@ -1,16 +1,29 @@
 //! HTTP API handlers
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
+use std::time::Instant;
 
 use crate::audio::device::list_devices;
+use crate::logs::{self, LogLevel, LogRecord};
+use crate::network::file_transfer::TransferStatus;
+use crate::network::link_test;
+use crate::network::packet_log::{self, LoggedPacket};
+use crate::profiling::{self, StageSummary};
 use crate::protocol::{
-    AudioDeviceInfo, ControlMessage, TrackConfig, TrackConfigUpdate, TrackStatus,
+    AudioDeviceInfo, ControlMessage, LinkTestReport, MidiLearn, MidiStatus, OutputDsp, PairingStatus, PeerMix, PeerStatus, RecordingRequest, RecordingStatus, RemoteCapabilities,
+    TrackConfig, TrackConfigUpdate, TrackDrops,
 };
+use crate::sessions::SessionsReport;
+use crate::stats::StatsReport;
+use crate::tracks::ActivityEvent;
 use crate::ui::server::AppState;
 
 /// API response wrapper
@@ -41,6 +54,45 @@ impl<T> ApiResponse<T> {
     }
 }
 
+/// Serve JSON with an ETag (hash of the body). Clients that send the ETag
+/// back in `If-None-Match` get 304 Not Modified while nothing changed.
+fn conditional_json<T: serde::Serialize>(headers: &HeaderMap, body: &T) -> Response {
+    let json = match serde_json::to_vec(body) {
+        Ok(json) => json,
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(e.to_string()))).into_response();
+        }
+    };
+    
+    let etag = etag_for(&json);
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, &etag));
+    let validators = [(header::ETAG, etag), (header::CACHE_CONTROL, "no-cache".to_string())];
+    
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, validators).into_response();
+    }
+    (validators, [(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+/// Strong ETag for a response body
+fn etag_for(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Check an `If-None-Match` value against an ETag (weak comparison, as
+/// required for If-None-Match)
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
 /// System status
 #[derive(serde::Serialize)]
 pub struct SystemStatus {
@@ -49,17 +101,18 @@ pub struct SystemStatus {
     pub uptime_seconds: u64,
 }
 
-/// Get system status
+/// Get system status (supports conditional GET)
 pub async fn get_status(
     State(state): State<Arc<AppState>>,
-) -> Json<ApiResponse<SystemStatus>> {
+    headers: HeaderMap,
+) -> Response {
     let status = SystemStatus {
         mode: if state.is_sender { "sender" } else { "receiver" }.to_string(),
         track_count: state.track_manager.track_count(),
         uptime_seconds: 0, // TODO: Track uptime
     };
     
-    Json(ApiResponse::ok(status))
+    conditional_json(&headers, &ApiResponse::ok(status))
 }
 
 /// Get available audio devices
@@ -68,12 +121,366 @@ pub async fn get_devices() -> Json<ApiResponse<Vec<AudioDeviceInfo>>> {
     Json(ApiResponse::ok(devices))
 }
 
-/// Get all tracks
+/// Get all tracks (supports conditional GET)
 pub async fn get_tracks(
     State(state): State<Arc<AppState>>,
-) -> Json<ApiResponse<Vec<TrackStatus>>> {
-    let tracks = state.track_manager.get_all_statuses();
-    Json(ApiResponse::ok(tracks))
+    headers: HeaderMap,
+) -> Response {
+    // Stable order so identical state hashes to the same ETag
+    let mut tracks = state.track_manager.get_all_statuses();
+    tracks.sort_by_key(|track| track.track_id);
+    conditional_json(&headers, &ApiResponse::ok(tracks))
+}
+
+/// Get known peers with bandwidth usage
+pub async fn get_peers(
+    State(state): State<Arc<AppState>>,
+) -> Json<ApiResponse<Vec<PeerStatus>>> {
+    Json(ApiResponse::ok(state.peers.statuses()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AddPeerRequest {
+    /// Audio address ("ip:port", or an IP for the default port)
+    pub address: String,
+    pub name: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RenamePeerRequest {
+    pub name: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PairPeerRequest {
+    /// One-time PIN shown in the peer's web UI
+    pub pin: String,
+}
+
+/// Connect to a peer by address (peers discovery can't reach)
+pub async fn add_peer(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<AddPeerRequest>,
+) -> (StatusCode, Json<ApiResponse<PeerStatus>>) {
+    let result = state.connect_peer(&req.address).and_then(|peer| match req.name {
+        Some(ref name) => state.rename_peer(&peer.id, name),
+        None => Ok(peer),
+    });
+    peer_response(result)
+}
+
+/// Start sending to a known peer
+pub async fn connect_peer(
+    State(state): State<Arc<AppState>>,
+    Path(peer): Path<String>,
+) -> (StatusCode, Json<ApiResponse<PeerStatus>>) {
+    peer_response(state.connect_peer(&peer))
+}
+
+/// Stop sending to a peer
+pub async fn disconnect_peer(
+    State(state): State<Arc<AppState>>,
+    Path(peer): Path<String>,
+) -> (StatusCode, Json<ApiResponse<PeerStatus>>) {
+    peer_response(state.disconnect_peer(&peer))
+}
+
+/// Change the display name of a peer
+pub async fn rename_peer(
+    State(state): State<Arc<AppState>>,
+    Path(peer): Path<String>,
+    Json(req): Json<RenamePeerRequest>,
+) -> (StatusCode, Json<ApiResponse<PeerStatus>>) {
+    peer_response(state.rename_peer(&peer, &req.name))
+}
+
+/// Connect to a peer proving the PIN it shows
+pub async fn pair_peer(
+    State(state): State<Arc<AppState>>,
+    Path(peer): Path<String>,
+    Json(req): Json<PairPeerRequest>,
+) -> (StatusCode, Json<ApiResponse<PeerStatus>>) {
+    peer_response(state.pair_peer(&peer, &req.pin))
+}
+
+/// Pairing requirement, node key, allowlist and the current PIN
+pub async fn get_pairing(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<PairingStatus>>) {
+    match state.pairing {
+        Some(ref pairing) => (StatusCode::OK, Json(ApiResponse::ok(pairing.status(Instant::now())))),
+        None => (StatusCode::NOT_FOUND, Json(ApiResponse::error("pairing is not available"))),
+    }
+}
+
+/// Replace the one-time PIN, invalidating the previous one
+pub async fn new_pairing_pin(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<PairingStatus>>) {
+    let Some(ref pairing) = state.pairing else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error("pairing is not available")));
+    };
+    let now = Instant::now();
+    pairing.new_pin(now);
+    (StatusCode::OK, Json(ApiResponse::ok(pairing.status(now))))
+}
+
+/// Test the link to a peer before streaming: RTT, loss and jitter at
+/// several packet rates and sizes with recommended settings (takes a few
+/// seconds)
+pub async fn test_peer_link(
+    State(state): State<Arc<AppState>>,
+    Path(peer): Path<String>,
+) -> (StatusCode, Json<ApiResponse<LinkTestReport>>) {
+    let Some(address) = state.peers.resolve_address(&peer) else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("Unknown peer: {}", peer))));
+    };
+    match tokio::task::spawn_blocking(move || link_test::run(address)).await {
+        Ok(Ok(report)) => (StatusCode::OK, Json(ApiResponse::ok(report))),
+        Ok(Err(e)) => (StatusCode::BAD_GATEWAY, Json(ApiResponse::error(e.to_string()))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+fn peer_response(result: Result<PeerStatus, (StatusCode, String)>) -> (StatusCode, Json<ApiResponse<PeerStatus>>) {
+    match result {
+        Ok(peer) => (StatusCode::OK, Json(ApiResponse::ok(peer))),
+        Err((status, message)) => (status, Json(ApiResponse::error(message))),
+    }
+}
+
+/// Per-track pipeline profile (`profiling` feature)
+pub async fn get_profile() -> (StatusCode, Json<ApiResponse<Vec<StageSummary>>>) {
+    if !profiling::is_enabled() {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(ApiResponse::error("built without the profiling feature")),
+        );
+    }
+    (StatusCode::OK, Json(ApiResponse::ok(profiling::summary())))
+}
+
+/// Clear the pipeline profile counters
+pub async fn reset_profile() -> Json<ApiResponse<()>> {
+    profiling::reset();
+    Json(ApiResponse::ok(()))
+}
+
+/// Control packets sent and received (`network.debug_capture`)
+pub async fn get_debug_packets() -> (StatusCode, Json<ApiResponse<Vec<LoggedPacket>>>) {
+    if !packet_log::is_enabled() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("packet log is off (set network.debug_capture or LAN_AUDIO_DEBUG_CAPTURE=1)")),
+        );
+    }
+    (StatusCode::OK, Json(ApiResponse::ok(packet_log::snapshot())))
+}
+
+/// Clear the control packet log
+pub async fn clear_debug_packets() -> Json<ApiResponse<()>> {
+    packet_log::clear();
+    Json(ApiResponse::ok(()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct LogsQuery {
+    /// Only records after this `seq`
+    pub since: Option<u64>,
+    /// Only records at this level or more severe (error, warn, info, debug)
+    pub level: Option<LogLevel>,
+    /// Only the newest records
+    pub limit: Option<usize>,
+}
+
+/// Log records kept in memory, oldest first
+pub async fn get_logs(Query(query): Query<LogsQuery>) -> Json<ApiResponse<Vec<LogRecord>>> {
+    Json(ApiResponse::ok(logs::records(query.since, query.level, query.limit)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EventsQuery {
+    /// Only events after this time (ms since the Unix epoch)
+    pub since: Option<u64>,
+    /// Only events of this track
+    pub track: Option<u8>,
+    /// Only audio incidents (clipping, underruns, jitter-buffer resets, gaps)
+    #[serde(default)]
+    pub incidents: bool,
+}
+
+/// Track and peer activity timeline
+pub async fn get_events(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EventsQuery>,
+) -> Json<ApiResponse<Vec<ActivityEvent>>> {
+    let events = state
+        .track_manager
+        .timeline()
+        .since(query.since)
+        .into_iter()
+        .filter(|event| query.track.is_none() || event.track_id == query.track)
+        .filter(|event| !query.incidents || event.kind.is_incident())
+        .collect();
+    Json(ApiResponse::ok(events))
+}
+
+#[derive(serde::Deserialize)]
+pub struct StatsQuery {
+    /// Only samples after this time (ms since the Unix epoch)
+    pub since: Option<u64>,
+    /// Only the last minutes (the whole history if absent)
+    pub minutes: Option<u64>,
+}
+
+/// Per-second history of track and peer statistics
+pub async fn get_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatsQuery>,
+) -> Json<ApiResponse<StatsReport>> {
+    let window_secs = query.minutes.map(|minutes| minutes.saturating_mul(60));
+    Json(ApiResponse::ok(state.stats.report(query.since, window_secs)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SessionsQuery {
+    /// Only sessions that ended after this time (ms since the Unix epoch)
+    pub since: Option<u64>,
+    /// Only sessions with this peer (key or name)
+    pub peer: Option<String>,
+}
+
+/// Running and past streaming sessions with their loss, jitter and glitches
+pub async fn get_sessions(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SessionsQuery>,
+) -> Json<ApiResponse<SessionsReport>> {
+    Json(ApiResponse::ok(state.sessions.report(query.since, query.peer.as_deref())))
+}
+
+/// Recorder state and files of the current or last recording
+pub async fn get_recording(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<RecordingStatus>>) {
+    match state.recorder {
+        Some(ref recorder) => (StatusCode::OK, Json(ApiResponse::ok(recorder.status()))),
+        None => (StatusCode::NOT_FOUND, Json(ApiResponse::error("recording needs a receiving mode"))),
+    }
+}
+
+/// Start recording received audio
+pub async fn start_recording(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RecordingRequest>,
+) -> (StatusCode, Json<ApiResponse<RecordingStatus>>) {
+    let Some(ref recorder) = state.recorder else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error("recording needs a receiving mode")));
+    };
+    match recorder.start(request) {
+        Ok(status) => {
+            let _ = state.control_tx.send(ControlMessage::Recording(status.clone()));
+            (StatusCode::OK, Json(ApiResponse::ok(status)))
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    }
+}
+
+/// Stop recording and close the files
+pub async fn stop_recording(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<RecordingStatus>>) {
+    let Some(ref recorder) = state.recorder else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error("recording needs a receiving mode")));
+    };
+    let status = recorder.stop();
+    let _ = state.control_tx.send(ControlMessage::Recording(status.clone()));
+    (StatusCode::OK, Json(ApiResponse::ok(status)))
+}
+
+/// MIDI ports, mappings and learn mode
+pub async fn get_midi(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<MidiStatus>>) {
+    match state.midi {
+        Some(ref midi) => (StatusCode::OK, Json(ApiResponse::ok(midi.status()))),
+        None => (StatusCode::NOT_FOUND, Json(ApiResponse::error("MIDI is not enabled"))),
+    }
+}
+
+/// Map the next MIDI control moved to a track control
+pub async fn start_midi_learn(
+    State(state): State<Arc<AppState>>,
+    Json(learn): Json<MidiLearn>,
+) -> (StatusCode, Json<ApiResponse<MidiStatus>>) {
+    let Some(ref midi) = state.midi else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error("MIDI is not enabled")));
+    };
+    if state.track_manager.get_track(learn.track_id).is_none() {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("Track {} not found", learn.track_id))));
+    }
+    midi.learn(Some(learn));
+    (StatusCode::OK, Json(ApiResponse::ok(midi.status())))
+}
+
+/// Leave learn mode without mapping a control
+pub async fn cancel_midi_learn(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<MidiStatus>>) {
+    let Some(ref midi) = state.midi else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error("MIDI is not enabled")));
+    };
+    midi.learn(None);
+    (StatusCode::OK, Json(ApiResponse::ok(midi.status())))
+}
+
+/// Remove a MIDI mapping by its index
+pub async fn delete_midi_mapping(
+    State(state): State<Arc<AppState>>,
+    Path(index): Path<usize>,
+) -> (StatusCode, Json<ApiResponse<MidiStatus>>) {
+    let Some(ref midi) = state.midi else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error("MIDI is not enabled")));
+    };
+    if midi.forget(index).is_none() {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("No MIDI mapping {}", index))));
+    }
+    state.midi_changed();
+    (StatusCode::OK, Json(ApiResponse::ok(midi.status())))
+}
+
+/// Files sent to and received from peers
+pub async fn get_file_transfers(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<Vec<TransferStatus>>>) {
+    match state.files {
+        Some(ref files) => (StatusCode::OK, Json(ApiResponse::ok(files.transfers()))),
+        None => (StatusCode::NOT_FOUND, Json(ApiResponse::error("file drop needs peer mode"))),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SendFileQuery {
+    pub name: String,
+}
+
+/// Send the request body as a file to a connected peer
+pub async fn send_file(
+    State(state): State<Arc<AppState>>,
+    Path(peer): Path<String>,
+    Query(query): Query<SendFileQuery>,
+    body: Bytes,
+) -> (StatusCode, Json<ApiResponse<u32>>) {
+    let Some(ref files) = state.files else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error("file drop needs peer mode")));
+    };
+    let Some(address) = state.peers.get(&peer).filter(|entry| entry.is_active()).map(|entry| entry.address) else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("Peer not connected: {}", peer))));
+    };
+    
+    match files.send(&peer, address, &query.name, body) {
+        Ok(id) => (StatusCode::OK, Json(ApiResponse::ok(id))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    }
 }
 
 /// Create a new track
@@ -83,6 +490,8 @@ pub async fn create_track(
 ) -> (StatusCode, Json<ApiResponse<u8>>) {
     match state.track_manager.create_track(config) {
         Ok(id) => {
+            state.track_changed(id);
+            
             // Broadcast creation
             let _ = state.control_tx.send(ControlMessage::CreateTrack(
                 state.track_manager.get_track(id)
@@ -105,6 +514,7 @@ pub async fn delete_track(
 ) -> (StatusCode, Json<ApiResponse<()>>) {
     match state.track_manager.remove_track(id) {
         Ok(_) => {
+            state.track_removed(id);
             let _ = state.control_tx.send(ControlMessage::RemoveTrack { track_id: id });
             (StatusCode::OK, Json(ApiResponse::ok(())))
         }
@@ -122,6 +532,7 @@ pub async fn update_track(
 ) -> (StatusCode, Json<ApiResponse<()>>) {
     match state.track_manager.update_track(id, update.clone()) {
         Ok(_) => {
+            state.track_changed(id);
             let _ = state.control_tx.send(ControlMessage::UpdateTrack {
                 track_id: id,
                 config: update,
@@ -184,6 +595,110 @@ pub async fn set_solo(
     }
 }
 
+/// Press/release the talkback button
+#[derive(serde::Deserialize)]
+pub struct TalkbackRequest {
+    pub active: bool,
+}
+
+pub async fn set_talkback(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TalkbackRequest>,
+) -> (StatusCode, Json<ApiResponse<()>>) {
+    match state.track_manager.set_talkback(req.active) {
+        Ok(_) => {
+            let _ = state.control_tx.send(ControlMessage::SetTalkback {
+                active: req.active,
+            });
+            (StatusCode::OK, Json(ApiResponse::ok(())))
+        }
+        Err(e) => {
+            (StatusCode::NOT_FOUND, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// What the receivers of our tracks support
+pub async fn get_capabilities(
+    State(state): State<Arc<AppState>>,
+) -> Json<ApiResponse<RemoteCapabilities>> {
+    Json(ApiResponse::ok(state.track_manager.remote_capabilities()))
+}
+
+/// Why received audio didn't play, per track, including deleted tracks
+pub async fn get_drops(
+    State(state): State<Arc<AppState>>,
+) -> Json<ApiResponse<Vec<TrackDrops>>> {
+    Json(ApiResponse::ok(state.track_manager.playout_drops()))
+}
+
+/// Get per-peer mixer settings
+pub async fn get_peer_mixer(
+    State(state): State<Arc<AppState>>,
+) -> Json<ApiResponse<Vec<PeerMix>>> {
+    Json(ApiResponse::ok(state.track_manager.peer_mixer()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PeerMixRequest {
+    pub gain: Option<f32>,
+    pub muted: Option<bool>,
+}
+
+/// Set volume/mute of audio received from a peer
+pub async fn set_peer_mix(
+    State(state): State<Arc<AppState>>,
+    Path(peer): Path<String>,
+    Json(req): Json<PeerMixRequest>,
+) -> (StatusCode, Json<ApiResponse<PeerMix>>) {
+    let Some(ip) = state.peers.resolve_ip(&peer) else {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("Unknown peer: {}", peer))));
+    };
+    
+    match state.track_manager.set_peer_mix(ip, req.gain, req.muted) {
+        Ok(mix) => {
+            let _ = state.control_tx.send(ControlMessage::PeerMixer(state.track_manager.peer_mixer()));
+            (StatusCode::OK, Json(ApiResponse::ok(mix)))
+        }
+        Err(e) => {
+            (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Get the master processing of an output device (empty when bypassed)
+pub async fn get_output_dsp(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+) -> Json<ApiResponse<OutputDsp>> {
+    Json(ApiResponse::ok(state.track_manager.output_dsp(&device_id).unwrap_or_default()))
+}
+
+/// Replace the master processing of an output device
+pub async fn set_output_dsp(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Json(settings): Json<OutputDsp>,
+) -> (StatusCode, Json<ApiResponse<OutputDsp>>) {
+    match state.track_manager.set_output_dsp(&device_id, Some(settings.clone())) {
+        Ok(()) => {
+            state.output_dsp_changed(&device_id);
+            (StatusCode::OK, Json(ApiResponse::ok(settings)))
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Bypass the master processing of an output device
+pub async fn clear_output_dsp(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+) -> Json<ApiResponse<()>> {
+    let _ = state.track_manager.set_output_dsp(&device_id, None);
+    state.output_dsp_changed(&device_id);
+    Json(ApiResponse::ok(()))
+}
+
 /// Start a track
 pub async fn start_track(
     State(state): State<Arc<AppState>>,
@@ -213,3 +728,33 @@ pub async fn stop_track(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_conditional_json() {
+        let body = ApiResponse::ok(vec![1, 2, 3]);
+        let first = conditional_json(&HeaderMap::new(), &body);
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let cached = conditional_json(&headers, &body);
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(cached.headers()[header::ETAG], etag);
+
+        // Changed state gets a new representation
+        let changed = conditional_json(&headers, &ApiResponse::ok(vec![1, 2]));
+        assert_eq!(changed.status(), StatusCode::OK);
+        assert_ne!(changed.headers()[header::ETAG], etag);
+
+        // Lists and weak validators match too
+        let list = format!("\"other\", W/{}", etag.to_str().unwrap());
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&list).unwrap());
+        assert_eq!(conditional_json(&headers, &body).status(), StatusCode::NOT_MODIFIED);
+    }
+}
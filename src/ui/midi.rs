@@ -0,0 +1,328 @@
+//! MIDI controller input
+//!
+//! CC and note messages of a controller such as a nanoKONTROL are mapped to
+//! track volume, mute and solo ([`MidiMapping`]): faders and knobs set the
+//! volume (0..127 from silence to unity), buttons toggle mute or solo when
+//! pressed. In learn mode the next control moved is mapped to the track
+//! control chosen in the web UI and saved to the configuration file.
+//!
+//! Input ports are looked for every [`PORT_SCAN_INTERVAL`], so a controller
+//! plugged in while streaming is picked up.
+
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::MidiConfig;
+use crate::protocol::{ControlMessage, MidiKind, MidiLearn, MidiMapping, MidiStatus, MidiTarget, TrackConfigUpdate};
+use crate::ui::server::AppState;
+
+/// Interval of looking for input ports plugged in or removed
+pub const PORT_SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Client name shown by the MIDI system
+const CLIENT_NAME: &str = "lan-audio-streamer";
+
+/// A CC or note message received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidiEvent {
+    /// MIDI channel 1-16
+    pub channel: u8,
+    pub kind: MidiKind,
+    pub number: u8,
+    /// Controller value or velocity (0 for note off)
+    pub value: u8,
+}
+
+impl MidiEvent {
+    /// Parse a channel message; other messages are `None`
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        let (&number, &value) = match data {
+            [number, value, ..] => (number, value),
+            _ => return None,
+        };
+        let channel = (status & 0x0F) + 1;
+        let (kind, value) = match status & 0xF0 {
+            0x80 => (MidiKind::Note, 0),
+            0x90 => (MidiKind::Note, value),
+            0xB0 => (MidiKind::Cc, value),
+            _ => return None,
+        };
+        Some(Self { channel, kind, number: number & 0x7F, value: value & 0x7F })
+    }
+
+    fn matches(&self, mapping: &MidiMapping) -> bool {
+        mapping.channel == self.channel && mapping.kind == self.kind && mapping.number == self.number
+    }
+}
+
+/// Mappings and learn mode shared by the input ports and the web UI
+pub struct MidiRemote {
+    mappings: Mutex<Vec<MidiMapping>>,
+    learning: Mutex<Option<MidiLearn>>,
+    ports: Mutex<Vec<String>>,
+}
+
+impl MidiRemote {
+    pub fn new(mappings: Vec<MidiMapping>) -> Self {
+        Self {
+            mappings: Mutex::new(mappings),
+            learning: Mutex::new(None),
+            ports: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn status(&self) -> MidiStatus {
+        MidiStatus {
+            ports: self.ports.lock().clone(),
+            mappings: self.mappings(),
+            learning: *self.learning.lock(),
+        }
+    }
+
+    pub fn mappings(&self) -> Vec<MidiMapping> {
+        self.mappings.lock().clone()
+    }
+
+    /// Map the next control moved to a track control (None cancels)
+    pub fn learn(&self, learn: Option<MidiLearn>) {
+        *self.learning.lock() = learn;
+    }
+
+    /// Remove a mapping by its index in the list
+    pub fn forget(&self, index: usize) -> Option<MidiMapping> {
+        let mut mappings = self.mappings.lock();
+        (index < mappings.len()).then(|| mappings.remove(index))
+    }
+
+    /// Apply a message to the tracks it is mapped to; in learn mode it is
+    /// mapped instead, and the new mapping is returned
+    pub fn handle(&self, event: &MidiEvent, state: &AppState) -> Option<MidiMapping> {
+        if let Some(learn) = self.learning.lock().take() {
+            let mapping = MidiMapping {
+                channel: event.channel,
+                kind: event.kind,
+                number: event.number,
+                track_id: learn.track_id,
+                control: learn.control,
+            };
+            // A control drives one track control, a track control is driven by one control
+            let mut mappings = self.mappings.lock();
+            mappings.retain(|m| {
+                !event.matches(m) && (m.track_id, m.control) != (learn.track_id, learn.control)
+            });
+            mappings.push(mapping);
+            drop(mappings);
+            tracing::info!(
+                "MIDI {:?} {} on channel {} mapped to {:?} of track {}",
+                event.kind, event.number, event.channel, learn.control, learn.track_id
+            );
+            state.midi_changed();
+            return Some(mapping);
+        }
+
+        let track_manager = &state.track_manager;
+        for mapping in self.mappings().iter().filter(|m| event.matches(m)) {
+            let track_id = mapping.track_id;
+            let Some((muted, solo)) = track_manager
+                .get_track(track_id)
+                .map(|track| (track.is_muted(), track.is_solo()))
+            else {
+                continue;
+            };
+            let result = match mapping.control {
+                MidiTarget::Volume => {
+                    let update = TrackConfigUpdate {
+                        volume: Some(event.value as f32 / 127.0),
+                        ..Default::default()
+                    };
+                    track_manager.update_track(track_id, update).map(|()| state.track_changed(track_id))
+                }
+                // Buttons toggle on press and ignore the release
+                _ if event.value == 0 => Ok(()),
+                MidiTarget::Mute => {
+                    let muted = !muted;
+                    track_manager.set_muted(track_id, muted).map(|()| {
+                        let _ = state.control_tx.send(ControlMessage::SetMute { track_id, muted });
+                    })
+                }
+                MidiTarget::Solo => {
+                    let solo = !solo;
+                    track_manager.set_solo(track_id, solo).map(|()| {
+                        let _ = state.control_tx.send(ControlMessage::SetSolo { track_id, solo });
+                    })
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!("MIDI {:?} of track {}: {}", mapping.control, track_id, e);
+            }
+        }
+        None
+    }
+}
+
+/// Connect the input ports of `config` and apply their messages until dropped
+pub async fn serve(config: MidiConfig, remote: Arc<MidiRemote>, state: Arc<AppState>) -> std::io::Result<()> {
+    struct StopOnDrop(Arc<AtomicBool>);
+    impl Drop for StopOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let _stop = StopOnDrop(stop.clone());
+    // Connections of some MIDI systems can't move between threads
+    std::thread::Builder::new()
+        .name("midi-input".to_string())
+        .spawn(move || run_ports(config.port, remote, state, stop))?;
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+fn run_ports(filter: Option<String>, remote: Arc<MidiRemote>, state: Arc<AppState>, stop: Arc<AtomicBool>) {
+    let mut connections: HashMap<String, MidiInputConnection<()>> = HashMap::new();
+    let mut warned = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        match MidiInput::new(CLIENT_NAME) {
+            Ok(input) => {
+                let names: Vec<String> = input
+                    .ports()
+                    .iter()
+                    .filter_map(|port| input.port_name(port).ok())
+                    .filter(|name| filter.as_deref().is_none_or(|filter| name.contains(filter)))
+                    .collect();
+                connections.retain(|name, _| {
+                    let present = names.contains(name);
+                    if !present {
+                        tracing::info!("MIDI input {} removed", name);
+                    }
+                    present
+                });
+                for name in &names {
+                    if connections.contains_key(name) {
+                        continue;
+                    }
+                    match connect(name, &remote, &state) {
+                        Ok(connection) => {
+                            tracing::info!("MIDI input {} connected", name);
+                            connections.insert(name.clone(), connection);
+                        }
+                        Err(e) => tracing::warn!("MIDI input {}: {}", name, e),
+                    }
+                }
+                *remote.ports.lock() = connections.keys().cloned().collect();
+                warned = false;
+            }
+            Err(e) if !warned => {
+                tracing::warn!("MIDI is not available: {}", e);
+                warned = true;
+            }
+            Err(_) => {}
+        }
+        std::thread::sleep(PORT_SCAN_INTERVAL);
+    }
+}
+
+fn connect(name: &str, remote: &Arc<MidiRemote>, state: &Arc<AppState>) -> Result<MidiInputConnection<()>, String> {
+    let mut input = MidiInput::new(CLIENT_NAME).map_err(|e| e.to_string())?;
+    // SysEx, clock and active sensing are of no use here
+    input.ignore(Ignore::All);
+    let port = input
+        .ports()
+        .into_iter()
+        .find(|port| input.port_name(port).is_ok_and(|port_name| port_name == name))
+        .ok_or_else(|| "port is gone".to_string())?;
+    let (remote, state) = (remote.clone(), state.clone());
+    input
+        .connect(
+            &port,
+            CLIENT_NAME,
+            move |_, bytes, _| {
+                if let Some(event) = MidiEvent::parse(bytes) {
+                    remote.handle(&event, &state);
+                }
+            },
+            (),
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::TrackConfig;
+    use crate::tracks::TrackManager;
+
+    #[test]
+    fn test_parse() {
+        let cc = MidiEvent::parse(&[0xB1, 7, 100]).unwrap();
+        assert_eq!(cc, MidiEvent { channel: 2, kind: MidiKind::Cc, number: 7, value: 100 });
+        let note_on = MidiEvent::parse(&[0x90, 36, 127]).unwrap();
+        assert_eq!(note_on, MidiEvent { channel: 1, kind: MidiKind::Note, number: 36, value: 127 });
+        let note_off = MidiEvent::parse(&[0x8F, 36, 64]).unwrap();
+        assert_eq!(note_off, MidiEvent { channel: 16, kind: MidiKind::Note, number: 36, value: 0 });
+
+        // Pitch bend, program change and short messages are not mapped
+        assert_eq!(MidiEvent::parse(&[0xE0, 0, 64]), None);
+        assert_eq!(MidiEvent::parse(&[0xC0, 5]), None);
+        assert_eq!(MidiEvent::parse(&[0xF8]), None);
+        assert_eq!(MidiEvent::parse(&[]), None);
+    }
+
+    #[test]
+    fn test_learn_and_handle() {
+        let manager = Arc::new(TrackManager::new());
+        let track_id = manager.create_track(TrackConfig::default()).unwrap();
+        let state = AppState::new(manager.clone(), true);
+        let remote = MidiRemote::new(Vec::new());
+        let cc = |number, value| MidiEvent { channel: 1, kind: MidiKind::Cc, number, value };
+
+        // Not mapped yet
+        assert_eq!(remote.handle(&cc(0, 64), &state), None);
+        assert_eq!(manager.get_track(track_id).unwrap().config.volume, 1.0);
+
+        // The next control moved is mapped
+        remote.learn(Some(MidiLearn { track_id, control: MidiTarget::Volume }));
+        let mapping = remote.handle(&cc(0, 64), &state).unwrap();
+        assert_eq!(mapping.number, 0);
+        assert_eq!(remote.status().learning, None);
+        assert_eq!(manager.get_track(track_id).unwrap().config.volume, 1.0);
+
+        remote.handle(&cc(0, 127), &state);
+        assert_eq!(manager.get_track(track_id).unwrap().config.volume, 1.0);
+        remote.handle(&cc(0, 0), &state);
+        assert_eq!(manager.get_track(track_id).unwrap().config.volume, 0.0);
+
+        // A button toggles on press and ignores the release
+        remote.learn(Some(MidiLearn { track_id, control: MidiTarget::Mute }));
+        remote.handle(&cc(32, 127), &state);
+        remote.handle(&cc(32, 0), &state);
+        assert!(!manager.get_track(track_id).unwrap().is_muted());
+        remote.handle(&cc(32, 127), &state);
+        remote.handle(&cc(32, 0), &state);
+        assert!(manager.get_track(track_id).unwrap().is_muted());
+        remote.handle(&cc(32, 127), &state);
+        assert!(!manager.get_track(track_id).unwrap().is_muted());
+
+        // Learning a control again replaces both its mapping and the track control's
+        remote.learn(Some(MidiLearn { track_id, control: MidiTarget::Solo }));
+        remote.handle(&cc(0, 127), &state);
+        let mappings = remote.mappings();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!((mappings[1].number, mappings[1].control), (0, MidiTarget::Solo));
+        remote.learn(Some(MidiLearn { track_id, control: MidiTarget::Solo }));
+        remote.handle(&cc(1, 127), &state);
+        assert_eq!(remote.mappings().len(), 2);
+        assert_eq!(remote.mappings()[1].number, 1);
+
+        assert_eq!(remote.forget(0).unwrap().control, MidiTarget::Mute);
+        assert_eq!(remote.forget(5), None);
+        assert_eq!(remote.mappings().len(), 1);
+    }
+}
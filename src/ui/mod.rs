@@ -2,6 +2,9 @@
 
 pub mod server;
 pub mod handlers;
+pub mod midi;
+pub mod osc;
+pub mod rpc;
 pub mod websocket;
 
 pub use server::WebServer;
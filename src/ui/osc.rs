@@ -0,0 +1,412 @@
+//! OSC (Open Sound Control) remote control
+//!
+//! Control surfaces and TouchOSC mute, solo and set the volume of tracks
+//! with OSC 1.0 messages over UDP, at the addresses of [`OscConfig`]
+//! (`/track/2/mute 1`). Track levels, and mute, solo and volume whenever
+//! they change, are sent back to every client that sent a message and to
+//! the configured feedback addresses; a message without an argument asks
+//! for the current value.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::{parse_socket_addr, OscConfig};
+use crate::constants::MAX_TRACK_VOLUME;
+use crate::protocol::{TrackConfigUpdate, TrackStatus};
+use crate::ui::server::AppState;
+
+/// Clients remembered for feedback
+const MAX_CLIENTS: usize = 16;
+
+/// Interval of checking track state for changes to send
+const STATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Argument of an OSC message
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    /// Numeric value (true = 1)
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            OscArg::Int(value) => Some(value as f32),
+            OscArg::Float(value) => Some(value),
+            OscArg::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+            OscArg::Str(_) => None,
+        }
+    }
+}
+
+/// An OSC message
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: impl Into<String>, args: Vec<OscArg>) -> Self {
+        Self { address: address.into(), args }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_string(&mut out, &self.address);
+        let tags: String = std::iter::once(',')
+            .chain(self.args.iter().map(|arg| match arg {
+                OscArg::Int(_) => 'i',
+                OscArg::Float(_) => 'f',
+                OscArg::Str(_) => 's',
+                OscArg::Bool(true) => 'T',
+                OscArg::Bool(false) => 'F',
+            }))
+            .collect();
+        write_string(&mut out, &tags);
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(value) => out.extend_from_slice(&value.to_be_bytes()),
+                OscArg::Float(value) => out.extend_from_slice(&value.to_be_bytes()),
+                OscArg::Str(value) => write_string(&mut out, value),
+                OscArg::Bool(_) => {}
+            }
+        }
+        out
+    }
+}
+
+/// Null-terminated string padded to four bytes
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(value.as_bytes());
+    let padding = 4 - value.len() % 4;
+    out.extend(std::iter::repeat_n(0, padding));
+}
+
+/// Messages of an OSC packet: one message, or every message of a bundle
+/// (nested bundles included, time tags ignored). Malformed packets and
+/// unsupported argument types yield nothing.
+pub fn decode_packet(data: &[u8]) -> Vec<OscMessage> {
+    let mut messages = Vec::new();
+    decode_into(data, &mut messages);
+    messages
+}
+
+fn decode_into(data: &[u8], messages: &mut Vec<OscMessage>) -> Option<()> {
+    let mut reader = Reader { data, pos: 0 };
+    if data.starts_with(b"#bundle\0") {
+        reader.pos = 16; // "#bundle" and the time tag
+        while reader.pos < data.len() {
+            let size = usize::try_from(reader.read_i32()?).ok()?;
+            let element = data.get(reader.pos..reader.pos.checked_add(size)?)?;
+            decode_into(element, messages);
+            reader.pos += size;
+        }
+        return Some(());
+    }
+
+    let address = reader.read_string()?;
+    if !address.starts_with('/') {
+        return None;
+    }
+    // Messages from old implementations may have no type tags
+    let tags = if reader.pos < data.len() { reader.read_string()? } else { ",".to_string() };
+    let mut args = Vec::new();
+    for tag in tags.strip_prefix(',')?.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(reader.read_i32()?),
+            'f' => OscArg::Float(f32::from_bits(reader.read_i32()? as u32)),
+            's' => OscArg::Str(reader.read_string()?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            _ => return None,
+        });
+    }
+    messages.push(OscMessage { address, args });
+    Some(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn read_i32(&mut self) -> Option<i32> {
+        let bytes = self.data.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(i32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    fn read_string(&mut self) -> Option<String> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        let value = std::str::from_utf8(&rest[..len]).ok()?.to_string();
+        self.pos += (len / 4 + 1) * 4;
+        Some(value)
+    }
+}
+
+/// What an address controls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Mute,
+    Solo,
+    Volume,
+    Level,
+}
+
+/// Address patterns of the controls, `{id}` standing for the track ID
+struct AddressMap {
+    patterns: Vec<(Control, String, String)>,
+}
+
+impl AddressMap {
+    fn new(config: &OscConfig) -> Self {
+        let patterns = [
+            (Control::Mute, &config.mute),
+            (Control::Solo, &config.solo),
+            (Control::Volume, &config.volume),
+            (Control::Level, &config.level),
+        ];
+        Self {
+            patterns: patterns
+                .into_iter()
+                .filter_map(|(control, pattern)| {
+                    let (prefix, suffix) = pattern.split_once("{id}")?;
+                    Some((control, prefix.to_string(), suffix.to_string()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Control and track of an address
+    fn parse(&self, address: &str) -> Option<(Control, u8)> {
+        self.patterns.iter().find_map(|(control, prefix, suffix)| {
+            let id = address.strip_prefix(prefix.as_str())?.strip_suffix(suffix.as_str())?;
+            Some((*control, id.parse().ok()?))
+        })
+    }
+
+    /// Address of a control of a track (None = the control is off)
+    fn address(&self, control: Control, track_id: u8) -> Option<String> {
+        self.patterns
+            .iter()
+            .find(|(c, _, _)| *c == control)
+            .map(|(_, prefix, suffix)| format!("{}{}{}", prefix, track_id, suffix))
+    }
+}
+
+/// Mute, solo and volume of a track as last sent to clients
+type TrackState = (bool, bool, f32);
+
+fn track_state(status: &TrackStatus) -> TrackState {
+    (status.muted, status.solo, status.volume)
+}
+
+/// Receive OSC messages on `socket` and send feedback until it fails
+pub async fn serve(socket: UdpSocket, config: OscConfig, state: Arc<AppState>) -> std::io::Result<()> {
+    let map = AddressMap::new(&config);
+    let mut clients: Vec<SocketAddr> = config
+        .feedback
+        .iter()
+        .filter_map(|address| {
+            let addr = parse_socket_addr(address, config.port);
+            if addr.is_none() {
+                tracing::warn!("Invalid OSC feedback address: {}", address);
+            }
+            addr
+        })
+        .collect();
+    let feedback = clients.len();
+    let mut sent: HashMap<u8, TrackState> = HashMap::new();
+
+    let mut levels = (config.level_hz.is_finite() && config.level_hz > 0.0).then(|| {
+        let mut interval = tokio::time::interval(Duration::from_secs_f32(1.0 / config.level_hz));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        interval
+    });
+    let mut changes = tokio::time::interval(STATE_INTERVAL);
+    changes.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut buf = vec![0u8; 4096];
+
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (len, from) = received?;
+                if !clients.contains(&from) {
+                    // The client heard from longest ago makes room
+                    if clients.len() >= feedback + MAX_CLIENTS {
+                        clients.remove(feedback);
+                    }
+                    tracing::debug!("OSC client {}", from);
+                    clients.push(from);
+                }
+                for msg in decode_packet(&buf[..len]) {
+                    if let Some(reply) = handle_message(&msg, &map, &state) {
+                        let _ = socket.send_to(&reply.encode(), from).await;
+                    }
+                }
+            }
+            _ = async { levels.as_mut().expect("guarded").tick().await }, if levels.is_some() => {
+                for status in state.track_manager.get_all_statuses() {
+                    if let Some(address) = map.address(Control::Level, status.track_id) {
+                        let msg = OscMessage::new(address, vec![OscArg::Float(status.level_normalized)]);
+                        send_all(&socket, &clients, &msg).await;
+                    }
+                }
+            }
+            _ = changes.tick() => {
+                let statuses = state.track_manager.get_all_statuses();
+                sent.retain(|track_id, _| statuses.iter().any(|status| status.track_id == *track_id));
+                for status in statuses {
+                    let current = track_state(&status);
+                    if sent.insert(status.track_id, current) == Some(current) {
+                        continue;
+                    }
+                    for control in [Control::Mute, Control::Solo, Control::Volume] {
+                        if let Some(msg) = value_message(&map, control, &status) {
+                            send_all(&socket, &clients, &msg).await;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn send_all(socket: &UdpSocket, clients: &[SocketAddr], msg: &OscMessage) {
+    let packet = msg.encode();
+    for client in clients {
+        let _ = socket.send_to(&packet, client).await;
+    }
+}
+
+/// Apply a message; returns the current value when it asks for one
+fn handle_message(msg: &OscMessage, map: &AddressMap, state: &AppState) -> Option<OscMessage> {
+    let (control, track_id) = map.parse(&msg.address)?;
+    let track_manager = &state.track_manager;
+
+    let Some(value) = msg.args.first().and_then(OscArg::as_f32) else {
+        let status = track_manager.get_track(track_id)?.status();
+        return value_message(map, control, &status);
+    };
+    let result = match control {
+        Control::Mute => track_manager.set_muted(track_id, value >= 0.5),
+        Control::Solo => track_manager.set_solo(track_id, value >= 0.5),
+        Control::Volume => {
+            let update = TrackConfigUpdate {
+                volume: Some(value.clamp(0.0, MAX_TRACK_VOLUME)),
+                ..Default::default()
+            };
+            track_manager.update_track(track_id, update).map(|()| state.track_changed(track_id))
+        }
+        Control::Level => Ok(()),
+    };
+    if let Err(e) = result {
+        tracing::debug!("OSC {}: {}", msg.address, e);
+    }
+    None
+}
+
+/// Current value of a control as a message to clients
+fn value_message(map: &AddressMap, control: Control, status: &TrackStatus) -> Option<OscMessage> {
+    let value = match control {
+        Control::Mute => status.muted as u8 as f32,
+        Control::Solo => status.solo as u8 as f32,
+        Control::Volume => status.volume,
+        Control::Level => status.level_normalized,
+    };
+    Some(OscMessage::new(map.address(control, status.track_id)?, vec![OscArg::Float(value)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::TrackConfig;
+    use crate::tracks::TrackManager;
+
+    #[test]
+    fn test_encode_decode() {
+        let msg = OscMessage::new(
+            "/track/2/mute",
+            vec![OscArg::Int(1), OscArg::Float(0.5), OscArg::Str("vox".to_string()), OscArg::Bool(true)],
+        );
+        let packet = msg.encode();
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(&packet[..16], b"/track/2/mute\0\0\0");
+        assert_eq!(decode_packet(&packet), vec![msg.clone()]);
+
+        // A bundle of two messages
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend_from_slice(&1u64.to_be_bytes());
+        for _ in 0..2 {
+            bundle.extend_from_slice(&(packet.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(&packet);
+        }
+        assert_eq!(decode_packet(&bundle), vec![msg.clone(), msg]);
+
+        assert!(decode_packet(b"track\0\0\0").is_empty());
+        assert!(decode_packet(&packet[..packet.len() - 2]).is_empty());
+    }
+
+    #[test]
+    fn test_address_map() {
+        let config = OscConfig {
+            mute: "/ch/{id}/mix/on".to_string(),
+            level: String::new(),
+            ..OscConfig::default()
+        };
+        let map = AddressMap::new(&config);
+        assert_eq!(map.parse("/ch/3/mix/on"), Some((Control::Mute, 3)));
+        assert_eq!(map.parse("/track/12/volume"), Some((Control::Volume, 12)));
+        assert_eq!(map.parse("/track/x/volume"), None);
+        assert_eq!(map.parse("/track/1/level"), None);
+        assert_eq!(map.address(Control::Solo, 4).as_deref(), Some("/track/4/solo"));
+        assert_eq!(map.address(Control::Level, 4), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let track_manager = Arc::new(TrackManager::new());
+        let id = track_manager.create_track(TrackConfig::default()).unwrap();
+        let state = Arc::new(AppState::new(track_manager.clone(), true));
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let config = OscConfig { level_hz: 0.0, ..OscConfig::default() };
+        let server = tokio::spawn(serve(socket, config, state));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let send = |msg: OscMessage| {
+            let client = &client;
+            async move { client.send_to(&msg.encode(), addr).await.unwrap() }
+        };
+        send(OscMessage::new(format!("/track/{}/mute", id), vec![OscArg::Float(1.0)])).await;
+        send(OscMessage::new(format!("/track/{}/volume", id), vec![OscArg::Float(0.25)])).await;
+        send(OscMessage::new(format!("/track/{}/volume", id), vec![])).await;
+
+        // The asked volume comes back among the state feedback
+        let mut buf = [0u8; 1024];
+        let volume = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let len = client.recv(&mut buf).await.unwrap();
+                let msg = decode_packet(&buf[..len]).remove(0);
+                if msg.address.ends_with("/volume") {
+                    return msg.args[0].as_f32().unwrap();
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(volume, 0.25);
+        assert!(track_manager.get_track(id).unwrap().is_muted());
+        server.abort();
+    }
+}
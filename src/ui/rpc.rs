@@ -0,0 +1,314 @@
+//! JSON-RPC control interface for scripts and external tools
+//!
+//! JSON-RPC 2.0 over TCP, one request or response per line. The methods
+//! are the requests of [`ControlMessage`] with its `data` as params, e.g.
+//! `{"jsonrpc":"2.0","id":1,"method":"SetMute","params":{"track_id":0,"muted":true}}`,
+//! and the result is the `data` of the reply (`GetStatus` returns the track
+//! statuses, `CreateTrack` the new track's ID, `null` for requests without a
+//! reply). A request without an `id` is a notification and gets no answer.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error::NetworkError;
+use crate::protocol::ControlMessage;
+use crate::ui::server::AppState;
+use crate::ui::websocket::handle_control_message;
+
+/// Invalid JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// JSON that isn't a request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// Not a request of `ControlMessage`
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Params that don't fit the method
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request failed (the message of an `Error` reply)
+pub const REQUEST_FAILED: i64 = -32000;
+
+/// A JSON-RPC request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    /// None for a notification
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+impl RpcRequest {
+    pub fn new(id: u64, method: &str, params: Option<Value>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id: Some(id.into()),
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+/// A JSON-RPC response: `result` on success, `error` otherwise
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+/// Error object of a failed request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcResponse {
+    fn result(id: Value, result: Value) -> Self {
+        Self { jsonrpc: "2.0".to_string(), id, result: Some(result), error: None }
+    }
+
+    fn error(id: Value, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError { code, message: message.into() }),
+        }
+    }
+}
+
+/// Accept control clients until the listener fails
+pub async fn serve(listener: TcpListener, state: Arc<AppState>) -> std::io::Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        tracing::debug!("Control client connected from {}", addr);
+        tokio::spawn(handle_connection(stream, state.clone()));
+    }
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<AppState>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // Talkback held by this client (released if the connection drops)
+    let mut talkback_held = false;
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match parse_request(&line) {
+            Ok((id, msg)) => {
+                if let ControlMessage::SetTalkback { active } = msg {
+                    talkback_held = active;
+                }
+                let response = call(&state, id.clone().unwrap_or(Value::Null), msg).await;
+                id.map(|_| response)
+            }
+            Err(response) => Some(response),
+        };
+        let Some(response) = response else {
+            continue;
+        };
+        let Ok(mut json) = serde_json::to_string(&response) else {
+            continue;
+        };
+        json.push('\n');
+        if writer.write_all(json.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+
+    if talkback_held {
+        let _ = state.track_manager.set_talkback(false);
+    }
+}
+
+/// Parse a request line into its ID and control message
+fn parse_request(line: &str) -> Result<(Option<Value>, ControlMessage), RpcResponse> {
+    let value: Value = serde_json::from_str(line)
+        .map_err(|e| RpcResponse::error(Value::Null, PARSE_ERROR, e.to_string()))?;
+    let request: RpcRequest = serde_json::from_value(value)
+        .map_err(|e| RpcResponse::error(Value::Null, INVALID_REQUEST, e.to_string()))?;
+    let id = request.id.clone().unwrap_or(Value::Null);
+    if request.jsonrpc != "2.0" {
+        return Err(RpcResponse::error(id, INVALID_REQUEST, "jsonrpc must be \"2.0\""));
+    }
+
+    let mut message = json!({ "type": request.method });
+    if let Some(params) = request.params.filter(|params| !params.is_null()) {
+        message["data"] = params;
+    }
+    let msg: ControlMessage = serde_json::from_value(message).map_err(|e| {
+        let code = if e.to_string().starts_with("unknown variant") { METHOD_NOT_FOUND } else { INVALID_PARAMS };
+        RpcResponse::error(id.clone(), code, e.to_string())
+    })?;
+    if msg.is_reply() {
+        return Err(RpcResponse::error(id, METHOD_NOT_FOUND, format!("{} is not a request", request.method)));
+    }
+    Ok((request.id, msg))
+}
+
+/// Run a control message and answer with the `data` of its reply
+async fn call(state: &AppState, id: Value, msg: ControlMessage) -> RpcResponse {
+    match handle_control_message(msg, state).await {
+        Ok(Some(reply)) => {
+            let data = serde_json::to_value(&reply)
+                .ok()
+                .and_then(|mut reply| reply.get_mut("data").map(Value::take))
+                .unwrap_or(Value::Null);
+            RpcResponse::result(id, data)
+        }
+        Ok(None) => RpcResponse::result(id, Value::Null),
+        Err(message) => RpcResponse::error(id, REQUEST_FAILED, message),
+    }
+}
+
+/// Blocking client of the control port (the `ctl` subcommand)
+pub struct RpcClient {
+    reader: std::io::BufReader<std::net::TcpStream>,
+    writer: std::net::TcpStream,
+    next_id: u64,
+}
+
+impl RpcClient {
+    /// How long to wait for the instance to answer
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn connect(addr: SocketAddr) -> crate::Result<Self> {
+        let stream = std::net::TcpStream::connect_timeout(&addr, Self::TIMEOUT)
+            .map_err(|e| NetworkError::ConnectionFailed(format!("{}: {}", addr, e)))?;
+        stream.set_read_timeout(Some(Self::TIMEOUT))?;
+        Ok(Self {
+            reader: std::io::BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_id: 1,
+        })
+    }
+
+    /// Send a control message and return the `data` of its reply
+    pub fn request(&mut self, msg: &ControlMessage) -> crate::Result<Value> {
+        let mut message = serde_json::to_value(msg).expect("control messages are JSON");
+        let params = message.get_mut("data").map(Value::take);
+        let method = message["type"].as_str().unwrap_or_default().to_string();
+        self.call(&method, params)
+    }
+
+    /// Call a method and wait for its result
+    pub fn call(&mut self, method: &str, params: Option<Value>) -> crate::Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut line = serde_json::to_string(&RpcRequest::new(id, method, params)).expect("requests are JSON");
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+
+        line.clear();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(NetworkError::ReceiveFailed("the instance closed the connection".to_string()).into());
+        }
+        let response: RpcResponse =
+            serde_json::from_str(&line).map_err(|e| NetworkError::ReceiveFailed(e.to_string()))?;
+        match response.error {
+            Some(error) => Err(NetworkError::RequestFailed(error.message).into()),
+            None => Ok(response.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracks::TrackManager;
+    use std::time::Duration;
+
+    fn error_code(line: &str) -> i64 {
+        parse_request(line).err().and_then(|response| response.error).map(|error| error.code).unwrap()
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let (id, msg) = parse_request(r#"{"jsonrpc":"2.0","id":7,"method":"SetMute","params":{"track_id":1,"muted":true}}"#).unwrap();
+        assert_eq!(id, Some(json!(7)));
+        assert!(matches!(msg, ControlMessage::SetMute { track_id: 1, muted: true }));
+
+        // Requests without data take no params (or null), notifications no ID
+        let (id, msg) = parse_request(r#"{"jsonrpc":"2.0","method":"GetStatus","params":null}"#).unwrap();
+        assert_eq!(id, None);
+        assert!(matches!(msg, ControlMessage::GetStatus));
+
+        assert_eq!(error_code("{"), PARSE_ERROR);
+        assert_eq!(error_code(r#"[1, 2]"#), INVALID_REQUEST);
+        assert_eq!(error_code(r#"{"jsonrpc":"1.0","id":1,"method":"GetStatus"}"#), INVALID_REQUEST);
+        assert_eq!(error_code(r#"{"jsonrpc":"2.0","id":1,"method":"Reboot"}"#), METHOD_NOT_FOUND);
+        assert_eq!(error_code(r#"{"jsonrpc":"2.0","id":1,"method":"Status","params":[]}"#), METHOD_NOT_FOUND);
+        assert_eq!(error_code(r#"{"jsonrpc":"2.0","id":1,"method":"SetMute","params":{"muted":true}}"#), INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn test_call() {
+        let state = AppState::new(Arc::new(TrackManager::new()), true);
+
+        let created = call(&state, json!(1), ControlMessage::CreateTrack(Default::default())).await;
+        assert_eq!(created.result, Some(json!({ "track_id": 0 })));
+        let muted = call(&state, json!(2), ControlMessage::SetMute { track_id: 0, muted: true }).await;
+        assert_eq!(muted.result, Some(Value::Null));
+        let status = call(&state, json!(3), ControlMessage::GetStatus).await;
+        assert_eq!(status.result.unwrap()[0]["muted"], json!(true));
+
+        let failed = call(&state, json!(4), ControlMessage::RemoveTrack { track_id: 9 }).await;
+        assert_eq!(failed.error.unwrap().code, REQUEST_FAILED);
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let state = Arc::new(AppState::new(Arc::new(TrackManager::new()), true));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, state));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        // The notification gets no answer, the ping does
+        writer.write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"Ping\"}\n").await.unwrap();
+        let ping = serde_json::to_string(&RpcRequest::new(5, "Ping", None)).unwrap();
+        writer.write_all(format!("{}\n", ping).as_bytes()).await.unwrap();
+
+        let line = tokio::time::timeout(Duration::from_secs(2), lines.next_line()).await.unwrap().unwrap().unwrap();
+        let response: RpcResponse = serde_json::from_str(&line).unwrap();
+        assert_eq!(response.id, json!(5));
+        assert!(response.error.is_none());
+        server.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client() {
+        let state = Arc::new(AppState::new(Arc::new(TrackManager::new()), true));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, state));
+
+        tokio::task::spawn_blocking(move || {
+            let mut client = RpcClient::connect(addr).unwrap();
+            let created = client.request(&ControlMessage::CreateTrack(Default::default())).unwrap();
+            assert_eq!(created["track_id"], json!(0));
+            client.request(&ControlMessage::SetSolo { track_id: 0, solo: true }).unwrap();
+            let status = client.call("GetStatus", None).unwrap();
+            assert_eq!(status[0]["solo"], json!(true));
+            assert!(client.request(&ControlMessage::SetMute { track_id: 7, muted: true }).is_err());
+        })
+        .await
+        .unwrap();
+        server.abort();
+    }
+}
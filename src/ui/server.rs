@@ -6,7 +6,7 @@ use axum::{
     http::{StatusCode, header},
     response::{Response, IntoResponse},
     body::Body,
-    extract::Path,
+    extract::{DefaultBodyLimit, Path},
 };
 use rust_embed::RustEmbed;
 use std::net::SocketAddr;
@@ -14,12 +14,25 @@ use std::sync::Arc;
 use tokio::sync::broadcast;
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::config::UiConfig;
-use crate::protocol::ControlMessage;
+use crate::config::{parse_socket_addr, UiConfig};
+use crate::config_store::ConfigStore;
+use crate::network::file_transfer::MAX_FILE_SIZE;
+use crate::network::pairing::Pairing;
+use crate::network::{FileTransfers, PeerRegistry};
+use crate::protocol::{ControlMessage, PeerStatus};
+use crate::recording::Recorder;
+use crate::routing::RoutingMatrix;
+use crate::sessions::SessionStore;
+use crate::stats::{self, StatsHistory};
 use crate::tracks::TrackManager;
 use crate::ui::handlers;
+use crate::ui::midi::{self, MidiRemote};
+use crate::ui::{osc, rpc};
 use crate::ui::websocket;
 
+/// Interval of pushing new log records to WebSocket clients
+const LOG_PUSH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
 /// Embedded static files (compiled into the binary)
 #[derive(RustEmbed)]
 #[folder = "static/"]
@@ -28,23 +41,191 @@ struct StaticAssets;
 /// Shared application state
 pub struct AppState {
     pub track_manager: Arc<TrackManager>,
+    pub peers: Arc<PeerRegistry>,
+    pub routing: Arc<RoutingMatrix>,
     pub control_tx: broadcast::Sender<ControlMessage>,
     pub is_sender: bool,
+    /// File drop between peers (peer mode only)
+    pub files: Option<Arc<FileTransfers>>,
+    /// Recorder of received audio (receiving modes only)
+    pub recorder: Option<Arc<Recorder>>,
+    /// Per-second track and peer statistics
+    pub stats: Arc<StatsHistory>,
+    /// History of streaming sessions with peers
+    pub sessions: Arc<SessionStore>,
+    /// Connecting and disconnecting peers acts on the link (peer mode only)
+    pub peer_control: bool,
+    /// Configuration file the UI's track, peer and output changes are saved to
+    pub config_store: Option<Arc<ConfigStore>>,
+    /// Peer allowlist and one-time PIN shown in the UI
+    pub pairing: Option<Arc<Pairing>>,
+    /// MIDI controller mappings and learn mode (if MIDI is enabled)
+    pub midi: Option<Arc<MidiRemote>>,
 }
 
 impl AppState {
     pub fn new(track_manager: Arc<TrackManager>, is_sender: bool) -> Self {
+        Self::with_peers(track_manager, Arc::new(PeerRegistry::new()), is_sender)
+    }
+    
+    pub fn with_peers(
+        track_manager: Arc<TrackManager>,
+        peers: Arc<PeerRegistry>,
+        is_sender: bool,
+    ) -> Self {
+        Self::with_routing(track_manager, peers, Arc::new(RoutingMatrix::new()), is_sender)
+    }
+    
+    pub fn with_routing(
+        track_manager: Arc<TrackManager>,
+        peers: Arc<PeerRegistry>,
+        routing: Arc<RoutingMatrix>,
+        is_sender: bool,
+    ) -> Self {
         let (control_tx, _) = broadcast::channel(256);
         Self {
             track_manager,
+            peers,
+            routing,
             control_tx,
             is_sender,
+            files: None,
+            recorder: None,
+            stats: Arc::new(StatsHistory::new()),
+            sessions: Arc::new(SessionStore::new()),
+            peer_control: false,
+            config_store: None,
+            pairing: None,
+            midi: None,
         }
     }
     
     pub fn subscribe_control(&self) -> broadcast::Receiver<ControlMessage> {
         self.control_tx.subscribe()
     }
+    
+    /// Start sending to a peer given by key, or by the audio address of a
+    /// peer discovery didn't find
+    pub fn connect_peer(&self, peer: &str) -> Result<PeerStatus, (StatusCode, String)> {
+        self.check_peer_control()?;
+        let key = self.peers.connect(peer).ok_or_else(|| unknown_peer(peer))?;
+        tracing::info!("Peer {} connected from the UI", key);
+        if let Some(store) = &self.config_store {
+            store.save_peer(&key, None);
+        }
+        self.peer_changed(&key)
+    }
+    
+    /// Stop sending to a peer; it stays listed and can be connected again
+    pub fn disconnect_peer(&self, peer: &str) -> Result<PeerStatus, (StatusCode, String)> {
+        self.check_peer_control()?;
+        if !self.peers.set_active(peer, false) {
+            return Err(unknown_peer(peer));
+        }
+        tracing::info!("Peer {} disconnected from the UI", peer);
+        if let Some(store) = &self.config_store {
+            store.forget_peer(peer);
+        }
+        self.peer_changed(peer)
+    }
+    
+    /// Connect to a peer proving the one-time PIN it shows; on success
+    /// both peers keep each other on their allowlists
+    pub fn pair_peer(&self, peer: &str, pin: &str) -> Result<PeerStatus, (StatusCode, String)> {
+        self.check_peer_control()?;
+        let Some(pairing) = &self.pairing else {
+            return Err((StatusCode::NOT_FOUND, "pairing is not available".to_string()));
+        };
+        let key = self.peers.connect(peer).ok_or_else(|| unknown_peer(peer))?;
+        let address = self
+            .peers
+            .status(&key)
+            .and_then(|status| status.address.parse::<SocketAddr>().ok())
+            .ok_or_else(|| unknown_peer(&key))?;
+        pairing.set_peer_pin(address, pin).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        tracing::info!("Pairing with peer {} from the UI", key);
+        if let Some(store) = &self.config_store {
+            store.save_peer(&key, None);
+        }
+        self.peer_changed(&key)
+    }
+    
+    /// Change the display name of a peer
+    pub fn rename_peer(&self, peer: &str, name: &str) -> Result<PeerStatus, (StatusCode, String)> {
+        self.check_peer_control()?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "Peer name must not be empty".to_string()));
+        }
+        if !self.peers.rename(peer, name) {
+            return Err(unknown_peer(peer));
+        }
+        if let Some(store) = &self.config_store {
+            store.save_peer(peer, Some(name));
+        }
+        self.peer_changed(peer)
+    }
+    
+    /// Save a track created or edited in the UI to the configuration file
+    pub fn track_changed(&self, track_id: u8) {
+        let Some(store) = &self.config_store else {
+            return;
+        };
+        let config = self.track_manager.get_track(track_id).map(|track| track.config.clone());
+        if let Some(config) = config {
+            store.save_track(&config);
+        }
+    }
+    
+    /// Drop a track deleted in the UI from the configuration file
+    pub fn track_removed(&self, track_id: u8) {
+        if let Some(store) = &self.config_store {
+            store.forget_track(track_id);
+        }
+    }
+    
+    /// Save the MIDI mappings after one was learned or removed
+    pub fn midi_changed(&self) {
+        let (Some(store), Some(midi)) = (&self.config_store, &self.midi) else {
+            return;
+        };
+        let mappings = midi.mappings();
+        store.update(|config| config.ui.midi.mappings = mappings);
+    }
+    
+    /// Save the master processing of an output device
+    pub fn output_dsp_changed(&self, device_id: &str) {
+        let Some(store) = &self.config_store else {
+            return;
+        };
+        let dsp = self.track_manager.output_dsp(device_id);
+        store.update(|config| match dsp {
+            Some(dsp) => {
+                config.audio.output_dsp.insert(device_id.to_string(), dsp);
+            }
+            None => {
+                config.audio.output_dsp.remove(device_id);
+            }
+        });
+    }
+    
+    fn check_peer_control(&self) -> Result<(), (StatusCode, String)> {
+        if self.peer_control {
+            Ok(())
+        } else {
+            Err((StatusCode::NOT_FOUND, "peer control needs peer mode".to_string()))
+        }
+    }
+    
+    /// Broadcast the peer list after a change and return the changed peer
+    fn peer_changed(&self, key: &str) -> Result<PeerStatus, (StatusCode, String)> {
+        let _ = self.control_tx.send(ControlMessage::Peers(self.peers.statuses()));
+        self.peers.status(key).ok_or_else(|| unknown_peer(key))
+    }
+}
+
+fn unknown_peer(peer: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Unknown peer: {}", peer))
 }
 
 /// Serve embedded static files
@@ -105,10 +286,83 @@ pub struct WebServer {
 impl WebServer {
     /// Create a new web server
     pub fn new(config: UiConfig, track_manager: Arc<TrackManager>, is_sender: bool) -> Self {
-        Self {
-            config,
-            state: Arc::new(AppState::new(track_manager, is_sender)),
+        Self::with_state(config, AppState::new(track_manager, is_sender))
+    }
+    
+    /// Create a web server that also exposes a shared peer registry
+    pub fn with_peers(
+        config: UiConfig,
+        track_manager: Arc<TrackManager>,
+        peers: Arc<PeerRegistry>,
+        is_sender: bool,
+    ) -> Self {
+        Self::with_state(config, AppState::with_peers(track_manager, peers, is_sender))
+    }
+    
+    /// Create a web server that also edits a shared routing matrix
+    pub fn with_routing(
+        config: UiConfig,
+        track_manager: Arc<TrackManager>,
+        peers: Arc<PeerRegistry>,
+        routing: Arc<RoutingMatrix>,
+        is_sender: bool,
+    ) -> Self {
+        Self::with_state(config, AppState::with_routing(track_manager, peers, routing, is_sender))
+    }
+    
+    fn with_state(config: UiConfig, mut state: AppState) -> Self {
+        if config.midi.enabled {
+            state.midi = Some(Arc::new(MidiRemote::new(config.midi.mappings.clone())));
         }
+        Self { config, state: Arc::new(state) }
+    }
+    
+    /// Serve file drop to peers from the UI (before the server starts)
+    pub fn with_file_transfers(mut self, files: Arc<FileTransfers>) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("state is shared only once the server runs")
+            .files = Some(files);
+        self
+    }
+    
+    /// Control recording of received audio from the UI (before the server starts)
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("state is shared only once the server runs")
+            .recorder = Some(recorder);
+        self
+    }
+    
+    /// Let the UI connect, disconnect and rename peers (before the server starts)
+    pub fn with_peer_control(mut self) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("state is shared only once the server runs")
+            .peer_control = true;
+        self
+    }
+    
+    /// Show pairing status and PINs, pair with peers from the UI (before the server starts)
+    pub fn with_pairing(mut self, pairing: Arc<Pairing>) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("state is shared only once the server runs")
+            .pairing = Some(pairing);
+        self
+    }
+    
+    /// Save track, peer and output changes to the configuration file (before the server starts)
+    pub fn with_config_store(mut self, store: Arc<ConfigStore>) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("state is shared only once the server runs")
+            .config_store = Some(store);
+        self
+    }
+    
+    /// Record streaming sessions into a saved history (before the server starts)
+    pub fn with_sessions(mut self, sessions: Arc<SessionStore>) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("state is shared only once the server runs")
+            .sessions = sessions;
+        self
     }
     
     /// Get shared state
@@ -121,7 +375,8 @@ impl WebServer {
         let cors = CorsLayer::new()
             .allow_origin(Any)
             .allow_methods(Any)
-            .allow_headers(Any);
+            .allow_headers(Any)
+            .expose_headers([header::ETAG]);
 
         Router::new()
             // API routes
@@ -129,12 +384,48 @@ impl WebServer {
             .route("/api/devices", get(handlers::get_devices))
             .route("/api/tracks", get(handlers::get_tracks))
             .route("/api/tracks", post(handlers::create_track))
+            .route("/api/tracks/drops", get(handlers::get_drops))
             .route("/api/tracks/:id", axum::routing::delete(handlers::delete_track))
             .route("/api/tracks/:id", axum::routing::patch(handlers::update_track))
             .route("/api/tracks/:id/mute", post(handlers::set_mute))
             .route("/api/tracks/:id/solo", post(handlers::set_solo))
             .route("/api/tracks/:id/start", post(handlers::start_track))
             .route("/api/tracks/:id/stop", post(handlers::stop_track))
+            .route("/api/talkback", post(handlers::set_talkback))
+            .route("/api/peers", get(handlers::get_peers).post(handlers::add_peer))
+            .route("/api/peers/:id", axum::routing::patch(handlers::rename_peer))
+            .route("/api/peers/:id/connect", post(handlers::connect_peer))
+            .route("/api/peers/:id/disconnect", post(handlers::disconnect_peer))
+            .route("/api/peers/:id/pair", post(handlers::pair_peer))
+            .route("/api/peers/:id/test", post(handlers::test_peer_link))
+            .route("/api/pairing", get(handlers::get_pairing))
+            .route("/api/pairing/pin", post(handlers::new_pairing_pin))
+            .route("/api/peers/mixer", get(handlers::get_peer_mixer))
+            .route("/api/capabilities", get(handlers::get_capabilities))
+            .route("/api/peers/:id/mix", post(handlers::set_peer_mix))
+            .route(
+                "/api/outputs/:device_id/dsp",
+                get(handlers::get_output_dsp)
+                    .put(handlers::set_output_dsp)
+                    .delete(handlers::clear_output_dsp),
+            )
+            .route("/api/profile", get(handlers::get_profile).delete(handlers::reset_profile))
+            .route("/api/debug/packets", get(handlers::get_debug_packets).delete(handlers::clear_debug_packets))
+            .route("/api/events", get(handlers::get_events))
+            .route("/api/logs", get(handlers::get_logs))
+            .route("/api/stats", get(handlers::get_stats))
+            .route("/api/sessions", get(handlers::get_sessions))
+            .route("/api/midi", get(handlers::get_midi))
+            .route("/api/midi/learn", post(handlers::start_midi_learn).delete(handlers::cancel_midi_learn))
+            .route("/api/midi/mappings/:index", axum::routing::delete(handlers::delete_midi_mapping))
+            .route("/api/recording", get(handlers::get_recording))
+            .route("/api/recording/start", post(handlers::start_recording))
+            .route("/api/recording/stop", post(handlers::stop_recording))
+            .route("/api/files", get(handlers::get_file_transfers))
+            .route(
+                "/api/files/:peer",
+                post(handlers::send_file).layer(DefaultBodyLimit::max(MAX_FILE_SIZE as usize)),
+            )
             // WebSocket
             .route("/ws", get(websocket::websocket_handler))
             // Health check
@@ -148,20 +439,80 @@ impl WebServer {
             .with_state(self.state.clone())
     }
     
-    /// Start the web server
+    /// Start the web server, the JSON-RPC control port, OSC and MIDI (each if enabled)
     pub async fn start(&self) -> anyhow::Result<()> {
-        let addr: SocketAddr = format!("{}:{}", self.config.bind_address, self.config.http_port)
-            .parse()?;
+        let addr: SocketAddr = parse_socket_addr(&self.config.bind_address, self.config.http_port)
+            .ok_or_else(|| anyhow::anyhow!("Invalid bind address: {}", self.config.bind_address))?;
+        
+        let rpc_listener = match self.config.rpc_port {
+            0 => None,
+            port => {
+                let rpc_addr = SocketAddr::new(addr.ip(), port);
+                let listener = tokio::net::TcpListener::bind(rpc_addr).await?;
+                tracing::info!("JSON-RPC control listening on {}", rpc_addr);
+                Some(listener)
+            }
+        };
+        let osc_socket = match self.config.osc.enabled {
+            false => None,
+            true => {
+                let osc_addr = SocketAddr::new(addr.ip(), self.config.osc.port);
+                let socket = tokio::net::UdpSocket::bind(osc_addr).await?;
+                tracing::info!("OSC control listening on udp://{}", osc_addr);
+                Some(socket)
+            }
+        };
         
         let router = self.build_router();
         
-        tracing::info!("Web server listening on http://{}", addr);
-        tracing::info!("Static assets are embedded in the binary");
+        let sampler = stats::spawn_sampler(
+            self.state.stats.clone(),
+            self.state.sessions.clone(),
+            self.state.track_manager.clone(),
+            self.state.peers.clone(),
+        );
+        let push = websocket::spawn_status_push(
+            self.state.clone(),
+            self.config.level_push_hz,
+            self.config.status_push_hz,
+        );
+        let peer_push = websocket::spawn_peer_push(self.state.clone());
+        let log_push = websocket::spawn_log_push(self.state.clone(), LOG_PUSH_INTERVAL);
+        let mut controls = Vec::new();
+        if let Some(listener) = rpc_listener {
+            controls.push(tokio::spawn(rpc::serve(listener, self.state.clone())));
+        }
+        if let Some(socket) = osc_socket {
+            controls.push(tokio::spawn(osc::serve(socket, self.config.osc.clone(), self.state.clone())));
+        }
+        if let Some(remote) = self.state.midi.clone() {
+            controls.push(tokio::spawn(midi::serve(self.config.midi.clone(), remote, self.state.clone())));
+        }
         
-        let listener = tokio::net::TcpListener::bind(addr).await?;
-        axum::serve(listener, router).await?;
+        let result = async {
+            if !self.config.enabled {
+                // Headless: only the control port, OSC and MIDI run
+                if controls.is_empty() {
+                    return Ok(());
+                }
+                let (result, _, _) = futures_util::future::select_all(controls.iter_mut()).await;
+                return result.unwrap_or(Ok(()));
+            }
+            tracing::info!("Web server listening on http://{}", addr);
+            tracing::info!("Static assets are embedded in the binary");
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            axum::serve(listener, router).await
+        }
+        .await;
+        sampler.abort();
+        push.abort();
+        peer_push.abort();
+        log_push.abort();
+        for control in controls {
+            control.abort();
+        }
         
-        Ok(())
+        Ok(result?)
     }
     
     /// Start the web server in the background
@@ -8,10 +8,14 @@ use axum::{
     response::IntoResponse,
 };
 use futures_util::{SinkExt, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
+use tokio::time::{Interval, MissedTickBehavior};
 
-use crate::protocol::{ControlMessage, DevicesResponse};
+use crate::logs;
+use crate::protocol::{ControlMessage, DevicesResponse, TrackLevel};
 use crate::ui::server::AppState;
 
 /// WebSocket upgrade handler
@@ -19,18 +23,18 @@ pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
-    let is_sender = state.is_sender;
-    ws.on_upgrade(move |socket| handle_socket(socket, state, is_sender))
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
 }
 
 /// Handle WebSocket connection
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>, is_sender: bool) {
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     let (mut sender, mut receiver) = socket.split();
     
     // Subscribe to control messages
     let mut control_rx = state.control_tx.subscribe();
     let track_manager = state.track_manager.clone();
     let control_tx = state.control_tx.clone();
+    let state_for_recv = state.clone();
     
     // Send initial status
     let statuses = track_manager.get_all_statuses();
@@ -50,13 +54,27 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, is_sender: bool)
         }
     });
     
+    // Talkback held by this client (released if the connection drops)
+    let talkback_held = Arc::new(AtomicBool::new(false));
+    let talkback_held_for_recv = talkback_held.clone();
+    
     // Handle incoming messages
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = receiver.next().await {
             match msg {
                 Message::Text(text) => {
                     if let Ok(control_msg) = serde_json::from_str::<ControlMessage>(&text) {
-                        handle_control_message(control_msg, &track_manager, &control_tx, is_sender).await;
+                        if let ControlMessage::SetTalkback { active } = control_msg {
+                            talkback_held_for_recv.store(active, Ordering::Relaxed);
+                        }
+                        // Replies go to every client, like the pushed status
+                        let reply = match handle_control_message(control_msg, &state_for_recv).await {
+                            Ok(reply) => reply,
+                            Err(message) => Some(ControlMessage::Error { message }),
+                        };
+                        if let Some(reply) = reply {
+                            let _ = control_tx.send(reply);
+                        }
                     }
                 }
                 Message::Binary(_) => {
@@ -84,78 +102,292 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, is_sender: bool)
             send_task.abort();
         }
     }
+    
+    // Never leave talkback transmitting after the client went away
+    if talkback_held.load(Ordering::Relaxed) {
+        let _ = state.track_manager.set_talkback(false);
+    }
+}
+
+/// Push the peer list to every connected client whenever a peer connects,
+/// leaves or stops answering
+pub fn spawn_peer_push(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    let mut changes = state.peers.subscribe();
+    tokio::spawn(async move {
+        while let Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) = changes.recv().await {
+            let _ = state.control_tx.send(ControlMessage::Peers(state.peers.statuses()));
+        }
+    })
+}
+
+/// Push new log records to every connected client every `interval`
+pub fn spawn_log_push(state: Arc<AppState>, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut last_seq = logs::last_seq();
+        loop {
+            ticks.tick().await;
+            if state.control_tx.receiver_count() == 0 {
+                last_seq = logs::last_seq();
+                continue;
+            }
+            let records = logs::records(Some(last_seq), None, None);
+            if let Some(last) = records.last() {
+                last_seq = last.seq;
+                let _ = state.control_tx.send(ControlMessage::Logs(records));
+            }
+        }
+    })
+}
+
+/// Push track state to every connected client: level meters `level_hz`
+/// times a second, the full status `status_hz` times (0 = never)
+pub fn spawn_status_push(state: Arc<AppState>, level_hz: f32, status_hz: f32) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut levels = push_interval(level_hz);
+        let mut status = push_interval(status_hz);
+        
+        loop {
+            tokio::select! {
+                Some(()) = tick(&mut levels) => {
+                    if state.control_tx.receiver_count() > 0 {
+                        let statuses = state.track_manager.get_all_statuses();
+                        let levels = statuses.iter().map(TrackLevel::from).collect();
+                        let _ = state.control_tx.send(ControlMessage::Levels(levels));
+                    }
+                }
+                Some(()) = tick(&mut status) => {
+                    if state.control_tx.receiver_count() > 0 {
+                        let statuses = state.track_manager.get_all_statuses();
+                        let _ = state.control_tx.send(ControlMessage::Status(statuses));
+                    }
+                }
+                else => break,
+            }
+        }
+    })
+}
+
+fn push_interval(hz: f32) -> Option<Interval> {
+    (hz.is_finite() && hz > 0.0).then(|| {
+        let mut interval = tokio::time::interval(Duration::from_secs_f32(1.0 / hz));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        interval
+    })
+}
+
+/// Wait for the next tick (None at once for a disabled push)
+async fn tick(interval: &mut Option<Interval>) -> Option<()> {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+            Some(())
+        }
+        None => None,
+    }
 }
 
-/// Handle incoming control message
-async fn handle_control_message(
+/// Handle a control message and return its reply (None for requests that
+/// don't have one, Err with the message of an `Error` reply)
+pub(crate) async fn handle_control_message(
     msg: ControlMessage,
-    track_manager: &Arc<crate::tracks::TrackManager>,
-    control_tx: &broadcast::Sender<ControlMessage>,
-    is_sender: bool,
-) {
+    state: &AppState,
+) -> Result<Option<ControlMessage>, String> {
+    let track_manager = &state.track_manager;
     match msg {
         ControlMessage::GetStatus => {
-            let statuses = track_manager.get_all_statuses();
-            let _ = control_tx.send(ControlMessage::Status(statuses));
+            Ok(Some(ControlMessage::Status(track_manager.get_all_statuses())))
+        }
+        
+        ControlMessage::GetCapabilities => {
+            Ok(Some(ControlMessage::Capabilities(track_manager.remote_capabilities())))
         }
         
         ControlMessage::ListDevices => {
             let devices = crate::audio::device::list_devices();
-            let resp = DevicesResponse { devices, is_receiver: !is_sender };
-            let _ = control_tx.send(ControlMessage::Devices(resp));
+            let resp = DevicesResponse { devices, is_receiver: !state.is_sender };
+            Ok(Some(ControlMessage::Devices(resp)))
         }
         
         ControlMessage::CreateTrack(config) => {
-            match track_manager.create_track(config) {
-                Ok(id) => {
-                    tracing::info!("Created track {}", id);
-                }
-                Err(e) => {
-                    let _ = control_tx.send(ControlMessage::Error {
-                        message: e.to_string(),
-                    });
-                }
-            }
+            let id = track_manager.create_track(config).map_err(|e| e.to_string())?;
+            tracing::info!("Created track {}", id);
+            state.track_changed(id);
+            Ok(Some(ControlMessage::TrackCreated { track_id: id }))
         }
         
         ControlMessage::RemoveTrack { track_id } => {
-            if let Err(e) = track_manager.remove_track(track_id) {
-                let _ = control_tx.send(ControlMessage::Error {
-                    message: e.to_string(),
-                });
-            }
+            track_manager.remove_track(track_id).map_err(|e| e.to_string())?;
+            state.track_removed(track_id);
+            Ok(None)
         }
         
         ControlMessage::UpdateTrack { track_id, config } => {
-            if let Err(e) = track_manager.update_track(track_id, config) {
-                let _ = control_tx.send(ControlMessage::Error {
-                    message: e.to_string(),
-                });
-            }
+            track_manager.update_track(track_id, config).map_err(|e| e.to_string())?;
+            state.track_changed(track_id);
+            Ok(None)
         }
         
         ControlMessage::SetMute { track_id, muted } => {
-            if let Err(e) = track_manager.set_muted(track_id, muted) {
-                let _ = control_tx.send(ControlMessage::Error {
-                    message: e.to_string(),
-                });
-            }
+            track_manager.set_muted(track_id, muted).map_err(|e| e.to_string())?;
+            Ok(None)
         }
         
         ControlMessage::SetSolo { track_id, solo } => {
-            if let Err(e) = track_manager.set_solo(track_id, solo) {
-                let _ = control_tx.send(ControlMessage::Error {
-                    message: e.to_string(),
-                });
+            track_manager.set_solo(track_id, solo).map_err(|e| e.to_string())?;
+            Ok(None)
+        }
+        
+        ControlMessage::SetTalkback { active } => {
+            track_manager.set_talkback(active).map_err(|e| e.to_string())?;
+            Ok(None)
+        }
+        
+        ControlMessage::GetRouting => {
+            Ok(Some(ControlMessage::Routing(state.routing.routes())))
+        }
+        
+        ControlMessage::SetRoute { track_id, destinations } => {
+            if track_manager.get_track(track_id).is_none() {
+                return Err(format!("Track {} not found", track_id));
             }
+            state.routing.set_route(track_id, destinations);
+            Ok(Some(ControlMessage::Routing(state.routing.routes())))
         }
         
-        ControlMessage::Ping => {
-            let _ = control_tx.send(ControlMessage::Pong);
+        ControlMessage::GetPeerMixer => {
+            Ok(Some(ControlMessage::PeerMixer(track_manager.peer_mixer())))
         }
         
+        ControlMessage::SetPeerMix { peer, gain, muted } => {
+            let ip = state.peers.resolve_ip(&peer).ok_or_else(|| format!("Unknown peer: {}", peer))?;
+            track_manager.set_peer_mix(ip, gain, muted).map_err(|e| e.to_string())?;
+            Ok(Some(ControlMessage::PeerMixer(track_manager.peer_mixer())))
+        }
+        
+        ControlMessage::ListPeers => {
+            Ok(Some(ControlMessage::Peers(state.peers.statuses())))
+        }
+        
+        ControlMessage::ConnectPeer { peer } => {
+            state.connect_peer(&peer).map_err(|(_, message)| message)?;
+            Ok(None)
+        }
+        
+        ControlMessage::DisconnectPeer { peer } => {
+            state.disconnect_peer(&peer).map_err(|(_, message)| message)?;
+            Ok(None)
+        }
+        
+        ControlMessage::RenamePeer { peer, name } => {
+            state.rename_peer(&peer, &name).map_err(|(_, message)| message)?;
+            Ok(None)
+        }
+        
+        ControlMessage::StartRecording(request) => {
+            let recorder = state.recorder.as_deref().ok_or("Recording needs a receiving mode")?;
+            Ok(Some(ControlMessage::Recording(recorder.start(request)?)))
+        }
+        
+        ControlMessage::StopRecording => {
+            Ok(state.recorder.as_deref().map(|recorder| ControlMessage::Recording(recorder.stop())))
+        }
+        
+        ControlMessage::GetRecording => {
+            Ok(state.recorder.as_deref().map(|recorder| ControlMessage::Recording(recorder.status())))
+        }
+        
+        ControlMessage::FilePlayer { track_id, command } => {
+            let status = track_manager.control_player(track_id, command).map_err(|e| e.to_string())?;
+            Ok(Some(ControlMessage::Player { track_id, status }))
+        }
+        
+        ControlMessage::GetLogs { since } => Ok(Some(ControlMessage::Logs(logs::records(since, None, None)))),
+        
+        ControlMessage::Ping => Ok(Some(ControlMessage::Pong)),
+        
         _ => {
             // Other messages are informational
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::peers::PeerRegistry;
+    use crate::protocol::{PeerConnection, TrackConfig};
+    use crate::tracks::TrackManager;
+
+    #[tokio::test]
+    async fn test_status_push() {
+        let track_manager = Arc::new(TrackManager::new());
+        track_manager.create_track(TrackConfig::default()).unwrap();
+        let state = Arc::new(AppState::new(track_manager, true));
+        let mut control_rx = state.subscribe_control();
+
+        let push = spawn_status_push(state.clone(), 50.0, 0.0);
+        let message = tokio::time::timeout(Duration::from_secs(2), control_rx.recv()).await.unwrap().unwrap();
+        match message {
+            ControlMessage::Levels(levels) => assert_eq!(levels[0].track_id, 0),
+            other => panic!("expected levels, got {:?}", other),
+        }
+        push.abort();
+
+        // Nothing to push, the task ends
+        let idle = spawn_status_push(state, 0.0, 0.0);
+        tokio::time::timeout(Duration::from_secs(2), idle).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peer_push_on_connection_change() {
+        let peers = Arc::new(PeerRegistry::new());
+        peers.upsert("192.168.1.20:5000".parse().unwrap(), "Studio", "", true);
+        let state = Arc::new(AppState::with_peers(Arc::new(TrackManager::new()), peers.clone(), false));
+        let mut control_rx = state.subscribe_control();
+
+        let push = spawn_peer_push(state);
+        peers.set_connection("192.168.1.20:5000", PeerConnection::Lost);
+        let message = tokio::time::timeout(Duration::from_secs(2), control_rx.recv()).await.unwrap().unwrap();
+        match message {
+            ControlMessage::Peers(peers) => assert_eq!(peers[0].connection, PeerConnection::Lost),
+            other => panic!("expected peers, got {:?}", other),
+        }
+        push.abort();
+    }
+
+    /// Handle a message and return the first reply
+    async fn send(state: &AppState, msg: ControlMessage) -> ControlMessage {
+        let mut control_rx = state.subscribe_control();
+        match handle_control_message(msg, state).await {
+            Ok(Some(reply)) => reply,
+            Ok(None) => control_rx.try_recv().unwrap(),
+            Err(message) => ControlMessage::Error { message },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peer_control() {
+        let mut state = AppState::new(Arc::new(TrackManager::new()), true);
+
+        // Sender and receiver have no peers to connect
+        let connect = ControlMessage::ConnectPeer { peer: "10.8.0.5".to_string() };
+        assert!(matches!(send(&state, connect.clone()).await, ControlMessage::Error { .. }));
+
+        state.peer_control = true;
+        match send(&state, connect).await {
+            ControlMessage::Peers(peers) => assert!(peers[0].active),
+            other => panic!("expected peers, got {:?}", other),
+        }
+        let rename = ControlMessage::RenamePeer { peer: "10.8.0.5:5000".to_string(), name: "Booth".to_string() };
+        send(&state, rename).await;
+        let disconnect = ControlMessage::DisconnectPeer { peer: "10.8.0.5:5000".to_string() };
+        send(&state, disconnect).await;
+        match send(&state, ControlMessage::ListPeers).await {
+            ControlMessage::Peers(peers) => assert_eq!((peers[0].name.as_str(), peers[0].active), ("Booth", false)),
+            other => panic!("expected peers, got {:?}", other),
         }
     }
 }